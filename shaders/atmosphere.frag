@@ -0,0 +1,38 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in vec3 Position;
+
+#include "frame.glsl"
+// The planet's center in view space
+uniform vec3 planetCenter;
+uniform float planetRadius;
+uniform float shellRadius;
+// The planet's pole in view space, and how squashed along it it is
+uniform vec3 planetAxis;
+uniform float flattening;
+uniform vec3 tint;
+uniform float intensity;
+
+#include "ellipsoid.glsl"
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    // Where the view ray passes closest to the planet's center, the glow is
+    // brightest where that is just above the surface. Worked out with the
+    // planet stretched back into a sphere, the eye at the origin stays put.
+    vec3 center = unflatten(planetCenter, planetAxis, flattening);
+    vec3 ray = unflatten(normalize(Position), planetAxis, flattening);
+    vec3 closest = ray * max(dot(center, ray) / dot(ray, ray), 0.0);
+    float height = (length(closest - center) - planetRadius) / (shellRadius - planetRadius);
+    float glow = exp(-4.0 * max(height, 0.0)) * (1.0 - smoothstep(0.7, 1.0, height));
+
+    // Only the day side scatters, with a soft terminator
+    vec3 up = normalize(closest - center);
+    vec3 toSun = unflatten(sunPos - planetCenter, planetAxis, flattening);
+    float lit = smoothstep(-0.3, 0.3, dot(up, normalize(toSun)));
+
+    FragColor = vec4(tint * intensity * glow * lit, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 430
+
+layout(location = 0) in vec3 pos;
+
+out vec3 Position;
+
+uniform mat4 MV;
+#include "frame.glsl"
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    Position = vec3(MV * vec4(pos, 1.0));
+    gl_Position = (P * MV) * vec4(pos, 1.0);
+}
@@ -0,0 +1,28 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in vec3 Position;
+in vec3 Normal;
+in vec3 ModelNormal;
+in vec2 UV;
+
+#include "frame.glsl"
+uniform sampler2D baseColor;
+// 0 lit, 1 normals
+uniform int shading;
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    if (shading == 1) {
+        FragColor = vec4(normalize(ModelNormal) * 0.5 + 0.5, 1.0);
+        return;
+    }
+
+    vec3 lightDir = normalize(sunPos - Position);
+    float diffuse = max(dot(normalize(Normal), lightDir), 0.0);
+    vec3 color = texture(baseColor, UV).rgb * (0.08 + diffuse);
+
+    FragColor = vec4(pow(color, vec3(2.2)), 1.0);
+}
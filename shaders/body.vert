@@ -0,0 +1,25 @@
+#version 430
+
+layout(location = 0) in vec3 pos;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 tex;
+
+out vec3 Position;
+out vec3 Normal;
+out vec3 ModelNormal;
+out vec2 UV;
+
+uniform mat4 MV;
+#include "frame.glsl"
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    Position = vec3(MV * vec4(pos, 1.0));
+    Normal = normalize(mat3(MV) * normal);
+    ModelNormal = normal;
+    UV = tex;
+
+    gl_Position = (P * MV) * vec4(pos, 1.0);
+}
@@ -5,8 +5,11 @@ in vec3 Position;
 in vec3 vPos;
 in vec3 Normal;
 
-uniform vec3 sunPos;
-uniform float time;
+#include "frame.glsl"
+uniform vec3 atmosphereColor;
+uniform float density;
+
+#include "wind.glsl"
 
 const float shininess = 1.0;
 
@@ -192,8 +195,8 @@ void main () {
 	///////////////////////////////////////////////////////////////////////////
 	// Color
 
-	float noise = abs(smoothstep(0.1, 0.9, fbm(vec4(vPos, time * 0.01))));
-  vec4 color = vec4(1.f, 1.f, 1.f, clamp(2 * noise, 0.f, 1.f)); 
+	float noise = abs(smoothstep(0.1, 0.9, fbm(vec4(windAdvect(vPos), time * 0.01))));
+  vec4 color = vec4(atmosphereColor, clamp(2 * noise * density, 0.f, 1.f)); 
 
 	////////////////////////////////////////////////////////////////////////////
 	// Lighting
@@ -209,6 +212,7 @@ void main () {
 	// Ambient-------------
 	vec4 ambient = 0.3 * color;
 	
-	vec4 resultLight = ambient + diffuse;
+	// Dimmer light leaves the clouds as thick
+	vec4 resultLight = vec4(ambient.rgb + diffuse.rgb * sunIntensity, ambient.a + diffuse.a);
 	FragColor = resultLight;
 }
\ No newline at end of file
@@ -9,7 +9,7 @@ out vec3 Normal;
 out vec2 UV;
 
 uniform mat4 MV;
-uniform mat4 P;
+#include "frame.glsl"
 
 //////////////////////////////////////////////////////////////////////////////////////////
 
@@ -0,0 +1,20 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+uniform sampler2D sceneDepth;
+// Scene pixels along each axis for every pixel of the clouds
+uniform int divisor;
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    // One scene pixel from the middle of the block, rather than a blend of
+    // them, so the depth is always that of something actually there
+    ivec2 size = textureSize(sceneDepth, 0);
+    ivec2 pixel = min(ivec2(gl_FragCoord.xy) * divisor + divisor / 2, size - 1);
+    float depth = texelFetch(sceneDepth, pixel, 0).r;
+
+    FragColor = vec4(depth);
+    gl_FragDepth = depth;
+}
@@ -0,0 +1,25 @@
+#version 430
+
+// The depth of the clouds' target after they were drawn, the scene's where
+// the shell wasn't in front of it
+uniform sampler2D shellDepth;
+// The scene depth the clouds were tested against
+uniform sampler2D cloudDepth;
+// Scene pixels along each axis for every pixel of the clouds
+uniform int divisor;
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    // The cloud pixel this one is in, as the downsample picked them
+    ivec2 pixel = min(ivec2(gl_FragCoord.xy) / divisor, textureSize(shellDepth, 0) - 1);
+    float shell = texelFetch(shellDepth, pixel, 0).r;
+
+    // Leaves the scene's depth where the shell didn't cover it, the depth
+    // test keeps it where the scene is in front at this size
+    if (shell >= texelFetch(cloudDepth, pixel, 0).r) {
+        discard;
+    }
+    gl_FragDepth = shell;
+}
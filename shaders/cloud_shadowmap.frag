@@ -1,12 +1,15 @@
 #version 400
-layout( location = 0 ) out vec4 FragColor;
+layout( location = 0 ) out vec2 result;
 
 in vec3 Position;
 in vec3 vPos;
 in vec3 Normal;
 
-uniform vec3 sunPos;
-uniform float time;
+#include "frame.glsl"
+uniform float density;
+
+#include "wind.glsl"
+uniform mat4 shadowmap_v;
 
 const float shininess = 1.0;
 
@@ -185,30 +188,12 @@ float fbm(vec4 x) {
 ///////////////////////////////////////////////////////////////////////////////////////////////////////////
 
 void main () {
-  vec3 X = dFdx(Position);
-  vec3 Y = dFdy(Position);
-  vec3 normal = normalize(cross(X,Y));
-
-  ///////////////////////////////////////////////////////////////////////////
-  // Color
-
-  float noise = abs(smoothstep(0.1, 0.9, fbm(vec4(vPos, time * 0.01))));
-  vec4 color = vec4(1.f, 1.f, 1.f, clamp(2 * noise, 0.f, 1.f)); 
-
-  ////////////////////////////////////////////////////////////////////////////
-  // Lighting
-  vec3 lightDir = normalize(sunPos - Position);
-  vec3 viewDir  = normalize(-Position);
-  
-  lightDir = normalize(lightDir);
-
-  //Diffuse part-----------
-  float diff = max(dot(lightDir, normal), 0.0);
-  vec4 diffuse = diff * color;
-
-  // Ambient-------------
-  vec4 ambient = 0.3 * color;
-  
-  vec4 resultLight = ambient + diffuse;
-  FragColor = resultLight;
+  // Only the dense parts of the cloud layer cast a shadow
+  float noise = abs(smoothstep(0.1, 0.9, fbm(vec4(windAdvect(vPos), time * 0.01))));
+  if (clamp(2 * noise * density, 0.f, 1.f) < 0.5) {
+    discard;
+  }
+
+  vec4 ShadowPos = shadowmap_v * vec4(Position, 1.0);
+  result = vec2(ShadowPos.z + 10000.0, 1.0f);
 }
\ No newline at end of file
@@ -0,0 +1,53 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+#include "frame.glsl"
+uniform sampler2D clouds;
+uniform sampler2D cloudDepth;
+uniform sampler2D sceneDepth;
+// How far apart, relative to the distance, the scene behind a cloud pixel
+// and behind this pixel can be and still be the same surface
+uniform float depthTolerance;
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+// Distance from the eye of a depth buffer value
+float linearDepth(float depth)
+{
+    return P[3][2] / (2.0 * depth - 1.0 + P[2][2]);
+}
+
+void main ()
+{
+    float depth = linearDepth(texelFetch(sceneDepth, ivec2(gl_FragCoord.xy), 0).r);
+
+    // The four cloud pixels around this one, as bilinear filtering has them
+    ivec2 size = textureSize(clouds, 0);
+    vec2 pos = gl_FragCoord.xy * vec2(size) / viewport - 0.5;
+    ivec2 base = ivec2(floor(pos));
+    vec2 f = fract(pos);
+
+    vec4 blended = vec4(0.0);
+    vec4 nearest = vec4(0.0);
+    float nearestDifference = 1e30;
+    bool edge = false;
+    for (int i = 0; i < 4; i++) {
+        ivec2 offset = ivec2(i & 1, i >> 1);
+        ivec2 pixel = clamp(base + offset, ivec2(0), size - 1);
+        vec4 color = texelFetch(clouds, pixel, 0);
+
+        vec2 weights = mix(1.0 - f, f, vec2(offset));
+        blended += color * weights.x * weights.y;
+
+        float difference = abs(linearDepth(texelFetch(cloudDepth, pixel, 0).r) - depth) / depth;
+        if (difference < nearestDifference) {
+            nearestDifference = difference;
+            nearest = color;
+        }
+        edge = edge || difference > depthTolerance;
+    }
+
+    // Across an edge, like the planet's limb against the sky, the blend
+    // would bleed one side into the other, so take the pixel from this side
+    FragColor = edge ? nearest : blended;
+}
@@ -0,0 +1,10 @@
+#version 430
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    // One triangle over the whole target, from the vertex index alone
+    vec2 corner = vec2(gl_VertexID & 1, gl_VertexID >> 1) * 4.0 - 1.0;
+    gl_Position = vec4(corner, 0.0, 1.0);
+}
@@ -0,0 +1,8 @@
+// Mirror of the stretch in math::ray_ellipsoid. The planet is a sphere
+// squashed along its pole by `flattening`, stretching a vector back along
+// the pole turns the planet into that sphere again so the sphere math
+// holds. Linear, so distances along a ray carry over.
+vec3 unflatten(vec3 v, vec3 axis, float flattening)
+{
+    return v + axis * (dot(v, axis) * (1.0 / (1.0 - flattening) - 1.0));
+}
@@ -0,0 +1,12 @@
+// Set once per pass rather than for every draw, see frame.rs
+layout(std140) uniform Frame
+{
+    mat4 P;
+    // In view space, or in world space for the shadowmap
+    vec3 sunPos;
+    float time;
+    // Size of the target in pixels
+    vec2 viewport;
+    // Sunlight relative to 1 AU, 1 unless lit from the real distance
+    float sunIntensity;
+};
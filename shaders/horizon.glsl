@@ -0,0 +1,8 @@
+// Mirror of math::is_beyond_horizon, camera and point in planet-local space.
+bool isBeyondHorizon(vec3 camera, vec3 point, float radius)
+{
+    float radius2 = radius * radius;
+    float cameraHorizon = sqrt(max(dot(camera, camera) - radius2, 0.0));
+    float pointHorizon = sqrt(max(dot(point, point) - radius2, 0.0));
+    return length(point - camera) > cameraHorizon + pointHorizon;
+}
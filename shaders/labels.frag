@@ -0,0 +1,17 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in vec2 Tex;
+in float Fade;
+
+uniform sampler2D atlas;
+uniform vec4 color;
+
+void main ()
+{
+    float alpha = texture(atlas, Tex).a * color.a * Fade;
+    if (alpha <= 0.0) {
+        discard;
+    }
+    FragColor = vec4(color.rgb, alpha);
+}
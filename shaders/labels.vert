@@ -0,0 +1,37 @@
+#version 430
+
+in vec3 label_anchor;
+in vec2 label_offset;
+in vec2 label_tex;
+
+out vec2 Tex;
+out float Fade;
+
+uniform mat4 MV;
+#include "frame.glsl"
+uniform float viewportHeight;
+uniform float lineHeight;
+uniform float size;
+uniform float minPixels;
+uniform float maxPixels;
+uniform float fadeDistance;
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    vec4 anchor = MV * vec4(label_anchor, 1.0);
+    float depth = max(-anchor.z, 1e-6);
+
+    // Lines are `size` high in the world, shrinking with distance, but kept
+    // readable and from filling the screen up close
+    float pixels = size * P[1][1] * 0.5 * viewportHeight / depth;
+    float height = size * clamp(pixels, minPixels, maxPixels) / max(pixels, 1e-6);
+
+    // Billboarded: the offset is along the view space axes
+    vec2 offset = label_offset / lineHeight * height;
+    gl_Position = P * vec4(anchor.xy + offset, anchor.z, 1.0);
+
+    Tex = label_tex;
+    Fade = 1.0 - smoothstep(0.5 * fadeDistance, fadeDistance, length(anchor.xyz));
+}
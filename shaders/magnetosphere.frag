@@ -0,0 +1,11 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in vec4 LineColor;
+
+#include "polyline_coverage.glsl"
+
+void main ()
+{
+    FragColor = vec4(LineColor.rgb, LineColor.a * polylineCoverage());
+}
@@ -0,0 +1,33 @@
+#version 430
+
+out vec4 LineColor;
+
+uniform mat4 MV;
+#include "frame.glsl"
+uniform float radius;
+uniform vec3 sunDir;
+uniform float compression;
+uniform float tail;
+uniform vec4 color;
+
+#include "polyline.glsl"
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+// Squash the lines on the sunward side and drag them out into a tail on the
+// night side. Only the height above the surface is scaled, so the footpoints
+// stay put.
+vec4 distort(vec3 field_pos)
+{
+    float r = length(field_pos);
+    vec3 dir = field_pos / r;
+    float facing = dot(dir, sunDir);
+    float k = 1.0 - compression * max(facing, 0.0) + tail * max(-facing, 0.0) * max(-facing, 0.0);
+    return P * MV * vec4(dir * (1.0 + (r - 1.0) * k) * radius, 1.0);
+}
+
+void main ()
+{
+    LineColor = color * line_color;
+    expandPolyline(distort(line_prev), distort(line_start), distort(line_end), distort(line_next));
+}
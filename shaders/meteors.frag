@@ -0,0 +1,18 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in float Along;
+in float Side;
+in float Fade;
+
+uniform float brightness;
+
+void main ()
+{
+    // Hot white head, cooling to orange along the fading trail
+    float core = 1.0 - Side * Side;
+    float trail = Along * Along;
+    vec3 color = mix(vec3(1.0, 0.45, 0.15), vec3(1.0, 0.95, 0.85), trail);
+
+    FragColor = vec4(color * core * trail * Fade * brightness, 1.0);
+}
@@ -0,0 +1,38 @@
+#version 430
+
+in vec3 meteor_pos;
+in vec3 meteor_axis;
+in float meteor_side;
+in float meteor_along;
+in float meteor_fade;
+
+out float Along;
+out float Side;
+out float Fade;
+
+uniform mat4 MV;
+#include "frame.glsl"
+uniform float width;
+uniform vec3 sunDir;
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    // Widen the streak sideways to the view, perpendicular to its direction
+    vec4 viewPos = MV * vec4(meteor_pos, 1.0);
+    vec3 viewAxis = mat3(MV) * meteor_axis;
+    vec3 side = cross(viewAxis, viewPos.xyz);
+    side /= max(length(side), 1e-12);
+    viewPos.xyz += side * meteor_side * width;
+
+    // Mostly visible on the night side, washed out by daylight
+    float daylight = dot(normalize(meteor_pos), sunDir);
+    float night = 1.0 - smoothstep(-0.1, 0.3, daylight);
+
+    Along = meteor_along;
+    Side = meteor_side;
+    Fade = meteor_fade * mix(0.15, 1.0, night);
+
+    gl_Position = P * viewPos;
+}
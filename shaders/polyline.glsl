@@ -0,0 +1,117 @@
+// Screen-space expansion of the segment quads of a PolylineBatch. The vertex
+// shader transforms the four points of the segment to clip space and passes
+// them to expandPolyline, which places this corner lineWidth pixels across
+// the line whatever the distance.
+
+in vec3 line_prev;
+in vec3 line_start;
+in vec3 line_end;
+in vec3 line_next;
+in vec4 line_color;
+in vec2 line_corner;
+
+// Position in pixels along and across the segment, its length in pixels and
+// whether each end is round rather than mitered
+noperspective out vec2 LinePixel;
+noperspective out float LineLength;
+noperspective out vec2 LineRound;
+
+// The viewport is in frame.glsl, included before this
+uniform float lineWidth;
+uniform float miterLimit;
+
+// Points closer to the camera plane than this are pulled in along the segment
+const float LINE_NEAR = 1e-4;
+
+vec2 lineToPixels(vec4 clip)
+{
+    return (clip.xy / clip.w * 0.5 + 0.5) * viewport;
+}
+
+vec4 lineClipNear(vec4 point, vec4 toward)
+{
+    if (point.w >= LINE_NEAR) {
+        return point;
+    }
+    return mix(point, toward, (LINE_NEAR - point.w) / (toward.w - point.w));
+}
+
+// Offset of the left side of a mitered corner going from direction d0 to d1,
+// false if the corner is too sharp for the miter limit
+bool lineMiter(vec2 d0, vec2 d1, float halfWidth, out vec2 offset)
+{
+    offset = vec2(0.0);
+    vec2 tangent = d0 + d1;
+    if (dot(tangent, tangent) < 1e-6) {
+        return false;
+    }
+    tangent = normalize(tangent);
+    vec2 miter = vec2(-tangent.y, tangent.x);
+    float cosine = dot(miter, vec2(-d0.y, d0.x));
+    if (cosine * miterLimit < 1.0) {
+        return false;
+    }
+    offset = miter * halfWidth / cosine;
+    return true;
+}
+
+// Direction from a to b in pixels, false and along x if they are on top of
+// each other or the neighbour is behind the camera
+bool lineDirection(vec2 a, vec2 b, float w, out vec2 dir)
+{
+    vec2 d = b - a;
+    if (w < LINE_NEAR || dot(d, d) < 1e-6) {
+        dir = vec2(1.0, 0.0);
+        return false;
+    }
+    dir = normalize(d);
+    return true;
+}
+
+void expandPolyline(vec4 clipPrev, vec4 clipStart, vec4 clipEnd, vec4 clipNext)
+{
+    if (clipStart.w < LINE_NEAR && clipEnd.w < LINE_NEAR) {
+        // Entirely behind the camera, outside the clip volume
+        gl_Position = vec4(0.0, 0.0, 2.0, 1.0);
+        return;
+    }
+
+    vec4 start = lineClipNear(clipStart, clipEnd);
+    vec4 end = lineClipNear(clipEnd, clipStart);
+    vec2 a = lineToPixels(start);
+    vec2 b = lineToPixels(end);
+
+    vec2 dir;
+    lineDirection(a, b, 1.0, dir);
+    vec2 normal = vec2(-dir.y, dir.x);
+
+    // One extra pixel all around for the antialiased edge
+    float halfWidth = 0.5 * lineWidth + 1.0;
+
+    vec2 startOffset;
+    vec2 prevDir;
+    bool startMitered = lineDirection(lineToPixels(clipPrev), a, clipPrev.w, prevDir)
+        && lineMiter(prevDir, dir, halfWidth, startOffset);
+
+    vec2 endOffset;
+    vec2 nextDir;
+    bool endMitered = lineDirection(b, lineToPixels(clipNext), clipNext.w, nextDir)
+        && lineMiter(dir, nextDir, halfWidth, endOffset);
+
+    bool atEnd = line_corner.x > 0.5;
+    float side = line_corner.y;
+
+    vec2 pixel;
+    if (atEnd) {
+        pixel = b + (endMitered ? side * endOffset : (side * normal + dir) * halfWidth);
+    } else {
+        pixel = a + (startMitered ? side * startOffset : (side * normal - dir) * halfWidth);
+    }
+
+    LinePixel = vec2(dot(pixel - a, dir), dot(pixel - a, normal));
+    LineLength = length(b - a);
+    LineRound = vec2(startMitered ? 0.0 : 1.0, endMitered ? 0.0 : 1.0);
+
+    vec4 clip = atEnd ? end : start;
+    gl_Position = vec4((pixel / viewport * 2.0 - 1.0) * clip.w, clip.z, clip.w);
+}
@@ -0,0 +1,20 @@
+// Antialiasing for lines expanded by polyline.glsl.
+
+noperspective in vec2 LinePixel;
+noperspective in float LineLength;
+noperspective in vec2 LineRound;
+
+uniform float lineWidth;
+
+// How much of the pixel the line covers, with the unmitered ends rounded
+float polylineCoverage()
+{
+    float distance = abs(LinePixel.y);
+    if (LinePixel.x < 0.0 && LineRound.x > 0.5) {
+        distance = length(LinePixel);
+    }
+    if (LinePixel.x > LineLength && LineRound.y > 0.5) {
+        distance = length(vec2(LinePixel.x - LineLength, LinePixel.y));
+    }
+    return clamp(0.5 * lineWidth + 0.5 - distance, 0.0, 1.0);
+}
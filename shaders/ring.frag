@@ -0,0 +1,53 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in vec3 Position;
+in vec3 Normal;
+in vec2 UV;
+
+#include "frame.glsl"
+// The planet's center in view space, for the shadow it casts on the ring
+uniform vec3 planetCenter;
+uniform float planetRadius;
+// The planet's pole in view space, and how squashed along it it is
+uniform vec3 planetAxis;
+uniform float flattening;
+uniform float opacity;
+
+#include "ellipsoid.glsl"
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+// Bands of dust and ice from the inner edge at 0 to the outer edge at 1,
+// with a few gaps cleared out
+float ringDensity(float u)
+{
+    float bands = 0.6 + 0.25 * sin(u * 97.0) + 0.15 * sin(u * 331.0 + 1.3);
+    float gaps = smoothstep(0.0, 0.015, abs(u - 0.62)) * smoothstep(0.0, 0.006, abs(u - 0.35));
+    float edges = smoothstep(0.0, 0.05, u) * smoothstep(0.0, 0.02, 1.0 - u);
+    return clamp(bands, 0.0, 1.0) * gaps * edges;
+}
+
+void main ()
+{
+    float alpha = opacity * ringDensity(UV.x);
+    // Leave no depth where nothing shows, so the clouds behind a gap still
+    // draw
+    if (alpha < 0.01) {
+        discard;
+    }
+
+    vec3 lightDir = normalize(sunPos - Position);
+
+    // In the planet's shadow when the way to the sun passes through it
+    vec3 toCenter = unflatten(planetCenter - Position, planetAxis, flattening);
+    vec3 toSun = unflatten(lightDir, planetAxis, flattening);
+    float along = dot(toCenter, toSun) / dot(toSun, toSun);
+    float shadow = along > 0.0 && length(toCenter - toSun * along) < planetRadius ? 1.0 : 0.0;
+
+    // Lit from either side, the particles scatter through the plane
+    float diffuse = 0.3 + 0.7 * abs(dot(normalize(Normal), lightDir));
+    vec3 color = vec3(0.85, 0.78, 0.66) * mix(diffuse, 0.05, shadow);
+
+    FragColor = vec4(pow(color, vec3(2.2)), alpha);
+}
@@ -0,0 +1,43 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in vec3 Direction;
+
+uniform float brightness;
+
+#include "terrain.glsl"
+
+// The galactic pole in the stars' space, tilted well away from the planet's
+const vec3 galacticPole = vec3(0.0, 0.8660254, 0.5);
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+float skyNoise(vec3 p)
+{
+    float sum = 0.0;
+    float amplitude = 0.5;
+    for (int i = 0; i < 5; i++) {
+        sum += amplitude * snoise(p);
+        p *= 2.03;
+        amplitude *= 0.5;
+    }
+    return sum;
+}
+
+void main ()
+{
+    vec3 dir = normalize(Direction);
+
+    // The band of the galaxy, brightest at its core and broken up by dust
+    float latitude = dot(dir, galacticPole);
+    float band = exp(-latitude * latitude * 40.0);
+    float core = pow(max(dot(dir, normalize(cross(galacticPole, vec3(1.0, 0.0, 0.0)))), 0.0), 4.0);
+    float dust = smoothstep(-0.2, 0.4, skyNoise(dir * 6.0));
+    float glow = band * (0.4 + 0.6 * core) * (0.35 + 0.65 * dust);
+
+    // Faint colored haze over the whole sky
+    float haze = 0.5 + 0.5 * skyNoise(dir * 2.0 + 7.0);
+    vec3 color = vec3(0.09, 0.08, 0.07) * glow + vec3(0.006, 0.007, 0.014) * haze;
+
+    FragColor = vec4(color * brightness, 1.0);
+}
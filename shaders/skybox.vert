@@ -0,0 +1,17 @@
+#version 430
+
+layout(location = 0) in vec3 sky_pos;
+
+// The view and star rotations with no translation, so the sky stays at
+// infinity
+uniform mat4 V;
+#include "frame.glsl"
+
+out vec3 Direction;
+
+void main ()
+{
+    Direction = sky_pos;
+    // At the far plane, whatever the distance
+    gl_Position = (P * V * vec4(sky_pos, 1.0)).xyww;
+}
@@ -3,6 +3,7 @@
 layout(location = 0) out vec4 color;
 
 in vec2 Position;
+in float Brightness;
 // GLSL textureless classic 2D noise "cnoise",
 // with an RSL-style periodic variant "pnoise".
 // Author:  Stefan Gustavson (stefan.gustavson@liu.se)
@@ -83,5 +84,5 @@ void main ()
 	float r = clamp(cnoise(Position), 0.1f, 1.0f);
 	float g = clamp(cnoise(sin(Position)), 0.1f, 1.0f);
 	float b = clamp(cnoise(cos(Position)), 0.1f, 1.0f);
-    color = vec4(r + b, g + b, b + g, 1.0);
+    color = vec4(vec3(r + b, g + b, b + g) * Brightness, 1.0);
 }
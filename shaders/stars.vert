@@ -1,10 +1,14 @@
 #version 430
 
 layout(location = 0) in vec3 pos;
+layout(location = 1) in float distance;
 
 uniform mat4 mvp;
+// Render units per light year, shrunk by the parallax exaggeration
+uniform float lightYear;
 
 out vec2 Position;
+out float Brightness;
 
 //
 // GLSL textureless classic 3D noise "cnoise",
@@ -120,6 +124,18 @@ void main ()
 {
 	vec4 position = vec4(500.0*pos, 1.0);
 	Position = position.xy;
-    gl_Position = mvp * position;
-    gl_PointSize = clamp(cnoise(position.xyz) * 3.0f, 0.3f, 3.0f);
+
+    // Stars at infinity are directions and only turn with the view, nearby
+    // ones are placed and shift as the camera moves. Either way they go to
+    // the far plane so they're never clipped and stay behind everything.
+    if (distance > 0.0) {
+        gl_Position = mvp * vec4(pos * distance * lightYear, 1.0);
+        Brightness = clamp(pow(10.0 / distance, 2.0), 0.3, 3.0);
+    } else {
+        gl_Position = mvp * vec4(pos, 0.0);
+        Brightness = 1.0;
+    }
+    gl_Position.z = gl_Position.w * 0.999999;
+
+    gl_PointSize = clamp(cnoise(position.xyz) * 3.0f * sqrt(Brightness), 0.3f, 4.0f);
 }
\ No newline at end of file
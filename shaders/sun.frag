@@ -0,0 +1,51 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in vec2 Disc;
+
+uniform sampler2D granulation;
+#include "frame.glsl"
+uniform float intensity;
+uniform float limbDarkening;
+uniform float granulationStrength;
+uniform float granulationPeriod;
+uniform float extent;
+
+const float PI = 3.14159265;
+
+// The same as the planet's specular sun color
+const vec3 SUN_COLOR = vec3(0.9321, 0.97, 0.7039);
+
+void main ()
+{
+    float r = length(Disc);
+    vec3 color = vec3(0.0);
+
+    if (r < 1.0) {
+        // Linear limb darkening, mu is the cosine of the angle between the
+        // line of sight and the surface normal
+        float mu = sqrt(1.0 - r * r);
+        float limb = 1.0 - limbDarkening * (1.0 - mu);
+
+        // Two cell layers that fade into each other and drift apart, the
+        // cells get squashed toward the limb
+        float t = time / granulationPeriod;
+        vec2 uv = Disc / (1.0 + mu) * 1.5;
+        float a = texture(granulation, uv + vec2(0.03, 0.01) * t).r;
+        float b = texture(granulation, uv * 1.2 - vec2(0.01, 0.03) * t).g;
+        float cells = mix(a, b, 0.5 + 0.5 * sin(2.0 * PI * t));
+        float granules = 1.0 + granulationStrength * (cells - 0.5) * mu;
+
+        float edge = clamp((1.0 - r) / max(fwidth(r), 1e-5), 0.0, 1.0);
+        color = SUN_COLOR * intensity * limb * granules * edge;
+    }
+
+    if (extent > 1.0 && r > 0.95) {
+        // Falls off over the corona's reach and is gone at the billboard edge
+        float reach = (r - 1.0) / (extent - 1.0);
+        float glow = exp(-4.0 * max(reach, 0.0)) * (1.0 - smoothstep(0.7, 1.0, reach));
+        color += SUN_COLOR * 0.35 * intensity * glow * smoothstep(0.95, 1.0, r);
+    }
+
+    FragColor = vec4(color, 1.0);
+}
@@ -0,0 +1,30 @@
+#version 430
+
+in vec2 sun_corner;
+
+// Position on the billboard in disc radii
+out vec2 Disc;
+
+uniform mat4 V;
+#include "frame.glsl"
+uniform vec3 sunDir;
+uniform float angularRadius;
+uniform float extent;
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    // Built in view space so the disc always faces the camera upright
+    vec3 dir = normalize(mat3(V) * sunDir);
+    vec3 up = abs(dir.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0);
+    vec3 right = normalize(cross(up, dir));
+    up = cross(dir, right);
+
+    Disc = sun_corner * extent;
+    vec3 pos = dir + (right * Disc.x + up * Disc.y) * tan(angularRadius);
+
+    // A direction like the distant stars, on the far plane behind everything
+    gl_Position = P * vec4(pos, 0.0);
+    gl_Position.z = gl_Position.w * 0.999999;
+}
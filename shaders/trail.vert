@@ -0,0 +1,19 @@
+#version 430
+
+out vec4 LineColor;
+
+uniform mat4 MV;
+#include "frame.glsl"
+uniform vec4 color;
+
+#include "polyline.glsl"
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    mat4 MVP = P * MV;
+    LineColor = color * line_color;
+    expandPolyline(MVP * vec4(line_prev, 1.0), MVP * vec4(line_start, 1.0),
+                   MVP * vec4(line_end, 1.0), MVP * vec4(line_next, 1.0));
+}
@@ -0,0 +1,39 @@
+// Blend weights for projecting a texture along the three axis planes, the
+// larger the sharpness the tighter each plane's contribution gets.
+vec3 triplanarWeights(vec3 normal, float sharpness)
+{
+    vec3 weights = pow(abs(normal), vec3(sharpness));
+    return weights / (weights.x + weights.y + weights.z);
+}
+
+vec4 triplanarSample(sampler2D tex, vec3 pos, vec3 weights)
+{
+    return texture(tex, pos.yz) * weights.x
+        + texture(tex, pos.xz) * weights.y
+        + texture(tex, pos.xy) * weights.z;
+}
+
+// How fast the brightness of `tex` changes along the plane at `uv`, per
+// texture repeat.
+vec2 planeGradient(sampler2D tex, vec2 uv, vec2 texel)
+{
+    const vec3 grey = vec3(1.0 / 3.0);
+    float height = dot(texture(tex, uv).rgb, grey);
+    return vec2(
+        dot(texture(tex, uv + vec2(texel.x, 0.0)).rgb, grey) - height,
+        dot(texture(tex, uv + vec2(0.0, texel.y)).rgb, grey) - height) / texel;
+}
+
+// Gradient along `pos` of the texture's brightness read as a height,
+// projected like triplanarSample. Each plane's gradient lies in that
+// plane, so no tangent frame is needed.
+vec3 triplanarGradient(sampler2D tex, vec3 pos, vec3 weights)
+{
+    vec2 texel = 1.0 / vec2(textureSize(tex, 0));
+    vec2 x = planeGradient(tex, pos.yz, texel);
+    vec2 y = planeGradient(tex, pos.xz, texel);
+    vec2 z = planeGradient(tex, pos.xy, texel);
+    return vec3(0.0, x) * weights.x
+        + vec3(y.x, 0.0, y.y) * weights.y
+        + vec3(z, 0.0) * weights.z;
+}
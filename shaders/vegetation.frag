@@ -0,0 +1,23 @@
+#version 430
+layout(location = 0) out vec4 FragColor;
+
+in vec3 Position;
+in vec3 Normal;
+in vec2 UV;
+
+#include "frame.glsl"
+uniform sampler2D atlas;
+
+void main ()
+{
+    vec4 color = texture(atlas, UV);
+    if (color.a < 0.5) {
+        discard;
+    }
+
+    vec3 lightDir = normalize(sunPos - Position);
+    float diff = max(dot(lightDir, normalize(Normal)), 0.0);
+
+    vec3 resultLight = 0.08 * color.rgb + diff * color.rgb;
+    FragColor = vec4(pow(resultLight, vec3(2.2)), 1.0);
+}
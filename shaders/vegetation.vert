@@ -0,0 +1,53 @@
+#version 430
+
+in vec2 corner;
+in float plane;
+
+in vec3 instance_pos;
+in float instance_scale;
+in float instance_rotation;
+in float instance_type;
+in float instance_rank;
+
+out vec3 Position;
+out vec3 Normal;
+out vec2 UV;
+
+uniform mat4 MV;
+#include "frame.glsl"
+uniform float size;
+uniform float viewDistance;
+uniform vec3 cameraLocal;
+uniform float horizonRadius;
+
+#include "horizon.glsl"
+
+//////////////////////////////////////////////////////////////////////////////////////////
+
+void main ()
+{
+    // Local tangent frame on the surface, the two quads of the cross are
+    // rotated a quarter turn apart around the up axis
+    vec3 up = normalize(instance_pos);
+    vec3 tangent = normalize(cross(abs(up.y) < 0.99 ? vec3(0.0, 1.0, 0.0) : vec3(1.0, 0.0, 0.0), up));
+    vec3 bitangent = cross(up, tangent);
+    float angle = instance_rotation + plane * 1.5707963;
+    vec3 side = cos(angle) * tangent + sin(angle) * bitangent;
+
+    vec3 localPos = instance_pos + (side * corner.x + up * corner.y) * size * instance_scale;
+    vec4 viewPos = MV * vec4(localPos, 1.0);
+
+    Position = viewPos.xyz;
+    Normal = normalize(mat3(MV) * up);
+    UV = vec2((corner.x + 0.5 + instance_type) * 0.5, corner.y);
+
+    gl_Position = P * viewPos;
+
+    // Thin out with distance by dropping the instances whose rank is above
+    // the density left at this range, and the ones behind the planet
+    float dist = length(vec3(MV * vec4(instance_pos, 1.0)));
+    float density = 1.0 - smoothstep(0.5 * viewDistance, viewDistance, dist);
+    if (instance_rank >= density || isBeyondHorizon(cameraLocal, instance_pos, horizonRadius)) {
+        gl_Position = vec4(0.0, 0.0, -2.0, 1.0);
+    }
+}
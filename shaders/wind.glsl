@@ -0,0 +1,20 @@
+// Zonal wind profile in m/s, eastward, from the south to the north pole
+uniform sampler1D windProfile;
+uniform float windTime;
+uniform float windShellRadius;
+
+// Where the cloud pattern at pos was windTime simulated seconds ago. Each
+// latitude circle turns around the spin axis at its own angular speed.
+vec3 windAdvect(vec3 pos)
+{
+    vec3 dir = normalize(pos);
+    float latitude = asin(clamp(dir.y, -1.0, 1.0));
+    float speed = texture(windProfile, latitude / 3.14159265 + 0.5).r;
+
+    float circleRadius = max(cos(latitude), 0.01) * windShellRadius;
+    float angle = -speed * windTime / circleRadius;
+
+    float c = cos(angle);
+    float s = sin(angle);
+    return vec3(c * pos.x + s * pos.z, pos.y, -s * pos.x + c * pos.z);
+}
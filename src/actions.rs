@@ -0,0 +1,147 @@
+use crate::units::PRESETS;
+use crate::workspace::BUILTIN_NAMES;
+
+/// Everything that can be triggered from a hotkey, a button or the command
+/// palette. Each goes through `run_action` in main.rs.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    OpenCommandPalette,
+    ReloadShaders,
+    SaveDebugCapture,
+    SaveScreenshot,
+    SaveSceneScreenshot,
+    RunBenchmark,
+    StartTimeLapse,
+    ToggleRecording,
+    ApplyPreset(usize),
+    ApplyWorkspace(usize),
+    ToggleSurfaceView,
+    ToggleDetail,
+    ToggleVegetation,
+    RegenerateVegetation,
+    ToggleMeteors,
+    MeteorStorm,
+    ToggleMagnetosphere,
+    LaunchSpacecraft,
+    ToggleFollowSpacecraft,
+    ToggleSatelliteView,
+    ToggleFreeFly,
+    ToggleMouseLook,
+    ResetView,
+    ToggleFullscreen,
+    TogglePause,
+    StepFrame,
+    RecallBookmark(usize),
+    ToggleOcclusionSkip,
+    ToggleLabels,
+}
+
+impl Action {
+    pub fn all() -> Vec<Action> {
+        let mut actions = vec![
+            Action::OpenCommandPalette,
+            Action::ReloadShaders,
+            Action::SaveDebugCapture,
+            Action::SaveScreenshot,
+            Action::SaveSceneScreenshot,
+            Action::RunBenchmark,
+            Action::StartTimeLapse,
+            Action::ToggleRecording,
+        ];
+        actions.extend((0..PRESETS.len()).map(Action::ApplyPreset));
+        actions.extend((0..BUILTIN_NAMES.len()).map(Action::ApplyWorkspace));
+        actions.extend_from_slice(&[
+            Action::ToggleSurfaceView,
+            Action::ToggleDetail,
+            Action::ToggleVegetation,
+            Action::RegenerateVegetation,
+            Action::ToggleMeteors,
+            Action::MeteorStorm,
+            Action::ToggleMagnetosphere,
+            Action::LaunchSpacecraft,
+            Action::ToggleFollowSpacecraft,
+            Action::ToggleSatelliteView,
+            Action::ToggleFreeFly,
+            Action::ToggleMouseLook,
+            Action::ResetView,
+            Action::ToggleFullscreen,
+            Action::TogglePause,
+            Action::StepFrame,
+            Action::ToggleOcclusionSkip,
+            Action::ToggleLabels,
+        ]);
+        actions
+    }
+
+    pub fn name(self) -> String {
+        match self {
+            Action::OpenCommandPalette => "Open command palette".to_string(),
+            Action::ReloadShaders => "Reload all shaders".to_string(),
+            Action::SaveDebugCapture => "Save debug capture".to_string(),
+            Action::SaveScreenshot => "Save screenshot".to_string(),
+            Action::SaveSceneScreenshot => "Save screenshot without the UI".to_string(),
+            Action::RunBenchmark => "Run benchmark".to_string(),
+            Action::StartTimeLapse => "Start time-lapse".to_string(),
+            Action::ToggleRecording => "Start/stop recording frames".to_string(),
+            Action::ApplyPreset(index) => format!("Switch preset: {}", PRESETS[index].name),
+            Action::ApplyWorkspace(index) => match BUILTIN_NAMES.get(index) {
+                Some(name) => format!("Switch workspace: {}", name),
+                None => format!("Switch workspace {}", index + 1),
+            },
+            Action::ToggleSurfaceView => "Toggle surface view".to_string(),
+            Action::ToggleDetail => "Toggle detail textures".to_string(),
+            Action::ToggleVegetation => "Toggle vegetation".to_string(),
+            Action::RegenerateVegetation => "Regenerate vegetation".to_string(),
+            Action::ToggleMeteors => "Toggle meteors".to_string(),
+            Action::MeteorStorm => "Start meteor storm".to_string(),
+            Action::ToggleMagnetosphere => "Toggle magnetosphere field lines".to_string(),
+            Action::LaunchSpacecraft => "Launch spacecraft".to_string(),
+            Action::ToggleFollowSpacecraft => "Toggle following the spacecraft".to_string(),
+            Action::ToggleSatelliteView => "Toggle satellite view".to_string(),
+            Action::ToggleFreeFly => "Toggle free-fly camera".to_string(),
+            Action::ToggleMouseLook => "Toggle mouse look".to_string(),
+            Action::ResetView => "Reset view".to_string(),
+            Action::ToggleFullscreen => "Toggle fullscreen".to_string(),
+            Action::TogglePause => "Pause/resume animation".to_string(),
+            Action::StepFrame => "Step animation one frame".to_string(),
+            Action::RecallBookmark(index) => format!("Recall bookmark {}", index + 1),
+            Action::ToggleOcclusionSkip => "Toggle skipping occluded passes".to_string(),
+            Action::ToggleLabels => "Toggle labels".to_string(),
+        }
+    }
+
+    /// Name in bindings.toml.
+    pub fn id(self) -> String {
+        match self {
+            Action::OpenCommandPalette => "open_command_palette".to_string(),
+            Action::ReloadShaders => "reload_shaders".to_string(),
+            Action::SaveDebugCapture => "save_debug_capture".to_string(),
+            Action::SaveScreenshot => "save_screenshot".to_string(),
+            Action::SaveSceneScreenshot => "save_scene_screenshot".to_string(),
+            Action::RunBenchmark => "run_benchmark".to_string(),
+            Action::StartTimeLapse => "start_time_lapse".to_string(),
+            Action::ToggleRecording => "toggle_recording".to_string(),
+            Action::ApplyPreset(index) => format!("apply_preset_{}", index + 1),
+            Action::ApplyWorkspace(index) => format!("apply_workspace_{}", index + 1),
+            Action::ToggleSurfaceView => "toggle_surface_view".to_string(),
+            Action::ToggleDetail => "toggle_detail".to_string(),
+            Action::ToggleVegetation => "toggle_vegetation".to_string(),
+            Action::RegenerateVegetation => "regenerate_vegetation".to_string(),
+            Action::ToggleMeteors => "toggle_meteors".to_string(),
+            Action::MeteorStorm => "meteor_storm".to_string(),
+            Action::ToggleMagnetosphere => "toggle_magnetosphere".to_string(),
+            Action::LaunchSpacecraft => "launch_spacecraft".to_string(),
+            Action::ToggleFollowSpacecraft => "toggle_follow_spacecraft".to_string(),
+            Action::ToggleSatelliteView => "toggle_satellite_view".to_string(),
+            Action::ToggleFreeFly => "toggle_free_fly".to_string(),
+            Action::ToggleMouseLook => "toggle_mouse_look".to_string(),
+            Action::ResetView => "reset_view".to_string(),
+            Action::ToggleFullscreen => "toggle_fullscreen".to_string(),
+            Action::TogglePause => "toggle_pause".to_string(),
+            Action::StepFrame => "step_frame".to_string(),
+            Action::RecallBookmark(index) => format!("recall_bookmark_{}", index + 1),
+            Action::ToggleOcclusionSkip => "toggle_occlusion_skip".to_string(),
+            Action::ToggleLabels => "toggle_labels".to_string(),
+        }
+    }
+}
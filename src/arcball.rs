@@ -0,0 +1,123 @@
+use cgmath::{vec3, Deg, InnerSpace, Matrix3, Quaternion, Rotation3, Vector3};
+
+/// Point in view space on a unit sphere filling the smaller side of a
+/// `size` pixel window, under the pixel at `cursor`. Off the sphere the
+/// cursor is taken to its edge.
+pub fn sphere_point(cursor: (i32, i32), size: (u32, u32)) -> Vector3<f32> {
+    let extent = size.0.min(size.1).max(1) as f32;
+    let x = (2.0 * cursor.0 as f32 - size.0 as f32) / extent;
+    // Pixel rows go down, view space y goes up
+    let y = (size.1 as f32 - 2.0 * cursor.1 as f32) / extent;
+
+    let length2 = x * x + y * y;
+    if length2 > 1.0 {
+        vec3(x, y, 0.0) / length2.sqrt()
+    } else {
+        vec3(x, y, (1.0 - length2).sqrt())
+    }
+}
+
+/// The rotation dragging from `from` to `to`, both in view space, turns
+/// the world by. `view` is the rotation of the world-to-eye transform.
+pub fn drag_rotation(from: Vector3<f32>, to: Vector3<f32>, view: Matrix3<f32>) -> Quaternion<f32> {
+    let view = Quaternion::from(view);
+    // Too short a drag to have an axis
+    if (to - from).magnitude2() < 1e-10 {
+        return Quaternion::new(1.0, 0.0, 0.0, 0.0);
+    }
+    let arc = Quaternion::from_arc(from, to, None);
+    (view.conjugate() * arc * view).normalize()
+}
+
+/// `rotation` turned further by `turn` in world space. Renormalized, so a
+/// long drag doesn't let rounding errors creep into the length.
+pub fn compose(turn: Quaternion<f32>, rotation: Quaternion<f32>) -> Quaternion<f32> {
+    (turn * rotation).normalize()
+}
+
+/// `rotation` spun by `angle` around its own pole, the y axis.
+pub fn spin(rotation: Quaternion<f32>, angle: Deg<f32>) -> Quaternion<f32> {
+    (rotation * Quaternion::from_angle_y(angle)).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Matrix4, SquareMatrix};
+
+    const SIZE: (u32, u32) = (1280, 720);
+
+    fn is_unit(rotation: Quaternion<f32>) -> bool {
+        (rotation.magnitude() - 1.0).abs() < 1e-5
+    }
+
+    #[test]
+    fn sphere_points_are_on_the_unit_sphere() {
+        assert!((sphere_point((640, 360), SIZE) - vec3(0.0, 0.0, 1.0)).magnitude() < 1e-6);
+        for x in (0..1280).step_by(37) {
+            for y in (0..720).step_by(29) {
+                let point = sphere_point((x, y), SIZE);
+                assert!((point.magnitude() - 1.0).abs() < 1e-5);
+                assert!(point.z >= 0.0);
+            }
+        }
+        // Up the window is up the view
+        assert!(sphere_point((640, 100), SIZE).y > 0.0);
+        // Off the sphere the point is on its edge
+        assert_eq!(sphere_point((0, 360), SIZE), vec3(-1.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn drags_turn_from_onto_to() {
+        let view = Matrix3::identity();
+        let from = sphere_point((600, 300), SIZE);
+        let to = sphere_point((700, 420), SIZE);
+        let turn = drag_rotation(from, to, view);
+        assert!((turn * from - to).magnitude() < 1e-5);
+        assert_eq!(
+            drag_rotation(from, from, view),
+            Quaternion::new(1.0, 0.0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn long_drags_stay_normalized() {
+        let view = Matrix3::from(Quaternion::from_angle_x(Deg(-30.0)));
+        let mut rotation = Quaternion::from_angle_z(Deg(10.0));
+        let mut cursor = (640, 360);
+        for step in 0..100_000 {
+            // Wander round the window a few pixels at a time
+            let next = (
+                640 + ((step as f32 * 0.013).sin() * 500.0) as i32,
+                360 + ((step as f32 * 0.021).cos() * 300.0) as i32,
+            );
+            let turn = drag_rotation(sphere_point(cursor, SIZE), sphere_point(next, SIZE), view);
+            rotation = compose(turn, rotation);
+            cursor = next;
+            assert!(
+                is_unit(rotation),
+                "{} long after {} steps",
+                rotation.magnitude(),
+                step
+            );
+        }
+        // Still a rotation, it keeps lengths and handedness
+        let matrix = Matrix4::from(rotation);
+        assert!((matrix.determinant() - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn long_spins_stay_normalized_and_keep_the_pole() {
+        let start = Quaternion::from_angle_x(Deg(23.5));
+        let pole = start * vec3(0.0, 1.0, 0.0);
+        let mut rotation = start;
+        // A hundred turns in small steps
+        for _ in 0..100_000 {
+            rotation = spin(rotation, Deg(0.36));
+            assert!(is_unit(rotation));
+        }
+        assert!((rotation * vec3(0.0, 1.0, 0.0) - pole).magnitude() < 1e-4);
+        let back = rotation * vec3(1.0, 0.0, 0.0) - start * vec3(1.0, 0.0, 0.0);
+        assert!(back.magnitude() < 1e-2);
+    }
+}
@@ -0,0 +1,507 @@
+use crate::clock::Clock;
+use crate::detail::DetailSettings;
+use crate::magnetosphere::MagnetosphereSettings;
+use crate::meteors::MeteorSettings;
+use crate::spin::SunCycle;
+use crate::stutter::Span;
+use crate::surface::SurfaceView;
+use crate::units::PRESETS;
+use crate::vegetation::VegetationSettings;
+use crate::{capture, State};
+use cgmath::{Deg, Quaternion, Rotation3};
+use glium::backend::Facade;
+use image::RgbaImage;
+use std::error;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Length of the scripted run in simulated seconds and the fixed step it is
+// simulated with, whatever the frame rate
+pub const DURATION: f32 = 30.0;
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+
+// Frames rendered at the start pose before measuring, they pay for uploads
+// and driver warmup
+const WARMUP_FRAMES: u32 = 30;
+
+// The camera orbits for the first half and stands on the surface for the
+// rest
+const ORBIT_DURATION: f32 = 15.0;
+
+const PRESET: usize = 0;
+
+struct FrameSample {
+    frame_ms: f32,
+    cpu_ms: f32,
+    // The latest pass times read back, a frame or two behind
+    gpu: Vec<Span>,
+}
+
+pub struct PassCost {
+    pub name: &'static str,
+    pub average_ms: f32,
+    pub max_ms: f32,
+}
+
+/// How far the run's last frame is from a golden image of it.
+pub struct ImageDifference {
+    // Pixels with any channel off
+    pub pixels: usize,
+    // The largest difference in any channel, in 8 bit steps
+    pub max: u8,
+}
+
+pub struct BenchmarkReport {
+    pub path: PathBuf,
+    pub frames: usize,
+    pub average_fps: f32,
+    pub low_fps: f32,
+    pub average_cpu_ms: f32,
+    pub average_gpu_ms: f32,
+    pub passes: Vec<PassCost>,
+    pub renderer: String,
+    pub resolution: (u32, u32),
+    pub settings_hash: u64,
+    pub golden: Option<Result<ImageDifference, String>>,
+}
+
+/// A scripted camera path over the default preset at fixed settings,
+/// simulated with a fixed timestep so that two runs render the same frames
+/// and only the time they take differs.
+pub struct Benchmark {
+    pub report: Option<BenchmarkReport>,
+    pub exit_when_done: bool,
+    // The last frame of an earlier run, to check this one renders the same
+    pub golden: Option<PathBuf>,
+    frame: Option<u32>,
+    samples: Vec<FrameSample>,
+    settings_hash: u64,
+}
+
+impl Benchmark {
+    pub fn new() -> Benchmark {
+        Benchmark {
+            report: None,
+            exit_when_done: false,
+            golden: None,
+            frame: None,
+            samples: Vec::new(),
+            settings_hash: 0,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.frame.is_some()
+    }
+
+    fn frame_count() -> u32 {
+        WARMUP_FRAMES + (DURATION / TIMESTEP).round() as u32
+    }
+
+    /// How far along the run is, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        self.frame
+            .map_or(0.0, |frame| frame as f32 / Benchmark::frame_count() as f32)
+    }
+
+    /// Simulated seconds into the scripted path, or `None` when not running.
+    pub fn time(&self) -> Option<f32> {
+        self.frame
+            .map(|frame| frame.saturating_sub(WARMUP_FRAMES) as f32 * TIMESTEP)
+    }
+}
+
+/// Puts the scene into the benchmark's fixed state and starts the run.
+pub fn start<F: Facade>(facade: &F, p: &mut State) -> Result<(), Box<dyn error::Error>> {
+    p.apply_preset(PRESET);
+    p.detail = DetailSettings::new();
+    p.reset_view();
+    p.surface = SurfaceView::new();
+    p.clock = Clock::new();
+    p.auto_rotate.enabled = false;
+    p.sun_cycle = SunCycle::new();
+    p.occlusion_auto_skip = false;
+
+    p.vegetation.settings = VegetationSettings::new();
+    p.vegetation.regenerate();
+    p.vegetation.wait(facade)?;
+
+    p.meteors.settings = MeteorSettings::new();
+    p.meteors.reset();
+    p.spacecraft.reset();
+    p.magnetosphere.settings = MagnetosphereSettings::new();
+    p.magnetosphere.regenerate();
+
+    p.benchmark.frame = Some(0);
+    p.benchmark.samples.clear();
+    p.benchmark.report = None;
+    pose(p);
+
+    // Hashed after posing so the path's own state is part of it, the
+    // resolution is reported on its own
+    p.benchmark.settings_hash = capture::settings(p, 0, 0, 0.0)
+        .iter()
+        .fold(FNV_OFFSET, |hash, (key, value)| {
+            fnv1a(hash, format!("{}={}\n", key, value).as_bytes())
+        });
+
+    Ok(())
+}
+
+/// Places the camera and the sun where the path has them at this frame.
+pub fn pose(p: &mut State) {
+    let time = match p.benchmark.time() {
+        Some(time) => time,
+        None => return,
+    };
+
+    p.view.rotation = Quaternion::from_angle_y(Deg(360.0 * time / ORBIT_DURATION));
+    p.view.sun_angle = -45.0 + 90.0 * time / DURATION;
+
+    if time < ORBIT_DURATION {
+        p.surface.enabled = false;
+    } else {
+        // Turn a full circle looking out over the horizon
+        let turn = (time - ORBIT_DURATION) / (DURATION - ORBIT_DURATION);
+        p.surface.enabled = true;
+        p.surface.heading = -180.0 + 360.0 * turn;
+        p.surface.pitch = 10.0;
+    }
+
+    p.update_sun_pos();
+}
+
+/// Records the frame that was just presented and advances the run. Returns
+/// where the report was written once the last frame is in.
+pub fn record<F: Facade>(
+    facade: &F,
+    p: &mut State,
+    frame_ms: f32,
+    cpu_ms: f32,
+    resolution: (u32, u32),
+) -> Result<Option<PathBuf>, Box<dyn error::Error>> {
+    let frame = match p.benchmark.frame {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+
+    if frame >= WARMUP_FRAMES {
+        let gpu = p.gpu_spans();
+        p.benchmark.samples.push(FrameSample {
+            frame_ms,
+            cpu_ms,
+            gpu,
+        });
+    }
+
+    if frame + 1 < Benchmark::frame_count() {
+        p.benchmark.frame = Some(frame + 1);
+        return Ok(None);
+    }
+
+    // The last frame again without the UI, for comparing runs
+    let time = p.benchmark.time().unwrap_or(0.0);
+    let pixels = capture::render_image(facade, p, resolution.0, resolution.1, time)?;
+    let last = RgbaImage::from_raw(resolution.0, resolution.1, pixels)
+        .ok_or("the last frame doesn't match the resolution")?;
+
+    p.benchmark.frame = None;
+    let report = write_report(facade, &p.benchmark, resolution, &last)?;
+    let path = report.path.clone();
+    p.benchmark.report = Some(report);
+    Ok(Some(path))
+}
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+// Stable across builds and platforms, unlike the standard library's hasher
+fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100_0000_01b3)
+    })
+}
+
+/// Compares two images pixel for pixel.
+pub fn compare_images(frame: &RgbaImage, golden: &RgbaImage) -> Result<ImageDifference, String> {
+    if frame.dimensions() != golden.dimensions() {
+        return Err(format!(
+            "the frame is {}x{} but the golden image is {}x{}",
+            frame.width(),
+            frame.height(),
+            golden.width(),
+            golden.height()
+        ));
+    }
+    let mut difference = ImageDifference { pixels: 0, max: 0 };
+    for (a, b) in frame.pixels().zip(golden.pixels()) {
+        let off =
+            a.0.iter()
+                .zip(&b.0)
+                .map(|(a, b)| a.abs_diff(*b))
+                .max()
+                .unwrap_or(0);
+        if off > 0 {
+            difference.pixels += 1;
+            difference.max = difference.max.max(off);
+        }
+    }
+    Ok(difference)
+}
+
+fn average(values: impl Iterator<Item = f32>) -> f32 {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), value| (sum + value, count + 1));
+    if count == 0 {
+        0.0
+    } else {
+        sum / count as f32
+    }
+}
+
+pub fn json_string(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn json_array(values: impl Iterator<Item = f32>) -> String {
+    values
+        .map(|value| format!("{:.3}", value))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Summarizes the samples and writes `benchmarks/benchmark_<timestamp>`
+/// as JSON, with every frame, and as markdown for sharing, and the `last`
+/// frame as PNG.
+fn write_report<F: Facade>(
+    facade: &F,
+    benchmark: &Benchmark,
+    resolution: (u32, u32),
+    last: &RgbaImage,
+) -> Result<BenchmarkReport, Box<dyn error::Error>> {
+    let samples = &benchmark.samples;
+
+    let total_ms: f32 = samples.iter().map(|sample| sample.frame_ms).sum();
+    let average_fps = if total_ms > 0.0 {
+        1000.0 * samples.len() as f32 / total_ms
+    } else {
+        0.0
+    };
+
+    // The frame rate over the slowest 1% of frames
+    let low_fps = {
+        let mut frame_ms: Vec<f32> = samples.iter().map(|sample| sample.frame_ms).collect();
+        frame_ms.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        let slowest = average(frame_ms.iter().cloned().take(frame_ms.len().div_ceil(100)));
+        if slowest > 0.0 {
+            1000.0 / slowest
+        } else {
+            0.0
+        }
+    };
+
+    let names: Vec<&'static str> = samples.first().map_or(Vec::new(), |sample| {
+        sample.gpu.iter().map(|span| span.name).collect()
+    });
+    let passes: Vec<PassCost> = names
+        .iter()
+        .enumerate()
+        .map(|(i, &name)| {
+            let times = || samples.iter().map(move |sample| sample.gpu[i].milliseconds);
+            PassCost {
+                name,
+                average_ms: average(times()),
+                max_ms: times().fold(0.0, f32::max),
+            }
+        })
+        .collect();
+    let gpu_ms =
+        |sample: &FrameSample| -> f32 { sample.gpu.iter().map(|span| span.milliseconds).sum() };
+
+    let context = facade.get_context();
+    let renderer = context.get_opengl_renderer_string().to_string();
+    let gl_version = context.get_opengl_version_string().to_string();
+
+    let report = BenchmarkReport {
+        path: PathBuf::new(),
+        frames: samples.len(),
+        average_fps,
+        low_fps,
+        average_cpu_ms: average(samples.iter().map(|sample| sample.cpu_ms)),
+        average_gpu_ms: average(samples.iter().map(gpu_ms)),
+        passes,
+        renderer,
+        resolution,
+        settings_hash: benchmark.settings_hash,
+        golden: benchmark.golden.as_ref().map(|golden| {
+            image::open(golden)
+                .map_err(|e| format!("{}: {}", golden.display(), e))
+                .and_then(|image| compare_images(last, &image.to_rgba8()))
+        }),
+    };
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let dir = PathBuf::from("benchmarks");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("benchmark_{}.json", timestamp));
+    last.save(path.with_extension("png"))?;
+
+    let passes_json = report
+        .passes
+        .iter()
+        .map(|pass| {
+            format!(
+                "{{\"name\": {}, \"average_ms\": {:.3}, \"max_ms\": {:.3}}}",
+                json_string(pass.name),
+                pass.average_ms,
+                pass.max_ms
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut file = fs::File::create(&path)?;
+    writeln!(file, "{{")?;
+    writeln!(
+        file,
+        "  \"version\": {},",
+        json_string(env!("CARGO_PKG_VERSION"))
+    )?;
+    writeln!(file, "  \"preset\": {},", json_string(PRESETS[PRESET].name))?;
+    writeln!(file, "  \"renderer\": {},", json_string(&report.renderer))?;
+    writeln!(file, "  \"gl_version\": {},", json_string(&gl_version))?;
+    writeln!(file, "  \"width\": {},", report.resolution.0)?;
+    writeln!(file, "  \"height\": {},", report.resolution.1)?;
+    writeln!(
+        file,
+        "  \"settings_hash\": \"{:016x}\",",
+        report.settings_hash
+    )?;
+    writeln!(file, "  \"duration_s\": {},", DURATION)?;
+    writeln!(file, "  \"timestep_s\": {},", TIMESTEP)?;
+    writeln!(file, "  \"frames\": {},", report.frames)?;
+    writeln!(file, "  \"average_fps\": {:.2},", report.average_fps)?;
+    writeln!(file, "  \"low_1_percent_fps\": {:.2},", report.low_fps)?;
+    writeln!(file, "  \"average_cpu_ms\": {:.3},", report.average_cpu_ms)?;
+    writeln!(file, "  \"average_gpu_ms\": {:.3},", report.average_gpu_ms)?;
+    match report.golden {
+        Some(Ok(ref difference)) => writeln!(
+            file,
+            "  \"golden\": {{\"differing_pixels\": {}, \"max_difference\": {}}},",
+            difference.pixels, difference.max
+        )?,
+        Some(Err(ref e)) => writeln!(file, "  \"golden\": {{\"error\": {}}},", json_string(e))?,
+        None => {}
+    }
+    writeln!(file, "  \"passes\": [{}],", passes_json)?;
+    writeln!(
+        file,
+        "  \"frame_ms\": [{}],",
+        json_array(samples.iter().map(|sample| sample.frame_ms))
+    )?;
+    writeln!(
+        file,
+        "  \"cpu_ms\": [{}],",
+        json_array(samples.iter().map(|sample| sample.cpu_ms))
+    )?;
+    writeln!(
+        file,
+        "  \"gpu_ms\": [{}]",
+        json_array(samples.iter().map(gpu_ms))
+    )?;
+    writeln!(file, "}}")?;
+
+    let mut file = fs::File::create(path.with_extension("md"))?;
+    writeln!(file, "# Benchmark {}", timestamp)?;
+    writeln!(file)?;
+    writeln!(file, "| | |")?;
+    writeln!(file, "|---|---|")?;
+    writeln!(file, "| Renderer | {} |", report.renderer)?;
+    writeln!(file, "| OpenGL | {} |", gl_version)?;
+    writeln!(
+        file,
+        "| Resolution | {}x{} |",
+        report.resolution.0, report.resolution.1
+    )?;
+    writeln!(file, "| Settings hash | `{:016x}` |", report.settings_hash)?;
+    writeln!(
+        file,
+        "| Run | {} s of {} at {:.0} Hz, {} frames |",
+        DURATION,
+        PRESETS[PRESET].name,
+        1.0 / TIMESTEP,
+        report.frames
+    )?;
+    if let Some(ref golden) = report.golden {
+        writeln!(file, "| Golden image | {} |", describe_golden(golden))?;
+    }
+    writeln!(file)?;
+    writeln!(file, "| Average FPS | 1% low FPS | CPU (ms) | GPU (ms) |")?;
+    writeln!(file, "|---:|---:|---:|---:|")?;
+    writeln!(
+        file,
+        "| {:.1} | {:.1} | {:.2} | {:.2} |",
+        report.average_fps, report.low_fps, report.average_cpu_ms, report.average_gpu_ms
+    )?;
+    writeln!(file)?;
+    writeln!(file, "| GPU pass | Average (ms) | Max (ms) |")?;
+    writeln!(file, "|---|---:|---:|")?;
+    for pass in &report.passes {
+        writeln!(
+            file,
+            "| {} | {:.3} | {:.3} |",
+            pass.name, pass.average_ms, pass.max_ms
+        )?;
+    }
+
+    Ok(BenchmarkReport { path, ..report })
+}
+
+/// A line on how the last frame compared with the golden image.
+pub fn describe_golden(golden: &Result<ImageDifference, String>) -> String {
+    match golden {
+        Ok(difference) if difference.pixels == 0 => "identical".to_string(),
+        Ok(difference) => format!(
+            "{} pixels differ, by up to {}",
+            difference.pixels, difference.max
+        ),
+        Err(e) => e.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn image(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([x as u8, y as u8, 100, 255]))
+    }
+
+    #[test]
+    fn identical_images() {
+        let difference = compare_images(&image(8, 4), &image(8, 4)).unwrap();
+        assert_eq!((difference.pixels, difference.max), (0, 0));
+        assert_eq!(describe_golden(&Ok(difference)), "identical");
+    }
+
+    #[test]
+    fn counts_differing_pixels_and_the_largest_step() {
+        let mut frame = image(8, 4);
+        frame.put_pixel(1, 1, Rgba([1, 1, 103, 255]));
+        frame.put_pixel(7, 3, Rgba([7, 3, 100, 250]));
+        frame.put_pixel(0, 0, Rgba([0, 0, 99, 255]));
+        let difference = compare_images(&frame, &image(8, 4)).unwrap();
+        assert_eq!((difference.pixels, difference.max), (3, 5));
+        assert_eq!(
+            describe_golden(&Ok(difference)),
+            "3 pixels differ, by up to 5"
+        );
+    }
+
+    #[test]
+    fn different_sizes_do_not_compare() {
+        let error = compare_images(&image(8, 4), &image(4, 8)).err().unwrap();
+        assert_eq!(error, "the frame is 8x4 but the golden image is 4x8");
+    }
+}
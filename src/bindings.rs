@@ -0,0 +1,583 @@
+use crate::actions::Action;
+use crate::colors::strip_comment;
+use crate::workspace::BUILTIN_NAMES;
+use glium::glutin::VirtualKeyCode as Key;
+use std::fs;
+use std::io;
+
+const BINDINGS_FILE: &str = "bindings.toml";
+
+// Workspaces and bookmarks past the ones there are at startup can be bound
+// too, saved ones come after the built-in workspaces
+const BINDABLE_WORKSPACES: usize = 9;
+const BINDABLE_BOOKMARKS: usize = 9;
+
+/// Keys held down rather than pressed once, and Escape letting go of the
+/// mouse.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Control {
+    RotateLeft,
+    RotateRight,
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    Sprint,
+    ReleaseMouse,
+}
+
+const CONTROLS: [(Control, &str); 10] = [
+    (Control::RotateLeft, "rotate_left"),
+    (Control::RotateRight, "rotate_right"),
+    (Control::MoveForward, "move_forward"),
+    (Control::MoveBack, "move_back"),
+    (Control::MoveLeft, "move_left"),
+    (Control::MoveRight, "move_right"),
+    (Control::MoveUp, "move_up"),
+    (Control::MoveDown, "move_down"),
+    (Control::Sprint, "sprint"),
+    (Control::ReleaseMouse, "release_mouse"),
+];
+
+// Names in bindings.toml, the left and right modifier keys are one
+const KEY_NAMES: [(Key, &str); 70] = [
+    (Key::A, "A"),
+    (Key::B, "B"),
+    (Key::C, "C"),
+    (Key::D, "D"),
+    (Key::E, "E"),
+    (Key::F, "F"),
+    (Key::G, "G"),
+    (Key::H, "H"),
+    (Key::I, "I"),
+    (Key::J, "J"),
+    (Key::K, "K"),
+    (Key::L, "L"),
+    (Key::M, "M"),
+    (Key::N, "N"),
+    (Key::O, "O"),
+    (Key::P, "P"),
+    (Key::Q, "Q"),
+    (Key::R, "R"),
+    (Key::S, "S"),
+    (Key::T, "T"),
+    (Key::U, "U"),
+    (Key::V, "V"),
+    (Key::W, "W"),
+    (Key::X, "X"),
+    (Key::Y, "Y"),
+    (Key::Z, "Z"),
+    (Key::Key0, "0"),
+    (Key::Key1, "1"),
+    (Key::Key2, "2"),
+    (Key::Key3, "3"),
+    (Key::Key4, "4"),
+    (Key::Key5, "5"),
+    (Key::Key6, "6"),
+    (Key::Key7, "7"),
+    (Key::Key8, "8"),
+    (Key::Key9, "9"),
+    (Key::F1, "F1"),
+    (Key::F2, "F2"),
+    (Key::F3, "F3"),
+    (Key::F4, "F4"),
+    (Key::F5, "F5"),
+    (Key::F6, "F6"),
+    (Key::F7, "F7"),
+    (Key::F8, "F8"),
+    (Key::F9, "F9"),
+    (Key::F10, "F10"),
+    (Key::F11, "F11"),
+    (Key::F12, "F12"),
+    (Key::Left, "Left"),
+    (Key::Right, "Right"),
+    (Key::Up, "Up"),
+    (Key::Down, "Down"),
+    (Key::Home, "Home"),
+    (Key::End, "End"),
+    (Key::PageUp, "PageUp"),
+    (Key::PageDown, "PageDown"),
+    (Key::Insert, "Insert"),
+    (Key::Delete, "Delete"),
+    (Key::Back, "Backspace"),
+    (Key::Return, "Enter"),
+    (Key::Escape, "Escape"),
+    (Key::Space, "Space"),
+    (Key::Tab, "Tab"),
+    (Key::Comma, "Comma"),
+    (Key::Period, "Period"),
+    (Key::Minus, "Minus"),
+    (Key::Equals, "Equals"),
+    (Key::LShift, "Shift"),
+    (Key::LControl, "Control"),
+    (Key::LAlt, "Alt"),
+];
+
+/// The left key for a right modifier, so either side matches.
+fn normalize(key: Key) -> Key {
+    match key {
+        Key::RShift => Key::LShift,
+        Key::RControl => Key::LControl,
+        Key::RAlt => Key::LAlt,
+        key => key,
+    }
+}
+
+fn key_name(key: Key) -> &'static str {
+    KEY_NAMES
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map_or("?", |(_, name)| name)
+}
+
+/// A key, with Ctrl and Shift held or not.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct KeyCombo {
+    pub key: Key,
+    pub ctrl: bool,
+    pub shift: bool,
+}
+
+impl KeyCombo {
+    fn new(key: Key) -> KeyCombo {
+        KeyCombo {
+            key,
+            ctrl: false,
+            shift: false,
+        }
+    }
+
+    fn ctrl(key: Key) -> KeyCombo {
+        KeyCombo {
+            ctrl: true,
+            ..KeyCombo::new(key)
+        }
+    }
+
+    fn shift(key: Key) -> KeyCombo {
+        KeyCombo {
+            shift: true,
+            ..KeyCombo::new(key)
+        }
+    }
+
+    /// Reads "F5", "Ctrl+P", "Ctrl+Shift+F12" and the like, names as in
+    /// `KEY_NAMES`.
+    pub fn parse(text: &str) -> Result<KeyCombo, String> {
+        let mut name = text;
+        let (mut ctrl, mut shift) = (false, false);
+        loop {
+            if let Some(rest) = name.strip_prefix("Ctrl+") {
+                ctrl = true;
+                name = rest;
+            } else if let Some(rest) = name.strip_prefix("Shift+") {
+                shift = true;
+                name = rest;
+            } else {
+                break;
+            }
+        }
+        KEY_NAMES
+            .iter()
+            .find(|(_, key_name)| key_name.eq_ignore_ascii_case(name))
+            .map(|&(key, _)| KeyCombo { key, ctrl, shift })
+            .ok_or_else(|| format!("unknown key '{}'", name))
+    }
+
+    pub fn name(self) -> String {
+        let mut name = String::new();
+        if self.ctrl {
+            name.push_str("Ctrl+");
+        }
+        if self.shift {
+            name.push_str("Shift+");
+        }
+        name.push_str(key_name(self.key));
+        name
+    }
+
+    /// Whether the key types into a text field, so it is left to the UI
+    /// while one has focus.
+    fn is_typing(self) -> bool {
+        let function_key = matches!(
+            self.key,
+            Key::F1
+                | Key::F2
+                | Key::F3
+                | Key::F4
+                | Key::F5
+                | Key::F6
+                | Key::F7
+                | Key::F8
+                | Key::F9
+                | Key::F10
+                | Key::F11
+                | Key::F12
+        );
+        !self.ctrl && !function_key
+    }
+}
+
+/// Everything that can be bound, with its name in bindings.toml.
+fn bindable_actions() -> Vec<(Action, String)> {
+    let mut actions = Action::all();
+    actions.extend((BUILTIN_NAMES.len()..BINDABLE_WORKSPACES).map(Action::ApplyWorkspace));
+    actions.extend((0..BINDABLE_BOOKMARKS).map(Action::RecallBookmark));
+    actions
+        .into_iter()
+        .map(|action| (action, action.id()))
+        .collect()
+}
+
+/// Which keys trigger which actions and controls. The application only
+/// ever asks for an action or control, the fixed key indices imgui is fed
+/// are kept apart in the event loop.
+pub struct InputBindings {
+    pub actions: Vec<(Action, KeyCombo)>,
+    pub controls: Vec<(Control, Key)>,
+}
+
+impl InputBindings {
+    pub fn defaults() -> InputBindings {
+        let mut actions = vec![
+            (Action::OpenCommandPalette, KeyCombo::ctrl(Key::P)),
+            (Action::ReloadShaders, KeyCombo::new(Key::F5)),
+            (Action::SaveScreenshot, KeyCombo::new(Key::F12)),
+            (Action::SaveSceneScreenshot, KeyCombo::shift(Key::F12)),
+            (Action::SaveDebugCapture, KeyCombo::ctrl(Key::F12)),
+            (Action::ToggleFullscreen, KeyCombo::new(Key::F11)),
+            (Action::ToggleFreeFly, KeyCombo::new(Key::F)),
+            (Action::ToggleSurfaceView, KeyCombo::new(Key::G)),
+            (Action::ToggleMouseLook, KeyCombo::new(Key::M)),
+            (Action::ResetView, KeyCombo::new(Key::Home)),
+            (Action::TogglePause, KeyCombo::new(Key::Space)),
+        ];
+        let digits = [
+            Key::Key1,
+            Key::Key2,
+            Key::Key3,
+            Key::Key4,
+            Key::Key5,
+            Key::Key6,
+            Key::Key7,
+            Key::Key8,
+            Key::Key9,
+        ];
+        actions.extend(
+            digits[..4]
+                .iter()
+                .enumerate()
+                .map(|(i, &key)| (Action::ApplyWorkspace(i), KeyCombo::ctrl(key))),
+        );
+        actions.extend(
+            digits
+                .iter()
+                .enumerate()
+                .map(|(i, &key)| (Action::RecallBookmark(i), KeyCombo::new(key))),
+        );
+
+        InputBindings {
+            actions,
+            controls: vec![
+                (Control::RotateLeft, Key::Left),
+                (Control::RotateRight, Key::Right),
+                (Control::MoveForward, Key::W),
+                (Control::MoveBack, Key::S),
+                (Control::MoveLeft, Key::A),
+                (Control::MoveRight, Key::D),
+                (Control::MoveUp, Key::E),
+                (Control::MoveDown, Key::Q),
+                (Control::Sprint, Key::LShift),
+                (Control::ReleaseMouse, Key::Escape),
+            ],
+        }
+    }
+
+    /// The defaults with `bindings.toml` on top, if there is one. Whatever
+    /// in it can't be used is printed and skipped.
+    pub fn load() -> InputBindings {
+        let mut bindings = InputBindings::defaults();
+        match fs::read_to_string(BINDINGS_FILE) {
+            Ok(text) => {
+                for warning in bindings.merge(&text) {
+                    println!("{}: {}", BINDINGS_FILE, warning);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => println!("{}: {}", BINDINGS_FILE, e),
+        }
+        bindings
+    }
+
+    /// Rebinds what `text` names, one `name = "key"` per line. An empty key
+    /// unbinds. Returns a warning for each line left out.
+    pub fn merge(&mut self, text: &str) -> Vec<String> {
+        let actions = bindable_actions();
+        let mut warnings = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let mut warn =
+                |message: String| warnings.push(format!("line {}: {}", number + 1, message));
+
+            let mut parts = line.splitn(2, '=');
+            let (name, value) = match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => (name.trim(), value.trim().trim_matches('"')),
+                _ => {
+                    warn(format!("expected 'name = \"key\"' but got '{}'", line));
+                    continue;
+                }
+            };
+            let combo = if value.is_empty() {
+                None
+            } else {
+                match KeyCombo::parse(value) {
+                    Ok(combo) => Some(combo),
+                    Err(e) => {
+                        warn(e);
+                        continue;
+                    }
+                }
+            };
+
+            if let Some(&(action, _)) = actions.iter().find(|(_, id)| id == name) {
+                self.actions.retain(|&(bound, _)| bound != action);
+                self.actions.extend(combo.map(|combo| (action, combo)));
+            } else if let Some(&(control, _)) = CONTROLS.iter().find(|(_, id)| *id == name) {
+                let combo = match combo {
+                    Some(combo) if combo.ctrl || combo.shift => {
+                        warn(format!(
+                            "'{}' is held down, it can't take Ctrl or Shift",
+                            name
+                        ));
+                        continue;
+                    }
+                    combo => combo,
+                };
+                self.controls.retain(|&(bound, _)| bound != control);
+                self.controls
+                    .extend(combo.map(|combo| (control, combo.key)));
+            } else {
+                warn(format!("unknown action '{}'", name));
+            }
+        }
+
+        warnings.extend(self.duplicates());
+        warnings
+    }
+
+    /// Keys bound more than once. The first binding wins, actions before
+    /// controls.
+    fn duplicates(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut seen: Vec<(KeyCombo, String)> = Vec::new();
+        let bound = self
+            .actions
+            .iter()
+            .map(|&(action, combo)| (combo, action.id()))
+            .chain(
+                self.controls
+                    .iter()
+                    .map(|&(control, key)| (KeyCombo::new(key), control_id(control).to_string())),
+            );
+        for (combo, id) in bound {
+            match seen.iter().find(|(other, _)| *other == combo) {
+                Some((_, first)) => warnings.push(format!(
+                    "{} is bound to both '{}' and '{}', '{}' gets it",
+                    combo.name(),
+                    first,
+                    id,
+                    first
+                )),
+                None => seen.push((combo, id)),
+            }
+        }
+        warnings
+    }
+
+    /// The action `key` pressed with Ctrl and Shift held or not triggers.
+    /// A key bound without Shift still goes with it held, for sprinting.
+    /// Keys that type are left alone while `typing` into the UI.
+    pub fn action(&self, key: Key, ctrl: bool, shift: bool, typing: bool) -> Option<Action> {
+        let combo = KeyCombo {
+            key: normalize(key),
+            ctrl,
+            shift,
+        };
+        let unshifted = KeyCombo {
+            shift: false,
+            ..combo
+        };
+        self.actions
+            .iter()
+            .find(|&&(_, bound)| bound == combo)
+            .or_else(|| {
+                self.actions
+                    .iter()
+                    .find(|&&(_, bound)| shift && bound == unshifted)
+            })
+            .filter(|(_, combo)| !(typing && combo.is_typing()))
+            .map(|&(action, _)| action)
+    }
+
+    /// The controls `key` holds down.
+    pub fn controls(&self, key: Key) -> impl Iterator<Item = Control> + '_ {
+        let key = normalize(key);
+        self.controls
+            .iter()
+            .filter(move |&&(_, bound)| bound == key)
+            .map(|&(control, _)| control)
+    }
+
+    /// The key to show next to an action in the UI.
+    pub fn hotkey(&self, action: Action) -> Option<String> {
+        self.actions
+            .iter()
+            .find(|&&(bound, _)| bound == action)
+            .map(|(_, combo)| combo.name())
+    }
+
+    /// `text` with the key bound to `action` after it, for buttons and
+    /// menus.
+    pub fn label(&self, text: &str, action: Action) -> String {
+        match self.hotkey(action) {
+            Some(key) => format!("{} ({})", text, key),
+            None => text.to_string(),
+        }
+    }
+
+    /// Writes every binding to `bindings.toml`, to edit from there.
+    pub fn save(&self) -> io::Result<()> {
+        fs::write(BINDINGS_FILE, self.to_toml())
+    }
+
+    /// All bindings in the format `merge` reads.
+    pub fn to_toml(&self) -> String {
+        let mut text = String::new();
+        for &(action, combo) in &self.actions {
+            text.push_str(&format!("{} = \"{}\"\n", action.id(), combo.name()));
+        }
+        for &(control, key) in &self.controls {
+            text.push_str(&format!(
+                "{} = \"{}\"\n",
+                control_id(control),
+                key_name(key)
+            ));
+        }
+        text
+    }
+}
+
+fn control_id(control: Control) -> &'static str {
+    CONTROLS
+        .iter()
+        .find(|(c, _)| *c == control)
+        .map_or("?", |(_, id)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> InputBindings {
+        InputBindings {
+            actions: Vec::new(),
+            controls: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn saved_defaults_merge_back_to_the_defaults() {
+        let defaults = InputBindings::defaults();
+        let saved = defaults.to_toml();
+
+        let mut merged = empty();
+        assert_eq!(merged.merge(&saved), Vec::<String>::new());
+        assert_eq!(merged.actions, defaults.actions);
+        assert_eq!(merged.controls, defaults.controls);
+
+        // Over the defaults themselves it changes nothing
+        let mut again = InputBindings::defaults();
+        assert_eq!(again.merge(&saved), Vec::<String>::new());
+        assert_eq!(again.actions, defaults.actions);
+        assert_eq!(again.controls, defaults.controls);
+    }
+
+    #[test]
+    fn key_names_parse_back() {
+        for &(key, _) in KEY_NAMES.iter() {
+            for &(ctrl, shift) in &[(false, false), (true, false), (false, true), (true, true)] {
+                let combo = KeyCombo { key, ctrl, shift };
+                assert_eq!(KeyCombo::parse(&combo.name()), Ok(combo));
+            }
+        }
+        assert_eq!(
+            KeyCombo::parse("ctrl+p"),
+            Err("unknown key 'ctrl+p'".to_string())
+        );
+        assert_eq!(KeyCombo::parse("Ctrl+p"), Ok(KeyCombo::ctrl(Key::P)));
+    }
+
+    #[test]
+    fn rebinds_and_unbinds() {
+        let mut bindings = InputBindings::defaults();
+        let text = "# comments and blank lines are skipped\n\n\
+                    reset_view = \"Ctrl+Shift+R\" # trailing too\n\
+                    toggle_pause = \"\"\n\
+                    sprint = \"Control\"\n";
+        assert_eq!(bindings.merge(text), Vec::<String>::new());
+        assert_eq!(
+            bindings.hotkey(Action::ResetView),
+            Some("Ctrl+Shift+R".to_string())
+        );
+        assert_eq!(bindings.hotkey(Action::TogglePause), None);
+        assert_eq!(
+            bindings.controls(Key::RControl).collect::<Vec<_>>(),
+            [Control::Sprint]
+        );
+        assert_eq!(bindings.controls(Key::LShift).count(), 0);
+    }
+
+    #[test]
+    fn unusable_lines_are_warned_about_and_skipped() {
+        let mut bindings = InputBindings::defaults();
+        let text = "fly = \"F\"\n\
+                    reset_view = \"Hyper+Home\"\n\
+                    reset_view\n\
+                    move_forward = \"Ctrl+W\"\n";
+        assert_eq!(
+            bindings.merge(text),
+            [
+                "line 1: unknown action 'fly'",
+                "line 2: unknown key 'Hyper+Home'",
+                "line 3: expected 'name = \"key\"' but got 'reset_view'",
+                "line 4: 'move_forward' is held down, it can't take Ctrl or Shift",
+            ]
+        );
+        let defaults = InputBindings::defaults();
+        assert_eq!(bindings.actions, defaults.actions);
+        assert_eq!(bindings.controls, defaults.controls);
+    }
+
+    #[test]
+    fn duplicate_keys_are_warned_about() {
+        let mut bindings = InputBindings::defaults();
+        let warnings = bindings.merge("toggle_vegetation = \"F5\"\nmove_up = \"Space\"\n");
+        assert_eq!(
+            warnings,
+            [
+                "F5 is bound to both 'reload_shaders' and 'toggle_vegetation', 'reload_shaders' gets it",
+                "Space is bound to both 'toggle_pause' and 'move_up', 'toggle_pause' gets it",
+            ]
+        );
+        assert_eq!(
+            bindings.action(Key::F5, false, false, false),
+            Some(Action::ReloadShaders)
+        );
+    }
+}
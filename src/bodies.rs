@@ -0,0 +1,175 @@
+use crate::gltf;
+use crate::mesh::AnyIndexBuffer;
+use crate::obj;
+use crate::Vertex;
+use cgmath::{Deg, InnerSpace, Matrix4, Vector3};
+use glium::{
+    backend::Facade,
+    texture::{texture2d::Texture2d, MipmapsOption, RawImage2d},
+    VertexBuffer,
+};
+use imgui::ImString;
+use std::error;
+use std::fs;
+use std::path::Path;
+
+/// How an extra body is shaded.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BodyShader {
+    // Diffuse lighting from the sun on the model's base color
+    Lit,
+    // Normals as colors, for checking a model came in the right way round
+    Normals,
+}
+
+impl BodyShader {
+    pub const ALL: [BodyShader; 2] = [BodyShader::Lit, BodyShader::Normals];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            BodyShader::Lit => "Lit",
+            BodyShader::Normals => "Normals",
+        }
+    }
+}
+
+/// A model loaded from an OBJ or glTF file and placed near the planet.
+pub struct ExtraBody {
+    pub name: String,
+    pub vertex_buffer: VertexBuffer<Vertex>,
+    pub index_buffer: AnyIndexBuffer,
+    // The base color texture, a flat grey for models without one
+    pub texture: Texture2d,
+    // In planet radii from the planet's center, turning with it
+    pub position: [f32; 3],
+    // Render units for each unit of the model
+    pub scale: f32,
+    // About the model's own y axis
+    pub rotation: f32,
+    // Degrees a second around the planet's pole, on top of its turning
+    pub orbit_speed: f32,
+    pub shader: BodyShader,
+}
+
+impl ExtraBody {
+    /// Loads a model by its extension, .gltf or .glb as glTF and anything
+    /// else as OBJ. Whatever a glTF file has that can't be shown is added
+    /// to `warnings`.
+    pub fn load<F: Facade>(
+        facade: &F,
+        path: &Path,
+        warnings: &mut Vec<String>,
+    ) -> Result<ExtraBody, Box<dyn error::Error>> {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let (vertices, indices, base_color) = match extension.as_deref() {
+            Some("gltf") | Some("glb") => {
+                let model = gltf::read_gltf(path)?;
+                warnings.extend(model.warnings);
+                (model.vertices, model.indices, model.base_color)
+            }
+            _ => {
+                let (vertices, indices) = obj::read_obj(&fs::read_to_string(path)?)?;
+                (vertices, indices, None)
+            }
+        };
+        if indices.is_empty() {
+            return Err("the file has no faces".into());
+        }
+
+        // Fit the model inside a tenth of a planet radius to start with
+        let extent = vertices
+            .iter()
+            .map(|vertex| Vector3::from(vertex.pos).magnitude())
+            .fold(0.0, f32::max);
+        let scale = if extent > 0.0 {
+            0.1 * crate::PLANET_RADIUS / extent
+        } else {
+            1.0
+        };
+
+        // glTF images start at the top left, as their UVs do, so the rows
+        // go up as they are without flipping
+        let image = match base_color {
+            Some(image) => {
+                let size = image.dimensions();
+                RawImage2d::from_raw_rgba(image.into_raw(), size)
+            }
+            None => RawImage2d::from_raw_rgba(vec![128, 128, 128, 255], (1, 1)),
+        };
+        let texture = Texture2d::with_mipmaps(facade, image, MipmapsOption::AutoGeneratedMipmaps)?;
+
+        Ok(ExtraBody {
+            name: path.file_name().map_or_else(
+                || path.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+            vertex_buffer: VertexBuffer::new(facade, &vertices)?,
+            index_buffer: AnyIndexBuffer::new(facade, vertices.len(), &indices)?,
+            texture,
+            position: [0.0, 0.0, 1.5],
+            scale,
+            rotation: 0.0,
+            orbit_speed: 0.0,
+            shader: BodyShader::Lit,
+        })
+    }
+
+    /// From the model to the planet's space at `time` seconds.
+    pub fn matrix(&self, time: f32) -> Matrix4<f32> {
+        let position = Vector3::from(self.position) * crate::PLANET_RADIUS;
+        Matrix4::from_angle_y(Deg(self.orbit_speed * time))
+            * Matrix4::from_translation(position)
+            * Matrix4::from_angle_y(Deg(self.rotation))
+            * Matrix4::from_scale(self.scale)
+    }
+}
+
+/// The extra bodies in the scene, and the path the UI loads the next one
+/// from.
+pub struct ExtraBodies {
+    pub bodies: Vec<ExtraBody>,
+    pub path: ImString,
+    // Load `path` on the next update
+    pub load_requested: bool,
+    // Why the last load failed
+    pub error: Option<String>,
+    // What the loaded models had that was left out
+    pub log: Vec<String>,
+}
+
+impl ExtraBodies {
+    pub fn new() -> ExtraBodies {
+        ExtraBodies {
+            bodies: Vec::new(),
+            path: ImString::with_capacity(256),
+            load_requested: false,
+            error: None,
+            log: Vec::new(),
+        }
+    }
+
+    pub fn update<F: Facade>(&mut self, facade: &F) {
+        if !self.load_requested {
+            return;
+        }
+        self.load_requested = false;
+
+        let path = self.path.to_str().trim().to_string();
+        let mut warnings = Vec::new();
+        let loaded = ExtraBody::load(facade, Path::new(&path), &mut warnings);
+        self.log.extend(
+            warnings
+                .into_iter()
+                .map(|warning| format!("{}: {}", path, warning)),
+        );
+        match loaded {
+            Ok(body) => {
+                self.bodies.push(body);
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("{}: {}", path, e)),
+        }
+    }
+}
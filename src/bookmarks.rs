@@ -0,0 +1,273 @@
+use crate::benchmark::json_string;
+use crate::session::{json_unescape, write_atomic};
+use cgmath::{vec2, InnerSpace, Quaternion, Vector2};
+use imgui::ImString;
+use std::env;
+use std::error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+const BOOKMARKS_FILE: &str = "bookmarks.json";
+
+// Seconds a recalled bookmark takes to fly to
+const TRANSITION_TIME: f32 = 0.5;
+
+/// Where the orbit camera is, everything a bookmark puts back.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ViewPose {
+    pub rotation: Quaternion<f32>,
+    // From the planet's center, in km so it means the same for every preset
+    pub distance_km: f64,
+    pub sun_angle: f32,
+    pub orbit_yaw: f32,
+    pub orbit_pitch: f32,
+    // In camera distances, see `State::pan`
+    pub pan: Vector2<f32>,
+}
+
+/// Degrees from `from` to `to` the short way round.
+fn angle_lerp(from: f32, to: f32, t: f32) -> f32 {
+    let delta = (to - from + 180.0).rem_euclid(360.0) - 180.0;
+    from + delta * t
+}
+
+impl ViewPose {
+    /// Part way from `self` to `to`, the distance changing by the same
+    /// factor every step so zooming out far doesn't rush the start.
+    fn lerp(&self, to: &ViewPose, t: f32) -> ViewPose {
+        let to_rotation = if self.rotation.dot(to.rotation) < 0.0 {
+            -to.rotation
+        } else {
+            to.rotation
+        };
+        let distance_ratio = to.distance_km / self.distance_km.max(1e-9);
+
+        ViewPose {
+            rotation: self.rotation.slerp(to_rotation, t).normalize(),
+            distance_km: self.distance_km * distance_ratio.powf(f64::from(t)),
+            sun_angle: angle_lerp(self.sun_angle, to.sun_angle, t),
+            orbit_yaw: angle_lerp(self.orbit_yaw, to.orbit_yaw, t),
+            orbit_pitch: self.orbit_pitch + (to.orbit_pitch - self.orbit_pitch) * t,
+            pan: self.pan + (to.pan - self.pan) * t,
+        }
+    }
+}
+
+pub struct CameraBookmark {
+    pub name: String,
+    pub pose: ViewPose,
+}
+
+struct Transition {
+    from: ViewPose,
+    to: ViewPose,
+    time: f32,
+}
+
+/// Named viewpoints kept in `bookmarks.json` next to the executable.
+/// Recalling one eases the camera there over `TRANSITION_TIME` rather than
+/// jumping.
+pub struct Bookmarks {
+    pub list: Vec<CameraBookmark>,
+    pub save_name: ImString,
+    transition: Option<Transition>,
+}
+
+/// Next to the executable, or the working directory if that can't be
+/// found.
+fn bookmarks_path() -> PathBuf {
+    env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(BOOKMARKS_FILE)))
+        .unwrap_or_else(|| PathBuf::from(BOOKMARKS_FILE))
+}
+
+impl Bookmarks {
+    pub fn load() -> Bookmarks {
+        let path = bookmarks_path();
+        let list = match fs::read_to_string(&path) {
+            Ok(text) => parse_bookmarks(&text).unwrap_or_else(|e| {
+                println!("{}: {}", path.display(), e);
+                Vec::new()
+            }),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                println!("{}: {}", path.display(), e);
+                Vec::new()
+            }
+        };
+
+        Bookmarks {
+            list,
+            save_name: ImString::with_capacity(64),
+            transition: None,
+        }
+    }
+
+    /// Saves `pose` as `name`, replacing a bookmark of the same name.
+    pub fn add(&mut self, name: &str, pose: ViewPose) {
+        let bookmark = CameraBookmark {
+            name: name.to_string(),
+            pose,
+        };
+        match self.list.iter().position(|b| b.name == name) {
+            Some(i) => self.list[i] = bookmark,
+            None => self.list.push(bookmark),
+        }
+        self.save();
+    }
+
+    pub fn remove(&mut self, index: usize) {
+        if index < self.list.len() {
+            self.list.remove(index);
+            self.save();
+        }
+    }
+
+    /// Starts easing from `current` to the bookmark at `index`. Returns
+    /// false if there is no such bookmark.
+    pub fn recall(&mut self, index: usize, current: ViewPose) -> bool {
+        match self.list.get(index).map(|bookmark| bookmark.pose) {
+            Some(pose) => {
+                self.fly_to(current, pose);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Starts easing from `from` to `to` the way a recall does.
+    pub fn fly_to(&mut self, from: ViewPose, to: ViewPose) {
+        self.transition = Some(Transition {
+            from,
+            to,
+            time: 0.0,
+        });
+    }
+
+    /// Stops a recall on its way, for when the camera is moved by hand.
+    pub fn cancel(&mut self) {
+        self.transition = None;
+    }
+
+    /// Where a recall has the camera after `dt` more seconds, or `None` when
+    /// there is none under way.
+    pub fn step(&mut self, dt: f32) -> Option<ViewPose> {
+        let transition = self.transition.as_mut()?;
+        transition.time += dt;
+        let t = (transition.time / TRANSITION_TIME).min(1.0);
+        // Smoothstep, setting off and arriving gently
+        let pose = transition
+            .from
+            .lerp(&transition.to, t * t * (3.0 - 2.0 * t));
+        if t >= 1.0 {
+            self.transition = None;
+        }
+        Some(pose)
+    }
+
+    fn save(&self) {
+        let path = bookmarks_path();
+        if let Err(e) = write_atomic(&path, bookmarks_json(&self.list).as_bytes()) {
+            println!("Saving {} failed: {}", path.display(), e);
+        }
+    }
+}
+
+fn bookmarks_json(list: &[CameraBookmark]) -> String {
+    let bookmarks: Vec<String> = list
+        .iter()
+        .map(|bookmark| {
+            let pose = &bookmark.pose;
+            let r = pose.rotation;
+            format!(
+                "  {{\n    \"name\": {},\n    \"rotation\": [{}, {}, {}, {}],\n    \"distance_km\": {},\n    \"sun_angle\": {},\n    \"orbit_yaw\": {},\n    \"orbit_pitch\": {},\n    \"pan\": [{}, {}]\n  }}",
+                json_string(&bookmark.name),
+                r.s,
+                r.v.x,
+                r.v.y,
+                r.v.z,
+                pose.distance_km,
+                pose.sun_angle,
+                pose.orbit_yaw,
+                pose.orbit_pitch,
+                pose.pan.x,
+                pose.pan.y
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", bookmarks.join(",\n"))
+}
+
+/// A JSON array of `count` numbers.
+fn parse_numbers(value: &str, count: usize) -> Result<Vec<f32>, Box<dyn error::Error>> {
+    let numbers = value
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .split(',')
+        .map(|part| part.trim().parse())
+        .collect::<Result<Vec<f32>, _>>()?;
+    if numbers.len() != count {
+        return Err(format!("expected {} numbers but got '{}'", count, value).into());
+    }
+    Ok(numbers)
+}
+
+/// Reads back what `bookmarks_json` wrote, one member per line. Members
+/// missing from a bookmark keep their defaults.
+fn parse_bookmarks(text: &str) -> Result<Vec<CameraBookmark>, Box<dyn error::Error>> {
+    let default_pose = ViewPose {
+        rotation: Quaternion::new(1.0, 0.0, 0.0, 0.0),
+        distance_km: 0.0,
+        sun_angle: 0.0,
+        orbit_yaw: 0.0,
+        orbit_pitch: 0.0,
+        pan: vec2(0.0, 0.0),
+    };
+    let mut list = Vec::new();
+    let mut name = None;
+    let mut pose = default_pose;
+
+    for line in text.lines().map(str::trim) {
+        let line = line.trim_end_matches(',');
+        if line == "}" {
+            let name = name.take().ok_or("bookmark without a name")?;
+            if pose.distance_km <= 0.0 {
+                return Err(format!("bookmark '{}' has no distance_km", name).into());
+            }
+            list.push(CameraBookmark { name, pose });
+            pose = default_pose;
+            continue;
+        }
+
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim().trim_matches('"'), value.trim()),
+            None => continue,
+        };
+        match key {
+            "name" => {
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .ok_or_else(|| format!("malformed name '{}'", value))?;
+                name = Some(json_unescape(value));
+            }
+            "rotation" => {
+                let r = parse_numbers(value, 4)?;
+                pose.rotation = Quaternion::new(r[0], r[1], r[2], r[3]).normalize();
+            }
+            "pan" => {
+                let pan = parse_numbers(value, 2)?;
+                pose.pan = vec2(pan[0], pan[1]);
+            }
+            "distance_km" => pose.distance_km = value.parse()?,
+            "sun_angle" => pose.sun_angle = value.parse()?,
+            "orbit_yaw" => pose.orbit_yaw = value.parse()?,
+            "orbit_pitch" => pose.orbit_pitch = value.parse()?,
+            _ => return Err(format!("unknown bookmark member '{}'", key).into()),
+        }
+    }
+
+    Ok(list)
+}
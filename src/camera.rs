@@ -0,0 +1,171 @@
+use cgmath::{
+    perspective, vec4, Deg, EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3,
+};
+
+/// The eye and its lens, in camera-relative render units. The scene is
+/// placed with model matrices of its own, this only turns the world into
+/// view space and view space into clip space.
+#[derive(Copy, Clone, Debug)]
+pub struct Camera {
+    pub position: Vector3<f32>,
+    pub target: Vector3<f32>,
+    pub up: Vector3<f32>,
+    // Vertical, in degrees
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Camera {
+    /// The camera a world-to-eye `view` transform looks through, one unit
+    /// ahead of the eye.
+    pub fn from_view(view: Matrix4<f32>, fov: f32, near: f32, far: f32) -> Camera {
+        let inverse = view.invert().unwrap_or_else(Matrix4::identity);
+        let position = inverse.w.truncate();
+        let forward = -inverse.z.truncate().normalize();
+
+        Camera {
+            position,
+            target: position + forward,
+            up: inverse.y.truncate().normalize(),
+            fov,
+            near,
+            far,
+        }
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
+        (self.target - self.position).normalize()
+    }
+
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        Matrix4::look_at_dir(Point3::from_vec(self.position), self.forward(), self.up)
+    }
+
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix4<f32> {
+        perspective(Deg(self.fov), aspect, self.near, self.far)
+    }
+
+    /// The ray from the eye through the pixel at `cursor` in a `size` pixel
+    /// window, as its origin and unit direction.
+    pub fn cursor_ray(&self, cursor: (i32, i32), size: (u32, u32)) -> (Vector3<f32>, Vector3<f32>) {
+        let (width, height) = (size.0.max(1) as f32, size.1.max(1) as f32);
+        let x = 2.0 * cursor.0 as f32 / width - 1.0;
+        // Pixel rows go down, clip space y goes up
+        let y = 1.0 - 2.0 * cursor.1 as f32 / height;
+
+        let inverse = (self.projection_matrix(width / height) * self.view_matrix())
+            .invert()
+            .unwrap_or_else(Matrix4::identity);
+        // Through the near plane, the far one is too far off for f32
+        let near = inverse * vec4(x, y, -1.0, 1.0);
+        let near = near.truncate() / near.w;
+        (self.position, (near - self.position).normalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{vec3, Angle, Vector4};
+
+    const SIZE: (u32, u32) = (800, 600);
+
+    fn camera() -> Camera {
+        Camera {
+            position: vec3(1.0, 2.0, 5.0),
+            target: vec3(0.0, 1.0, 0.0),
+            up: vec3(0.0, 1.0, 0.0),
+            fov: 60.0,
+            near: 0.1,
+            far: 100.0,
+        }
+    }
+
+    fn close(a: Vector3<f32>, b: Vector3<f32>) -> bool {
+        (a - b).magnitude() < 1e-4
+    }
+
+    // Where a world position lands in normalized device coordinates
+    fn ndc(camera: &Camera, point: Vector3<f32>) -> Vector3<f32> {
+        let aspect = SIZE.0 as f32 / SIZE.1 as f32;
+        let clip: Vector4<f32> =
+            camera.projection_matrix(aspect) * camera.view_matrix() * point.extend(1.0);
+        clip.truncate() / clip.w
+    }
+
+    #[test]
+    fn view_puts_the_eye_at_the_origin_looking_down_z() {
+        let camera = camera();
+        let view = camera.view_matrix();
+        let eye = |point: Vector3<f32>| (view * point.extend(1.0)).truncate();
+        assert!(close(eye(camera.position), vec3(0.0, 0.0, 0.0)));
+        let distance = (camera.target - camera.position).magnitude();
+        assert!(close(eye(camera.target), vec3(0.0, 0.0, -distance)));
+        // Up stays up, as far as it is across the view direction
+        let above = eye(camera.position + camera.up) - eye(camera.position);
+        assert!(above.y > 0.0 && above.x.abs() < 1e-5);
+    }
+
+    #[test]
+    fn from_view_gives_the_camera_back() {
+        let camera = camera();
+        let back = Camera::from_view(camera.view_matrix(), camera.fov, camera.near, camera.far);
+        assert!(close(back.position, camera.position));
+        assert!(close(back.forward(), camera.forward()));
+        assert!(((back.target - back.position).magnitude() - 1.0).abs() < 1e-5);
+        assert!(back.up.dot(camera.forward()).abs() < 1e-5);
+        assert!(back.up.dot(camera.up) > 0.0);
+        let (a, b) = (back.view_matrix(), camera.view_matrix());
+        for column in 0..4 {
+            assert!(close(a[column].truncate(), b[column].truncate()));
+            assert!((a[column].w - b[column].w).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn projection_maps_near_and_far_to_the_depth_range() {
+        let camera = camera();
+        let forward = camera.forward();
+        let near = ndc(&camera, camera.position + forward * camera.near);
+        let far = ndc(&camera, camera.position + forward * camera.far);
+        assert!(close(near, vec3(0.0, 0.0, -1.0)));
+        assert!((far - vec3(0.0, 0.0, 1.0)).magnitude() < 1e-2);
+        // The top of the field of view is the top of the screen
+        let right = forward.cross(camera.up).normalize();
+        let up = right.cross(forward);
+        let half = Deg(camera.fov / 2.0);
+        let top = camera.position + (forward * half.cos() + up * half.sin()) * 10.0;
+        assert!((ndc(&camera, top).y - 1.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn cursor_rays_go_through_their_pixels() {
+        let camera = camera();
+        let (origin, direction) = camera.cursor_ray((400, 300), SIZE);
+        assert!(close(origin, camera.position));
+        assert!(close(direction, camera.forward()));
+
+        for &cursor in &[(0, 0), (799, 0), (123, 456), (800, 600)] {
+            let (origin, direction) = camera.cursor_ray(cursor, SIZE);
+            assert!((direction.magnitude() - 1.0).abs() < 1e-5);
+            let point = ndc(&camera, origin + direction * 3.0);
+            let pixel = (
+                (point.x + 1.0) / 2.0 * SIZE.0 as f32,
+                (1.0 - point.y) / 2.0 * SIZE.1 as f32,
+            );
+            assert!(
+                (pixel.0 - cursor.0 as f32).abs() < 0.05,
+                "{:?} {:?}",
+                cursor,
+                pixel
+            );
+            assert!(
+                (pixel.1 - cursor.1 as f32).abs() < 0.05,
+                "{:?} {:?}",
+                cursor,
+                pixel
+            );
+        }
+    }
+}
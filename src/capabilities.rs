@@ -0,0 +1,162 @@
+use glium::glutin::{self, Api, GlProfile, GlRequest};
+use glium::{backend::Facade, CapabilitiesSource, HeadlessRenderer, Version};
+use std::borrow::Cow;
+use std::error;
+
+/// Context versions to try, the first one that can be created is used.
+pub const GL_VERSIONS: [(u8, u8); 3] = [(4, 3), (4, 1), (3, 3)];
+
+/// The newest context version to ask for, from `--max-gl <major.minor>`.
+pub fn max_gl_from_args(args: &[String]) -> Result<(u8, u8), Box<dyn error::Error>> {
+    let i = match args.iter().position(|arg| arg == "--max-gl") {
+        Some(i) => i,
+        None => return Ok(GL_VERSIONS[0]),
+    };
+
+    let value = args.get(i + 1).ok_or("--max-gl needs a version like 3.3")?;
+    let mut parts = value.splitn(2, '.');
+    let version = match (parts.next(), parts.next()) {
+        (Some(major), Some(minor)) => (major.parse()?, minor.parse()?),
+        _ => return Err(format!("--max-gl expects a version like 3.3 but got '{}'", value).into()),
+    };
+
+    if !GL_VERSIONS.iter().any(|&v| v <= version) {
+        return Err(format!("--max-gl {} is older than any supported version", value).into());
+    }
+    Ok(version)
+}
+
+/// The context versions to try given the newest one allowed.
+pub fn versions_to_try(max_gl: (u8, u8)) -> impl Iterator<Item = (u8, u8)> {
+    GL_VERSIONS.iter().cloned().filter(move |&v| v <= max_gl)
+}
+
+/// What the context turned out to support. Drivers often hand out a newer
+/// context than asked for, so with `--max-gl` the version is capped at the
+/// limit and extensions from past it are ignored, to make the fallback path
+/// testable on any machine.
+pub struct GlCapabilities {
+    pub version: (u8, u8),
+    pub limited: bool,
+    pub tessellation: bool,
+    pub compute: bool,
+    pub debug_output: bool,
+    pub max_samples: u32,
+    pub max_anisotropy: f32,
+}
+
+impl GlCapabilities {
+    pub fn detect<F: Facade>(facade: &F, max_gl: (u8, u8)) -> GlCapabilities {
+        let context = facade.get_context();
+        let Version(_, major, minor) = *context.get_opengl_version();
+        let limited = (major, minor) > max_gl;
+        let version = if limited { max_gl } else { (major, minor) };
+
+        let extensions = context.get_extensions();
+        let capabilities = context.get_capabilities();
+
+        let max_samples = capabilities
+            .internal_formats_renderbuffers
+            .values()
+            .filter_map(|format| format.multisamples.as_ref())
+            .flatten()
+            .cloned()
+            .max()
+            .unwrap_or(0)
+            .max(0) as u32;
+
+        GlCapabilities {
+            version,
+            limited,
+            tessellation: version >= (4, 0) || (!limited && extensions.gl_arb_tessellation_shader),
+            compute: version >= (4, 3) || (!limited && extensions.gl_arb_compute_shader),
+            debug_output: version >= (4, 3) || (!limited && extensions.gl_khr_debug),
+            max_samples,
+            max_anisotropy: capabilities.max_texture_max_anisotropy.unwrap_or(1.0),
+        }
+    }
+
+    /// Names of the features that are off because the context lacks them.
+    pub fn degraded(&self) -> Vec<&'static str> {
+        let mut degraded = Vec::new();
+        if !self.tessellation {
+            degraded.push("tessellation LOD");
+        }
+        if !self.compute {
+            degraded.push("compute heightmaps");
+        }
+        if !self.debug_output {
+            degraded.push("debug output");
+        }
+        degraded
+    }
+
+    /// Notes on the console what runs in a reduced form.
+    pub fn report(&self) {
+        println!(
+            "OpenGL {}.{}{}, up to {}x MSAA and {}x anisotropy",
+            self.version.0,
+            self.version.1,
+            if self.limited {
+                " (limited by --max-gl)"
+            } else {
+                ""
+            },
+            self.max_samples,
+            self.max_anisotropy
+        );
+        for feature in self.degraded() {
+            println!("  {} is not available and is disabled", feature);
+        }
+    }
+}
+
+/// Lowers the `#version` line of a shader to what the context compiles. The
+/// shaders stick to what GLSL 3.30 has, they only ask for more where it's
+/// there.
+pub fn fit_glsl_version<'a, F: Facade>(facade: &F, source: &'a str) -> Cow<'a, str> {
+    let Version(_, major, minor) = facade.get_context().get_supported_glsl_version();
+    let supported = u32::from(major) * 100 + u32::from(minor) * 10;
+
+    let mut lines = source.splitn(2, '\n');
+    let first = lines.next().unwrap_or("");
+    let requested = first
+        .trim()
+        .strip_prefix("#version")
+        .and_then(|version| version.trim().parse::<u32>().ok());
+
+    match requested {
+        Some(requested) if requested > supported => Cow::Owned(format!(
+            "#version {}\n{}",
+            supported,
+            lines.next().unwrap_or("")
+        )),
+        _ => Cow::Borrowed(source),
+    }
+}
+
+/// An offscreen context of the newest version there is, up to `max_gl`.
+pub fn create_headless(
+    width: u32,
+    height: u32,
+    max_gl: (u8, u8),
+) -> Result<HeadlessRenderer, Box<dyn error::Error>> {
+    let mut last_error = None;
+    for version in versions_to_try(max_gl) {
+        let context = glutin::HeadlessRendererBuilder::new(width, height)
+            .with_gl_profile(GlProfile::Core)
+            .with_gl(GlRequest::Specific(Api::OpenGl, version))
+            .build();
+        match context {
+            Ok(context) => return Ok(HeadlessRenderer::new(context)?),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(format!(
+        "no OpenGL context up to {}.{} could be created: {}",
+        max_gl.0,
+        max_gl.1,
+        last_error.map(|e| e.to_string()).unwrap_or_default()
+    )
+    .into())
+}
@@ -0,0 +1,1005 @@
+use crate::capabilities;
+use crate::cloud_target::CloudResolution;
+use crate::colors::ColorPalette;
+use crate::labels::Label;
+use crate::mesh::PlanetMesh;
+use crate::orbit::SATELLITES;
+use crate::units::PRESETS;
+use crate::wind::WindCurve;
+use crate::zip;
+use crate::{render_scene, Shadowmap, State};
+use cgmath::{Deg, InnerSpace, Quaternion, Rotation3};
+use glium::{
+    backend::Facade,
+    framebuffer::{DepthRenderBuffer, SimpleFrameBuffer},
+    texture::{
+        texture2d::Texture2d, DepthFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat,
+    },
+};
+use image::{codecs::png::PngEncoder, ColorType, ImageEncoder};
+use std::convert::TryInto;
+use std::error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Everything needed to render a frame again, one `key=value` per line.
+/// Only the state that `apply_setting` can restore is written.
+pub fn settings(p: &State, width: u32, height: u32, time: f32) -> Vec<(&'static str, String)> {
+    let surface = &p.surface;
+    let satellite = &p.satellite;
+    let detail = &p.detail;
+    let vegetation = &p.vegetation.settings;
+    let magnetosphere = &p.magnetosphere.settings;
+    let sun = &p.sun.settings;
+    let displacement = &p.geometry.displacement;
+    let craters = &displacement.craters;
+    let body = &p.body;
+    let ring = &p.ring.settings;
+    let shell = &p.atmosphere_shell.settings;
+    let labels = &p.labels.settings;
+    let celestial = &p.celestial.settings;
+    let meteors = &p.meteors.settings;
+
+    let mut settings = vec![
+        ("width", width.to_string()),
+        ("height", height.to_string()),
+        ("time", time.to_string()),
+        ("preset", p.preset.to_string()),
+        // Before the camera, which keeps its altitude when the body changes
+        ("scale_model", p.scale_model.to_string()),
+        ("body.planet_radius_km", body.planet_radius_km.to_string()),
+        ("body.sun_distance_au", body.sun_distance_au.to_string()),
+        ("body.day_length_hours", body.day_length_hours.to_string()),
+        ("body.year_length_hours", body.year_length_hours.to_string()),
+        ("body.axial_tilt", body.axial_tilt.to_string()),
+        ("body.surface_gravity", body.surface_gravity.to_string()),
+        ("sun_angle", p.view.sun_angle.to_string()),
+        ("season", p.season.to_string()),
+        (
+            "rotation",
+            format!(
+                "{} {} {} {}",
+                p.view.rotation.s, p.view.rotation.v.x, p.view.rotation.v.y, p.view.rotation.v.z
+            ),
+        ),
+        ("orbit_yaw", p.view.orbit_yaw.to_string()),
+        ("orbit_pitch", p.view.orbit_pitch.to_string()),
+        ("pan.x", p.view.pan.x.to_string()),
+        ("pan.y", p.view.pan.y.to_string()),
+        // Before the distance, which is clamped to them
+        (
+            "zoom.min_altitude_km",
+            p.view.min_zoom_altitude_km.to_string(),
+        ),
+        ("zoom.max_radii", p.view.max_zoom_radii.to_string()),
+        ("camera_distance", p.view.camera_distance.to_string()),
+        ("fov", p.view.fov.to_string()),
+        ("sun_cycle.enabled", p.sun_cycle.enabled.to_string()),
+        ("sun_cycle.period", p.sun_cycle.period.to_string()),
+        ("sun_cycle.seasons", p.sun_cycle.seasons.to_string()),
+        (
+            "sun_cycle.season_period",
+            p.sun_cycle.season_period.to_string(),
+        ),
+        ("auto_rotate.enabled", p.auto_rotate.enabled.to_string()),
+        (
+            "auto_rotate.speed",
+            p.auto_rotate.speed_deg_per_sec.to_string(),
+        ),
+        ("camera_pos.x", p.camera_pos.x.to_string()),
+        ("camera_pos.y", p.camera_pos.y.to_string()),
+        ("camera_pos.z", p.camera_pos.z.to_string()),
+        ("fly.enabled", p.fly.enabled.to_string()),
+        ("fly.yaw", p.fly.yaw.to_string()),
+        ("fly.pitch", p.fly.pitch.to_string()),
+        ("shadow_resolution", p.shadow_resolution.to_string()),
+        ("cloud_resolution", p.cloud_resolution.name().to_lowercase()),
+        ("lod_bias", p.geometry.lod_bias.to_string()),
+        ("displacement.seed", displacement.seed.to_string()),
+        ("displacement.amplitude", displacement.amplitude.to_string()),
+        ("displacement.octaves", displacement.octaves.to_string()),
+        ("displacement.frequency", displacement.frequency.to_string()),
+        ("craters.count", craters.count.to_string()),
+        ("craters.min_size", craters.min_size.to_string()),
+        ("craters.max_size", craters.max_size.to_string()),
+        ("craters.depth", craters.depth.to_string()),
+        ("flattening", p.geometry.flattening.to_string()),
+        ("occlusion_auto_skip", p.occlusion_auto_skip.to_string()),
+        ("surface.enabled", surface.enabled.to_string()),
+        ("surface.latitude", surface.latitude.to_string()),
+        ("surface.longitude", surface.longitude.to_string()),
+        ("surface.heading", surface.heading.to_string()),
+        ("surface.pitch", surface.pitch.to_string()),
+        ("surface.eye_height_km", surface.eye_height_km.to_string()),
+        ("satellite.enabled", satellite.enabled.to_string()),
+        ("satellite.satellite", satellite.satellite.to_string()),
+        ("satellite.nadir", satellite.nadir.to_string()),
+        ("satellite.time_warp", satellite.time_warp.to_string()),
+        ("satellite.time", satellite.time.to_string()),
+        ("palette", p.colors.name.clone()),
+        ("detail.enabled", detail.enabled.to_string()),
+        ("detail.scale", detail.scale.to_string()),
+        (
+            "detail.fade_distance_km",
+            detail.fade_distance_km.to_string(),
+        ),
+        ("detail.strength", detail.strength.to_string()),
+        ("detail.normals", detail.normals.to_string()),
+        ("detail.relief", detail.relief.to_string()),
+        ("vegetation.enabled", vegetation.enabled.to_string()),
+        ("vegetation.seed", vegetation.seed.to_string()),
+        ("vegetation.candidates", vegetation.candidates.to_string()),
+        ("vegetation.size", vegetation.size.to_string()),
+        (
+            "vegetation.view_distance_km",
+            vegetation.view_distance_km.to_string(),
+        ),
+        ("magnetosphere.enabled", magnetosphere.enabled.to_string()),
+        ("magnetosphere.shells", magnetosphere.shells.to_string()),
+        (
+            "magnetosphere.lines_per_shell",
+            magnetosphere.lines_per_shell.to_string(),
+        ),
+        (
+            "magnetosphere.max_shell",
+            magnetosphere.max_shell.to_string(),
+        ),
+        (
+            "magnetosphere.axis_tilt",
+            magnetosphere.axis_tilt.to_string(),
+        ),
+        (
+            "magnetosphere.axis_azimuth",
+            magnetosphere.axis_azimuth.to_string(),
+        ),
+        (
+            "magnetosphere.compression",
+            magnetosphere.compression.to_string(),
+        ),
+        ("magnetosphere.tail", magnetosphere.tail.to_string()),
+        (
+            "magnetosphere.line_width",
+            magnetosphere.line_width.to_string(),
+        ),
+        ("wind.enabled", p.wind.settings.enabled.to_string()),
+        ("wind.time_scale", p.wind.settings.time_scale.to_string()),
+        ("wind.curve", p.wind.curve.to_string()),
+        ("entry.enabled", p.entry.settings.enabled.to_string()),
+        ("meteors.enabled", meteors.enabled.to_string()),
+        ("meteors.seed", meteors.seed.to_string()),
+        ("meteors.rate", meteors.rate.to_string()),
+        (
+            "meteors.radiant_latitude",
+            meteors.radiant_latitude.to_string(),
+        ),
+        (
+            "meteors.radiant_longitude",
+            meteors.radiant_longitude.to_string(),
+        ),
+        ("meteors.speed_km", meteors.speed_km.to_string()),
+        ("meteors.width_km", meteors.width_km.to_string()),
+        ("meteors.brightness", meteors.brightness.to_string()),
+        ("sun.enabled", sun.enabled.to_string()),
+        ("sun.intensity", sun.intensity.to_string()),
+        ("sun.limb_darkening", sun.limb_darkening.to_string()),
+        ("sun.granulation", sun.granulation.to_string()),
+        ("sun.granulation_period", sun.granulation_period.to_string()),
+        ("sun.corona", sun.corona.to_string()),
+        ("sun.corona_size", sun.corona_size.to_string()),
+        ("sun.disc_scale", sun.disc_scale.to_string()),
+        ("vsync", p.vsync.to_string()),
+        ("frame_limit.enabled", p.frame_limiter.enabled.to_string()),
+        ("frame_limit.fps", p.frame_limiter.target_fps.to_string()),
+        ("clock.time", p.clock.time.to_string()),
+        ("clock.scale", p.clock.scale.to_string()),
+        ("clock.paused", p.clock.paused.to_string()),
+        ("atmosphere.enabled", p.atmosphere.is_some().to_string()),
+        ("atmosphere_shell.enabled", shell.enabled.to_string()),
+        ("atmosphere_shell.thickness", shell.thickness.to_string()),
+        ("atmosphere_shell.tint", floats(&shell.tint)),
+        ("atmosphere_shell.intensity", shell.intensity.to_string()),
+        ("ring.enabled", ring.enabled.to_string()),
+        ("ring.inner_radius", ring.inner_radius.to_string()),
+        ("ring.outer_radius", ring.outer_radius.to_string()),
+        ("ring.opacity", ring.opacity.to_string()),
+        ("skybox.enabled", p.skybox.enabled.to_string()),
+        ("skybox.brightness", p.skybox.brightness.to_string()),
+        ("star_parallax", p.star_parallax.to_string()),
+        ("cell_view", p.cell_view.to_string()),
+        ("labels.enabled", labels.enabled.to_string()),
+        ("labels.size_km", labels.size_km.to_string()),
+        ("labels.min_pixels", labels.min_pixels.to_string()),
+        ("labels.max_pixels", labels.max_pixels.to_string()),
+        (
+            "labels.fade_distance_km",
+            labels.fade_distance_km.to_string(),
+        ),
+        ("labels.color", floats(&labels.color)),
+        ("labels.list", write_labels(&p.labels.labels)),
+        ("celestial.orbit_view", celestial.orbit_view.to_string()),
+        ("celestial.equator", celestial.equator.to_string()),
+        ("celestial.ecliptic", celestial.ecliptic.to_string()),
+        ("celestial.grid", celestial.grid.to_string()),
+        ("celestial.hour_marks", celestial.hour_marks.to_string()),
+        ("celestial.sun_path", celestial.sun_path.to_string()),
+        ("celestial.labels", celestial.labels.to_string()),
+        ("celestial.equator_color", floats(&celestial.equator_color)),
+        (
+            "celestial.ecliptic_color",
+            floats(&celestial.ecliptic_color),
+        ),
+        ("celestial.grid_color", floats(&celestial.grid_color)),
+        (
+            "celestial.sun_path_color",
+            floats(&celestial.sun_path_color),
+        ),
+        ("celestial.line_width", celestial.line_width.to_string()),
+        ("celestial.label_size", celestial.label_size.to_string()),
+    ];
+
+    // Only there to be edited while the planet has an atmosphere
+    if let Some(atmosphere) = p.atmosphere {
+        settings.extend(vec![
+            ("atmosphere.height_km", atmosphere.height_km.to_string()),
+            (
+                "atmosphere.scale_height_km",
+                atmosphere.scale_height_km.to_string(),
+            ),
+            ("atmosphere.density", atmosphere.density.to_string()),
+            ("atmosphere.tint", floats(&atmosphere.tint)),
+        ]);
+    }
+    settings
+}
+
+/// Colors and the like, written space separated.
+fn floats(values: &[f32]) -> String {
+    values
+        .iter()
+        .map(f32::to_string)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn parse_floats<const N: usize>(value: &str) -> Result<[f32; N], Box<dyn error::Error>> {
+    let parts = value
+        .split_whitespace()
+        .map(str::parse)
+        .collect::<Result<Vec<f32>, _>>()?;
+    parts
+        .try_into()
+        .map_err(|_| format!("'{}' is not {} numbers", value, N).into())
+}
+
+/// The labels as `latitude longitude altitude world_space text`, tab
+/// separated. The text goes last so it can hold spaces, and the UI's
+/// single line text input doesn't take tabs.
+fn write_labels(labels: &[Label]) -> String {
+    labels
+        .iter()
+        .map(|label| {
+            format!(
+                "{} {} {} {} {}",
+                label.latitude,
+                label.longitude,
+                label.altitude_km,
+                label.world_space,
+                label.text.to_str()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\t")
+}
+
+fn read_labels(value: &str) -> Result<Vec<Label>, Box<dyn error::Error>> {
+    value
+        .split('\t')
+        .filter(|label| !label.trim().is_empty())
+        .map(|label| {
+            let parts: Vec<&str> = label.trim_start().splitn(5, ' ').collect();
+            match parts[..] {
+                [latitude, longitude, altitude_km, world_space, text] => {
+                    let mut label = Label::new(
+                        text,
+                        latitude.parse()?,
+                        longitude.parse()?,
+                        altitude_km.parse()?,
+                    );
+                    label.world_space = world_space.parse()?;
+                    Ok(label)
+                }
+                _ => Err(format!("label '{}' is not 'lat lon alt world text'", label).into()),
+            }
+        })
+        .collect()
+}
+
+/// Settings of a capture that aren't part of the state itself.
+struct FrameSettings {
+    width: u32,
+    height: u32,
+    time: f32,
+}
+
+fn apply_setting(
+    p: &mut State,
+    frame: &mut FrameSettings,
+    key: &str,
+    value: &str,
+) -> Result<(), Box<dyn error::Error>> {
+    match key {
+        "width" => frame.width = value.parse()?,
+        "height" => frame.height = value.parse()?,
+        "time" => frame.time = value.parse()?,
+        // Captures from newer builds may know more, restore what we can
+        _ => {
+            if !set(p, key, value)? {
+                println!("Debug capture: ignoring unknown setting '{}'", key);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sets one of the state's `settings` from its written `value`, the way
+/// the UI would. False if there is no such setting.
+pub fn set(p: &mut State, key: &str, value: &str) -> Result<bool, Box<dyn error::Error>> {
+    match key {
+        "preset" => {
+            let preset: usize = value.parse()?;
+            if preset >= PRESETS.len() {
+                return Err(format!("unknown preset {}", preset).into());
+            }
+            p.apply_preset(preset);
+        }
+        "scale_model" => p.scale_model = value.parse()?,
+        // Through set_body like the scale model sliders, which rescales
+        // the scene around it
+        "body.planet_radius_km"
+        | "body.sun_distance_au"
+        | "body.day_length_hours"
+        | "body.year_length_hours" => {
+            let mut body = p.body;
+            let field = match key {
+                "body.planet_radius_km" => &mut body.planet_radius_km,
+                "body.sun_distance_au" => &mut body.sun_distance_au,
+                "body.day_length_hours" => &mut body.day_length_hours,
+                _ => &mut body.year_length_hours,
+            };
+            *field = value.parse()?;
+            p.set_body(body);
+        }
+        "body.axial_tilt" => p.body.axial_tilt = value.parse()?,
+        "body.surface_gravity" => p.body.surface_gravity = value.parse()?,
+        "sun_angle" => p.view.sun_angle = value.parse()?,
+        "season" => p.season = value.parse()?,
+        // Captures from before the arcball only spun the planet
+        "rot" => p.view.rotation = Quaternion::from_angle_y(Deg(value.parse::<f32>()?)),
+        "rotation" => {
+            let parts = value
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<Vec<f32>, _>>()?;
+            match parts[..] {
+                [s, x, y, z] => p.view.rotation = Quaternion::new(s, x, y, z).normalize(),
+                _ => return Err(format!("rotation '{}' is not 's x y z'", value).into()),
+            }
+        }
+        "orbit_yaw" => p.view.orbit_yaw = value.parse()?,
+        "orbit_pitch" => p.view.orbit_pitch = value.parse()?,
+        "pan.x" => p.view.pan.x = value.parse()?,
+        "pan.y" => p.view.pan.y = value.parse()?,
+        "camera_distance" => p.set_camera_distance(value.parse()?),
+        "zoom.min_altitude_km" => p.view.min_zoom_altitude_km = value.parse()?,
+        "zoom.max_radii" => p.view.max_zoom_radii = value.parse()?,
+        // Past the easing, like the rest of a capture
+        "fov" => {
+            p.view.fov = value.parse()?;
+            p.view.shown_fov = p.view.fov;
+        }
+        "sun_cycle.enabled" => p.sun_cycle.enabled = value.parse()?,
+        "sun_cycle.period" => p.sun_cycle.period = value.parse()?,
+        "sun_cycle.seasons" => p.sun_cycle.seasons = value.parse()?,
+        "sun_cycle.season_period" => p.sun_cycle.season_period = value.parse()?,
+        "auto_rotate.enabled" => p.auto_rotate.enabled = value.parse()?,
+        "auto_rotate.speed" => p.auto_rotate.speed_deg_per_sec = value.parse()?,
+        "camera_pos.x" => p.camera_pos.x = value.parse()?,
+        "camera_pos.y" => p.camera_pos.y = value.parse()?,
+        "camera_pos.z" => p.camera_pos.z = value.parse()?,
+        "fly.enabled" => p.fly.enabled = value.parse()?,
+        "fly.yaw" => p.fly.yaw = value.parse()?,
+        "fly.pitch" => p.fly.pitch = value.parse()?,
+        "shadow_resolution" => p.shadow_resolution = value.parse()?,
+        "cloud_resolution" => p.cloud_resolution = CloudResolution::parse(value)?,
+        "lod_bias" => p.geometry.lod_bias = value.parse()?,
+        // The mesh is rebuilt with them on the next frame
+        "displacement.seed" => p.geometry.displacement.seed = value.parse()?,
+        "displacement.amplitude" => p.geometry.displacement.amplitude = value.parse()?,
+        "displacement.octaves" => p.geometry.displacement.octaves = value.parse()?,
+        "displacement.frequency" => p.geometry.displacement.frequency = value.parse()?,
+        "craters.count" => p.geometry.displacement.craters.count = value.parse()?,
+        "craters.min_size" => p.geometry.displacement.craters.min_size = value.parse()?,
+        "craters.max_size" => p.geometry.displacement.craters.max_size = value.parse()?,
+        "craters.depth" => p.geometry.displacement.craters.depth = value.parse()?,
+        "flattening" => p.geometry.flattening = value.parse()?,
+        "occlusion_auto_skip" => p.occlusion_auto_skip = value.parse()?,
+        "surface.enabled" => p.surface.enabled = value.parse()?,
+        "surface.latitude" => p.surface.latitude = value.parse()?,
+        "surface.longitude" => p.surface.longitude = value.parse()?,
+        "surface.heading" => p.surface.heading = value.parse()?,
+        "surface.pitch" => p.surface.pitch = value.parse()?,
+        "surface.eye_height_km" => p.surface.eye_height_km = value.parse()?,
+        // A capture is of the view once the blend is over
+        "satellite.enabled" => {
+            p.satellite.enabled = value.parse()?;
+            p.satellite.snap();
+        }
+        "satellite.satellite" => {
+            let satellite: usize = value.parse()?;
+            if satellite >= SATELLITES.len() {
+                return Err(format!("unknown satellite {}", satellite).into());
+            }
+            p.satellite.satellite = satellite as i32;
+        }
+        "satellite.nadir" => p.satellite.nadir = value.parse()?,
+        "satellite.time_warp" => p.satellite.time_warp = value.parse()?,
+        "satellite.time" => p.satellite.time = value.parse()?,
+        "palette" => p.colors = ColorPalette::load(value)?,
+        "detail.enabled" => p.detail.enabled = value.parse()?,
+        "detail.scale" => p.detail.scale = value.parse()?,
+        "detail.fade_distance_km" => p.detail.fade_distance_km = value.parse()?,
+        "detail.strength" => p.detail.strength = value.parse()?,
+        "detail.normals" => p.detail.normals = value.parse()?,
+        "detail.relief" => p.detail.relief = value.parse()?,
+        "vegetation.enabled" => p.vegetation.settings.enabled = value.parse()?,
+        "vegetation.seed" => p.vegetation.settings.seed = value.parse()?,
+        "vegetation.candidates" => p.vegetation.settings.candidates = value.parse()?,
+        "vegetation.size" => p.vegetation.settings.size = value.parse()?,
+        "vegetation.view_distance_km" => p.vegetation.settings.view_distance_km = value.parse()?,
+        "magnetosphere.enabled" => p.magnetosphere.settings.enabled = value.parse()?,
+        "magnetosphere.shells" => p.magnetosphere.settings.shells = value.parse()?,
+        "magnetosphere.lines_per_shell" => {
+            p.magnetosphere.settings.lines_per_shell = value.parse()?
+        }
+        "magnetosphere.max_shell" => p.magnetosphere.settings.max_shell = value.parse()?,
+        "magnetosphere.axis_tilt" => p.magnetosphere.settings.axis_tilt = value.parse()?,
+        "magnetosphere.axis_azimuth" => p.magnetosphere.settings.axis_azimuth = value.parse()?,
+        "magnetosphere.compression" => p.magnetosphere.settings.compression = value.parse()?,
+        "magnetosphere.tail" => p.magnetosphere.settings.tail = value.parse()?,
+        "magnetosphere.line_width" => p.magnetosphere.settings.line_width = value.parse()?,
+        "wind.enabled" => p.wind.settings.enabled = value.parse()?,
+        "wind.time_scale" => p.wind.settings.time_scale = value.parse()?,
+        "wind.curve" => p.wind.set_curve(WindCurve::parse(value)?),
+        "entry.enabled" => p.entry.settings.enabled = value.parse()?,
+        "meteors.enabled" => p.meteors.settings.enabled = value.parse()?,
+        "meteors.seed" => p.meteors.settings.seed = value.parse()?,
+        "meteors.rate" => p.meteors.settings.rate = value.parse()?,
+        "meteors.radiant_latitude" => p.meteors.settings.radiant_latitude = value.parse()?,
+        "meteors.radiant_longitude" => p.meteors.settings.radiant_longitude = value.parse()?,
+        "meteors.speed_km" => p.meteors.settings.speed_km = value.parse()?,
+        "meteors.width_km" => p.meteors.settings.width_km = value.parse()?,
+        "meteors.brightness" => p.meteors.settings.brightness = value.parse()?,
+        "sun.enabled" => p.sun.settings.enabled = value.parse()?,
+        "sun.intensity" => p.sun.settings.intensity = value.parse()?,
+        "sun.limb_darkening" => p.sun.settings.limb_darkening = value.parse()?,
+        "sun.granulation" => p.sun.settings.granulation = value.parse()?,
+        "sun.granulation_period" => p.sun.settings.granulation_period = value.parse()?,
+        "sun.corona" => p.sun.settings.corona = value.parse()?,
+        "sun.corona_size" => p.sun.settings.corona_size = value.parse()?,
+        "sun.disc_scale" => p.sun.settings.disc_scale = value.parse()?,
+        "vsync" => p.vsync = value.parse()?,
+        "frame_limit.enabled" => p.frame_limiter.enabled = value.parse()?,
+        "frame_limit.fps" => p.frame_limiter.target_fps = value.parse()?,
+        "clock.time" => p.clock.time = value.parse()?,
+        "clock.scale" => p.clock.scale = value.parse()?,
+        "clock.paused" => p.clock.paused = value.parse()?,
+        // Switching it on starts from the body's, or Earth's, like the UI
+        "atmosphere.enabled" => {
+            p.atmosphere = if value.parse()? {
+                p.atmosphere.or(p.body.atmosphere).or(PRESETS[1].atmosphere)
+            } else {
+                None
+            }
+        }
+        "atmosphere.height_km"
+        | "atmosphere.scale_height_km"
+        | "atmosphere.density"
+        | "atmosphere.tint" => {
+            let atmosphere = p
+                .atmosphere
+                .as_mut()
+                .ok_or_else(|| format!("{} without an atmosphere", key))?;
+            match key {
+                "atmosphere.height_km" => atmosphere.height_km = value.parse()?,
+                "atmosphere.scale_height_km" => atmosphere.scale_height_km = value.parse()?,
+                "atmosphere.density" => atmosphere.density = value.parse()?,
+                _ => atmosphere.tint = parse_floats(value)?,
+            }
+        }
+        "atmosphere_shell.enabled" => p.atmosphere_shell.settings.enabled = value.parse()?,
+        "atmosphere_shell.thickness" => p.atmosphere_shell.settings.thickness = value.parse()?,
+        "atmosphere_shell.tint" => p.atmosphere_shell.settings.tint = parse_floats(value)?,
+        "atmosphere_shell.intensity" => p.atmosphere_shell.settings.intensity = value.parse()?,
+        "ring.enabled" => p.ring.settings.enabled = value.parse()?,
+        "ring.inner_radius" => p.ring.settings.inner_radius = value.parse()?,
+        "ring.outer_radius" => p.ring.settings.outer_radius = value.parse()?,
+        "ring.opacity" => p.ring.settings.opacity = value.parse()?,
+        "skybox.enabled" => p.skybox.enabled = value.parse()?,
+        "skybox.brightness" => p.skybox.brightness = value.parse()?,
+        "star_parallax" => p.star_parallax = value.parse()?,
+        "cell_view" => p.cell_view = value.parse()?,
+        "labels.enabled" => p.labels.settings.enabled = value.parse()?,
+        "labels.size_km" => p.labels.settings.size_km = value.parse()?,
+        "labels.min_pixels" => p.labels.settings.min_pixels = value.parse()?,
+        "labels.max_pixels" => p.labels.settings.max_pixels = value.parse()?,
+        "labels.fade_distance_km" => p.labels.settings.fade_distance_km = value.parse()?,
+        "labels.color" => p.labels.settings.color = parse_floats(value)?,
+        "labels.list" => {
+            p.labels.labels = read_labels(value)?;
+            p.labels.invalidate();
+        }
+        "celestial.orbit_view" => p.celestial.settings.orbit_view = value.parse()?,
+        "celestial.equator" => p.celestial.settings.equator = value.parse()?,
+        "celestial.ecliptic" => p.celestial.settings.ecliptic = value.parse()?,
+        "celestial.grid" => p.celestial.settings.grid = value.parse()?,
+        "celestial.hour_marks" => p.celestial.settings.hour_marks = value.parse()?,
+        "celestial.sun_path" => p.celestial.settings.sun_path = value.parse()?,
+        "celestial.labels" => p.celestial.settings.labels = value.parse()?,
+        "celestial.equator_color" => p.celestial.settings.equator_color = parse_floats(value)?,
+        "celestial.ecliptic_color" => p.celestial.settings.ecliptic_color = parse_floats(value)?,
+        "celestial.grid_color" => p.celestial.settings.grid_color = parse_floats(value)?,
+        "celestial.sun_path_color" => p.celestial.settings.sun_path_color = parse_floats(value)?,
+        "celestial.line_width" => p.celestial.settings.line_width = value.parse()?,
+        "celestial.label_size" => p.celestial.settings.label_size = value.parse()?,
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+fn apply_settings(
+    p: &mut State,
+    frame: &mut FrameSettings,
+    lines: &[(&str, &str)],
+) -> Result<(), Box<dyn error::Error>> {
+    // The preset moves the planet and the sun, so it goes first and the
+    // sun is placed once everything else is set
+    for &(key, value) in lines.iter().filter(|&&(key, _)| key == "preset") {
+        apply_setting(p, frame, key, value)?;
+    }
+    for &(key, value) in lines.iter().filter(|&&(key, _)| key != "preset") {
+        apply_setting(p, frame, key, value)?;
+    }
+    p.update_sun_pos();
+    Ok(())
+}
+
+/// Puts saved `settings` back into a running state, leaving the frame size
+/// and time alone.
+pub fn restore(p: &mut State, settings: &[(String, String)]) -> Result<(), Box<dyn error::Error>> {
+    let lines: Vec<(&str, &str)> = settings
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let mut frame = FrameSettings {
+        width: 0,
+        height: 0,
+        time: 0.0,
+    };
+    apply_settings(p, &mut frame, &lines)?;
+
+    p.vegetation.regenerate();
+    p.magnetosphere.regenerate();
+    Ok(())
+}
+
+/// File names the shader sources are stored under in a capture, the ones
+/// of the shader files they were loaded from.
+fn shader_file_names(vert_path: &str, frag_path: &str) -> (String, String) {
+    let name = |path: &str| {
+        Path::new(path).file_name().map_or_else(
+            || path.to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        )
+    };
+    (name(vert_path), name(frag_path))
+}
+
+/// Renders the scene offscreen and returns its RGBA pixels, top row first.
+pub fn render_image<F: Facade>(
+    facade: &F,
+    p: &State,
+    width: u32,
+    height: u32,
+    time: f32,
+) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let shadowmap = Shadowmap::new(facade, p.shadowmap_size(width, height))?;
+    let mut shadowmap_framebuffer = shadowmap.framebuffer(facade)?;
+
+    let color_texture = Texture2d::empty_with_format(
+        facade,
+        UncompressedFloatFormat::U8U8U8U8,
+        MipmapsOption::NoMipmap,
+        width,
+        height,
+    )?;
+    let depthbuffer = DepthRenderBuffer::new(facade, DepthFormat::F32, width, height)?;
+    let mut target = SimpleFrameBuffer::with_depth_buffer(facade, &color_texture, &depthbuffer)?;
+
+    render_scene(
+        &mut target,
+        p,
+        &mut shadowmap_framebuffer,
+        &shadowmap.texture,
+        None,
+        width as f32 / height as f32,
+        time,
+    )?;
+
+    Ok(read_pixels(&color_texture))
+}
+
+/// The RGBA pixels of `texture`, top row first.
+pub fn read_pixels(texture: &Texture2d) -> Vec<u8> {
+    let image: RawImage2d<u8> = texture.read();
+    let stride = 4 * image.width as usize;
+    image
+        .data
+        .chunks(stride)
+        .rev()
+        .flat_map(|row| row.iter().cloned())
+        .collect()
+}
+
+/// Writes the current frame to `captures/debug_<timestamp>.zip`: the
+/// settings, the shader sources exactly as compiled, the GL renderer and
+/// the frame rendered without the UI.
+pub fn save<F: Facade>(
+    facade: &F,
+    p: &State,
+    width: u32,
+    height: u32,
+    time: f32,
+) -> Result<PathBuf, Box<dyn error::Error>> {
+    let mut files = Vec::new();
+
+    let mut settings_file = Vec::new();
+    for (key, value) in settings(p, width, height, time) {
+        writeln!(settings_file, "{}={}", key, value)?;
+    }
+    files.push(("settings.txt".to_string(), settings_file));
+
+    for shader in p.shaders().iter() {
+        let (vert_name, frag_name) = shader_file_names(&shader.vert_path, &shader.frag_path);
+        files.push((
+            format!("shaders/{}", vert_name),
+            shader.vertex_source.clone().into_bytes(),
+        ));
+        files.push((
+            format!("shaders/{}", frag_name),
+            shader.fragment_source.clone().into_bytes(),
+        ));
+    }
+
+    let context = facade.get_context();
+    files.push((
+        "renderer.txt".to_string(),
+        format!(
+            "{}\n{}\n{}\n",
+            context.get_opengl_vendor_string(),
+            context.get_opengl_renderer_string(),
+            context.get_opengl_version_string()
+        )
+        .into_bytes(),
+    ));
+
+    let pixels = render_image(facade, p, width, height, time)?;
+    let mut png = Vec::new();
+    PngEncoder::new(&mut png).write_image(&pixels, width, height, ColorType::Rgba8)?;
+    files.push(("frame.png".to_string(), png));
+
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    fs::create_dir_all("captures")?;
+    let path = PathBuf::from("captures").join(format!("debug_{}.zip", timestamp));
+    fs::write(&path, zip::write(&files)?)?;
+    Ok(path)
+}
+
+/// Restores a capture into a fresh headless state, renders the frame again
+/// into `<capture>.replay.png` next to it and reports how far that is from
+/// the captured one.
+pub fn load(path: &Path, max_gl: (u8, u8)) -> Result<(), Box<dyn error::Error>> {
+    let files = zip::read(&fs::read(path)?)?;
+    let file = |name: &str| {
+        files
+            .iter()
+            .find(|(file, _)| file == name)
+            .map(|(_, data)| data.as_slice())
+    };
+
+    let settings = String::from_utf8(
+        file("settings.txt")
+            .ok_or("no settings.txt in the debug capture")?
+            .to_vec(),
+    )?;
+    let lines: Vec<(&str, &str)> = settings
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => Ok((key.trim(), value.trim())),
+                _ => Err(format!("malformed capture setting '{}'", line)),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+
+    let mut frame = FrameSettings {
+        width: 1280,
+        height: 720,
+        time: 0.0,
+    };
+    for &(key, value) in &lines {
+        match key {
+            "width" => frame.width = value.parse()?,
+            "height" => frame.height = value.parse()?,
+            _ => {}
+        }
+    }
+    if frame.width == 0 || frame.height == 0 {
+        return Err("debug capture width and height must be non-zero".into());
+    }
+
+    let facade = capabilities::create_headless(frame.width, frame.height, max_gl)?;
+
+    let mut p = State::new(&facade, max_gl, PlanetMesh::UvSphere)?;
+
+    apply_settings(&mut p, &mut frame, &lines)?;
+
+    for shader in p.shaders_mut().iter_mut() {
+        let (vert_name, frag_name) = shader_file_names(&shader.vert_path, &shader.frag_path);
+        let source = |name: &str| {
+            file(&format!("shaders/{}", name))
+                .map(|source| String::from_utf8_lossy(source).into_owned())
+        };
+        match (source(&vert_name), source(&frag_name)) {
+            (Some(vertex), Some(fragment)) => shader.replace_source(&facade, vertex, fragment)?,
+            _ => println!(
+                "Debug capture: no {} / {}, using the current shaders",
+                vert_name, frag_name
+            ),
+        }
+    }
+
+    p.geometry.wait(&facade)?;
+    p.vegetation.regenerate();
+    p.vegetation.wait(&facade)?;
+    p.magnetosphere.regenerate();
+    p.magnetosphere.update(&facade)?;
+    p.wind.update(&facade)?;
+
+    let pixels = render_image(&facade, &p, frame.width, frame.height, frame.time)?;
+    let replay = path.with_extension("replay.png");
+    image::save_buffer(
+        &replay,
+        &pixels,
+        frame.width,
+        frame.height,
+        image::ColorType::Rgba8,
+    )?;
+
+    match file("frame.png")
+        .ok_or_else(|| "no frame.png".to_string())
+        .and_then(|png| image::load_from_memory(png).map_err(|e| e.to_string()))
+    {
+        Ok(captured) => {
+            let captured = captured.to_rgba8();
+            if captured.dimensions() != (frame.width, frame.height) {
+                return Err("captured frame doesn't match the capture size".into());
+            }
+
+            let difference = captured
+                .as_raw()
+                .iter()
+                .zip(&pixels)
+                .map(|(&a, &b)| (i32::from(a) - i32::from(b)).abs() as f64)
+                .sum::<f64>()
+                / pixels.len() as f64;
+
+            println!(
+                "Debug capture: replayed into {}, mean difference {:.3} / 255",
+                replay.display(),
+                difference
+            );
+        }
+        Err(e) => println!("Debug capture: no captured frame to compare with ({})", e),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    // A State can't be made without a GL context, so the round trip is
+    // checked on the source of `settings` and `set` and on the values
+    // that aren't plain numbers
+    const SOURCE: &str = include_str!("capture.rs");
+
+    fn function(name: &str) -> &'static str {
+        let start = SOURCE
+            .find(&format!("pub fn {}(", name))
+            .unwrap_or_else(|| panic!("no function {}", name));
+        let end = SOURCE[start..].find("\n}\n").unwrap();
+        &SOURCE[start..start + end]
+    }
+
+    /// The string literals in `body` that are followed by `after`.
+    fn keys(body: &'static str, after: &str) -> BTreeSet<&'static str> {
+        body.split('"')
+            .enumerate()
+            .filter(|&(i, _)| i % 2 == 1)
+            .map(|(_, literal)| literal)
+            .filter(|literal| {
+                !literal.is_empty()
+                    && literal.chars().all(|c| {
+                        c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '.'
+                    })
+            })
+            .filter(|literal| body.contains(&format!("\"{}\"{}", literal, after)))
+            .collect()
+    }
+
+    #[test]
+    fn every_written_setting_is_restored() {
+        let written = keys(function("settings"), ",");
+        // Arms matching several keys have them on lines of their own
+        let set = function("set");
+        let restored: BTreeSet<_> = keys(set, " =>").union(&keys(set, "\n")).copied().collect();
+
+        // Handled by apply_setting, they aren't part of the state
+        let frame: BTreeSet<_> = ["width", "height", "time"].iter().copied().collect();
+        let missing: Vec<_> = written
+            .difference(&restored)
+            .filter(|key| !frame.contains(*key))
+            .collect();
+        assert!(
+            missing.is_empty(),
+            "written but not restored: {:?}",
+            missing
+        );
+
+        // Only read from captures made before the arcball
+        let unwritten: Vec<_> = restored
+            .difference(&written)
+            .filter(|&&key| key != "rot")
+            .collect();
+        assert!(
+            unwritten.is_empty(),
+            "restored but not written: {:?}",
+            unwritten
+        );
+    }
+
+    #[test]
+    fn the_whole_render_state_is_written() {
+        let written = keys(function("settings"), ",");
+        let required = [
+            "scale_model",
+            "body.planet_radius_km",
+            "body.sun_distance_au",
+            "body.day_length_hours",
+            "body.year_length_hours",
+            "body.axial_tilt",
+            "body.surface_gravity",
+            "atmosphere.enabled",
+            "atmosphere.height_km",
+            "atmosphere.scale_height_km",
+            "atmosphere.density",
+            "atmosphere.tint",
+            "atmosphere_shell.enabled",
+            "atmosphere_shell.thickness",
+            "atmosphere_shell.tint",
+            "atmosphere_shell.intensity",
+            "ring.enabled",
+            "ring.inner_radius",
+            "ring.outer_radius",
+            "ring.opacity",
+            "skybox.enabled",
+            "skybox.brightness",
+            "star_parallax",
+            "cell_view",
+            "labels.enabled",
+            "labels.size_km",
+            "labels.min_pixels",
+            "labels.max_pixels",
+            "labels.fade_distance_km",
+            "labels.color",
+            "labels.list",
+            "celestial.orbit_view",
+            "celestial.equator",
+            "celestial.ecliptic",
+            "celestial.grid",
+            "celestial.hour_marks",
+            "celestial.sun_path",
+            "celestial.labels",
+            "celestial.equator_color",
+            "celestial.ecliptic_color",
+            "celestial.grid_color",
+            "celestial.sun_path_color",
+            "celestial.line_width",
+            "celestial.label_size",
+            "meteors.enabled",
+            "meteors.seed",
+            "meteors.rate",
+            "meteors.radiant_latitude",
+            "meteors.radiant_longitude",
+            "meteors.speed_km",
+            "meteors.width_km",
+            "meteors.brightness",
+        ];
+        let missing: Vec<_> = required
+            .iter()
+            .filter(|key| !written.contains(*key))
+            .collect();
+        assert!(missing.is_empty(), "not written: {:?}", missing);
+    }
+
+    #[test]
+    fn colors_read_back() {
+        let color = [0.25, 1.0e-3, 0.8, 1.0];
+        assert_eq!(parse_floats::<4>(&floats(&color)).unwrap(), color);
+        assert!(parse_floats::<3>(&floats(&color)).is_err());
+        assert!(parse_floats::<3>("0.5 red 1").is_err());
+    }
+
+    #[test]
+    fn labels_read_back() {
+        let mut summit = Label::new("Olympus  Mons", 18.65, -133.8, 21.9);
+        summit.world_space = false;
+        let labels = vec![Label::new("Base", -12.5, 0.0, 0.0), summit];
+
+        let read = read_labels(&write_labels(&labels)).unwrap();
+        assert_eq!(read.len(), labels.len());
+        for (read, label) in read.iter().zip(&labels) {
+            assert_eq!(read.text.to_str(), label.text.to_str());
+            assert_eq!(read.latitude, label.latitude);
+            assert_eq!(read.longitude, label.longitude);
+            assert_eq!(read.altitude_km, label.altitude_km);
+            assert_eq!(read.world_space, label.world_space);
+        }
+
+        assert!(read_labels(&write_labels(&[])).unwrap().is_empty());
+        assert!(read_labels("12 north").is_err());
+    }
+
+    #[test]
+    fn cloud_resolutions_read_back() {
+        for &resolution in CloudResolution::ALL.iter() {
+            let written = resolution.name().to_lowercase();
+            assert_eq!(CloudResolution::parse(&written).unwrap(), resolution);
+        }
+    }
+
+    #[test]
+    fn wind_curves_read_back() {
+        let curve = WindCurve::new(&[[-90.0, 0.0], [-30.5, -7.25], [0.0, 20.0], [90.0, 0.0]]);
+        let written = curve.to_string();
+        let read = WindCurve::parse(&written).unwrap();
+        assert_eq!(read.points, curve.points);
+        assert_eq!(read.to_string(), written);
+    }
+
+    #[test]
+    fn numbers_read_back_exactly() {
+        // Written with Display, which is the shortest that parses back
+        for &value in [0.1f32, -1.0e-7, 6371.008, std::f32::consts::PI].iter() {
+            assert_eq!(value.to_string().parse::<f32>().unwrap(), value);
+        }
+        let far = 1.495_978_707e8f64 + 0.123;
+        assert_eq!(far.to_string().parse::<f64>().unwrap(), far);
+    }
+}
@@ -0,0 +1,452 @@
+use crate::labels::{FontAtlas, LabelVertex};
+use crate::orbit;
+use crate::polyline::{PolylineBatch, PolylineVertex};
+use crate::stream::StreamPool;
+use crate::units::Preset;
+use cgmath::{vec3, InnerSpace, Vector3};
+use glium::{backend::Facade, VertexBuffer};
+use std::error;
+
+// Points on each great circle, and on the sun's path over a day
+const CIRCLE_SEGMENTS: usize = 180;
+const SUN_PATH_SAMPLES: usize = 288;
+
+// Half length of the hour marks across the equator, in degrees
+const HOUR_MARK: f64 = 1.5;
+
+// Spacing of the right ascension and declination grid
+const GRID_RA_STEP_HOURS: u32 = 2;
+const GRID_DECLINATION_STEP: i32 = 30;
+
+/// Direction in the sky-fixed frame of a point `azimuth` degrees around the
+/// pole from the sun at an angle of zero, `declination` degrees north of
+/// the celestial equator.
+pub fn sky_direction(azimuth: f64, declination: f64) -> Vector3<f64> {
+    let (azimuth, declination) = (azimuth.to_radians(), declination.to_radians());
+    vec3(
+        declination.cos() * azimuth.sin(),
+        declination.sin(),
+        -declination.cos() * azimuth.cos(),
+    )
+}
+
+/// The great circle through `start` and `toward`, as a closed strip of
+/// `segments + 1` unit vectors beginning and ending at `start`. `toward`
+/// only picks the plane and need not be at right angles to `start`.
+pub fn great_circle(
+    start: Vector3<f64>,
+    toward: Vector3<f64>,
+    segments: usize,
+) -> Vec<Vector3<f64>> {
+    let start = start.normalize();
+    let side = (toward - start * start.dot(toward)).normalize();
+    (0..=segments)
+        .map(|i| {
+            let angle = std::f64::consts::TAU * i as f64 / segments as f64;
+            start * angle.cos() + side * angle.sin()
+        })
+        .collect()
+}
+
+/// The circle `declination` degrees north of the celestial equator, as a
+/// closed strip of `segments + 1` unit vectors from an azimuth of zero.
+pub fn parallel(declination: f64, segments: usize) -> Vec<Vector3<f64>> {
+    (0..=segments)
+        .map(|i| sky_direction(360.0 * i as f64 / segments as f64, declination))
+        .collect()
+}
+
+/// Where the sun goes over the day from `sun`, `SUN_PATH_SAMPLES + 1`
+/// directions in the sky-fixed frame.
+fn sun_path(preset: &Preset, sun: SunPlace) -> Vec<Vector3<f64>> {
+    (0..=SUN_PATH_SAMPLES)
+        .map(|i| {
+            let hours = preset.day_length_hours * i as f64 / SUN_PATH_SAMPLES as f64;
+            sun.after(preset, hours).direction(preset)
+        })
+        .collect()
+}
+
+/// Degrees above the horizon of the highest of `path`, seen with the
+/// zenith at the unit vector `up`.
+fn highest_altitude(path: &[Vector3<f64>], up: Vector3<f64>) -> Option<f64> {
+    path.iter()
+        .map(|direction| direction.dot(up).clamp(-1.0, 1.0).asin().to_degrees())
+        .fold(None, |highest: Option<f64>, altitude| {
+            Some(highest.map_or(altitude, |highest| highest.max(altitude)))
+        })
+}
+
+fn to_f32(v: Vector3<f64>) -> Vector3<f32> {
+    vec3(v.x as f32, v.y as f32, v.z as f32)
+}
+
+/// Where the sun is in the sky.
+#[derive(Copy, Clone)]
+pub struct SunPlace {
+    // The sun angle, which stands in for the planet's spin
+    pub angle: f64,
+    // Degrees along the ecliptic from the northern spring equinox
+    pub season: f64,
+}
+
+impl SunPlace {
+    pub fn declination(self, preset: &Preset) -> f64 {
+        orbit::sun_declination(preset.axial_tilt, self.season)
+    }
+
+    pub fn direction(self, preset: &Preset) -> Vector3<f64> {
+        sky_direction(self.angle, self.declination(preset))
+    }
+
+    /// Azimuth of the spring equinox. The grid turns with the sun angle so
+    /// that the sun stays on the ecliptic.
+    fn equinox(self, preset: &Preset) -> f64 {
+        self.angle - orbit::ecliptic_right_ascension(preset.axial_tilt, self.season)
+    }
+
+    /// `hours` of sim time later, with the sun angle going round once a day
+    /// and the season once a year.
+    fn after(self, preset: &Preset, hours: f64) -> SunPlace {
+        SunPlace {
+            angle: self.angle + 360.0 * hours / preset.day_length_hours,
+            season: self.season + 360.0 * hours / preset.year_length_hours,
+        }
+    }
+}
+
+pub struct CelestialSettings {
+    // Also drawn around the orbit camera, not just from the surface
+    pub orbit_view: bool,
+    pub equator: bool,
+    pub ecliptic: bool,
+    pub grid: bool,
+    pub hour_marks: bool,
+    pub sun_path: bool,
+    pub labels: bool,
+    pub equator_color: [f32; 4],
+    pub ecliptic_color: [f32; 4],
+    pub grid_color: [f32; 4],
+    pub sun_path_color: [f32; 4],
+    // In pixels
+    pub line_width: f32,
+    // Height of a line of label text, in degrees of sky
+    pub label_size: f32,
+}
+
+impl CelestialSettings {
+    pub fn new() -> CelestialSettings {
+        CelestialSettings {
+            orbit_view: false,
+            equator: true,
+            ecliptic: true,
+            grid: false,
+            hour_marks: true,
+            sun_path: true,
+            labels: true,
+            equator_color: [0.45, 0.7, 1.0, 0.6],
+            ecliptic_color: [1.0, 0.8, 0.35, 0.6],
+            grid_color: [0.6, 0.6, 0.75, 0.3],
+            sun_path_color: [1.0, 0.5, 0.25, 0.8],
+            line_width: 1.5,
+            label_size: 1.2,
+        }
+    }
+}
+
+/// Reference circles of the sky: the celestial equator with hour marks, a
+/// right ascension and declination grid, the ecliptic and the sun's path
+/// over the next day. All of it is in the sky-fixed frame on a unit sphere,
+/// centered on the eye when drawn.
+pub struct Celestial {
+    pub settings: CelestialSettings,
+    pub lines: PolylineBatch,
+    pub label_buffer: Option<VertexBuffer<LabelVertex>>,
+    pub label_count: usize,
+    // Highest the sun gets on the path, in degrees above the horizon
+    pub path_noon_altitude: Option<f64>,
+}
+
+impl Celestial {
+    pub fn new() -> Celestial {
+        Celestial {
+            settings: CelestialSettings::new(),
+            lines: PolylineBatch::new(),
+            label_buffer: None,
+            label_count: 0,
+            path_noon_altitude: None,
+        }
+    }
+
+    /// Rebuilds the circles for the sun at `sun`, seen from where `up`
+    /// points in the sky-fixed frame.
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        pool: &mut StreamPool<PolylineVertex>,
+        preset: &Preset,
+        sun: SunPlace,
+        up: Vector3<f64>,
+        atlas: Option<&FontAtlas>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let settings = &self.settings;
+        let equinox = sun.equinox(preset);
+        let tilt = preset.axial_tilt;
+        let mut labels: Vec<(String, Vector3<f64>)> = Vec::new();
+
+        self.lines.clear();
+
+        if settings.grid {
+            let color = settings.grid_color;
+            // Each great circle through the poles carries two hours
+            for hour in (0..12).step_by(GRID_RA_STEP_HOURS as usize) {
+                let azimuth = equinox + 15.0 * f64::from(hour);
+                let meridian = great_circle(
+                    sky_direction(azimuth, 0.0),
+                    vec3(0.0, 1.0, 0.0),
+                    CIRCLE_SEGMENTS,
+                );
+                let points: Vec<_> = meridian
+                    .iter()
+                    .map(|&point| (to_f32(point), color))
+                    .collect();
+                self.lines.push(&points);
+            }
+            for step in 1..90 / GRID_DECLINATION_STEP {
+                for declination in [step * GRID_DECLINATION_STEP, -step * GRID_DECLINATION_STEP] {
+                    let circle = parallel(f64::from(declination), CIRCLE_SEGMENTS);
+                    let points: Vec<_> =
+                        circle.iter().map(|&point| (to_f32(point), color)).collect();
+                    self.lines.push(&points);
+                    labels.push((
+                        format!("{:+}\u{b0}", declination),
+                        sky_direction(equinox + 1.0, f64::from(declination) + 1.0),
+                    ));
+                }
+            }
+            // The hour marks label every hour already
+            if !settings.hour_marks {
+                for hour in (0..24).step_by(6) {
+                    let azimuth = equinox + 15.0 * f64::from(hour);
+                    labels.push((format!("{}h", hour), sky_direction(azimuth, -3.0)));
+                }
+            }
+        }
+
+        if settings.equator {
+            let color = settings.equator_color;
+            let equator = great_circle(
+                sky_direction(0.0, 0.0),
+                sky_direction(90.0, 0.0),
+                CIRCLE_SEGMENTS,
+            );
+            let points: Vec<_> = equator
+                .iter()
+                .map(|&point| (to_f32(point), color))
+                .collect();
+            self.lines.push(&points);
+            labels.push((
+                "Celestial equator".to_string(),
+                sky_direction(equinox + 97.5, 2.0),
+            ));
+        }
+
+        if settings.hour_marks {
+            let color = settings.equator_color;
+            for hour in 0..24 {
+                let azimuth = equinox + 15.0 * f64::from(hour);
+                self.lines.push(&[
+                    (to_f32(sky_direction(azimuth, -HOUR_MARK)), color),
+                    (to_f32(sky_direction(azimuth, HOUR_MARK)), color),
+                ]);
+                labels.push((format!("{}h", hour), sky_direction(azimuth, -3.0)));
+            }
+        }
+
+        if settings.ecliptic {
+            let color = settings.ecliptic_color;
+            // From the spring equinox through the summer solstice
+            let ecliptic = great_circle(
+                sky_direction(equinox, 0.0),
+                sky_direction(equinox + 90.0, tilt),
+                CIRCLE_SEGMENTS,
+            );
+            let points: Vec<_> = ecliptic
+                .iter()
+                .map(|&point| (to_f32(point), color))
+                .collect();
+            self.lines.push(&points);
+
+            let longitude = 262.5;
+            labels.push((
+                "Ecliptic".to_string(),
+                sky_direction(
+                    equinox + orbit::ecliptic_right_ascension(tilt, longitude),
+                    orbit::sun_declination(tilt, longitude) + 2.0,
+                ),
+            ));
+        }
+
+        self.path_noon_altitude = None;
+        if settings.sun_path {
+            let color = settings.sun_path_color;
+            let path = sun_path(preset, sun);
+            let up = up.normalize();
+            self.path_noon_altitude = highest_altitude(&path, up);
+
+            let points: Vec<_> = path.iter().map(|&point| (to_f32(point), color)).collect();
+            self.lines.push(&points);
+            labels.push((
+                "Sun path".to_string(),
+                (path[SUN_PATH_SAMPLES / 8] + up * 0.03).normalize(),
+            ));
+        }
+
+        self.lines.stream(facade, pool)?;
+
+        if !settings.labels {
+            labels.clear();
+        }
+        self.upload_labels(facade, &labels, atlas)
+    }
+
+    fn upload_labels<F: Facade>(
+        &mut self,
+        facade: &F,
+        labels: &[(String, Vector3<f64>)],
+        atlas: Option<&FontAtlas>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let mut vertices = Vec::new();
+        if let Some(atlas) = atlas {
+            for (text, direction) in labels {
+                atlas.layout(text, to_f32(*direction).into(), &mut vertices);
+            }
+        }
+
+        self.label_count = vertices.len();
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        let fits = self
+            .label_buffer
+            .as_ref()
+            .is_some_and(|buffer| buffer.len() >= vertices.len());
+        if !fits {
+            self.label_buffer = Some(VertexBuffer::empty_dynamic(
+                facade,
+                vertices.len().next_power_of_two(),
+            )?);
+        }
+        if let Some(ref buffer) = self.label_buffer {
+            buffer
+                .slice(0..vertices.len())
+                .ok_or("celestial label vertices out of range")?
+                .write(&vertices);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::PRESETS;
+    use std::f64::consts::TAU;
+
+    fn close(a: Vector3<f64>, b: Vector3<f64>) -> bool {
+        (a - b).magnitude() < 1e-9
+    }
+
+    #[test]
+    fn sky_directions() {
+        assert!(close(sky_direction(0.0, 0.0), vec3(0.0, 0.0, -1.0)));
+        assert!(close(sky_direction(90.0, 0.0), vec3(1.0, 0.0, 0.0)));
+        assert!(close(sky_direction(123.0, 90.0), vec3(0.0, 1.0, 0.0)));
+        assert!(close(sky_direction(0.0, -90.0), vec3(0.0, -1.0, 0.0)));
+    }
+
+    #[test]
+    fn great_circles() {
+        let start = vec3(1.0, 2.0, -0.5);
+        let toward = vec3(0.3, -1.0, 2.0);
+        for &segments in &[3, 4, 64, CIRCLE_SEGMENTS] {
+            let points = great_circle(start, toward, segments);
+            assert_eq!(points.len(), segments + 1);
+            assert!(close(points[0], start.normalize()));
+            assert!(close(points[segments], points[0]));
+
+            let normal = start.cross(toward).normalize();
+            for pair in points.windows(2) {
+                assert!((pair[0].magnitude() - 1.0).abs() < 1e-9);
+                assert!(pair[0].dot(normal).abs() < 1e-9);
+                // Evenly spaced, a turn in all
+                let step = pair[0].dot(pair[1]).clamp(-1.0, 1.0).acos();
+                assert!((step - TAU / segments as f64).abs() < 1e-6);
+            }
+            // Headed toward `toward` first
+            assert!(points[1].dot(toward) > points[0].dot(toward));
+        }
+    }
+
+    #[test]
+    fn parallels() {
+        for &declination in &[-60.0, 0.0, 23.44, 89.0] {
+            let points = parallel(declination, CIRCLE_SEGMENTS);
+            assert_eq!(points.len(), CIRCLE_SEGMENTS + 1);
+            assert!(close(points[0], sky_direction(0.0, declination)));
+            assert!(close(points[CIRCLE_SEGMENTS], points[0]));
+
+            let (height, radius) = (
+                declination.to_radians().sin(),
+                declination.to_radians().cos(),
+            );
+            for point in &points {
+                assert!((point.magnitude() - 1.0).abs() < 1e-9);
+                assert!((point.y - height).abs() < 1e-9);
+                assert!(((point.x * point.x + point.z * point.z).sqrt() - radius).abs() < 1e-9);
+            }
+        }
+        // The equator is the great circle round the pole
+        let equator = parallel(0.0, 8);
+        let circle = great_circle(vec3(0.0, 0.0, -1.0), vec3(1.0, 0.0, 0.0), 8);
+        for (a, b) in equator.iter().zip(&circle) {
+            assert!(close(*a, *b));
+        }
+    }
+
+    #[test]
+    fn solstice_noon_is_the_analytic_altitude() {
+        for preset in PRESETS.iter().filter(|preset| preset.axial_tilt > 20.0) {
+            let tilt = preset.axial_tilt;
+            // Northern summer solstice, with the day starting away from noon
+            let sun = SunPlace {
+                angle: 37.0,
+                season: 90.0,
+            };
+            assert!((sun.declination(preset) - tilt).abs() < 1e-9);
+
+            let path = sun_path(preset, sun);
+            let step = 360.0 / SUN_PATH_SAMPLES as f64;
+            for (i, &latitude) in [0.0, 23.44, 45.0, -33.9, 70.0, -80.0].iter().enumerate() {
+                // On the meridian of a sample, so that noon itself is on
+                // the path. Elsewhere the highest sample can be up to half
+                // a step below noon near the zenith
+                let meridian = sun.angle + step * (37 * i + 5) as f64;
+                let up = sky_direction(meridian, latitude);
+                let noon = highest_altitude(&path, up).unwrap();
+                let expected = 90.0 - (latitude - tilt).abs();
+                assert!(
+                    (noon - expected).abs() < 0.01,
+                    "{} at {}: {} rather than {}",
+                    preset.name,
+                    latitude,
+                    noon,
+                    expected
+                );
+                assert!((orbit::noon_altitude(latitude, tilt) - expected).abs() < 1e-12);
+            }
+        }
+    }
+}
@@ -0,0 +1,392 @@
+use crate::cubesphere::{face_tangent, spherify, FACES};
+use crate::grid::PlanetGrid;
+use crate::math;
+use crate::mesh::{AnyIndexBuffer, SphereMesh};
+use crate::noise;
+use crate::Vertex;
+use cgmath::{vec3, InnerSpace, Vector3, Vector4};
+use glium::{backend::Facade, VertexBuffer};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::error;
+use std::f32::consts::PI;
+
+// Chunks split no further than this many times below the cube's faces
+pub const MAX_DEPTH: u32 = 5;
+
+// Quads along each side of a chunk, whatever its depth
+const CHUNK_RESOLUTION: u32 = 16;
+
+// A chunk splits when the camera is closer to its center than this many
+// times its edge
+const SPLIT_DISTANCE: f32 = 2.0;
+
+// How far the skirts hang below a chunk's edges, as a fraction of its edge.
+// Enough to cover the gap to a neighbour a level coarser or finer, and the
+// terrain displacement differing along it.
+const SKIRT_DEPTH: f32 = 0.25;
+
+/// A square of one of the cube's faces, `depth` splits down, at `(x, y)` in
+/// the grid of 2^depth by 2^depth chunks at that depth.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct ChunkKey {
+    pub face: usize,
+    pub depth: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl ChunkKey {
+    fn root(face: usize) -> ChunkKey {
+        ChunkKey {
+            face,
+            depth: 0,
+            x: 0,
+            y: 0,
+        }
+    }
+
+    pub fn children(self) -> [ChunkKey; 4] {
+        let child = |dx, dy| ChunkKey {
+            face: self.face,
+            depth: self.depth + 1,
+            x: self.x * 2 + dx,
+            y: self.y * 2 + dy,
+        };
+        [child(0, 0), child(1, 0), child(0, 1), child(1, 1)]
+    }
+
+    /// The chunk's side as a fraction of the face's.
+    fn size(self) -> f32 {
+        1.0 / (1 << self.depth) as f32
+    }
+
+    /// The point on the unit sphere at `(u, v)` from 0 to 1 across the
+    /// chunk.
+    fn point(self, u: f32, v: f32) -> Vector3<f32> {
+        let [normal, u_axis, v_axis] = FACES[self.face];
+        let axis = |a: [i32; 3]| vec3(a[0] as f32, a[1] as f32, a[2] as f32);
+        let (s, t) = self.face_uv(u, v);
+        spherify(axis(normal) + axis(u_axis) * (2.0 * s - 1.0) + axis(v_axis) * (2.0 * t - 1.0))
+    }
+
+    /// Chunk coordinates to coordinates across the whole face.
+    fn face_uv(self, u: f32, v: f32) -> (f32, f32) {
+        let size = self.size();
+        ((self.x as f32 + u) * size, (self.y as f32 + v) * size)
+    }
+
+    /// About how long the chunk's sides are on the unit sphere.
+    pub fn edge(self) -> f32 {
+        PI / 2.0 * self.size()
+    }
+
+    /// Whether the camera, in planet radii from the center, is close enough
+    /// for the chunk's children to be drawn instead.
+    pub fn should_split(self, camera: Vector3<f32>) -> bool {
+        self.depth < MAX_DEPTH
+            && (camera - self.point(0.5, 0.5)).magnitude() < SPLIT_DISTANCE * self.edge()
+    }
+}
+
+/// The chunks to draw for a camera at `camera`, in planet radii from the
+/// center. Together they cover the sphere once.
+pub fn select_leaves(camera: Vector3<f32>) -> Vec<ChunkKey> {
+    let mut leaves = Vec::new();
+    let mut open: Vec<ChunkKey> = (0..FACES.len()).map(ChunkKey::root).collect();
+    while let Some(key) = open.pop() {
+        if key.should_split(camera) {
+            open.extend_from_slice(&key.children());
+        } else {
+            leaves.push(key);
+        }
+    }
+    leaves
+}
+
+/// The mesh of one chunk on a sphere of `radius`, with `tex` and tangents
+/// as on the cube sphere. Its edges have skirts that hang down towards the
+/// center, so the cracks between chunks of different depths show the skirt
+/// rather than the sky.
+pub fn create_chunk(key: ChunkKey, radius: f32) -> SphereMesh {
+    let n = CHUNK_RESOLUTION;
+    let u_axis = FACES[key.face][1];
+    let vertex = |i: u32, j: u32, sink: f32| {
+        let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+        let point = key.point(u, v);
+        let (s, t) = key.face_uv(u, v);
+        Vertex {
+            pos: (point * radius * (1.0 - sink)).into(),
+            normal: point.into(),
+            tex: [key.face as f32 * 2.0 + s, t],
+            tangent: face_tangent(point, u_axis),
+            cell: 0,
+        }
+    };
+
+    let mut vertices = Vec::with_capacity(((n + 1) * (n + 1) + 8 * (n + 1)) as usize);
+    let mut indices = Vec::with_capacity((n * n * 6 + 4 * n * 6) as usize);
+    for j in 0..=n {
+        for i in 0..=n {
+            vertices.push(vertex(i, j, 0.0));
+        }
+    }
+    let at = |i: u32, j: u32| j * (n + 1) + i;
+    for j in 0..n {
+        for i in 0..n {
+            indices.extend_from_slice(&[at(i, j), at(i + 1, j), at(i + 1, j + 1)]);
+            indices.extend_from_slice(&[at(i, j), at(i + 1, j + 1), at(i, j + 1)]);
+        }
+    }
+
+    // Round the edge counter-clockwise from outside, so the chunk is on
+    // the left and the skirt faces away from it
+    let edges: [Vec<(u32, u32)>; 4] = [
+        (0..=n).map(|i| (i, 0)).collect(),
+        (0..=n).map(|j| (n, j)).collect(),
+        (0..=n).rev().map(|i| (i, n)).collect(),
+        (0..=n).rev().map(|j| (0, j)).collect(),
+    ];
+    let sink = SKIRT_DEPTH * key.edge();
+    for edge in &edges {
+        let base = vertices.len() as u32;
+        for &(i, j) in edge {
+            vertices.push(vertex(i, j, sink));
+        }
+        for k in 0..n {
+            let ((ai, aj), (bi, bj)) = (edge[k as usize], edge[k as usize + 1]);
+            let (a, b) = (at(ai, aj), at(bi, bj));
+            let (a_low, b_low) = (base + k, base + k + 1);
+            indices.extend_from_slice(&[a, a_low, b_low]);
+            indices.extend_from_slice(&[a, b_low, b]);
+        }
+    }
+
+    SphereMesh { vertices, indices }
+}
+
+pub struct ChunkMesh {
+    pub vertex_buffer: VertexBuffer<Vertex>,
+    pub index_buffer: AnyIndexBuffer,
+    // A sphere around the chunk with the terrain raised as high as it goes
+    pub center: Vector3<f32>,
+    pub bound: f32,
+}
+
+/// The planet as a quadtree of chunks on a cube sphere, split finer where
+/// the camera is close. Chunks are built as they are first needed and
+/// dropped once they are no longer drawn.
+pub struct ChunkedPlanet {
+    pub enabled: bool,
+    radius: f32,
+    chunks: HashMap<ChunkKey, ChunkMesh>,
+    leaves: Vec<ChunkKey>,
+    // Chunks built by the last update
+    pub built: usize,
+    // Skip the chunks outside the view
+    pub culling: bool,
+    // Chunks the last frame drew
+    pub drawn: Cell<usize>,
+}
+
+impl ChunkedPlanet {
+    pub fn new(radius: f32) -> ChunkedPlanet {
+        ChunkedPlanet {
+            enabled: false,
+            radius,
+            chunks: HashMap::new(),
+            leaves: Vec::new(),
+            built: 0,
+            culling: true,
+            drawn: Cell::new(0),
+        }
+    }
+
+    /// Picks the chunks for a camera at `camera`, in planet radii from the
+    /// center, and builds the ones missing with their vertices in `grid`'s
+    /// cells.
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        camera: Vector3<f32>,
+        grid: &PlanetGrid,
+    ) -> Result<(), Box<dyn error::Error>> {
+        self.built = 0;
+        if !self.enabled {
+            self.chunks.clear();
+            self.leaves.clear();
+            return Ok(());
+        }
+
+        self.leaves = select_leaves(camera);
+        for &key in &self.leaves {
+            if !self.chunks.contains_key(&key) {
+                let mut mesh = create_chunk(key, self.radius);
+                grid.assign(&mut mesh.vertices);
+                let center = key.point(0.5, 0.5) * self.radius;
+                let bound = mesh
+                    .vertices
+                    .iter()
+                    .map(|vertex| (Vector3::from(vertex.pos) - center).magnitude())
+                    .fold(0.0, f32::max);
+                self.chunks.insert(
+                    key,
+                    ChunkMesh {
+                        vertex_buffer: VertexBuffer::new(facade, &mesh.vertices)?,
+                        index_buffer: AnyIndexBuffer::new(
+                            facade,
+                            mesh.vertices.len(),
+                            &mesh.indices,
+                        )?,
+                        center,
+                        bound: bound + noise::FBM_MAX,
+                    },
+                );
+                self.built += 1;
+            }
+        }
+        let leaves = &self.leaves;
+        self.chunks.retain(|key, _| leaves.contains(key));
+        Ok(())
+    }
+
+    /// Whether the planet is drawn from chunks rather than as one mesh.
+    pub fn is_drawing(&self) -> bool {
+        self.enabled && !self.leaves.is_empty()
+    }
+
+    pub fn leaves(&self) -> impl Iterator<Item = &ChunkMesh> {
+        self.leaves
+            .iter()
+            .filter_map(move |key| self.chunks.get(key))
+    }
+
+    /// The leaves to draw, leaving out those wholly outside `frustum`, in
+    /// the planet's space.
+    pub fn visible<'a>(
+        &'a self,
+        frustum: Option<&'a [Vector4<f32>; 6]>,
+    ) -> impl Iterator<Item = &'a ChunkMesh> {
+        self.leaves().filter(move |chunk| {
+            frustum.is_none_or(|planes| {
+                !math::sphere_outside_frustum(planes, chunk.center, chunk.bound)
+            })
+        })
+    }
+
+    pub fn leaf_count(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn deepest(&self) -> u32 {
+        self.leaves.iter().map(|key| key.depth).max().unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn parent(key: ChunkKey) -> Option<ChunkKey> {
+        if key.depth == 0 {
+            return None;
+        }
+        Some(ChunkKey {
+            face: key.face,
+            depth: key.depth - 1,
+            x: key.x / 2,
+            y: key.y / 2,
+        })
+    }
+
+    // Whether `key` or one of its ancestors is in `leaves`
+    fn under(key: ChunkKey, leaves: &HashSet<ChunkKey>) -> bool {
+        let mut key = Some(key);
+        while let Some(k) = key {
+            if leaves.contains(&k) {
+                return true;
+            }
+            key = parent(k);
+        }
+        false
+    }
+
+    fn nearest(leaves: &[ChunkKey], direction: Vector3<f32>) -> ChunkKey {
+        let distance = |key: &ChunkKey| (key.point(0.5, 0.5) - direction.normalize()).magnitude();
+        *leaves
+            .iter()
+            .min_by(|a, b| distance(a).partial_cmp(&distance(b)).unwrap())
+            .unwrap()
+    }
+
+    fn assert_covers_once(leaves: &[ChunkKey]) {
+        let set: HashSet<ChunkKey> = leaves.iter().cloned().collect();
+        assert_eq!(set.len(), leaves.len(), "a leaf is selected twice");
+        for &leaf in leaves {
+            let overlaps = parent(leaf).is_some_and(|p| under(p, &set));
+            assert!(!overlaps, "{:?} is under another leaf", leaf);
+        }
+        for face in 0..FACES.len() {
+            let area: f32 = leaves
+                .iter()
+                .filter(|key| key.face == face)
+                .map(|key| key.size() * key.size())
+                .sum();
+            assert!(
+                (area - 1.0).abs() < 1e-6,
+                "face {} covered {} times",
+                face,
+                area
+            );
+        }
+    }
+
+    #[test]
+    fn far_camera_draws_the_faces() {
+        let leaves = select_leaves(vec3(0.0, 0.0, 100.0));
+        assert_eq!(leaves.len(), FACES.len());
+        assert!(leaves.iter().all(|key| key.depth == 0));
+        assert_covers_once(&leaves);
+    }
+
+    #[test]
+    fn near_camera_splits_to_the_deepest_below_it() {
+        let camera = vec3(0.3, 0.2, 1.0).normalize() * 1.01;
+        let leaves = select_leaves(camera);
+        assert_covers_once(&leaves);
+        assert_eq!(nearest(&leaves, camera).depth, MAX_DEPTH);
+        assert!(nearest(&leaves, -camera).depth <= 1);
+    }
+
+    #[test]
+    fn no_split_below_the_max_depth() {
+        let key = ChunkKey {
+            face: 0,
+            depth: MAX_DEPTH,
+            x: 3,
+            y: 7,
+        };
+        assert!(!key.should_split(key.point(0.5, 0.5)));
+        let above = parent(key).unwrap();
+        assert!(above.should_split(above.point(0.5, 0.5)));
+    }
+
+    #[test]
+    fn moving_away_merges() {
+        let direction = vec3(-0.4, 0.8, 0.1).normalize();
+        let mut near: HashSet<ChunkKey> = select_leaves(direction * 1.001).into_iter().collect();
+        for step in 1..40 {
+            let leaves = select_leaves(direction * (1.001 + step as f32 * 0.1));
+            assert_covers_once(&leaves);
+            let far: HashSet<ChunkKey> = leaves.into_iter().collect();
+            // Every chunk drawn from nearer is the same or a split of one
+            // drawn from further away
+            for &key in &near {
+                assert!(under(key, &far), "{:?} split moving away", key);
+            }
+            near = far;
+        }
+        assert_eq!(near.len(), FACES.len());
+    }
+}
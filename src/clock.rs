@@ -0,0 +1,44 @@
+/// The clock the clouds and everything else that moves on its own run on.
+/// It runs `scale` times as fast as real time and can be paused, the camera
+/// and the UI keep going on the frame time either way.
+pub struct Clock {
+    // Seconds of sim time since the start
+    pub time: f32,
+    pub scale: f32,
+    pub paused: bool,
+    // Let one frame through while paused
+    step_once: bool,
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock {
+            time: 0.0,
+            scale: 1.0,
+            paused: false,
+            step_once: false,
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    /// Pauses and moves on by a single frame.
+    pub fn step_frame(&mut self) {
+        self.paused = true;
+        self.step_once = true;
+    }
+
+    /// Moves on by a frame of `dt` real seconds, returning the sim seconds
+    /// that passed.
+    pub fn advance(&mut self, dt: f32) -> f32 {
+        let step_once = std::mem::replace(&mut self.step_once, false);
+        if self.paused && !step_once {
+            return 0.0;
+        }
+        let step = dt * self.scale.max(0.0);
+        self.time += step;
+        step
+    }
+}
@@ -0,0 +1,144 @@
+use glium::{
+    backend::{Context, Facade},
+    framebuffer::SimpleFrameBuffer,
+    texture::{DepthFormat, DepthTexture2d, MipmapsOption, Texture2d, UncompressedFloatFormat},
+};
+use std::error;
+use std::rc::Rc;
+
+/// How large the cloud layer is drawn, against the window.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum CloudResolution {
+    Full,
+    Half,
+    Quarter,
+}
+
+impl CloudResolution {
+    pub const ALL: [CloudResolution; 3] = [
+        CloudResolution::Full,
+        CloudResolution::Half,
+        CloudResolution::Quarter,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            CloudResolution::Full => "Full",
+            CloudResolution::Half => "Half",
+            CloudResolution::Quarter => "Quarter",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<CloudResolution, Box<dyn error::Error>> {
+        CloudResolution::ALL
+            .iter()
+            .find(|resolution| resolution.name().eq_ignore_ascii_case(value))
+            .copied()
+            .ok_or_else(|| format!("expected full, half or quarter but got '{}'", value).into())
+    }
+
+    /// Window pixels along each axis for every pixel of the clouds.
+    pub fn divisor(self) -> u32 {
+        match self {
+            CloudResolution::Full => 1,
+            CloudResolution::Half => 2,
+            CloudResolution::Quarter => 4,
+        }
+    }
+}
+
+/// What the scene renders into when the clouds are drawn smaller than the
+/// window. The scene's depth has to be a texture for the upsample to look
+/// at, so it can't go straight to the window, and is copied there after.
+/// The clouds get a target of their own at the reduced size, with the
+/// scene's depth copied down into it to hide them behind the planet. The
+/// depth the cloud shell leaves there is merged back into the scene's, so
+/// what's drawn after is hidden behind the clouds as at full size.
+pub struct CloudTarget {
+    context: Rc<Context>,
+    resolution: CloudResolution,
+    pub scene_color: Texture2d,
+    pub scene_depth: DepthTexture2d,
+    // Premultiplied by their coverage, to go over the scene in one blend
+    pub cloud_color: Texture2d,
+    // The scene depth each cloud pixel was tested against, as a color so
+    // the upsample can read it while the clouds write their own depth
+    pub cloud_depth: Texture2d,
+    // The clouds' own depth buffer, the scene depth with the shell's over it
+    pub shell_depth: DepthTexture2d,
+}
+
+impl CloudTarget {
+    pub fn new<F: Facade>(
+        facade: &F,
+        (width, height): (u32, u32),
+        resolution: CloudResolution,
+    ) -> Result<CloudTarget, Box<dyn error::Error>> {
+        let (width, height) = (width.max(1), height.max(1));
+        let divisor = resolution.divisor();
+        let (low_width, low_height) = (width.div_ceil(divisor), height.div_ceil(divisor));
+
+        let texture = |format, width, height| {
+            Texture2d::empty_with_format(facade, format, MipmapsOption::NoMipmap, width, height)
+        };
+        let depth_texture = |width, height| {
+            DepthTexture2d::empty_with_format(
+                facade,
+                DepthFormat::F32,
+                MipmapsOption::NoMipmap,
+                width,
+                height,
+            )
+        };
+        Ok(CloudTarget {
+            context: facade.get_context().clone(),
+            resolution,
+            scene_color: texture(UncompressedFloatFormat::U8U8U8U8, width, height)?,
+            scene_depth: depth_texture(width, height)?,
+            cloud_color: texture(UncompressedFloatFormat::F16F16F16F16, low_width, low_height)?,
+            cloud_depth: texture(UncompressedFloatFormat::F32, low_width, low_height)?,
+            shell_depth: depth_texture(low_width, low_height)?,
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.scene_color.dimensions()
+    }
+
+    pub fn resolution(&self) -> CloudResolution {
+        self.resolution
+    }
+
+    /// Where the scene is drawn, in place of the window.
+    pub fn scene_framebuffer(&self) -> Result<SimpleFrameBuffer<'_>, Box<dyn error::Error>> {
+        Ok(SimpleFrameBuffer::with_depth_buffer(
+            &self.context,
+            &self.scene_color,
+            &self.scene_depth,
+        )?)
+    }
+
+    /// Where the scene depth is copied down to.
+    pub fn depth_framebuffer(&self) -> Result<SimpleFrameBuffer<'_>, Box<dyn error::Error>> {
+        Ok(SimpleFrameBuffer::with_depth_buffer(
+            &self.context,
+            &self.cloud_depth,
+            &self.shell_depth,
+        )?)
+    }
+
+    /// Where the clouds are drawn, over the depth copied down.
+    pub fn cloud_framebuffer(&self) -> Result<SimpleFrameBuffer<'_>, Box<dyn error::Error>> {
+        Ok(SimpleFrameBuffer::with_depth_buffer(
+            &self.context,
+            &self.cloud_color,
+            &self.shell_depth,
+        )?)
+    }
+
+    /// The scene's color without its depth, for the upsample to draw into
+    /// while it reads the depth.
+    pub fn composite_framebuffer(&self) -> Result<SimpleFrameBuffer<'_>, Box<dyn error::Error>> {
+        Ok(SimpleFrameBuffer::new(&self.context, &self.scene_color)?)
+    }
+}
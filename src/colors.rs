@@ -0,0 +1,348 @@
+use std::error;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+const PALETTE_DIR: &str = "palettes";
+
+/// Named color stops every palette file has to define, in the order they
+/// are written back.
+pub const STOPS: [&str; 8] = [
+    "ocean_deep",
+    "ocean_shallow",
+    "beach",
+    "grass",
+    "rock",
+    "snow",
+    "night_lights",
+    "atmosphere",
+];
+
+pub const OCEAN_DEEP: usize = 0;
+pub const OCEAN_SHALLOW: usize = 1;
+pub const BEACH: usize = 2;
+pub const GRASS: usize = 3;
+pub const ROCK: usize = 4;
+pub const SNOW: usize = 5;
+pub const NIGHT_LIGHTS: usize = 6;
+pub const ATMOSPHERE: usize = 7;
+
+/// The colors of the procedural planet, loaded from
+/// `palettes/<name>.toml` and reloaded when the file changes.
+pub struct ColorPalette {
+    pub name: String,
+    pub title: String,
+    pub colors: [[f32; 3]; 8],
+    modified: Option<SystemTime>,
+}
+
+impl ColorPalette {
+    pub fn load(name: &str) -> Result<ColorPalette, Box<dyn error::Error>> {
+        let path = palette_path(name);
+        let text = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let (title, colors) =
+            parse_palette(&text).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+        Ok(ColorPalette {
+            name: name.to_string(),
+            title,
+            colors,
+            modified: fs::metadata(&path).and_then(|m| m.modified()).ok(),
+        })
+    }
+
+    /// Names of the palette files there are to choose from, sorted.
+    pub fn available() -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(PALETTE_DIR)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+                    .filter_map(|path| {
+                        path.file_stem()
+                            .map(|stem| stem.to_string_lossy().into_owned())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        names.sort();
+        names
+    }
+
+    /// Rereads the file if it was modified since it was loaded. A file that
+    /// fails to parse is reported and the current colors are kept.
+    pub fn reload_if_changed(&mut self) {
+        let modified = fs::metadata(palette_path(&self.name))
+            .and_then(|m| m.modified())
+            .ok();
+        if modified.is_none() || modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+
+        match ColorPalette::load(&self.name) {
+            Ok(palette) => *self = palette,
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    pub fn save(&mut self) -> Result<(), Box<dyn error::Error>> {
+        let path = palette_path(&self.name);
+        let mut file = fs::File::create(&path)?;
+        writeln!(file, "name = \"{}\"", self.title)?;
+        writeln!(file)?;
+        for (stop, color) in STOPS.iter().zip(&self.colors) {
+            writeln!(file, "{} = {}", stop, format_hex(*color))?;
+        }
+        drop(file);
+
+        // Don't pick our own write up as an outside change
+        self.modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        Ok(())
+    }
+}
+
+fn palette_path(name: &str) -> PathBuf {
+    PathBuf::from(PALETTE_DIR).join(format!("{}.toml", name))
+}
+
+fn format_hex(color: [f32; 3]) -> String {
+    let byte = |c: f32| (c.clamp(0.0, 1.0) * 255.0).round() as u8;
+    format!(
+        "\"#{:02x}{:02x}{:02x}\"",
+        byte(color[0]),
+        byte(color[1]),
+        byte(color[2])
+    )
+}
+
+/// Strips a `#` comment, leaving a `#` inside quotes alone.
+pub fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/// Either `"#rrggbb"` or `[r, g, b]` with components from 0 to 1.
+fn parse_color(value: &str) -> Result<[f32; 3], String> {
+    if let Some(hex) = value
+        .strip_prefix("\"#")
+        .and_then(|rest| rest.strip_suffix('"'))
+    {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("'{}' is not a color like \"#e0c89e\"", value));
+        }
+        let component =
+            |i: usize| f32::from(u8::from_str_radix(&hex[i..i + 2], 16).unwrap()) / 255.0;
+        return Ok([component(0), component(2), component(4)]);
+    }
+
+    if let Some(list) = value
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+    {
+        let components = list
+            .split(',')
+            .map(|c| c.trim().parse::<f32>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("'{}' has a component that isn't a number", value))?;
+        if components.len() != 3 {
+            return Err(format!("'{}' needs exactly three components", value));
+        }
+        if components.iter().any(|&c| !(0.0..=1.0).contains(&c)) {
+            return Err(format!("'{}' has a component outside 0 to 1", value));
+        }
+        return Ok([components[0], components[1], components[2]]);
+    }
+
+    Err(format!(
+        "'{}' is not a color, use \"#rrggbb\" or [r, g, b]",
+        value
+    ))
+}
+
+/// Parses the small TOML subset the palette files use: `key = value` lines
+/// with `#` comments.
+fn parse_palette(text: &str) -> Result<(String, [[f32; 3]; 8]), String> {
+    let mut title = None;
+    let mut colors: [Option<[f32; 3]>; 8] = [None; 8];
+
+    for (number, line) in text.lines().enumerate() {
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+
+        let mut parts = line.splitn(2, '=');
+        let (key, value) = match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => return Err(error(format!("expected 'key = value' but got '{}'", line))),
+        };
+
+        if key == "name" {
+            let name = value
+                .strip_prefix('"')
+                .and_then(|rest| rest.strip_suffix('"'))
+                .ok_or_else(|| error("name has to be a quoted string".to_string()))?;
+            title = Some(name.to_string());
+            continue;
+        }
+
+        let index = STOPS.iter().position(|&stop| stop == key).ok_or_else(|| {
+            error(format!(
+                "unknown color '{}', expected one of {}",
+                key,
+                STOPS.join(", ")
+            ))
+        })?;
+        if colors[index].is_some() {
+            return Err(error(format!("'{}' is defined twice", key)));
+        }
+        colors[index] = Some(parse_color(value).map_err(|e| error(format!("{}: {}", key, e)))?);
+    }
+
+    let missing: Vec<&str> = STOPS
+        .iter()
+        .zip(&colors)
+        .filter(|(_, color)| color.is_none())
+        .map(|(&stop, _)| stop)
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("missing {}", missing.join(", ")));
+    }
+
+    let mut result = [[0.0; 3]; 8];
+    for (result, color) in result.iter_mut().zip(&colors) {
+        *result = color.unwrap();
+    }
+    Ok((title.unwrap_or_default(), result))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Every stop, in order, as a hex color
+    fn palette(name: &str) -> String {
+        let mut text = format!("name = \"{}\"\n", name);
+        for (i, stop) in STOPS.iter().enumerate() {
+            text.push_str(&format!("{} = \"#{:02x}0000\"\n", stop, i * 30));
+        }
+        text
+    }
+
+    fn error(text: &str) -> String {
+        parse_palette(text).unwrap_err()
+    }
+
+    #[test]
+    fn parses_every_stop() {
+        let (title, colors) = parse_palette(&palette("Test")).unwrap();
+        assert_eq!(title, "Test");
+        for (i, color) in colors.iter().enumerate() {
+            assert_eq!(*color, [(i * 30) as f32 / 255.0, 0.0, 0.0]);
+        }
+    }
+
+    #[test]
+    fn parses_lists_comments_and_any_order() {
+        let mut lines: Vec<String> = palette("Dusk # not a comment")
+            .lines()
+            .map(String::from)
+            .collect();
+        lines.reverse();
+        lines[0] = "atmosphere = [0.25, 0.5, 1.0] # a comment".to_string();
+        lines.insert(3, "# a line of comment".to_string());
+        lines.insert(4, String::new());
+        let (title, colors) = parse_palette(&lines.join("\n")).unwrap();
+        assert_eq!(title, "Dusk # not a comment");
+        assert_eq!(colors[ATMOSPHERE], [0.25, 0.5, 1.0]);
+        assert_eq!(colors[OCEAN_DEEP], [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn name_is_optional() {
+        let text = palette("x").replace("name = \"x\"\n", "");
+        assert_eq!(parse_palette(&text).unwrap().0, "");
+    }
+
+    #[test]
+    fn malformed_palettes_name_the_line() {
+        let text = palette("x");
+        assert_eq!(
+            error(&format!("{}grass = \"#000000\"\n", text)),
+            "line 10: 'grass' is defined twice"
+        );
+        assert!(error(&format!("{}lava = \"#ff0000\"\n", text))
+            .starts_with("line 10: unknown color 'lava', expected one of ocean_deep,"));
+        assert_eq!(
+            error(&format!("{}grass\n", text)),
+            "line 10: expected 'key = value' but got 'grass'"
+        );
+        assert_eq!(
+            error(&text.replace("name = \"x\"", "name = x")),
+            "line 1: name has to be a quoted string"
+        );
+        assert_eq!(
+            error(&text.replace("beach = \"#3c0000\"", "beach = \"#3c00\"")),
+            "line 4: beach: '\"#3c00\"' is not a color like \"#e0c89e\""
+        );
+        assert_eq!(
+            error(&text.replace("beach = \"#3c0000\"", "beach = [0.1, 0.2]")),
+            "line 4: beach: '[0.1, 0.2]' needs exactly three components"
+        );
+        assert_eq!(
+            error(&text.replace("beach = \"#3c0000\"", "beach = [0.1, 2, 0.3]")),
+            "line 4: beach: '[0.1, 2, 0.3]' has a component outside 0 to 1"
+        );
+        assert_eq!(
+            error(&text.replace("beach = \"#3c0000\"", "beach = [0.1, red, 0.3]")),
+            "line 4: beach: '[0.1, red, 0.3]' has a component that isn't a number"
+        );
+        assert_eq!(
+            error(&text.replace("beach = \"#3c0000\"", "beach = red")),
+            "line 4: beach: 'red' is not a color, use \"#rrggbb\" or [r, g, b]"
+        );
+    }
+
+    #[test]
+    fn missing_stops_are_listed() {
+        let text: String = palette("x")
+            .lines()
+            .filter(|line| !line.starts_with("rock") && !line.starts_with("snow"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        assert_eq!(error(&text), "missing rock, snow");
+    }
+
+    #[test]
+    fn hex_colors_round_trip() {
+        for byte in 0..=255u8 {
+            let value = f32::from(byte) / 255.0;
+            let color = [value, 1.0 - value, 0.5];
+            let parsed = parse_color(&format_hex(color)).unwrap();
+            assert_eq!(format_hex(parsed), format_hex(color));
+            assert_eq!(parsed[0], value);
+        }
+    }
+
+    #[test]
+    fn shipped_palettes_parse() {
+        let names = ColorPalette::available();
+        assert!(!names.is_empty());
+        for name in names {
+            let text = fs::read_to_string(palette_path(&name)).unwrap();
+            if let Err(e) = parse_palette(&text) {
+                panic!("{}: {}", name, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use cgmath::{InnerSpace, Vector3};
+use rand::distributions::{Distribution, UnitSphereSurface};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Height of the rim above the surrounding ground, as a fraction of the bowl's
+// depth
+const RIM_HEIGHT: f32 = 0.25;
+
+// How far the rim slopes down outside the bowl, in crater radii
+const RIM_WIDTH: f32 = 0.5;
+
+/// Craters to stamp into the planet mesh. Sizes are angular radii in
+/// radians, and the depth is the bowl's depth over its radius.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Craters {
+    pub count: i32,
+    pub min_size: f32,
+    pub max_size: f32,
+    pub depth: f32,
+}
+
+impl Craters {
+    pub fn new() -> Craters {
+        Craters {
+            count: 0,
+            min_size: 0.02,
+            max_size: 0.2,
+            depth: 0.2,
+        }
+    }
+}
+
+/// The height of a crater at `r` crater radii from its center, in units of
+/// its depth: -1 at the center, rising to `RIM_HEIGHT` at the rim and back
+/// down to 0 at `1 + RIM_WIDTH`.
+pub fn crater_profile(r: f32) -> f32 {
+    if r < 1.0 {
+        (1.0 + RIM_HEIGHT) * r * r - 1.0
+    } else if r < 1.0 + RIM_WIDTH {
+        let t = (r - 1.0) / RIM_WIDTH;
+        RIM_HEIGHT * (1.0 - t) * (1.0 - t)
+    } else {
+        0.0
+    }
+}
+
+struct Crater {
+    center: Vector3<f32>,
+    size: f32,
+}
+
+/// Crater centers scattered over the unit sphere, sorted along z so a lookup
+/// only has to look at the band of them that could reach.
+pub struct CraterField {
+    craters: Vec<Crater>,
+    depth: f32,
+    // Furthest any crater reaches from its center, in radians
+    reach: f32,
+}
+
+impl CraterField {
+    pub fn new(settings: &Craters, seed: i32) -> CraterField {
+        let mut rng = StdRng::seed_from_u64(seed as u64);
+        let sphere = UnitSphereSurface::new();
+        let (min_size, max_size) = (
+            settings.min_size.min(settings.max_size),
+            settings.max_size.max(settings.min_size),
+        );
+
+        let mut craters: Vec<Crater> = (0..settings.count.max(0))
+            .map(|_| {
+                let v = sphere.sample(&mut rng);
+                // Many small craters for each large one
+                let t: f32 = rng.gen();
+                Crater {
+                    center: Vector3::new(v[0] as f32, v[1] as f32, v[2] as f32),
+                    size: min_size + (max_size - min_size) * t * t * t,
+                }
+            })
+            .collect();
+        craters.sort_by(|a, b| a.center.z.total_cmp(&b.center.z));
+        let reach = craters
+            .iter()
+            .map(|crater| crater.size * (1.0 + RIM_WIDTH))
+            .fold(0.0, f32::max);
+
+        CraterField {
+            craters,
+            depth: settings.depth,
+            reach,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.craters.is_empty()
+    }
+
+    /// The height of the craters at `direction` on the unit sphere, in the
+    /// sphere's radii. Where craters overlap the deepest bowl and the highest
+    /// rim are taken, rather than adding them all up.
+    pub fn height(&self, direction: Vector3<f32>) -> f32 {
+        // The straight line between two points is shorter than the arc
+        let low = direction.z - self.reach;
+        let start = self.craters.partition_point(|crater| crater.center.z < low);
+        let (mut bowl, mut rim) = (0.0f32, 0.0f32);
+        for crater in &self.craters[start..] {
+            if crater.center.z > direction.z + self.reach {
+                break;
+            }
+            let angle = crater.center.dot(direction).clamp(-1.0, 1.0).acos();
+            if angle >= crater.size * (1.0 + RIM_WIDTH) {
+                continue;
+            }
+            let height = crater_profile(angle / crater.size) * crater.size * self.depth;
+            bowl = bowl.min(height);
+            rim = rim.max(height);
+        }
+        bowl + rim
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::vec3;
+
+    const STEPS: usize = 1000;
+
+    // The profile sampled evenly from the center out past the rim
+    fn samples(end: f32) -> Vec<(f32, f32)> {
+        (0..=STEPS)
+            .map(|i| {
+                let r = end * i as f32 / STEPS as f32;
+                (r, crater_profile(r))
+            })
+            .collect()
+    }
+
+    // A field built by hand. `CraterField::new` draws from rand_core 0.3's
+    // block RNG, which trips the misaligned pointer check in debug builds.
+    fn field(craters: &[(Vector3<f32>, f32)], depth: f32) -> CraterField {
+        let mut craters: Vec<Crater> = craters
+            .iter()
+            .map(|&(center, size)| Crater {
+                center: center.normalize(),
+                size,
+            })
+            .collect();
+        craters.sort_by(|a, b| a.center.z.total_cmp(&b.center.z));
+        let reach = craters
+            .iter()
+            .map(|crater| crater.size * (1.0 + RIM_WIDTH))
+            .fold(0.0, f32::max);
+        CraterField {
+            craters,
+            depth,
+            reach,
+        }
+    }
+
+    #[test]
+    fn profile_heights() {
+        assert_eq!(crater_profile(0.0), -1.0);
+        assert_eq!(crater_profile(1.0), RIM_HEIGHT);
+        assert_eq!(crater_profile(1.0 + RIM_WIDTH), 0.0);
+        assert_eq!(crater_profile(5.0), 0.0);
+    }
+
+    #[test]
+    fn profile_rises_to_the_rim_then_falls() {
+        for pair in samples(2.0 * (1.0 + RIM_WIDTH)).windows(2) {
+            let ((r, a), (_, b)) = (pair[0], pair[1]);
+            if r < 1.0 {
+                assert!(b > a, "falls at {}", r);
+            } else {
+                assert!(b <= a, "rises at {}", r);
+            }
+        }
+    }
+
+    #[test]
+    fn profile_is_continuous() {
+        let end = 2.0 * (1.0 + RIM_WIDTH);
+        let step = end / STEPS as f32;
+        // Steepest in the bowl, at the rim
+        let steepest = 2.0 * (1.0 + RIM_HEIGHT);
+        for pair in samples(end).windows(2) {
+            assert!((pair[1].1 - pair[0].1).abs() <= steepest * step * 1.001);
+        }
+        for &edge in &[1.0, 1.0 + RIM_WIDTH] {
+            let jump = crater_profile(edge + 1e-4) - crater_profile(edge - 1e-4);
+            assert!(jump.abs() < 1e-3, "jumps {} at {}", jump, edge);
+        }
+    }
+
+    #[test]
+    fn field_heights() {
+        let (size, depth) = (0.1, 0.2);
+        let craters = field(&[(vec3(0.0, 0.0, 1.0), size)], depth);
+        let at = |angle: f32| craters.height(vec3(angle.sin(), 0.0, angle.cos()));
+        assert!((at(0.0) + size * depth).abs() < 1e-6);
+        assert!((at(size) - RIM_HEIGHT * size * depth).abs() < 1e-4);
+        assert_eq!(at(size * (1.0 + RIM_WIDTH) * 1.01), 0.0);
+        assert_eq!(at(1.0), 0.0);
+        assert!(field(&[], depth).is_empty());
+    }
+
+    #[test]
+    fn overlaps_take_the_deepest_bowl_and_highest_rim() {
+        let (small, large, depth) = (0.05, 0.1, 0.2);
+        let craters = field(
+            &[(vec3(0.0, 0.0, 1.0), large), (vec3(0.0, 0.0, 1.0), small)],
+            depth,
+        );
+        // The large crater's bowl is deeper than the small one's center
+        assert!((craters.height(vec3(0.0, 0.0, 1.0)) + large * depth).abs() < 1e-6);
+
+        // On the small one's rim, inside the large one's bowl, the two add
+        let angle: f32 = small;
+        let direction = vec3(angle.sin(), 0.0, angle.cos());
+        let bowl = crater_profile(angle / large) * large * depth;
+        let rim = RIM_HEIGHT * small * depth;
+        assert!((craters.height(direction) - (bowl + rim)).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::Vertex;
+use cgmath::{vec3, InnerSpace, Vector3};
+// Each face as the axis it looks down and the axes its u and v run along,
+// with u x v pointing out so the triangles wind counter-clockwise from
+// outside
+pub const FACES: [[[i32; 3]; 3]; 6] = [
+    [[1, 0, 0], [0, 1, 0], [0, 0, 1]],
+    [[-1, 0, 0], [0, 0, 1], [0, 1, 0]],
+    [[0, 1, 0], [0, 0, 1], [1, 0, 0]],
+    [[0, -1, 0], [1, 0, 0], [0, 0, 1]],
+    [[0, 0, 1], [1, 0, 0], [0, 1, 0]],
+    [[0, 0, -1], [0, 1, 0], [1, 0, 0]],
+];
+
+/// A point on the cube from -1 to 1 moved onto the unit sphere. Spreads the
+/// grid out more evenly than normalizing would, which bunches it up towards
+/// the middle of each face.
+pub fn spherify(p: Vector3<f32>) -> Vector3<f32> {
+    let (x2, y2, z2) = (p.x * p.x, p.y * p.y, p.z * p.z);
+    vec3(
+        p.x * (1.0 - y2 / 2.0 - z2 / 2.0 + y2 * z2 / 3.0).sqrt(),
+        p.y * (1.0 - z2 / 2.0 - x2 / 2.0 + z2 * x2 / 3.0).sqrt(),
+        p.z * (1.0 - x2 / 2.0 - y2 / 2.0 + x2 * y2 / 3.0).sqrt(),
+    )
+    .normalize()
+}
+
+/// The face's u axis flattened onto the sphere at `point`. The face's axes
+/// are right handed around its normal, so w is always 1.
+pub fn face_tangent(point: Vector3<f32>, u_axis: [i32; 3]) -> [f32; 4] {
+    let axis = vec3(u_axis[0] as f32, u_axis[1] as f32, u_axis[2] as f32);
+    let tangent = (axis - point * point.dot(axis)).normalize();
+    [tangent.x, tangent.y, tangent.z, 1.0]
+}
+
+/// A sphere of `radius` from the six faces of a cube, each a grid of
+/// `face_resolution` by `face_resolution` quads.
+///
+/// Every face has its own vertices along its edges, so that `tex` and the
+/// tangent are the face's own on each side. The copies are made from the
+/// same point on the cube and have bit-identical positions and normals, so
+/// the mesh still has no cracks.
+///
+/// `tex` is the face local position from 0 to 1, with the face index added
+/// to x twice over: the face is `floor(x / 2)` and u is `x - 2 * face`.
+pub fn create_cube_sphere(face_resolution: usize, radius: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let n = face_resolution.max(1) as i32;
+    let mut vertices = Vec::with_capacity(6 * ((n + 1) * (n + 1)) as usize);
+    let mut indices = Vec::with_capacity(6 * (n * n) as usize * 6);
+
+    for (face, &[normal, u_axis, v_axis]) in FACES.iter().enumerate() {
+        let first = vertices.len() as u32;
+        for j in 0..=n {
+            for i in 0..=n {
+                // The place on the cube in steps of half a quad, whole
+                // numbers so every face sharing it gets the same point
+                let mut cube = [0; 3];
+                for axis in 0..3 {
+                    cube[axis] =
+                        normal[axis] * n + u_axis[axis] * (2 * i - n) + v_axis[axis] * (2 * j - n);
+                }
+                let cube = vec3(cube[0] as f32, cube[1] as f32, cube[2] as f32) / n as f32;
+                let point = spherify(cube);
+                let (u, v) = (i as f32 / n as f32, j as f32 / n as f32);
+                vertices.push(Vertex {
+                    pos: (point * radius).into(),
+                    normal: point.into(),
+                    tex: [face as f32 * 2.0 + u, v],
+                    tangent: face_tangent(point, u_axis),
+                    cell: 0,
+                });
+            }
+        }
+
+        let at = |i: i32, j: i32| first + (j * (n + 1) + i) as u32;
+        for j in 0..n {
+            for i in 0..n {
+                indices.extend_from_slice(&[at(i, j), at(i + 1, j), at(i + 1, j + 1)]);
+                indices.extend_from_slice(&[at(i, j), at(i + 1, j + 1), at(i, j + 1)]);
+            }
+        }
+    }
+
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const RESOLUTIONS: [usize; 4] = [1, 2, 7, 32];
+
+    #[test]
+    fn every_edge_is_shared_by_two_triangles() {
+        for &n in RESOLUTIONS.iter() {
+            let (vertices, indices) = create_cube_sphere(n, 2.5);
+            // By position, so the copies along the face edges are one
+            let key = |index: u32| vertices[index as usize].pos.map(f32::to_bits);
+
+            let mut edges: HashMap<_, usize> = HashMap::new();
+            for triangle in indices.chunks(3) {
+                for k in 0..3 {
+                    let (a, b) = (key(triangle[k]), key(triangle[(k + 1) % 3]));
+                    *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+                }
+            }
+
+            // Three to a triangle, each counted from both sides
+            assert_eq!(edges.len(), 6 * n * n * 2 * 3 / 2, "{} quads", n);
+            for (edge, count) in edges {
+                assert_eq!(count, 2, "{} quads, edge {:?}", n, edge);
+            }
+        }
+    }
+
+    #[test]
+    fn face_copies_keep_their_own_uvs_and_tangents() {
+        for &n in RESOLUTIONS.iter() {
+            let (vertices, indices) = create_cube_sphere(n, 1.0);
+            assert_eq!(vertices.len(), 6 * (n + 1) * (n + 1));
+
+            // Every triangle is within one face, a quad across in u and v
+            let step = 1.0 / n as f32 + 1e-6;
+            for triangle in indices.chunks(3) {
+                let tex = |k: usize| vertices[triangle[k] as usize].tex;
+                let face = (tex(0)[0] / 2.0).floor();
+                for k in 1..3 {
+                    assert_eq!((tex(k)[0] / 2.0).floor(), face);
+                    assert!((tex(k)[0] - tex(0)[0]).abs() <= step);
+                    assert!((tex(k)[1] - tex(0)[1]).abs() <= step);
+                }
+            }
+
+            for vertex in &vertices {
+                let face = (vertex.tex[0] / 2.0).floor() as usize;
+                let point = Vector3::from(vertex.normal);
+                assert_eq!(vertex.tangent, face_tangent(point, FACES[face][1]));
+            }
+        }
+    }
+}
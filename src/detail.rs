@@ -0,0 +1,128 @@
+use crate::resample::{self, Edges};
+use glium::{backend::Facade, texture::texture2d::Texture2d};
+use image::RgbaImage;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::error;
+use std::path::Path;
+
+const GENERATED_SIZE: u32 = 256;
+
+pub struct DetailSettings {
+    pub enabled: bool,
+    pub scale: f32,
+    pub fade_distance_km: f32,
+    pub strength: f32,
+    // Lights the detail as relief too
+    pub normals: bool,
+    // Height of the relief, in repeats of the texture
+    pub relief: f32,
+}
+
+impl DetailSettings {
+    pub fn new() -> DetailSettings {
+        DetailSettings {
+            enabled: true,
+            scale: 40.0,
+            fade_distance_km: 29_400.0,
+            strength: 0.5,
+            normals: true,
+            relief: 0.02,
+        }
+    }
+
+    pub fn effective_strength(&self) -> f32 {
+        if self.enabled {
+            self.strength
+        } else {
+            0.0
+        }
+    }
+
+    pub fn effective_relief(&self) -> f32 {
+        if self.enabled && self.normals {
+            self.relief
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Loads a tiling detail texture from `path`, or generates a procedural one
+/// from `seed` when the file isn't there.
+pub fn load_detail_texture<F: Facade>(
+    facade: &F,
+    path: &str,
+    seed: u64,
+    base_cells: usize,
+    contrast: f32,
+) -> Result<Texture2d, Box<dyn error::Error>> {
+    let (data, dimensions) = if Path::new(path).exists() {
+        let image = image::open(path)?.to_rgba8();
+        let dimensions = image.dimensions();
+        (image.into_raw(), dimensions)
+    } else {
+        (
+            generate_detail(GENERATED_SIZE as usize, seed, base_cells, contrast),
+            (GENERATED_SIZE, GENERATED_SIZE),
+        )
+    };
+
+    // The mips wrap like the texture tiles, the values modulate albedo
+    // rather than being colors
+    let image = RgbaImage::from_raw(dimensions.0, dimensions.1, data)
+        .ok_or("detail texture data doesn't match its size")?;
+    resample::texture_with_mip_chain(facade, &image, Edges::Wrap, false)
+}
+
+/// Grey value noise that wraps at the texture edges, centered on 0.5 so it
+/// can modulate albedo without changing the average brightness.
+fn generate_detail(size: usize, seed: u64, base_cells: usize, contrast: f32) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut values = vec![0.0f32; size * size];
+
+    let mut cells = base_cells;
+    let mut amplitude = 1.0;
+    let mut total = 0.0;
+
+    for _ in 0..4 {
+        let lattice: Vec<f32> = (0..cells * cells).map(|_| rng.gen()).collect();
+
+        for y in 0..size {
+            let fy = y as f32 * cells as f32 / size as f32;
+            let y0 = fy as usize % cells;
+            let y1 = (y0 + 1) % cells;
+            let ty = smooth(fy.fract());
+
+            for x in 0..size {
+                let fx = x as f32 * cells as f32 / size as f32;
+                let x0 = fx as usize % cells;
+                let x1 = (x0 + 1) % cells;
+                let tx = smooth(fx.fract());
+
+                let top = lerp(lattice[y0 * cells + x0], lattice[y0 * cells + x1], tx);
+                let bottom = lerp(lattice[y1 * cells + x0], lattice[y1 * cells + x1], tx);
+                values[y * size + x] += amplitude * lerp(top, bottom, ty);
+            }
+        }
+
+        total += amplitude;
+        amplitude *= 0.5;
+        cells *= 2;
+    }
+
+    let mut data = Vec::with_capacity(size * size * 4);
+    for value in values {
+        let grey = (0.5 + contrast * (value / total - 0.5)).clamp(0.0, 1.0);
+        let byte = (grey * 255.0) as u8;
+        data.extend_from_slice(&[byte, byte, byte, 255]);
+    }
+    data
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
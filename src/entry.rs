@@ -0,0 +1,179 @@
+use cgmath::{Deg, Matrix4};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+// Descent speed at which the effects reach full strength, about orbital
+// velocity
+const FULL_SPEED_KM: f32 = 7.8;
+
+// Faster than this is a jump (preset switch, view change), not a descent
+const TELEPORT_SPEED_KM: f32 = 1000.0;
+
+const MAX_SHAKE: f32 = 0.6;
+const MAX_FOV_WIDENING: f32 = 12.0;
+
+pub struct EntrySettings {
+    pub enabled: bool,
+    pub intensity: f32,
+}
+
+/// Screen shake and field of view widening while the camera drops fast
+/// through the atmosphere.
+pub struct EntryEffects {
+    pub settings: EntrySettings,
+    pub descent_speed_km: f32,
+    pub strength: f32,
+    last_altitude_km: Option<f32>,
+    shake: (f32, f32, f32),
+    rng: StdRng,
+}
+
+impl EntryEffects {
+    pub fn new() -> EntryEffects {
+        EntryEffects {
+            settings: EntrySettings {
+                enabled: true,
+                intensity: 1.0,
+            },
+            descent_speed_km: 0.0,
+            strength: 0.0,
+            last_altitude_km: None,
+            shake: (0.0, 0.0, 0.0),
+            rng: StdRng::seed_from_u64(7),
+        }
+    }
+
+    /// `density` is that of the atmosphere around the camera relative to the
+    /// surface.
+    pub fn update(&mut self, dt: f32, altitude_km: f32, density: f32) {
+        if dt <= 0.0 {
+            return;
+        }
+
+        let descent = match self.last_altitude_km {
+            Some(last) => ((last - altitude_km) / dt).max(0.0),
+            None => 0.0,
+        };
+        self.last_altitude_km = Some(altitude_km);
+
+        // Smooth over a few frames so single jumps don't kick the camera
+        if descent > TELEPORT_SPEED_KM {
+            self.descent_speed_km = 0.0;
+        } else {
+            let blend = (dt * 8.0).min(1.0);
+            self.descent_speed_km += (descent - self.descent_speed_km) * blend;
+        }
+
+        self.strength = if self.settings.enabled {
+            let speed = (self.descent_speed_km / FULL_SPEED_KM).min(1.0);
+            speed * density * self.settings.intensity
+        } else {
+            0.0
+        };
+
+        // Jitter towards a new random target each frame, scaled by strength
+        let mut jitter = || self.rng.gen_range(-1.0f32, 1.0);
+        let target = (jitter(), jitter(), jitter());
+        self.shake = (
+            self.shake.0 + (target.0 - self.shake.0) * 0.5,
+            self.shake.1 + (target.1 - self.shake.1) * 0.5,
+            self.shake.2 + (target.2 - self.shake.2) * 0.5,
+        );
+    }
+
+    /// Small rotation about the eye, applied on top of the view matrix.
+    pub fn shake_matrix(&self) -> Matrix4<f32> {
+        let amount = self.strength * MAX_SHAKE;
+        Matrix4::from_angle_x(Deg(self.shake.0 * amount))
+            * Matrix4::from_angle_y(Deg(self.shake.1 * amount))
+            * Matrix4::from_angle_z(Deg(self.shake.2 * amount * 0.5))
+    }
+
+    pub fn fov_widening(&self) -> f32 {
+        self.strength * MAX_FOV_WIDENING
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::{Atmosphere, PRESETS};
+    use cgmath::SquareMatrix;
+
+    const DT: f32 = 1.0 / 60.0;
+
+    fn earth() -> Atmosphere {
+        PRESETS[1].atmosphere.unwrap()
+    }
+
+    /// The effects after two seconds of falling at `speed_km` km/s down to
+    /// `altitude_km`, with the density sampled the way the scene does.
+    fn falling(atmosphere: &Atmosphere, altitude_km: f32, speed_km: f32) -> EntryEffects {
+        let mut effects = EntryEffects::new();
+        let frames = 120;
+        for i in 0..=frames {
+            let altitude = altitude_km + speed_km * DT * (frames - i) as f32;
+            effects.update(DT, altitude, atmosphere.density_at(f64::from(altitude)));
+        }
+        effects
+    }
+
+    #[test]
+    fn effects_fall_off_with_the_air() {
+        let air = earth();
+        let strengths: Vec<f32> = [5.0, 20.0, 40.0, 60.0, 80.0]
+            .iter()
+            .map(|&altitude| falling(&air, altitude, FULL_SPEED_KM).strength)
+            .collect();
+        assert!(strengths[0] > 0.5);
+        assert!(strengths.windows(2).all(|pair| pair[0] > pair[1]));
+
+        // At full speed the strength is the density at the camera, which
+        // drops by e every scale height
+        let scale_height = air.scale_height_km as f32;
+        let low = falling(&air, 10.0, FULL_SPEED_KM).strength;
+        let high = falling(&air, 10.0 + scale_height, FULL_SPEED_KM).strength;
+        assert!((high / low - (-1.0f32).exp()).abs() < 1e-3);
+
+        // Faster than full speed is still full strength
+        let fast = falling(&air, 10.0, 2.0 * FULL_SPEED_KM).strength;
+        assert!((fast - low).abs() < 1e-5);
+    }
+
+    #[test]
+    fn no_effects_above_the_air() {
+        let air = earth();
+        let top = air.height_km as f32;
+        for &altitude in &[top, top + 1.0, 400.0] {
+            let effects = falling(&air, altitude, FULL_SPEED_KM);
+            assert!(effects.descent_speed_km > 0.9 * FULL_SPEED_KM);
+            assert_eq!(effects.strength, 0.0);
+            assert_eq!(effects.fov_widening(), 0.0);
+            assert_eq!(effects.shake_matrix(), Matrix4::identity());
+        }
+    }
+
+    #[test]
+    fn no_effects_at_rest_or_climbing() {
+        let air = earth();
+        for &speed in &[0.0, -FULL_SPEED_KM] {
+            let effects = falling(&air, 10.0, speed);
+            assert_eq!(effects.descent_speed_km, 0.0);
+            assert_eq!(effects.strength, 0.0);
+            assert_eq!(effects.fov_widening(), 0.0);
+            assert_eq!(effects.shake_matrix(), Matrix4::identity());
+        }
+    }
+
+    #[test]
+    fn turned_off_means_no_effects() {
+        let mut effects = EntryEffects::new();
+        effects.settings.enabled = false;
+        let air = earth();
+        for i in 0..60 {
+            let altitude = 20.0 - FULL_SPEED_KM * DT * i as f32;
+            effects.update(DT, altitude, air.density_at(f64::from(altitude)));
+        }
+        assert_eq!(effects.strength, 0.0);
+        assert_eq!(effects.fov_widening(), 0.0);
+    }
+}
@@ -0,0 +1,374 @@
+use glium::{
+    backend::Facade,
+    draw_parameters::{BackfaceCullingMode, Blend, BlendingFunction, LinearBlendingFactor},
+    program::BlockLayout,
+    uniforms::{LayoutMismatchError, UniformBlock, UniformBuffer},
+    Depth, DepthTest, DrawParameters,
+};
+use std::cell::{Cell, Ref, RefCell};
+use std::error;
+use std::mem;
+
+// Enough buffers that the one written is never one the GPU may still be
+// reading, with a shadow and a color pass per frame and frames in flight
+const RING_SIZE: usize = 6;
+
+/// What every draw of a pass shares, the `Frame` block of frame.glsl. The
+/// fields are laid out as std140 has them, the float after the vec3 packs
+/// into its last four bytes.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct FrameBlock {
+    pub projection: [[f32; 4]; 4],
+    pub sun_pos: [f32; 3],
+    pub time: f32,
+    pub viewport: [f32; 2],
+    pub sun_intensity: f32,
+    padding: f32,
+}
+
+impl FrameBlock {
+    pub fn new(
+        projection: [[f32; 4]; 4],
+        sun_pos: [f32; 3],
+        time: f32,
+        viewport: [f32; 2],
+        sun_intensity: f32,
+    ) -> FrameBlock {
+        FrameBlock {
+            projection,
+            sun_pos,
+            time,
+            viewport,
+            sun_intensity,
+            padding: 0.0,
+        }
+    }
+
+    // The name in the shader of each field, and where it is
+    fn members() -> [(&'static str, usize, BlockLayout); 5] {
+        [
+            (
+                "P",
+                mem::offset_of!(FrameBlock, projection),
+                <[[f32; 4]; 4]>::build_layout(mem::offset_of!(FrameBlock, projection)),
+            ),
+            (
+                "sunPos",
+                mem::offset_of!(FrameBlock, sun_pos),
+                <[f32; 3]>::build_layout(mem::offset_of!(FrameBlock, sun_pos)),
+            ),
+            (
+                "time",
+                mem::offset_of!(FrameBlock, time),
+                f32::build_layout(mem::offset_of!(FrameBlock, time)),
+            ),
+            (
+                "viewport",
+                mem::offset_of!(FrameBlock, viewport),
+                <[f32; 2]>::build_layout(mem::offset_of!(FrameBlock, viewport)),
+            ),
+            (
+                "sunIntensity",
+                mem::offset_of!(FrameBlock, sun_intensity),
+                f32::build_layout(mem::offset_of!(FrameBlock, sun_intensity)),
+            ),
+        ]
+    }
+}
+
+// By hand rather than with `implement_uniform_block!`, which finds the
+// field offsets through a null reference
+impl UniformBlock for FrameBlock {
+    fn matches(layout: &BlockLayout, base_offset: usize) -> Result<(), LayoutMismatchError> {
+        let members = match layout {
+            BlockLayout::Struct { members } => members,
+            _ => {
+                return Err(LayoutMismatchError::LayoutMismatch {
+                    expected: layout.clone(),
+                    obtained: FrameBlock::build_layout(base_offset),
+                })
+            }
+        };
+
+        let fields = FrameBlock::members();
+        for (name, member) in members {
+            let &(_, offset, _) = fields
+                .iter()
+                .find(|(field, _, _)| field == name)
+                .ok_or_else(|| LayoutMismatchError::MissingField { name: name.clone() })?;
+            let result = match name.as_str() {
+                "P" => <[[f32; 4]; 4]>::matches(member, base_offset + offset),
+                "sunPos" => <[f32; 3]>::matches(member, base_offset + offset),
+                "viewport" => <[f32; 2]>::matches(member, base_offset + offset),
+                _ => f32::matches(member, base_offset + offset),
+            };
+            result.map_err(|err| LayoutMismatchError::MemberMismatch {
+                member: name.clone(),
+                err: Box::new(err),
+            })?;
+        }
+        Ok(())
+    }
+
+    fn build_layout(base_offset: usize) -> BlockLayout {
+        BlockLayout::Struct {
+            members: FrameBlock::members()
+                .iter()
+                .map(|(name, _, layout)| (name.to_string(), offset_layout(layout, base_offset)))
+                .collect(),
+        }
+    }
+}
+
+fn offset_layout(layout: &BlockLayout, base_offset: usize) -> BlockLayout {
+    match layout {
+        BlockLayout::BasicType {
+            ty,
+            offset_in_buffer,
+        } => BlockLayout::BasicType {
+            ty: *ty,
+            offset_in_buffer: offset_in_buffer + base_offset,
+        },
+        other => other.clone(),
+    }
+}
+
+/// The `Frame` blocks of the passes, in buffers that stay mapped. Each pass
+/// writes the next buffer round, so a write doesn't wait on draws of the
+/// frames before that are still in flight.
+pub struct FrameUniforms {
+    ring: Vec<RefCell<UniformBuffer<FrameBlock>>>,
+    next: Cell<usize>,
+}
+
+impl FrameUniforms {
+    pub fn new<F: Facade>(facade: &F) -> Result<FrameUniforms, Box<dyn error::Error>> {
+        let empty = FrameBlock::new([[0.0; 4]; 4], [0.0; 3], 0.0, [1.0; 2], 1.0);
+        let ring = (0..RING_SIZE)
+            .map(|_| {
+                // Persistent mapping needs GL 4.4 or ARB_buffer_storage
+                UniformBuffer::persistent(facade, empty)
+                    .or_else(|_| UniformBuffer::dynamic(facade, empty))
+                    .map(RefCell::new)
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(FrameUniforms {
+            ring,
+            next: Cell::new(0),
+        })
+    }
+
+    /// Writes `block` for the draws of one pass and hands back its buffer.
+    pub fn write(&self, block: FrameBlock) -> Ref<'_, UniformBuffer<FrameBlock>> {
+        let index = self.next.get();
+        self.next.set((index + 1) % self.ring.len());
+
+        let buffer = &self.ring[index];
+        buffer.borrow_mut().map_write().write(block);
+        buffer.borrow()
+    }
+}
+
+/// The draw parameters that stay the same from frame to frame, built once.
+/// Timer and occlusion queries are set on a copy when drawing.
+pub struct PassParameters {
+    pub shadow_clockwise: DrawParameters<'static>,
+    pub shadow_counter_clockwise: DrawParameters<'static>,
+    pub skybox: DrawParameters<'static>,
+    pub sky: DrawParameters<'static>,
+    pub planet: DrawParameters<'static>,
+    pub vegetation: DrawParameters<'static>,
+    pub body: DrawParameters<'static>,
+    pub stars: DrawParameters<'static>,
+    pub sun: DrawParameters<'static>,
+    pub labels: DrawParameters<'static>,
+    pub ring: DrawParameters<'static>,
+    pub atmosphere: DrawParameters<'static>,
+    pub cloud_back: DrawParameters<'static>,
+    pub cloud_forward: DrawParameters<'static>,
+    // The clouds drawn smaller and upsampled, see cloud_target.rs
+    pub cloud_downsample: DrawParameters<'static>,
+    pub cloud_back_reduced: DrawParameters<'static>,
+    pub cloud_forward_reduced: DrawParameters<'static>,
+    pub cloud_upsample: DrawParameters<'static>,
+    pub cloud_merge: DrawParameters<'static>,
+    pub lines: DrawParameters<'static>,
+    pub meteors: DrawParameters<'static>,
+}
+
+impl PassParameters {
+    pub fn new() -> PassParameters {
+        let opaque = |backface_culling| DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write: true,
+                ..Default::default()
+            },
+            backface_culling,
+            ..Default::default()
+        };
+        let blended = |blend, write| DrawParameters {
+            depth: Depth {
+                test: DepthTest::IfLess,
+                write,
+                ..Default::default()
+            },
+            blend,
+            ..Default::default()
+        };
+        let additive = Blend {
+            color: BlendingFunction::Addition {
+                source: LinearBlendingFactor::One,
+                destination: LinearBlendingFactor::One,
+            },
+            ..Default::default()
+        };
+        // Into a target cleared to nothing, leaving the color premultiplied
+        // by how much the layers cover
+        let coverage = Blend {
+            color: BlendingFunction::Addition {
+                source: LinearBlendingFactor::SourceAlpha,
+                destination: LinearBlendingFactor::OneMinusSourceAlpha,
+            },
+            alpha: BlendingFunction::Addition {
+                source: LinearBlendingFactor::One,
+                destination: LinearBlendingFactor::OneMinusSourceAlpha,
+            },
+            ..Default::default()
+        };
+        let premultiplied = Blend {
+            color: BlendingFunction::Addition {
+                source: LinearBlendingFactor::One,
+                destination: LinearBlendingFactor::OneMinusSourceAlpha,
+            },
+            ..Default::default()
+        };
+        // On the far plane, behind everything
+        let background = DrawParameters {
+            depth: Depth {
+                test: DepthTest::Overwrite,
+                write: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        PassParameters {
+            shadow_clockwise: opaque(BackfaceCullingMode::CullClockwise),
+            shadow_counter_clockwise: opaque(BackfaceCullingMode::CullCounterClockwise),
+            skybox: DrawParameters {
+                backface_culling: BackfaceCullingMode::CullCounterClockwise,
+                ..background.clone()
+            },
+            sky: DrawParameters {
+                blend: Blend::alpha_blending(),
+                ..background
+            },
+            planet: opaque(BackfaceCullingMode::CullClockwise),
+            vegetation: opaque(BackfaceCullingMode::CullingDisabled),
+            // Models of unknown winding, so both sides are drawn
+            body: opaque(BackfaceCullingMode::CullingDisabled),
+            stars: opaque(BackfaceCullingMode::CullingDisabled),
+            sun: blended(additive, false),
+            labels: blended(Blend::alpha_blending(), false),
+            // Writes depth where it shows, see where it's drawn
+            ring: blended(Blend::alpha_blending(), true),
+            // The inside of the shell, so the glow reaches right down to
+            // the planet's silhouette
+            atmosphere: DrawParameters {
+                backface_culling: BackfaceCullingMode::CullCounterClockwise,
+                ..blended(additive, false)
+            },
+            cloud_back: DrawParameters {
+                backface_culling: BackfaceCullingMode::CullCounterClockwise,
+                ..blended(Blend::alpha_blending(), true)
+            },
+            cloud_forward: DrawParameters {
+                backface_culling: BackfaceCullingMode::CullClockwise,
+                ..blended(Blend::alpha_blending(), true)
+            },
+            cloud_downsample: DrawParameters {
+                depth: Depth {
+                    test: DepthTest::Overwrite,
+                    write: true,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            cloud_back_reduced: DrawParameters {
+                backface_culling: BackfaceCullingMode::CullCounterClockwise,
+                ..blended(coverage, true)
+            },
+            cloud_forward_reduced: DrawParameters {
+                backface_culling: BackfaceCullingMode::CullClockwise,
+                ..blended(coverage, true)
+            },
+            cloud_upsample: DrawParameters {
+                blend: premultiplied,
+                ..Default::default()
+            },
+            // Depth only, where the shell is in front
+            cloud_merge: DrawParameters {
+                color_mask: (false, false, false, false),
+                ..opaque(BackfaceCullingMode::CullingDisabled)
+            },
+            lines: blended(Blend::alpha_blending(), false),
+            meteors: blended(additive, false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glium::uniforms::UniformType;
+
+    // The Frame block as a driver reports it, std140 offsets and all
+    fn std140(sun_intensity: usize) -> BlockLayout {
+        let member = |name: &str, ty, offset_in_buffer| {
+            (
+                name.to_string(),
+                BlockLayout::BasicType {
+                    ty,
+                    offset_in_buffer,
+                },
+            )
+        };
+        BlockLayout::Struct {
+            members: vec![
+                member("P", UniformType::FloatMat4, 0),
+                member("sunPos", UniformType::FloatVec3, 64),
+                member("time", UniformType::Float, 76),
+                member("viewport", UniformType::FloatVec2, 80),
+                member("sunIntensity", UniformType::Float, sun_intensity),
+            ],
+        }
+    }
+
+    #[test]
+    fn fields_are_where_std140_puts_them() {
+        let offsets: Vec<(&str, usize)> = FrameBlock::members()
+            .iter()
+            .map(|&(name, offset, _)| (name, offset))
+            .collect();
+        assert_eq!(
+            offsets,
+            [
+                ("P", 0),
+                ("sunPos", 64),
+                ("time", 76),
+                ("viewport", 80),
+                ("sunIntensity", 88)
+            ]
+        );
+        // Rounded up to a whole vec4
+        assert_eq!(mem::size_of::<FrameBlock>(), 96);
+    }
+
+    #[test]
+    fn matches_the_shader_block() {
+        assert!(FrameBlock::matches(&std140(88), 0).is_ok());
+        assert!(FrameBlock::matches(&std140(92), 0).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+use cgmath::{vec3, Deg, InnerSpace, Matrix3, Matrix4, Vector3};
+
+// Degrees of view rotation per pixel of mouse drag
+const DRAG_SPEED: f32 = 0.2;
+
+/// Movement keys held down.
+#[derive(Copy, Clone, Default)]
+pub struct FlyKeys {
+    pub forward: bool,
+    pub back: bool,
+    pub left: bool,
+    pub right: bool,
+    pub up: bool,
+    pub down: bool,
+    pub sprint: bool,
+}
+
+/// A camera flying freely through space, moved with W/A/S/D/Q/E along its
+/// own axes and turned by dragging. It is placed by moving the world's
+/// camera position, so the view itself is only a rotation.
+pub struct FreeFly {
+    pub enabled: bool,
+    // Degrees, yaw turning right from -z and pitch up from the horizon
+    pub yaw: f32,
+    pub pitch: f32,
+    pub speed_km: f32,
+    // Speed multiplier while shift is held
+    pub sprint: f32,
+    pub keys: FlyKeys,
+}
+
+impl FreeFly {
+    pub fn new() -> FreeFly {
+        FreeFly {
+            enabled: false,
+            yaw: 0.0,
+            pitch: 0.0,
+            speed_km: 2_000.0,
+            sprint: 5.0,
+            keys: FlyKeys::default(),
+        }
+    }
+
+    /// Turns the camera to look along `forward`, dropping any roll.
+    pub fn look_along(&mut self, forward: Vector3<f32>) {
+        let forward = forward.normalize();
+        self.yaw = forward.x.atan2(-forward.z).to_degrees();
+        self.pitch = forward.y.clamp(-1.0, 1.0).asin().to_degrees();
+    }
+
+    /// Camera axes to world axes.
+    fn rotation(&self) -> Matrix3<f32> {
+        Matrix3::from_angle_y(Deg(-self.yaw)) * Matrix3::from_angle_x(Deg(self.pitch))
+    }
+
+    /// World-to-eye transform for an eye at the origin.
+    pub fn view_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_angle_x(Deg(-self.pitch)) * Matrix4::from_angle_y(Deg(self.yaw))
+    }
+
+    /// How far the held keys move the camera over `dt` seconds, in km along
+    /// the world axes.
+    pub fn step(&self, dt: f32) -> Vector3<f32> {
+        let axis = |positive: bool, negative: bool| match (positive, negative) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        };
+        let keys = &self.keys;
+        let local = vec3(
+            axis(keys.right, keys.left),
+            axis(keys.up, keys.down),
+            axis(keys.back, keys.forward),
+        );
+        if local.magnitude2() == 0.0 {
+            return local;
+        }
+
+        let speed = if keys.sprint {
+            self.speed_km * self.sprint
+        } else {
+            self.speed_km
+        };
+        self.rotation() * local.normalize() * speed * dt
+    }
+
+    pub fn drag(&mut self, dx: f32, dy: f32) {
+        self.yaw = (self.yaw + dx * DRAG_SPEED) % 360.0;
+        self.pitch = (self.pitch - dy * DRAG_SPEED).clamp(-89.0, 89.0);
+    }
+}
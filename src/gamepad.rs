@@ -0,0 +1,243 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Looking for a controller this often while none is plugged in
+const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+// Axes and buttons as the Linux joystick driver numbers them for an Xbox
+// style pad
+const LEFT_X: usize = 0;
+const LEFT_TRIGGER: usize = 2;
+const RIGHT_X: usize = 3;
+const RIGHT_Y: usize = 4;
+const RIGHT_TRIGGER: usize = 5;
+const LEFT_SHOULDER: usize = 4;
+const RIGHT_SHOULDER: usize = 5;
+
+const AXES: usize = 8;
+const BUTTONS: usize = 16;
+
+// js_event types, the initial state of each control comes with the init
+// flag set
+const EVENT_BUTTON: u8 = 0x01;
+const EVENT_AXIS: u8 = 0x02;
+const EVENT_INIT: u8 = 0x80;
+
+pub struct GamepadSettings {
+    pub enabled: bool,
+    // Stick deflection ignored around the center, from 0 to 1
+    pub dead_zone: f32,
+    pub invert_x: bool,
+    pub invert_y: bool,
+    // Degrees per second at full deflection
+    pub spin_speed: f32,
+    pub orbit_speed: f32,
+    pub sun_speed: f32,
+    // Scroll wheel notches per second with a trigger held all the way
+    pub zoom_speed: f32,
+}
+
+impl GamepadSettings {
+    pub fn new() -> GamepadSettings {
+        GamepadSettings {
+            enabled: true,
+            dead_zone: 0.15,
+            invert_x: false,
+            invert_y: false,
+            spin_speed: 90.0,
+            orbit_speed: 90.0,
+            sun_speed: 30.0,
+            zoom_speed: 8.0,
+        }
+    }
+}
+
+/// What the sticks, triggers and shoulder buttons ask for this frame, with
+/// the dead zone and inversion applied.
+pub struct GamepadInput {
+    // Degrees
+    pub spin: f32,
+    pub orbit_yaw: f32,
+    pub orbit_pitch: f32,
+    pub sun_angle: f32,
+    // Scroll wheel notches, positive in
+    pub zoom: f32,
+}
+
+/// The first joystick device, read on a thread of its own. Unplugging it
+/// ends the thread, after which the devices are scanned again every
+/// `RESCAN_INTERVAL`, so a controller can come and go at any time.
+pub struct Gamepad {
+    pub settings: GamepadSettings,
+    pub device: Option<PathBuf>,
+    events: Option<Receiver<(u8, u8, i16)>>,
+    last_scan: Option<Instant>,
+    // From -1 to 1
+    axes: [f32; AXES],
+    buttons: [bool; BUTTONS],
+}
+
+impl Gamepad {
+    pub fn new() -> Gamepad {
+        Gamepad {
+            settings: GamepadSettings::new(),
+            device: None,
+            events: None,
+            last_scan: None,
+            axes: resting_axes(),
+            buttons: [false; BUTTONS],
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.events.is_some()
+    }
+
+    /// Takes the events since the last frame, or looks for a controller
+    /// if there is none.
+    pub fn poll(&mut self) {
+        if !self.settings.enabled {
+            self.disconnect();
+            return;
+        }
+
+        if self.events.is_none() {
+            let due = self
+                .last_scan
+                .is_none_or(|scan| scan.elapsed() >= RESCAN_INTERVAL);
+            if due {
+                self.last_scan = Some(Instant::now());
+                self.connect();
+            }
+        }
+
+        let mut gone = false;
+        if let Some(ref events) = self.events {
+            loop {
+                match events.try_recv() {
+                    Ok((kind, number, value)) => {
+                        let number = number as usize;
+                        match kind & !EVENT_INIT {
+                            EVENT_AXIS if number < AXES => {
+                                self.axes[number] = f32::from(value) / 32767.0;
+                            }
+                            EVENT_BUTTON if number < BUTTONS => self.buttons[number] = value != 0,
+                            _ => {}
+                        }
+                    }
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => {
+                        gone = true;
+                        break;
+                    }
+                }
+            }
+        }
+        if gone {
+            println!("Gamepad disconnected");
+            self.disconnect();
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.events = None;
+        self.device = None;
+        self.axes = resting_axes();
+        self.buttons = [false; BUTTONS];
+    }
+
+    fn connect(&mut self) {
+        let device = match find_device() {
+            Some(device) => device,
+            None => return,
+        };
+        let mut file = match File::open(&device) {
+            Ok(file) => file,
+            Err(e) => {
+                println!("Opening {}: {}", device.display(), e);
+                return;
+            }
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            // struct js_event: u32 time, i16 value, u8 type, u8 number
+            let mut event = [0u8; 8];
+            while file.read_exact(&mut event).is_ok() {
+                let value = i16::from_ne_bytes([event[4], event[5]]);
+                if sender.send((event[6], event[7], value)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        println!("Gamepad connected: {}", device.display());
+        self.device = Some(device);
+        self.events = Some(receiver);
+    }
+
+    fn axis(&self, index: usize) -> f32 {
+        let value = self.axes[index];
+        let dead_zone = self.settings.dead_zone.clamp(0.0, 0.99);
+        if value.abs() <= dead_zone {
+            0.0
+        } else {
+            // Rescaled to start from zero at the edge of the dead zone
+            value.signum() * (value.abs() - dead_zone) / (1.0 - dead_zone)
+        }
+    }
+
+    /// Triggers rest at -1, this goes from 0 released to 1 held down.
+    fn trigger(&self, index: usize) -> f32 {
+        (0.5 * (self.axes[index] + 1.0)).clamp(0.0, 1.0)
+    }
+
+    pub fn input(&self, dt: f32) -> GamepadInput {
+        let settings = &self.settings;
+        let x_sign = if settings.invert_x { -1.0 } else { 1.0 };
+        let y_sign = if settings.invert_y { -1.0 } else { 1.0 };
+
+        let sun = match (self.buttons[LEFT_SHOULDER], self.buttons[RIGHT_SHOULDER]) {
+            (true, false) => -1.0,
+            (false, true) => 1.0,
+            _ => 0.0,
+        };
+
+        GamepadInput {
+            spin: x_sign * self.axis(LEFT_X) * settings.spin_speed * dt,
+            orbit_yaw: x_sign * self.axis(RIGHT_X) * settings.orbit_speed * dt,
+            orbit_pitch: y_sign * self.axis(RIGHT_Y) * settings.orbit_speed * dt,
+            sun_angle: sun * settings.sun_speed * dt,
+            zoom: (self.trigger(RIGHT_TRIGGER) - self.trigger(LEFT_TRIGGER))
+                * settings.zoom_speed
+                * dt,
+        }
+    }
+}
+
+/// Sticks centered and triggers released, until the driver reports them.
+fn resting_axes() -> [f32; AXES] {
+    let mut axes = [0.0; AXES];
+    axes[LEFT_TRIGGER] = -1.0;
+    axes[RIGHT_TRIGGER] = -1.0;
+    axes
+}
+
+/// The first `/dev/input/js*`, in name order. Other systems don't have the
+/// joystick devices and never find a controller.
+fn find_device() -> Option<PathBuf> {
+    let mut devices: Vec<PathBuf> = fs::read_dir("/dev/input")
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("js"))
+        })
+        .collect();
+    devices.sort();
+    devices.into_iter().next()
+}
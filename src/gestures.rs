@@ -0,0 +1,168 @@
+use glium::glutin::{Touch, TouchPhase};
+
+// Trackpad scrolling pixels that make a mouse wheel notch for the UI
+const PIXELS_PER_LINE: f32 = 20.0;
+
+pub struct GestureSettings {
+    pub enabled: bool,
+    // Degrees of orbit per pixel of two finger scrolling or dragging
+    pub orbit_speed: f32,
+    // Scroll wheel notches per pixel of pinching
+    pub zoom_speed: f32,
+    // Pixels the view moves per pixel of a three finger drag
+    pub pan_speed: f32,
+    pub invert: bool,
+}
+
+impl GestureSettings {
+    pub fn new() -> GestureSettings {
+        GestureSettings {
+            enabled: true,
+            orbit_speed: 0.25,
+            zoom_speed: 0.02,
+            pan_speed: 1.0,
+            invert: false,
+        }
+    }
+}
+
+/// What the gestures since the last frame ask for, with the settings
+/// applied.
+pub struct GestureInput {
+    // Degrees
+    pub orbit_yaw: f32,
+    pub orbit_pitch: f32,
+    // Scroll wheel notches, positive in
+    pub zoom: f32,
+    // Pixels
+    pub pan: (f32, f32),
+}
+
+/// Trackpads and touch screens. Scrolling by pixels is two fingers on a
+/// trackpad and orbits, unless Ctrl is held, which is how platforms that
+/// have pinching hand it on as scrolling. On a touch screen two fingers
+/// orbit and pinch, three pan.
+pub struct Gestures {
+    pub settings: GestureSettings,
+    // Fingers down, by id, in logical pixels
+    touches: Vec<(u64, (f64, f64))>,
+    // Since the last frame, in pixels
+    scroll: (f32, f32),
+    orbit: (f32, f32),
+    pinch: f32,
+    pan: (f32, f32),
+}
+
+fn centroid(touches: &[(u64, (f64, f64))]) -> (f64, f64) {
+    let n = touches.len().max(1) as f64;
+    let (x, y) = touches
+        .iter()
+        .fold((0.0, 0.0), |(x, y), (_, pos)| (x + pos.0, y + pos.1));
+    (x / n, y / n)
+}
+
+/// Mean distance of the fingers from their centroid.
+fn spread(touches: &[(u64, (f64, f64))]) -> f64 {
+    let center = centroid(touches);
+    let n = touches.len().max(1) as f64;
+    touches
+        .iter()
+        .map(|(_, pos)| (pos.0 - center.0).hypot(pos.1 - center.1))
+        .sum::<f64>()
+        / n
+}
+
+impl Gestures {
+    pub fn new() -> Gestures {
+        Gestures {
+            settings: GestureSettings::new(),
+            touches: Vec::new(),
+            scroll: (0.0, 0.0),
+            orbit: (0.0, 0.0),
+            pinch: 0.0,
+            pan: (0.0, 0.0),
+        }
+    }
+
+    /// Scrolling by pixels, with Ctrl held for a pinch.
+    pub fn scroll(&mut self, x: f32, y: f32, ctrl: bool) {
+        self.scroll.0 += x;
+        self.scroll.1 += y;
+        if ctrl {
+            self.pinch += y;
+        } else {
+            self.orbit.0 += x;
+            self.orbit.1 += y;
+        }
+    }
+
+    /// This frame's pixel scrolling as mouse wheel notches, for the UI.
+    pub fn wheel(&self) -> f32 {
+        self.scroll.1 / PIXELS_PER_LINE
+    }
+
+    pub fn touch(&mut self, touch: Touch) {
+        let location = (touch.location.x, touch.location.y);
+        let index = self.touches.iter().position(|&(id, _)| id == touch.id);
+        match (touch.phase, index) {
+            (TouchPhase::Started, None) => self.touches.push((touch.id, location)),
+            (TouchPhase::Moved, Some(index)) => {
+                let before = (centroid(&self.touches), spread(&self.touches));
+                self.touches[index].1 = location;
+                let after = (centroid(&self.touches), spread(&self.touches));
+
+                let moved = (
+                    (after.0 .0 - before.0 .0) as f32,
+                    (after.0 .1 - before.0 .1) as f32,
+                );
+                match self.touches.len() {
+                    2 => {
+                        self.orbit.0 += moved.0;
+                        self.orbit.1 += moved.1;
+                        // Fingers spreading zoom in
+                        self.pinch += (after.1 - before.1) as f32;
+                    }
+                    3 => {
+                        self.pan.0 += moved.0;
+                        self.pan.1 += moved.1;
+                    }
+                    _ => {}
+                }
+            }
+            (TouchPhase::Ended, Some(index)) | (TouchPhase::Cancelled, Some(index)) => {
+                self.touches.remove(index);
+            }
+            _ => {}
+        }
+    }
+
+    /// Takes what was gathered since the last call.
+    pub fn input(&mut self) -> GestureInput {
+        let settings = &self.settings;
+        let sign = if settings.invert { -1.0 } else { 1.0 };
+        let input = if settings.enabled {
+            GestureInput {
+                orbit_yaw: sign * self.orbit.0 * settings.orbit_speed,
+                orbit_pitch: sign * self.orbit.1 * settings.orbit_speed,
+                zoom: self.pinch * settings.zoom_speed,
+                pan: (
+                    sign * self.pan.0 * settings.pan_speed,
+                    sign * self.pan.1 * settings.pan_speed,
+                ),
+            }
+        } else {
+            GestureInput {
+                orbit_yaw: 0.0,
+                orbit_pitch: 0.0,
+                zoom: 0.0,
+                pan: (0.0, 0.0),
+            }
+        };
+
+        self.scroll = (0.0, 0.0);
+        self.orbit = (0.0, 0.0);
+        self.pinch = 0.0;
+        self.pan = (0.0, 0.0);
+        input
+    }
+}
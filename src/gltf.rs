@@ -0,0 +1,502 @@
+use crate::json::{self, Json};
+use crate::obj::any_tangent;
+use crate::Vertex;
+use cgmath::{vec3, InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, SquareMatrix, Vector3};
+use image::RgbaImage;
+use std::error;
+use std::fs;
+use std::path::Path;
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const CHUNK_JSON: u32 = 0x4e4f_534a;
+const CHUNK_BIN: u32 = 0x004e_4942;
+
+// Primitive modes, only triangle lists are read
+const MODE_TRIANGLES: usize = 4;
+
+/// The first primitive of the first mesh of a glTF file, with the transform
+/// of the node that holds it baked in.
+pub struct GltfModel {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+    pub base_color: Option<RgbaImage>,
+    // What was left out, for the user to see
+    pub warnings: Vec<String>,
+}
+
+fn le_u32(bytes: &[u8], at: usize) -> Option<u32> {
+    bytes
+        .get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+/// The JSON and binary chunks of a .glb container.
+fn split_glb(bytes: &[u8]) -> Result<(String, Option<Vec<u8>>), String> {
+    let version = le_u32(bytes, 4).ok_or("truncated header")?;
+    if version != 2 {
+        return Err(format!("glTF version {} is not supported", version));
+    }
+    let length = (le_u32(bytes, 8).ok_or("truncated header")? as usize).min(bytes.len());
+
+    let (mut text, mut bin) = (None, None);
+    let mut at = 12;
+    while at + 8 <= length {
+        let chunk_length = le_u32(bytes, at).ok_or("truncated chunk")? as usize;
+        let kind = le_u32(bytes, at + 4).ok_or("truncated chunk")?;
+        let data = bytes
+            .get(at + 8..at + 8 + chunk_length)
+            .ok_or("truncated chunk")?;
+        match kind {
+            CHUNK_JSON => {
+                text = Some(String::from_utf8(data.to_vec()).map_err(|_| "JSON is not UTF-8")?)
+            }
+            CHUNK_BIN if bin.is_none() => bin = Some(data.to_vec()),
+            _ => {}
+        }
+        // Chunks are padded to four bytes
+        at += 8 + chunk_length.div_ceil(4) * 4;
+    }
+    Ok((text.ok_or("no JSON chunk")?, bin))
+}
+
+fn base64(text: &str) -> Result<Vec<u8>, String> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' | b'-' => Some(62),
+        b'/' | b'_' => Some(63),
+        _ => None,
+    };
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut bits, mut count) = (0u32, 0);
+    for c in text
+        .bytes()
+        .filter(|&c| c != b'=' && !c.is_ascii_whitespace())
+    {
+        bits = bits << 6 | u32::from(value(c).ok_or("bad base64")?);
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            bytes.push((bits >> count) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+/// The bytes a buffer or image `uri` points at, embedded or next to the
+/// file at `dir`.
+fn load_uri(uri: &str, dir: &Path) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    if let Some(data) = uri.strip_prefix("data:") {
+        let (_, encoded) = data
+            .split_once(";base64,")
+            .ok_or("data URI is not base64")?;
+        Ok(base64(encoded)?)
+    } else {
+        Ok(fs::read(dir.join(uri))?)
+    }
+}
+
+struct Document {
+    json: Json,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Document {
+    fn list(&self, name: &str) -> &[Json] {
+        self.json.get(name).map_or(&[], Json::elements)
+    }
+
+    fn item(&self, name: &str, index: usize) -> Result<&Json, String> {
+        self.list(name)
+            .get(index)
+            .ok_or_else(|| format!("{} {} does not exist", name, index))
+    }
+
+    /// The bytes of buffer view `index`, and the stride between elements
+    /// if it sets one.
+    fn view(&self, index: usize) -> Result<(&[u8], Option<usize>), String> {
+        let view = self.item("bufferViews", index)?;
+        let buffer = view
+            .get("buffer")
+            .and_then(Json::as_usize)
+            .and_then(|buffer| self.buffers.get(buffer))
+            .ok_or("buffer view has no buffer")?;
+        let offset = view.get("byteOffset").and_then(Json::as_usize).unwrap_or(0);
+        let length = view
+            .get("byteLength")
+            .and_then(Json::as_usize)
+            .ok_or("buffer view has no length")?;
+        let bytes = buffer
+            .get(offset..offset + length)
+            .ok_or("buffer view is out of its buffer")?;
+        Ok((bytes, view.get("byteStride").and_then(Json::as_usize)))
+    }
+
+    /// Accessor `index` as numbers, normalized integers mapped to 0 to 1,
+    /// and how many numbers make each element.
+    fn accessor(&self, index: usize) -> Result<(Vec<f64>, usize), String> {
+        let accessor = self.item("accessors", index)?;
+        let count = accessor
+            .get("count")
+            .and_then(Json::as_usize)
+            .ok_or("accessor has no count")?;
+        let components = match accessor.get("type").and_then(Json::as_str) {
+            Some("SCALAR") => 1,
+            Some("VEC2") => 2,
+            Some("VEC3") => 3,
+            Some("VEC4") => 4,
+            Some("MAT4") => 16,
+            other => return Err(format!("accessor type {:?} is not supported", other)),
+        };
+        let component_type = accessor
+            .get("componentType")
+            .and_then(Json::as_usize)
+            .ok_or("accessor has no component type")?;
+        let size = match component_type {
+            5120 | 5121 => 1,
+            5122 | 5123 => 2,
+            5125 | 5126 => 4,
+            other => return Err(format!("component type {} is not supported", other)),
+        };
+        let normalized = accessor.get("normalized") == Some(&Json::Bool(true));
+
+        // An accessor with no view is all zeros
+        let view = match accessor.get("bufferView").and_then(Json::as_usize) {
+            Some(view) => view,
+            None => return Ok((vec![0.0; count * components], components)),
+        };
+        let (bytes, stride) = self.view(view)?;
+        let offset = accessor
+            .get("byteOffset")
+            .and_then(Json::as_usize)
+            .unwrap_or(0);
+        let stride = stride.unwrap_or(size * components);
+
+        let mut values = Vec::with_capacity(count * components);
+        for element in 0..count {
+            for component in 0..components {
+                let at = offset + element * stride + component * size;
+                let b = bytes
+                    .get(at..at + size)
+                    .ok_or("accessor is out of its view")?;
+                let value = match component_type {
+                    5120 => f64::from(b[0] as i8) / if normalized { 127.0 } else { 1.0 },
+                    5121 => f64::from(b[0]) / if normalized { 255.0 } else { 1.0 },
+                    5122 => {
+                        f64::from(i16::from_le_bytes([b[0], b[1]]))
+                            / if normalized { 32767.0 } else { 1.0 }
+                    }
+                    5123 => {
+                        f64::from(u16::from_le_bytes([b[0], b[1]]))
+                            / if normalized { 65535.0 } else { 1.0 }
+                    }
+                    5125 => f64::from(u32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                    _ => f64::from(f32::from_le_bytes([b[0], b[1], b[2], b[3]])),
+                };
+                values.push(value);
+            }
+        }
+        Ok((values, components))
+    }
+
+    /// The transform of node `index` alone, from its matrix or from its
+    /// translation, rotation and scale.
+    fn local_transform(&self, node: &Json) -> Matrix4<f32> {
+        let numbers = |key: &str| -> Vec<f32> {
+            node.get(key)
+                .map_or(&[][..], Json::elements)
+                .iter()
+                .filter_map(Json::as_f64)
+                .map(|value| value as f32)
+                .collect()
+        };
+
+        let m = numbers("matrix");
+        if m.len() == 16 {
+            // Column major, as cgmath takes it
+            return Matrix4::new(
+                m[0], m[1], m[2], m[3], m[4], m[5], m[6], m[7], m[8], m[9], m[10], m[11], m[12],
+                m[13], m[14], m[15],
+            );
+        }
+        let t = numbers("translation");
+        let r = numbers("rotation");
+        let s = numbers("scale");
+        let translation = if t.len() == 3 {
+            Matrix4::from_translation(vec3(t[0], t[1], t[2]))
+        } else {
+            Matrix4::identity()
+        };
+        // x, y, z, w in the file
+        let rotation = if r.len() == 4 {
+            Matrix4::from(Quaternion::new(r[3], r[0], r[1], r[2]).normalize())
+        } else {
+            Matrix4::identity()
+        };
+        let scale = if s.len() == 3 {
+            Matrix4::from_nonuniform_scale(s[0], s[1], s[2])
+        } else {
+            Matrix4::identity()
+        };
+        translation * rotation * scale
+    }
+
+    /// The transform from mesh `mesh` to the scene, through the first node
+    /// holding it and that node's parents.
+    fn mesh_transform(&self, mesh: usize) -> Matrix4<f32> {
+        let nodes = self.list("nodes");
+        let holder = nodes
+            .iter()
+            .position(|node| node.get("mesh").and_then(Json::as_usize) == Some(mesh));
+        let parent = |child: usize| {
+            nodes.iter().position(|node| {
+                node.get("children")
+                    .map_or(&[][..], Json::elements)
+                    .iter()
+                    .any(|index| index.as_usize() == Some(child))
+            })
+        };
+
+        let mut transform = Matrix4::identity();
+        let mut next = holder;
+        // Bounded in case a broken file has a cycle
+        for _ in 0..nodes.len() {
+            let index = match next {
+                Some(index) => index,
+                None => break,
+            };
+            transform = self.local_transform(&nodes[index]) * transform;
+            next = parent(index);
+        }
+        transform
+    }
+
+    /// The image of the base color texture of `material`, if it is one the
+    /// image crate can read.
+    fn base_color(
+        &self,
+        material: usize,
+        dir: &Path,
+        warnings: &mut Vec<String>,
+    ) -> Result<Option<RgbaImage>, Box<dyn error::Error>> {
+        let texture = self
+            .item("materials", material)?
+            .get("pbrMetallicRoughness")
+            .and_then(|pbr| pbr.get("baseColorTexture"));
+        let texture = match texture {
+            Some(texture) => texture,
+            None => return Ok(None),
+        };
+        if texture
+            .get("texCoord")
+            .and_then(Json::as_usize)
+            .unwrap_or(0)
+            != 0
+        {
+            warnings.push("The base color uses a second set of UVs, the first is used".into());
+        }
+        let source = texture
+            .get("index")
+            .and_then(Json::as_usize)
+            .map(|index| self.item("textures", index))
+            .transpose()?
+            .and_then(|texture| texture.get("source"))
+            .and_then(Json::as_usize)
+            .ok_or("base color texture has no image")?;
+        let image = self.item("images", source)?;
+
+        let bytes = match (
+            image.get("bufferView").and_then(Json::as_usize),
+            image.get("uri").and_then(Json::as_str),
+        ) {
+            (Some(view), _) => self.view(view)?.0.to_vec(),
+            (None, Some(uri)) => load_uri(uri, dir)?,
+            (None, None) => return Err("image has neither a buffer view nor a URI".into()),
+        };
+        match image::load_from_memory(&bytes) {
+            Ok(decoded) => Ok(Some(decoded.to_rgba8())),
+            Err(e) => {
+                let kind = image
+                    .get("mimeType")
+                    .and_then(Json::as_str)
+                    .unwrap_or("image");
+                warnings.push(format!(
+                    "The base color texture ({}) was skipped: {}",
+                    kind, e
+                ));
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Reads a .gltf or .glb file. What it can't show, skins, morph targets,
+/// animations and any meshes past the first, is left out with a warning.
+pub fn read_gltf(path: &Path) -> Result<GltfModel, Box<dyn error::Error>> {
+    let bytes = fs::read(path)?;
+    let (text, bin) = if bytes.starts_with(GLB_MAGIC) {
+        split_glb(&bytes)?
+    } else {
+        (
+            String::from_utf8(bytes).map_err(|_| "glTF is not UTF-8")?,
+            None,
+        )
+    };
+    let json = json::parse(&text)?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let mut warnings = Vec::new();
+    let mut bin = bin;
+    let mut buffers = Vec::new();
+    for buffer in json.get("buffers").map_or(&[][..], Json::elements) {
+        buffers.push(match buffer.get("uri").and_then(Json::as_str) {
+            Some(uri) => load_uri(uri, dir)?,
+            None => bin.take().ok_or("buffer has no data")?,
+        });
+    }
+    let document = Document { json, buffers };
+
+    let meshes = document.list("meshes");
+    let mesh = meshes.first().ok_or("the file has no meshes")?;
+    let primitives = mesh.get("primitives").map_or(&[][..], Json::elements);
+    let primitive = primitives.first().ok_or("the mesh has no primitives")?;
+    if meshes.len() > 1 || primitives.len() > 1 {
+        warnings.push(format!(
+            "Only the first primitive of the first mesh was loaded, of {} meshes",
+            meshes.len()
+        ));
+    }
+    if !document.list("skins").is_empty()
+        || primitive
+            .get("attributes")
+            .and_then(|a| a.get("JOINTS_0"))
+            .is_some()
+    {
+        warnings.push("Skinning is not supported, the mesh is in its bind pose".into());
+    }
+    if !primitive
+        .get("targets")
+        .map_or(&[][..], Json::elements)
+        .is_empty()
+    {
+        warnings.push("Morph targets are not supported and were skipped".into());
+    }
+    if !document.list("animations").is_empty() {
+        warnings.push("Animations are not supported and were skipped".into());
+    }
+    if let Some(required) = document.json.get("extensionsRequired") {
+        for extension in required.elements().iter().filter_map(Json::as_str) {
+            warnings.push(format!("Required extension {} is not supported", extension));
+        }
+    }
+
+    let mode = primitive
+        .get("mode")
+        .and_then(Json::as_usize)
+        .unwrap_or(MODE_TRIANGLES);
+    if mode != MODE_TRIANGLES {
+        return Err(format!("primitive mode {} is not supported, only triangles", mode).into());
+    }
+
+    let attributes = primitive
+        .get("attributes")
+        .ok_or("primitive has no attributes")?;
+    let attribute = |name: &str, components: usize| -> Result<Option<Vec<f64>>, String> {
+        match attributes.get(name).and_then(Json::as_usize) {
+            Some(index) => {
+                let (values, found) = document.accessor(index)?;
+                if found != components {
+                    return Err(format!("{} has {} components", name, found));
+                }
+                Ok(Some(values))
+            }
+            None => Ok(None),
+        }
+    };
+    let positions = attribute("POSITION", 3)?.ok_or("primitive has no positions")?;
+    let normals = attribute("NORMAL", 3)?;
+    let texes = attribute("TEXCOORD_0", 2)?;
+    let count = positions.len() / 3;
+
+    let indices: Vec<u32> = match primitive.get("indices").and_then(Json::as_usize) {
+        Some(index) => document
+            .accessor(index)?
+            .0
+            .into_iter()
+            .map(|index| index as u32)
+            .collect(),
+        None => (0..count as u32).collect(),
+    };
+    if indices.iter().any(|&index| index as usize >= count) {
+        return Err("an index is past the last vertex".into());
+    }
+
+    let transform = document.mesh_transform(0);
+    let normal_transform = Matrix3::from_cols(
+        transform.x.truncate(),
+        transform.y.truncate(),
+        transform.z.truncate(),
+    )
+    .invert()
+    .map_or(Matrix3::identity(), |inverse| inverse.transpose());
+
+    let at3 = |values: &[f64], i: usize| {
+        vec3(
+            values[3 * i] as f32,
+            values[3 * i + 1] as f32,
+            values[3 * i + 2] as f32,
+        )
+    };
+    let mut vertices: Vec<Vertex> = (0..count)
+        .map(|i| {
+            let pos = (transform * at3(&positions, i).extend(1.0)).truncate();
+            let normal = normals
+                .as_ref()
+                .map(|normals| (normal_transform * at3(normals, i)).normalize())
+                .filter(|normal| normal.magnitude2() > 0.0)
+                .unwrap_or_else(|| vec3(0.0, 0.0, 0.0));
+            Vertex {
+                pos: pos.into(),
+                normal: normal.into(),
+                tex: texes
+                    .as_ref()
+                    .map_or([0.0, 0.0], |t| [t[2 * i] as f32, t[2 * i + 1] as f32]),
+                tangent: [0.0; 4],
+                cell: 0,
+            }
+        })
+        .collect();
+
+    // Smooth normals from the faces where the file has none
+    if normals.is_none() {
+        let mut sums = vec![vec3(0.0, 0.0, 0.0); count];
+        for triangle in indices.chunks_exact(3) {
+            let corner = |k: usize| Vector3::from(vertices[triangle[k] as usize].pos);
+            let normal = (corner(1) - corner(0)).cross(corner(2) - corner(0));
+            for &index in triangle {
+                sums[index as usize] += normal;
+            }
+        }
+        for (vertex, sum) in vertices.iter_mut().zip(sums) {
+            if sum.magnitude2() > 0.0 {
+                vertex.normal = sum.normalize().into();
+            }
+        }
+    }
+    for vertex in &mut vertices {
+        vertex.tangent = any_tangent(Vector3::from(vertex.normal));
+    }
+
+    let base_color = match primitive.get("material").and_then(Json::as_usize) {
+        Some(material) => document.base_color(material, dir, &mut warnings)?,
+        None => None,
+    };
+
+    Ok(GltfModel {
+        vertices,
+        indices,
+        base_color,
+        warnings,
+    })
+}
@@ -0,0 +1,82 @@
+use glium::{backend::Facade, draw_parameters::TimeElapsedQuery};
+use std::cell::Cell;
+
+/// GPU time of a single pass, measured with a time-elapsed query that is
+/// read back a frame or more later so the CPU never waits on it.
+pub struct PassTimer {
+    query: Option<TimeElapsedQuery>,
+    in_flight: Cell<bool>,
+    pub milliseconds: f32,
+}
+
+impl PassTimer {
+    pub fn new() -> PassTimer {
+        PassTimer {
+            query: None,
+            in_flight: Cell::new(false),
+            milliseconds: 0.0,
+        }
+    }
+
+    /// Picks up the previous result if it has arrived and makes sure a query
+    /// is ready for this frame's draw.
+    pub fn begin_frame<F: Facade>(&mut self, facade: &F) {
+        if self.in_flight.get() && self.query.as_ref().is_some_and(|q| q.is_ready()) {
+            if let Some(query) = self.query.take() {
+                self.milliseconds = query.get() as f32 * 1e-6;
+            }
+            self.in_flight.set(false);
+        }
+
+        if self.query.is_none() {
+            self.query = TimeElapsedQuery::new(facade).ok();
+        }
+    }
+
+    /// The query to attach to this frame's draw, or `None` while the last
+    /// measurement is still pending.
+    pub fn query(&self) -> Option<&TimeElapsedQuery> {
+        if self.in_flight.get() {
+            return None;
+        }
+
+        let query = self.query.as_ref();
+        if query.is_some() {
+            self.in_flight.set(true);
+        }
+        query
+    }
+}
+
+/// Timers of the passes `render_scene` draws besides vegetation, which has
+/// its own. Each covers a run of consecutive draws.
+pub struct PassTimers {
+    pub shadowmap: PassTimer,
+    pub planet: PassTimer,
+    pub stars: PassTimer,
+    pub labels: PassTimer,
+    pub clouds: PassTimer,
+    pub overlays: PassTimer,
+}
+
+impl PassTimers {
+    pub fn new() -> PassTimers {
+        PassTimers {
+            shadowmap: PassTimer::new(),
+            planet: PassTimer::new(),
+            stars: PassTimer::new(),
+            labels: PassTimer::new(),
+            clouds: PassTimer::new(),
+            overlays: PassTimer::new(),
+        }
+    }
+
+    pub fn begin_frame<F: Facade>(&mut self, facade: &F) {
+        self.shadowmap.begin_frame(facade);
+        self.planet.begin_frame(facade);
+        self.stars.begin_frame(facade);
+        self.labels.begin_frame(facade);
+        self.clouds.begin_frame(facade);
+        self.overlays.begin_frame(facade);
+    }
+}
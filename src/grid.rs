@@ -0,0 +1,155 @@
+use crate::icosphere::{icosahedron, subdivide};
+use crate::Vertex;
+use cgmath::{InnerSpace, Vector3};
+
+// Splits of the icosahedron under the grid, 2562 cells
+pub const GRID_SUBDIVISIONS: u32 = 4;
+
+/// A geodesic grid over the unit sphere: the hexagons, and twelve
+/// pentagons, around the points of a split icosahedron. A direction is in
+/// the cell whose center is nearest.
+pub struct PlanetGrid {
+    pub centers: Vec<Vector3<f32>>,
+    // The cells sharing an edge with each cell
+    neighbours: Vec<Vec<usize>>,
+    // One value for each cell for the shaders to show, a rough temperature
+    // from 0 at the poles to 1 at the equator until something better fills
+    // it in
+    pub values: Vec<f32>,
+}
+
+impl PlanetGrid {
+    pub fn new(subdivisions: u32) -> PlanetGrid {
+        let (mut centers, mut faces) = icosahedron();
+        for _ in 0..subdivisions {
+            faces = subdivide(&mut centers, &faces);
+        }
+
+        let mut neighbours = vec![Vec::new(); centers.len()];
+        for face in &faces {
+            for k in 0..3 {
+                let (a, b) = (face[k] as usize, face[(k + 1) % 3] as usize);
+                if !neighbours[a].contains(&b) {
+                    neighbours[a].push(b);
+                    neighbours[b].push(a);
+                }
+            }
+        }
+
+        let values = centers
+            .iter()
+            .map(|center| (1.0 - center.z * center.z).max(0.0).sqrt())
+            .collect();
+        PlanetGrid {
+            centers,
+            neighbours,
+            values,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.centers.len()
+    }
+
+    /// The cell `direction` from the center is in. Walks from the nearest
+    /// of the icosahedron's corners, which come first, to whichever
+    /// neighbour is nearer until none is. The points' triangles are
+    /// Delaunay, so that ends at the nearest of them all.
+    pub fn cell_at(&self, direction: Vector3<f32>) -> usize {
+        let (mut cell, mut closeness) = self.nearest(0..12.min(self.len()), direction);
+        loop {
+            let next = self.nearest(self.neighbours[cell].iter().copied(), direction);
+            if next.1 <= closeness {
+                return cell;
+            }
+            cell = next.0;
+            closeness = next.1;
+        }
+    }
+
+    /// The one of `cells` with its center nearest `direction`, and the cosine
+    /// of the angle to it.
+    fn nearest(&self, cells: impl Iterator<Item = usize>, direction: Vector3<f32>) -> (usize, f32) {
+        cells
+            .map(|cell| (cell, self.centers[cell].dot(direction)))
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap_or((0, f32::MIN))
+    }
+
+    /// Sets each vertex's cell from the direction of its position.
+    pub fn assign(&self, vertices: &mut [Vertex]) {
+        for vertex in vertices {
+            vertex.cell = self.cell_at(Vector3::from(vertex.pos)) as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::vec3;
+
+    // Directions spread over the sphere on a golden spiral
+    fn directions(count: usize) -> impl Iterator<Item = Vector3<f32>> {
+        let golden = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+        (0..count).map(move |i| {
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let r = (1.0 - z * z).sqrt();
+            let angle = golden * i as f32;
+            vec3(r * angle.cos(), r * angle.sin(), z)
+        })
+    }
+
+    #[test]
+    fn cells_and_neighbours() {
+        for subdivisions in 0..=GRID_SUBDIVISIONS {
+            let grid = PlanetGrid::new(subdivisions);
+            assert_eq!(grid.len(), 10 * 4usize.pow(subdivisions) + 2);
+            assert_eq!(grid.values.len(), grid.len());
+            for (cell, center) in grid.centers.iter().enumerate() {
+                assert!((center.magnitude() - 1.0).abs() < 1e-5);
+                let expected = if cell < 12 { 5 } else { 6 };
+                assert_eq!(grid.neighbours[cell].len(), expected, "cell {}", cell);
+            }
+        }
+    }
+
+    #[test]
+    fn centers_are_in_their_own_cells() {
+        let grid = PlanetGrid::new(GRID_SUBDIVISIONS);
+        for (cell, &center) in grid.centers.iter().enumerate() {
+            assert_eq!(grid.cell_at(center), cell);
+        }
+    }
+
+    #[test]
+    fn cell_at_finds_the_nearest_center() {
+        for subdivisions in 0..=GRID_SUBDIVISIONS {
+            let grid = PlanetGrid::new(subdivisions);
+            for direction in directions(5000) {
+                let found = grid.centers[grid.cell_at(direction)].dot(direction);
+                let best = grid
+                    .centers
+                    .iter()
+                    .map(|center| center.dot(direction))
+                    .fold(f32::MIN, f32::max);
+                assert!(
+                    found >= best - 1e-6,
+                    "{:?} is {} from the nearest",
+                    direction,
+                    best - found
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn cell_at_ignores_the_length() {
+        let grid = PlanetGrid::new(GRID_SUBDIVISIONS);
+        for direction in directions(500) {
+            let cell = grid.cell_at(direction);
+            assert_eq!(grid.cell_at(direction * 0.65), cell);
+            assert_eq!(grid.cell_at(direction * 40.0), cell);
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::mesh::{sphere_tangent, sphere_uv};
+use crate::Vertex;
+use cgmath::{vec3, InnerSpace, Vector3};
+use std::collections::HashMap;
+
+// The icosahedron with a vertex on each pole, as rings of five around them
+pub fn icosahedron() -> (Vec<Vector3<f32>>, Vec<[u32; 3]>) {
+    let ring_z = 1.0 / 5f32.sqrt();
+    let ring_r = 2.0 / 5f32.sqrt();
+    let mut points = vec![vec3(0.0, 0.0, 1.0)];
+    for i in 0..10 {
+        // Alternating between the upper and the lower ring, a tenth of a
+        // turn apart
+        let phi = i as f32 * std::f32::consts::PI / 5.0;
+        let z = if i % 2 == 0 { ring_z } else { -ring_z };
+        points.push(vec3(ring_r * phi.cos(), ring_r * phi.sin(), z));
+    }
+    points.push(vec3(0.0, 0.0, -1.0));
+
+    let upper = |i: u32| 1 + 2 * (i % 5);
+    let lower = |i: u32| 2 + 2 * (i % 5);
+    let mut faces = Vec::new();
+    for i in 0..5 {
+        // Counter-clockwise seen from outside
+        faces.push([0, upper(i), upper(i + 1)]);
+        faces.push([upper(i), lower(i), upper(i + 1)]);
+        faces.push([upper(i + 1), lower(i), lower(i + 1)]);
+        faces.push([11, lower(i + 1), lower(i)]);
+    }
+    (points, faces)
+}
+
+/// Splits every triangle in four, with the new points pushed out onto the
+/// unit sphere.
+pub fn subdivide(points: &mut Vec<Vector3<f32>>, faces: &[[u32; 3]]) -> Vec<[u32; 3]> {
+    let mut midpoints: HashMap<(u32, u32), u32> = HashMap::new();
+    let mut midpoint = |a: u32, b: u32| {
+        let key = (a.min(b), a.max(b));
+        *midpoints.entry(key).or_insert_with(|| {
+            points.push((points[a as usize] + points[b as usize]).normalize());
+            points.len() as u32 - 1
+        })
+    };
+
+    let mut split = Vec::with_capacity(faces.len() * 4);
+    for &[a, b, c] in faces {
+        let ab = midpoint(a, b);
+        let bc = midpoint(b, c);
+        let ca = midpoint(c, a);
+        split.push([a, ab, ca]);
+        split.push([b, bc, ab]);
+        split.push([c, ca, bc]);
+        split.push([ab, bc, ca]);
+    }
+    split
+}
+
+/// A sphere of `radius` from an icosahedron split `subdivisions` times,
+/// with the same `tex` coordinates `create_sphere` has. The triangles that
+/// cross the seam at 0 longitude get copies of their vertices on the far
+/// side, and each triangle at a pole gets a pole vertex of its own, under
+/// the middle of the triangle's longitudes.
+pub fn create_icosphere(subdivisions: u32, radius: f32) -> (Vec<Vertex>, Vec<u32>) {
+    let (mut points, mut faces) = icosahedron();
+    for _ in 0..subdivisions {
+        faces = subdivide(&mut points, &faces);
+    }
+
+    let vertex = |point: Vector3<f32>, tex: [f32; 2]| Vertex {
+        pos: (point * radius).into(),
+        normal: point.into(),
+        tex,
+        tangent: sphere_tangent(tex[0]),
+        cell: 0,
+    };
+    let mut vertices: Vec<Vertex> = points
+        .iter()
+        .map(|&point| vertex(point, sphere_uv(point)))
+        .collect();
+    let is_pole = |point: Vector3<f32>| point.z.abs() > 1.0 - 1e-6;
+
+    // The copy of each vertex one turn further east
+    let mut wrapped: HashMap<u32, u32> = HashMap::new();
+    let mut indices = Vec::with_capacity(faces.len() * 3);
+    for face in &mut faces {
+        let (poles, others): (Vec<usize>, Vec<usize>) =
+            (0..3).partition(|&k| is_pole(points[face[k] as usize]));
+
+        let mut u = [0.0; 3];
+        for k in 0..3 {
+            u[k] = vertices[face[k] as usize].tex[0];
+        }
+        let min = others.iter().map(|&k| u[k]).fold(f32::MAX, f32::min);
+        let max = others.iter().map(|&k| u[k]).fold(f32::MIN, f32::max);
+        if max - min > 0.5 {
+            for &k in &others {
+                if u[k] >= 0.5 {
+                    continue;
+                }
+                let index = face[k];
+                face[k] = *wrapped.entry(index).or_insert_with(|| {
+                    let mut copy = vertices[index as usize];
+                    copy.tex[0] += 1.0;
+                    copy.tangent = sphere_tangent(copy.tex[0]);
+                    vertices.push(copy);
+                    vertices.len() as u32 - 1
+                });
+                u[k] += 1.0;
+            }
+        }
+
+        for &k in &poles {
+            let mut pole = vertices[face[k] as usize];
+            pole.tex[0] =
+                others.iter().map(|&other| u[other]).sum::<f32>() / others.len().max(1) as f32;
+            pole.tangent = sphere_tangent(pole.tex[0]);
+            vertices.push(pole);
+            face[k] = vertices.len() as u32 - 1;
+        }
+
+        indices.extend_from_slice(face);
+    }
+
+    (vertices, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RADIUS: f32 = 0.65;
+
+    fn pos(vertices: &[Vertex], index: u32) -> Vector3<f32> {
+        Vector3::from(vertices[index as usize].pos)
+    }
+
+    #[test]
+    fn subdivided_counts() {
+        for n in 0..=5 {
+            let (mut points, mut faces) = icosahedron();
+            for _ in 0..n {
+                faces = subdivide(&mut points, &faces);
+            }
+            let split = 4usize.pow(n);
+            assert_eq!(points.len(), 10 * split + 2);
+            assert_eq!(faces.len(), 20 * split);
+            assert!(points
+                .iter()
+                .all(|point| (point.magnitude() - 1.0).abs() < 1e-6));
+        }
+    }
+
+    #[test]
+    fn icosphere_vertex_count() {
+        for n in 0..=5 {
+            let (vertices, indices) = create_icosphere(n, RADIUS);
+            let points = 10 * 4usize.pow(n) + 2;
+            assert_eq!(indices.len(), 3 * 20 * 4usize.pow(n));
+
+            // The points, then a copy of each one the seam goes past, then
+            // one pole vertex for each of the five triangles at each pole
+            let is_pole = |vertex: &Vertex| vertex.pos[2].abs() > RADIUS * (1.0 - 1e-6);
+            let copies = vertices[points..]
+                .iter()
+                .filter(|vertex| !is_pole(vertex) && vertex.tex[0] >= 1.0)
+                .count();
+            assert_eq!(vertices.len(), points + copies + 10);
+            let poles = vertices[points..]
+                .iter()
+                .filter(|vertex| is_pole(vertex))
+                .count();
+            assert_eq!(poles, 10);
+            for index in points as u32..vertices.len() as u32 {
+                let uses = indices.iter().filter(|&&i| i == index).count();
+                if is_pole(&vertices[index as usize]) {
+                    assert_eq!(uses, 1);
+                } else {
+                    assert!(uses > 0);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn icosphere_winds_counter_clockwise_from_outside() {
+        for n in 0..=4 {
+            let (vertices, indices) = create_icosphere(n, RADIUS);
+            for triangle in indices.chunks(3) {
+                let (a, b, c) = (
+                    pos(&vertices, triangle[0]),
+                    pos(&vertices, triangle[1]),
+                    pos(&vertices, triangle[2]),
+                );
+                assert!((b - a).cross(c - a).dot(a + b + c) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn icosphere_triangles_do_not_stretch_over_the_seam() {
+        for n in 0..=4 {
+            let (vertices, indices) = create_icosphere(n, RADIUS);
+            for triangle in indices.chunks(3) {
+                let u = |k: usize| vertices[triangle[k] as usize].tex[0];
+                let span = u(0).max(u(1)).max(u(2)) - u(0).min(u(1)).min(u(2));
+                assert!(span <= 0.5, "{} across", span);
+            }
+            assert!(vertices
+                .iter()
+                .all(|vertex| { (Vector3::from(vertex.pos).magnitude() - RADIUS).abs() < 1e-5 }));
+        }
+    }
+}
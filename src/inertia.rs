@@ -0,0 +1,152 @@
+use cgmath::{InnerSpace, Quaternion, Rad, Rotation3, Vector3, Zero};
+
+/// This frame's share of the camera and planet movement asked for.
+pub struct InertiaStep {
+    // Degrees of orbit yaw and pitch
+    pub yaw: f32,
+    pub pitch: f32,
+    // Scroll wheel notches
+    pub zoom: f32,
+    // Planet turn in world space, then its spin around the pole in degrees
+    pub turn: Quaternion<f32>,
+    pub spin: f32,
+}
+
+/// Eases the camera and the planet towards where the input asked for
+/// rather than jumping there. Input adds to what is still left to go, and
+/// every frame takes a share of that, the same share per second at any
+/// frame rate. A planet let go of mid-drag keeps turning and slows down.
+pub struct Inertia {
+    pub enabled: bool,
+    // Per second, the higher the more direct
+    pub damping: f32,
+    // Per second, how fast a let go planet slows down
+    pub spin_decay: f32,
+
+    yaw: f32,
+    pitch: f32,
+    zoom: f32,
+    turn: Quaternion<f32>,
+    spin: f32,
+
+    // Turn asked for since the last step, and the planet's angular
+    // velocity in radians per second around its axis
+    frame_turn: Quaternion<f32>,
+    velocity: Vector3<f32>,
+}
+
+fn identity() -> Quaternion<f32> {
+    Quaternion::new(1.0, 0.0, 0.0, 0.0)
+}
+
+/// Axis scaled by the angle in radians.
+fn rotation_vector(rotation: Quaternion<f32>) -> Vector3<f32> {
+    // The short way round
+    let rotation = if rotation.s < 0.0 {
+        -rotation
+    } else {
+        rotation
+    };
+    let sin = rotation.v.magnitude();
+    if sin < 1e-7 {
+        return Vector3::zero();
+    }
+    rotation.v / sin * 2.0 * sin.atan2(rotation.s)
+}
+
+fn from_rotation_vector(vector: Vector3<f32>) -> Quaternion<f32> {
+    let angle = vector.magnitude();
+    if angle < 1e-7 {
+        return identity();
+    }
+    Quaternion::from_axis_angle(vector / angle, Rad(angle))
+}
+
+impl Inertia {
+    pub fn new() -> Inertia {
+        Inertia {
+            enabled: true,
+            damping: 12.0,
+            spin_decay: 1.5,
+            yaw: 0.0,
+            pitch: 0.0,
+            zoom: 0.0,
+            turn: identity(),
+            spin: 0.0,
+            frame_turn: identity(),
+            velocity: Vector3::zero(),
+        }
+    }
+
+    pub fn add_orbit(&mut self, yaw: f32, pitch: f32) {
+        self.yaw += yaw;
+        self.pitch += pitch;
+    }
+
+    pub fn add_zoom(&mut self, notches: f32) {
+        self.zoom += notches;
+    }
+
+    pub fn add_turn(&mut self, turn: Quaternion<f32>) {
+        self.turn = (turn * self.turn).normalize();
+        self.frame_turn = (turn * self.frame_turn).normalize();
+    }
+
+    pub fn add_spin(&mut self, degrees: f32) {
+        self.spin += degrees;
+    }
+
+    /// Drops whatever is left to go, for when something else takes over.
+    pub fn stop(&mut self) {
+        *self = Inertia {
+            enabled: self.enabled,
+            damping: self.damping,
+            spin_decay: self.spin_decay,
+            ..Inertia::new()
+        };
+    }
+
+    /// Takes the share of the movement due over `dt` seconds. While
+    /// `dragging` the planet, its speed is measured to keep it turning once
+    /// it is let go.
+    pub fn step(&mut self, dt: f32, dragging: bool) -> InertiaStep {
+        let frame_turn = std::mem::replace(&mut self.frame_turn, identity());
+
+        let share = if self.enabled {
+            1.0 - (-self.damping * dt).exp()
+        } else {
+            1.0
+        };
+
+        if !self.enabled {
+            self.velocity = Vector3::zero();
+        } else if dragging {
+            if dt > 0.0 {
+                self.velocity = rotation_vector(frame_turn) / dt;
+            }
+        } else if self.velocity.magnitude2() > 0.0 {
+            self.turn = (from_rotation_vector(self.velocity * dt) * self.turn).normalize();
+            self.velocity *= (-self.spin_decay * dt).exp();
+            if self.velocity.magnitude() < 1e-4 {
+                self.velocity = Vector3::zero();
+            }
+        }
+
+        let turn = from_rotation_vector(rotation_vector(self.turn) * share);
+        // The turn and its share are around the same axis, so they commute
+        self.turn = (self.turn * turn.conjugate()).normalize();
+
+        let step = InertiaStep {
+            yaw: self.yaw * share,
+            pitch: self.pitch * share,
+            zoom: self.zoom * share,
+            turn,
+            spin: self.spin * share,
+        };
+        self.yaw -= step.yaw;
+        self.pitch -= step.pitch;
+        self.zoom -= step.zoom;
+        self.spin -= step.spin;
+        step
+    }
+}
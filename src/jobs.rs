@@ -0,0 +1,242 @@
+use crate::grid::PlanetGrid;
+use crate::mesh::{build_levels, Displacement, LevelMesh, PlanetMesh};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// A planet mesh to build, tagged with the generation it was asked for in.
+pub struct MeshRequest {
+    pub generator: PlanetMesh,
+    pub detail: i32,
+    pub displacement: Displacement,
+    pub flattening: f32,
+    pub generation_id: u64,
+}
+
+/// The levels built for one request, still on the CPU.
+pub struct MeshResult {
+    pub generator: PlanetMesh,
+    pub detail: i32,
+    pub displacement: Displacement,
+    pub flattening: f32,
+    pub generation_id: u64,
+    pub levels: Vec<LevelMesh>,
+}
+
+/// Builds planet meshes on a thread that lives as long as it does. Only the
+/// result of the latest request is handed back, anything older is skipped
+/// by the thread if it hasn't started on it yet and dropped here if it has.
+pub struct MeshWorker {
+    requests: Option<Sender<MeshRequest>>,
+    results: Receiver<MeshResult>,
+    thread: Option<JoinHandle<()>>,
+    // The generation of the last request, and of the last one finished or
+    // given up on
+    latest: u64,
+    finished: u64,
+    requested_at: Instant,
+}
+
+impl MeshWorker {
+    pub fn new(grid: Arc<PlanetGrid>) -> MeshWorker {
+        let (requests, request_receiver) = mpsc::channel::<MeshRequest>();
+        let (result_sender, results) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            while let Ok(mut request) = request_receiver.recv() {
+                // Skip to the newest of whatever queued up during the last build
+                while let Ok(newer) = request_receiver.try_recv() {
+                    request = newer;
+                }
+                let levels = build_levels(
+                    request.generator,
+                    request.detail,
+                    request.displacement,
+                    request.flattening,
+                    &grid,
+                );
+                let result = MeshResult {
+                    generator: request.generator,
+                    detail: request.detail,
+                    displacement: request.displacement,
+                    flattening: request.flattening,
+                    generation_id: request.generation_id,
+                    levels,
+                };
+                if result_sender.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        MeshWorker {
+            requests: Some(requests),
+            results,
+            thread: Some(thread),
+            latest: 0,
+            finished: 0,
+            requested_at: Instant::now(),
+        }
+    }
+
+    /// Asks for a build, making everything asked for before it stale.
+    /// Returns its generation.
+    pub fn submit(
+        &mut self,
+        generator: PlanetMesh,
+        detail: i32,
+        displacement: Displacement,
+        flattening: f32,
+    ) -> u64 {
+        self.latest += 1;
+        let request = MeshRequest {
+            generator,
+            detail,
+            displacement,
+            flattening,
+            generation_id: self.latest,
+        };
+        let sent = self
+            .requests
+            .as_ref()
+            .is_some_and(|requests| requests.send(request).is_ok());
+        if sent {
+            self.requested_at = Instant::now();
+        } else {
+            // The thread is gone, there is nothing to wait for
+            self.finished = self.latest;
+        }
+        self.latest
+    }
+
+    /// Makes whatever is being built stale without asking for anything new.
+    pub fn cancel(&mut self) {
+        self.latest += 1;
+        self.finished = self.latest;
+    }
+
+    /// Whether the latest request is still being built.
+    pub fn is_pending(&self) -> bool {
+        self.finished != self.latest
+    }
+
+    /// When the latest request was made.
+    pub fn requested_at(&self) -> Instant {
+        self.requested_at
+    }
+
+    /// The result of the latest request, once it is done. Stale results are
+    /// dropped.
+    pub fn poll(&mut self) -> Option<MeshResult> {
+        let mut newest = None;
+        loop {
+            match self.results.try_recv() {
+                Ok(result) => {
+                    if result.generation_id == self.latest {
+                        self.finished = self.latest;
+                        newest = Some(result);
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.finished = self.latest;
+                    break;
+                }
+            }
+        }
+        newest
+    }
+}
+
+impl Drop for MeshWorker {
+    /// Closes the channel, which ends the thread once it finishes any build
+    /// it is in, and waits for it.
+    fn drop(&mut self) {
+        self.requests = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn worker() -> MeshWorker {
+        MeshWorker::new(Arc::new(PlanetGrid::new(1)))
+    }
+
+    fn submit(worker: &mut MeshWorker, detail: i32) -> u64 {
+        worker.submit(PlanetMesh::UvSphere, detail, Displacement::new(), 0.0)
+    }
+
+    // Everything poll hands back until the latest request is done
+    fn wait(worker: &mut MeshWorker) -> Vec<MeshResult> {
+        let start = Instant::now();
+        let mut results = Vec::new();
+        while worker.is_pending() {
+            assert!(start.elapsed() < Duration::from_secs(30), "never finished");
+            results.extend(worker.poll());
+            thread::sleep(Duration::from_millis(1));
+        }
+        results
+    }
+
+    #[test]
+    fn returns_what_was_asked_for() {
+        let mut worker = worker();
+        assert!(!worker.is_pending());
+        let generation = submit(&mut worker, 12);
+        assert!(worker.is_pending());
+
+        let results = wait(&mut worker);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].generation_id, generation);
+        assert_eq!(results[0].detail, 12);
+        assert_eq!(results[0].levels.len(), 4);
+        assert!(worker.poll().is_none());
+    }
+
+    #[test]
+    fn only_the_latest_generation_is_returned() {
+        let mut worker = worker();
+        let generations: Vec<u64> = (1..=20)
+            .map(|detail| submit(&mut worker, detail * 8))
+            .collect();
+        assert!(generations.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let results = wait(&mut worker);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].generation_id, *generations.last().unwrap());
+        assert_eq!(results[0].detail, 160);
+    }
+
+    #[test]
+    fn cancel_drops_the_build() {
+        let mut worker = worker();
+        submit(&mut worker, 64);
+        worker.cancel();
+        assert!(!worker.is_pending());
+        // Long enough for the build to finish and be thrown away
+        for _ in 0..200 {
+            assert!(worker.poll().is_none());
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // And what is asked for after still comes back
+        let generation = submit(&mut worker, 16);
+        let results = wait(&mut worker);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].generation_id, generation);
+    }
+
+    #[test]
+    fn drop_waits_for_the_build() {
+        let mut worker = worker();
+        submit(&mut worker, 256);
+        drop(worker);
+    }
+}
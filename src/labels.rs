@@ -0,0 +1,272 @@
+use crate::noise;
+use cgmath::{vec3, Vector3};
+use glium::{
+    backend::Facade,
+    implement_vertex,
+    texture::{texture2d::Texture2d, MipmapsOption, RawImage2d},
+    VertexBuffer,
+};
+use imgui::{sys, ImGui, ImString};
+use std::collections::HashMap;
+use std::error;
+
+// Height above the terrain surface labels are lifted to, in render units,
+// so the billboard doesn't cut into the ground it names
+const SURFACE_LIFT: f32 = 0.004;
+
+const MAX_TEXT: usize = 64;
+
+#[derive(Copy, Clone)]
+pub struct LabelVertex {
+    label_anchor: [f32; 3],
+    label_offset: [f32; 2],
+    label_tex: [f32; 2],
+}
+implement_vertex!(LabelVertex, label_anchor, label_offset, label_tex);
+
+// Layout of imgui-sys's ImFontGlyph, whose fields aren't public
+#[repr(C)]
+#[allow(dead_code)]
+struct RawGlyph {
+    codepoint: sys::ImWchar,
+    advance_x: f32,
+    x0: f32,
+    y0: f32,
+    x1: f32,
+    y1: f32,
+    u0: f32,
+    v0: f32,
+    u1: f32,
+    v1: f32,
+}
+
+#[derive(Copy, Clone)]
+struct Glyph {
+    advance: f32,
+    // Quad relative to the pen on the top of the line, in font pixels with
+    // y down, and where it is in the atlas
+    rect: [f32; 4],
+    uv: [f32; 4],
+}
+
+/// A copy of imgui's font atlas texture with the metrics of the printable
+/// ASCII glyphs of its first font.
+pub struct FontAtlas {
+    pub texture: Texture2d,
+    pub line_height: f32,
+    glyphs: HashMap<char, Glyph>,
+}
+
+impl FontAtlas {
+    /// Needs the fonts built, which the imgui renderer does when it is
+    /// created.
+    pub fn from_imgui<F: Facade>(
+        facade: &F,
+        imgui: &mut ImGui,
+    ) -> Result<FontAtlas, Box<dyn error::Error>> {
+        let texture = imgui.prepare_texture(|handle| {
+            let image =
+                RawImage2d::from_raw_rgba(handle.pixels.to_vec(), (handle.width, handle.height));
+            Texture2d::with_mipmaps(facade, image, MipmapsOption::AutoGeneratedMipmaps)
+        })?;
+
+        let mut glyphs = HashMap::new();
+        let line_height;
+        unsafe {
+            let atlas = (*sys::igGetIO()).fonts;
+            if sys::ImFontAtlas_Fonts_size(atlas) == 0 {
+                return Err("imgui has no font to label with".into());
+            }
+            let font = sys::ImFontAtlas_Fonts_index(atlas, 0);
+            line_height = sys::ImFont_GetFontSize(font);
+
+            for c in (32u8..127).map(char::from) {
+                let glyph = sys::ImFont_FindGlyph(font, c as sys::ImWchar) as *const RawGlyph;
+                if let Some(glyph) = glyph.as_ref() {
+                    glyphs.insert(
+                        c,
+                        Glyph {
+                            advance: glyph.advance_x,
+                            rect: [glyph.x0, glyph.y0, glyph.x1, glyph.y1],
+                            uv: [glyph.u0, glyph.v0, glyph.u1, glyph.v1],
+                        },
+                    );
+                }
+            }
+        }
+
+        Ok(FontAtlas {
+            texture,
+            line_height,
+            glyphs,
+        })
+    }
+
+    /// Quads for `text` centered on the origin, in font pixels with y up.
+    /// The atlas has no kerning pairs, so the pen only moves by each
+    /// glyph's advance.
+    pub fn layout(&self, text: &str, anchor: [f32; 3], vertices: &mut Vec<LabelVertex>) {
+        let fallback = self.glyphs.get(&'?').cloned();
+        let glyphs: Vec<Glyph> = text
+            .chars()
+            .filter_map(|c| self.glyphs.get(&c).cloned().or(fallback))
+            .collect();
+
+        let width: f32 = glyphs.iter().map(|glyph| glyph.advance).sum();
+        let mut pen = -width / 2.0;
+        let top = self.line_height / 2.0;
+
+        for glyph in glyphs {
+            let [x0, y0, x1, y1] = glyph.rect;
+            let [u0, v0, u1, v1] = glyph.uv;
+            let corner = |x: f32, y: f32, u: f32, v: f32| LabelVertex {
+                label_anchor: anchor,
+                label_offset: [pen + x, top - y],
+                label_tex: [u, v],
+            };
+
+            vertices.extend_from_slice(&[
+                corner(x0, y1, u0, v1),
+                corner(x1, y1, u1, v1),
+                corner(x1, y0, u1, v0),
+                corner(x0, y1, u0, v1),
+                corner(x1, y0, u1, v0),
+                corner(x0, y0, u0, v0),
+            ]);
+            pen += glyph.advance;
+        }
+    }
+}
+
+pub struct Label {
+    pub text: ImString,
+    pub latitude: f32,
+    pub longitude: f32,
+    // Zero puts the label on the terrain
+    pub altitude_km: f32,
+    // Drawn into the scene, occluded by the planet, rather than as an overlay
+    pub world_space: bool,
+}
+
+impl Label {
+    pub fn new(text: &str, latitude: f32, longitude: f32, altitude_km: f32) -> Label {
+        let mut label = ImString::with_capacity(MAX_TEXT);
+        label.push_str(text);
+        Label {
+            text: label,
+            latitude,
+            longitude,
+            altitude_km,
+            world_space: true,
+        }
+    }
+
+    /// Position in planet-local space, `altitude` being the label's altitude
+    /// in render units.
+    pub fn anchor(&self, radius: f32, altitude: f32) -> Vector3<f32> {
+        let (lat, lon) = (self.latitude.to_radians(), self.longitude.to_radians());
+        let up = vec3(lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos());
+        if self.altitude_km > 0.0 {
+            up * (radius + altitude)
+        } else {
+            up * (noise::terrain_altitude([up.x, up.y, up.z], radius) + SURFACE_LIFT)
+        }
+    }
+}
+
+pub struct LabelSettings {
+    pub enabled: bool,
+    // Height of a line of text in km, before the pixel size limits
+    pub size_km: f32,
+    pub min_pixels: f32,
+    pub max_pixels: f32,
+    pub fade_distance_km: f32,
+    pub color: [f32; 4],
+}
+
+/// Names placed on the planet and in orbit around it. The world-space ones
+/// share one vertex buffer of glyph quads, billboarded in the vertex
+/// shader, that is only rebuilt when a label changes.
+pub struct Labels {
+    pub settings: LabelSettings,
+    pub labels: Vec<Label>,
+    pub atlas: Option<FontAtlas>,
+    pub vertex_buffer: Option<VertexBuffer<LabelVertex>>,
+    pub vertex_count: usize,
+    dirty: bool,
+}
+
+impl Labels {
+    pub fn new() -> Labels {
+        Labels {
+            settings: LabelSettings {
+                enabled: true,
+                size_km: 400.0,
+                min_pixels: 10.0,
+                max_pixels: 28.0,
+                fade_distance_km: 60_000.0,
+                color: [1.0, 1.0, 1.0, 0.9],
+            },
+            labels: vec![
+                Label::new("North Pole", 90.0, 0.0, 0.0),
+                Label::new("South Pole", -90.0, 0.0, 0.0),
+                Label::new("Prime meridian", 0.0, 0.0, 0.0),
+                Label::new("Low orbit", 0.0, 90.0, 2000.0),
+            ],
+            atlas: None,
+            vertex_buffer: None,
+            vertex_count: 0,
+            dirty: true,
+        }
+    }
+
+    /// Marks the glyph quads as stale, after a label or the scale changed.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Rebuilds the quads of the world-space labels if anything changed.
+    /// `to_render` converts km to render units.
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        radius: f32,
+        to_render: impl Fn(f32) -> f32,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let atlas = match self.atlas {
+            Some(ref atlas) if self.dirty => atlas,
+            _ => return Ok(()),
+        };
+        self.dirty = false;
+
+        let mut vertices = Vec::new();
+        for label in self.labels.iter().filter(|label| label.world_space) {
+            let anchor = label.anchor(radius, to_render(label.altitude_km));
+            atlas.layout(label.text.to_str(), anchor.into(), &mut vertices);
+        }
+
+        self.vertex_count = vertices.len();
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        let fits = self
+            .vertex_buffer
+            .as_ref()
+            .is_some_and(|buffer| buffer.len() >= vertices.len());
+        if !fits {
+            self.vertex_buffer = Some(VertexBuffer::empty_dynamic(
+                facade,
+                vertices.len().next_power_of_two(),
+            )?);
+        }
+        if let Some(ref buffer) = self.vertex_buffer {
+            buffer
+                .slice(0..vertices.len())
+                .ok_or("label vertices out of range")?
+                .write(&vertices);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,55 @@
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Sleeping can overshoot by about a scheduler tick, the last of the wait is
+// spun instead
+const SPIN_TIME: Duration = Duration::from_millis(2);
+
+/// Holds the frame rate down to `target_fps` by waiting out what is left of
+/// each frame's budget on the CPU.
+pub struct FrameLimiter {
+    pub enabled: bool,
+    pub target_fps: f32,
+    // When the frame being limited started
+    frame_start: Instant,
+    // How long the last frame waited, none when it ran over its budget
+    waited: Duration,
+}
+
+impl FrameLimiter {
+    pub fn new() -> FrameLimiter {
+        FrameLimiter {
+            enabled: false,
+            target_fps: 60.0,
+            frame_start: Instant::now(),
+            waited: Duration::from_secs(0),
+        }
+    }
+
+    /// Whether the limiter rather than the frame's work set the last frame's
+    /// length.
+    pub fn is_limiting(&self) -> bool {
+        self.waited > Duration::from_secs(0)
+    }
+
+    /// Waits until the frame has taken its share of a second, then starts
+    /// timing the next one. `active` is false while something needs frames
+    /// as fast as they come.
+    pub fn wait(&mut self, active: bool) {
+        self.waited = Duration::from_secs(0);
+        if self.enabled && active && self.target_fps > 0.0 {
+            let deadline = self.frame_start + Duration::from_secs_f32(1.0 / self.target_fps);
+            let now = Instant::now();
+            if deadline > now {
+                self.waited = deadline - now;
+                if deadline - now > SPIN_TIME {
+                    thread::sleep(deadline - now - SPIN_TIME);
+                }
+                while Instant::now() < deadline {
+                    std::hint::spin_loop();
+                }
+            }
+        }
+        self.frame_start = Instant::now();
+    }
+}
@@ -0,0 +1,181 @@
+use crate::polyline::PolylineBatch;
+use cgmath::{vec3, Deg, InnerSpace, Matrix3, Vector3};
+use glium::backend::Facade;
+use std::error;
+
+const POINTS_PER_LINE: usize = 64;
+
+pub struct MagnetosphereSettings {
+    pub enabled: bool,
+    pub shells: i32,
+    pub lines_per_shell: i32,
+    pub max_shell: f32,
+    pub axis_tilt: f32,
+    pub axis_azimuth: f32,
+    pub compression: f32,
+    pub tail: f32,
+    pub color: [f32; 4],
+    pub line_width: f32,
+}
+
+impl MagnetosphereSettings {
+    pub fn new() -> MagnetosphereSettings {
+        MagnetosphereSettings {
+            enabled: false,
+            shells: 4,
+            lines_per_shell: 12,
+            max_shell: 8.0,
+            axis_tilt: 11.0,
+            axis_azimuth: 0.0,
+            compression: 0.4,
+            tail: 2.0,
+            color: [0.4, 0.6, 1.0, 0.35],
+            line_width: 1.5,
+        }
+    }
+}
+
+/// Dipole field lines around the planet, in planet radii. The lines are
+/// traced once per parameter change, the sunward compression and the tail
+/// are applied in the vertex shader from the live sun direction.
+pub struct Magnetosphere {
+    pub settings: MagnetosphereSettings,
+    pub lines: PolylineBatch,
+    dirty: bool,
+}
+
+impl Magnetosphere {
+    pub fn new() -> Magnetosphere {
+        Magnetosphere {
+            settings: MagnetosphereSettings::new(),
+            lines: PolylineBatch::new(),
+            dirty: true,
+        }
+    }
+
+    pub fn regenerate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Retraces the field lines if the shape parameters changed.
+    pub fn update<F: Facade>(&mut self, facade: &F) -> Result<(), Box<dyn error::Error>> {
+        if !self.dirty || !self.settings.enabled {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        trace_field_lines(&self.settings, &mut self.lines);
+        self.lines.upload(facade)
+    }
+}
+
+/// Dipole field lines r = L cos²(latitude) in the magnetic frame, from one
+/// footpoint on the surface to the other. The outer shells are fainter and
+/// every line fades out right at its footpoints.
+fn trace_field_lines(settings: &MagnetosphereSettings, lines: &mut PolylineBatch) {
+    let shells = settings.shells.max(1) as usize;
+    let lines_per_shell = settings.lines_per_shell.max(1) as usize;
+    let max_shell = settings.max_shell.max(1.5);
+
+    let axis = Matrix3::from_angle_y(Deg(settings.axis_azimuth))
+        * Matrix3::from_angle_x(Deg(settings.axis_tilt));
+
+    lines.clear();
+    for shell in 0..shells {
+        let t = if shells > 1 {
+            shell as f32 / (shells - 1) as f32
+        } else {
+            1.0
+        };
+        let l = 1.5 + (max_shell - 1.5) * t;
+
+        for line in 0..lines_per_shell {
+            let longitude = 2.0 * std::f32::consts::PI * line as f32 / lines_per_shell as f32;
+
+            let points: Vec<_> = trace_line(l, longitude)
+                .into_iter()
+                .map(|local| {
+                    let height = ((local.magnitude() - 1.0) / 0.2).clamp(0.0, 1.0);
+                    let alpha = (1.0 - 0.5 * t) * height * height * (3.0 - 2.0 * height);
+                    (axis * local, [1.0, 1.0, 1.0, alpha])
+                })
+                .collect();
+            lines.push(&points);
+        }
+    }
+}
+
+/// The line of shell `l` at `longitude` radians in the magnetic frame, with
+/// the magnetic pole along y, from its southern footpoint to its northern.
+fn trace_line(l: f32, longitude: f32) -> Vec<Vector3<f32>> {
+    // Latitude where the line meets the surface, r = 1
+    let footpoint = (1.0 / l).sqrt().acos();
+
+    (0..=POINTS_PER_LINE)
+        .map(|i| {
+            let latitude = -footpoint + 2.0 * footpoint * i as f32 / POINTS_PER_LINE as f32;
+            let r = l * latitude.cos() * latitude.cos();
+            vec3(
+                r * latitude.cos() * longitude.cos(),
+                r * latitude.sin(),
+                r * latitude.cos() * longitude.sin(),
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHELLS: [f32; 5] = [1.5, 2.0, 3.0, 4.5, 8.0];
+    const LONGITUDES: [f32; 4] = [0.0, 1.0, 3.5, 5.9];
+
+    #[test]
+    fn lines_are_symmetric_about_the_magnetic_equator() {
+        for &l in &SHELLS {
+            for &longitude in &LONGITUDES {
+                let points = trace_line(l, longitude);
+                assert_eq!(points.len(), POINTS_PER_LINE + 1);
+                for (south, north) in points.iter().zip(points.iter().rev()) {
+                    let mirrored = vec3(north.x, -north.y, north.z);
+                    assert!((south - mirrored).magnitude() < 1e-5 * l);
+                }
+                // Crossing the equator at the top of the shell
+                let apex = points[POINTS_PER_LINE / 2];
+                assert!(apex.y.abs() < 1e-5);
+                assert!((apex.magnitude() - l).abs() < 1e-5 * l);
+            }
+        }
+    }
+
+    #[test]
+    fn lines_close_on_the_surface() {
+        for &l in &SHELLS {
+            for &longitude in &LONGITUDES {
+                let points = trace_line(l, longitude);
+                let (south, north) = (points[0], points[POINTS_PER_LINE]);
+                assert!((south.magnitude() - 1.0).abs() < 1e-5);
+                assert!((north.magnitude() - 1.0).abs() < 1e-5);
+                assert!(south.y < 0.0 && north.y > 0.0);
+                // Never below the surface in between
+                assert!(points.iter().all(|point| point.magnitude() > 1.0 - 1e-5));
+            }
+        }
+    }
+
+    #[test]
+    fn lines_follow_the_dipole_field() {
+        // B ~ 3 (m.r) r / |r|^5 - m / |r|^3, with m along -y
+        let m = vec3(0.0, -1.0, 0.0);
+        for &l in &SHELLS {
+            for pair in trace_line(l, 1.0).windows(2) {
+                let r = (pair[0] + pair[1]) * 0.5;
+                let d = r.magnitude();
+                let field = r * (3.0 * m.dot(r) / d.powi(5)) - m / d.powi(3);
+                let step = pair[1] - pair[0];
+                assert!(step.normalize().dot(field.normalize()).abs() > 0.999);
+            }
+        }
+    }
+}
@@ -1,82 +1,306 @@
+// glium's implement_vertex! expands to the deprecated mem::uninitialized
+#![allow(deprecated)]
+
+use actions::Action;
+use benchmark::Benchmark;
+use bindings::{Control, InputBindings};
+use bodies::{BodyShader, ExtraBodies};
+use bookmarks::{Bookmarks, ViewPose};
+use camera::Camera;
+use capabilities::GlCapabilities;
+use celestial::{Celestial, SunPlace};
 use cgmath::{
     conv::{array3, array4x4},
-    ortho, perspective, vec3, Deg, InnerSpace, Matrix4, Point3, Vector3,
+    ortho, vec3, Deg, EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, Quaternion,
+    SquareMatrix, Vector2, Vector3, Vector4,
 };
+use chunks::ChunkedPlanet;
+use clock::Clock;
+use cloud_target::{CloudResolution, CloudTarget};
+use colors::ColorPalette;
+use detail::DetailSettings;
+use entry::EntryEffects;
+use frame::{FrameBlock, FrameUniforms, PassParameters};
+use freefly::{FlyKeys, FreeFly};
+use gamepad::Gamepad;
+use gestures::Gestures;
 use glium::glutin::{dpi::LogicalPosition, Api, GlProfile, GlRequest};
 use glium::{
     backend::Facade,
-    draw_parameters::{BackfaceCullingMode, Blend},
     framebuffer::{DepthRenderBuffer, SimpleFrameBuffer},
     glutin, implement_vertex,
     index::PrimitiveType,
     texture::{texture2d::Texture2d, DepthFormat, MipmapsOption, UncompressedFloatFormat},
     uniform,
-    uniforms::Sampler,
-    Depth, DepthTest, Display, DrawParameters, Program, Surface,
+    uniforms::{Sampler, SamplerWrapFunction, UniformBuffer, Uniforms},
+    Display, DrawParameters, Program, Surface,
 };
-use imgui::{im_str, FrameSize, ImGui, ImGuiCond, ImGuiKey, Ui};
+use gpu_timer::PassTimers;
+use imgui::{im_str, FrameSize, ImGui, ImGuiCol, ImGuiCond, ImGuiKey, ImStr, ImString, Ui};
+use inertia::Inertia;
+use labels::{FontAtlas, Label, Labels};
+use limiter::FrameLimiter;
+use magnetosphere::Magnetosphere;
+use mesh::{sphere_uv, PlanetGeometry, PlanetMesh};
+use meteors::Meteors;
+use mouselook::MouseLook;
+use nbody::NBody;
+use occlusion::PassOcclusion;
+use orbit::SATELLITES;
+use palette::CommandPalette;
+use polyline::PolylineVertex;
+use quality::Quality;
 use rand::distributions::{Distribution, UnitSphereSurface};
+use rand::Rng;
+use recording::Recording;
+use ring::Ring;
+use satellite::{SatelliteState, SatelliteView};
+use screenshot::ScreenshotWriter;
+use session::Session;
+use shell::{AtmosphereShell, ATMOSPHERE_AFTER_CLOUDS};
+use skybox::Skybox;
+use spacecraft::Spacecraft;
+use spin::{Spin, SunCycle};
+use starfield::Starfield;
 use std::borrow::Cow;
 use std::cmp::max;
+use std::env;
 use std::error;
-use std::f32::consts::PI;
 use std::fs;
-use std::time::{Instant, SystemTime};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use stream::StreamPool;
+use stutter::{Span, StutterDetector};
+use sun::Sun;
+use surface::SurfaceView;
+use timelapse::TimeLapse;
+use units::{Atmosphere, Preset, SceneScale, PRESETS};
+use vegetation::Vegetation;
+use wind::{Wind, WindCurve};
+use workspace::Workspaces;
+
+mod actions;
+mod arcball;
+mod benchmark;
+mod bindings;
+mod bodies;
+mod bookmarks;
+mod camera;
+mod capabilities;
+mod capture;
+mod celestial;
+mod chunks;
+mod clock;
+mod cloud_target;
+mod colors;
+mod craters;
+mod cubesphere;
+mod detail;
+mod entry;
+mod frame;
+mod freefly;
+mod gamepad;
+mod gestures;
+mod gltf;
+mod gpu_timer;
+mod grid;
+mod icosphere;
+mod inertia;
+mod jobs;
+mod json;
+mod labels;
+mod limiter;
+mod magnetosphere;
+mod math;
+mod mesh;
+mod meteors;
+mod mouselook;
+mod nbody;
+mod noise;
+mod obj;
+mod occlusion;
+mod orbit;
+mod palette;
+mod polyline;
+mod quality;
+mod recording;
+mod resample;
+mod ring;
+mod satellite;
+mod scaling;
+mod screenshot;
+mod session;
+mod shell;
+mod skybox;
+mod spacecraft;
+mod spin;
+mod starfield;
+mod stream;
+mod stutter;
+mod sun;
+mod surface;
+mod sweep;
+mod timelapse;
+mod units;
+mod vegetation;
+mod wind;
+mod workspace;
+mod zip;
 
 #[derive(Copy, Clone, Default)]
 struct Vertex {
     pos: [f32; 3],
     normal: [f32; 3],
     tex: [f32; 2],
+    // The direction u grows along the surface, w is the sign that turns
+    // normal x tangent into the direction v grows
+    tangent: [f32; 4],
+    // The geodesic grid cell the vertex is in
+    cell: u32,
 }
-implement_vertex!(Vertex, pos, normal, tex);
-
-#[derive(Copy, Clone, Default)]
-struct Triangle {
-    ind: [i32; 3],
-}
+implement_vertex!(Vertex, pos, normal, tex, tangent, cell);
 
 #[derive(Copy, Clone, Default)]
 struct StarVertex {
     pos: [f32; 3],
+    // Light years to the star, or zero for one at infinity
+    distance: f32,
 }
-implement_vertex!(StarVertex, pos);
+implement_vertex!(StarVertex, pos, distance);
 
 #[derive(Debug, Copy, Clone)]
+/// The mouse as the events of a frame left it. What happened during the
+/// frame, scrolling and clicks, is gathered from the events and cleared by
+/// `end_frame` once the frame has used it.
 struct MouseState {
-    pos: (i32, i32),
-    pressed: (bool, bool, bool),
+    // In physical pixels, as the framebuffer counts them
+    pos: (f64, f64),
+    // Per button, left, right and middle
+    held: [bool; 3],
+    clicked: [bool; 3],
+    double_click: [bool; 3],
+    last_press: [Option<Instant>; 3],
+    // Scroll wheel notches this frame
     wheel: f32,
 }
 
 impl MouseState {
     fn new() -> MouseState {
         MouseState {
-            pos: (0, 0),
-            pressed: (false, false, false),
+            pos: (0.0, 0.0),
+            held: [false; 3],
+            clicked: [false; 3],
+            double_click: [false; 3],
+            last_press: [None; 3],
             wheel: 0.0,
         }
     }
+
+    /// Notes `button` going down or up at `now`. A press counts as a click
+    /// this frame, and as a double click if it came soon enough after the
+    /// last.
+    fn button(&mut self, button: usize, pressed: bool, now: Instant) {
+        self.held[button] = pressed;
+        if !pressed {
+            return;
+        }
+
+        self.clicked[button] = true;
+        let last = self.last_press[button].replace(now);
+        if last.is_some_and(|last| now - last < DOUBLE_CLICK_TIME) {
+            self.double_click[button] = true;
+            self.last_press[button] = None;
+        }
+    }
+
+    /// The framebuffer pixel under the cursor.
+    fn pixel(&self) -> (i32, i32) {
+        (self.pos.0.floor() as i32, self.pos.1.floor() as i32)
+    }
+
+    fn scroll(&mut self, notches: f32) {
+        self.wheel += notches;
+    }
+
+    /// Down now or at some point this frame, so a click shorter than a
+    /// frame isn't missed.
+    fn down(&self, button: usize) -> bool {
+        self.held[button] || self.clicked[button]
+    }
+
+    fn end_frame(&mut self) {
+        self.clicked = [false; 3];
+        self.double_click = [false; 3];
+        self.wheel = 0.0;
+    }
 }
 
 fn get_shader_change_time(
     frag_path: &str,
     vert_path: &str,
-) -> Result<SystemTime, Box<error::Error>> {
+) -> Result<SystemTime, Box<dyn error::Error>> {
     let metadata_vert = fs::metadata(frag_path)?;
     let metadata_frag = fs::metadata(vert_path)?;
     Ok(max(metadata_vert.modified()?, metadata_frag.modified()?))
 }
 
+fn read_shader_source(
+    path: &str,
+    include_paths: &mut Vec<String>,
+) -> Result<String, Box<dyn error::Error>> {
+    let mut source = String::new();
+
+    for line in fs::read_to_string(path)?.lines() {
+        if let Some(include) = line.trim().strip_prefix("#include") {
+            let name = include.trim().trim_matches('"');
+            let include_path = format!("shaders/{}", name);
+            source.push_str(&fs::read_to_string(&include_path)?);
+            include_paths.push(include_path);
+        } else {
+            source.push_str(line);
+        }
+        source.push('\n');
+    }
+
+    Ok(source)
+}
+
+fn compile_program<F: Facade>(
+    facade: &F,
+    vertex_shader: &str,
+    fragment_shader: &str,
+) -> Result<Program, Box<dyn error::Error>> {
+    let vertex_shader = capabilities::fit_glsl_version(facade, vertex_shader);
+    let fragment_shader = capabilities::fit_glsl_version(facade, fragment_shader);
+    let input = glium::program::ProgramCreationInput::SourceCode {
+        vertex_shader: &vertex_shader,
+        tessellation_control_shader: None,
+        tessellation_evaluation_shader: None,
+        geometry_shader: None,
+        fragment_shader: &fragment_shader,
+        transform_feedback_varyings: None,
+        outputs_srgb: false,
+        uses_point_size: true,
+    };
+
+    Ok(Program::new(facade, input)?)
+}
+
 struct Shader {
     program: Program,
     program_time: SystemTime,
     frag_path: String,
     vert_path: String,
+    include_paths: Vec<String>,
+
+    // The exact text the program was compiled from, includes expanded
+    vertex_source: String,
+    fragment_source: String,
 }
 
 impl Shader {
-    fn load_shadowmap<F: Facade>(facade: &F, name: &str) -> Result<Shader, Box<error::Error>> {
+    fn load_shadowmap<F: Facade>(facade: &F, name: &str) -> Result<Shader, Box<dyn error::Error>> {
         let frag_path = format!("shaders/{}_shadowmap.frag", name);
         let vert_path = format!("shaders/{}.vert", name);
         let program_time = get_shader_change_time(&frag_path, &vert_path)?;
@@ -88,7 +312,7 @@ impl Shader {
         )
     }
 
-    fn load<F: Facade>(facade: &F, name: &str) -> Result<Shader, Box<error::Error>> {
+    fn load<F: Facade>(facade: &F, name: &str) -> Result<Shader, Box<dyn error::Error>> {
         let frag_path = format!("shaders/{}.frag", name);
         let vert_path = format!("shaders/{}.vert", name);
         let program_time = get_shader_change_time(&frag_path, &vert_path)?;
@@ -105,663 +329,5203 @@ impl Shader {
         program_time: SystemTime,
         frag_path: Cow<str>,
         vert_path: Cow<str>,
-    ) -> Result<Shader, Box<error::Error>> {
-        let input = glium::program::ProgramCreationInput::SourceCode {
-            vertex_shader: &fs::read_to_string(&*vert_path)?,
-            tessellation_control_shader: None,
-            tessellation_evaluation_shader: None,
-            geometry_shader: None,
-            fragment_shader: &fs::read_to_string(&*frag_path)?,
-            transform_feedback_varyings: None,
-            outputs_srgb: false,
-            uses_point_size: true,
-        };
+    ) -> Result<Shader, Box<dyn error::Error>> {
+        let mut include_paths = Vec::new();
+        let vertex_source = read_shader_source(&vert_path, &mut include_paths)?;
+        let fragment_source = read_shader_source(&frag_path, &mut include_paths)?;
+
+        let program_time = include_paths
+            .iter()
+            .filter_map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .fold(program_time, max);
 
         Ok(Shader {
-            program: Program::new(facade, input)?,
-            program_time: program_time,
+            program: compile_program(facade, &vertex_source, &fragment_source)?,
+            program_time,
             frag_path: frag_path.into_owned(),
             vert_path: vert_path.into_owned(),
+            include_paths,
+            vertex_source,
+            fragment_source,
         })
     }
 
+    /// Recompiles the program from the given sources instead of the files
+    /// it was loaded from, without touching its paths.
+    fn replace_source<F: Facade>(
+        &mut self,
+        facade: &F,
+        vertex_source: String,
+        fragment_source: String,
+    ) -> Result<(), Box<dyn error::Error>> {
+        self.program = compile_program(facade, &vertex_source, &fragment_source)?;
+        self.vertex_source = vertex_source;
+        self.fragment_source = fragment_source;
+        Ok(())
+    }
+
+    fn change_time(&self) -> Result<SystemTime, Box<dyn error::Error>> {
+        let mut time = get_shader_change_time(&self.frag_path, &self.vert_path)?;
+        for path in &self.include_paths {
+            time = max(time, fs::metadata(path)?.modified()?);
+        }
+        Ok(time)
+    }
+
     fn reload_if_changed<F: Facade>(&mut self, facade: &F) {
-        if let Ok(new_time) = get_shader_change_time(&self.frag_path, &self.vert_path) {
+        if let Ok(new_time) = self.change_time() {
             if new_time > self.program_time {
-                match Shader::new(
-                    facade,
-                    new_time,
-                    Cow::Borrowed(&self.frag_path),
-                    Cow::Borrowed(&self.vert_path),
-                ) {
-                    Ok(program) => {
-                        *self = program;
-                    }
-                    Err(e) => {
-                        print!("{}", e);
-                    }
-                }
+                self.reload(facade, new_time);
             }
         }
     }
-}
 
-fn create_sphere(vertices: &mut [Vertex], indices: &mut [Triangle], radius: f32, segments: usize) {
-    let vsegs = if segments < 2 { 2 } else { segments };
-    let hsegs = vsegs * 2;
-    let nverts = (1 + (vsegs - 1) * (hsegs + 1) + 1) as i32;
-
-    // Top
-    vertices[0].pos[0] = 0.0;
-    vertices[0].pos[1] = 0.0;
-    vertices[0].pos[2] = radius;
-    vertices[0].normal[0] = 0.0;
-    vertices[0].normal[1] = 0.0;
-    vertices[0].normal[2] = 1.0;
-    vertices[0].tex[0] = 0.5;
-    vertices[0].tex[1] = 1.0;
-
-    // Bottom
-    let base = (nverts as usize) - 1;
-    vertices[base].pos[0] = 0.0;
-    vertices[base].pos[1] = 0.0;
-    vertices[base].pos[2] = -radius;
-    vertices[base].normal[0] = 0.0;
-    vertices[base].normal[1] = 0.0;
-    vertices[base].normal[2] = -1.0;
-    vertices[base].tex[0] = 0.5;
-    vertices[base].tex[1] = 0.0;
-
-    for j in 0..(vsegs - 1) {
-        let theta = ((j + 1) as f32) / (vsegs as f32) * PI;
-        let z = theta.cos();
-        let r = theta.sin();
-        for i in 0..hsegs {
-            let phi = i as f32 / hsegs as f32 * 2.0 * PI;
-            let x = r * phi.cos();
-            let y = r * phi.sin();
-            let base = 1 + j * (hsegs + 1) + i;
-            vertices[base].pos[0] = radius * x;
-            vertices[base].pos[1] = radius * y;
-            vertices[base].pos[2] = radius * z;
-            vertices[base].normal[0] = x;
-            vertices[base].normal[1] = y;
-            vertices[base].normal[2] = z;
-            vertices[base].tex[0] = i as f32 / hsegs as f32;
-            vertices[base].tex[1] = 1.0 - (j as f32 + 1.0) / vsegs as f32;
-        }
-    }
-
-    // Top cap
-    for i in 0..hsegs {
-        indices[i].ind[0] = 0;
-        indices[i].ind[1] = 1 + (i as i32);
-        indices[i].ind[2] = 2 + (i as i32);
-    }
-    // Middle part (possibly empty if vsegs=2)
-    for j in 0..(vsegs - 2) {
-        for i in 0..hsegs {
-            let base = hsegs + 2 * (j * hsegs + i);
-            let i0 = (1 + j * (hsegs + 1) + i) as i32;
-            if i == hsegs - 1 {
-                let i00 = (j * (hsegs + 1)) as i32;
-
-                indices[base].ind[0] = i0;
-                indices[base].ind[1] = i0 + (hsegs as i32) + 1;
-                indices[base].ind[2] = i00 + 1;
-                indices[base + 1].ind[0] = i00 + 1;
-                indices[base + 1].ind[1] = i0 + (hsegs as i32) + 1;
-                indices[base + 1].ind[2] = i00 + (hsegs as i32) + 2;
-            } else {
-                indices[base].ind[0] = i0;
-                indices[base].ind[1] = i0 + (hsegs as i32) + 1;
-                indices[base].ind[2] = i0 + 1;
-                indices[base + 1].ind[0] = i0 + 1;
-                indices[base + 1].ind[1] = i0 + (hsegs as i32) + 1;
-                indices[base + 1].ind[2] = i0 + (hsegs as i32) + 2;
+    fn reload<F: Facade>(&mut self, facade: &F, program_time: SystemTime) {
+        match Shader::new(
+            facade,
+            program_time,
+            Cow::Borrowed(&self.frag_path),
+            Cow::Borrowed(&self.vert_path),
+        ) {
+            Ok(program) => {
+                *self = program;
+            }
+            Err(e) => {
+                print!("{}", e);
             }
         }
     }
+}
+
+// Render radius the planet mesh is built at, presets are scaled to fit it
+const PLANET_RADIUS: f32 = 0.65;
+
+const NEAR_PLANE_KM: f64 = 98.0;
+const MIN_NEAR_PLANE_KM: f64 = 1.0;
+// The sky grid is drawn on a sphere this large around the eye
+const CELESTIAL_RADIUS: f32 = PLANET_RADIUS * 16.0;
+const FAR_PLANE_KM: f64 = 9_800_000.0;
+
+// Stars of the starfield close enough to show parallax, in light years
+const NEARBY_STARS: usize = 300;
+const NEARBY_STAR_RADIUS: f64 = 60.0;
+const MIN_STAR_DISTANCE: f64 = 4.0;
+
+// The shadowmap is rendered from a stand-in eye at this distance along the
+// sun direction, the shaders offset light space depth by the same amount
+const SHADOW_EYE_DISTANCE: f32 = 10000.0;
+
+// Per second, the shown field of view gets within 5% of a new setting in
+// about 0.2 s
+const FOV_EASE_RATE: f32 = 15.0;
+
+// Seconds, a window drag or a stall longer than this only moves things on
+// by this much
+const MAX_FRAME_STEP: f32 = 0.1;
+
+// Degrees per second the planet turns by itself when left to
+const AUTO_ROTATE_SPEED: f32 = 10.0;
+
+// Degrees the orbit camera turns per pixel dragged
+const ORBIT_DRAG_SPEED: f32 = 0.25;
+
+// Farthest the view can be panned off the planet, in camera distances
+const MAX_PAN: f32 = 2.0;
+const DOUBLE_CLICK_TIME: Duration = Duration::from_millis(300);
+
+// Each notch of the scroll wheel takes the camera this much closer
+const ZOOM_PER_NOTCH: f64 = 0.9;
+// Defaults for how close the camera gets, clear of the near plane, and how
+// far in planet radii, well inside the far plane
+const MIN_ZOOM_ALTITUDE_KM: f32 = 2.0 * NEAR_PLANE_KM as f32;
+const MAX_ZOOM_RADII: f32 = 100.0;
+
+/// Where the orbit camera is and how far it zooms, everything a reset puts
+/// back. Distances are in render units.
+struct OrbitView {
+    // The planet's turn, spun around its pole by the arrow keys and turned
+    // any way by the arcball
+    rotation: Quaternion<f32>,
+    sun_angle: f32,
+    // From the camera to the planet's center
+    camera_distance: f64,
+    min_zoom_altitude_km: f32,
+    max_zoom_radii: f32,
+    // The orbit camera, in degrees around the planet's center
+    orbit_yaw: f32,
+    orbit_pitch: f32,
+    // Offset of the view in its own plane, as a fraction of the camera
+    // distance so it frames the planet the same at any zoom
+    pan: Vector2<f32>,
+    // Vertical, in degrees, and the one shown while easing towards it
+    fov: f32,
+    shown_fov: f32,
+}
+
+impl OrbitView {
+    /// The default pose with the camera at the planet's center, until a
+    /// body puts it at a distance.
+    fn new() -> OrbitView {
+        OrbitView {
+            rotation: State::DEFAULT_ROTATION,
+            sun_angle: State::DEFAULT_SUN_ANGLE,
+            camera_distance: 0.0,
+            min_zoom_altitude_km: MIN_ZOOM_ALTITUDE_KM,
+            max_zoom_radii: MAX_ZOOM_RADII,
+            orbit_yaw: State::DEFAULT_ORBIT_YAW,
+            orbit_pitch: State::DEFAULT_ORBIT_PITCH,
+            pan: State::DEFAULT_PAN,
+            fov: State::DEFAULT_FOV,
+            shown_fov: State::DEFAULT_FOV,
+        }
+    }
+
+    fn pose(&self, scale: &SceneScale) -> ViewPose {
+        ViewPose {
+            rotation: self.rotation,
+            distance_km: scale.to_km(self.camera_distance),
+            sun_angle: self.sun_angle,
+            orbit_yaw: self.orbit_yaw,
+            orbit_pitch: self.orbit_pitch,
+            pan: self.pan,
+        }
+    }
+
+    fn set_pose(&mut self, pose: ViewPose, body: &Preset, scale: &SceneScale) {
+        self.rotation = pose.rotation;
+        self.sun_angle = pose.sun_angle;
+        self.orbit_yaw = pose.orbit_yaw;
+        self.orbit_pitch = pose.orbit_pitch;
+        self.pan = pose.pan;
+        self.set_distance(scale.to_render(pose.distance_km), body, scale);
+    }
 
-    // // Bottom cap
-    let base = hsegs + 2 * (vsegs - 2) * hsegs;
-    for i in 0..hsegs {
-        indices[base + i].ind[0] = nverts - 1;
-        indices[base + i].ind[1] = nverts - 2 - (i as i32);
-        indices[base + i].ind[2] = nverts - 3 - (i as i32);
+    /// Puts the camera `distance` from the planet's center, kept between
+    /// `min_zoom_altitude_km` above the surface and `max_zoom_radii` away.
+    fn set_distance(&mut self, distance: f64, body: &Preset, scale: &SceneScale) {
+        let radius_km = body.planet_radius_km;
+        let closest = scale.to_render(radius_km + f64::from(self.min_zoom_altitude_km));
+        let farthest = scale
+            .to_render(radius_km * f64::from(self.max_zoom_radii))
+            .max(closest);
+        self.camera_distance = distance.clamp(closest, farthest);
+    }
+
+    /// Goes back to `State::default_pose` for `body` and the default field
+    /// of view. The zoom limits stay as they are.
+    fn reset(&mut self, body: &Preset, scale: &SceneScale) {
+        self.fov = State::DEFAULT_FOV;
+        self.shown_fov = State::DEFAULT_FOV;
+        self.set_pose(State::default_pose(body), body, scale);
     }
 }
 
 struct State {
-    vertex_buffer: glium::VertexBuffer<Vertex>,
-    index_buffer: glium::IndexBuffer<u32>,
-    star_buffer: glium::VertexBuffer<StarVertex>,
+    geometry: PlanetGeometry,
+    chunks: ChunkedPlanet,
+    stars: Starfield,
 
-    sun_pos: Vector3<f32>,
-    sun_angle: f32,
+    detail_rock: Texture2d,
+    detail_soil: Texture2d,
+    detail: DetailSettings,
+    colors: ColorPalette,
+    palette_names: Vec<String>,
+
+    vegetation: Vegetation,
+    meteors: Meteors,
+    magnetosphere: Magnetosphere,
+    spacecraft: Spacecraft,
+    nbody: NBody,
+    entry: EntryEffects,
+    wind: Wind,
+    labels: Labels,
+    sun: Sun,
+    ring: Ring,
+    extra_bodies: ExtraBodies,
+    atmosphere_shell: AtmosphereShell,
+    skybox: Skybox,
+
+    cloud_occlusion: PassOcclusion,
+    vegetation_occlusion: PassOcclusion,
+    occlusion_auto_skip: bool,
+    // Shadowmap texels per framebuffer pixel, along each axis
+    shadow_resolution: u32,
+    cloud_resolution: CloudResolution,
+    quality: Quality,
+    framebuffer_size: (u32, u32),
+    pass_timers: PassTimers,
+    frame_uniforms: FrameUniforms,
+    pass_params: PassParameters,
+    // The lines rewritten every frame, the trails and the sky grid
+    line_pool: StreamPool<PolylineVertex>,
+
+    // Positions are kept in f64 around a fixed world origin and rebased on
+    // the camera each frame, so everything sent to the GPU stays small
+    camera_pos: Vector3<f64>,
+    planet_pos: Vector3<f64>,
+    sun_pos: Vector3<f64>,
+    // Degrees along the orbit from the northern spring equinox
+    season: f32,
+
+    preset: i32,
+    // The planet in physical units, copied from the preset and edited in
+    // the scale model
+    body: Preset,
+    // Light falls off with the real distance to the sun
+    scale_model: bool,
+    scale: SceneScale,
+    sun_distance: f64,
+    view: OrbitView,
+
+    // The body's own atmosphere, copied from the preset and edited in the UI
+    atmosphere: Option<Atmosphere>,
+
+    // log10 of how much closer the nearby stars are drawn than they are,
+    // real distances are far too large to show parallax at planet scale
+    star_parallax: f32,
+
+    surface: SurfaceView,
+    celestial: Celestial,
+    satellite: SatelliteView,
+    fly: FreeFly,
+    // What imgui asked for when the frame was built, the scene leaves that
+    // input alone
+    ui_wants_keyboard: bool,
+    ui_wants_mouse: bool,
+    picking: bool,
+    // 1 colors the surface by geodesic cell ID and 2 by the cell's value
+    cell_view: i32,
+    drag_pos: Option<(i32, i32)>,
+    pan_pos: Option<(i32, i32)>,
 
     planet_program: Shader,
     planet_shadowmap_program: Shader,
     cloud_program: Shader,
     cloud_shadowmap_program: Shader,
+    cloud_downsample_program: Shader,
+    cloud_upsample_program: Shader,
+    cloud_merge_program: Shader,
     star_program: Shader,
+    vegetation_program: Shader,
+    meteor_program: Shader,
+    magnetosphere_program: Shader,
+    trail_program: Shader,
+    label_program: Shader,
+    sun_program: Shader,
+    ring_program: Shader,
+    body_program: Shader,
+    atmosphere_program: Shader,
+    skybox_program: Shader,
+
+    palette: CommandPalette,
+    stutter: StutterDetector,
+    gl: GlCapabilities,
+    workspaces: Workspaces,
+    pending_actions: Vec<Action>,
+    force_shader_reload: bool,
+    capture_requested: bool,
+    screenshot_requested: bool,
+    scene_screenshot_requested: bool,
+    screenshots: ScreenshotWriter,
+    benchmark: Benchmark,
+    benchmark_requested: bool,
+    time_lapse: TimeLapse,
+    recording: Recording,
+    session: Session,
 
     run: bool,
     right_pressed: bool,
     left_pressed: bool,
-    rot: f32,
-    start_time: Instant,
+    arcball_pos: Option<(i32, i32)>,
+    inertia: Inertia,
+    bookmarks: Bookmarks,
+    gamepad: Gamepad,
+    gestures: Gestures,
+    // Borderless on the window's monitor, asked for and as the window is
+    fullscreen: bool,
+    window_fullscreen: bool,
+    // Waiting for the display's refresh on swap, asked for and as the
+    // context was made
+    vsync: bool,
+    window_vsync: bool,
+    frame_limiter: FrameLimiter,
+    // How long the last frame's swap took, in ms
+    swap_ms: f32,
+    clock: Clock,
+    // The planet turning around its pole on its own
+    auto_rotate: Spin,
+    sun_cycle: SunCycle,
     last_time: Instant,
+    // Of the real frame times, unclamped
     average_frame_time: f32,
+    // Seconds a frame moves anything on by at most, after a stall
+    max_frame_step: f32,
+    clamped_frames: u32,
     mouse_state: MouseState,
+    mouse_look: MouseLook,
+    bindings: InputBindings,
 }
 
 impl State {
-    fn new<F: Facade>(facade: &F) -> Result<State, Box<error::Error>> {
-        let (vertex_buffer, index_buffer) = {
-            const VSEGS: usize = 512;
-            const HSEGS: usize = VSEGS * 2;
-            const NVERTS: usize = 1 + (VSEGS - 1) * (HSEGS + 1) + 1; // top + middle + bottom
-            const NTRIS: usize = HSEGS + (VSEGS - 2) * HSEGS * 2 + HSEGS; // top + middle + bottom
-
-            let mut vertex_list = vec![Default::default(); NVERTS];
-            let mut index_list = vec![Default::default(); NTRIS];
-
-            create_sphere(&mut vertex_list, &mut index_list, 0.65, VSEGS);
-
-            let mut flat_index_list = Vec::new();
-
-            for tri in index_list {
-                flat_index_list.push(tri.ind[0] as u32);
-                flat_index_list.push(tri.ind[1] as u32);
-                flat_index_list.push(tri.ind[2] as u32);
-            }
+    // Where the view starts and goes back to on reset, the distance comes
+    // from the preset
+    const DEFAULT_ROTATION: Quaternion<f32> = Quaternion {
+        s: 1.0,
+        v: Vector3 {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+    };
+    const DEFAULT_SUN_ANGLE: f32 = 0.0;
+    const DEFAULT_ORBIT_YAW: f32 = 0.0;
+    const DEFAULT_ORBIT_PITCH: f32 = 0.0;
+    const DEFAULT_FOV: f32 = 90.0;
+    const DEFAULT_PAN: Vector2<f32> = Vector2 { x: 0.0, y: 0.0 };
 
-            let index_buffer =
-                glium::IndexBuffer::new(facade, PrimitiveType::TrianglesList, &flat_index_list)?;
+    /// Where the view starts for `body`, and goes back to on reset.
+    fn default_pose(body: &Preset) -> ViewPose {
+        ViewPose {
+            rotation: State::DEFAULT_ROTATION,
+            distance_km: body.planet_radius_km + body.camera_altitude_km,
+            sun_angle: State::DEFAULT_SUN_ANGLE,
+            orbit_yaw: State::DEFAULT_ORBIT_YAW,
+            orbit_pitch: State::DEFAULT_ORBIT_PITCH,
+            pan: State::DEFAULT_PAN,
+        }
+    }
 
-            let vertex_buffer = glium::VertexBuffer::new(facade, &vertex_list)?;
+    fn new<F: Facade>(
+        facade: &F,
+        max_gl: (u8, u8),
+        mesh: PlanetMesh,
+    ) -> Result<State, Box<dyn error::Error>> {
+        let gl = GlCapabilities::detect(facade, max_gl);
+        gl.report();
 
-            (vertex_buffer, index_buffer)
-        };
+        let geometry = PlanetGeometry::new(facade, mesh)?;
+        println!(
+            "Planet mesh: {}, {} triangles",
+            mesh.name(),
+            geometry.planet().triangle_count()
+        );
 
-        let star_buffer = {
+        let stars = {
             let mut star_list = Vec::new();
 
             let sphere = UnitSphereSurface::new();
             let mut rng = rand::thread_rng();
 
-            for _ in 0..10000 {
+            // The first few are given finite distances, spread evenly through
+            // the volume out to NEARBY_STAR_RADIUS light years
+            for _ in 0..NEARBY_STARS {
                 let v = sphere.sample(&mut rng);
+                let u: f64 = rng.gen();
                 star_list.push(StarVertex {
                     pos: [v[0] as f32, v[1] as f32, v[2] as f32],
+                    distance: (NEARBY_STAR_RADIUS * u.cbrt()).max(MIN_STAR_DISTANCE) as f32,
                 });
             }
 
-            let vertex_buffer = glium::VertexBuffer::new(facade, &star_list)?;
+            for _ in NEARBY_STARS..10000 {
+                let v = sphere.sample(&mut rng);
+                star_list.push(StarVertex {
+                    pos: [v[0] as f32, v[1] as f32, v[2] as f32],
+                    distance: 0.0,
+                });
+            }
 
-            vertex_buffer
+            Starfield::new(facade, star_list, NEARBY_STARS)?
         };
 
-        Ok(State {
-            vertex_buffer: vertex_buffer,
-            index_buffer: index_buffer,
-            star_buffer: star_buffer,
+        let mut state = State {
+            geometry,
+            chunks: ChunkedPlanet::new(PLANET_RADIUS),
+            stars,
 
-            sun_pos: vec3(0.0, 0.0, -1.0),
-            sun_angle: 0.0,
+            detail_rock: detail::load_detail_texture(
+                facade,
+                "textures/detail_rock.png",
+                1,
+                8,
+                1.6,
+            )?,
+            detail_soil: detail::load_detail_texture(
+                facade,
+                "textures/detail_soil.png",
+                2,
+                16,
+                0.8,
+            )?,
+            detail: DetailSettings::new(),
+            colors: ColorPalette::load(PRESETS[0].palette)?,
+            palette_names: ColorPalette::available(),
+
+            vegetation: Vegetation::new(facade, PLANET_RADIUS)?,
+            meteors: Meteors::new(facade)?,
+            magnetosphere: Magnetosphere::new(),
+            spacecraft: Spacecraft::new(),
+            nbody: NBody::new(),
+            entry: EntryEffects::new(),
+            wind: Wind::new(PRESETS[0].wind_profile),
+            labels: Labels::new(),
+            sun: Sun::new(facade)?,
+            ring: Ring::new(),
+            extra_bodies: ExtraBodies::new(),
+            atmosphere_shell: AtmosphereShell::new(),
+            skybox: Skybox::new(facade)?,
+
+            cloud_occlusion: PassOcclusion::new(),
+            vegetation_occlusion: PassOcclusion::new(),
+            occlusion_auto_skip: false,
+            shadow_resolution: 4,
+            cloud_resolution: CloudResolution::Full,
+            quality: Quality::load(),
+            framebuffer_size: (1, 1),
+            pass_timers: PassTimers::new(),
+            frame_uniforms: FrameUniforms::new(facade)?,
+            pass_params: PassParameters::new(),
+            line_pool: StreamPool::new(facade, polyline::STREAM_CAPACITY)?,
+
+            camera_pos: vec3(0.0, 0.0, 0.0),
+            planet_pos: vec3(0.0, 0.0, 0.0),
+            sun_pos: vec3(0.0, 0.0, 0.0),
+            season: 0.0,
+
+            preset: 0,
+            body: PRESETS[0],
+            scale_model: false,
+            scale: SceneScale::for_preset(&PRESETS[0], PLANET_RADIUS),
+            sun_distance: 0.0,
+            // The distance is set along with the preset below
+            view: OrbitView::new(),
+            atmosphere: PRESETS[0].atmosphere,
+
+            star_parallax: 0.0,
+
+            surface: SurfaceView::new(),
+            celestial: Celestial::new(),
+            satellite: SatelliteView::new(),
+            fly: FreeFly::new(),
+            ui_wants_keyboard: false,
+            ui_wants_mouse: false,
+            picking: false,
+            cell_view: 0,
+            pan_pos: None,
+            drag_pos: None,
 
             planet_program: Shader::load(facade, "planet")?,
             planet_shadowmap_program: Shader::load_shadowmap(facade, "planet")?,
             cloud_program: Shader::load(facade, "cloud")?,
             cloud_shadowmap_program: Shader::load_shadowmap(facade, "cloud")?,
+            cloud_downsample_program: Shader::load(facade, "cloud_downsample")?,
+            cloud_upsample_program: Shader::load(facade, "cloud_upsample")?,
+            cloud_merge_program: Shader::load(facade, "cloud_merge")?,
             star_program: Shader::load(facade, "stars")?,
+            vegetation_program: Shader::load(facade, "vegetation")?,
+            meteor_program: Shader::load(facade, "meteors")?,
+            magnetosphere_program: Shader::load(facade, "magnetosphere")?,
+            trail_program: Shader::load(facade, "trail")?,
+            label_program: Shader::load(facade, "labels")?,
+            sun_program: Shader::load(facade, "sun")?,
+            ring_program: Shader::load(facade, "ring")?,
+            body_program: Shader::load(facade, "body")?,
+            atmosphere_program: Shader::load(facade, "atmosphere")?,
+            skybox_program: Shader::load(facade, "skybox")?,
+
+            palette: CommandPalette::new(),
+            stutter: StutterDetector::new(),
+            gl,
+            workspaces: Workspaces::load(),
+            pending_actions: Vec::new(),
+            force_shader_reload: false,
+            capture_requested: false,
+            screenshot_requested: false,
+            scene_screenshot_requested: false,
+            screenshots: ScreenshotWriter::new(),
+            benchmark: Benchmark::new(),
+            benchmark_requested: false,
+            time_lapse: TimeLapse::new(),
+            recording: Recording::new(),
+            session: Session::new(),
 
             run: true,
             right_pressed: false,
             left_pressed: false,
-            rot: 0.0,
-            start_time: Instant::now(),
+            arcball_pos: None,
+            inertia: Inertia::new(),
+            bookmarks: Bookmarks::load(),
+            gamepad: Gamepad::new(),
+            gestures: Gestures::new(),
+            fullscreen: false,
+            window_fullscreen: false,
+            vsync: false,
+            window_vsync: false,
+            frame_limiter: FrameLimiter::new(),
+            swap_ms: 0.0,
+            clock: Clock::new(),
+            auto_rotate: Spin::new(AUTO_ROTATE_SPEED),
+            sun_cycle: SunCycle::new(),
             last_time: Instant::now(),
             average_frame_time: 0.0,
+            max_frame_step: MAX_FRAME_STEP,
+            clamped_frames: 0,
             mouse_state: MouseState::new(),
-        })
+            mouse_look: MouseLook::new(),
+            bindings: InputBindings::load(),
+        };
+        state.apply_preset(0);
+
+        Ok(state)
     }
-}
 
-fn update_ui<'a>(ui: &Ui<'a>, p: &mut State) {
-    ui.window(im_str!("Planet"))
-        .size((300.0, 100.0), ImGuiCond::FirstUseEver)
-        .build(|| {
-            ui.text(im_str!(
-                "{:.1} fps ({:.1} ms)",
-                1.0 / p.average_frame_time,
-                p.average_frame_time * 1000.0,
-            ));
+    fn shaders(&self) -> [&Shader; 18] {
+        [
+            &self.planet_program,
+            &self.planet_shadowmap_program,
+            &self.cloud_program,
+            &self.cloud_shadowmap_program,
+            &self.cloud_downsample_program,
+            &self.cloud_upsample_program,
+            &self.cloud_merge_program,
+            &self.star_program,
+            &self.vegetation_program,
+            &self.meteor_program,
+            &self.magnetosphere_program,
+            &self.trail_program,
+            &self.label_program,
+            &self.sun_program,
+            &self.ring_program,
+            &self.body_program,
+            &self.atmosphere_program,
+            &self.skybox_program,
+        ]
+    }
 
-            if ui
-                .slider_float(im_str!("Sun Angle"), &mut p.sun_angle, -180.0, 180.0)
-                .build()
-            {
-                let x = p.sun_angle.to_radians().cos();
-                let y = p.sun_angle.to_radians().sin();
-                p.sun_pos = vec3(10000.0 * y, 0.0, 10000.0 * -x);
-            }
+    fn shaders_mut(&mut self) -> [&mut Shader; 18] {
+        [
+            &mut self.planet_program,
+            &mut self.planet_shadowmap_program,
+            &mut self.cloud_program,
+            &mut self.cloud_shadowmap_program,
+            &mut self.cloud_downsample_program,
+            &mut self.cloud_upsample_program,
+            &mut self.cloud_merge_program,
+            &mut self.star_program,
+            &mut self.vegetation_program,
+            &mut self.meteor_program,
+            &mut self.magnetosphere_program,
+            &mut self.trail_program,
+            &mut self.label_program,
+            &mut self.sun_program,
+            &mut self.ring_program,
+            &mut self.body_program,
+            &mut self.atmosphere_program,
+            &mut self.skybox_program,
+        ]
+    }
 
-            ui.text(im_str!("Sun Pos: {:?}", &p.sun_pos));
-        });
-}
+    fn reload_shaders<F: Facade>(&mut self, facade: &F) {
+        let force = self.force_shader_reload;
+        self.force_shader_reload = false;
 
-fn main() -> Result<(), Box<error::Error>> {
-    let mut event_loop = glutin::EventsLoop::new();
+        for shader in self.shaders_mut().iter_mut() {
+            if force {
+                let time = shader.change_time().unwrap_or(shader.program_time);
+                shader.reload(facade, time);
+            } else {
+                shader.reload_if_changed(facade);
+            }
+        }
+    }
 
-    let window = glutin::WindowBuilder::new().with_title("Planet");
-    let context = glutin::ContextBuilder::new()
-        .with_gl_profile(GlProfile::Core)
-        .with_gl(GlRequest::Specific(Api::OpenGl, (4, 3)));
-    let display = Display::new(window, context, &event_loop)?;
+    fn apply_preset(&mut self, index: usize) {
+        let preset = PRESETS[index];
+        self.preset = index as i32;
+        self.atmosphere = preset.atmosphere;
+        self.wind.set_curve(WindCurve::new(preset.wind_profile));
+        self.load_palette(preset.palette);
+        self.geometry.displacement.craters.count = preset.craters;
+        self.geometry.flattening = preset.flattening;
 
-    let mut imgui = ImGui::init();
-    imgui.set_ini_filename(None);
-    imgui.style_mut().window_rounding = 0.0;
-    imgui.set_imgui_key(ImGuiKey::Tab, 0);
-    imgui.set_imgui_key(ImGuiKey::LeftArrow, 1);
-    imgui.set_imgui_key(ImGuiKey::RightArrow, 2);
-    imgui.set_imgui_key(ImGuiKey::UpArrow, 3);
-    imgui.set_imgui_key(ImGuiKey::DownArrow, 4);
-    imgui.set_imgui_key(ImGuiKey::PageUp, 5);
-    imgui.set_imgui_key(ImGuiKey::PageDown, 6);
-    imgui.set_imgui_key(ImGuiKey::Home, 7);
-    imgui.set_imgui_key(ImGuiKey::End, 8);
-    imgui.set_imgui_key(ImGuiKey::Delete, 9);
-    imgui.set_imgui_key(ImGuiKey::Backspace, 10);
-    imgui.set_imgui_key(ImGuiKey::Enter, 11);
-    imgui.set_imgui_key(ImGuiKey::Escape, 12);
-    imgui.set_imgui_key(ImGuiKey::A, 13);
-    imgui.set_imgui_key(ImGuiKey::C, 14);
-    imgui.set_imgui_key(ImGuiKey::V, 15);
-    imgui.set_imgui_key(ImGuiKey::X, 16);
-    imgui.set_imgui_key(ImGuiKey::Y, 17);
-    imgui.set_imgui_key(ImGuiKey::Z, 18);
+        self.set_body(preset);
+        self.set_camera_distance(self.default_camera_distance());
+        self.update_sun_pos();
+    }
 
-    let mut imgui_renderer = imgui_glium_renderer::Renderer::init(&mut imgui, &display).unwrap();
+    /// Rescales the scene for `body`, keeping the camera at the same
+    /// altitude above the surface.
+    fn set_body(&mut self, body: Preset) {
+        let altitude_km = self.scale.to_km(self.view.camera_distance) - self.body.planet_radius_km;
+        self.body = body;
+        self.scale = SceneScale::for_preset(&body, PLANET_RADIUS);
+        self.sun_distance = self.scale.to_render(units::au_to_km(body.sun_distance_au));
 
-    let shadowmap_texture = {
-        let (width, height) = display.get_framebuffer_dimensions();
-        Texture2d::empty_with_format(
-            &display,
-            UncompressedFloatFormat::F32F32,
-            MipmapsOption::NoMipmap,
-            4 * width,
-            4 * height,
-        )?
-    };
-    let shadowmap_depthbuffer = {
-        let (width, height) = display.get_framebuffer_dimensions();
-        DepthRenderBuffer::new(&display, DepthFormat::F32, 4 * width, 4 * height)?
-    };
-    let mut shadowmap_framebuffer =
-        SimpleFrameBuffer::with_depth_buffer(&display, &shadowmap_texture, &shadowmap_depthbuffer)?;
+        self.set_camera_distance(self.scale.to_render(body.planet_radius_km + altitude_km));
+        self.labels.invalidate();
+        self.update_sun_pos();
+    }
 
-    let mut p = State::new(&display)?;
+    /// Sunlight at the planet relative to 1 AU in the scale model, otherwise
+    /// the same everywhere.
+    fn sun_intensity(&self) -> f32 {
+        if self.scale_model {
+            units::irradiance(self.scale.to_km(self.sun_distance)) as f32
+        } else {
+            1.0
+        }
+    }
 
-    while p.run {
-        let dt = {
-            let new_time = Instant::now();
-            let duration = new_time.duration_since(p.last_time);
-            p.last_time = new_time;
-            duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9
-        };
+    fn default_camera_distance(&self) -> f64 {
+        self.scale
+            .to_render(State::default_pose(&self.body).distance_km)
+    }
 
-        p.average_frame_time = p.average_frame_time * 0.95 + dt * 0.05;
+    /// Puts the camera, the planet's turn and the sun back where they
+    /// started, leaving the surface, satellite and free-fly views for the
+    /// orbit camera.
+    fn reset_view(&mut self) {
+        self.take_orbit_camera();
+        self.view.reset(&self.body, &self.scale);
+        self.place_planet();
+        self.update_sun_pos();
+    }
 
-        p.planet_program.reload_if_changed(&display);
-        p.planet_shadowmap_program.reload_if_changed(&display);
-        p.cloud_program.reload_if_changed(&display);
-        p.cloud_shadowmap_program.reload_if_changed(&display);
-        p.star_program.reload_if_changed(&display);
+    /// Leaves the surface, satellite and free-fly views and drops any
+    /// movement still under way, for putting the orbit camera somewhere.
+    fn take_orbit_camera(&mut self) {
+        self.set_free_fly(false);
+        self.surface.enabled = false;
+        self.satellite.enabled = false;
+        self.satellite.snap();
+        self.spacecraft.settings.follow = false;
+        self.inertia.stop();
+        self.bookmarks.cancel();
+        self.arcball_pos = None;
+        self.drag_pos = None;
+        self.pan_pos = None;
+    }
 
-        event_loop.poll_events(|event| {
-            use glium::glutin::{
-                ElementState, Event, MouseButton, MouseScrollDelta, TouchPhase, WindowEvent,
-            };
+    fn view_pose(&self) -> ViewPose {
+        self.view.pose(&self.scale)
+    }
 
-            match event {
-                Event::WindowEvent { event, .. } => match event {
-                    glutin::WindowEvent::CloseRequested => {
-                        p.run = false;
-                    }
-                    WindowEvent::KeyboardInput { input, .. } => {
-                        use glium::glutin::VirtualKeyCode as Key;
+    fn set_view_pose(&mut self, pose: ViewPose) {
+        self.view.set_pose(pose, &self.body, &self.scale);
+        self.place_planet();
+        self.update_sun_pos();
+    }
 
-                        let pressed = input.state == ElementState::Pressed;
-                        match input.virtual_keycode {
-                            Some(Key::Tab) => imgui.set_key(0, pressed),
-                            Some(Key::Left) => {
-                                imgui.set_key(1, pressed);
-                                p.left_pressed = pressed;
-                            }
-                            Some(Key::Right) => {
-                                imgui.set_key(2, pressed);
-                                p.right_pressed = pressed;
-                            }
-                            Some(Key::Up) => imgui.set_key(3, pressed),
-                            Some(Key::Down) => imgui.set_key(4, pressed),
-                            Some(Key::PageUp) => imgui.set_key(5, pressed),
-                            Some(Key::PageDown) => imgui.set_key(6, pressed),
-                            Some(Key::Home) => imgui.set_key(7, pressed),
-                            Some(Key::End) => imgui.set_key(8, pressed),
-                            Some(Key::Delete) => imgui.set_key(9, pressed),
-                            Some(Key::Back) => imgui.set_key(10, pressed),
-                            Some(Key::Return) => imgui.set_key(11, pressed),
-                            Some(Key::Escape) => imgui.set_key(12, pressed),
-                            Some(Key::A) => imgui.set_key(13, pressed),
-                            Some(Key::C) => imgui.set_key(14, pressed),
-                            Some(Key::V) => imgui.set_key(15, pressed),
-                            Some(Key::X) => imgui.set_key(16, pressed),
-                            Some(Key::Y) => imgui.set_key(17, pressed),
-                            Some(Key::Z) => imgui.set_key(18, pressed),
-                            Some(Key::LControl) | Some(Key::RControl) => {
-                                imgui.set_key_ctrl(pressed)
-                            }
-                            Some(Key::LShift) | Some(Key::RShift) => imgui.set_key_shift(pressed),
-                            Some(Key::LAlt) | Some(Key::RAlt) => imgui.set_key_alt(pressed),
-                            Some(Key::LWin) | Some(Key::RWin) => imgui.set_key_super(pressed),
-                            _ => {}
-                        }
-                    }
-                    WindowEvent::CursorMoved {
-                        position: LogicalPosition { x, y },
-                        ..
-                    } => {
-                        if x as i32 != 0 && y as i32 != 0 {
-                            p.mouse_state.pos = (x as i32, y as i32);
-                        }
-                    }
-                    WindowEvent::MouseInput { state, button, .. } => match button {
-                        MouseButton::Left => {
-                            p.mouse_state.pressed.0 = state == ElementState::Pressed
-                        }
-                        MouseButton::Right => {
-                            p.mouse_state.pressed.1 = state == ElementState::Pressed
-                        }
-                        MouseButton::Middle => {
-                            p.mouse_state.pressed.2 = state == ElementState::Pressed
-                        }
-                        _ => {}
-                    },
-                    WindowEvent::MouseWheel {
-                        delta: MouseScrollDelta::LineDelta(_, y),
-                        phase: TouchPhase::Moved,
-                        ..
-                    } => {
-                        p.mouse_state.wheel = y;
-                    }
-                    WindowEvent::MouseWheel {
-                        delta: MouseScrollDelta::PixelDelta(LogicalPosition { y, .. }),
-                        phase: TouchPhase::Moved,
-                        ..
-                    } => {
-                        p.mouse_state.wheel = y as f32;
-                    }
-                    WindowEvent::ReceivedCharacter(c) => imgui.add_input_character(c),
-                    _ => (),
-                },
-                _ => (),
-            }
-        });
+    /// Where the ray through the pixel at `cursor` meets the planet's
+    /// ellipsoid, in camera-relative world space.
+    fn cursor_hit(&self, cursor: (i32, i32)) -> Option<Vector3<f32>> {
+        let (origin, direction) = self.camera().cursor_ray(cursor, self.framebuffer_size);
+        let center = self.camera_relative(self.planet_pos);
+        math::ray_ellipsoid(
+            origin,
+            direction,
+            center,
+            PLANET_RADIUS,
+            self.view.rotation * vec3(0.0, 1.0, 0.0),
+            self.geometry.built_flattening(),
+        )
+        .map(|distance| origin + direction * distance)
+    }
 
-        {
-            let scale = imgui.display_framebuffer_scale();
+    /// The point of the planet mesh under `cursor`, in planet-local space.
+    fn pick(&self, cursor: (i32, i32)) -> Option<Vector3<f32>> {
+        let center = self.camera_relative(self.planet_pos);
+        self.cursor_hit(cursor)
+            .map(|hit| self.view.rotation.conjugate() * (hit - center))
+    }
 
-            imgui.set_mouse_pos(
-                p.mouse_state.pos.0 as f32 / scale.0,
-                p.mouse_state.pos.1 as f32 / scale.1,
-            );
+    /// Eases the planet round to put the point on its surface under
+    /// `cursor` in the middle of the view. Nothing happens off the planet.
+    fn focus_on(&mut self, cursor: (i32, i32)) {
+        let center = self.camera_relative(self.planet_pos);
+        let hit = match self.cursor_hit(cursor) {
+            Some(hit) => hit,
+            None => return,
+        };
 
-            imgui.set_mouse_down([
-                p.mouse_state.pressed.0,
-                p.mouse_state.pressed.1,
-                p.mouse_state.pressed.2,
-                false,
-                false,
-            ]);
+        // Where the eye is once the pan is gone, the planet's center is
+        // then in the middle
+        let orbit = Matrix4::from_angle_y(Deg(-self.view.orbit_yaw))
+            * Matrix4::from_angle_x(Deg(-self.view.orbit_pitch));
+        let eye = center + (orbit * (-center).extend(0.0)).truncate();
 
-            imgui.set_mouse_wheel(p.mouse_state.wheel / scale.1);
-        }
+        let current = self.view_pose();
+        let turn =
+            Quaternion::from_arc((hit - center).normalize(), (eye - center).normalize(), None);
+        let target = ViewPose {
+            rotation: arcball::compose(turn, self.view.rotation),
+            pan: State::DEFAULT_PAN,
+            ..current
+        };
+        self.inertia.stop();
+        self.bookmarks.fly_to(current, target);
+    }
 
-        if p.right_pressed {
-            p.rot += dt * 45.0;
+    fn recall_bookmark(&mut self, index: usize) {
+        let current = self.view_pose();
+        if index < self.bookmarks.list.len() {
+            self.take_orbit_camera();
+            self.bookmarks.recall(index, current);
         }
+    }
 
-        if p.left_pressed {
-            p.rot -= dt * 45.0;
-        }
+    /// Moves the planet to `distance` in front of the camera, see
+    /// `OrbitView::set_distance`. The sun has to be placed again after.
+    fn set_camera_distance(&mut self, distance: f64) {
+        self.view.set_distance(distance, &self.body, &self.scale);
+        self.place_planet();
+    }
 
-        let (width, height) = display.get_framebuffer_dimensions();
+    fn place_planet(&mut self) {
+        // The orbit camera stays at the world origin, only the free-fly
+        // camera moves away from it
+        self.planet_pos = vec3(0.0, 0.0, -self.view.camera_distance);
+    }
 
-        let planet_pos = vec3(0.0, 0.0, -3.0);
+    /// Takes off from wherever the view is, or goes back to the camera of
+    /// the other modes.
+    fn set_free_fly(&mut self, enabled: bool) {
+        if enabled == self.fly.enabled {
+            return;
+        }
 
-        let ui = imgui.frame(FrameSize::new(width as f64, height as f64, 1.0), dt);
-        update_ui(&ui, &mut p);
+        if enabled {
+            let camera = self.camera();
+            self.camera_pos += camera.position.map(f64::from);
+            self.fly.look_along(camera.forward());
+            self.surface.enabled = false;
+            self.satellite.enabled = false;
+            self.satellite.snap();
+        } else {
+            self.camera_pos = vec3(0.0, 0.0, 0.0);
+            self.fly.keys = FlyKeys::default();
+        }
+        self.fly.enabled = enabled;
+    }
 
-        let planet_matrix = Matrix4::from_translation(planet_pos)
-            * Matrix4::from_axis_angle(vec3(0.0, 1.0, 0.0), Deg(p.rot));
-        let cloud_matrix = Matrix4::from_translation(planet_pos)
-            * Matrix4::from_scale(1.2)
-            * Matrix4::from_axis_angle(vec3(0.0, 1.0, 0.0), Deg(p.rot));
+    fn zoom(&mut self, notches: f32) {
+        self.set_camera_distance(
+            self.view.camera_distance * ZOOM_PER_NOTCH.powf(f64::from(notches)),
+        );
+        self.update_sun_pos();
+    }
 
-        let projection = perspective(Deg(90.0), width as f32 / height as f32, 0.01, 1000.0);
+    fn load_palette(&mut self, name: &str) {
+        match ColorPalette::load(name) {
+            Ok(colors) => self.colors = colors,
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    fn sun_place(&self) -> SunPlace {
+        SunPlace {
+            angle: f64::from(self.view.sun_angle),
+            season: f64::from(self.season),
+        }
+    }
+
+    /// From the sun angle and season, or from the N-body simulation while
+    /// it runs.
+    fn update_sun_pos(&mut self) {
+        let direction = self
+            .nbody
+            .sun_direction()
+            .unwrap_or_else(|| self.sun_place().direction(&self.body));
+        self.sun_pos = self.planet_pos + direction * self.sun_distance;
+    }
+
+    /// Straight up from the surface view, in the sky-fixed frame.
+    fn surface_up(&self) -> Vector3<f64> {
+        let up = self.view.rotation * self.surface.up();
+        vec3(f64::from(up.x), f64::from(up.y), f64::from(up.z))
+    }
+
+    /// Radius of the cloud shell relative to the planet, at the top of the
+    /// atmosphere.
+    fn cloud_scale(&self) -> f32 {
+        let height_km = self
+            .atmosphere
+            .map_or(0.0, |atmosphere| atmosphere.height_km);
+        (1.0 + height_km / self.body.planet_radius_km) as f32
+    }
+
+    fn atmosphere_density(&self) -> f32 {
+        self.atmosphere
+            .map_or(0.0, |atmosphere| atmosphere.density_at(self.altitude_km()))
+    }
+
+    /// The latest GPU time of every timed pass, in drawing order.
+    fn gpu_spans(&self) -> Vec<Span> {
+        let timers = &self.pass_timers;
+        [
+            ("shadowmap pass", &timers.shadowmap),
+            ("planet pass", &timers.planet),
+            ("vegetation pass", &self.vegetation.timer),
+            ("star pass", &timers.stars),
+            ("label pass", &timers.labels),
+            ("cloud pass", &timers.clouds),
+            ("overlay pass", &timers.overlays),
+        ]
+        .iter()
+        .map(|&(name, timer)| Span {
+            name,
+            milliseconds: timer.milliseconds,
+        })
+        .collect()
+    }
+
+    fn shadowmap_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let resolution = self.shadow_resolution.max(1);
+        (resolution * width.max(1), resolution * height.max(1))
+    }
+
+    fn altitude_km(&self) -> f64 {
+        let distance = f64::from(self.camera_local().magnitude());
+        self.scale.to_km(distance) - self.body.planet_radius_km
+    }
+
+    /// The camera of this frame, looking through `view_matrix`.
+    fn camera(&self) -> Camera {
+        // Standing on the ground needs a near plane well below eye height,
+        // until the satellite view has left it
+        let near = if self.surface.enabled && !self.satellite.is_riding() {
+            self.eye_height() * 0.5
+        } else {
+            // Zoomed in close, the near plane comes in to stay in front of
+            // the cloud shell, or the ground once under it
+            let distance = self.camera_local().magnitude();
+            let cloud_radius = self.cloud_scale() * PLANET_RADIUS;
+            let clearance = if distance > cloud_radius {
+                distance - cloud_radius
+            } else {
+                distance - PLANET_RADIUS
+            };
+            (0.5 * clearance).clamp(
+                self.scale.to_render(MIN_NEAR_PLANE_KM) as f32,
+                self.scale.to_render(NEAR_PLANE_KM) as f32,
+            )
+        };
+
+        Camera::from_view(
+            self.view_matrix(),
+            self.view.shown_fov + self.entry.fov_widening(),
+            near,
+            self.scale.to_render(FAR_PLANE_KM) as f32,
+        )
+    }
+
+    fn eye_height(&self) -> f32 {
+        self.scale.to_render(f64::from(self.surface.eye_height_km)) as f32
+    }
+
+    fn view_matrix(&self) -> Matrix4<f32> {
+        let base = self.mode_view_matrix();
+        if !self.satellite.is_active() {
+            return base;
+        }
+
+        self.satellite.view_matrix(
+            base,
+            &self.satellite_state(),
+            self.camera_relative(self.planet_pos),
+            self.scale,
+        )
+    }
+
+    fn satellite_state(&self) -> SatelliteState {
+        self.satellite.state(&self.body, self.view.rotation)
+    }
+
+    // The view of the surface, spacecraft and orbit cameras, which the
+    // satellite view blends from
+    fn mode_view_matrix(&self) -> Matrix4<f32> {
+        if self.fly.enabled {
+            return self.fly.view_matrix();
+        }
+
+        let follow = if self.spacecraft.settings.follow {
+            self.spacecraft.position()
+        } else {
+            None
+        };
+
+        match follow {
+            // Keep the eye where it is and turn it to track the spacecraft
+            Some(position) => {
+                let planet_matrix = self.planet_matrix();
+                let target = (planet_matrix * position.extend(1.0)).truncate();
+                let (eye, up) = if self.surface.enabled {
+                    let eye = self.surface.eye(PLANET_RADIUS, self.eye_height());
+                    (
+                        (planet_matrix * eye.extend(1.0)).truncate(),
+                        (planet_matrix * self.surface.up().extend(0.0)).truncate(),
+                    )
+                } else {
+                    (vec3(0.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0))
+                };
+                Matrix4::look_at(Point3::from_vec(eye), Point3::from_vec(target), up)
+            }
+            None if self.surface.enabled => {
+                self.surface
+                    .view_matrix(self.planet_matrix(), PLANET_RADIUS, self.eye_height())
+            }
+            None => self.orbit_view_matrix(),
+        }
+    }
+
+    /// The eye swung around the planet's center by the orbit yaw and pitch,
+    /// keeping its distance, then moved sideways by the pan.
+    fn orbit_view_matrix(&self) -> Matrix4<f32> {
+        let center = self.camera_relative(self.planet_pos);
+        let pan = self.view.pan * self.view.camera_distance as f32;
+        Matrix4::from_translation(-pan.extend(0.0))
+            * Matrix4::from_translation(center)
+            * Matrix4::from_angle_x(Deg(self.view.orbit_pitch))
+            * Matrix4::from_angle_y(Deg(self.view.orbit_yaw))
+            * Matrix4::from_translation(-center)
+    }
+
+    fn orbit_drag(&mut self, dx: f32, dy: f32) {
+        self.inertia
+            .add_orbit(dx * ORBIT_DRAG_SPEED, dy * ORBIT_DRAG_SPEED);
+    }
+
+    /// Moves the view so what is at the planet's distance follows the
+    /// cursor.
+    fn pan_drag(&mut self, dx: f32, dy: f32) {
+        let height = self.framebuffer_size.1.max(1) as f32;
+        let per_pixel = 2.0 * (0.5 * self.view.shown_fov).to_radians().tan() / height;
+        // Pixel rows go down, view space y goes up
+        self.view.pan.x = (self.view.pan.x - dx * per_pixel).clamp(-MAX_PAN, MAX_PAN);
+        self.view.pan.y = (self.view.pan.y + dy * per_pixel).clamp(-MAX_PAN, MAX_PAN);
+    }
+
+    /// Moves the orbit camera and turns the planet by this frame's share of
+    /// the input, see `Inertia`.
+    fn apply_inertia(&mut self, dt: f32) {
+        let step = self.inertia.step(dt, self.arcball_pos.is_some());
+
+        self.view.orbit_yaw = (self.view.orbit_yaw + step.yaw) % 360.0;
+        self.view.orbit_pitch = (self.view.orbit_pitch + step.pitch).clamp(-89.0, 89.0);
+        if step.zoom != 0.0 {
+            self.zoom(step.zoom);
+        }
+        self.view.rotation = arcball::compose(step.turn, self.view.rotation);
+        self.view.rotation = arcball::spin(self.view.rotation, Deg(step.spin));
+    }
+
+    // The stars stay fixed in space while standing on the surface, so the
+    // planet's spin turns the sky
+    fn star_matrix(&self) -> Matrix4<f32> {
+        if self.surface.enabled {
+            Matrix4::from_translation(self.camera_relative(self.planet_pos))
+        } else {
+            self.planet_matrix()
+        }
+    }
+
+    fn camera_relative(&self, pos: Vector3<f64>) -> Vector3<f32> {
+        let relative = pos - self.camera_pos;
+        vec3(relative.x as f32, relative.y as f32, relative.z as f32)
+    }
+
+    fn planet_matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.camera_relative(self.planet_pos))
+            * Matrix4::from(self.view.rotation)
+    }
+
+    // The camera sits at the origin of view space, in planet-local space it's
+    // wherever the inverse of the view and planet transforms put it
+    fn camera_local(&self) -> Vector3<f32> {
+        (self.view_matrix() * self.planet_matrix())
+            .invert()
+            .map_or(vec3(0.0, 0.0, 0.0), |inverse| inverse.w.truncate())
+    }
+}
+
+fn run_action(p: &mut State, action: Action) {
+    match action {
+        Action::OpenCommandPalette => p.palette.open(),
+        Action::ReloadShaders => p.force_shader_reload = true,
+        Action::SaveDebugCapture => p.capture_requested = true,
+        Action::SaveScreenshot => p.screenshot_requested = true,
+        Action::SaveSceneScreenshot => p.scene_screenshot_requested = true,
+        Action::ToggleRecording => {
+            if p.recording.is_running() {
+                p.recording.stop();
+            } else if let Err(e) = p.recording.start(Path::new("recordings")) {
+                p.recording.error = Some(e.to_string());
+            }
+        }
+        Action::RunBenchmark => p.benchmark_requested = true,
+        Action::StartTimeLapse => {
+            let started = p.time_lapse.start(
+                &mut p.screenshots,
+                Path::new("timelapses"),
+                p.view.sun_angle,
+                p.framebuffer_size,
+            );
+            if let Err(e) = started {
+                println!("Starting the time-lapse failed: {}", e);
+            }
+        }
+        Action::ApplyPreset(index) => p.apply_preset(index),
+        Action::ApplyWorkspace(index) => p.workspaces.apply(index),
+        Action::ToggleSurfaceView => {
+            // The orbit camera is left as it is, to go back to after
+            if !p.surface.enabled {
+                p.set_free_fly(false);
+            }
+            p.surface.enabled = !p.surface.enabled;
+        }
+        Action::ToggleDetail => p.detail.enabled = !p.detail.enabled,
+        Action::ToggleVegetation => p.vegetation.settings.enabled = !p.vegetation.settings.enabled,
+        Action::RegenerateVegetation => p.vegetation.regenerate(),
+        Action::ToggleMeteors => p.meteors.settings.enabled = !p.meteors.settings.enabled,
+        Action::MeteorStorm => p.meteors.start_storm(),
+        Action::ToggleMagnetosphere => {
+            p.magnetosphere.settings.enabled = !p.magnetosphere.settings.enabled
+        }
+        Action::LaunchSpacecraft => {
+            // Launch from under the surface view, heading where it looks
+            let altitude = p
+                .scale
+                .to_render(f64::from(p.spacecraft.settings.orbit_altitude_km));
+            p.spacecraft.launch(
+                p.surface.up(),
+                p.surface.heading_dir(),
+                PLANET_RADIUS,
+                altitude as f32,
+            );
+        }
+        Action::ToggleFollowSpacecraft => {
+            p.spacecraft.settings.follow = !p.spacecraft.settings.follow
+        }
+        Action::ToggleSatelliteView => p.satellite.enabled = !p.satellite.enabled,
+        Action::ToggleFreeFly => p.set_free_fly(!p.fly.enabled),
+        Action::ToggleMouseLook => p.mouse_look.enabled = !p.mouse_look.enabled,
+        Action::ResetView => p.reset_view(),
+        Action::ToggleFullscreen => p.fullscreen = !p.fullscreen,
+        Action::TogglePause => p.clock.toggle_pause(),
+        Action::StepFrame => p.clock.step_frame(),
+        Action::RecallBookmark(index) => p.recall_bookmark(index),
+        Action::ToggleOcclusionSkip => p.occlusion_auto_skip = !p.occlusion_auto_skip,
+        Action::ToggleLabels => p.labels.settings.enabled = !p.labels.settings.enabled,
+    }
+}
+
+fn update_ui<'a>(ui: &Ui<'a>, p: &mut State) {
+    // First so every other window is on top of the labels
+    draw_screen_labels(ui, p);
+
+    if let Some(index) = p.workspaces.draw_menu(ui) {
+        run_action(p, Action::ApplyWorkspace(index));
+    }
+
+    ui.main_menu_bar(|| {
+        ui.menu(im_str!("Debug")).build(|| {
+            if ui.menu_item(im_str!("Save session now")).build() {
+                p.session.save();
+            }
+            if ui.menu_item(im_str!("Write key bindings")).build() {
+                match p.bindings.save() {
+                    Ok(()) => println!("Saved the key bindings to bindings.toml"),
+                    Err(e) => println!("Saving the key bindings failed: {}", e),
+                }
+            }
+            if ui.menu_item(im_str!("Simulate panic")).build() {
+                panic!("Simulated panic from the debug menu");
+            }
+        });
+    });
+
+    update_restore_prompt(ui, p);
+
+    if p.satellite.is_active() {
+        draw_satellite_hud(ui, p);
+    }
+
+    let cond = p.workspaces.window_cond();
+
+    let stats = p.workspaces.current.stats;
+    if stats.open {
+        let mut open = true;
+        ui.window(im_str!("Stats"))
+            .position(stats.pos, cond)
+            .size(stats.size, cond)
+            .opened(&mut open)
+            .build(|| {
+                let frame_ms = p.average_frame_time * 1000.0;
+                // Most of a frame spent in the swap is waiting for the display
+                let held_by = if p.frame_limiter.is_limiting() {
+                    ", held by the frame limiter"
+                } else if p.window_vsync && p.swap_ms > 0.25 * frame_ms {
+                    ", held by vsync"
+                } else {
+                    ""
+                };
+                ui.text(im_str!(
+                    "{:.1} fps ({:.1} ms){}",
+                    1.0 / p.average_frame_time,
+                    frame_ms,
+                    held_by,
+                ));
+                ui.slider_float(
+                    im_str!("Longest frame step (s)"),
+                    &mut p.max_frame_step,
+                    0.01,
+                    1.0,
+                )
+                .build();
+                ui.text(im_str!("Frames clamped: {}", p.clamped_frames));
+                ui.checkbox(im_str!("VSync"), &mut p.vsync);
+                ui.checkbox(im_str!("Limit frame rate"), &mut p.frame_limiter.enabled);
+                ui.slider_float(
+                    im_str!("Target fps"),
+                    &mut p.frame_limiter.target_fps,
+                    10.0,
+                    240.0,
+                )
+                .build();
+
+                ui.text(im_str!(
+                    "UI has the mouse: {}, the keyboard: {}",
+                    if p.ui_wants_mouse { "yes" } else { "no" },
+                    if p.ui_wants_keyboard { "yes" } else { "no" },
+                ));
+
+                let degraded = p.gl.degraded();
+                ui.text(im_str!(
+                    "OpenGL {}.{}{}, degraded: {}",
+                    p.gl.version.0,
+                    p.gl.version.1,
+                    if p.gl.limited { " (--max-gl)" } else { "" },
+                    if degraded.is_empty() {
+                        "nothing".to_string()
+                    } else {
+                        degraded.join(", ")
+                    },
+                ));
+
+                ui.text(im_str!(
+                    "Vegetation: {} instances, {} above the horizon ({:.2} ms){}",
+                    p.vegetation.instance_count(),
+                    p.vegetation.count_above_horizon(
+                        p.camera_local(),
+                        p.geometry.horizon_radius(PLANET_RADIUS),
+                    ),
+                    p.vegetation.timer.milliseconds,
+                    if p.vegetation.is_generating() {
+                        ", generating"
+                    } else {
+                        ""
+                    },
+                ));
+
+                let streams = p.line_pool.stats().add(p.meteors.pool.stats());
+                ui.text(im_str!(
+                    "Streamed: {:.1} KiB a frame, pools {:.0}% in use, {:.1} KiB overflow",
+                    streams.streamed_bytes as f32 / 1024.0,
+                    100.0 * streams.used_bytes as f32 / streams.capacity_bytes.max(1) as f32,
+                    streams.overflow_bytes as f32 / 1024.0,
+                ));
+
+                if ui.button(im_str!("Run benchmark"), (0.0, 0.0)) {
+                    run_action(p, Action::RunBenchmark);
+                }
+
+                if ui.collapsing_header(im_str!("Stutter log")).build() {
+                    update_stutter_log(ui, p);
+                }
+
+                if ui.collapsing_header(im_str!("Session")).build() {
+                    update_session(ui, p);
+                }
+
+                let layout = &mut p.workspaces.current.stats;
+                layout.pos = ui.get_window_pos();
+                layout.size = ui.get_window_size();
+            });
+        p.workspaces.current.stats.open = open;
+    }
+
+    let planet = p.workspaces.current.planet;
+    if planet.open {
+        let mut open = true;
+        ui.window(im_str!("Planet"))
+            .position(planet.pos, cond)
+            .size(planet.size, cond)
+            .opened(&mut open)
+            .build(|| {
+                update_planet_window(ui, p);
+
+                let layout = &mut p.workspaces.current.planet;
+                layout.pos = ui.get_window_pos();
+                layout.size = ui.get_window_size();
+            });
+        p.workspaces.current.planet.open = open;
+    }
+
+    if p.benchmark.is_running() || p.benchmark.report.is_some() {
+        update_benchmark_window(ui, p);
+    }
+
+    if let Some(action) = p.palette.draw(ui, &Action::all(), &p.bindings) {
+        run_action(p, action);
+    }
+}
+
+/// Draws the labels that are overlays rather than part of the scene, at
+/// where their anchors project to.
+fn draw_screen_labels<'a>(ui: &Ui<'a>, p: &State) {
+    if !p.labels.settings.enabled {
+        return;
+    }
+
+    // Where the UI draws, in logical pixels
+    let (width, height) = ui.frame_size().logical_size;
+    let (width, height) = (width as f32, (height as f32).max(1.0));
+    let camera = p.camera();
+    let mvp = camera.projection_matrix(width / height)
+        * p.entry.shake_matrix()
+        * camera.view_matrix()
+        * p.planet_matrix();
+    let color = p.labels.settings.color;
+    let transparent = (0.0, 0.0, 0.0, 0.0);
+
+    ui.with_color_vars(
+        &[
+            (ImGuiCol::WindowBg, transparent),
+            (ImGuiCol::Border, transparent),
+        ],
+        || {
+            ui.window(im_str!("##screen_labels"))
+                .position((0.0, 0.0), ImGuiCond::Always)
+                .size((width, height), ImGuiCond::Always)
+                .title_bar(false)
+                .resizable(false)
+                .movable(false)
+                .scroll_bar(false)
+                .inputs(false)
+                .save_settings(false)
+                .no_bring_to_front_on_focus(true)
+                .build(|| {
+                    let draw_list = ui.get_window_draw_list();
+                    for label in p.labels.labels.iter().filter(|label| !label.world_space) {
+                        let altitude = p.scale.to_render(f64::from(label.altitude_km)) as f32;
+                        let clip = mvp * label.anchor(PLANET_RADIUS, altitude).extend(1.0);
+                        if clip.w <= 0.0 {
+                            continue;
+                        }
+
+                        let size = ui.calc_text_size(&label.text, false, -1.0);
+                        let x = (0.5 + 0.5 * clip.x / clip.w) * width - size.x / 2.0;
+                        let y = (0.5 - 0.5 * clip.y / clip.w) * height - size.y / 2.0;
+                        draw_list.add_text((x, y), color, label.text.to_str());
+                    }
+                });
+        },
+    );
+}
+
+/// Where the satellite being ridden is and how fast it goes, top center.
+fn draw_satellite_hud<'a>(ui: &Ui<'a>, p: &State) {
+    let state = p.satellite_state();
+    let name = SATELLITES[p.satellite.satellite as usize % SATELLITES.len()].name;
+    let width = ui.frame_size().logical_size.0 as f32;
+
+    ui.window(im_str!("##satellite_hud"))
+        .position((width / 2.0 - 150.0, 30.0), ImGuiCond::Always)
+        .size((300.0, 0.0), ImGuiCond::Always)
+        .title_bar(false)
+        .resizable(false)
+        .movable(false)
+        .always_auto_resize(true)
+        .save_settings(false)
+        .build(|| {
+            ui.text(im_str!(
+                "{}, {}, time warp {:.0}x",
+                name,
+                if p.satellite.nadir {
+                    "nadir"
+                } else {
+                    "along-track"
+                },
+                p.satellite.time_warp,
+            ));
+            ui.text(im_str!("Altitude: {:.1} km", state.altitude_km));
+            ui.text(im_str!(
+                "Orbital velocity: {:.3} km/s, over the ground {:.3} km/s",
+                state.speed,
+                state.ground_speed,
+            ));
+            ui.text(im_str!(
+                "Below: {:.2}\u{b0}{}, {:.2}\u{b0}{}",
+                state.latitude.abs(),
+                if state.latitude >= 0.0 { "N" } else { "S" },
+                state.longitude.abs(),
+                if state.longitude >= 0.0 { "E" } else { "W" },
+            ));
+        });
+}
+
+fn update_benchmark_window<'a>(ui: &Ui<'a>, p: &mut State) {
+    let mut open = true;
+    ui.window(im_str!("Benchmark"))
+        .position((400.0, 60.0), ImGuiCond::FirstUseEver)
+        .size((460.0, 300.0), ImGuiCond::FirstUseEver)
+        .opened(&mut open)
+        .build(|| {
+            if p.benchmark.is_running() {
+                ui.text(im_str!("Running the {} s camera path", benchmark::DURATION));
+                ui.progress_bar(p.benchmark.progress()).build();
+                return;
+            }
+
+            let report = match p.benchmark.report {
+                Some(ref report) => report,
+                None => return,
+            };
+
+            ui.text(im_str!(
+                "{}, {}x{}",
+                report.renderer,
+                report.resolution.0,
+                report.resolution.1
+            ));
+            ui.text(im_str!(
+                "Settings hash {:016x}, {} frames",
+                report.settings_hash,
+                report.frames
+            ));
+            ui.text(im_str!(
+                "{:.1} fps average, {:.1} fps 1% low",
+                report.average_fps,
+                report.low_fps
+            ));
+            ui.text(im_str!(
+                "CPU {:.2} ms, GPU {:.2} ms per frame",
+                report.average_cpu_ms,
+                report.average_gpu_ms
+            ));
+            if let Some(ref golden) = report.golden {
+                ui.text(im_str!(
+                    "Golden image: {}",
+                    benchmark::describe_golden(golden)
+                ));
+            }
+            ui.separator();
+
+            ui.columns(3, im_str!("benchmark_passes"), true);
+            ui.text(im_str!("GPU pass"));
+            ui.next_column();
+            ui.text(im_str!("Average (ms)"));
+            ui.next_column();
+            ui.text(im_str!("Max (ms)"));
+            ui.next_column();
+            ui.separator();
+            for pass in &report.passes {
+                ui.text(im_str!("{}", pass.name));
+                ui.next_column();
+                ui.text(im_str!("{:.3}", pass.average_ms));
+                ui.next_column();
+                ui.text(im_str!("{:.3}", pass.max_ms));
+                ui.next_column();
+            }
+            ui.columns(1, im_str!("benchmark_passes"), false);
+            ui.separator();
+
+            ui.text(im_str!("Saved to {}", report.path.display()));
+        });
+
+    if !open {
+        p.benchmark.report = None;
+    }
+}
+
+/// Offers the autosave of a session that didn't shut down cleanly, once at
+/// startup.
+fn update_restore_prompt<'a>(ui: &Ui<'a>, p: &mut State) {
+    let popup: &ImStr = im_str!("Restore session?");
+    let saved_at = match p.session.restorable {
+        Some(ref restorable) => restorable.saved_at,
+        None => return,
+    };
+    ui.open_popup(popup);
+
+    ui.popup_modal(popup).always_auto_resize(true).build(|| {
+        let ago = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs().saturating_sub(saved_at));
+        ui.text(im_str!(
+            "The last session didn't shut down cleanly.\nRestore its autosave from {} min ago?",
+            ago / 60
+        ));
+
+        if ui.button(im_str!("Restore"), (0.0, 0.0)) {
+            if let Some(restorable) = p.session.restorable.take() {
+                if let Err(e) = capture::restore(p, &restorable.settings) {
+                    println!("Restoring the session failed: {}", e);
+                }
+            }
+            ui.close_current_popup();
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Discard"), (0.0, 0.0)) {
+            p.session.restorable = None;
+            ui.close_current_popup();
+        }
+    });
+}
+
+fn update_session<'a>(ui: &Ui<'a>, p: &mut State) {
+    ui.slider_float(
+        im_str!("Autosave every (s)"),
+        &mut p.session.interval,
+        5.0,
+        300.0,
+    )
+    .build();
+
+    match p.session.last_save {
+        Some(saved_at) => {
+            let ago = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs().saturating_sub(saved_at));
+            ui.text(im_str!(
+                "Saved {} s ago{}",
+                ago,
+                if p.session.is_dirty() {
+                    ", changed since"
+                } else {
+                    ""
+                }
+            ));
+        }
+        None => ui.text(im_str!("Not saved yet")),
+    }
+    if let Some(ref error) = p.session.error {
+        ui.text_colored((1.0, 0.4, 0.4, 1.0), im_str!("Autosave failed: {}", error));
+    }
+
+    if ui.button(im_str!("Save now"), (0.0, 0.0)) {
+        p.session.save();
+    }
+}
+
+fn update_stutter_log<'a>(ui: &Ui<'a>, p: &mut State) {
+    ui.text(im_str!(
+        "Frames over 2x the median ({:.1} ms) are logged",
+        p.stutter.median()
+    ));
+
+    if ui.button(im_str!("Inject 30 ms stall"), (0.0, 0.0)) {
+        p.stutter.inject_stall = true;
+    }
+    ui.same_line(0.0);
+    if ui.button(im_str!("Export JSON"), (0.0, 0.0)) {
+        match p.stutter.export("stutter_log.json") {
+            Ok(()) => println!("Saved stutter log to stutter_log.json"),
+            Err(e) => println!("Exporting the stutter log failed: {}", e),
+        }
+    }
+    ui.same_line(0.0);
+    if ui.button(im_str!("Clear"), (0.0, 0.0)) {
+        p.stutter.entries.clear();
+    }
+
+    for entry in &p.stutter.entries {
+        let label = ImString::new(format!(
+            "Frame {}: {:.1} ms (median {:.1} ms)",
+            entry.frame, entry.milliseconds, entry.median
+        ));
+        ui.tree_node(&label).build(|| {
+            for span in &entry.spans {
+                ui.text(im_str!("{}: {:.2} ms", span.name, span.milliseconds));
+            }
+            for span in &entry.gpu {
+                ui.text(im_str!("GPU {}: {:.2} ms", span.name, span.milliseconds));
+            }
+        });
+    }
+}
+
+/// A character that turns a step every tenth of a second of `elapsed`.
+fn spinner(elapsed: Duration) -> char {
+    ['|', '/', '-', '\\'][(elapsed.as_millis() / 100 % 4) as usize]
+}
+
+fn update_planet_window<'a>(ui: &Ui<'a>, p: &mut State) {
+    if ui.button(
+        &ImString::new(p.bindings.label("Commands", Action::OpenCommandPalette)),
+        (0.0, 0.0),
+    ) {
+        run_action(p, Action::OpenCommandPalette);
+    }
+    ui.same_line(0.0);
+    if ui.button(
+        &ImString::new(p.bindings.label("Debug capture", Action::SaveDebugCapture)),
+        (0.0, 0.0),
+    ) {
+        run_action(p, Action::SaveDebugCapture);
+    }
+    ui.same_line(0.0);
+    if ui.button(
+        &ImString::new(p.bindings.label("Screenshot", Action::SaveScreenshot)),
+        (0.0, 0.0),
+    ) {
+        run_action(p, Action::SaveScreenshot);
+    }
+    ui.same_line(0.0);
+    if ui.button(
+        &ImString::new(p.bindings.label("Without UI", Action::SaveSceneScreenshot)),
+        (0.0, 0.0),
+    ) {
+        run_action(p, Action::SaveSceneScreenshot);
+    }
+    ui.same_line(0.0);
+    if ui.button(
+        &ImString::new(p.bindings.label("Reset view", Action::ResetView)),
+        (0.0, 0.0),
+    ) {
+        run_action(p, Action::ResetView);
+    }
+    ui.same_line(0.0);
+    let mut fullscreen = p.fullscreen;
+    if ui.checkbox(
+        &ImString::new(p.bindings.label("Fullscreen", Action::ToggleFullscreen)),
+        &mut fullscreen,
+    ) {
+        run_action(p, Action::ToggleFullscreen);
+    }
+
+    let pause_label = if p.clock.paused { "Resume" } else { "Pause" };
+    if ui.button(
+        &ImString::new(p.bindings.label(pause_label, Action::TogglePause)),
+        (0.0, 0.0),
+    ) {
+        run_action(p, Action::TogglePause);
+    }
+    ui.same_line(0.0);
+    if ui.button(im_str!("Step one frame"), (0.0, 0.0)) {
+        run_action(p, Action::StepFrame);
+    }
+    ui.same_line(0.0);
+    ui.text(im_str!("Sim time {:.1} s", p.clock.time));
+    ui.slider_float(im_str!("Time scale"), &mut p.clock.scale, 0.0, 10.0)
+        .build();
+
+    if ui
+        .slider_float(im_str!("Sun Angle"), &mut p.view.sun_angle, -180.0, 180.0)
+        .build()
+    {
+        p.update_sun_pos();
+    }
+    let mut held = ui.is_item_active();
+    ui.checkbox(im_str!("Auto-rotate"), &mut p.auto_rotate.enabled);
+    ui.slider_float(
+        im_str!("Rotation speed (deg/s)"),
+        &mut p.auto_rotate.speed_deg_per_sec,
+        -90.0,
+        90.0,
+    )
+    .build();
+    if ui
+        .slider_float(im_str!("Season"), &mut p.season, 0.0, 360.0)
+        .display_format(im_str!("%.0f deg from spring equinox"))
+        .build()
+    {
+        p.update_sun_pos();
+    }
+    held |= ui.is_item_active();
+    p.sun_cycle.held = held;
+
+    ui.checkbox(im_str!("Animate sun"), &mut p.sun_cycle.enabled);
+    ui.slider_float(im_str!("Sun period"), &mut p.sun_cycle.period, 1.0, 3600.0)
+        .power(3.0)
+        .display_format(im_str!("%.0f s per turn"))
+        .build();
+    ui.checkbox(im_str!("Animate seasons"), &mut p.sun_cycle.seasons);
+    ui.slider_float(
+        im_str!("Season period"),
+        &mut p.sun_cycle.season_period,
+        10.0,
+        36_000.0,
+    )
+    .power(3.0)
+    .display_format(im_str!("%.0f s per year"))
+    .build();
+
+    let mut preset = p.preset;
+    let names: Vec<_> = PRESETS
+        .iter()
+        .map(|preset| ImString::new(preset.name))
+        .collect();
+    let items: Vec<&ImStr> = names.iter().map(|name| name.as_ref()).collect();
+    if ui.combo(im_str!("Preset"), &mut preset, &items, -1) {
+        p.apply_preset(preset as usize);
+    }
+
+    update_quality(ui, p);
+
+    ui.slider_float(im_str!("Field of view"), &mut p.view.fov, 20.0, 120.0)
+        .display_format(im_str!("%.0f deg"))
+        .build();
+
+    ui.text(im_str!("Sun Pos: {:?}", &p.sun_pos));
+    ui.text(im_str!(
+        "Altitude: {:.0} km ({:.4} units), sun distance: {:.3} AU",
+        p.altitude_km(),
+        p.scale.to_render(p.altitude_km()),
+        units::km_to_au(p.scale.to_km(p.sun_distance)),
+    ));
+
+    ui.checkbox(im_str!("Pick under cursor"), &mut p.picking);
+    if p.picking {
+        let picked = if p.ui_wants_mouse {
+            None
+        } else {
+            p.pick(p.mouse_state.pixel())
+        };
+        match picked {
+            Some(point) => {
+                let (latitude, longitude) = orbit::sub_point(point.map(f64::from));
+                let uv = sphere_uv(point);
+                let cell = p.geometry.grid.cell_at(point);
+                ui.text(im_str!(
+                    "Cursor: {:.2}\u{b0}{}, {:.2}\u{b0}{}, tex ({:.4}, {:.4})",
+                    latitude.abs(),
+                    if latitude >= 0.0 { "N" } else { "S" },
+                    longitude.abs(),
+                    if longitude >= 0.0 { "E" } else { "W" },
+                    uv[0],
+                    uv[1]
+                ));
+                ui.text(im_str!(
+                    "Cell {} of {}, value {:.3}",
+                    cell,
+                    p.geometry.grid.len(),
+                    p.geometry.grid.values[cell]
+                ));
+            }
+            // The default font has no em dash
+            None => ui.text(im_str!("Cursor: -")),
+        }
+    }
+
+    if ui.collapsing_header(im_str!("Occlusion")).build() {
+        ui.checkbox(
+            im_str!("Skip passes with nothing visible"),
+            &mut p.occlusion_auto_skip,
+        );
+
+        let pixels = max(p.framebuffer_size.0 * p.framebuffer_size.1, 1);
+        for &(name, occlusion) in &[
+            ("Clouds", &p.cloud_occlusion),
+            ("Vegetation", &p.vegetation_occlusion),
+        ] {
+            ui.text(im_str!(
+                "{}: {} samples ({:.1}% of screen){}",
+                name,
+                occlusion.samples,
+                100.0 * occlusion.samples as f32 / pixels as f32,
+                if occlusion.is_skipped() {
+                    ", skipped"
+                } else {
+                    ""
+                },
+            ));
+        }
+    }
+
+    if ui.collapsing_header(im_str!("Surface")).build() {
+        let surface = &mut p.surface;
+        let mut enabled = surface.enabled;
+        if ui.checkbox(
+            &ImString::new(
+                p.bindings
+                    .label("Stand on the surface", Action::ToggleSurfaceView),
+            ),
+            &mut enabled,
+        ) {
+            run_action(p, Action::ToggleSurfaceView);
+        }
+        let surface = &mut p.surface;
+        ui.text(im_str!("W/A/S/D to walk, shift to run, drag to look"));
+        ui.slider_float(im_str!("Latitude"), &mut surface.latitude, -90.0, 90.0)
+            .build();
+        ui.slider_float(im_str!("Longitude"), &mut surface.longitude, -180.0, 180.0)
+            .build();
+        ui.slider_float(im_str!("Heading"), &mut surface.heading, -180.0, 180.0)
+            .build();
+        ui.slider_float(im_str!("Pitch"), &mut surface.pitch, -80.0, 89.0)
+            .build();
+        ui.slider_float(
+            im_str!("Eye height (km)"),
+            &mut surface.eye_height_km,
+            0.01,
+            100.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Walking speed (km/s)"),
+            &mut surface.walk_speed_km,
+            0.01,
+            1000.0,
+        )
+        .power(3.0)
+        .build();
+
+        update_celestial(ui, p);
+    }
+
+    if ui.collapsing_header(im_str!("Meteors")).build() {
+        ui.text(im_str!(
+            "{} meteors{}",
+            p.meteors.count(),
+            if p.meteors.is_storming() {
+                ", storm"
+            } else {
+                ""
+            },
+        ));
+
+        let settings = &mut p.meteors.settings;
+        ui.checkbox(im_str!("Meteors"), &mut settings.enabled);
+        ui.slider_int(im_str!("Meteor seed"), &mut settings.seed, 0, 100)
+            .build();
+        ui.slider_float(
+            im_str!("Meteor rate (per s)"),
+            &mut settings.rate,
+            0.0,
+            20.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Radiant latitude"),
+            &mut settings.radiant_latitude,
+            -90.0,
+            90.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Radiant longitude"),
+            &mut settings.radiant_longitude,
+            -180.0,
+            180.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Meteor speed (km/s)"),
+            &mut settings.speed_km,
+            10.0,
+            2000.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Meteor width (km)"),
+            &mut settings.width_km,
+            1.0,
+            100.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Meteor brightness"),
+            &mut settings.brightness,
+            0.0,
+            4.0,
+        )
+        .build();
+
+        if ui.button(im_str!("Meteor storm"), (0.0, 0.0)) {
+            run_action(p, Action::MeteorStorm);
+        }
+    }
+
+    if ui.collapsing_header(im_str!("Magnetosphere")).build() {
+        let settings = &mut p.magnetosphere.settings;
+        ui.checkbox(im_str!("Field lines"), &mut settings.enabled);
+
+        let mut changed = false;
+        changed |= ui
+            .slider_int(im_str!("Field shells"), &mut settings.shells, 1, 12)
+            .build();
+        changed |= ui
+            .slider_int(
+                im_str!("Field lines per shell"),
+                &mut settings.lines_per_shell,
+                1,
+                48,
+            )
+            .build();
+        changed |= ui
+            .slider_float(
+                im_str!("Field extent (radii)"),
+                &mut settings.max_shell,
+                1.5,
+                20.0,
+            )
+            .build();
+        changed |= ui
+            .slider_float(
+                im_str!("Magnetic axis tilt"),
+                &mut settings.axis_tilt,
+                0.0,
+                90.0,
+            )
+            .build();
+        changed |= ui
+            .slider_float(
+                im_str!("Magnetic axis azimuth"),
+                &mut settings.axis_azimuth,
+                -180.0,
+                180.0,
+            )
+            .build();
+        ui.slider_float(
+            im_str!("Sunward compression"),
+            &mut settings.compression,
+            0.0,
+            0.9,
+        )
+        .build();
+        ui.slider_float(im_str!("Tail stretch"), &mut settings.tail, 0.0, 10.0)
+            .build();
+        ui.color_edit(im_str!("Field line color"), &mut settings.color)
+            .build();
+        ui.slider_float(
+            im_str!("Field line width (px)"),
+            &mut settings.line_width,
+            0.5,
+            8.0,
+        )
+        .build();
+
+        if changed {
+            p.magnetosphere.regenerate();
+        }
+    }
+
+    if ui.collapsing_header(im_str!("Atmosphere")).build() {
+        let preset = &p.body;
+        let mut has_atmosphere = p.atmosphere.is_some();
+        if ui.checkbox(im_str!("Has an atmosphere"), &mut has_atmosphere) {
+            // Switching it back on starts from the preset's, or Earth's
+            p.atmosphere = if has_atmosphere {
+                preset.atmosphere.or(PRESETS[1].atmosphere)
+            } else {
+                None
+            };
+        }
+
+        if let Some(ref mut atmosphere) = p.atmosphere {
+            let mut height_km = atmosphere.height_km as f32;
+            if ui
+                .slider_float(im_str!("Height (km)"), &mut height_km, 1.0, 2000.0)
+                .power(2.0)
+                .build()
+            {
+                atmosphere.height_km = f64::from(height_km);
+            }
+
+            let mut scale_height_km = atmosphere.scale_height_km as f32;
+            if ui
+                .slider_float(
+                    im_str!("Scale height (km)"),
+                    &mut scale_height_km,
+                    1.0,
+                    200.0,
+                )
+                .power(2.0)
+                .build()
+            {
+                atmosphere.scale_height_km = f64::from(scale_height_km);
+            }
+
+            ui.slider_float(im_str!("Cloud density"), &mut atmosphere.density, 0.0, 3.0)
+                .build();
+            ui.color_edit(im_str!("Dust tint"), &mut atmosphere.tint)
+                .build();
+        }
+
+        let settings = &mut p.atmosphere_shell.settings;
+        ui.checkbox(im_str!("Limb glow"), &mut settings.enabled);
+        ui.slider_float(
+            im_str!("Glow thickness"),
+            &mut settings.thickness,
+            0.005,
+            0.3,
+        )
+        .power(2.0)
+        .build();
+        ui.slider_float(im_str!("Glow intensity"), &mut settings.intensity, 0.0, 4.0)
+            .build();
+        ui.color_edit(im_str!("Glow tint"), &mut settings.tint)
+            .build();
+    }
+
+    if ui.collapsing_header(im_str!("Extra bodies")).build() {
+        update_extra_bodies(ui, p);
+    }
+
+    if ui.collapsing_header(im_str!("Ring")).build() {
+        let settings = &mut p.ring.settings;
+        ui.checkbox(im_str!("Draw ring"), &mut settings.enabled);
+        ui.slider_float(
+            im_str!("Inner radius"),
+            &mut settings.inner_radius,
+            1.0,
+            4.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Outer radius"),
+            &mut settings.outer_radius,
+            1.0,
+            4.0,
+        )
+        .build();
+        ui.slider_float(im_str!("Ring opacity"), &mut settings.opacity, 0.0, 1.0)
+            .build();
+    }
+
+    if ui.collapsing_header(im_str!("Wind")).build() {
+        ui.checkbox(im_str!("Advect clouds"), &mut p.wind.settings.enabled);
+        ui.slider_float(
+            im_str!("Simulated seconds per second"),
+            &mut p.wind.settings.time_scale,
+            0.0,
+            20000.0,
+        )
+        .power(2.0)
+        .build();
+        ui.text(im_str!("Zonal wind, m/s eastward against latitude"));
+        p.wind.draw_editor(ui, im_str!("##wind_curve"));
+    }
+
+    if ui.collapsing_header(im_str!("Entry effects")).build() {
+        ui.text(im_str!(
+            "Descent {:.2} km/s, density {:.3}, strength {:.2}",
+            p.entry.descent_speed_km,
+            p.atmosphere_density(),
+            p.entry.strength,
+        ));
+        ui.checkbox(
+            im_str!("Shake and FOV on entry"),
+            &mut p.entry.settings.enabled,
+        );
+        ui.slider_float(
+            im_str!("Entry effect intensity"),
+            &mut p.entry.settings.intensity,
+            0.0,
+            2.0,
+        )
+        .build();
+    }
+
+    if ui.collapsing_header(im_str!("Spacecraft")).build() {
+        let status = if p.spacecraft.is_ascending() {
+            "ascending"
+        } else if p.spacecraft.is_flying() {
+            "in orbit"
+        } else {
+            "on the ground"
+        };
+        ui.text(im_str!(
+            "Spacecraft {}, {:.2} km/s",
+            status,
+            p.scale.to_km(f64::from(p.spacecraft.speed())),
+        ));
+
+        if ui.button(im_str!("Launch"), (0.0, 0.0)) {
+            run_action(p, Action::LaunchSpacecraft);
+        }
+
+        let settings = &mut p.spacecraft.settings;
+        ui.checkbox(im_str!("Follow spacecraft"), &mut settings.follow);
+        ui.slider_float(
+            im_str!("Ascent duration (s)"),
+            &mut settings.ascent_duration,
+            1.0,
+            60.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Orbit altitude (km)"),
+            &mut settings.orbit_altitude_km,
+            100.0,
+            20_000.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Orbit period (s)"),
+            &mut settings.orbit_period,
+            5.0,
+            600.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Trail lifetime (s)"),
+            &mut settings.trail_lifetime,
+            1.0,
+            120.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Trail width (px)"),
+            &mut settings.trail_width,
+            0.5,
+            8.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Marker size (px)"),
+            &mut settings.marker_size,
+            1.0,
+            16.0,
+        )
+        .build();
+    }
+
+    if ui.collapsing_header(im_str!("N-body")).build() {
+        update_nbody(ui, p);
+    }
+
+    if ui.collapsing_header(im_str!("Scale model")).build() {
+        update_scale_model(ui, p);
+    }
+
+    if ui.collapsing_header(im_str!("Satellite view")).build() {
+        let mut enabled = p.satellite.enabled;
+        if ui.checkbox(im_str!("Ride satellite"), &mut enabled) {
+            run_action(p, Action::ToggleSatelliteView);
+        }
+
+        let names: Vec<_> = SATELLITES
+            .iter()
+            .map(|satellite| ImString::new(satellite.name))
+            .collect();
+        let items: Vec<&ImStr> = names.iter().map(|name| name.as_ref()).collect();
+        ui.combo(im_str!("Satellite"), &mut p.satellite.satellite, &items, -1);
+
+        let orbit = p.satellite.orbit(&p.body);
+        ui.text(im_str!(
+            "Period {:.1} min, eccentricity {:.3}",
+            orbit.period() / 60.0,
+            orbit.eccentricity,
+        ));
+
+        let view = &mut p.satellite;
+        ui.checkbox(im_str!("Look straight down"), &mut view.nadir);
+        ui.slider_float(im_str!("Time warp"), &mut view.time_warp, 1.0, 1000.0)
+            .power(3.0)
+            .build();
+        ui.slider_float(
+            im_str!("Blend duration (s)"),
+            &mut view.blend_duration,
+            0.0,
+            5.0,
+        )
+        .build();
+    }
+
+    if ui.collapsing_header(im_str!("Camera")).build() {
+        let closest = ui
+            .slider_float(
+                im_str!("Closest altitude (km)"),
+                &mut p.view.min_zoom_altitude_km,
+                2.0 * MIN_NEAR_PLANE_KM as f32,
+                10_000.0,
+            )
+            .power(3.0)
+            .build();
+        let farthest = ui
+            .slider_float(
+                im_str!("Farthest distance (radii)"),
+                &mut p.view.max_zoom_radii,
+                2.0,
+                1000.0,
+            )
+            .power(3.0)
+            .build();
+        if closest || farthest {
+            p.zoom(0.0);
+        }
+
+        let inertia = &mut p.inertia;
+        ui.checkbox(im_str!("Inertia"), &mut inertia.enabled);
+        ui.slider_float(im_str!("Damping (1/s)"), &mut inertia.damping, 1.0, 50.0)
+            .build();
+        ui.slider_float(
+            im_str!("Spin decay (1/s)"),
+            &mut inertia.spin_decay,
+            0.0,
+            10.0,
+        )
+        .build();
+    }
+
+    if ui.collapsing_header(im_str!("Bookmarks")).build() {
+        ui.input_text(im_str!("Name"), &mut p.bookmarks.save_name)
+            .build();
+        ui.same_line(0.0);
+        let name = p.bookmarks.save_name.to_str().trim().to_string();
+        if ui.button(im_str!("Save view"), (0.0, 0.0)) && !name.is_empty() {
+            let pose = p.view_pose();
+            p.bookmarks.add(&name, pose);
+            p.bookmarks.save_name.clear();
+        }
+
+        let mut recalled = None;
+        let mut removed = None;
+        for (i, bookmark) in p.bookmarks.list.iter().enumerate() {
+            let label = p.bindings.label(&bookmark.name, Action::RecallBookmark(i));
+            let label = im_str!("{}##recall{}", label, i);
+            if ui.button(label, (0.0, 0.0)) {
+                recalled = Some(i);
+            }
+            ui.same_line(0.0);
+            if ui.small_button(im_str!("Delete##delete{}", i)) {
+                removed = Some(i);
+            }
+        }
+        if let Some(index) = recalled {
+            run_action(p, Action::RecallBookmark(index));
+        }
+        if let Some(index) = removed {
+            p.bookmarks.remove(index);
+        }
+    }
+
+    if ui.collapsing_header(im_str!("Gamepad")).build() {
+        match p.gamepad.device {
+            Some(ref device) => ui.text(im_str!("Connected: {}", device.display())),
+            None => ui.text(im_str!("No gamepad connected")),
+        }
+        ui.text(im_str!(
+            "Left stick spins the planet, right stick orbits, triggers zoom, shoulders move the sun"
+        ));
+
+        let settings = &mut p.gamepad.settings;
+        ui.checkbox(im_str!("Read gamepad"), &mut settings.enabled);
+        ui.slider_float(im_str!("Dead zone"), &mut settings.dead_zone, 0.0, 0.5)
+            .build();
+        ui.checkbox(im_str!("Invert X"), &mut settings.invert_x);
+        ui.same_line(0.0);
+        ui.checkbox(im_str!("Invert Y"), &mut settings.invert_y);
+        ui.slider_float(
+            im_str!("Spin speed (deg/s)"),
+            &mut settings.spin_speed,
+            1.0,
+            360.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Orbit speed (deg/s)"),
+            &mut settings.orbit_speed,
+            1.0,
+            360.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Sun speed (deg/s)"),
+            &mut settings.sun_speed,
+            1.0,
+            180.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Zoom speed (notches/s)"),
+            &mut settings.zoom_speed,
+            1.0,
+            30.0,
+        )
+        .build();
+    }
+
+    if ui.collapsing_header(im_str!("Touchpad")).build() {
+        ui.text(im_str!(
+            "Two fingers orbit, pinching or Ctrl scrolling zooms, three fingers pan"
+        ));
+
+        let settings = &mut p.gestures.settings;
+        ui.checkbox(im_str!("Read gestures"), &mut settings.enabled);
+        ui.same_line(0.0);
+        ui.checkbox(im_str!("Invert##gestures"), &mut settings.invert);
+        ui.slider_float(
+            im_str!("Orbit (deg/pixel)"),
+            &mut settings.orbit_speed,
+            0.01,
+            2.0,
+        )
+        .power(2.0)
+        .build();
+        ui.slider_float(
+            im_str!("Pinch zoom (notches/pixel)"),
+            &mut settings.zoom_speed,
+            0.001,
+            0.2,
+        )
+        .power(2.0)
+        .build();
+        ui.slider_float(im_str!("Pan"), &mut settings.pan_speed, 0.1, 5.0)
+            .build();
+    }
+
+    if ui.collapsing_header(im_str!("Free fly")).build() {
+        let mut enabled = p.fly.enabled;
+        if ui.checkbox(
+            &ImString::new(p.bindings.label("Free-fly camera", Action::ToggleFreeFly)),
+            &mut enabled,
+        ) {
+            run_action(p, Action::ToggleFreeFly);
+        }
+        ui.text(im_str!(
+            "W/A/S/D to move, Q/E down and up, shift to sprint, drag to look"
+        ));
+        let mut mouse_look = p.mouse_look.enabled;
+        if ui.checkbox(
+            &ImString::new(p.bindings.label("Mouse look", Action::ToggleMouseLook)),
+            &mut mouse_look,
+        ) {
+            run_action(p, Action::ToggleMouseLook);
+        }
+        ui.text(im_str!("Looks with the cursor grabbed, Escape lets it go"));
+        ui.slider_float(
+            im_str!("Speed (km/s)"),
+            &mut p.fly.speed_km,
+            10.0,
+            100_000.0,
+        )
+        .power(3.0)
+        .build();
+        ui.slider_float(im_str!("Sprint multiplier"), &mut p.fly.sprint, 1.0, 20.0)
+            .build();
+        if p.fly.enabled {
+            ui.text(im_str!("Altitude: {:.0} km", p.altitude_km()));
+        }
+    }
+
+    if ui.collapsing_header(im_str!("Sun")).build() {
+        let settings = &mut p.sun.settings;
+        ui.checkbox(im_str!("Sun disc"), &mut settings.enabled);
+        ui.slider_float(im_str!("Intensity"), &mut settings.intensity, 0.1, 4.0)
+            .build();
+        ui.slider_float(
+            im_str!("Limb darkening"),
+            &mut settings.limb_darkening,
+            0.0,
+            1.0,
+        )
+        .build();
+        ui.slider_float(im_str!("Granulation"), &mut settings.granulation, 0.0, 1.0)
+            .build();
+        ui.slider_float(
+            im_str!("Granulation period (s)"),
+            &mut settings.granulation_period,
+            5.0,
+            600.0,
+        )
+        .build();
+        ui.checkbox(im_str!("Corona"), &mut settings.corona);
+        ui.slider_float(
+            im_str!("Corona size (radii)"),
+            &mut settings.corona_size,
+            0.0,
+            8.0,
+        )
+        .build();
+        ui.slider_float(im_str!("Disc scale"), &mut settings.disc_scale, 1.0, 20.0)
+            .build();
+
+        let distance_km = p.scale.to_km(p.sun_distance);
+        ui.text(im_str!(
+            "Disc {:.3} deg across",
+            2.0 * p.sun.angular_radius(distance_km).to_degrees()
+        ));
+    }
+
+    if ui.collapsing_header(im_str!("Stars")).build() {
+        ui.slider_float(
+            im_str!("Parallax exaggeration (log10)"),
+            &mut p.star_parallax,
+            0.0,
+            12.0,
+        )
+        .build();
+        ui.text(im_str!(
+            "Nearest star drawn at {:.3e} km",
+            MIN_STAR_DISTANCE * units::KM_PER_LIGHT_YEAR / 10f64.powf(f64::from(p.star_parallax)),
+        ));
+        ui.checkbox(
+            im_str!("Skip stars behind the planet"),
+            &mut p.stars.culling,
+        );
+        ui.checkbox(im_str!("Skybox"), &mut p.skybox.enabled);
+        ui.slider_float(
+            im_str!("Skybox brightness"),
+            &mut p.skybox.brightness,
+            0.0,
+            4.0,
+        )
+        .build();
+        ui.text(im_str!(
+            "Stars drawn: {} / {}",
+            p.stars.drawn.get(),
+            p.stars.len()
+        ));
+    }
+
+    if ui.collapsing_header(im_str!("Time-lapse")).build() {
+        update_time_lapse(ui, p);
+    }
+
+    if ui.collapsing_header(im_str!("Recording")).build() {
+        update_recording(ui, p);
+    }
+
+    if ui.collapsing_header(im_str!("Labels")).build() {
+        update_labels(ui, p);
+    }
+
+    if ui.collapsing_header(im_str!("Colors")).build() {
+        let names: Vec<_> = p
+            .palette_names
+            .iter()
+            .map(|name| ImString::new(name.as_str()))
+            .collect();
+        let items: Vec<&ImStr> = names.iter().map(|name| name.as_ref()).collect();
+        let mut selected = p
+            .palette_names
+            .iter()
+            .position(|name| *name == p.colors.name)
+            .map_or(-1, |i| i as i32);
+        if ui.combo(im_str!("Palette"), &mut selected, &items, -1) {
+            if let Some(name) = p.palette_names.get(selected as usize).cloned() {
+                p.load_palette(&name);
+            }
+        }
+
+        for (stop, color) in colors::STOPS.iter().zip(p.colors.colors.iter_mut()) {
+            ui.color_edit(&ImString::new(*stop), color).build();
+        }
+
+        if ui.button(im_str!("Save palette"), (0.0, 0.0)) {
+            if let Err(e) = p.colors.save() {
+                println!("Saving palette failed: {}", e);
+            }
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Revert"), (0.0, 0.0)) {
+            let name = p.colors.name.clone();
+            p.load_palette(&name);
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Rescan"), (0.0, 0.0)) {
+            p.palette_names = ColorPalette::available();
+        }
+    }
+
+    if ui.collapsing_header(im_str!("Detail")).build() {
+        let geometry = &mut p.geometry;
+        let mut detail = geometry.detail as f32;
+        if ui
+            .slider_float(
+                im_str!("Mesh detail"),
+                &mut detail,
+                mesh::MIN_DETAIL as f32,
+                mesh::MAX_DETAIL as f32,
+            )
+            .power(3.0)
+            .display_format(im_str!("%.0f"))
+            .build()
+        {
+            geometry.detail = detail.round() as i32;
+        }
+        ui.slider_float(
+            im_str!("Flattening"),
+            &mut geometry.flattening,
+            0.0,
+            mesh::MAX_FLATTENING,
+        )
+        .display_format(im_str!("%.4f"))
+        .power(3.0)
+        .build();
+        let terrain = &mut geometry.displacement;
+        ui.slider_int(im_str!("Terrain seed"), &mut terrain.seed, 0, 100)
+            .build();
+        ui.slider_float(
+            im_str!("Terrain amplitude"),
+            &mut terrain.amplitude,
+            0.0,
+            0.1,
+        )
+        .build();
+        ui.slider_int(im_str!("Terrain octaves"), &mut terrain.octaves, 1, 10)
+            .build();
+        ui.slider_float(
+            im_str!("Terrain frequency"),
+            &mut terrain.frequency,
+            0.5,
+            8.0,
+        )
+        .build();
+        let craters = &mut terrain.craters;
+        ui.slider_int(im_str!("Craters"), &mut craters.count, 0, 2000)
+            .build();
+        ui.slider_float(
+            im_str!("Smallest crater"),
+            &mut craters.min_size,
+            0.005,
+            0.5,
+        )
+        .power(2.0)
+        .build();
+        ui.slider_float(im_str!("Largest crater"), &mut craters.max_size, 0.005, 0.5)
+            .power(2.0)
+            .build();
+        ui.slider_float(im_str!("Crater depth"), &mut craters.depth, 0.0, 0.5)
+            .build();
+        match geometry.building_for() {
+            Some(elapsed) => ui.text(im_str!(
+                "{} {} rebuilding {:.1} s",
+                geometry.mesh.name(),
+                spinner(elapsed),
+                elapsed.as_secs_f32()
+            )),
+            None => ui.text(im_str!("{}", geometry.mesh.name())),
+        }
+        for (i, level) in geometry.levels.iter().enumerate() {
+            let mut drawn = String::new();
+            if i == geometry.planet_level() {
+                drawn.push_str(" planet");
+            }
+            if i == geometry.cloud_level() {
+                drawn.push_str(" clouds");
+            }
+            let range = if level.max_distance.is_finite() {
+                format!("to {} radii", level.max_distance)
+            } else {
+                "beyond".to_string()
+            };
+            ui.text(im_str!(
+                "LOD {} ({}): {} vertices, {} triangles{}",
+                i,
+                range,
+                level.vertex_count(),
+                level.triangle_count(),
+                drawn
+            ));
+        }
+
+        if ui.button(im_str!("Export mesh..."), (0.0, 0.0)) {
+            match export_mesh(p) {
+                Ok(path) => println!("Saved the planet mesh to {}", path.display()),
+                Err(e) => println!("Exporting the planet mesh failed: {}", e),
+            }
+        }
+
+        ui.combo(
+            im_str!("Cell view"),
+            &mut p.cell_view,
+            &[im_str!("Off"), im_str!("Cell IDs"), im_str!("Cell values")],
+            -1,
+        );
+
+        let geometry = &mut p.geometry;
+        let names: Vec<ImString> = std::iter::once(ImString::new("Automatic"))
+            .chain((0..geometry.levels.len()).map(|i| ImString::new(format!("LOD {}", i))))
+            .collect();
+        let items: Vec<&ImStr> = names.iter().map(|name| name.as_ref()).collect();
+        let mut forced = geometry.forced_level.map_or(0, |level| level as i32 + 1);
+        if ui.combo(im_str!("Force LOD"), &mut forced, &items, -1) {
+            geometry.forced_level = if forced > 0 {
+                Some(forced as usize - 1)
+            } else {
+                None
+            };
+        }
+
+        ui.checkbox(im_str!("Quadtree surface"), &mut p.chunks.enabled);
+        if p.chunks.enabled {
+            ui.text(im_str!(
+                "{} chunks, {} deep, {} built this frame",
+                p.chunks.leaf_count(),
+                p.chunks.deepest(),
+                p.chunks.built
+            ));
+            ui.checkbox(im_str!("Frustum culling"), &mut p.chunks.culling);
+            ui.text(im_str!(
+                "Chunks drawn: {} / {}",
+                p.chunks.drawn.get(),
+                p.chunks.leaf_count()
+            ));
+        }
+
+        ui.checkbox(im_str!("Detail textures"), &mut p.detail.enabled);
+        ui.slider_float(im_str!("Detail scale"), &mut p.detail.scale, 1.0, 200.0)
+            .build();
+        ui.slider_float(
+            im_str!("Detail fade distance (km)"),
+            &mut p.detail.fade_distance_km,
+            1_000.0,
+            100_000.0,
+        )
+        .build();
+        ui.slider_float(im_str!("Detail strength"), &mut p.detail.strength, 0.0, 1.0)
+            .build();
+        ui.checkbox(im_str!("Detail normals"), &mut p.detail.normals);
+        ui.slider_float(im_str!("Detail relief"), &mut p.detail.relief, 0.0, 0.1)
+            .display_format(im_str!("%.3f repeats"))
+            .build();
+    }
+
+    if ui.collapsing_header(im_str!("Vegetation")).build() {
+        let settings = &mut p.vegetation.settings;
+        ui.checkbox(im_str!("Vegetation"), &mut settings.enabled);
+
+        let mut changed = false;
+        changed |= ui
+            .slider_int(im_str!("Vegetation seed"), &mut settings.seed, 0, 100)
+            .build();
+        changed |= ui
+            .slider_int(
+                im_str!("Vegetation density"),
+                &mut settings.candidates,
+                0,
+                500_000,
+            )
+            .build();
+        ui.slider_float(
+            im_str!("Vegetation view distance (km)"),
+            &mut settings.view_distance_km,
+            1_000.0,
+            100_000.0,
+        )
+        .build();
+
+        if changed {
+            p.vegetation.regenerate();
+        }
+    }
+}
+
+fn update_quality<'a>(ui: &Ui<'a>, p: &mut State) {
+    // "Custom" comes last, after the levels of quality.toml
+    let custom = p.quality.levels.len();
+    let mut level = p.quality.current.unwrap_or(custom) as i32;
+    let names: Vec<_> = p
+        .quality
+        .levels
+        .iter()
+        .map(|level| ImString::new(level.name.as_str()))
+        .chain(std::iter::once(ImString::new("Custom")))
+        .collect();
+    let items: Vec<&ImStr> = names.iter().map(|name| name.as_ref()).collect();
+    if ui.combo(im_str!("Quality"), &mut level, &items, -1) && (level as usize) < custom {
+        if let Err(e) = quality::apply(p, level as usize) {
+            println!("Applying quality {}: {}", names[level as usize].to_str(), e);
+        }
+    }
+    if p.quality.is_picking() {
+        ui.text(im_str!("Picking a quality level: {}", p.quality.reason));
+    } else if !p.quality.reason.is_empty() {
+        ui.text(im_str!("Quality at startup: {}", p.quality.reason));
+    }
+
+    let mut resolution = p.shadow_resolution as i32;
+    if ui
+        .slider_int(im_str!("Shadow resolution"), &mut resolution, 1, 4)
+        .display_format(im_str!("%dx framebuffer"))
+        .build()
+    {
+        p.shadow_resolution = resolution as u32;
+    }
+
+    let names: Vec<ImString> = CloudResolution::ALL
+        .iter()
+        .map(|resolution| ImString::new(resolution.name()))
+        .collect();
+    let items: Vec<&ImStr> = names.iter().map(|name| name.as_ref()).collect();
+    let mut resolution = CloudResolution::ALL
+        .iter()
+        .position(|&resolution| resolution == p.cloud_resolution)
+        .unwrap_or(0) as i32;
+    if ui.combo(im_str!("Cloud resolution"), &mut resolution, &items, -1) {
+        p.cloud_resolution = CloudResolution::ALL[resolution as usize];
+    }
+
+    let mut bias = p.geometry.lod_bias as i32;
+    if ui
+        .slider_int(im_str!("LOD bias"), &mut bias, 0, 3)
+        .display_format(im_str!("%d levels coarser"))
+        .build()
+    {
+        p.geometry.lod_bias = bias as u32;
+    }
+
+    ui.checkbox(im_str!("Automatic scaling"), &mut p.quality.auto_scale);
+    ui.same_line(0.0);
+    // Holds the settings as they are, for screenshots and comparisons
+    ui.checkbox(im_str!("Pin quality"), &mut p.quality.pinned);
+    if p.quality.auto_scale {
+        let quality = &p.quality;
+        let step = match quality.last_step() {
+            Some((key, value)) => format!(", {} = {}", key, value),
+            None => String::new(),
+        };
+        ui.text(im_str!(
+            "Step {} of {}{}",
+            quality.scaler.step,
+            quality.scaling.ladder.len(),
+            step
+        ));
+        if let Some(gpu_ms) = quality.scaler.smoothed_ms() {
+            ui.text(im_str!(
+                "GPU {:.1} ms, down over {:.1}, up under {:.1}, waiting {}x",
+                gpu_ms,
+                quality.scaling.target_ms,
+                quality.scaling.headroom_ms,
+                quality.scaler.backoff()
+            ));
+        }
+        if quality.scaler.step > 0 && ui.button(im_str!("Take back steps"), (0.0, 0.0)) {
+            quality::restore(p);
+        }
+    }
+}
+
+fn update_celestial<'a>(ui: &Ui<'a>, p: &mut State) {
+    ui.separator();
+    ui.text(im_str!("Sky grid"));
+
+    let preset = &p.body;
+    let declination = p.sun_place().declination(preset);
+    ui.text(im_str!(
+        "Sun declination {:.2} deg, axial tilt {:.2} deg",
+        declination,
+        preset.axial_tilt,
+    ));
+    ui.text(im_str!(
+        "Noon sun altitude: {:.2} deg (analytic {:.2} deg)",
+        p.celestial.path_noon_altitude.unwrap_or(f64::NAN),
+        orbit::noon_altitude(f64::from(p.surface.latitude), declination),
+    ));
+
+    let settings = &mut p.celestial.settings;
+    ui.checkbox(im_str!("Sky grid in orbit view"), &mut settings.orbit_view);
+    ui.checkbox(im_str!("Celestial equator"), &mut settings.equator);
+    ui.checkbox(im_str!("Hour marks"), &mut settings.hour_marks);
+    ui.checkbox(im_str!("RA/Dec grid"), &mut settings.grid);
+    ui.checkbox(im_str!("Ecliptic"), &mut settings.ecliptic);
+    ui.checkbox(im_str!("Sun path"), &mut settings.sun_path);
+    ui.checkbox(im_str!("Sky labels"), &mut settings.labels);
+    ui.color_edit(im_str!("Equator color"), &mut settings.equator_color)
+        .build();
+    ui.color_edit(im_str!("Ecliptic color"), &mut settings.ecliptic_color)
+        .build();
+    ui.color_edit(im_str!("Grid color"), &mut settings.grid_color)
+        .build();
+    ui.color_edit(im_str!("Sun path color"), &mut settings.sun_path_color)
+        .build();
+    ui.slider_float(
+        im_str!("Sky line width (px)"),
+        &mut settings.line_width,
+        0.5,
+        6.0,
+    )
+    .build();
+}
+
+fn context_builder<'a>(version: (u8, u8), vsync: bool) -> glutin::ContextBuilder<'a> {
+    glutin::ContextBuilder::new()
+        .with_gl_profile(GlProfile::Core)
+        .with_gl(GlRequest::Specific(Api::OpenGl, version))
+        .with_vsync(vsync)
+}
+
+fn milliseconds_since(start: Instant) -> f32 {
+    let duration = Instant::now().duration_since(start);
+    duration.as_secs() as f32 * 1000.0 + duration.subsec_nanos() as f32 * 1e-6
+}
+
+fn update_recording<'a>(ui: &Ui<'a>, p: &mut State) {
+    let recording = &mut p.recording;
+    if !recording.is_running() {
+        ui.checkbox(im_str!("Include the UI"), &mut recording.include_ui);
+    }
+    let label = if recording.is_running() {
+        "Stop recording"
+    } else {
+        "Start recording"
+    };
+    if ui.button(
+        &ImString::new(p.bindings.label(label, Action::ToggleRecording)),
+        (0.0, 0.0),
+    ) {
+        run_action(p, Action::ToggleRecording);
+    }
+
+    let recording = &p.recording;
+    if let Some(ref dir) = recording.dir {
+        ui.text(im_str!("Output: {}", dir.display()));
+        ui.text(im_str!(
+            "{} frames, {} written, {:.1} s at {:.0} fps",
+            recording.queued(),
+            recording.written(),
+            recording.queued() as f32 * recording::TIMESTEP,
+            1.0 / recording::TIMESTEP
+        ));
+    }
+    if recording.stalled() > 0 {
+        ui.text_colored(
+            (1.0, 0.8, 0.3, 1.0),
+            im_str!(
+                "{} frames waited on a full writer queue",
+                recording.stalled()
+            ),
+        );
+    }
+    if let Some(ref error) = recording.error {
+        ui.text_colored((1.0, 0.4, 0.4, 1.0), im_str!("Stopped: {}", error));
+    }
+}
+
+fn update_time_lapse<'a>(ui: &Ui<'a>, p: &mut State) {
+    let running = p.time_lapse.is_running();
+    let settings = &mut p.time_lapse.settings;
+
+    if !running {
+        ui.slider_float(
+            im_str!("Interval (sim minutes)"),
+            &mut settings.interval_minutes,
+            1.0,
+            720.0,
+        )
+        .power(2.0)
+        .build();
+        ui.slider_int(im_str!("Frames"), &mut settings.count, 1, 256)
+            .build();
+        ui.checkbox(im_str!("Encode with ffmpeg"), &mut settings.ffmpeg);
+        if !settings.ffmpeg {
+            ui.checkbox(im_str!("Contact sheet"), &mut settings.contact_sheet);
+        }
+    }
+    ui.slider_float(
+        im_str!("Speed (sim minutes / s)"),
+        &mut settings.speed,
+        1.0,
+        1440.0,
+    )
+    .power(3.0)
+    .build();
+
+    if running {
+        let minutes = p.time_lapse.sim_seconds() / 60.0;
+        ui.text(im_str!(
+            "Sim time {:.0} h {:02.0} min",
+            (minutes / 60.0).floor(),
+            minutes % 60.0
+        ));
+        ui.progress_bar(p.time_lapse.progress()).build();
+        if ui.button(im_str!("Stop"), (0.0, 0.0)) {
+            p.time_lapse.stop();
+        }
+    } else if ui.button(im_str!("Start time-lapse"), (0.0, 0.0)) {
+        run_action(p, Action::StartTimeLapse);
+    }
+
+    if let Some(ref dir) = p.time_lapse.dir {
+        ui.text(im_str!("Output: {}", dir.display()));
+    }
+    let writer = &p.screenshots;
+    if writer.pending() > 0 || writer.throughput() > 0.0 {
+        ui.text(im_str!(
+            "Queue {}/{}, {} encoders, {:.1} images/s",
+            writer.pending(),
+            screenshot::QUEUE_DEPTH + writer.workers(),
+            writer.workers(),
+            writer.throughput(),
+        ));
+    }
+    if writer.stalled().as_millis() > 0 {
+        ui.text(im_str!(
+            "Rendering waited {:.2} s on a full queue",
+            writer.stalled().as_secs_f32()
+        ));
+    }
+    if let Some(ref error) = p.time_lapse.error {
+        ui.text_colored((1.0, 0.4, 0.4, 1.0), im_str!("Stopped: {}", error));
+    }
+}
+
+fn update_scale_model<'a>(ui: &Ui<'a>, p: &mut State) {
+    ui.checkbox(im_str!("Light from the real distance"), &mut p.scale_model);
+
+    let mut radius = p.body.planet_radius_km as f32;
+    let mut sun_distance = p.body.sun_distance_au as f32;
+    let mut day_length = p.body.day_length_hours as f32;
+    let mut year_length = units::hours_to_days(p.body.year_length_hours) as f32;
+    let mut changed = ui
+        .slider_float(im_str!("Planet radius"), &mut radius, 100.0, 100_000.0)
+        .power(3.0)
+        .display_format(im_str!("%.0f km"))
+        .build();
+    changed |= ui
+        .slider_float(
+            im_str!("Sun distance##scale"),
+            &mut sun_distance,
+            0.05,
+            50.0,
+        )
+        .power(3.0)
+        .display_format(im_str!("%.3f AU"))
+        .build();
+    changed |= ui
+        .slider_float(im_str!("Day length"), &mut day_length, 1.0, 2000.0)
+        .power(3.0)
+        .display_format(im_str!("%.1f h"))
+        .build();
+    changed |= ui
+        .slider_float(im_str!("Orbital period"), &mut year_length, 1.0, 100_000.0)
+        .power(4.0)
+        .display_format(im_str!("%.1f days"))
+        .build();
+    if changed {
+        let mut body = p.body;
+        body.planet_radius_km = f64::from(radius);
+        body.sun_distance_au = f64::from(sun_distance);
+        body.day_length_hours = f64::from(day_length);
+        body.year_length_hours = units::days_to_hours(f64::from(year_length));
+        p.set_body(body);
+    }
+
+    let mut tilt = p.body.axial_tilt as f32;
+    if ui
+        .slider_float(im_str!("Axial tilt##scale"), &mut tilt, 0.0, 180.0)
+        .display_format(im_str!("%.2f deg"))
+        .build()
+    {
+        p.body.axial_tilt = f64::from(tilt);
+        p.update_sun_pos();
+    }
+    let mut gravity = p.body.surface_gravity as f32;
+    if ui
+        .slider_float(im_str!("Surface gravity"), &mut gravity, 0.1, 30.0)
+        .power(2.0)
+        .display_format(im_str!("%.2f m/s^2"))
+        .build()
+    {
+        p.body.surface_gravity = f64::from(gravity);
+    }
+
+    let sun_km = p.scale.to_km(p.sun_distance);
+    ui.text(im_str!(
+        "{:.1} km per render unit, sun {:.0} km away",
+        p.scale.to_km(1.0),
+        sun_km
+    ));
+    ui.text(im_str!(
+        "Sun {:.3} deg across, sunlight {:.3}x that at 1 AU",
+        2.0 * p.sun.angular_radius(sun_km).to_degrees(),
+        units::irradiance(sun_km)
+    ));
+}
+
+fn update_nbody<'a>(ui: &Ui<'a>, p: &mut State) {
+    // Off goes back to the sun angle and season
+    if ui.checkbox(im_str!("N-body mode"), &mut p.nbody.settings.enabled) {
+        p.update_sun_pos();
+    }
+    let nbody = &mut p.nbody;
+    ui.text(im_str!(
+        "{} steps, energy drift {:.2e}",
+        nbody.steps(),
+        nbody.energy_drift()
+    ));
+    if ui.button(im_str!("Reset system"), (0.0, 0.0)) {
+        nbody.reset();
+    }
+
+    let settings = &mut nbody.settings;
+    ui.slider_float(
+        im_str!("Time scale##nbody"),
+        &mut settings.time_scale,
+        0.0,
+        20.0,
+    )
+    .power(2.0)
+    .build();
+    ui.slider_float(
+        im_str!("View extent (radii)"),
+        &mut settings.view_extent,
+        10.0,
+        2000.0,
+    )
+    .power(3.0)
+    .build();
+    ui.slider_float(
+        im_str!("Trail width (px)##nbody"),
+        &mut settings.trail_width,
+        0.5,
+        8.0,
+    )
+    .build();
+    ui.slider_float(
+        im_str!("Marker size (px)##nbody"),
+        &mut settings.marker_size,
+        1.0,
+        16.0,
+    )
+    .build();
+
+    ui.text(im_str!(
+        "Click to add a body, drag to move one, right click to remove one"
+    ));
+    nbody.draw_system_view(ui, im_str!("##system_view"));
+
+    let selected = nbody.selected.min(nbody.bodies.len() - 1);
+    let body = &mut nbody.bodies[selected];
+    ui.text(im_str!("{}", body.name));
+    let mut mass = body.mass as f32;
+    let mut velocity = [
+        body.velocity.x as f32,
+        body.velocity.y as f32,
+        body.velocity.z as f32,
+    ];
+    let mut changed = ui
+        .slider_float(im_str!("Mass##nbody"), &mut mass, 0.001, 100_000.0)
+        .power(6.0)
+        .build();
+    changed |= ui
+        .slider_float3(im_str!("Velocity##nbody"), &mut velocity, -50.0, 50.0)
+        .build();
+    if changed {
+        body.mass = f64::from(mass);
+        body.velocity = vec3(
+            f64::from(velocity[0]),
+            f64::from(velocity[1]),
+            f64::from(velocity[2]),
+        );
+        nbody.restart();
+    }
+    if selected > nbody::PLANET && ui.small_button(im_str!("Remove##nbody")) {
+        nbody.remove_body(selected);
+    }
+}
+
+fn update_extra_bodies<'a>(ui: &Ui<'a>, p: &mut State) {
+    let bodies = &mut p.extra_bodies;
+    ui.input_text(im_str!("OBJ or glTF file"), &mut bodies.path)
+        .build();
+    ui.same_line(0.0);
+    if ui.button(im_str!("Load"), (0.0, 0.0)) {
+        bodies.load_requested = true;
+    }
+    if let Some(ref error) = bodies.error {
+        ui.text_colored((1.0, 0.4, 0.4, 1.0), im_str!("{}", error));
+    }
+
+    let names: Vec<ImString> = BodyShader::ALL
+        .iter()
+        .map(|shader| ImString::new(shader.name()))
+        .collect();
+    let items: Vec<&ImStr> = names.iter().map(|name| name.as_ref()).collect();
+    let mut removed = None;
+    for (i, body) in bodies.bodies.iter_mut().enumerate() {
+        ui.separator();
+        ui.text(im_str!("{}", body.name));
+        ui.slider_float3(
+            im_str!("Position##body{}", i),
+            &mut body.position,
+            -4.0,
+            4.0,
+        )
+        .build();
+        ui.slider_float(im_str!("Scale##body{}", i), &mut body.scale, 0.0001, 1.0)
+            .power(4.0)
+            .build();
+        ui.slider_float(
+            im_str!("Rotation##body{}", i),
+            &mut body.rotation,
+            0.0,
+            360.0,
+        )
+        .build();
+        ui.slider_float(
+            im_str!("Orbit speed##body{}", i),
+            &mut body.orbit_speed,
+            -90.0,
+            90.0,
+        )
+        .build();
+        let mut shader = BodyShader::ALL
+            .iter()
+            .position(|&shader| shader == body.shader)
+            .unwrap_or(0) as i32;
+        if ui.combo(im_str!("Shader##body{}", i), &mut shader, &items, -1) {
+            body.shader = BodyShader::ALL[shader as usize];
+        }
+        if ui.small_button(im_str!("Remove##body{}", i)) {
+            removed = Some(i);
+        }
+    }
+    if let Some(i) = removed {
+        bodies.bodies.remove(i);
+    }
+
+    if !bodies.log.is_empty() {
+        ui.separator();
+        for warning in &bodies.log {
+            ui.text_colored((1.0, 0.8, 0.4, 1.0), im_str!("{}", warning));
+        }
+        if ui.small_button(im_str!("Clear log")) {
+            bodies.log.clear();
+        }
+    }
+}
+
+/// Writes the finest level of the planet mesh, terrain and all, to
+/// `exports/planet-YYYYMMDD-HHMMSS.obj`.
+fn export_mesh(p: &State) -> Result<PathBuf, Box<dyn error::Error>> {
+    let level = p
+        .geometry
+        .levels
+        .first()
+        .ok_or("no planet mesh to export")?;
+    let (vertices, indices) = level.read_surface()?;
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let dir = PathBuf::from("exports");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("planet-{}.obj", screenshot::timestamp(seconds)));
+    obj::export_obj(&path, &vertices, &indices)?;
+    Ok(path)
+}
+
+fn screenshot_path(p: &mut State) -> Result<PathBuf, Box<dyn error::Error>> {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let dir = PathBuf::from("screenshots");
+    fs::create_dir_all(&dir)?;
+    Ok(p.screenshots.next_path(&dir, seconds))
+}
+
+/// Copies what is in `target` so far, the UI too, into `screenshots/` on
+/// the screenshot writer's thread.
+fn save_screenshot<F: Facade, S: Surface>(
+    facade: &F,
+    target: &S,
+    p: &mut State,
+) -> Result<PathBuf, Box<dyn error::Error>> {
+    let path = screenshot_path(p)?;
+    let (width, height) = target.get_dimensions();
+    let pixels = read_frame(facade, target)?;
+    p.screenshots.save(path.clone(), pixels, width, height);
+    Ok(path)
+}
+
+/// The RGBA pixels of what is in `target` so far, top row first.
+fn read_frame<F: Facade, S: Surface>(
+    facade: &F,
+    target: &S,
+) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    let (width, height) = target.get_dimensions();
+    let texture = Texture2d::empty_with_format(
+        facade,
+        UncompressedFloatFormat::U8U8U8U8,
+        MipmapsOption::NoMipmap,
+        width,
+        height,
+    )?;
+    target.blit_whole_color_to(
+        &texture.as_surface(),
+        &glium::BlitTarget {
+            left: 0,
+            bottom: 0,
+            width: width as i32,
+            height: height as i32,
+        },
+        glium::uniforms::MagnifySamplerFilter::Nearest,
+    );
+    Ok(capture::read_pixels(&texture))
+}
+
+/// Queues this frame of the recording, with the UI or before it is drawn.
+fn record_frame<F: Facade, S: Surface>(facade: &F, target: &S, p: &mut State) {
+    match read_frame(facade, target) {
+        Ok(pixels) => p
+            .recording
+            .queue(&mut p.screenshots, pixels, target.get_dimensions()),
+        Err(e) => {
+            p.recording.error = Some(e.to_string());
+            p.recording.stop();
+        }
+    }
+}
+
+/// Renders the frame again without the UI into `screenshots/` on the
+/// screenshot writer's thread.
+fn save_scene_screenshot<F: Facade>(
+    facade: &F,
+    p: &mut State,
+    width: u32,
+    height: u32,
+    time: f32,
+) -> Result<PathBuf, Box<dyn error::Error>> {
+    let path = screenshot_path(p)?;
+    let pixels = capture::render_image(facade, p, width, height, time)?;
+    p.screenshots.save(path.clone(), pixels, width, height);
+    Ok(path)
+}
+
+fn update_labels<'a>(ui: &Ui<'a>, p: &mut State) {
+    let labels = &mut p.labels;
+    if labels.atlas.is_none() {
+        ui.text(im_str!("No font atlas, world space labels are off"));
+    }
+
+    let settings = &mut labels.settings;
+    ui.checkbox(im_str!("Labels"), &mut settings.enabled);
+    ui.slider_float(
+        im_str!("Label size (km)"),
+        &mut settings.size_km,
+        10.0,
+        5000.0,
+    )
+    .power(2.0)
+    .build();
+    ui.slider_float(
+        im_str!("Min label height (px)"),
+        &mut settings.min_pixels,
+        4.0,
+        32.0,
+    )
+    .build();
+    ui.slider_float(
+        im_str!("Max label height (px)"),
+        &mut settings.max_pixels,
+        8.0,
+        96.0,
+    )
+    .build();
+    ui.slider_float(
+        im_str!("Label fade distance (km)"),
+        &mut settings.fade_distance_km,
+        1_000.0,
+        200_000.0,
+    )
+    .power(2.0)
+    .build();
+    ui.color_edit(im_str!("Label color"), &mut settings.color)
+        .build();
+
+    let mut changed = false;
+    let mut remove = None;
+    for (i, label) in labels.labels.iter_mut().enumerate() {
+        ui.push_id(i as i32);
+        changed |= ui.input_text(im_str!("Text"), &mut label.text).build();
+        changed |= ui
+            .slider_float(im_str!("Latitude"), &mut label.latitude, -90.0, 90.0)
+            .build();
+        changed |= ui
+            .slider_float(im_str!("Longitude"), &mut label.longitude, -180.0, 180.0)
+            .build();
+        changed |= ui
+            .slider_float(
+                im_str!("Altitude (km)"),
+                &mut label.altitude_km,
+                0.0,
+                50_000.0,
+            )
+            .power(3.0)
+            .build();
+        changed |= ui.checkbox(im_str!("World space"), &mut label.world_space);
+        ui.same_line(0.0);
+        if ui.button(im_str!("Remove"), (0.0, 0.0)) {
+            remove = Some(i);
+        }
+        ui.separator();
+        ui.pop_id();
+    }
+
+    if let Some(i) = remove {
+        labels.labels.remove(i);
+        changed = true;
+    }
+
+    if ui.button(im_str!("Add label here"), (0.0, 0.0)) {
+        // Where the surface view stands
+        labels.labels.push(Label::new(
+            "Label",
+            p.surface.latitude,
+            p.surface.longitude,
+            0.0,
+        ));
+        changed = true;
+    }
+
+    if changed {
+        labels.invalidate();
+    }
+}
+
+fn shadowmap_view_matrix(sun_pos: Vector3<f32>, target: Vector3<f32>) -> Matrix4<f32> {
+    let dir = (target - sun_pos).normalize();
+    let eye = target - dir * SHADOW_EYE_DISTANCE;
+
+    // look_at breaks down when the light direction is parallel to the up
+    // vector, so switch axis when the sun passes over either pole.
+    let up = if dir.y.abs() > 0.99 {
+        vec3(0.0, 0.0, 1.0)
+    } else {
+        vec3(0.0, 1.0, 0.0)
+    };
+
+    Matrix4::look_at(Point3::from_vec(eye), Point3::from_vec(target), up)
+}
+
+/// What the shadow pass renders into. The framebuffer borrows both, so it
+/// is made from them every time it is drawn to, and a new size replaces the
+/// whole thing at once.
+pub struct Shadowmap {
+    pub texture: Texture2d,
+    depthbuffer: DepthRenderBuffer,
+}
+
+impl Shadowmap {
+    pub fn new<F: Facade>(
+        facade: &F,
+        (width, height): (u32, u32),
+    ) -> Result<Shadowmap, Box<dyn error::Error>> {
+        let texture = Texture2d::empty_with_format(
+            facade,
+            UncompressedFloatFormat::F32F32,
+            MipmapsOption::NoMipmap,
+            width,
+            height,
+        )?;
+        let depthbuffer = DepthRenderBuffer::new(facade, DepthFormat::F32, width, height)?;
+        Ok(Shadowmap {
+            texture,
+            depthbuffer,
+        })
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        self.texture.dimensions()
+    }
+
+    pub fn framebuffer<F: Facade>(
+        &self,
+        facade: &F,
+    ) -> Result<SimpleFrameBuffer<'_>, Box<dyn error::Error>> {
+        Ok(SimpleFrameBuffer::with_depth_buffer(
+            facade,
+            &self.texture,
+            &self.depthbuffer,
+        )?)
+    }
+}
+
+/// Draws the planet's surface, from the quadtree chunks when those are on
+/// and built, leaving out the chunks outside `frustum`. Returns how many
+/// chunks were drawn.
+fn draw_planet<S: Surface, U: Uniforms>(
+    target: &mut S,
+    p: &State,
+    program: &Program,
+    uniforms: &U,
+    params: &DrawParameters,
+    frustum: Option<&[Vector4<f32>; 6]>,
+) -> Result<usize, glium::DrawError> {
+    if p.chunks.is_drawing() {
+        let mut drawn = 0;
+        for chunk in p.chunks.visible(frustum) {
+            target.draw(
+                &chunk.vertex_buffer,
+                &chunk.index_buffer,
+                program,
+                uniforms,
+                params,
+            )?;
+            drawn += 1;
+        }
+        Ok(drawn)
+    } else {
+        let level = p.geometry.planet();
+        target.draw(
+            level.surface(),
+            &level.index_buffer,
+            program,
+            uniforms,
+            params,
+        )?;
+        Ok(0)
+    }
+}
+
+/// The planet's pole in view space, the axis it is flattened along.
+fn view_planet_axis(view: Matrix4<f32>, p: &State) -> Vector3<f32> {
+    (view * p.planet_matrix() * vec3(0.0, 1.0, 0.0).extend(0.0))
+        .truncate()
+        .normalize()
+}
+
+/// The atmosphere's limb glow, added over whatever is behind the shell's
+/// far side. The planet hides the part of it in front of the disc.
+fn draw_atmosphere_shell<S: Surface>(
+    target: &mut S,
+    p: &State,
+    frame: &UniformBuffer<FrameBlock>,
+    view: Matrix4<f32>,
+    planet_pos: Vector3<f32>,
+) -> Result<(), glium::DrawError> {
+    let shell = &p.atmosphere_shell;
+    if !shell.settings.enabled || p.atmosphere.is_none() {
+        return Ok(());
+    }
+    let (vertex_buffer, index_buffer) = match (&shell.vertex_buffer, &shell.index_buffer) {
+        (Some(vertex_buffer), Some(index_buffer)) => (vertex_buffer, index_buffer),
+        _ => return Ok(()),
+    };
+
+    let uniforms = uniform! {
+        MV: array4x4(view * p.planet_matrix()),
+        Frame: frame,
+        planetCenter: array3((view * planet_pos.extend(1.0)).truncate()),
+        planetRadius: PLANET_RADIUS,
+        planetAxis: array3(view_planet_axis(view, p)),
+        flattening: p.geometry.built_flattening(),
+        shellRadius: shell.radius(),
+        tint: shell.settings.tint,
+        intensity: shell.settings.intensity,
+    };
+
+    target.draw(
+        vertex_buffer,
+        index_buffer,
+        &p.atmosphere_program.program,
+        &uniforms,
+        &p.pass_params.atmosphere,
+    )
+}
+
+/// Draws the cloud layer at the reduced size of `clouds` and blends it over
+/// the scene drawn into them, minding the scene's depth where it upsamples.
+/// The shell's depth then goes into `target`, their scene framebuffer, for
+/// what's drawn after the clouds.
+fn draw_reduced_clouds<S: Surface, U: Uniforms>(
+    target: &mut S,
+    p: &State,
+    clouds: &CloudTarget,
+    frame: &UniformBuffer<FrameBlock>,
+    cloud_uniforms: &U,
+    back_params: &DrawParameters,
+    forward_params: &DrawParameters,
+) -> Result<(), Box<dyn error::Error>> {
+    // The vertex shaders make a triangle over the whole target from nothing
+    let fullscreen = || glium::vertex::EmptyVertexAttributes { len: 3 };
+    let triangles = glium::index::NoIndices(PrimitiveType::TrianglesList);
+    let scene_depth = Sampler::new(&clouds.scene_depth)
+        .magnify_filter(glium::uniforms::MagnifySamplerFilter::Nearest)
+        .minify_filter(glium::uniforms::MinifySamplerFilter::Nearest);
+
+    // Timed with the clouds, it's what drawing them smaller costs
+    let params = &p.pass_params;
+    let downsample_params = DrawParameters {
+        time_elapsed_query: back_params.time_elapsed_query,
+        ..params.cloud_downsample.clone()
+    };
+    let upsample_params = DrawParameters {
+        time_elapsed_query: back_params.time_elapsed_query,
+        ..params.cloud_upsample.clone()
+    };
+    let merge_params = DrawParameters {
+        time_elapsed_query: back_params.time_elapsed_query,
+        ..params.cloud_merge.clone()
+    };
 
-        let shadowmap_v = Matrix4::look_at(
-            Point3 {
-                x: p.sun_pos.x,
-                y: p.sun_pos.y,
-                z: p.sun_pos.z,
-            },
-            Point3 {
-                x: planet_pos.x,
-                y: planet_pos.y,
-                z: planet_pos.z,
+    let downsample_uniforms = uniform! {
+        sceneDepth: scene_depth,
+        divisor: clouds.resolution().divisor() as i32,
+    };
+    clouds.depth_framebuffer()?.draw(
+        fullscreen(),
+        triangles,
+        &p.cloud_downsample_program.program,
+        &downsample_uniforms,
+        &downsample_params,
+    )?;
+
+    let mut cloud_framebuffer = clouds.cloud_framebuffer()?;
+    cloud_framebuffer.clear_color(0.0, 0.0, 0.0, 0.0);
+    for cloud_params in [back_params, forward_params] {
+        cloud_framebuffer.draw(
+            &p.geometry.clouds().vertex_buffer,
+            &p.geometry.clouds().index_buffer,
+            &p.cloud_program.program,
+            cloud_uniforms,
+            cloud_params,
+        )?;
+    }
+
+    let upsample_uniforms = uniform! {
+        Frame: frame,
+        clouds: Sampler::new(&clouds.cloud_color),
+        cloudDepth: Sampler::new(&clouds.cloud_depth),
+        sceneDepth: scene_depth,
+        depthTolerance: 0.1f32,
+    };
+    clouds.composite_framebuffer()?.draw(
+        fullscreen(),
+        triangles,
+        &p.cloud_upsample_program.program,
+        &upsample_uniforms,
+        &upsample_params,
+    )?;
+
+    // Reads only the clouds' target, the scene's depth is the depth buffer
+    let merge_uniforms = uniform! {
+        shellDepth: Sampler::new(&clouds.shell_depth),
+        cloudDepth: Sampler::new(&clouds.cloud_depth),
+        divisor: clouds.resolution().divisor() as i32,
+    };
+    target.draw(
+        fullscreen(),
+        triangles,
+        &p.cloud_merge_program.program,
+        &merge_uniforms,
+        &merge_params,
+    )?;
+
+    Ok(())
+}
+
+/// Draws the scene into `target`. With `clouds` the target has to be their
+/// scene framebuffer, and the cloud layer is drawn at their size.
+fn render_scene<S: Surface>(
+    target: &mut S,
+    p: &State,
+    shadowmap_framebuffer: &mut SimpleFrameBuffer,
+    shadowmap_texture: &Texture2d,
+    clouds: Option<&CloudTarget>,
+    aspect: f32,
+    time: f32,
+) -> Result<(), Box<dyn error::Error>> {
+    let planet_matrix = p.planet_matrix();
+    let planet_pos = p.camera_relative(p.planet_pos);
+    let sun_pos = p.camera_relative(p.sun_pos);
+
+    let cloud_matrix = Matrix4::from_translation(planet_pos)
+        * Matrix4::from_scale(p.cloud_scale())
+        * Matrix4::from(p.view.rotation);
+
+    let wind_profile = p
+        .wind
+        .texture
+        .as_ref()
+        .ok_or("wind profile is not uploaded")?;
+    let wind_time = p.wind.time(time);
+    let wind_shell_radius = (p.body.planet_radius_km * 1000.0) as f32 * p.cloud_scale();
+    let (cloud_density, cloud_tint) = p.atmosphere.map_or((0.0, [1.0; 3]), |atmosphere| {
+        (atmosphere.density, atmosphere.tint)
+    });
+    let cloud_color = {
+        let color = p.colors.colors[colors::ATMOSPHERE];
+        [
+            color[0] * cloud_tint[0],
+            color[1] * cloud_tint[1],
+            color[2] * cloud_tint[2],
+        ]
+    };
+
+    let camera = p.camera();
+    let projection = camera.projection_matrix(aspect);
+    let camera_local = p.camera_local();
+
+    let view = p.entry.shake_matrix() * camera.view_matrix();
+    let view_inverse = view.invert().unwrap_or_else(Matrix4::identity);
+    let view_sun_pos = (view * sun_pos.extend(1.0)).truncate();
+
+    let shadowmap_v = shadowmap_view_matrix(sun_pos, planet_pos);
+
+    let shadowmap_p = {
+        let dist = SHADOW_EYE_DISTANCE;
+        ortho(-1.5, 1.5, -1.5, 1.5, dist - 1.5, dist + 1.5)
+    };
+
+    let params = &p.pass_params;
+
+    {
+        let frame = p.frame_uniforms.write(FrameBlock::new(
+            array4x4(shadowmap_p * shadowmap_v),
+            array3(sun_pos),
+            time,
+            {
+                let (width, height) = shadowmap_framebuffer.get_dimensions();
+                [width as f32, height as f32]
             },
-            vec3(0.0, 1.0, 0.0),
-        );
+            p.sun_intensity(),
+        ));
+
+        let planet_uniforms = uniform! {
+            MV: array4x4(planet_matrix),
+            Frame: &*frame,
+            shadowmap_p: array4x4(shadowmap_p),
+            shadowmap_v: array4x4(shadowmap_v),
+        };
+
+        let cloud_uniforms = uniform! {
+            MV: array4x4(cloud_matrix),
+            Frame: &*frame,
+            shadowmap_p: array4x4(shadowmap_p),
+            shadowmap_v: array4x4(shadowmap_v),
+            windProfile: Sampler::new(wind_profile),
+            windTime: wind_time,
+            windShellRadius: wind_shell_radius,
+            density: cloud_density,
+        };
+
+        // All the shadowmap draws are timed together, keep them back to back
+        let shadowmap_query = p.pass_timers.shadowmap.query();
+
+        let clockwise_params = DrawParameters {
+            time_elapsed_query: shadowmap_query,
+            ..params.shadow_clockwise.clone()
+        };
+
+        let counter_clockwise_params = DrawParameters {
+            time_elapsed_query: shadowmap_query,
+            ..params.shadow_counter_clockwise.clone()
+        };
+
+        shadowmap_framebuffer.clear_color(0.0, 0.0, 0.0, 0.0);
+        shadowmap_framebuffer.clear_depth(1.0);
+
+        // Chunks outside the view can still cast shadows into it
+        draw_planet(
+            shadowmap_framebuffer,
+            p,
+            &p.planet_shadowmap_program.program,
+            &planet_uniforms,
+            &clockwise_params,
+            None,
+        )?;
+
+        if p.atmosphere.is_some() {
+            shadowmap_framebuffer.draw(
+                &p.geometry.clouds().vertex_buffer,
+                &p.geometry.clouds().index_buffer,
+                &p.cloud_shadowmap_program.program,
+                &cloud_uniforms,
+                &counter_clockwise_params,
+            )?;
+
+            shadowmap_framebuffer.draw(
+                &p.geometry.clouds().vertex_buffer,
+                &p.geometry.clouds().index_buffer,
+                &p.cloud_shadowmap_program.program,
+                &cloud_uniforms,
+                &clockwise_params,
+            )?;
+        }
+    }
+
+    {
+        let viewport = {
+            let (width, height) = target.get_dimensions();
+            [width as f32, height as f32]
+        };
+        let frame = p.frame_uniforms.write(FrameBlock::new(
+            array4x4(projection),
+            array3(view_sun_pos),
+            time,
+            viewport,
+            p.sun_intensity(),
+        ));
+
+        let planet_uniforms = uniform! {
+            MV: array4x4(view * planet_matrix),
+            Frame: &*frame,
+            shadowmap_p: array4x4(shadowmap_p),
+            shadowmap_v: array4x4(shadowmap_v * view_inverse),
+            tex: Sampler::new(shadowmap_texture),
+            detailRock: Sampler::new(&p.detail_rock).wrap_function(SamplerWrapFunction::Repeat),
+            detailSoil: Sampler::new(&p.detail_soil).wrap_function(SamplerWrapFunction::Repeat),
+            detailScale: p.detail.scale,
+            detailFadeDistance: p.scale.to_render(f64::from(p.detail.fade_distance_km)) as f32,
+            detailStrength: p.detail.effective_strength(),
+            detailRelief: p.detail.effective_relief(),
+            oceanDeepColor: p.colors.colors[colors::OCEAN_DEEP],
+            oceanShallowColor: p.colors.colors[colors::OCEAN_SHALLOW],
+            beachColor: p.colors.colors[colors::BEACH],
+            grassColor: p.colors.colors[colors::GRASS],
+            rockColor: p.colors.colors[colors::ROCK],
+            snowColor: p.colors.colors[colors::SNOW],
+            nightLightColor: p.colors.colors[colors::NIGHT_LIGHTS],
+            cellView: p.cell_view,
+            cellValues: Sampler::new(&p.geometry.cell_values),
+            flattening: p.geometry.built_flattening(),
+        };
+
+        let cloud_uniforms = uniform! {
+            MV: array4x4(view * cloud_matrix),
+            Frame: &*frame,
+            atmosphereColor: cloud_color,
+            density: cloud_density,
+            windProfile: Sampler::new(wind_profile),
+            windTime: wind_time,
+            windShellRadius: wind_shell_radius,
+        };
+
+        let light_year =
+            p.scale.to_render(units::KM_PER_LIGHT_YEAR) / 10f64.powf(f64::from(p.star_parallax));
+        let star_uniforms = uniform! {
+            mvp: array4x4(projection * view * p.star_matrix()),
+            lightYear: light_year as f32,
+        };
+
+        let planet_params = DrawParameters {
+            time_elapsed_query: p.pass_timers.planet.query(),
+            ..params.planet.clone()
+        };
+
+        // Both cloud draws count into the same queries, so keep them back to back
+        let draw_clouds = p.atmosphere.is_some() && p.cloud_occlusion.should_draw();
+        let (cloud_query, cloud_timer) = if draw_clouds {
+            (p.cloud_occlusion.query(), p.pass_timers.clouds.query())
+        } else {
+            (None, None)
+        };
+
+        let (cloud_back, cloud_forward) = if clouds.is_some() {
+            (&params.cloud_back_reduced, &params.cloud_forward_reduced)
+        } else {
+            (&params.cloud_back, &params.cloud_forward)
+        };
+
+        let cloud_params_back = DrawParameters {
+            samples_passed_query: cloud_query.map(|q| q.into()),
+            time_elapsed_query: cloud_timer,
+            ..cloud_back.clone()
+        };
+
+        let cloud_params_forward = DrawParameters {
+            samples_passed_query: cloud_query.map(|q| q.into()),
+            time_elapsed_query: cloud_timer,
+            ..cloud_forward.clone()
+        };
+
+        // The sun is drawn right after the stars and timed with them
+        let star_timer = p.pass_timers.stars.query();
+        let star_params = DrawParameters {
+            time_elapsed_query: star_timer,
+            ..params.stars.clone()
+        };
+
+        let sun_params = DrawParameters {
+            time_elapsed_query: star_timer,
+            ..params.sun.clone()
+        };
+
+        // The field lines, the spacecraft trail and the meteors are drawn
+        // last and timed as one pass
+        let magnetosphere_lines = if p.magnetosphere.settings.enabled {
+            p.magnetosphere.lines.slice(&p.line_pool)
+        } else {
+            None
+        };
+        let draw_trail = p.spacecraft.marker.slice(&p.line_pool).is_some();
+        let draw_nbody = p.nbody.markers.slice(&p.line_pool).is_some();
+        let (celestial_lines, celestial_labels) =
+            if p.surface.enabled || p.celestial.settings.orbit_view {
+                let labels = match (p.labels.atlas.as_ref(), p.celestial.label_buffer.as_ref()) {
+                    (Some(atlas), Some(buffer)) if p.celestial.label_count > 0 => {
+                        Some((atlas, buffer.slice(0..p.celestial.label_count)))
+                    }
+                    _ => None,
+                };
+                (p.celestial.lines.slice(&p.line_pool), labels)
+            } else {
+                (None, None)
+            };
+        let overlay_query = if magnetosphere_lines.is_some()
+            || draw_trail
+            || draw_nbody
+            || p.meteors.slice().is_some()
+        {
+            p.pass_timers.overlays.query()
+        } else {
+            None
+        };
+
+        target.clear_color(0.0, 0.0, 0.0, 0.0);
+        target.clear_depth(1.0);
+
+        // First, behind everything. It sits on the far plane and leaves the
+        // depth alone, so the stars and the rest draw over it.
+        if p.skybox.enabled {
+            let rotation = view * p.star_matrix();
+            let rotation = Matrix4::from(Matrix3::from_cols(
+                rotation.x.truncate(),
+                rotation.y.truncate(),
+                rotation.z.truncate(),
+            ));
+            let skybox_uniforms = uniform! {
+                V: array4x4(rotation),
+                Frame: &*frame,
+                brightness: p.skybox.brightness,
+            };
+
+            target.draw(
+                &p.skybox.vertex_buffer,
+                &p.skybox.index_buffer,
+                &p.skybox_program.program,
+                &skybox_uniforms,
+                &params.skybox,
+            )?;
+        }
+
+        // Fixed to the sky and turned with the camera only, so the grid is
+        // at infinity. Like the skybox it leaves the depth alone and
+        // whatever comes after draws over it, the ground hiding what is
+        // below the horizon.
+        if celestial_lines.is_some() || celestial_labels.is_some() {
+            let sky_matrix = Matrix4::from(Matrix3::from_cols(
+                view.x.truncate(),
+                view.y.truncate(),
+                view.z.truncate(),
+            )) * Matrix4::from_scale(CELESTIAL_RADIUS);
+            let settings = &p.celestial.settings;
+
+            if let Some(lines) = celestial_lines {
+                let celestial_uniforms = uniform! {
+                    MV: array4x4(sky_matrix),
+                    Frame: &*frame,
+                    color: [1.0f32, 1.0, 1.0, 1.0],
+                    lineWidth: settings.line_width,
+                    miterLimit: polyline::MITER_LIMIT,
+                };
+
+                target.draw(
+                    lines,
+                    glium::index::NoIndices(PrimitiveType::TrianglesList),
+                    &p.trail_program.program,
+                    &celestial_uniforms,
+                    &params.sky,
+                )?;
+            }
+
+            if let Some((atlas, Some(vertices))) = celestial_labels {
+                let label_settings = &p.labels.settings;
+                let label_uniforms = uniform! {
+                    MV: array4x4(sky_matrix),
+                    Frame: &*frame,
+                    viewportHeight: viewport[1],
+                    lineHeight: atlas.line_height,
+                    size: CELESTIAL_RADIUS * settings.label_size.to_radians(),
+                    minPixels: label_settings.min_pixels,
+                    maxPixels: label_settings.max_pixels,
+                    fadeDistance: CELESTIAL_RADIUS * 4.0,
+                    atlas: Sampler::new(&atlas.texture),
+                    color: label_settings.color,
+                };
+
+                target.draw(
+                    vertices,
+                    glium::index::NoIndices(PrimitiveType::TrianglesList),
+                    &p.label_program.program,
+                    &label_uniforms,
+                    &params.sky,
+                )?;
+            }
+        }
+
+        let frustum = math::frustum_planes(projection * view * planet_matrix);
+        let drawn = draw_planet(
+            target,
+            p,
+            &p.planet_program.program,
+            &planet_uniforms,
+            &planet_params,
+            p.chunks.culling.then_some(&frustum),
+        )?;
+        p.chunks.drawn.set(drawn);
+
+        if p.vegetation.settings.enabled && p.vegetation_occlusion.should_draw() {
+            if let Some(ref instances) = p.vegetation.instances {
+                let vegetation_uniforms = uniform! {
+                    MV: array4x4(view * planet_matrix),
+                    Frame: &*frame,
+                    size: p.vegetation.settings.size,
+                    viewDistance: p.scale.to_render(f64::from(p.vegetation.settings.view_distance_km)) as f32,
+                    cameraLocal: array3(camera_local),
+                    horizonRadius: p.geometry.horizon_radius(PLANET_RADIUS),
+                    atlas: Sampler::new(&p.vegetation.atlas),
+                };
+
+                let vegetation_params = DrawParameters {
+                    time_elapsed_query: p.vegetation.timer.query(),
+                    samples_passed_query: p.vegetation_occlusion.query().map(|q| q.into()),
+                    ..params.vegetation.clone()
+                };
+
+                target.draw(
+                    (
+                        &p.vegetation.billboard_vertices,
+                        instances
+                            .per_instance()
+                            .map_err(|_| "instanced drawing is not supported")?,
+                    ),
+                    &p.vegetation.billboard_indices,
+                    &p.vegetation_program.program,
+                    &vegetation_uniforms,
+                    &vegetation_params,
+                )?;
+            }
+        }
+
+        for body in &p.extra_bodies.bodies {
+            let body_uniforms = uniform! {
+                MV: array4x4(view * planet_matrix * body.matrix(time)),
+                Frame: &*frame,
+                baseColor: Sampler::new(&body.texture).wrap_function(SamplerWrapFunction::Repeat),
+                shading: match body.shader {
+                    BodyShader::Lit => 0,
+                    BodyShader::Normals => 1,
+                },
+            };
+            target.draw(
+                &body.vertex_buffer,
+                &body.index_buffer,
+                &p.body_program.program,
+                &body_uniforms,
+                &params.body,
+            )?;
+        }
+
+        let star_camera = p
+            .star_matrix()
+            .invert()
+            .map_or(vec3(0.0, 0.0, 0.0), |inverse| inverse.w.truncate());
+        let mut stars_drawn = 0;
+        let star_horizon = p.geometry.horizon_radius(PLANET_RADIUS);
+        for range in p.stars.visible(star_camera, star_horizon) {
+            if range.is_empty() {
+                continue;
+            }
+            stars_drawn += range.len();
+            target.draw(
+                p.stars
+                    .buffer
+                    .slice(range)
+                    .ok_or("star range out of bounds")?,
+                glium::index::NoIndices(PrimitiveType::Points),
+                &p.star_program.program,
+                &star_uniforms,
+                &star_params,
+            )?;
+        }
+        p.stars.drawn.set(stars_drawn);
+
+        if p.sun.settings.enabled {
+            let settings = &p.sun.settings;
+            let distance_km = p.scale.to_km(f64::from(sun_pos.magnitude()));
+            let sun_uniforms = uniform! {
+                V: array4x4(view),
+                Frame: &*frame,
+                sunDir: array3(sun_pos.normalize()),
+                angularRadius: p.sun.angular_radius(distance_km),
+                extent: p.sun.extent(),
+                granulation: Sampler::new(&p.sun.granulation).wrap_function(SamplerWrapFunction::Repeat),
+                intensity: settings.intensity,
+                limbDarkening: settings.limb_darkening,
+                granulationStrength: settings.granulation,
+                granulationPeriod: settings.granulation_period.max(1.0),
+            };
+
+            target.draw(
+                &p.sun.vertex_buffer,
+                glium::index::NoIndices(PrimitiveType::TriangleStrip),
+                &p.sun_program.program,
+                &sun_uniforms,
+                &sun_params,
+            )?;
+        }
+
+        // Before the clouds, whose shell would otherwise hide every label on
+        // the surface
+        if p.labels.settings.enabled && p.labels.vertex_count > 0 {
+            if let (Some(atlas), Some(vertex_buffer)) =
+                (p.labels.atlas.as_ref(), p.labels.vertex_buffer.as_ref())
+            {
+                let settings = &p.labels.settings;
+                let label_uniforms = uniform! {
+                    MV: array4x4(view * planet_matrix),
+                    Frame: &*frame,
+                    viewportHeight: target.get_dimensions().1 as f32,
+                    lineHeight: atlas.line_height,
+                    size: p.scale.to_render(f64::from(settings.size_km)) as f32,
+                    minPixels: settings.min_pixels,
+                    maxPixels: settings.max_pixels,
+                    fadeDistance: p.scale.to_render(f64::from(settings.fade_distance_km)) as f32,
+                    atlas: Sampler::new(&atlas.texture),
+                    color: settings.color,
+                };
+
+                let label_params = DrawParameters {
+                    time_elapsed_query: p.pass_timers.labels.query(),
+                    ..params.labels.clone()
+                };
+
+                target.draw(
+                    vertex_buffer
+                        .slice(0..p.labels.vertex_count)
+                        .ok_or("label vertex count out of range")?,
+                    glium::index::NoIndices(PrimitiveType::TrianglesList),
+                    &p.label_program.program,
+                    &label_uniforms,
+                    &label_params,
+                )?;
+            }
+        }
+
+        // The ring goes after everything opaque and before the clouds. It
+        // writes depth where it shows, so the cloud shell's limb behind it
+        // is hidden rather than drawn over it, at the cost of that limb not
+        // showing through the ring's thinner bands.
+        if p.ring.settings.enabled {
+            if let (Some(vertex_buffer), Some(index_buffer)) =
+                (p.ring.vertex_buffer.as_ref(), p.ring.index_buffer.as_ref())
+            {
+                // The ring turns with the planet. It looks the same all the
+                // way round, so the spin about the pole doesn't show.
+                let ring_uniforms = uniform! {
+                    MV: array4x4(view * planet_matrix),
+                    Frame: &*frame,
+                    planetCenter: array3((view * planet_pos.extend(1.0)).truncate()),
+                    planetRadius: PLANET_RADIUS,
+                    planetAxis: array3(view_planet_axis(view, p)),
+                    flattening: p.geometry.built_flattening(),
+                    opacity: p.ring.settings.opacity,
+                };
+
+                target.draw(
+                    vertex_buffer,
+                    index_buffer,
+                    &p.ring_program.program,
+                    &ring_uniforms,
+                    &params.ring,
+                )?;
+            }
+        }
+
+        if !ATMOSPHERE_AFTER_CLOUDS {
+            draw_atmosphere_shell(target, p, &frame, view, planet_pos)?;
+        }
+
+        if draw_clouds {
+            if let Some(clouds) = clouds {
+                draw_reduced_clouds(
+                    target,
+                    p,
+                    clouds,
+                    &frame,
+                    &cloud_uniforms,
+                    &cloud_params_back,
+                    &cloud_params_forward,
+                )?;
+            } else {
+                target.draw(
+                    &p.geometry.clouds().vertex_buffer,
+                    &p.geometry.clouds().index_buffer,
+                    &p.cloud_program.program,
+                    &cloud_uniforms,
+                    &cloud_params_back,
+                )?;
+
+                target.draw(
+                    &p.geometry.clouds().vertex_buffer,
+                    &p.geometry.clouds().index_buffer,
+                    &p.cloud_program.program,
+                    &cloud_uniforms,
+                    &cloud_params_forward,
+                )?;
+            }
+        }
+
+        if ATMOSPHERE_AFTER_CLOUDS {
+            draw_atmosphere_shell(target, p, &frame, view, planet_pos)?;
+        }
+
+        // Shared by every polyline, lineWidth is set per draw
+        let line_params = DrawParameters {
+            time_elapsed_query: overlay_query,
+            ..params.lines.clone()
+        };
+
+        if let Some(lines) = magnetosphere_lines {
+            // The field distortion is done in planet-local space
+            let sun_local = planet_matrix
+                .invert()
+                .map_or(vec3(0.0, 0.0, 1.0), |inverse| {
+                    (inverse * (sun_pos - planet_pos).extend(0.0)).truncate()
+                })
+                .normalize();
+
+            let settings = &p.magnetosphere.settings;
+            let magnetosphere_uniforms = uniform! {
+                MV: array4x4(view * planet_matrix),
+                Frame: &*frame,
+                radius: PLANET_RADIUS,
+                sunDir: array3(sun_local),
+                compression: settings.compression,
+                tail: settings.tail,
+                color: settings.color,
+                lineWidth: settings.line_width,
+                miterLimit: polyline::MITER_LIMIT,
+            };
+
+            target.draw(
+                lines,
+                glium::index::NoIndices(PrimitiveType::TrianglesList),
+                &p.magnetosphere_program.program,
+                &magnetosphere_uniforms,
+                &line_params,
+            )?;
+        }
+
+        if draw_trail {
+            let settings = &p.spacecraft.settings;
+            let trail = vec![
+                (
+                    p.spacecraft.trail_lines.slice(&p.line_pool),
+                    settings.trail_width,
+                ),
+                (
+                    p.spacecraft.marker.slice(&p.line_pool),
+                    settings.marker_size,
+                ),
+            ];
+
+            for (lines, width) in trail {
+                let lines = match lines {
+                    Some(lines) => lines,
+                    None => continue,
+                };
+
+                let trail_uniforms = uniform! {
+                    MV: array4x4(view * planet_matrix),
+                    Frame: &*frame,
+                    color: [1.0f32, 0.85, 0.4, 0.9],
+                    lineWidth: width,
+                    miterLimit: polyline::MITER_LIMIT,
+                };
+
+                target.draw(
+                    lines,
+                    glium::index::NoIndices(PrimitiveType::TrianglesList),
+                    &p.trail_program.program,
+                    &trail_uniforms,
+                    &line_params,
+                )?;
+            }
+        }
+
+        if draw_nbody {
+            // Sky-fixed like the sun, so only the planet's position
+            let nbody_matrix = Matrix4::from_translation(planet_pos);
+            let settings = &p.nbody.settings;
+            let lines = [
+                (
+                    p.nbody.trail_lines.slice(&p.line_pool),
+                    settings.trail_width,
+                ),
+                (p.nbody.markers.slice(&p.line_pool), settings.marker_size),
+            ];
+
+            for (lines, width) in lines {
+                let lines = match lines {
+                    Some(lines) => lines,
+                    None => continue,
+                };
+
+                let nbody_uniforms = uniform! {
+                    MV: array4x4(view * nbody_matrix),
+                    Frame: &*frame,
+                    color: [1.0f32, 1.0, 1.0, 1.0],
+                    lineWidth: width,
+                    miterLimit: polyline::MITER_LIMIT,
+                };
+
+                target.draw(
+                    lines,
+                    glium::index::NoIndices(PrimitiveType::TrianglesList),
+                    &p.trail_program.program,
+                    &nbody_uniforms,
+                    &line_params,
+                )?;
+            }
+        }
+
+        if let Some(meteors) = p.meteors.slice() {
+            // Sky-fixed like the radiant, so only the planet's position
+            let meteor_matrix = Matrix4::from_translation(planet_pos);
+
+            let meteor_uniforms = uniform! {
+                MV: array4x4(view * meteor_matrix),
+                Frame: &*frame,
+                width: p.scale.to_render(f64::from(p.meteors.settings.width_km)) as f32,
+                sunDir: array3((sun_pos - planet_pos).normalize()),
+                brightness: p.meteors.settings.brightness,
+            };
+
+            let meteor_params = DrawParameters {
+                time_elapsed_query: overlay_query,
+                ..params.meteors.clone()
+            };
+
+            target.draw(
+                meteors,
+                glium::index::NoIndices(PrimitiveType::TrianglesList),
+                &p.meteor_program.program,
+                &meteor_uniforms,
+                &meteor_params,
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> Result<(), Box<dyn error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let max_gl = capabilities::max_gl_from_args(&args)?;
+    if let Some(i) = args.iter().position(|arg| arg == "--sun-sweep") {
+        let config = sweep::SweepConfig::from_args(&args[i + 1..])?;
+        return sweep::run(&config, max_gl);
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--load-debug-capture") {
+        let path = args
+            .get(i + 1)
+            .ok_or("--load-debug-capture needs a capture file")?;
+        return capture::load(Path::new(path), max_gl);
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--time-lapse") {
+        let mut config = timelapse::TimeLapseConfig::from_args(&args[i + 1..])?;
+        config.settings.ffmpeg = args.iter().any(|arg| arg == "--ffmpeg");
+        return timelapse::run(config, max_gl);
+    }
+
+    let mut event_loop = glutin::EventsLoop::new();
+
+    let mut display = None;
+    let mut last_error = None;
+    for version in capabilities::versions_to_try(max_gl) {
+        let window = glutin::WindowBuilder::new().with_title("Planet");
+        match Display::new(window, context_builder(version, false), &event_loop) {
+            Ok(created) => {
+                display = Some(created);
+                break;
+            }
+            Err(e) => {
+                println!("OpenGL {}.{} context: {}", version.0, version.1, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    let display = match (display, last_error) {
+        (Some(display), _) => display,
+        (None, Some(e)) => return Err(e.into()),
+        (None, None) => return Err("no OpenGL version to try".into()),
+    };
+
+    let mut imgui = ImGui::init();
+    imgui.set_ini_filename(None);
+    imgui.style_mut().window_rounding = 0.0;
+    imgui.set_imgui_key(ImGuiKey::Tab, 0);
+    imgui.set_imgui_key(ImGuiKey::LeftArrow, 1);
+    imgui.set_imgui_key(ImGuiKey::RightArrow, 2);
+    imgui.set_imgui_key(ImGuiKey::UpArrow, 3);
+    imgui.set_imgui_key(ImGuiKey::DownArrow, 4);
+    imgui.set_imgui_key(ImGuiKey::PageUp, 5);
+    imgui.set_imgui_key(ImGuiKey::PageDown, 6);
+    imgui.set_imgui_key(ImGuiKey::Home, 7);
+    imgui.set_imgui_key(ImGuiKey::End, 8);
+    imgui.set_imgui_key(ImGuiKey::Delete, 9);
+    imgui.set_imgui_key(ImGuiKey::Backspace, 10);
+    imgui.set_imgui_key(ImGuiKey::Enter, 11);
+    imgui.set_imgui_key(ImGuiKey::Escape, 12);
+    imgui.set_imgui_key(ImGuiKey::A, 13);
+    imgui.set_imgui_key(ImGuiKey::C, 14);
+    imgui.set_imgui_key(ImGuiKey::V, 15);
+    imgui.set_imgui_key(ImGuiKey::X, 16);
+    imgui.set_imgui_key(ImGuiKey::Y, 17);
+    imgui.set_imgui_key(ImGuiKey::Z, 18);
+
+    let mut imgui_renderer = imgui_glium_renderer::Renderer::init(&mut imgui, &display).unwrap();
+
+    let mesh = if args.iter().any(|arg| arg == "--icosphere") {
+        PlanetMesh::Icosphere
+    } else if args.iter().any(|arg| arg == "--cube-sphere") {
+        PlanetMesh::CubeSphere
+    } else {
+        PlanetMesh::UvSphere
+    };
+    let mut p = State::new(&display, max_gl, mesh)?;
+
+    let mut shadowmap = {
+        let (width, height) = display.get_framebuffer_dimensions();
+        Shadowmap::new(&display, p.shadowmap_size(width, height))?
+    };
+    // Only while the clouds are drawn smaller than the window
+    let mut cloud_target: Option<CloudTarget> = None;
+
+    match FontAtlas::from_imgui(&display, &mut imgui) {
+        Ok(atlas) => p.labels.atlas = Some(atlas),
+        Err(e) => println!("World space labels are off: {}", e),
+    }
+    p.session.start();
+
+    p.time_lapse.settings.ffmpeg = args.iter().any(|arg| arg == "--ffmpeg");
+
+    let requested_quality = match args.iter().position(|arg| arg == "--quality") {
+        Some(i) => Some(
+            args.get(i + 1)
+                .ok_or("--quality needs a level name")?
+                .as_str(),
+        ),
+        None => None,
+    };
+    let renderer = display
+        .get_context()
+        .get_opengl_renderer_string()
+        .to_string();
+    quality::start(&mut p, &renderer, requested_quality);
+
+    if args.iter().any(|arg| arg == "--benchmark") {
+        p.benchmark_requested = true;
+        p.benchmark.exit_when_done = true;
+    }
+    if let Some(i) = args.iter().position(|arg| arg == "--golden") {
+        let path = args
+            .get(i + 1)
+            .ok_or("--golden needs a PNG from an earlier run")?;
+        p.benchmark.golden = Some(PathBuf::from(path));
+    }
+
+    // The first frame is timed from here, not from the loading before
+    p.last_time = Instant::now();
+
+    while p.run {
+        let frame_start = Instant::now();
+
+        let frame_time = {
+            let new_time = Instant::now();
+            let duration = new_time.duration_since(p.last_time);
+            p.last_time = new_time;
+            duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9
+        };
+
+        p.average_frame_time = if p.average_frame_time > 0.0 {
+            p.average_frame_time * 0.95 + frame_time * 0.05
+        } else {
+            frame_time
+        };
+
+        // A recording plays back at a steady rate however slow its frames
+        // are, anything else is kept from jumping after a stall
+        let dt = if p.recording.is_running() {
+            recording::TIMESTEP
+        } else if frame_time > p.max_frame_step {
+            p.clamped_frames += 1;
+            p.max_frame_step
+        } else {
+            frame_time
+        };
+
+        // The length of the previous frame, whose spans are recorded
+        let gpu_spans = p.gpu_spans();
+        p.stutter.end_frame(frame_time, &gpu_spans);
+        quality::update(&mut p, frame_time, &gpu_spans);
+
+        if p.stutter.inject_stall {
+            p.stutter.inject_stall = false;
+            let start = Instant::now();
+            thread::sleep(Duration::from_millis(30));
+            p.stutter.record("injected stall", start);
+        }
+
+        let start = Instant::now();
+        p.reload_shaders(&display);
+        p.stutter.record("shader reload", start);
+
+        let start = Instant::now();
+        p.colors.reload_if_changed();
+        p.stutter.record("palette reload", start);
+
+        let start = Instant::now();
+        p.vegetation.update(&display)?;
+        p.stutter.record("vegetation upload", start);
+
+        let start = Instant::now();
+        p.geometry.update(&display)?;
+        p.stutter.record("mesh upload", start);
+
+        let start = Instant::now();
+        p.ring.update(&display)?;
+        p.stutter.record("ring upload", start);
+
+        let start = Instant::now();
+        p.extra_bodies.update(&display);
+        p.stutter.record("body load", start);
+
+        let start = Instant::now();
+        p.atmosphere_shell
+            .update(&display, p.geometry.built_flattening())?;
+        p.stutter.record("atmosphere shell upload", start);
+
+        let start = Instant::now();
+        p.magnetosphere.update(&display)?;
+        p.stutter.record("field line upload", start);
+
+        let start = Instant::now();
+        p.wind.update(&display)?;
+        p.stutter.record("wind profile upload", start);
+
+        let start = Instant::now();
+        let scale = &p.scale;
+        p.labels.update(&display, PLANET_RADIUS, |km| {
+            scale.to_render(f64::from(km)) as f32
+        })?;
+        p.stutter.record("label upload", start);
+
+        if p.surface.enabled || p.celestial.settings.orbit_view {
+            let start = Instant::now();
+            let (sun, up) = (p.sun_place(), p.surface_up());
+            p.celestial.update(
+                &display,
+                &mut p.line_pool,
+                &p.body,
+                sun,
+                up,
+                p.labels.atlas.as_ref(),
+            )?;
+            p.stutter.record("sky grid upload", start);
+        }
+
+        if p.benchmark_requested {
+            p.benchmark_requested = false;
+            if let Err(e) = benchmark::start(&display, &mut p) {
+                println!("Starting the benchmark failed: {}", e);
+            }
+        }
 
-        let shadowmap_p = {
-            let dist = (planet_pos - p.sun_pos).magnitude();
-            ortho(-1.5, 1.5, -1.5, 1.5, dist - 1.5, dist + 1.5)
+        // The benchmark steps the simulation by a fixed amount every frame,
+        // otherwise it goes by the clock
+        let step = if p.benchmark.is_running() {
+            benchmark::TIMESTEP
+        } else {
+            p.clock.advance(dt)
         };
 
-        let time = {
-            let duration = Instant::now().duration_since(p.start_time);
-            duration.as_secs() as f32 + duration.subsec_nanos() as f32 * 1e-9
-        };
+        let start = Instant::now();
+        p.vegetation.timer.begin_frame(&display);
+        p.pass_timers.begin_frame(&display);
+        p.cloud_occlusion
+            .begin_frame(&display, p.occlusion_auto_skip);
+        p.vegetation_occlusion
+            .begin_frame(&display, p.occlusion_auto_skip);
 
         {
-            let planet_uniforms = uniform! {
-                MV: array4x4(planet_matrix),
-                P: array4x4(shadowmap_p * shadowmap_v),
-                shadowmap_p: array4x4(shadowmap_p),
-                shadowmap_v: array4x4(shadowmap_v),
-                sunPos: array3(p.sun_pos),
-            };
+            // Meteors burn up on a shell just above the cloud layer
+            let shell_radius = p.cloud_scale() * PLANET_RADIUS * 1.02;
+            let speed = p.scale.to_render(f64::from(p.meteors.settings.speed_km)) as f32;
+            p.meteors.update(&display, step, shell_radius, speed)?;
+        }
 
-            let cloud_uniforms = uniform! {
-                MV: array4x4(cloud_matrix),
-                P: array4x4(shadowmap_p * shadowmap_v),
-                shadowmap_p: array4x4(shadowmap_p),
-                shadowmap_v: array4x4(shadowmap_v),
-                time: time,
-                sunPos: array3(p.sun_pos),
-            };
+        p.spacecraft.update(&display, &mut p.line_pool, step)?;
+        p.nbody.update(&display, &mut p.line_pool, step)?;
+        if p.nbody.settings.enabled {
+            p.update_sun_pos();
+        }
+        p.satellite.update(step);
 
-            let clockwise_params = DrawParameters {
-                depth: Depth {
-                    test: DepthTest::IfLess,
-                    write: true,
-                    ..Default::default()
-                },
-                backface_culling: BackfaceCullingMode::CullClockwise,
-                ..Default::default()
+        p.entry
+            .update(step, p.altitude_km() as f32, p.atmosphere_density());
+
+        p.stutter.record("simulation", start);
+
+        let start = Instant::now();
+        let hidpi = display.gl_window().get_hidpi_factor();
+        event_loop.poll_events(|event| {
+            use glium::glutin::{
+                DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, TouchPhase,
+                WindowEvent,
             };
 
-            let counter_clockwise_params = DrawParameters {
-                depth: Depth {
-                    test: DepthTest::IfLess,
-                    write: true,
-                    ..Default::default()
-                },
-                backface_culling: BackfaceCullingMode::CullCounterClockwise,
-                ..Default::default()
+            if let Event::DeviceEvent {
+                event: DeviceEvent::MouseMotion { delta },
+                ..
+            } = event
+            {
+                p.mouse_look.add_motion(delta.0, delta.1);
+            }
+
+            if let Event::WindowEvent { event, .. } = event {
+                match event {
+                    glutin::WindowEvent::CloseRequested => {
+                        p.run = false;
+                    }
+                    WindowEvent::Focused(focused) => p.mouse_look.set_focused(focused),
+                    WindowEvent::KeyboardInput { input, .. } => {
+                        use glium::glutin::VirtualKeyCode as Key;
+
+                        let pressed = input.state == ElementState::Pressed;
+
+                        if let Some(key) = input.virtual_keycode {
+                            if pressed && !p.palette.is_open() {
+                                let action = p.bindings.action(
+                                    key,
+                                    imgui.key_ctrl(),
+                                    imgui.key_shift(),
+                                    p.ui_wants_keyboard,
+                                );
+                                p.pending_actions.extend(action);
+                            }
+
+                            // Releases always get through, so no key is left held
+                            let held = pressed && !p.ui_wants_keyboard && !p.palette.is_open();
+                            for control in p.bindings.controls(key) {
+                                let keys = &mut p.fly.keys;
+                                match control {
+                                    Control::RotateLeft => p.left_pressed = held,
+                                    Control::RotateRight => p.right_pressed = held,
+                                    Control::MoveForward => keys.forward = held,
+                                    Control::MoveBack => keys.back = held,
+                                    Control::MoveLeft => keys.left = held,
+                                    Control::MoveRight => keys.right = held,
+                                    Control::MoveUp => keys.up = held,
+                                    Control::MoveDown => keys.down = held,
+                                    Control::Sprint => keys.sprint = pressed,
+                                    Control::ReleaseMouse => {
+                                        if pressed {
+                                            p.mouse_look.release();
+                                        }
+                                    }
+                                }
+                            }
+                        }
+
+                        match input.virtual_keycode {
+                            Some(Key::Tab) => imgui.set_key(0, pressed),
+                            Some(Key::Left) => imgui.set_key(1, pressed),
+                            Some(Key::Right) => imgui.set_key(2, pressed),
+                            Some(Key::Up) => imgui.set_key(3, pressed),
+                            Some(Key::Down) => imgui.set_key(4, pressed),
+                            Some(Key::PageUp) => imgui.set_key(5, pressed),
+                            Some(Key::PageDown) => imgui.set_key(6, pressed),
+                            Some(Key::Home) => imgui.set_key(7, pressed),
+                            Some(Key::End) => imgui.set_key(8, pressed),
+                            Some(Key::Delete) => imgui.set_key(9, pressed),
+                            Some(Key::Back) => imgui.set_key(10, pressed),
+                            Some(Key::Return) => imgui.set_key(11, pressed),
+                            Some(Key::Escape) => imgui.set_key(12, pressed),
+                            Some(Key::A) => imgui.set_key(13, pressed),
+                            Some(Key::C) => imgui.set_key(14, pressed),
+                            Some(Key::V) => imgui.set_key(15, pressed),
+                            Some(Key::X) => imgui.set_key(16, pressed),
+                            Some(Key::Y) => imgui.set_key(17, pressed),
+                            Some(Key::Z) => imgui.set_key(18, pressed),
+                            Some(Key::LControl) | Some(Key::RControl) => {
+                                imgui.set_key_ctrl(pressed)
+                            }
+                            Some(Key::LShift) | Some(Key::RShift) => imgui.set_key_shift(pressed),
+                            Some(Key::LAlt) | Some(Key::RAlt) => imgui.set_key_alt(pressed),
+                            Some(Key::LWin) | Some(Key::RWin) => imgui.set_key_super(pressed),
+                            _ => {}
+                        }
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        let position = position.to_physical(hidpi);
+                        p.mouse_state.pos = (position.x, position.y);
+                    }
+                    WindowEvent::MouseInput { state, button, .. } => {
+                        let pressed = state == ElementState::Pressed;
+                        let now = Instant::now();
+                        match button {
+                            MouseButton::Left => p.mouse_state.button(0, pressed, now),
+                            MouseButton::Right => p.mouse_state.button(1, pressed, now),
+                            MouseButton::Middle => p.mouse_state.button(2, pressed, now),
+                            _ => {}
+                        }
+                    }
+                    WindowEvent::MouseWheel {
+                        delta: MouseScrollDelta::LineDelta(_, y),
+                        phase: TouchPhase::Moved,
+                        ..
+                    } => {
+                        p.mouse_state.scroll(y);
+                    }
+                    // Pixels rather than lines come from trackpads
+                    WindowEvent::MouseWheel {
+                        delta: MouseScrollDelta::PixelDelta(LogicalPosition { x, y }),
+                        phase: TouchPhase::Moved,
+                        ..
+                    } => {
+                        p.gestures.scroll(x as f32, y as f32, imgui.key_ctrl());
+                    }
+                    WindowEvent::Touch(touch) => p.gestures.touch(touch),
+                    WindowEvent::ReceivedCharacter(c) => imgui.add_input_character(c),
+                    _ => (),
+                }
+            }
+        });
+
+        p.stutter.record("events", start);
+
+        let start = Instant::now();
+        for action in std::mem::take(&mut p.pending_actions) {
+            run_action(&mut p, action);
+        }
+        p.stutter.record("actions", start);
+
+        let looking = (p.fly.enabled || p.surface.enabled) && !p.palette.is_open();
+        p.mouse_look.update(&display.gl_window(), looking);
+
+        if p.vsync != p.window_vsync {
+            // Only a new context can change it, glium moves everything over
+            // to one that shares the old one's objects
+            let mut window = glutin::WindowBuilder::new().with_title("Planet");
+            if let Some(size) = display.gl_window().get_inner_size() {
+                window = window.with_dimensions(size);
+            }
+            if p.window_fullscreen {
+                window = window.with_fullscreen(Some(display.gl_window().get_current_monitor()));
+            }
+            match display.rebuild(window, context_builder(p.gl.version, p.vsync), &event_loop) {
+                Ok(()) => p.mouse_look.window_replaced(),
+                Err(e) => {
+                    println!("Switching vsync failed: {}", e);
+                    p.vsync = p.window_vsync;
+                }
+            }
+            p.window_vsync = p.vsync;
+        }
+
+        if p.fullscreen != p.window_fullscreen {
+            // The framebuffer size is read again below, everything sized to
+            // it follows from there
+            let window = display.gl_window();
+            let monitor = if p.fullscreen {
+                Some(window.get_current_monitor())
+            } else {
+                None
             };
+            window.set_fullscreen(monitor);
+            p.window_fullscreen = p.fullscreen;
+        }
 
-            shadowmap_framebuffer.clear_color(0.0, 0.0, 0.0, 0.0);
-            shadowmap_framebuffer.clear_depth(1.0);
+        if p.mouse_look.is_grabbed() {
+            // The cursor is kept in the middle of the window, the UI sees
+            // no mouse at all
+            imgui.set_mouse_pos(-f32::MAX, -f32::MAX);
+            imgui.set_mouse_down([false; 5]);
+        } else {
+            // The UI counts in logical pixels
+            imgui.set_mouse_pos(
+                (p.mouse_state.pos.0 / hidpi) as f32,
+                (p.mouse_state.pos.1 / hidpi) as f32,
+            );
 
-            shadowmap_framebuffer.draw(
-                &p.vertex_buffer,
-                &p.index_buffer,
-                &p.planet_shadowmap_program.program,
-                &planet_uniforms,
-                &clockwise_params,
-            )?;
+            imgui.set_mouse_down([
+                p.mouse_state.down(0),
+                p.mouse_state.down(1),
+                p.mouse_state.down(2),
+                false,
+                false,
+            ]);
 
-            /*shadowmap_framebuffer.draw(
-                &p.vertex_buffer,
-                &p.index_buffer,
-                &p.cloud_shadowmap_program.program,
-                &cloud_uniforms,
-                &counter_clockwise_params,
-            )?;
+            imgui.set_mouse_wheel(p.mouse_state.wheel + p.gestures.wheel());
+        }
 
-            shadowmap_framebuffer.draw(
-                &p.vertex_buffer,
-                &p.index_buffer,
-                &p.cloud_shadowmap_program.program,
-                &cloud_uniforms,
-                &clockwise_params,
-            )?;*/
+        if !p.palette.is_open() {
+            if p.right_pressed {
+                p.inertia.add_spin(dt * 45.0);
+            }
+
+            if p.left_pressed {
+                p.inertia.add_spin(-dt * 45.0);
+            }
+        }
+        // Goes by the clock, the arrow keys take over while held
+        let held = p.left_pressed || p.right_pressed;
+        p.inertia.add_spin(p.auto_rotate.step(step, held));
+        if p.sun_cycle.step(step, &mut p.view.sun_angle, &mut p.season) {
+            p.update_sun_pos();
+        }
+
+        let (width, height) = display.get_framebuffer_dimensions();
+        p.framebuffer_size = (width, height);
+
+        let ui = imgui.frame(
+            FrameSize::new(f64::from(width) / hidpi, f64::from(height) / hidpi, hidpi),
+            dt,
+        );
+
+        // Keys are handled as their events come in, before the frame, so
+        // they go by last frame's answer
+        p.ui_wants_mouse = ui.want_capture_mouse();
+        let wants_keyboard = ui.want_capture_keyboard();
+        if wants_keyboard && !p.ui_wants_keyboard {
+            // Keys held down for the scene are let go once the UI takes the
+            // keyboard
+            p.fly.keys = FlyKeys::default();
+            p.left_pressed = false;
+            p.right_pressed = false;
+        }
+        p.ui_wants_keyboard = wants_keyboard;
+
+        // Where the camera was held from last frame, moving it from there
+        // stops a bookmark on its way
+        let held_at = [p.drag_pos, p.arcball_pos, p.pan_pos];
+
+        let (look_x, look_y) = p.mouse_look.take_delta();
+        if p.fly.enabled {
+            p.fly.drag(look_x, look_y);
+        } else if p.surface.enabled {
+            // Turning the view rather than dragging the sky
+            p.surface.drag(-look_x, -look_y);
+        }
+        if p.mouse_state.clicked[0] && !p.ui_wants_mouse {
+            p.mouse_look.click();
+        }
+
+        if p.mouse_state.held[0] && !p.ui_wants_mouse && !p.mouse_look.is_grabbed() {
+            let pos = p.mouse_state.pixel();
+            if let Some(last) = p.drag_pos {
+                let (dx, dy) = ((pos.0 - last.0) as f32, (pos.1 - last.1) as f32);
+                if p.fly.enabled {
+                    p.fly.drag(dx, dy);
+                } else if p.surface.enabled {
+                    p.surface.drag(dx, dy);
+                } else {
+                    p.orbit_drag(dx, dy);
+                }
+            }
+            p.drag_pos = Some(pos);
+        } else {
+            p.drag_pos = None;
+        }
+
+        if p.mouse_state.held[1] && !p.ui_wants_mouse {
+            let pos = p.mouse_state.pixel();
+            if let Some(last) = p.arcball_pos {
+                let view = p.view_matrix();
+                let view =
+                    Matrix3::from_cols(view.x.truncate(), view.y.truncate(), view.z.truncate());
+                let turn = arcball::drag_rotation(
+                    arcball::sphere_point(last, p.framebuffer_size),
+                    arcball::sphere_point(pos, p.framebuffer_size),
+                    view,
+                );
+                p.inertia.add_turn(turn);
+            }
+            p.arcball_pos = Some(pos);
+        } else {
+            p.arcball_pos = None;
+        }
+
+        let orbiting = !p.surface.enabled && !p.fly.enabled;
+        if p.mouse_state.held[2] && orbiting && !p.ui_wants_mouse {
+            let pos = p.mouse_state.pixel();
+            if let Some(last) = p.pan_pos {
+                p.pan_drag((pos.0 - last.0) as f32, (pos.1 - last.1) as f32);
+            }
+            p.pan_pos = Some(pos);
+        } else {
+            p.pan_pos = None;
+        }
+        if p.mouse_state.double_click[2] && !p.ui_wants_mouse {
+            p.view.pan = State::DEFAULT_PAN;
+        }
+        if p.mouse_state.double_click[0] && orbiting && !p.ui_wants_mouse {
+            p.focus_on(p.mouse_state.pixel());
         }
 
+        // The wheel scrolls the UI under the mouse and zooms anywhere else,
+        // either way it's used up this frame
+        if p.mouse_state.wheel != 0.0 && orbiting && !p.ui_wants_mouse {
+            p.inertia.add_zoom(p.mouse_state.wheel);
+            p.bookmarks.cancel();
+        }
+        let gestures = p.gestures.input();
+        if orbiting && !p.ui_wants_mouse {
+            let (pan_x, pan_y) = gestures.pan;
+            let moved = gestures.orbit_yaw != 0.0
+                || gestures.orbit_pitch != 0.0
+                || gestures.zoom != 0.0
+                || pan_x != 0.0
+                || pan_y != 0.0;
+            if moved {
+                p.inertia
+                    .add_orbit(gestures.orbit_yaw, gestures.orbit_pitch);
+                p.inertia.add_zoom(gestures.zoom);
+                // Touches come in logical pixels
+                let scale = hidpi as f32;
+                p.pan_drag(pan_x * scale, pan_y * scale);
+                p.bookmarks.cancel();
+            }
+        }
+        if held_at
+            .iter()
+            .flatten()
+            .any(|&last| last != p.mouse_state.pixel())
         {
-            let planet_uniforms = uniform! {
-                MV: array4x4(planet_matrix),
-                P: array4x4(projection),
-                sunPos: array3(p.sun_pos),
-                shadowmap_p: array4x4(shadowmap_p),
-                shadowmap_v: array4x4(shadowmap_v),
-                tex: Sampler::new(&shadowmap_texture),
-            };
+            p.bookmarks.cancel();
+        }
+        p.mouse_state.end_frame();
 
-            let cloud_uniforms = uniform! {
-                MV: array4x4(cloud_matrix),
-                P: array4x4(projection),
-                time: time,
-                sunPos: array3(p.sun_pos),
-            };
+        let start = Instant::now();
+        p.gamepad.poll();
+        if p.gamepad.is_connected() && !p.palette.is_open() {
+            let input = p.gamepad.input(dt);
+            p.inertia.add_spin(input.spin);
+            p.inertia.add_orbit(input.orbit_yaw, input.orbit_pitch);
+            p.inertia.add_zoom(input.zoom);
+            if input.sun_angle != 0.0 {
+                p.view.sun_angle =
+                    (p.view.sun_angle + input.sun_angle + 180.0).rem_euclid(360.0) - 180.0;
+                p.update_sun_pos();
+            }
+        }
+        p.stutter.record("gamepad", start);
 
-            let star_uniforms = uniform! {
-                mvp: array4x4(projection * planet_matrix),
-            };
+        p.apply_inertia(dt);
+        if let Some(pose) = p.bookmarks.step(dt) {
+            p.set_view_pose(pose);
+        }
+        p.view.shown_fov += (p.view.fov - p.view.shown_fov) * (1.0 - (-FOV_EASE_RATE * dt).exp());
 
-            let planet_params = DrawParameters {
-                depth: Depth {
-                    test: DepthTest::IfLess,
-                    write: true,
-                    ..Default::default()
-                },
-                backface_culling: BackfaceCullingMode::CullClockwise,
-                ..Default::default()
-            };
+        if p.fly.enabled {
+            let step = p.fly.step(dt);
+            p.camera_pos += step.map(|km| p.scale.to_render(f64::from(km)));
+        } else if p.surface.enabled && !p.satellite.is_active() {
+            // The movement keys are tracked by the free-fly camera in every
+            // mode
+            let radius_km = p.body.planet_radius_km as f32;
+            p.surface.walk(&p.fly.keys, dt, radius_km);
+        }
 
-            let cloud_params_back = DrawParameters {
-                depth: Depth {
-                    test: DepthTest::IfLess,
-                    write: true,
-                    ..Default::default()
-                },
-                blend: Blend::alpha_blending(),
-                backface_culling: BackfaceCullingMode::CullCounterClockwise,
-                ..Default::default()
-            };
+        let start = Instant::now();
+        update_ui(&ui, &mut p);
+        p.stutter.record("ui", start);
 
-            let cloud_params_forward = DrawParameters {
-                depth: Depth {
-                    test: DepthTest::IfLess,
-                    write: true,
-                    ..Default::default()
-                },
-                blend: Blend::alpha_blending(),
-                backface_culling: BackfaceCullingMode::CullClockwise,
-                ..Default::default()
-            };
+        // Posed after the UI so nothing in it can move the camera off the path
+        benchmark::pose(&mut p);
+        let time_lapse_frame = timelapse::pose(&mut p, step);
 
-            let star_params = DrawParameters {
-                depth: Depth {
-                    test: DepthTest::IfLess,
-                    write: true,
-                    ..Default::default()
-                },
-                ..Default::default()
-            };
+        let time = p.benchmark.time().unwrap_or(p.clock.time);
 
-            let mut target = display.draw();
-            target.clear_color(0.0, 0.0, 0.0, 0.0);
-            target.clear_depth(1.0);
+        // Follows the window size and the shadow resolution setting
+        let shadow_size = p.shadowmap_size(width, height);
+        if shadowmap.size() != shadow_size {
+            let start = Instant::now();
+            shadowmap = Shadowmap::new(&display, shadow_size)?;
+            p.stutter.record("shadowmap resize", start);
+        }
+        let mut shadowmap_framebuffer = shadowmap.framebuffer(&display)?;
 
-            target.draw(
-                &p.vertex_buffer,
-                &p.index_buffer,
-                &p.planet_program.program,
-                &planet_uniforms,
-                &planet_params,
-            )?;
+        // Follows the window size and the cloud resolution setting
+        if p.cloud_resolution == CloudResolution::Full {
+            cloud_target = None;
+        } else if cloud_target.as_ref().is_none_or(|clouds| {
+            clouds.size() != (width.max(1), height.max(1))
+                || clouds.resolution() != p.cloud_resolution
+        }) {
+            let start = Instant::now();
+            cloud_target = Some(CloudTarget::new(
+                &display,
+                (width, height),
+                p.cloud_resolution,
+            )?);
+            p.stutter.record("cloud target resize", start);
+        }
 
-            target.draw(
-                &p.star_buffer,
-                &glium::index::NoIndices(PrimitiveType::Points),
-                &p.star_program.program,
-                &star_uniforms,
-                &star_params,
-            )?;
+        let camera = p.camera_local() / PLANET_RADIUS;
+        p.geometry.select(camera.magnitude());
 
-            target.draw(
-                &p.vertex_buffer,
-                &p.index_buffer,
-                &p.cloud_program.program,
-                &cloud_uniforms,
-                &cloud_params_back,
-            )?;
+        let start = Instant::now();
+        p.chunks.update(&display, camera, &p.geometry.grid)?;
+        p.stutter.record("chunk upload", start);
 
-            target.draw(
-                &p.vertex_buffer,
-                &p.index_buffer,
-                &p.cloud_program.program,
-                &cloud_uniforms,
-                &cloud_params_forward,
-            )?;
+        {
+            let start = Instant::now();
+            let mut target = display.draw();
 
-            target.blit_from_simple_framebuffer(
-                &shadowmap_framebuffer,
-                &glium::Rect {
-                    left: 0,
-                    bottom: 0,
-                    width: width,
-                    height: height,
-                },
-                &glium::BlitTarget {
-                    left: 0,
-                    bottom: 0,
-                    width: width as i32 / 3,
-                    height: height as i32 / 3,
-                },
-                glium::uniforms::MagnifySamplerFilter::Linear,
-            );
+            // A minimized window has no height
+            let aspect = width as f32 / height.max(1) as f32;
+            if let Some(ref clouds) = cloud_target {
+                render_scene(
+                    &mut clouds.scene_framebuffer()?,
+                    &p,
+                    &mut shadowmap_framebuffer,
+                    &shadowmap.texture,
+                    Some(clouds),
+                    aspect,
+                    time,
+                )?;
+
+                let (scene_width, scene_height) = clouds.size();
+                clouds.scene_color.as_surface().blit_whole_color_to(
+                    &target,
+                    &glium::BlitTarget {
+                        left: 0,
+                        bottom: 0,
+                        width: scene_width as i32,
+                        height: scene_height as i32,
+                    },
+                    glium::uniforms::MagnifySamplerFilter::Nearest,
+                );
+            } else {
+                render_scene(
+                    &mut target,
+                    &p,
+                    &mut shadowmap_framebuffer,
+                    &shadowmap.texture,
+                    None,
+                    aspect,
+                    time,
+                )?;
+            }
+
+            if p.workspaces.current.shadowmap_preview {
+                // All of the shadowmap, which is larger than the window at
+                // higher shadow resolutions
+                let (shadow_width, shadow_height) = shadowmap.size();
+                target.blit_from_simple_framebuffer(
+                    &shadowmap_framebuffer,
+                    &glium::Rect {
+                        left: 0,
+                        bottom: 0,
+                        width: shadow_width,
+                        height: shadow_height,
+                    },
+                    &glium::BlitTarget {
+                        left: 0,
+                        bottom: 0,
+                        width: width as i32 / 3,
+                        height: height as i32 / 3,
+                    },
+                    glium::uniforms::MagnifySamplerFilter::Linear,
+                );
+            }
 
+            if p.recording.is_running() && !p.recording.include_ui {
+                record_frame(&display, &target, &mut p);
+            }
             imgui_renderer.render(&mut target, ui).unwrap();
+            if p.recording.is_running() && p.recording.include_ui {
+                record_frame(&display, &target, &mut p);
+            }
+            p.stutter.record("render", start);
+
+            // Read back before the swap, when the frame is still whole
+            if p.screenshot_requested {
+                p.screenshot_requested = false;
+                let start = Instant::now();
+                match save_screenshot(&display, &target, &mut p) {
+                    Ok(path) => println!("Saving screenshot to {}", path.display()),
+                    Err(e) => println!("Screenshot failed: {}", e),
+                }
+                p.stutter.record("screenshot", start);
+            }
+            let cpu_ms = milliseconds_since(frame_start);
+
+            let start = Instant::now();
             target.finish()?;
+            p.stutter.record("swap", start);
+            p.swap_ms = milliseconds_since(start);
+
+            let frame_ms = milliseconds_since(frame_start);
+            match benchmark::record(&display, &mut p, frame_ms, cpu_ms, (width, height)) {
+                Ok(Some(path)) => {
+                    println!("Benchmark report: {}", path.display());
+                    if let Some(golden) =
+                        p.benchmark.report.as_ref().and_then(|r| r.golden.as_ref())
+                    {
+                        println!("Golden image: {}", benchmark::describe_golden(golden));
+                    }
+                    if p.benchmark.exit_when_done {
+                        p.run = false;
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => println!("Writing the benchmark report failed: {}", e),
+            }
+        }
+
+        if p.capture_requested {
+            p.capture_requested = false;
+            let start = Instant::now();
+            match capture::save(&display, &p, width, height, time) {
+                Ok(path) => println!("Saved debug capture to {}", path.display()),
+                Err(e) => println!("Debug capture failed: {}", e),
+            }
+            p.stutter.record("debug capture", start);
+        }
+
+        if p.scene_screenshot_requested {
+            p.scene_screenshot_requested = false;
+            let start = Instant::now();
+            match save_scene_screenshot(&display, &mut p, width, height, time) {
+                Ok(path) => println!("Saving screenshot to {}", path.display()),
+                Err(e) => println!("Screenshot failed: {}", e),
+            }
+            p.stutter.record("screenshot", start);
+        }
+
+        if let Some(frame) = time_lapse_frame {
+            let start = Instant::now();
+            if let Err(e) = timelapse::capture(&display, &mut p, frame, width, height, time) {
+                p.time_lapse.error = Some(e.to_string());
+                p.time_lapse.stop();
+            }
+            p.stutter.record("time-lapse frame", start);
+        }
+
+        // The benchmark's fixed settings aren't the user's
+        if !p.benchmark.is_running() {
+            let snapshot = capture::settings(&p, width, height, 0.0);
+            p.session.update(dt, snapshot);
+        }
+
+        for written in p.screenshots.poll() {
+            if p.time_lapse.owns(&written.path) {
+                p.time_lapse.written(&written);
+            } else if p.recording.owns(&written.path) {
+                p.recording.frame_written(&written);
+            } else if let Some(ref error) = written.error {
+                println!("Writing {} failed: {}", written.path.display(), error);
+            }
+        }
+
+        // A stopped time-lapse leaves a playable video
+        if !p.time_lapse.is_running() && p.screenshots.has_video() {
+            p.screenshots.finish_video();
+        }
+
+        // What was streamed is drawn, the ranges go back to the pools
+        p.line_pool.end_frame();
+        p.meteors.pool.end_frame();
+
+        // Measuring or writing frames goes as fast as it can
+        let limit = !p.benchmark.is_running() && !p.recording.is_running();
+        p.frame_limiter.wait(limit);
+    }
+
+    // Nothing queued is lost on the way out
+    p.time_lapse.stop();
+    if p.screenshots.pending() > 0 {
+        println!(
+            "Writing {} queued images before exiting",
+            p.screenshots.pending()
+        );
+    }
+    for written in p.screenshots.wait() {
+        if let Some(ref error) = written.error {
+            println!("Writing {} failed: {}", written.path.display(), error);
         }
     }
 
+    p.session.shutdown();
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What the event loop hands MouseState, in the order it comes
+    enum Input {
+        Move(f64, f64),
+        Press(usize),
+        Release(usize),
+        Scroll(f32),
+        EndFrame,
+        // Time passing between events
+        Wait(Duration),
+    }
+
+    /// Feeds `inputs` to `mouse`, with the time the events come at kept in
+    /// `clock`.
+    fn play(mouse: &mut MouseState, clock: &mut Instant, inputs: &[Input]) {
+        for input in inputs {
+            match *input {
+                Input::Move(x, y) => mouse.pos = (x, y),
+                Input::Press(button) => mouse.button(button, true, *clock),
+                Input::Release(button) => mouse.button(button, false, *clock),
+                Input::Scroll(notches) => mouse.scroll(notches),
+                Input::EndFrame => mouse.end_frame(),
+                Input::Wait(duration) => *clock += duration,
+            }
+        }
+    }
+
+    #[test]
+    fn wheel_adds_up_over_a_frame() {
+        let mut mouse = MouseState::new();
+        let mut clock = Instant::now();
+        play(
+            &mut mouse,
+            &mut clock,
+            &[Input::Scroll(1.0), Input::Scroll(2.0), Input::Scroll(-0.5)],
+        );
+        assert_eq!(mouse.wheel, 2.5);
+        play(&mut mouse, &mut clock, &[Input::EndFrame]);
+        assert_eq!(mouse.wheel, 0.0);
+        play(
+            &mut mouse,
+            &mut clock,
+            &[Input::Scroll(-1.0), Input::EndFrame, Input::Scroll(-1.0)],
+        );
+        assert_eq!(mouse.wheel, -1.0);
+    }
+
+    #[test]
+    fn clicked_for_a_frame_held_until_released() {
+        let mut mouse = MouseState::new();
+        let mut clock = Instant::now();
+        play(&mut mouse, &mut clock, &[Input::Press(0)]);
+        assert!(mouse.clicked[0] && mouse.held[0]);
+        assert!(!mouse.clicked[1] && !mouse.held[1]);
+
+        play(&mut mouse, &mut clock, &[Input::EndFrame]);
+        assert!(!mouse.clicked[0] && mouse.held[0] && mouse.down(0));
+
+        play(&mut mouse, &mut clock, &[Input::Release(0)]);
+        assert!(!mouse.held[0] && !mouse.down(0));
+    }
+
+    #[test]
+    fn clicks_within_a_frame_are_not_missed() {
+        let mut mouse = MouseState::new();
+        let mut clock = Instant::now();
+        play(
+            &mut mouse,
+            &mut clock,
+            &[Input::Press(2), Input::Release(2)],
+        );
+        assert!(!mouse.held[2]);
+        assert!(mouse.down(2));
+        play(&mut mouse, &mut clock, &[Input::EndFrame]);
+        assert!(!mouse.down(2));
+    }
+
+    #[test]
+    fn double_clicks() {
+        let mut mouse = MouseState::new();
+        let mut clock = Instant::now();
+        play(
+            &mut mouse,
+            &mut clock,
+            &[Input::Press(0), Input::Release(0), Input::EndFrame],
+        );
+        assert!(!mouse.double_click[0]);
+        play(&mut mouse, &mut clock, &[Input::Press(0)]);
+        assert!(mouse.double_click[0]);
+        play(
+            &mut mouse,
+            &mut clock,
+            &[Input::Release(0), Input::EndFrame],
+        );
+        assert!(!mouse.double_click[0]);
+
+        // A third press starts over rather than making another
+        play(&mut mouse, &mut clock, &[Input::Press(0)]);
+        assert!(!mouse.double_click[0]);
+
+        // And the second of two slow ones is just a click
+        play(
+            &mut mouse,
+            &mut clock,
+            &[
+                Input::Release(0),
+                Input::EndFrame,
+                Input::Wait(DOUBLE_CLICK_TIME),
+                Input::Press(0),
+            ],
+        );
+        assert!(mouse.clicked[0] && !mouse.double_click[0]);
+
+        // Only just quick enough still counts
+        play(
+            &mut mouse,
+            &mut clock,
+            &[
+                Input::Release(0),
+                Input::EndFrame,
+                Input::Wait(DOUBLE_CLICK_TIME - Duration::from_millis(1)),
+                Input::Press(0),
+            ],
+        );
+        assert!(mouse.double_click[0]);
+    }
+
+    #[test]
+    fn pixel_under_the_cursor() {
+        let mut mouse = MouseState::new();
+        let mut clock = Instant::now();
+        play(&mut mouse, &mut clock, &[Input::Move(10.7, 3.2)]);
+        assert_eq!(mouse.pixel(), (10, 3));
+        play(&mut mouse, &mut clock, &[Input::Move(-0.2, 0.0)]);
+        assert_eq!(mouse.pixel(), (-1, 0));
+    }
+
+    #[test]
+    fn reset_goes_back_to_the_default_pose() {
+        for preset in PRESETS.iter() {
+            let scale = SceneScale::for_preset(preset, PLANET_RADIUS);
+            let mut view = OrbitView::new();
+            view.rotation = Quaternion::from(Matrix3::from_angle_y(Deg(30.0)));
+            view.sun_angle = 45.0;
+            view.set_distance(
+                scale.to_render(preset.planet_radius_km * 3.0),
+                preset,
+                &scale,
+            );
+            view.orbit_yaw = 20.0;
+            view.orbit_pitch = -10.0;
+            view.pan = Vector2::new(0.5, -0.25);
+            view.fov = 40.0;
+            view.shown_fov = 60.0;
+            assert_ne!(view.pose(&scale), State::default_pose(preset));
+
+            view.reset(preset, &scale);
+            let pose = view.pose(&scale);
+            let default = State::default_pose(preset);
+            // The distance goes through render units and back
+            assert!(
+                (pose.distance_km - default.distance_km).abs() < 1e-9 * default.distance_km,
+                "{}",
+                preset.name
+            );
+            assert_eq!(
+                ViewPose {
+                    distance_km: default.distance_km,
+                    ..pose
+                },
+                default
+            );
+            assert_eq!(
+                (view.fov, view.shown_fov),
+                (State::DEFAULT_FOV, State::DEFAULT_FOV)
+            );
+        }
+    }
+}
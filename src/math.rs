@@ -0,0 +1,247 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+/// Whether `point` is hidden behind a sphere of `radius` centered on the
+/// origin when seen from `camera`, both given in planet-local space.
+///
+/// The point is visible as long as the distance to it is within the sum of
+/// the camera's and the point's distances to the horizon. This is exact for
+/// points on the sphere and conservative for points above it.
+pub fn is_beyond_horizon(camera: Vector3<f32>, point: Vector3<f32>, radius: f32) -> bool {
+    let radius2 = radius * radius;
+    let camera_horizon = (camera.magnitude2() - radius2).max(0.0).sqrt();
+    let point_horizon = (point.magnitude2() - radius2).max(0.0).sqrt();
+    (point - camera).magnitude() > camera_horizon + point_horizon
+}
+
+/// Distance along the ray from `origin` in the unit `direction` to where it
+/// first enters the ellipsoid `mesh::flatten` squashes a sphere of `radius`
+/// around `center` into, its pole along the unit `axis`. `None` if it
+/// misses, and from inside it is where the ray leaves. Stretched back along
+/// the axis the ellipsoid is the sphere again, and as the stretch is linear
+/// distances along the ray stay the same.
+pub fn ray_ellipsoid(
+    origin: Vector3<f32>,
+    direction: Vector3<f32>,
+    center: Vector3<f32>,
+    radius: f32,
+    axis: Vector3<f32>,
+    flattening: f32,
+) -> Option<f32> {
+    let stretch = |v: Vector3<f32>| v + axis * (v.dot(axis) * (1.0 / (1.0 - flattening) - 1.0));
+    let to_origin = stretch(origin - center);
+    let direction = stretch(direction);
+    let a = direction.magnitude2();
+    let b = to_origin.dot(direction);
+    let c = to_origin.magnitude2() - radius * radius;
+    let discriminant = b * b - a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let root = discriminant.sqrt();
+    [(-b - root) / a, (-b + root) / a]
+        .iter()
+        .copied()
+        .find(|&distance| distance >= 0.0)
+}
+
+/// The six planes bounding what `matrix` projects into clip space, in the
+/// space it projects from. Each is a unit normal pointing inwards and the
+/// distance along it to the origin, so `plane.dot(point.extend(1.0))` is
+/// the distance of the point inside the plane.
+pub fn frustum_planes(matrix: Matrix4<f32>) -> [Vector4<f32>; 6] {
+    let rows = [matrix.row(0), matrix.row(1), matrix.row(2)];
+    let w = matrix.row(3);
+    let mut planes = [
+        w + rows[0],
+        w - rows[0],
+        w + rows[1],
+        w - rows[1],
+        w + rows[2],
+        w - rows[2],
+    ];
+    for plane in &mut planes {
+        let length = plane.truncate().magnitude();
+        if length > 0.0 {
+            *plane /= length;
+        }
+    }
+    planes
+}
+
+/// Whether a sphere lies wholly outside one of the `planes` from
+/// `frustum_planes`. Spheres near a corner of the frustum can pass without
+/// being inside, which only costs a draw.
+pub fn sphere_outside_frustum(
+    planes: &[Vector4<f32>; 6],
+    center: Vector3<f32>,
+    radius: f32,
+) -> bool {
+    planes
+        .iter()
+        .any(|plane| plane.dot(center.extend(1.0)) < -radius)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::vec3;
+
+    // A camera two radii up the z axis of a unit planet, and the point of
+    // the surface `angle` radians around from under it
+    const CAMERA: Vector3<f32> = Vector3 {
+        x: 0.0,
+        y: 0.0,
+        z: 3.0,
+    };
+
+    fn surface(angle: f32, radius: f32) -> Vector3<f32> {
+        vec3(angle.sin(), 0.0, angle.cos()) * radius
+    }
+
+    #[test]
+    fn horizon_at_the_nadir_and_the_antipode() {
+        assert!(!is_beyond_horizon(CAMERA, surface(0.0, 1.0), 1.0));
+        assert!(is_beyond_horizon(
+            CAMERA,
+            surface(std::f32::consts::PI, 1.0),
+            1.0
+        ));
+    }
+
+    #[test]
+    fn horizon_at_the_limb() {
+        // The tangent from the camera touches the sphere where cos = 1 / 3
+        let limb = (1.0f32 / 3.0).acos();
+        assert!(!is_beyond_horizon(CAMERA, surface(limb - 1e-3, 1.0), 1.0));
+        assert!(is_beyond_horizon(CAMERA, surface(limb + 1e-3, 1.0), 1.0));
+
+        // A mountain just past the limb still shows over it
+        assert!(!is_beyond_horizon(CAMERA, surface(limb + 0.05, 1.05), 1.0));
+        assert!(is_beyond_horizon(CAMERA, surface(limb + 0.5, 1.05), 1.0));
+    }
+
+    #[test]
+    fn horizon_of_terrain_below_the_sphere() {
+        // Ground sunk below the planet's radius past its limb is hidden by
+        // the sphere, but not by the lowest the ground gets
+        let limb = (0.9f32 / 3.0).acos();
+        let sunk = surface(limb - 1e-2, 0.9);
+        assert!(is_beyond_horizon(CAMERA, sunk, 1.0));
+        assert!(!is_beyond_horizon(CAMERA, sunk, 0.9));
+    }
+
+    #[test]
+    fn ray_ellipsoid_hits_land_on_the_ellipsoid() {
+        let center = vec3(1.0, -2.0, 0.5);
+        let radius = 0.65;
+        let flattening = 0.2;
+        let axis = vec3(0.3, 1.0, -0.2).normalize();
+        let polar = radius * (1.0 - flattening);
+
+        let mut hits = 0;
+        for i in 0..200 {
+            // Rays from all around, aimed near the middle
+            let t = i as f32 * 0.7;
+            let from = vec3(t.cos() * 3.0, (t * 0.37).sin() * 3.0, (t * 1.3).cos() * 3.0);
+            let aim = vec3((t * 2.1).sin(), (t * 1.7).cos(), t.sin()) * 0.5;
+            let origin = center + from;
+            let direction = (aim - from).normalize();
+
+            if let Some(distance) =
+                ray_ellipsoid(origin, direction, center, radius, axis, flattening)
+            {
+                hits += 1;
+                assert!(distance >= 0.0);
+                let offset = origin + direction * distance - center;
+                let along = offset.dot(axis);
+                let across = offset - axis * along;
+                let level =
+                    across.magnitude2() / (radius * radius) + along * along / (polar * polar);
+                assert!(
+                    (level - 1.0).abs() < 1e-4,
+                    "ray {} is {} off",
+                    i,
+                    level - 1.0
+                );
+            }
+        }
+        assert!(hits > 100);
+
+        // Straight down the pole it stops at the polar radius
+        let down = ray_ellipsoid(center + axis * 2.0, -axis, center, radius, axis, flattening);
+        assert!((down.unwrap() - (2.0 - polar)).abs() < 1e-5);
+        // And past the side it misses
+        let past = ray_ellipsoid(
+            center + axis * 2.0 + axis.cross(vec3(1.0, 0.0, 0.0)).normalize(),
+            -axis,
+            center,
+            radius,
+            axis,
+            flattening,
+        );
+        assert_eq!(past, None);
+    }
+
+    fn perspective() -> Matrix4<f32> {
+        cgmath::perspective(cgmath::Deg(90.0), 1.0, 0.1, 100.0)
+    }
+
+    #[test]
+    fn frustum_planes_are_unit_distances() {
+        let planes = frustum_planes(perspective());
+        for plane in &planes {
+            assert!((plane.truncate().magnitude() - 1.0).abs() < 1e-6);
+        }
+        // Left, right, bottom, top at 45 degrees, then near and far
+        let point = vec3(0.0, 0.0, -5.0).extend(1.0);
+        let side = 5.0 / 2.0f32.sqrt();
+        let expected = [side, side, side, side, 4.9, 95.0];
+        for (plane, expected) in planes.iter().zip(&expected) {
+            assert!((plane.dot(point) - expected).abs() < 1e-3, "{:?}", plane);
+        }
+    }
+
+    #[test]
+    fn frustum_planes_of_the_identity_are_the_unit_cube() {
+        let planes = frustum_planes(Matrix4::from_scale(1.0));
+        let inside = vec3(0.9, -0.9, 0.5);
+        assert!(planes
+            .iter()
+            .all(|plane| plane.dot(inside.extend(1.0)) > 0.0));
+        let outside = vec3(1.1, 0.0, 0.0);
+        assert!(planes
+            .iter()
+            .any(|plane| plane.dot(outside.extend(1.0)) < 0.0));
+    }
+
+    #[test]
+    fn spheres_outside_the_frustum() {
+        let planes = frustum_planes(perspective());
+        let outside = |x, y, z, radius| sphere_outside_frustum(&planes, vec3(x, y, z), radius);
+        // In front, and partly across each plane
+        assert!(!outside(0.0, 0.0, -10.0, 1.0));
+        assert!(!outside(-10.5, 0.0, -10.0, 1.0));
+        assert!(!outside(0.0, 10.5, -10.0, 1.0));
+        assert!(!outside(0.0, 0.0, 0.5, 1.0));
+        assert!(!outside(0.0, 0.0, -100.5, 1.0));
+        // Wholly past one of them
+        assert!(outside(-12.0, 0.0, -10.0, 1.0));
+        assert!(outside(0.0, -12.0, -10.0, 1.0));
+        assert!(outside(0.0, 0.0, 2.0, 1.0));
+        assert!(outside(0.0, 0.0, -102.0, 1.0));
+    }
+
+    #[test]
+    fn frustum_planes_follow_the_view() {
+        let view = Matrix4::look_at(
+            cgmath::Point3::new(0.0, 0.0, 5.0),
+            cgmath::Point3::new(0.0, 0.0, 0.0),
+            vec3(0.0, 1.0, 0.0),
+        );
+        let planes = frustum_planes(perspective() * view);
+        assert!(!sphere_outside_frustum(&planes, vec3(0.0, 0.0, 0.0), 1.0));
+        assert!(sphere_outside_frustum(&planes, vec3(0.0, 0.0, 8.0), 1.0));
+        assert!(sphere_outside_frustum(&planes, vec3(10.0, 0.0, 0.0), 1.0));
+    }
+}
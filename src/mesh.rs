@@ -0,0 +1,1002 @@
+use crate::craters::{CraterField, Craters};
+use crate::cubesphere::create_cube_sphere;
+use crate::grid::{PlanetGrid, GRID_SUBDIVISIONS};
+use crate::icosphere::create_icosphere;
+use crate::jobs::MeshWorker;
+use crate::noise;
+use crate::{Vertex, PLANET_RADIUS};
+use cgmath::{vec3, InnerSpace, Vector3};
+use glium::{
+    backend::Facade,
+    buffer::ReadError,
+    index::{IndicesSource, PrimitiveType},
+    texture::{texture1d::Texture1d, MipmapsOption, UncompressedFloatFormat},
+    IndexBuffer, VertexBuffer,
+};
+use std::collections::HashMap;
+use std::error;
+use std::f32::consts::PI;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Which mesh the planet and its cloud shell are drawn with.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PlanetMesh {
+    // Rings of latitude, crowded together at the poles
+    UvSphere,
+    // A subdivided icosahedron, about the same size triangles everywhere
+    Icosphere,
+    // Six grids projected out from a cube, even quads with face local UVs
+    CubeSphere,
+}
+
+impl PlanetMesh {
+    pub fn name(self) -> &'static str {
+        match self {
+            PlanetMesh::UvSphere => "UV sphere",
+            PlanetMesh::Icosphere => "Icosphere",
+            PlanetMesh::CubeSphere => "Cube sphere",
+        }
+    }
+}
+
+pub struct SphereMesh {
+    pub vertices: Vec<Vertex>,
+    pub indices: Vec<u32>,
+}
+
+/// The `tex` coordinates `create_sphere` gives the point of the mesh along
+/// `direction` from its center.
+pub fn sphere_uv(direction: Vector3<f32>) -> [f32; 2] {
+    let n = direction.normalize();
+    let phi = n.y.atan2(n.x).rem_euclid(2.0 * PI);
+    let theta = n.z.clamp(-1.0, 1.0).acos();
+    [phi / (2.0 * PI), 1.0 - theta / PI]
+}
+
+/// The tangent at any point of a mesh with `sphere_uv` coordinates, from
+/// the point's u. East does not change with latitude, so this holds at the
+/// poles too, where the pole vertex's u picks one, and is the same at u = 0
+/// and u = 1 across the seam.
+pub fn sphere_tangent(u: f32) -> [f32; 4] {
+    let phi = u * 2.0 * PI;
+    // North, the way v grows, is normal x east
+    [-phi.sin(), phi.cos(), 0.0, 1.0]
+}
+
+/// A UV sphere with `segments` rings of latitude, at least 2, and twice as
+/// many of longitude. Each ring has an extra vertex where it closes, with u
+/// at 1 rather than 0.
+pub fn create_sphere(radius: f32, segments: usize) -> SphereMesh {
+    let vsegs = segments.max(2);
+    let hsegs = vsegs * 2;
+    let nverts = 1 + (vsegs - 1) * (hsegs + 1) + 1; // top + middle + bottom
+    let ntris = hsegs + (vsegs - 2) * hsegs * 2 + hsegs; // top + middle + bottom
+
+    let mut vertices = Vec::with_capacity(nverts);
+    let mut indices = Vec::with_capacity(ntris * 3);
+
+    // Top
+    vertices.push(Vertex {
+        pos: [0.0, 0.0, radius],
+        normal: [0.0, 0.0, 1.0],
+        tex: [0.5, 1.0],
+        tangent: sphere_tangent(0.5),
+        cell: 0,
+    });
+
+    for j in 0..(vsegs - 1) {
+        let theta = ((j + 1) as f32) / (vsegs as f32) * PI;
+        let z = theta.cos();
+        let r = theta.sin();
+        for i in 0..=hsegs {
+            let phi = i as f32 / hsegs as f32 * 2.0 * PI;
+            let x = r * phi.cos();
+            let y = r * phi.sin();
+            vertices.push(Vertex {
+                pos: [radius * x, radius * y, radius * z],
+                normal: [x, y, z],
+                tex: [
+                    i as f32 / hsegs as f32,
+                    1.0 - (j as f32 + 1.0) / vsegs as f32,
+                ],
+                tangent: sphere_tangent(i as f32 / hsegs as f32),
+                cell: 0,
+            });
+        }
+    }
+
+    // Bottom
+    vertices.push(Vertex {
+        pos: [0.0, 0.0, -radius],
+        normal: [0.0, 0.0, -1.0],
+        tex: [0.5, 0.0],
+        tangent: sphere_tangent(0.5),
+        cell: 0,
+    });
+
+    let ring = |j: usize, i: usize| (1 + j * (hsegs + 1) + i) as u32;
+
+    // Top cap
+    for i in 0..hsegs {
+        indices.extend_from_slice(&[0, ring(0, i), ring(0, i + 1)]);
+    }
+    // Middle part (possibly empty if vsegs=2)
+    for j in 0..(vsegs - 2) {
+        for i in 0..hsegs {
+            indices.extend_from_slice(&[ring(j, i), ring(j + 1, i), ring(j, i + 1)]);
+            indices.extend_from_slice(&[ring(j, i + 1), ring(j + 1, i), ring(j + 1, i + 1)]);
+        }
+    }
+    // Bottom cap
+    let bottom = (nverts - 1) as u32;
+    for i in 0..hsegs {
+        indices.extend_from_slice(&[bottom, ring(vsegs - 2, i + 1), ring(vsegs - 2, i)]);
+    }
+
+    debug_assert_eq!(vertices.len(), nverts);
+    debug_assert_eq!(indices.len(), ntris * 3);
+    SphereMesh { vertices, indices }
+}
+
+// Mesh detail is counted in rings of latitude on the UV sphere, the other
+// meshes are built to about as many triangles
+pub const DEFAULT_DETAIL: i32 = 512;
+pub const MIN_DETAIL: i32 = 16;
+pub const MAX_DETAIL: i32 = 1024;
+
+// How much flatter than round a planet can be squashed, a little more
+// than Saturn
+pub const MAX_FLATTENING: f32 = 0.2;
+
+/// An index buffer of whichever width the mesh needs. Draw calls take it
+/// the same as a plain `IndexBuffer`.
+pub enum AnyIndexBuffer {
+    U16(IndexBuffer<u16>),
+    U32(IndexBuffer<u32>),
+}
+
+/// Whether 16 bit indices reach every one of `vertex_count` vertices. The
+/// top value is left out, it is the primitive restart index.
+pub fn fits_u16(vertex_count: usize) -> bool {
+    vertex_count <= u16::MAX as usize
+}
+
+impl AnyIndexBuffer {
+    pub fn new<F: Facade>(
+        facade: &F,
+        vertex_count: usize,
+        indices: &[u32],
+    ) -> Result<AnyIndexBuffer, Box<dyn error::Error>> {
+        Ok(if fits_u16(vertex_count) {
+            let narrow: Vec<u16> = indices.iter().map(|&i| i as u16).collect();
+            AnyIndexBuffer::U16(IndexBuffer::new(
+                facade,
+                PrimitiveType::TrianglesList,
+                &narrow,
+            )?)
+        } else {
+            AnyIndexBuffer::U32(IndexBuffer::new(
+                facade,
+                PrimitiveType::TrianglesList,
+                indices,
+            )?)
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            AnyIndexBuffer::U16(buffer) => buffer.len(),
+            AnyIndexBuffer::U32(buffer) => buffer.len(),
+        }
+    }
+
+    /// Reads the indices back from the GPU, widened to u32.
+    pub fn read(&self) -> Result<Vec<u32>, ReadError> {
+        Ok(match self {
+            AnyIndexBuffer::U16(buffer) => buffer.read()?.into_iter().map(u32::from).collect(),
+            AnyIndexBuffer::U32(buffer) => buffer.read()?,
+        })
+    }
+}
+
+impl<'a> From<&'a AnyIndexBuffer> for IndicesSource<'a> {
+    fn from(buffer: &'a AnyIndexBuffer) -> IndicesSource<'a> {
+        match buffer {
+            AnyIndexBuffer::U16(buffer) => buffer.into(),
+            AnyIndexBuffer::U32(buffer) => buffer.into(),
+        }
+    }
+}
+
+/// Builds `mesh` at `detail`. The UV sphere has about 4 * detail^2
+/// triangles, the icosphere 20 * 4^subdivisions and the cube sphere
+/// 12 * resolution^2.
+fn build(mesh: PlanetMesh, detail: i32) -> SphereMesh {
+    let detail = detail.clamp(MIN_DETAIL, MAX_DETAIL) as f32;
+    let (vertices, indices) = match mesh {
+        PlanetMesh::UvSphere => return create_sphere(PLANET_RADIUS, detail as usize),
+        PlanetMesh::Icosphere => {
+            let subdivisions = ((detail * detail / 5.0).log2() / 2.0).round();
+            create_icosphere(subdivisions.max(0.0) as u32, PLANET_RADIUS)
+        }
+        PlanetMesh::CubeSphere => {
+            let resolution = (detail / 3f32.sqrt()).round();
+            create_cube_sphere(resolution.max(1.0) as usize, PLANET_RADIUS)
+        }
+    };
+    SphereMesh { vertices, indices }
+}
+
+// Distance from the planet's center, in radii, out to which each level of
+// detail is drawn. Each level has half the detail of the one before.
+const LOD_DISTANCES: [f32; 4] = [2.0, 4.0, 8.0, f32::INFINITY];
+
+// How far past a level's limit the camera has to go before the level
+// changes, as a fraction of the limit, so it doesn't flicker at the edge
+const LOD_HYSTERESIS: f32 = 0.1;
+
+// The cloud shell sits above the surface, it keeps its detail out to this
+// much further
+const CLOUD_LOD_SCALE: f32 = 1.2;
+
+/// One of the planet meshes, drawn while the camera is within
+/// `max_distance` planet radii of the center and further out than the one
+/// before.
+pub struct LodLevel {
+    // The smooth sphere, which the clouds are always drawn with
+    pub vertex_buffer: VertexBuffer<Vertex>,
+    // The same vertices with the terrain built in, for the surface
+    pub displaced_buffer: Option<VertexBuffer<Vertex>>,
+    pub index_buffer: AnyIndexBuffer,
+    pub max_distance: f32,
+}
+
+impl LodLevel {
+    fn new<F: Facade>(
+        facade: &F,
+        level: &LevelMesh,
+        max_distance: f32,
+    ) -> Result<LodLevel, Box<dyn error::Error>> {
+        let sphere = &level.sphere;
+        Ok(LodLevel {
+            vertex_buffer: VertexBuffer::new(facade, &sphere.vertices)?,
+            displaced_buffer: match level.displaced {
+                Some(ref vertices) => Some(VertexBuffer::new(facade, vertices)?),
+                None => None,
+            },
+            index_buffer: AnyIndexBuffer::new(facade, sphere.vertices.len(), &sphere.indices)?,
+            max_distance,
+        })
+    }
+
+    /// The vertices to draw the planet's surface with.
+    pub fn surface(&self) -> &VertexBuffer<Vertex> {
+        self.displaced_buffer
+            .as_ref()
+            .unwrap_or(&self.vertex_buffer)
+    }
+
+    /// The surface's vertices and indices, read back from the GPU.
+    pub fn read_surface(&self) -> Result<(Vec<Vertex>, Vec<u32>), ReadError> {
+        Ok((self.surface().read()?, self.index_buffer.read()?))
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.vertex_buffer.len()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.index_buffer.len() / 3
+    }
+}
+
+/// The level to draw at `distance`, given each level's `max_distance`,
+/// moving on from `current` only once the distance is clearly past a
+/// level's limit.
+pub fn select_lod(max_distances: &[f32], current: usize, distance: f32) -> usize {
+    let mut level = current.min(max_distances.len().saturating_sub(1));
+    while level + 1 < max_distances.len()
+        && distance > max_distances[level] * (1.0 + LOD_HYSTERESIS)
+    {
+        level += 1;
+    }
+    while level > 0 && distance < max_distances[level - 1] * (1.0 - LOD_HYSTERESIS) {
+        level -= 1;
+    }
+    level
+}
+
+/// Terrain built into the planet mesh, on top of what the shaders add.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Displacement {
+    pub seed: i32,
+    // Render units out or in at the noise's peaks, none leaves the sphere
+    // smooth
+    pub amplitude: f32,
+    pub octaves: i32,
+    // Noise features across one render unit at the first octave
+    pub frequency: f32,
+    // Scattered with the same seed as the noise
+    pub craters: Craters,
+}
+
+impl Displacement {
+    pub fn new() -> Displacement {
+        Displacement {
+            seed: 1,
+            amplitude: 0.0,
+            octaves: 6,
+            frequency: 2.0,
+            craters: Craters::new(),
+        }
+    }
+
+    fn has_noise(&self) -> bool {
+        self.amplitude != 0.0 && self.octaves > 0
+    }
+
+    pub fn is_flat(&self) -> bool {
+        !self.has_noise() && self.craters.count <= 0
+    }
+
+    /// The furthest the terrain can sink below a sphere of `radius`: the
+    /// noise stays within its amplitude, and a crater's bowl is as deep as
+    /// the largest crater's size times the depth.
+    pub fn max_depth(&self, radius: f32) -> f32 {
+        let noise = if self.has_noise() {
+            self.amplitude.abs()
+        } else {
+            0.0
+        };
+        let craters = if self.craters.count > 0 {
+            let craters = &self.craters;
+            radius * craters.min_size.max(craters.max_size) * craters.depth.abs()
+        } else {
+            0.0
+        };
+        noise + craters
+    }
+}
+
+/// Squashes a mesh around the origin along y, its pole, into an ellipsoid
+/// whose polar radius is `1 - flattening` of its equatorial one. Normals go
+/// through the inverse transpose of the squash, which for points of a
+/// sphere is the ellipsoid's gradient, and tangents through the squash
+/// itself so they stay in the surface.
+pub fn flatten(vertices: &mut [Vertex], flattening: f32) {
+    if flattening == 0.0 {
+        return;
+    }
+    let polar = 1.0 - flattening.clamp(0.0, MAX_FLATTENING);
+    for vertex in vertices {
+        let [x, y, z] = vertex.pos;
+        vertex.pos = [x, y * polar, z];
+        let [nx, ny, nz] = vertex.normal;
+        vertex.normal = vec3(nx, ny / polar, nz).normalize().into();
+        let [tx, ty, tz, handedness] = vertex.tangent;
+        let tangent = vec3(tx, ty * polar, tz);
+        if tangent.magnitude2() > 0.0 {
+            let tangent = tangent.normalize();
+            vertex.tangent = [tangent.x, tangent.y, tangent.z, handedness];
+        }
+    }
+}
+
+/// The vertices of `sphere` moved along their normals by the noise and the
+/// craters, with smooth normals worked out again from the moved triangles. Vertices that
+/// share a position, as along a seam, get the same normal.
+pub fn displace(sphere: &SphereMesh, displacement: &Displacement) -> Vec<Vertex> {
+    let craters = CraterField::new(&displacement.craters, displacement.seed);
+    let mut vertices = sphere.vertices.clone();
+    for vertex in &mut vertices {
+        let pos = Vector3::from(vertex.pos);
+        let normal = Vector3::from(vertex.normal);
+        let mut height = 0.0;
+        if displacement.has_noise() {
+            let sample = pos * displacement.frequency;
+            height += displacement.amplitude
+                * noise::seeded_fbm(sample.into(), displacement.seed, displacement.octaves);
+        }
+        if !craters.is_empty() {
+            height += craters.height(pos.normalize()) * pos.magnitude();
+        }
+        vertex.pos = (pos + normal * height).into();
+    }
+
+    // Welded by where they were before moving, to a hundred thousandth of a
+    // render unit
+    let key = |vertex: &Vertex| {
+        let quantize = |x: f32| (x * 1e5).round() as i32;
+        [
+            quantize(vertex.pos[0]),
+            quantize(vertex.pos[1]),
+            quantize(vertex.pos[2]),
+        ]
+    };
+    let welds: Vec<[i32; 3]> = sphere.vertices.iter().map(key).collect();
+    let mut sums: HashMap<[i32; 3], Vector3<f32>> = HashMap::new();
+    for triangle in sphere.indices.chunks(3) {
+        let corner = |k: usize| Vector3::from(vertices[triangle[k] as usize].pos);
+        // Longer for larger triangles, which weights them by area
+        let normal = (corner(1) - corner(0)).cross(corner(2) - corner(0));
+        for &index in triangle {
+            *sums
+                .entry(welds[index as usize])
+                .or_insert_with(|| vec3(0.0, 0.0, 0.0)) += normal;
+        }
+    }
+
+    for (vertex, weld) in vertices.iter_mut().zip(welds.iter()) {
+        let sum = sums
+            .get(weld)
+            .copied()
+            .unwrap_or_else(|| vec3(0.0, 0.0, 0.0));
+        if sum.magnitude2() > 0.0 {
+            let normal = sum.normalize();
+            let tangent = vec3(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            let tangent = (tangent - normal * normal.dot(tangent)).normalize();
+            vertex.normal = normal.into();
+            vertex.tangent = [tangent.x, tangent.y, tangent.z, vertex.tangent[3]];
+        }
+    }
+    vertices
+}
+
+/// One level of detail, and its vertices displaced when there is any
+/// terrain to build in.
+pub struct LevelMesh {
+    sphere: SphereMesh,
+    displaced: Option<Vec<Vertex>>,
+}
+
+pub fn build_levels(
+    mesh: PlanetMesh,
+    detail: i32,
+    displacement: Displacement,
+    flattening: f32,
+    grid: &PlanetGrid,
+) -> Vec<LevelMesh> {
+    (0..LOD_DISTANCES.len())
+        .map(|level| {
+            let mut sphere = build(mesh, detail >> level);
+            // Cells by direction on the sphere, so they keep their latitude
+            grid.assign(&mut sphere.vertices);
+            flatten(&mut sphere.vertices, flattening);
+            let displaced = if displacement.is_flat() {
+                None
+            } else {
+                Some(displace(&sphere, &displacement))
+            };
+            LevelMesh { sphere, displaced }
+        })
+        .collect()
+}
+
+/// The planet mesh on the GPU, at each level of detail. Changing `mesh`,
+/// `detail`, `displacement` or `flattening` rebuilds them on the mesh worker and swaps
+/// them in once they are done, the old ones are drawn until then. A build
+/// asked for while another runs makes that one stale, it is never uploaded.
+pub struct PlanetGeometry {
+    pub mesh: PlanetMesh,
+    // Of the finest level
+    pub detail: i32,
+    pub displacement: Displacement,
+    // Of the planet and so of its cloud shell, see `flatten`
+    pub flattening: f32,
+    pub levels: Vec<LodLevel>,
+    // The cells each vertex is in, and their values on the GPU
+    pub grid: Arc<PlanetGrid>,
+    pub cell_values: Texture1d,
+    // Draw this level whatever the distance, for debugging
+    pub forced_level: Option<usize>,
+    // Levels coarser than the distance calls for, to save GPU time
+    pub lod_bias: u32,
+    planet_level: usize,
+    cloud_level: usize,
+    // What the buffers hold and what was last asked of the worker
+    built: (PlanetMesh, i32, Displacement, f32),
+    requested: (PlanetMesh, i32, Displacement, f32),
+    worker: MeshWorker,
+}
+
+impl PlanetGeometry {
+    pub fn new<F: Facade>(
+        facade: &F,
+        mesh: PlanetMesh,
+    ) -> Result<PlanetGeometry, Box<dyn error::Error>> {
+        let displacement = Displacement::new();
+        let grid = Arc::new(PlanetGrid::new(GRID_SUBDIVISIONS));
+        let levels = build_levels(mesh, DEFAULT_DETAIL, displacement, 0.0, &grid);
+        let mut geometry = PlanetGeometry {
+            mesh,
+            detail: DEFAULT_DETAIL,
+            displacement,
+            flattening: 0.0,
+            levels: Vec::new(),
+            cell_values: Texture1d::with_format(
+                facade,
+                grid.values.clone(),
+                UncompressedFloatFormat::F32,
+                MipmapsOption::NoMipmap,
+            )?,
+            worker: MeshWorker::new(grid.clone()),
+            grid,
+            forced_level: None,
+            lod_bias: 0,
+            planet_level: 0,
+            cloud_level: 0,
+            built: (mesh, DEFAULT_DETAIL, displacement, 0.0),
+            requested: (mesh, DEFAULT_DETAIL, displacement, 0.0),
+        };
+        geometry.upload(facade, &levels)?;
+        Ok(geometry)
+    }
+
+    fn upload<F: Facade>(
+        &mut self,
+        facade: &F,
+        levels: &[LevelMesh],
+    ) -> Result<(), Box<dyn error::Error>> {
+        self.levels = levels
+            .iter()
+            .zip(LOD_DISTANCES.iter())
+            .map(|(level, &max_distance)| LodLevel::new(facade, level, max_distance))
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    pub fn planet_level(&self) -> usize {
+        self.forced_level
+            .unwrap_or(self.planet_level + self.lod_bias as usize)
+            .min(self.levels.len() - 1)
+    }
+
+    pub fn cloud_level(&self) -> usize {
+        self.forced_level
+            .unwrap_or(self.cloud_level + self.lod_bias as usize)
+            .min(self.levels.len() - 1)
+    }
+
+    /// The mesh to draw the planet with.
+    pub fn planet(&self) -> &LodLevel {
+        &self.levels[self.planet_level()]
+    }
+
+    /// The mesh to draw the cloud shell with.
+    pub fn clouds(&self) -> &LodLevel {
+        &self.levels[self.cloud_level()]
+    }
+
+    /// Picks the levels for a camera `distance` planet radii from the
+    /// center.
+    pub fn select(&mut self, distance: f32) {
+        // The levels are always built for these
+        let max_distances = &LOD_DISTANCES[..self.levels.len()];
+        self.planet_level = select_lod(max_distances, self.planet_level, distance);
+        self.cloud_level = select_lod(max_distances, self.cloud_level, distance / CLOUD_LOD_SCALE);
+    }
+
+    /// The flattening of the mesh on the GPU, which lags `flattening` while
+    /// a rebuild runs.
+    pub fn built_flattening(&self) -> f32 {
+        self.built.3
+    }
+
+    /// The radius of a sphere the surface as built never dips inside of,
+    /// for a planet of `radius`. Hides what's behind the planet without
+    /// hiding what's over the lower ground at the limb.
+    pub fn horizon_radius(&self, radius: f32) -> f32 {
+        let polar = radius * (1.0 - self.built.3);
+        (polar - self.built.2.max_depth(radius)).max(0.0)
+    }
+
+    pub fn is_building(&self) -> bool {
+        self.worker.is_pending()
+    }
+
+    /// How long the build running has been going, if there is one.
+    pub fn building_for(&self) -> Option<Duration> {
+        if self.is_building() {
+            Some(self.worker.requested_at().elapsed())
+        } else {
+            None
+        }
+    }
+
+    /// Uploads a finished build, and asks for the next one if the mesh,
+    /// detail or displacement wanted is not the one on the GPU or on its
+    /// way.
+    pub fn update<F: Facade>(&mut self, facade: &F) -> Result<(), Box<dyn error::Error>> {
+        if let Some(result) = self.worker.poll() {
+            self.upload(facade, &result.levels)?;
+            self.built = (
+                result.generator,
+                result.detail,
+                result.displacement,
+                result.flattening,
+            );
+        }
+
+        let wanted = (
+            self.mesh,
+            self.detail.clamp(MIN_DETAIL, MAX_DETAIL),
+            self.displacement,
+            self.flattening.clamp(0.0, MAX_FLATTENING),
+        );
+        if wanted == self.built {
+            // Back to what is on the GPU before the build finished
+            if self.worker.is_pending() {
+                self.worker.cancel();
+            }
+        } else if wanted != self.requested || !self.worker.is_pending() {
+            self.worker.submit(wanted.0, wanted.1, wanted.2, wanted.3);
+        }
+        self.requested = wanted;
+
+        Ok(())
+    }
+
+    /// Builds and uploads the meshes for the settings as they are now,
+    /// blocking until they are done, for a frame rendered only once.
+    pub fn wait<F: Facade>(&mut self, facade: &F) -> Result<(), Box<dyn error::Error>> {
+        self.update(facade)?;
+        while self.is_building() {
+            thread::sleep(Duration::from_millis(1));
+            self.update(facade)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    const RADIUS: f32 = 0.65;
+    const SEGMENTS: [usize; 4] = [2, 3, 16, 512];
+
+    fn pos(mesh: &SphereMesh, index: u32) -> Vector3<f32> {
+        Vector3::from(mesh.vertices[index as usize].pos)
+    }
+
+    // Each vertex as the first vertex at its position, so the seam and the
+    // poles count once
+    fn welds(mesh: &SphereMesh) -> Vec<u32> {
+        let mut first: HashMap<[i32; 3], u32> = HashMap::new();
+        mesh.vertices
+            .iter()
+            .enumerate()
+            .map(|(index, vertex)| {
+                let key = vertex.pos.map(|x| (x * 1e5).round() as i32);
+                *first.entry(key).or_insert(index as u32)
+            })
+            .collect()
+    }
+
+    // The edges of the welded mesh, each once whichever way it runs
+    fn edges(mesh: &SphereMesh, welds: &[u32]) -> HashSet<(u32, u32)> {
+        let mut edges = HashSet::new();
+        for triangle in mesh.indices.chunks(3) {
+            for k in 0..3 {
+                let a = welds[triangle[k] as usize];
+                let b = welds[triangle[(k + 1) % 3] as usize];
+                edges.insert((a.min(b), a.max(b)));
+            }
+        }
+        edges
+    }
+
+    #[test]
+    fn sphere_triangle_count() {
+        for segments in SEGMENTS {
+            let mesh = create_sphere(RADIUS, segments);
+            assert_eq!(mesh.indices.len() % 3, 0);
+            assert_eq!(mesh.indices.len() / 3, 4 * segments * (segments - 1));
+        }
+    }
+
+    #[test]
+    fn sphere_sizes() {
+        // Two poles and a ring of 2 * segments + 1 between each pair of
+        // rings of triangles
+        let two = create_sphere(RADIUS, 2);
+        assert_eq!(two.vertices.len(), 7);
+        assert_eq!(two.indices.len(), 8 * 3);
+
+        let three = create_sphere(RADIUS, 3);
+        assert_eq!(three.vertices.len(), 16);
+        assert_eq!(three.indices.len(), 24 * 3);
+
+        let large = create_sphere(RADIUS, 1000);
+        assert_eq!(large.vertices.len(), 2 + 999 * 2001);
+        assert_eq!(large.indices.len(), 4 * 1000 * 999 * 3);
+
+        for mesh in [&two, &three, &large] {
+            let count = mesh.vertices.len() as u32;
+            assert!(mesh.indices.iter().all(|&index| index < count));
+        }
+    }
+
+    #[test]
+    fn sphere_has_at_least_two_segments() {
+        for segments in [0, 1] {
+            let mesh = create_sphere(RADIUS, segments);
+            assert_eq!(mesh.vertices.len(), 7);
+            assert_eq!(mesh.indices.len(), 8 * 3);
+        }
+    }
+
+    #[test]
+    fn sphere_euler_characteristic() {
+        for segments in SEGMENTS {
+            let mesh = create_sphere(RADIUS, segments);
+            let welds = welds(&mesh);
+            let vertices = welds.iter().collect::<HashSet<_>>().len() as i64;
+            let edges = edges(&mesh, &welds).len() as i64;
+            let faces = (mesh.indices.len() / 3) as i64;
+            assert_eq!(vertices - edges + faces, 2, "{} segments", segments);
+        }
+    }
+
+    #[test]
+    fn sphere_winds_counter_clockwise_from_outside() {
+        // The planet culls clockwise faces, so the outside has to be
+        // counter-clockwise
+        for segments in SEGMENTS {
+            let mesh = create_sphere(RADIUS, segments);
+            for triangle in mesh.indices.chunks(3) {
+                let [a, b, c] = [0, 1, 2].map(|k| pos(&mesh, triangle[k]));
+                let normal = (b - a).cross(c - a);
+                assert!(normal.dot(a + b + c) > 0.0, "{:?}", triangle);
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_normals_are_unit_and_outward() {
+        for segments in SEGMENTS {
+            let mesh = create_sphere(RADIUS, segments);
+            for vertex in &mesh.vertices {
+                let normal = Vector3::from(vertex.normal);
+                let outward = Vector3::from(vertex.pos).normalize();
+                assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+                assert!(normal.dot(outward) > 1.0 - 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_referenced_normals_are_not_zero() {
+        for segments in SEGMENTS {
+            let mesh = create_sphere(RADIUS, segments);
+            for &index in &mesh.indices {
+                let normal = Vector3::from(mesh.vertices[index as usize].normal);
+                assert!(normal.magnitude2() > 0.0, "vertex {}", index);
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_seam_is_continuous() {
+        for segments in SEGMENTS {
+            let mesh = create_sphere(RADIUS, segments);
+            // The u = 0 vertex of each ring, by its v
+            let starts: HashMap<u32, &Vertex> = mesh
+                .vertices
+                .iter()
+                .filter(|vertex| vertex.tex[0] == 0.0)
+                .map(|vertex| (vertex.tex[1].to_bits(), vertex))
+                .collect();
+            let ends: Vec<&Vertex> = mesh
+                .vertices
+                .iter()
+                .filter(|vertex| vertex.tex[0] == 1.0)
+                .collect();
+            assert_eq!(starts.len(), segments - 1);
+            assert_eq!(ends.len(), segments - 1);
+
+            for end in ends {
+                let start = starts[&end.tex[1].to_bits()];
+                let distance = Vector3::from(end.pos) - Vector3::from(start.pos);
+                let turn = Vector3::from(end.normal) - Vector3::from(start.normal);
+                assert!(distance.magnitude() < 1e-5, "v {}", end.tex[1]);
+                assert!(turn.magnitude() < 1e-5, "v {}", end.tex[1]);
+            }
+        }
+    }
+
+    #[test]
+    fn sphere_vertices_are_on_the_radius() {
+        for segments in SEGMENTS {
+            let mesh = create_sphere(RADIUS, segments);
+            for vertex in &mesh.vertices {
+                let length = Vector3::from(vertex.pos).magnitude();
+                assert!((length - RADIUS).abs() < 1e-5 * RADIUS);
+            }
+        }
+    }
+
+    #[test]
+    fn flattened_normals_follow_the_ellipsoid_gradient() {
+        for flattening in [0.05, MAX_FLATTENING] {
+            let mut mesh = create_sphere(RADIUS, 16);
+            flatten(&mut mesh.vertices, flattening);
+            // x^2 / a^2 + y^2 / b^2 + z^2 / a^2 = 1, the pole along y
+            let a2 = RADIUS * RADIUS;
+            let b2 = a2 * (1.0 - flattening) * (1.0 - flattening);
+            for vertex in &mesh.vertices {
+                let [x, y, z] = vertex.pos;
+                let level = x * x / a2 + y * y / b2 + z * z / a2;
+                assert!(
+                    (level - 1.0).abs() < 1e-4,
+                    "{:?} is off the ellipsoid",
+                    vertex.pos
+                );
+
+                let gradient = vec3(x / a2, y / b2, z / a2).normalize();
+                let normal = Vector3::from(vertex.normal);
+                assert!((normal.magnitude() - 1.0).abs() < 1e-5);
+                assert!(normal.dot(gradient) > 1.0 - 1e-5, "{:?}", vertex.pos);
+
+                // The tangents stay in the surface
+                let tangent = vec3(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+                assert!(tangent.dot(normal).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn terrain_stays_above_its_max_depth() {
+        let mut displacement = Displacement::new();
+        displacement.amplitude = 0.04;
+        let flattening = 0.1;
+
+        let mut sphere = create_sphere(RADIUS, 64);
+        flatten(&mut sphere.vertices, flattening);
+        let lowest = RADIUS * (1.0 - flattening) - displacement.max_depth(RADIUS);
+        for vertex in displace(&sphere, &displacement) {
+            assert!(Vector3::from(vertex.pos).magnitude() >= lowest);
+        }
+
+        // The deepest bowl of the largest crater on top
+        displacement.craters.count = 1;
+        let craters = RADIUS * displacement.craters.max_size * displacement.craters.depth;
+        assert_eq!(displacement.max_depth(RADIUS), 0.04 + craters);
+    }
+
+    #[test]
+    fn select_lod_by_distance() {
+        // From where it settled last, well away from any limit
+        assert_eq!(select_lod(&LOD_DISTANCES, 0, 1.5), 0);
+        assert_eq!(select_lod(&LOD_DISTANCES, 0, 3.0), 1);
+        assert_eq!(select_lod(&LOD_DISTANCES, 0, 6.0), 2);
+        assert_eq!(select_lod(&LOD_DISTANCES, 0, 100.0), 3);
+        assert_eq!(select_lod(&LOD_DISTANCES, 3, 1.5), 0);
+        assert_eq!(select_lod(&LOD_DISTANCES, 3, 3.0), 1);
+    }
+
+    #[test]
+    fn select_lod_holds_within_the_hysteresis() {
+        let limit = LOD_DISTANCES[0];
+        let inside = limit * (1.0 + LOD_HYSTERESIS / 2.0);
+        let outside = limit * (1.0 - LOD_HYSTERESIS / 2.0);
+        // Either side of the limit, within the band, it stays where it is
+        assert_eq!(select_lod(&LOD_DISTANCES, 0, inside), 0);
+        assert_eq!(select_lod(&LOD_DISTANCES, 1, outside), 1);
+        assert_eq!(select_lod(&LOD_DISTANCES, 0, limit), 0);
+        assert_eq!(select_lod(&LOD_DISTANCES, 1, limit), 1);
+
+        // And moves once it is past the band
+        let past = limit * (1.0 + LOD_HYSTERESIS * 1.5);
+        let back = limit * (1.0 - LOD_HYSTERESIS * 1.5);
+        assert_eq!(select_lod(&LOD_DISTANCES, 0, past), 1);
+        assert_eq!(select_lod(&LOD_DISTANCES, 1, back), 0);
+
+        // Going back and forth over the limit doesn't flicker
+        let mut level = 0;
+        for step in 0..100 {
+            let wobble = if step % 2 == 0 { inside } else { outside };
+            level = select_lod(&LOD_DISTANCES, level, wobble);
+            assert_eq!(level, 0);
+        }
+    }
+
+    #[test]
+    fn select_lod_with_fewer_levels() {
+        // The levels built may be fewer, and the current one out of range
+        assert_eq!(select_lod(&LOD_DISTANCES[..2], 3, 100.0), 1);
+        assert_eq!(select_lod(&LOD_DISTANCES[..1], 0, 100.0), 0);
+        assert_eq!(select_lod(&[], 2, 1.0), 0);
+    }
+
+    #[test]
+    fn u16_indices_exactly_up_to_65535_vertices() {
+        assert!(fits_u16(0));
+        assert!(fits_u16(65535));
+        assert!(!fits_u16(65536));
+        assert!(!fits_u16(usize::MAX));
+
+        // The UV spheres either side of it, 65342 and 66067 vertices
+        let under = create_sphere(RADIUS, 181);
+        assert!(fits_u16(under.vertices.len()));
+        // Narrowing loses nothing, and never makes the restart index
+        for &index in &under.indices {
+            assert!(index < u32::from(u16::MAX));
+            assert_eq!(u32::from(index as u16), index);
+        }
+        let over = create_sphere(RADIUS, 182);
+        assert!(!fits_u16(over.vertices.len()));
+    }
+
+    #[test]
+    fn tangents_are_across_the_normals_and_follow_the_uvs() {
+        for &mesh in &[
+            PlanetMesh::UvSphere,
+            PlanetMesh::Icosphere,
+            PlanetMesh::CubeSphere,
+        ] {
+            let sphere = build(mesh, 48);
+            for vertex in &sphere.vertices {
+                let normal = Vector3::from(vertex.normal);
+                let tangent = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+                assert!(tangent.dot(normal).abs() < 1e-5, "{:?}", mesh);
+                assert!((tangent.magnitude() - 1.0).abs() < 1e-5, "{:?}", mesh);
+                assert!(vertex.tangent[3] == 1.0 || vertex.tangent[3] == -1.0);
+            }
+
+            // The way u and v grow across each triangle, from its positions
+            // and texture coordinates, and the tangent and normal x tangent
+            // agreeing with them at each corner. Triangles over the seam of
+            // the UV sphere and the icosphere, and corners at their poles
+            // where u has no direction, are left out. The cube sphere's
+            // faces have their own edge vertices, so none of its triangles
+            // are.
+            let mut checked = 0;
+            for triangle in sphere.indices.chunks(3) {
+                let corner = |k: usize| &sphere.vertices[triangle[k] as usize];
+                let (a, b, c) = (corner(0), corner(1), corner(2));
+                let uv = |v: &Vertex| (v.tex[0] - a.tex[0], v.tex[1] - a.tex[1]);
+                let ((u1, v1), (u2, v2)) = (uv(b), uv(c));
+                let across = u1.abs().max(u2.abs()).max(v1.abs()).max(v2.abs());
+                assert!(
+                    mesh != PlanetMesh::CubeSphere || across < 0.25,
+                    "cube sphere triangle across a face edge"
+                );
+                if across > 0.25 {
+                    continue;
+                }
+                let det = u1 * v2 - u2 * v1;
+                if det.abs() < 1e-12 {
+                    continue;
+                }
+                let e1 = Vector3::from(b.pos) - Vector3::from(a.pos);
+                let e2 = Vector3::from(c.pos) - Vector3::from(a.pos);
+                let along_u = (e1 * v2 - e2 * v1) / det;
+                let along_v = (e2 * u1 - e1 * u2) / det;
+                for vertex in &[a, b, c] {
+                    let normal = Vector3::from(vertex.normal);
+                    if mesh != PlanetMesh::CubeSphere && normal.z.abs() > 0.999 {
+                        continue;
+                    }
+                    let tangent =
+                        Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+                    let bitangent = normal.cross(tangent) * vertex.tangent[3];
+                    assert!(tangent.dot(along_u) > 0.0, "{:?} tangent against u", mesh);
+                    assert!(
+                        bitangent.dot(along_v) > 0.0,
+                        "{:?} bitangent against v",
+                        mesh
+                    );
+                    checked += 1;
+                }
+            }
+            if mesh == PlanetMesh::CubeSphere {
+                assert_eq!(checked, sphere.indices.len(), "{:?}", mesh);
+            }
+            assert!(checked > sphere.indices.len() / 2, "{:?} {}", mesh, checked);
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use crate::stream::{Stream, StreamPool, FRAMES_IN_FLIGHT};
+use cgmath::{vec3, InnerSpace, Vector3};
+use glium::{backend::Facade, implement_vertex, vertex::VertexBufferSlice};
+use rand::distributions::{Distribution, UnitSphereSurface};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::error;
+
+const MAX_METEORS: usize = 256;
+const VERTICES_PER_METEOR: usize = 6;
+
+// How much a meteor storm multiplies the rate, and for how long
+const STORM_FACTOR: f32 = 25.0;
+const STORM_DURATION: f32 = 10.0;
+
+#[derive(Copy, Clone, Default)]
+pub struct MeteorVertex {
+    meteor_pos: [f32; 3],
+    meteor_axis: [f32; 3],
+    meteor_side: f32,
+    meteor_along: f32,
+    meteor_fade: f32,
+}
+implement_vertex!(
+    MeteorVertex,
+    meteor_pos,
+    meteor_axis,
+    meteor_side,
+    meteor_along,
+    meteor_fade
+);
+
+pub struct MeteorSettings {
+    pub enabled: bool,
+    pub seed: i32,
+    pub rate: f32,
+    pub radiant_latitude: f32,
+    pub radiant_longitude: f32,
+    pub speed_km: f32,
+    pub width_km: f32,
+    pub brightness: f32,
+}
+
+impl MeteorSettings {
+    pub fn new() -> MeteorSettings {
+        MeteorSettings {
+            enabled: true,
+            seed: 1,
+            rate: 2.0,
+            radiant_latitude: 58.0,
+            radiant_longitude: 45.0,
+            speed_km: 60.0,
+            width_km: 10.0,
+            brightness: 1.0,
+        }
+    }
+}
+
+struct Meteor {
+    start: Vector3<f32>,
+    tangent: Vector3<f32>,
+    age: f32,
+    lifetime: f32,
+}
+
+/// Short-lived streaks on a shell just above the clouds. They live in the
+/// sky-fixed frame centered on the planet, so the radiant stays put among
+/// the stars.
+pub struct Meteors {
+    pub settings: MeteorSettings,
+    pub pool: StreamPool<MeteorVertex>,
+    stream: Stream<MeteorVertex>,
+    meteors: Vec<Meteor>,
+    rng: StdRng,
+    seed: i32,
+    spawn_accumulator: f32,
+    storm_remaining: f32,
+}
+
+impl Meteors {
+    pub fn new<F: Facade>(facade: &F) -> Result<Meteors, Box<dyn error::Error>> {
+        let settings = MeteorSettings::new();
+        let seed = settings.seed;
+        Ok(Meteors {
+            settings,
+            // Room for every meteor in each frame in flight and the next
+            pool: StreamPool::new(
+                facade,
+                MAX_METEORS * VERTICES_PER_METEOR * (FRAMES_IN_FLIGHT + 1),
+            )?,
+            stream: Stream::Empty,
+            meteors: Vec::new(),
+            rng: StdRng::seed_from_u64(seed as u64),
+            seed,
+            spawn_accumulator: 0.0,
+            storm_remaining: 0.0,
+        })
+    }
+
+    /// Removes every meteor and restarts the random sequence of the seed, so
+    /// the same updates spawn the same meteors again.
+    pub fn reset(&mut self) {
+        self.meteors.clear();
+        self.rng = StdRng::seed_from_u64(self.settings.seed as u64);
+        self.seed = self.settings.seed;
+        self.spawn_accumulator = 0.0;
+        self.storm_remaining = 0.0;
+    }
+
+    pub fn start_storm(&mut self) {
+        self.storm_remaining = STORM_DURATION;
+    }
+
+    pub fn is_storming(&self) -> bool {
+        self.storm_remaining > 0.0
+    }
+
+    pub fn count(&self) -> usize {
+        self.meteors.len()
+    }
+
+    fn radiant(&self) -> Vector3<f32> {
+        let lat = self.settings.radiant_latitude.to_radians();
+        let lon = self.settings.radiant_longitude.to_radians();
+        vec3(lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos())
+    }
+
+    /// Ages and spawns meteors, then streams the streak vertices. `speed` is
+    /// the meteor speed in render units per second.
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        dt: f32,
+        shell_radius: f32,
+        speed: f32,
+    ) -> Result<(), Box<dyn error::Error>> {
+        if self.settings.seed != self.seed {
+            self.seed = self.settings.seed;
+            self.rng = StdRng::seed_from_u64(self.seed as u64);
+        }
+
+        self.storm_remaining = (self.storm_remaining - dt).max(0.0);
+
+        for meteor in &mut self.meteors {
+            meteor.age += dt;
+        }
+        self.meteors.retain(|meteor| meteor.age < meteor.lifetime);
+
+        if self.settings.enabled {
+            let rate = if self.is_storming() {
+                self.settings.rate * STORM_FACTOR
+            } else {
+                self.settings.rate
+            };
+
+            self.spawn_accumulator += rate * dt;
+            while self.spawn_accumulator >= 1.0 {
+                self.spawn_accumulator -= 1.0;
+                self.spawn();
+            }
+        } else {
+            self.meteors.clear();
+            self.spawn_accumulator = 0.0;
+        }
+
+        let mut writer = self.pool.writer();
+        for meteor in &self.meteors {
+            // Travel along the great circle through the entry point, with
+            // the trail lagging a third of the lifetime behind the head
+            let angular_speed = speed / shell_radius;
+            let point = |t: f32| {
+                let angle = angular_speed * t.max(0.0);
+                (meteor.start * angle.cos() + meteor.tangent * angle.sin()) * shell_radius
+            };
+
+            let head = point(meteor.age);
+            let tail = point(meteor.age - meteor.lifetime / 3.0);
+            let axis = head - tail;
+
+            // Fade in quickly and out over the last half of the lifetime
+            let life = meteor.age / meteor.lifetime;
+            let fade = (life * 10.0).min(1.0) * (2.0 - 2.0 * life).min(1.0);
+
+            let corner = |pos: Vector3<f32>, side: f32, along: f32| MeteorVertex {
+                meteor_pos: pos.into(),
+                meteor_axis: axis.into(),
+                meteor_side: side,
+                meteor_along: along,
+                meteor_fade: fade,
+            };
+
+            writer.extend(&[
+                corner(tail, -1.0, 0.0),
+                corner(head, -1.0, 1.0),
+                corner(head, 1.0, 1.0),
+                corner(tail, -1.0, 0.0),
+                corner(head, 1.0, 1.0),
+                corner(tail, 1.0, 0.0),
+            ]);
+        }
+
+        self.stream = writer.finish(facade)?;
+        Ok(())
+    }
+
+    /// The streaks streamed this frame, or `None` if there are none.
+    pub fn slice(&self) -> Option<VertexBufferSlice<'_, MeteorVertex>> {
+        self.pool.slice(&self.stream)
+    }
+
+    fn spawn(&mut self) {
+        if self.meteors.len() >= MAX_METEORS {
+            return;
+        }
+
+        // Meteors of a shower arrive as parallel tracks, moving away from
+        // the radiant, so they only enter on the hemisphere facing it
+        let radiant = self.radiant();
+        let v = UnitSphereSurface::new().sample(&mut self.rng);
+        let mut start = vec3(v[0] as f32, v[1] as f32, v[2] as f32);
+        if start.dot(radiant) < 0.0 {
+            start = -start;
+        }
+
+        let direction = -radiant;
+        let tangent = direction - start * direction.dot(start);
+        if tangent.magnitude2() < 1e-6 {
+            return;
+        }
+
+        let lifetime = self.rng.gen_range(0.3, 0.9);
+        self.meteors.push(Meteor {
+            start,
+            tangent: tangent.normalize(),
+            age: 0.0,
+            lifetime,
+        });
+    }
+}
@@ -0,0 +1,90 @@
+use glium::glutin::dpi::LogicalPosition;
+use glium::glutin::Window;
+
+/// Turning the free-fly and surface cameras by moving the mouse, with the
+/// cursor grabbed and hidden so it can't wander off the window. Escape or
+/// losing focus lets it go, clicking back into the window or coming back to
+/// it takes it again.
+pub struct MouseLook {
+    pub enabled: bool,
+    grabbed: bool,
+    focused: bool,
+    // Let go with Escape, until the next click into the window
+    released: bool,
+    delta: (f64, f64),
+}
+
+impl MouseLook {
+    pub fn new() -> MouseLook {
+        MouseLook {
+            enabled: false,
+            grabbed: false,
+            focused: true,
+            released: false,
+            delta: (0.0, 0.0),
+        }
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.grabbed
+    }
+
+    /// Raw motion of the mouse, only counted while grabbed.
+    pub fn add_motion(&mut self, dx: f64, dy: f64) {
+        if self.grabbed {
+            self.delta.0 += dx;
+            self.delta.1 += dy;
+        }
+    }
+
+    /// The motion since the last call.
+    pub fn take_delta(&mut self) -> (f32, f32) {
+        let (dx, dy) = std::mem::replace(&mut self.delta, (0.0, 0.0));
+        (dx as f32, dy as f32)
+    }
+
+    /// Lets go of the cursor if it is grabbed.
+    pub fn release(&mut self) {
+        if self.grabbed {
+            self.released = true;
+        }
+    }
+
+    /// A click into the window, outside the UI.
+    pub fn click(&mut self) {
+        self.released = false;
+    }
+
+    /// The window was made anew, without the grab.
+    pub fn window_replaced(&mut self) {
+        self.grabbed = false;
+    }
+
+    pub fn set_focused(&mut self, focused: bool) {
+        self.focused = focused;
+    }
+
+    /// Grabs or lets go of the cursor as the state asks, `active` being
+    /// whether a camera that looks with the mouse is in use. While grabbed
+    /// the cursor is put back in the middle of the window every frame.
+    pub fn update(&mut self, window: &Window, active: bool) {
+        let grab = self.enabled && active && self.focused && !self.released;
+        if grab != self.grabbed {
+            if let Err(e) = window.grab_cursor(grab) {
+                println!("Grabbing the cursor failed: {}", e);
+            }
+            window.hide_cursor(grab);
+            self.grabbed = grab;
+            self.delta = (0.0, 0.0);
+        }
+
+        if self.grabbed {
+            if let Some(size) = window.get_inner_size() {
+                let center = LogicalPosition::new(size.width * 0.5, size.height * 0.5);
+                // Not every platform can move the cursor, the grab still
+                // keeps it in the window
+                let _ = window.set_cursor_position(center);
+            }
+        }
+    }
+}
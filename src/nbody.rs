@@ -0,0 +1,487 @@
+use crate::polyline::{PolylineBatch, PolylineVertex};
+use crate::stream::StreamPool;
+use crate::PLANET_RADIUS;
+use cgmath::{vec3, InnerSpace, Vector3};
+use glium::backend::Facade;
+use imgui::{ImMouseButton, ImStr, Ui};
+use std::collections::VecDeque;
+use std::error;
+
+// Seconds of simulated time each leapfrog step covers
+pub const NBODY_TIMESTEP: f64 = 1.0 / 240.0;
+
+// Steps a frame can take before the simulation falls behind the clock
+// rather than stall the frame
+const MAX_STEPS_PER_FRAME: u32 = 4000;
+
+// Keeps bodies passing through each other from being flung off, in planet
+// radii
+const SOFTENING: f64 = 0.05;
+
+// Steps between trail points, and how many each body keeps
+const TRAIL_INTERVAL: u32 = 24;
+const MAX_TRAIL_POINTS: usize = 600;
+
+// The two bodies the scene is built around, which can't be removed
+pub const SUN: usize = 0;
+pub const PLANET: usize = 1;
+
+const VIEW_HEIGHT: f32 = 300.0;
+const BODY_RADIUS: f32 = 4.0;
+
+/// A point mass. Positions are in planet radii and velocities in planet
+/// radii a second, in the sky-fixed frame the sun is placed in, with the
+/// orbits in the xz plane to start with. G is 1.
+pub struct Body {
+    pub name: String,
+    pub mass: f64,
+    pub position: Vector3<f64>,
+    pub velocity: Vector3<f64>,
+    pub color: [f32; 4],
+    trail: VecDeque<Vector3<f64>>,
+}
+
+impl Body {
+    pub fn new(name: &str, mass: f64, position: Vector3<f64>, velocity: Vector3<f64>) -> Body {
+        Body {
+            name: name.to_string(),
+            mass,
+            position,
+            velocity,
+            color: [1.0, 1.0, 1.0, 1.0],
+            trail: VecDeque::new(),
+        }
+    }
+}
+
+/// The pull on each body from all the others.
+pub fn accelerations(bodies: &[Body], out: &mut Vec<Vector3<f64>>) {
+    out.clear();
+    out.resize(bodies.len(), vec3(0.0, 0.0, 0.0));
+    for i in 0..bodies.len() {
+        for j in i + 1..bodies.len() {
+            let offset = bodies[j].position - bodies[i].position;
+            let distance2 = offset.magnitude2() + SOFTENING * SOFTENING;
+            let pull = offset / (distance2 * distance2.sqrt());
+            out[i] += pull * bodies[j].mass;
+            out[j] -= pull * bodies[i].mass;
+        }
+    }
+}
+
+/// One kick-drift-kick leapfrog step. `acceleration` has to hold the
+/// accelerations at the start of the step and is left holding those at the
+/// end, ready for the next.
+pub fn leapfrog_step(bodies: &mut [Body], acceleration: &mut Vec<Vector3<f64>>, dt: f64) {
+    for (body, a) in bodies.iter_mut().zip(acceleration.iter()) {
+        body.velocity += a * (0.5 * dt);
+        body.position += body.velocity * dt;
+    }
+    accelerations(bodies, acceleration);
+    for (body, a) in bodies.iter_mut().zip(acceleration.iter()) {
+        body.velocity += a * (0.5 * dt);
+    }
+}
+
+/// Kinetic plus potential energy, with the same softening as the steps.
+pub fn energy(bodies: &[Body]) -> f64 {
+    let mut energy = 0.0;
+    for (i, body) in bodies.iter().enumerate() {
+        energy += 0.5 * body.mass * body.velocity.magnitude2();
+        for other in &bodies[i + 1..] {
+            let distance2 = (other.position - body.position).magnitude2();
+            energy -= body.mass * other.mass / (distance2 + SOFTENING * SOFTENING).sqrt();
+        }
+    }
+    energy
+}
+
+/// The velocity for a circular orbit at `position` around `center`, in
+/// the plane of the map.
+pub fn circular_velocity(center: &Body, position: Vector3<f64>) -> Vector3<f64> {
+    let offset = position - center.position;
+    let distance = offset.magnitude();
+    if distance <= 0.0 {
+        return center.velocity;
+    }
+    let along = vec3(0.0, 1.0, 0.0).cross(offset / distance);
+    let along = if along.magnitude2() > 0.0 {
+        along.normalize()
+    } else {
+        vec3(1.0, 0.0, 0.0)
+    };
+    center.velocity + along * (center.mass / distance).sqrt()
+}
+
+pub struct NBodySettings {
+    pub enabled: bool,
+    // Simulated seconds for each second on the clock
+    pub time_scale: f32,
+    // Planet radii across the system view
+    pub view_extent: f32,
+    // In pixels
+    pub trail_width: f32,
+    pub marker_size: f32,
+}
+
+/// A toy N-body system of the sun, the planet, a moon and whatever else is
+/// added, stepped with leapfrog at `NBODY_TIMESTEP`. While it runs the sun
+/// is lit from wherever it ends up, and the other bodies and their trails
+/// are drawn around the planet.
+pub struct NBody {
+    pub settings: NBodySettings,
+    pub bodies: Vec<Body>,
+    // Relative to the planet, in planet-centered render units
+    pub trail_lines: PolylineBatch,
+    pub markers: PolylineBatch,
+    acceleration: Vec<Vector3<f64>>,
+    accumulator: f64,
+    steps: u64,
+    initial_energy: f64,
+    dragging: Option<usize>,
+    // The body the UI edits
+    pub selected: usize,
+}
+
+impl NBody {
+    pub fn new() -> NBody {
+        let mut nbody = NBody {
+            settings: NBodySettings {
+                enabled: false,
+                time_scale: 1.0,
+                view_extent: 240.0,
+                trail_width: 1.5,
+                marker_size: 6.0,
+            },
+            bodies: Vec::new(),
+            trail_lines: PolylineBatch::new(),
+            markers: PolylineBatch::new(),
+            acceleration: Vec::new(),
+            accumulator: 0.0,
+            steps: 0,
+            initial_energy: 0.0,
+            dragging: None,
+            selected: PLANET,
+        };
+        nbody.reset();
+        nbody
+    }
+
+    /// Back to the sun, the planet on a circular orbit around it and a moon
+    /// on one around the planet, well inside its Hill sphere.
+    pub fn reset(&mut self) {
+        let mut sun = Body::new("Sun", 20_000.0, vec3(0.0, 0.0, 0.0), vec3(0.0, 0.0, 0.0));
+        sun.color = [1.0, 0.9, 0.5, 1.0];
+        let planet_position = vec3(100.0, 0.0, 0.0);
+        let mut planet = Body::new("Planet", 50.0, planet_position, vec3(0.0, 0.0, 0.0));
+        planet.velocity = circular_velocity(&sun, planet_position);
+        planet.color = [0.4, 0.7, 1.0, 1.0];
+        let moon_position = planet_position + vec3(4.0, 0.0, 0.0);
+        let mut moon = Body::new("Moon", 0.5, moon_position, vec3(0.0, 0.0, 0.0));
+        moon.velocity = circular_velocity(&planet, moon_position);
+        moon.color = [0.8, 0.8, 0.8, 1.0];
+
+        self.bodies = vec![sun, planet, moon];
+        self.selected = PLANET;
+        self.restart();
+    }
+
+    /// Starts over from the bodies as they are, after they are edited.
+    pub fn restart(&mut self) {
+        for body in &mut self.bodies {
+            body.trail.clear();
+        }
+        self.accumulator = 0.0;
+        self.steps = 0;
+        self.initial_energy = energy(&self.bodies);
+        self.dragging = None;
+    }
+
+    /// Adds a body at `position` on a circular orbit around whichever body
+    /// pulls on it hardest.
+    pub fn add_body(&mut self, position: Vector3<f64>) -> usize {
+        let center = self
+            .bodies
+            .iter()
+            .max_by(|a, b| {
+                let pull = |body: &Body| body.mass / (body.position - position).magnitude2();
+                pull(a).total_cmp(&pull(b))
+            })
+            .map(|center| circular_velocity(center, position))
+            .unwrap_or_else(|| vec3(0.0, 0.0, 0.0));
+        let name = format!("Body {}", self.bodies.len() + 1);
+        let mut body = Body::new(&name, 0.1, position, center);
+        body.color = [0.6, 1.0, 0.6, 1.0];
+        self.bodies.push(body);
+        self.restart();
+        self.bodies.len() - 1
+    }
+
+    pub fn remove_body(&mut self, index: usize) {
+        if index > PLANET && index < self.bodies.len() {
+            self.bodies.remove(index);
+            self.selected = self.selected.min(self.bodies.len() - 1);
+            self.restart();
+        }
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// How far the total energy has wandered since the last restart, as a
+    /// fraction of it.
+    pub fn energy_drift(&self) -> f64 {
+        if self.initial_energy == 0.0 {
+            0.0
+        } else {
+            (energy(&self.bodies) - self.initial_energy).abs() / self.initial_energy.abs()
+        }
+    }
+
+    /// From the planet toward the sun, while the simulation is on.
+    pub fn sun_direction(&self) -> Option<Vector3<f64>> {
+        if !self.settings.enabled {
+            return None;
+        }
+        let offset = self.bodies[SUN].position - self.bodies[PLANET].position;
+        if offset.magnitude2() > 0.0 {
+            Some(offset.normalize())
+        } else {
+            None
+        }
+    }
+
+    /// Runs the steps `dt` seconds of clock time call for and rewrites the
+    /// trails and markers around the planet.
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        pool: &mut StreamPool<PolylineVertex>,
+        dt: f32,
+    ) -> Result<(), Box<dyn error::Error>> {
+        self.trail_lines.clear();
+        self.markers.clear();
+        if !self.settings.enabled {
+            self.trail_lines.stream(facade, pool)?;
+            return self.markers.stream(facade, pool);
+        }
+
+        // Held still while a body is dragged around the map
+        if self.dragging.is_none() {
+            self.accumulator += f64::from(dt * self.settings.time_scale);
+        }
+        accelerations(&self.bodies, &mut self.acceleration);
+        let mut steps = 0;
+        while self.accumulator >= NBODY_TIMESTEP && steps < MAX_STEPS_PER_FRAME {
+            leapfrog_step(&mut self.bodies, &mut self.acceleration, NBODY_TIMESTEP);
+            self.accumulator -= NBODY_TIMESTEP;
+            self.steps += 1;
+            steps += 1;
+            if self.steps.is_multiple_of(u64::from(TRAIL_INTERVAL)) {
+                for body in &mut self.bodies {
+                    body.trail.push_back(body.position);
+                    if body.trail.len() > MAX_TRAIL_POINTS {
+                        body.trail.pop_front();
+                    }
+                }
+            }
+        }
+        // Behind by more than a frame's worth of steps, let it go
+        self.accumulator = self.accumulator.min(NBODY_TIMESTEP);
+
+        // The trails follow the planet, so a moon draws loops around it
+        // rather than the curl it makes around the sun
+        let planet = self.bodies[PLANET].position;
+        let local = |position: Vector3<f64>| {
+            let offset = (position - planet) * f64::from(PLANET_RADIUS);
+            vec3(offset.x as f32, offset.y as f32, offset.z as f32)
+        };
+        for (i, body) in self.bodies.iter().enumerate() {
+            // The sun and the planet are drawn already
+            if i == SUN || i == PLANET {
+                continue;
+            }
+            let count = body.trail.len().max(1) as f32;
+            let mut points: Vec<_> = body
+                .trail
+                .iter()
+                .enumerate()
+                .map(|(k, &position)| {
+                    let mut color = body.color;
+                    color[3] *= (k + 1) as f32 / count;
+                    (local(position), color)
+                })
+                .collect();
+            points.push((local(body.position), body.color));
+            if points.len() > 1 {
+                self.trail_lines.push(&points);
+            }
+            self.markers.push(&[(local(body.position), body.color)]);
+        }
+
+        self.trail_lines.stream(facade, pool)?;
+        self.markers.stream(facade, pool)
+    }
+
+    /// The system from above, an orthographic map of the xz plane centered
+    /// on the sun. Click on a body to select it, drag it to move it, click
+    /// anywhere else to add one there and right click to remove one.
+    pub fn draw_system_view<'a>(&mut self, ui: &Ui<'a>, label: &ImStr) {
+        let origin = ui.get_cursor_screen_pos();
+        let width = ui.get_content_region_avail().0.max(100.0);
+        let size = (width, VIEW_HEIGHT);
+        ui.invisible_button(label, size);
+        let hovered = ui.is_item_hovered();
+        let active = ui.is_item_active();
+
+        let center = self.bodies[SUN].position;
+        let per_pixel = f64::from(self.settings.view_extent) / f64::from(size.1);
+        let middle = (origin.0 + 0.5 * size.0, origin.1 + 0.5 * size.1);
+        let to_screen = |position: Vector3<f64>| {
+            let offset = (position - center) / per_pixel;
+            (middle.0 + offset.x as f32, middle.1 + offset.z as f32)
+        };
+        let from_screen = |pos: (f32, f32), y: f64| {
+            center
+                + vec3(
+                    f64::from(pos.0 - middle.0) * per_pixel,
+                    y,
+                    f64::from(pos.1 - middle.1) * per_pixel,
+                )
+        };
+
+        let mouse = ui.imgui().mouse_pos();
+        let nearest = self
+            .bodies
+            .iter()
+            .map(|body| {
+                let pos = to_screen(body.position);
+                (pos.0 - mouse.0).powi(2) + (pos.1 - mouse.1).powi(2)
+            })
+            .enumerate()
+            .filter(|&(_, distance2)| distance2 <= (2.0 * BODY_RADIUS).powi(2))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i);
+
+        if hovered && ui.imgui().is_mouse_clicked(ImMouseButton::Left) {
+            let index = match nearest {
+                Some(i) => i,
+                None => self.add_body(from_screen(mouse, 0.0)),
+            };
+            self.selected = index;
+            self.dragging = Some(index);
+        }
+        if hovered && ui.imgui().is_mouse_clicked(ImMouseButton::Right) {
+            if let Some(i) = nearest {
+                self.remove_body(i);
+            }
+        }
+
+        if !active {
+            self.dragging = None;
+        }
+        if let Some(i) = self.dragging {
+            let position = from_screen(mouse, self.bodies[i].position.y);
+            if position != self.bodies[i].position {
+                self.bodies[i].position = position;
+                self.restart();
+                self.dragging = Some(i);
+            }
+        }
+
+        let draw_list = ui.get_window_draw_list();
+        draw_list
+            .add_rect(
+                origin,
+                (origin.0 + size.0, origin.1 + size.1),
+                [0.05, 0.05, 0.1, 1.0],
+            )
+            .filled(true)
+            .build();
+        draw_list.with_clip_rect_intersect(origin, (origin.0 + size.0, origin.1 + size.1), || {
+            for body in &self.bodies {
+                let mut color = body.color;
+                color[3] *= 0.5;
+                let points: Vec<_> = body.trail.iter().map(|&p| to_screen(p)).collect();
+                for pair in points.windows(2) {
+                    draw_list.add_line(pair[0], pair[1], color).build();
+                }
+            }
+            for (i, body) in self.bodies.iter().enumerate() {
+                let color = if Some(i) == self.dragging || Some(i) == nearest {
+                    [1.0, 0.8, 0.2, 1.0]
+                } else {
+                    body.color
+                };
+                let pos = to_screen(body.position);
+                draw_list
+                    .add_circle(pos, BODY_RADIUS, color)
+                    .filled(true)
+                    .build();
+                if i == self.selected {
+                    draw_list
+                        .add_circle(pos, BODY_RADIUS + 3.0, [1.0, 1.0, 1.0, 1.0])
+                        .build();
+                }
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn two_body_circular_orbit_stays_circular() {
+        for distance in [20.0f64, 100.0] {
+            // The sun and the planet of the scene, around their barycenter
+            let (sun_mass, planet_mass) = (20_000.0, 50.0);
+            let total = sun_mass + planet_mass;
+            let offset = vec3(distance, 0.0, 0.0);
+            // What the softened pull keeps on a circle
+            let speed = (total * distance * distance).sqrt()
+                / (distance * distance + SOFTENING * SOFTENING).powf(0.75);
+            let along = vec3(0.0, 0.0, -speed);
+            let mut bodies = vec![
+                Body::new(
+                    "Sun",
+                    sun_mass,
+                    -offset * (planet_mass / total),
+                    -along * (planet_mass / total),
+                ),
+                Body::new(
+                    "Planet",
+                    planet_mass,
+                    offset * (sun_mass / total),
+                    along * (sun_mass / total),
+                ),
+            ];
+
+            let start = energy(&bodies);
+            let mut acceleration = Vec::new();
+            accelerations(&bodies, &mut acceleration);
+            let mut max_drift: f64 = 0.0;
+            for _ in 0..10_000 {
+                leapfrog_step(&mut bodies, &mut acceleration, NBODY_TIMESTEP);
+                let separation = (bodies[1].position - bodies[0].position).magnitude();
+                max_drift = max_drift.max((separation / distance - 1.0).abs());
+            }
+
+            assert!(
+                max_drift < 1e-4,
+                "radius drifted {} at {}",
+                max_drift,
+                distance
+            );
+            let energy_drift = ((energy(&bodies) - start) / start).abs();
+            assert!(
+                energy_drift < 1e-6,
+                "energy drifted {} at {}",
+                energy_drift,
+                distance
+            );
+        }
+    }
+}
@@ -0,0 +1,232 @@
+// CPU mirror of the simplex noise and fbm in shaders/terrain.glsl, used to
+// place things on the displaced surface without reading back from the GPU.
+//
+// Based on the Ashima Arts webgl-noise simplex noise by Ian McEwan, MIT
+// licensed: https://github.com/ashima/webgl-noise
+
+fn mod289(x: f32) -> f32 {
+    x - (x * (1.0 / 289.0)).floor() * 289.0
+}
+
+fn permute(x: f32) -> f32 {
+    mod289(((x * 34.0) + 1.0) * x)
+}
+
+fn taylor_inv_sqrt(r: f32) -> f32 {
+    1.792_842_9 - 0.853_734_73 * r
+}
+
+fn step(edge: f32, x: f32) -> f32 {
+    if x < edge {
+        0.0
+    } else {
+        1.0
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn snoise(v: [f32; 3]) -> f32 {
+    const C: (f32, f32) = (1.0 / 6.0, 1.0 / 3.0);
+
+    // First corner
+    let s = (v[0] + v[1] + v[2]) * C.1;
+    let i = [(v[0] + s).floor(), (v[1] + s).floor(), (v[2] + s).floor()];
+    let t = (i[0] + i[1] + i[2]) * C.0;
+    let x0 = [v[0] - i[0] + t, v[1] - i[1] + t, v[2] - i[2] + t];
+
+    // Other corners
+    let g = [step(x0[1], x0[0]), step(x0[2], x0[1]), step(x0[0], x0[2])];
+    let l = [1.0 - g[0], 1.0 - g[1], 1.0 - g[2]];
+    let i1 = [g[0].min(l[2]), g[1].min(l[0]), g[2].min(l[1])];
+    let i2 = [g[0].max(l[2]), g[1].max(l[0]), g[2].max(l[1])];
+
+    let corners = [
+        x0,
+        [
+            x0[0] - i1[0] + C.0,
+            x0[1] - i1[1] + C.0,
+            x0[2] - i1[2] + C.0,
+        ],
+        [
+            x0[0] - i2[0] + C.1,
+            x0[1] - i2[1] + C.1,
+            x0[2] - i2[2] + C.1,
+        ],
+        [x0[0] - 0.5, x0[1] - 0.5, x0[2] - 0.5],
+    ];
+    let offsets = [[0.0; 3], i1, i2, [1.0; 3]];
+
+    // Permutations
+    let i = [mod289(i[0]), mod289(i[1]), mod289(i[2])];
+
+    // Gradients: 7x7 points over a square, mapped onto an octahedron.
+    let ns = (2.0 / 7.0, 0.5 / 7.0 - 1.0, 1.0 / 7.0);
+
+    let mut sum = 0.0;
+    for k in 0..4 {
+        let o = offsets[k];
+        let p = permute(permute(permute(i[2] + o[2]) + i[1] + o[1]) + i[0] + o[0]);
+
+        let j = p - 49.0 * (p * ns.2 * ns.2).floor();
+        let x_ = (j * ns.2).floor();
+        let y_ = (j - 7.0 * x_).floor();
+
+        let x = x_ * ns.0 + ns.1;
+        let y = y_ * ns.0 + ns.1;
+        let h = 1.0 - x.abs() - y.abs();
+        let sh = -step(h, 0.0);
+
+        let gradient = [
+            x + (x.floor() * 2.0 + 1.0) * sh,
+            y + (y.floor() * 2.0 + 1.0) * sh,
+            h,
+        ];
+        let norm = taylor_inv_sqrt(dot(gradient, gradient));
+
+        let corner = corners[k];
+        let m = (0.6 - dot(corner, corner)).max(0.0);
+        sum += m * m * m * m * norm * dot(gradient, corner);
+    }
+
+    42.0 * sum
+}
+
+// The most `fbm` can reach, with every octave at its peak
+pub const FBM_MAX: f32 = 0.15 * (1.0 + 0.5 + 0.25 + 0.125 + 0.0625);
+
+pub fn fbm(x: [f32; 3]) -> f32 {
+    let mut freq = 0.7;
+    let mut amp = 0.15;
+    let lacunarity = 1.8715;
+    let gain = 0.5;
+
+    let mut sum = 0.0;
+    for _ in 0..5 {
+        sum += amp * snoise([x[0] * freq, x[1] * freq, x[2] * freq]);
+        freq *= lacunarity;
+        amp *= gain;
+    }
+    sum
+}
+
+/// Distance from the planet center of the displaced surface above the unit
+/// direction `dir`, matching planet.vert (oceans stay at `radius`).
+pub fn terrain_altitude(dir: [f32; 3], radius: f32) -> f32 {
+    let pos = [dir[0] * radius, dir[1] * radius, dir[2] * radius];
+    let altitude = radius + fbm(pos);
+    altitude.max(radius)
+}
+
+/// An offset into the noise for `seed` and `octave`, so each seed and each
+/// octave of it samples its own part of the noise. Kept small, the noise
+/// loses precision far from the origin.
+fn seed_offset(seed: i32, octave: i32) -> [f32; 3] {
+    let mut hash =
+        (seed as u32).wrapping_mul(0x9E37_79B9) ^ (octave as u32).wrapping_mul(0x85EB_CA6B);
+    let mut next = || {
+        hash ^= hash >> 15;
+        hash = hash.wrapping_mul(0x2C1B_3C6D);
+        hash ^= hash >> 12;
+        (hash % 25_600) as f32 / 100.0
+    };
+    [next(), next(), next()]
+}
+
+/// fbm of `octaves` octaves from a base frequency of 1, with the seed
+/// picking which terrain it is. The same arguments always give the same
+/// value. Stays within -1 to 1.
+pub fn seeded_fbm(x: [f32; 3], seed: i32, octaves: i32) -> f32 {
+    let mut freq = 1.0;
+    let mut amp = 0.5;
+    let mut sum = 0.0;
+    for octave in 0..octaves.max(0) {
+        let offset = seed_offset(seed, octave);
+        sum += amp
+            * snoise([
+                x[0] * freq + offset[0],
+                x[1] * freq + offset[1],
+                x[2] * freq + offset[2],
+            ]);
+        freq *= 2.0;
+        amp *= 0.5;
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Points spread over a sphere of radius 2, where the planet is sampled.
+    fn points() -> Vec<[f32; 3]> {
+        (0..200)
+            .map(|i| {
+                let t = i as f32 * 0.618_034;
+                let y = 1.0 - 2.0 * (i as f32 + 0.5) / 200.0;
+                let r = (1.0 - y * y).sqrt();
+                let angle = t * std::f32::consts::TAU;
+                [2.0 * r * angle.cos(), 2.0 * y, 2.0 * r * angle.sin()]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_terrain() {
+        for &seed in &[0, 1, 42, -7, i32::MAX] {
+            for &octaves in &[1, 4, 8] {
+                let first: Vec<u32> = points()
+                    .iter()
+                    .map(|&x| seeded_fbm(x, seed, octaves).to_bits())
+                    .collect();
+                let second: Vec<u32> = points()
+                    .iter()
+                    .map(|&x| seeded_fbm(x, seed, octaves).to_bits())
+                    .collect();
+                assert_eq!(first, second);
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_terrain() {
+        let seeds = [0, 1, 2, 42, -7, 1000];
+        let terrains: Vec<Vec<f32>> = seeds
+            .iter()
+            .map(|&seed| points().iter().map(|&x| seeded_fbm(x, seed, 6)).collect())
+            .collect();
+
+        for i in 0..seeds.len() {
+            for j in i + 1..seeds.len() {
+                let same = terrains[i]
+                    .iter()
+                    .zip(&terrains[j])
+                    .filter(|(a, b)| (*a - *b).abs() < 1e-6)
+                    .count();
+                assert!(
+                    same < points().len() / 20,
+                    "seeds {} and {} agree at {} points",
+                    seeds[i],
+                    seeds[j],
+                    same
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn seeded_terrain_stays_in_range() {
+        for &seed in &[0, 3, 99] {
+            let values: Vec<f32> = points().iter().map(|&x| seeded_fbm(x, seed, 8)).collect();
+            assert!(values.iter().all(|value| value.abs() <= 1.0));
+            // Not flat
+            let spread = values.iter().cloned().fold(f32::MIN, f32::max)
+                - values.iter().cloned().fold(f32::MAX, f32::min);
+            assert!(spread > 0.1);
+        }
+        // No octaves, no terrain
+        assert_eq!(seeded_fbm([0.3, 0.2, 0.1], 5, 0), 0.0);
+    }
+}
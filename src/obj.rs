@@ -0,0 +1,325 @@
+use crate::Vertex;
+use cgmath::{vec3, InnerSpace, Vector3};
+use std::collections::HashMap;
+use std::error;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Writes the mesh as Wavefront OBJ, a position, texture coordinate and
+/// normal for every vertex and a face for every three indices. OBJ counts
+/// from 1, and each face corner uses the same index for all three.
+pub fn write_obj<W: Write>(out: &mut W, vertices: &[Vertex], indices: &[u32]) -> io::Result<()> {
+    writeln!(
+        out,
+        "# {} vertices, {} triangles",
+        vertices.len(),
+        indices.len() / 3
+    )?;
+    for vertex in vertices {
+        let [x, y, z] = vertex.pos;
+        writeln!(out, "v {} {} {}", x, y, z)?;
+    }
+    for vertex in vertices {
+        let [u, v] = vertex.tex;
+        writeln!(out, "vt {} {}", u, v)?;
+    }
+    for vertex in vertices {
+        let [x, y, z] = vertex.normal;
+        writeln!(out, "vn {} {} {}", x, y, z)?;
+    }
+    for triangle in indices.chunks_exact(3) {
+        let (a, b, c) = (triangle[0] + 1, triangle[1] + 1, triangle[2] + 1);
+        writeln!(out, "f {0}/{0}/{0} {1}/{1}/{1} {2}/{2}/{2}", a, b, c)?;
+    }
+    Ok(())
+}
+
+pub fn export_obj(
+    path: &Path,
+    vertices: &[Vertex],
+    indices: &[u32],
+) -> Result<(), Box<dyn error::Error>> {
+    let mut file = BufWriter::new(fs::File::create(path)?);
+    write_obj(&mut file, vertices, indices)?;
+    file.flush()?;
+    Ok(())
+}
+
+/// Where a face corner's position, texture coordinate and normal are in
+/// their lists, from 0.
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+struct Corner {
+    position: usize,
+    tex: Option<usize>,
+    normal: Option<usize>,
+}
+
+/// An OBJ index, which counts from 1 or back from the end when negative,
+/// as an index from 0 into a list of `len`.
+fn resolve(index: &str, len: usize, line: usize) -> Result<usize, String> {
+    let index: i64 = index
+        .parse()
+        .map_err(|_| format!("line {}: bad index \"{}\"", line, index))?;
+    let resolved = if index < 0 {
+        len as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(format!("line {}: index {} out of range", line, index));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_floats<const N: usize>(
+    fields: std::str::SplitWhitespace,
+    line: usize,
+) -> Result<[f32; N], String> {
+    let mut values = [0.0; N];
+    let mut count = 0;
+    for field in fields {
+        if count < N {
+            values[count] = field
+                .parse()
+                .map_err(|_| format!("line {}: bad number \"{}\"", line, field))?;
+        }
+        count += 1;
+    }
+    if count < N {
+        return Err(format!("line {}: expected {} numbers", line, N));
+    }
+    Ok(values)
+}
+
+/// Some direction at right angles to `normal`, for meshes that come with
+/// no tangents.
+pub fn any_tangent(normal: Vector3<f32>) -> [f32; 4] {
+    let axis = if normal.x.abs() < 0.9 {
+        vec3(1.0, 0.0, 0.0)
+    } else {
+        vec3(0.0, 1.0, 0.0)
+    };
+    let tangent = (axis - normal * normal.dot(axis)).normalize();
+    [tangent.x, tangent.y, tangent.z, 1.0]
+}
+
+/// Reads the positions, texture coordinates, normals and faces of an OBJ
+/// file, with faces of more than three corners split into fans. Corners
+/// with no normal get their face's, so those faces are drawn flat.
+/// Everything else in the file is skipped.
+pub fn read_obj(text: &str) -> Result<(Vec<Vertex>, Vec<u32>), Box<dyn error::Error>> {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut texes: Vec<[f32; 2]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut faces: Vec<[Corner; 3]> = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        let number = i + 1;
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("v") => positions.push(parse_floats(fields, number)?),
+            Some("vt") => texes.push(parse_floats(fields, number)?),
+            Some("vn") => normals.push(parse_floats(fields, number)?),
+            Some("f") => {
+                let corners = fields
+                    .map(|field| {
+                        let mut parts = field.split('/');
+                        let position =
+                            resolve(parts.next().unwrap_or(""), positions.len(), number)?;
+                        let mut optional = |len: usize| match parts.next() {
+                            Some(part) if !part.is_empty() => resolve(part, len, number).map(Some),
+                            _ => Ok(None),
+                        };
+                        let tex = optional(texes.len())?;
+                        let normal = optional(normals.len())?;
+                        Ok(Corner {
+                            position,
+                            tex,
+                            normal,
+                        })
+                    })
+                    .collect::<Result<Vec<Corner>, String>>()?;
+                if corners.len() < 3 {
+                    return Err(format!("line {}: a face needs three corners", number).into());
+                }
+                for k in 1..corners.len() - 1 {
+                    faces.push([corners[0], corners[k], corners[k + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut vertices = Vec::new();
+    let mut indices = Vec::with_capacity(faces.len() * 3);
+    let mut shared: HashMap<Corner, u32> = HashMap::new();
+    for face in &faces {
+        let corner_pos = |k: usize| Vector3::from(positions[face[k].position]);
+        let flat = (corner_pos(1) - corner_pos(0)).cross(corner_pos(2) - corner_pos(0));
+        let flat = if flat.magnitude2() > 0.0 {
+            flat.normalize()
+        } else {
+            vec3(0.0, 1.0, 0.0)
+        };
+
+        for corner in face {
+            let make = || {
+                let normal = corner
+                    .normal
+                    .map(|n| Vector3::from(normals[n]))
+                    .filter(|n| n.magnitude2() > 0.0)
+                    .map_or(flat, |n| n.normalize());
+                Vertex {
+                    pos: positions[corner.position],
+                    normal: normal.into(),
+                    tex: corner.tex.map_or([0.0, 0.0], |t| texes[t]),
+                    tangent: any_tangent(normal),
+                    cell: 0,
+                }
+            };
+            // Corners with their own normal are shared between faces, the
+            // flat ones belong to theirs alone
+            let index = if corner.normal.is_some() {
+                *shared.entry(*corner).or_insert_with(|| {
+                    vertices.push(make());
+                    vertices.len() as u32 - 1
+                })
+            } else {
+                vertices.push(make());
+                vertices.len() as u32 - 1
+            };
+            indices.push(index);
+        }
+    }
+    Ok((vertices, indices))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: &str = "\
+# a triangle facing +z
+v 0 0 0
+v 1 0 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 0 1
+vn 0 0 2
+";
+
+    fn error(text: &str) -> String {
+        match read_obj(text) {
+            Ok(_) => panic!("{:?} parsed", text),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn reads_corners_with_all_three_indices() {
+        let (vertices, indices) = read_obj(&format!("{}f 1/1/1 2/2/1 3/3/1\n", TRIANGLE)).unwrap();
+        assert_eq!(indices, [0, 1, 2]);
+        assert_eq!(vertices[1].pos, [1.0, 0.0, 0.0]);
+        assert_eq!(vertices[2].tex, [0.0, 1.0]);
+        // Normals come out unit length, tangents across them
+        for vertex in &vertices {
+            assert_eq!(vertex.normal, [0.0, 0.0, 1.0]);
+            let tangent = Vector3::new(vertex.tangent[0], vertex.tangent[1], vertex.tangent[2]);
+            assert!(tangent.dot(Vector3::from(vertex.normal)).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn negative_indices_count_back_from_the_end() {
+        let forward = read_obj(&format!("{}f 1/1/1 2/2/1 3/3/1\n", TRIANGLE)).unwrap();
+        let back = read_obj(&format!("{}f -3/-3/-1 -2/-2/-1 -1/-1/-1\n", TRIANGLE)).unwrap();
+        assert_eq!(back.1, forward.1);
+        for (a, b) in back.0.iter().zip(&forward.0) {
+            assert_eq!((a.pos, a.tex, a.normal), (b.pos, b.tex, b.normal));
+        }
+        // Relative to what has been read so far, not the whole file
+        let early = read_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nf -3 -2 -1\nv 5 5 5\n").unwrap();
+        assert_eq!(early.0[0].pos, [0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn corners_without_normals_are_flat() {
+        let (vertices, indices) =
+            read_obj("v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nf 1 2 3 4\n").unwrap();
+        // The quad is split into a fan, and no corner is shared
+        assert_eq!(indices, [0, 1, 2, 3, 4, 5]);
+        assert!(vertices
+            .iter()
+            .all(|vertex| vertex.normal == [0.0, 0.0, 1.0]));
+        assert!(vertices.iter().all(|vertex| vertex.tex == [0.0, 0.0]));
+
+        let (vertices, _) = read_obj(&format!("{}f 1//1 2//1 3//1\n", TRIANGLE)).unwrap();
+        assert_eq!(vertices[0].tex, [0.0, 0.0]);
+        assert_eq!(vertices[0].normal, [0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn corners_with_normals_are_shared() {
+        let quad = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1 4//1\n";
+        let (vertices, indices) = read_obj(quad).unwrap();
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(indices, [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn skips_what_it_does_not_know() {
+        let text = format!(
+            "mtllib planet.mtl\no planet\ns 1\n\n{}usemtl rock\nf 1/1/1 2/2/1 3/3/1\n",
+            TRIANGLE
+        );
+        assert_eq!(read_obj(&text).unwrap().1.len(), 3);
+    }
+
+    #[test]
+    fn malformed_lines_name_the_line() {
+        assert!(error("v 0 0 0\nv 1 zero 0\n").starts_with("line 2: bad number"));
+        assert!(error("v 0 0\n").starts_with("line 1: expected 3 numbers"));
+        assert!(
+            error("v 0 0 0\nv 1 0 0\nf 1 2\n").starts_with("line 3: a face needs three corners")
+        );
+        assert!(error("v 0 0 0\nv 1 0 0\nf 1 2 3\n").starts_with("line 3: index 3 out of range"));
+        assert!(error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n").contains("index 0 out of range"));
+        assert!(error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 -4\n").contains("index -4 out of range"));
+        assert!(
+            error(&format!("{}f 1/1/2 2/2/1 3/3/1\n", TRIANGLE)).contains("index 2 out of range")
+        );
+        assert!(error("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 x\n").contains("bad index \"x\""));
+    }
+
+    #[test]
+    fn written_spheres_read_back() {
+        let sphere = crate::mesh::create_sphere(0.65, 4);
+        let mut out = Vec::new();
+        write_obj(&mut out, &sphere.vertices, &sphere.indices).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert!(text.starts_with(&format!(
+            "# {} vertices, {} triangles\n",
+            sphere.vertices.len(),
+            sphere.indices.len() / 3
+        )));
+
+        let (vertices, indices) = read_obj(&text).unwrap();
+        assert_eq!(indices.len(), sphere.indices.len());
+        // The reader numbers vertices as it meets them, so compare corner
+        // by corner
+        for (&read, &written) in indices.iter().zip(&sphere.indices) {
+            let (read, written) = (&vertices[read as usize], &sphere.vertices[written as usize]);
+            assert_eq!(read.pos, written.pos);
+            assert_eq!(read.tex, written.tex);
+            let normal = Vector3::from(read.normal) - Vector3::from(written.normal);
+            assert!(normal.magnitude() < 1e-6);
+        }
+        // And corners that were one vertex still are
+        let mut pairs = HashMap::new();
+        for (&read, &written) in indices.iter().zip(&sphere.indices) {
+            assert_eq!(*pairs.entry(written).or_insert(read), read);
+        }
+    }
+}
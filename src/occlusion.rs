@@ -0,0 +1,94 @@
+use glium::{backend::Facade, draw_parameters::SamplesPassedQuery};
+use std::cell::Cell;
+use std::collections::VecDeque;
+
+// Results are read back a few frames late, queries still pending beyond
+// that are dropped rather than waited on
+const MAX_PENDING: usize = 4;
+
+// A pass is skipped after this many frames in a row with nothing visible,
+// and while skipped it's drawn every PROBE_INTERVAL frames to notice when
+// it comes back into view
+const SKIP_AFTER: u32 = 3;
+const PROBE_INTERVAL: u32 = 8;
+
+/// Visible sample count of a pass from a samples-passed query, and whether
+/// the pass can be skipped because nothing of it has been visible lately.
+pub struct PassOcclusion {
+    pending: VecDeque<SamplesPassedQuery>,
+    query: Option<SamplesPassedQuery>,
+    used: Cell<bool>,
+    pub samples: u32,
+    hidden_frames: u32,
+    frame: u32,
+    skipped: bool,
+}
+
+impl PassOcclusion {
+    pub fn new() -> PassOcclusion {
+        PassOcclusion {
+            pending: VecDeque::new(),
+            query: None,
+            used: Cell::new(false),
+            samples: 0,
+            hidden_frames: 0,
+            frame: 0,
+            skipped: false,
+        }
+    }
+
+    /// Collects the results that have arrived and sets up this frame's
+    /// query. Without `auto_skip` the pass is always drawn.
+    pub fn begin_frame<F: Facade>(&mut self, facade: &F, auto_skip: bool) {
+        if let Some(query) = self.query.take() {
+            if self.used.get() {
+                self.pending.push_back(query);
+            }
+        }
+
+        while self.pending.front().is_some_and(|q| q.is_ready()) {
+            if let Some(query) = self.pending.pop_front() {
+                self.samples = query.get();
+
+                if self.samples == 0 {
+                    self.hidden_frames += 1;
+                } else {
+                    self.hidden_frames = 0;
+                }
+                self.skipped = self.hidden_frames >= SKIP_AFTER;
+            }
+        }
+
+        while self.pending.len() > MAX_PENDING {
+            self.pending.pop_front();
+        }
+
+        if !auto_skip {
+            self.skipped = false;
+        }
+
+        self.frame = self.frame.wrapping_add(1);
+        self.used.set(false);
+        self.query = SamplesPassedQuery::new(facade).ok();
+    }
+
+    pub fn is_skipped(&self) -> bool {
+        self.skipped
+    }
+
+    /// Whether to draw the pass this frame, either because it's visible or
+    /// to probe whether it has come back into view.
+    pub fn should_draw(&self) -> bool {
+        !self.skipped || self.frame.is_multiple_of(PROBE_INTERVAL)
+    }
+
+    /// The query to attach to every draw of the pass this frame. The draws
+    /// have to be consecutive for the samples to add up in one query.
+    pub fn query(&self) -> Option<&SamplesPassedQuery> {
+        let query = self.query.as_ref();
+        if query.is_some() {
+            self.used.set(true);
+        }
+        query
+    }
+}
@@ -0,0 +1,288 @@
+use cgmath::{vec3, InnerSpace, Matrix3, Vector3};
+use std::f64::consts::PI;
+
+/// A satellite worth riding along with, its orbit given by altitudes so it
+/// fits any body.
+pub struct Satellite {
+    pub name: &'static str,
+    pub perigee_km: f64,
+    pub apogee_km: f64,
+    // In degrees
+    pub inclination: f64,
+    pub ascending_node: f64,
+    pub argument_of_periapsis: f64,
+}
+
+pub const SATELLITES: [Satellite; 4] = [
+    Satellite {
+        name: "ISS",
+        perigee_km: 413.0,
+        apogee_km: 422.0,
+        inclination: 51.64,
+        ascending_node: 40.0,
+        argument_of_periapsis: 80.0,
+    },
+    Satellite {
+        name: "Sun-synchronous",
+        perigee_km: 700.0,
+        apogee_km: 705.0,
+        inclination: 98.2,
+        ascending_node: 90.0,
+        argument_of_periapsis: 0.0,
+    },
+    // Lingers high over the north for most of its period
+    Satellite {
+        name: "Molniya",
+        perigee_km: 600.0,
+        apogee_km: 39_700.0,
+        inclination: 63.4,
+        ascending_node: 0.0,
+        argument_of_periapsis: 270.0,
+    },
+    Satellite {
+        name: "GPS",
+        perigee_km: 20_180.0,
+        apogee_km: 20_200.0,
+        inclination: 55.0,
+        ascending_node: 120.0,
+        argument_of_periapsis: 0.0,
+    },
+];
+
+/// Keplerian elements of an orbit around a body with gravitational
+/// parameter `mu` in km^3/s^2. Angles are in radians and the mean anomaly is
+/// the one at time zero.
+///
+/// The reference plane is the equator, with the pole along +y and the
+/// vernal equinox along +z, the axes of planet-local space before the
+/// planet is turned.
+#[derive(Copy, Clone)]
+pub struct Orbit {
+    pub mu: f64,
+    pub semi_major_axis_km: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub ascending_node: f64,
+    pub argument_of_periapsis: f64,
+    pub mean_anomaly: f64,
+}
+
+impl Orbit {
+    /// The orbit of `satellite` around a body of `radius_km`, starting at
+    /// perigee.
+    pub fn for_satellite(satellite: &Satellite, radius_km: f64, mu: f64) -> Orbit {
+        let perigee = radius_km + satellite.perigee_km;
+        let apogee = radius_km + satellite.apogee_km.max(satellite.perigee_km);
+
+        Orbit {
+            mu,
+            semi_major_axis_km: 0.5 * (perigee + apogee),
+            eccentricity: (apogee - perigee) / (apogee + perigee),
+            inclination: satellite.inclination.to_radians(),
+            ascending_node: satellite.ascending_node.to_radians(),
+            argument_of_periapsis: satellite.argument_of_periapsis.to_radians(),
+            mean_anomaly: 0.0,
+        }
+    }
+
+    /// Radians of mean anomaly per second.
+    pub fn mean_motion(&self) -> f64 {
+        (self.mu / self.semi_major_axis_km.powi(3)).sqrt()
+    }
+
+    pub fn period(&self) -> f64 {
+        2.0 * PI / self.mean_motion()
+    }
+
+    /// Solves Kepler's equation, M = E - e sin E, for the eccentric anomaly.
+    fn eccentric_anomaly(&self, mean_anomaly: f64) -> f64 {
+        let e = self.eccentricity;
+        let mean_anomaly = mean_anomaly.rem_euclid(2.0 * PI);
+
+        // Starting from pi converges for every eccentricity below 1
+        let mut anomaly = if e > 0.8 { PI } else { mean_anomaly };
+        for _ in 0..32 {
+            let step = (anomaly - e * anomaly.sin() - mean_anomaly) / (1.0 - e * anomaly.cos());
+            anomaly -= step;
+            if step.abs() < 1e-12 {
+                break;
+            }
+        }
+        anomaly
+    }
+
+    /// Position in km and velocity in km/s `time` seconds after epoch,
+    /// relative to the body's center.
+    pub fn state(&self, time: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let a = self.semi_major_axis_km;
+        let e = self.eccentricity;
+        let anomaly = self.eccentric_anomaly(self.mean_anomaly + self.mean_motion() * time);
+        let (sin, cos) = anomaly.sin_cos();
+        let b = a * (1.0 - e * e).sqrt();
+
+        // In the orbital plane, periapsis along the first axis
+        let position = (a * (cos - e), b * sin);
+        // Rate of change of the eccentric anomaly
+        let rate = self.mean_motion() / (1.0 - e * cos);
+        let velocity = (-a * sin * rate, b * cos * rate);
+
+        let orientation = self.orientation();
+        (
+            orientation * vec3(position.0, position.1, 0.0),
+            orientation * vec3(velocity.0, velocity.1, 0.0),
+        )
+    }
+
+    /// Turns the orbital plane, periapsis along x and the orbit normal
+    /// along z, into the reference frame.
+    fn orientation(&self) -> Matrix3<f64> {
+        let rotate_z = |angle: f64| {
+            let (sin, cos) = angle.sin_cos();
+            Matrix3::new(cos, sin, 0.0, -sin, cos, 0.0, 0.0, 0.0, 1.0)
+        };
+        let (sin, cos) = self.inclination.sin_cos();
+        let rotate_x = Matrix3::new(1.0, 0.0, 0.0, 0.0, cos, sin, 0.0, -sin, cos);
+
+        // The usual equinox x, pole z axes, swapped cyclically onto the
+        // planet's equinox z, pole y
+        let axes = Matrix3::new(0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0);
+
+        axes * rotate_z(self.ascending_node) * rotate_x * rotate_z(self.argument_of_periapsis)
+    }
+}
+
+/// Orthonormal frame of a satellite at `position` moving at `velocity`.
+/// The columns are along-track, cross-track and radial: radial points away
+/// from the body's center, along-track is the velocity flattened onto the
+/// local horizontal, and cross-track is the orbit normal, completing a
+/// right-handed frame.
+///
+/// On an eccentric orbit the velocity climbs or dives through the horizon,
+/// the frame stays level with the ground below.
+pub fn along_track_frame(position: Vector3<f64>, velocity: Vector3<f64>) -> Matrix3<f64> {
+    let radial = position.normalize();
+    let mut along = velocity - radial * velocity.dot(radial);
+    if along.magnitude2() < 1e-12 * velocity.magnitude2().max(1e-12) {
+        // Falling straight down or up, any horizontal direction will do
+        let helper = if radial.y.abs() < 0.9 {
+            vec3(0.0, 1.0, 0.0)
+        } else {
+            vec3(1.0, 0.0, 0.0)
+        };
+        along = helper - radial * helper.dot(radial);
+    }
+    let along = along.normalize();
+    let cross = radial.cross(along);
+
+    Matrix3::from_cols(along, cross, radial)
+}
+
+/// Latitude and longitude in degrees of the point below `position`, in the
+/// planet-local axes of `Orbit`.
+pub fn sub_point(position: Vector3<f64>) -> (f64, f64) {
+    let up = position.normalize();
+    (
+        up.y.clamp(-1.0, 1.0).asin().to_degrees(),
+        up.x.atan2(up.z).to_degrees(),
+    )
+}
+
+/// Declination in degrees of the sun at `longitude` degrees along the
+/// ecliptic from the northern spring equinox, for an `axial_tilt` in
+/// degrees.
+pub fn sun_declination(axial_tilt: f64, longitude: f64) -> f64 {
+    (axial_tilt.to_radians().sin() * longitude.to_radians().sin())
+        .asin()
+        .to_degrees()
+}
+
+/// Right ascension in degrees of the point `longitude` degrees along the
+/// ecliptic, measured the same way as the longitude.
+pub fn ecliptic_right_ascension(axial_tilt: f64, longitude: f64) -> f64 {
+    let longitude = longitude.to_radians();
+    (longitude.sin() * axial_tilt.to_radians().cos())
+        .atan2(longitude.cos())
+        .to_degrees()
+}
+
+/// Altitude in degrees of the sun at local noon, seen from `latitude` with
+/// the sun at `declination`. Negative through the polar night.
+pub fn noon_altitude(latitude: f64, declination: f64) -> f64 {
+    90.0 - (latitude - declination).abs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::PRESETS;
+    use cgmath::{Matrix, SquareMatrix};
+
+    /// Checks the frame at `samples` times over a period of `orbit`.
+    fn check_frames(orbit: &Orbit, samples: usize) {
+        for i in 0..samples {
+            let time = orbit.period() * i as f64 / samples as f64;
+            let (position, velocity) = orbit.state(time);
+            let frame = along_track_frame(position, velocity);
+            let (along, cross, radial) = (frame.x, frame.y, frame.z);
+
+            // Orthonormal and right-handed
+            let identity = frame.transpose() * frame;
+            for (column, expected) in [
+                (identity.x, vec3(1.0, 0.0, 0.0)),
+                (identity.y, vec3(0.0, 1.0, 0.0)),
+                (identity.z, vec3(0.0, 0.0, 1.0)),
+            ] {
+                assert!((column - expected).magnitude() < 1e-12);
+            }
+            assert!((frame.determinant() - 1.0).abs() < 1e-12);
+
+            assert!((radial - position.normalize()).magnitude() < 1e-12);
+            let normal = position.cross(velocity).normalize();
+            assert!((cross - normal).magnitude() < 1e-12);
+
+            // Forward is the velocity, tipped level with the ground
+            let horizontal = (velocity - radial * velocity.dot(radial)).normalize();
+            assert!((along - horizontal).magnitude() < 1e-12);
+            let climb = velocity.normalize().dot(radial).asin();
+            assert!((along.dot(velocity.normalize()) - climb.cos()).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn along_track_frame_on_a_circular_orbit() {
+        let earth = &PRESETS[1];
+        let orbit = Orbit {
+            mu: earth.mu(),
+            semi_major_axis_km: earth.planet_radius_km + 500.0,
+            eccentricity: 0.0,
+            inclination: 51.6f64.to_radians(),
+            ascending_node: 40.0f64.to_radians(),
+            argument_of_periapsis: 0.0,
+            mean_anomaly: 0.0,
+        };
+        check_frames(&orbit, 37);
+
+        // Level flight the whole way round, forward is the velocity
+        for i in 0..37 {
+            let (position, velocity) = orbit.state(orbit.period() * i as f64 / 37.0);
+            let along = along_track_frame(position, velocity).x;
+            assert!((along - velocity.normalize()).magnitude() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn along_track_frame_on_an_eccentric_orbit() {
+        let earth = &PRESETS[1];
+        let orbit = Orbit::for_satellite(&SATELLITES[2], earth.planet_radius_km, earth.mu());
+        assert!(orbit.eccentricity > 0.7);
+        check_frames(&orbit, 101);
+
+        // Climbing away from perigee, forward stays ahead of the velocity
+        // but no longer along it
+        let (position, velocity) = orbit.state(0.1 * orbit.period());
+        let along = along_track_frame(position, velocity).x;
+        assert!(velocity.dot(position) > 0.0);
+        assert!(along.dot(velocity.normalize()) < 0.99);
+        assert!(along.dot(velocity) > 0.0);
+    }
+}
@@ -0,0 +1,173 @@
+use crate::actions::Action;
+use crate::bindings::InputBindings;
+use imgui::{im_str, ImGuiSelectableFlags, ImString, Ui};
+
+const MAX_RECENT: usize = 8;
+
+// Key indices as mapped with set_imgui_key in main
+const KEY_UP: usize = 3;
+const KEY_DOWN: usize = 4;
+const KEY_ENTER: usize = 11;
+const KEY_ESCAPE: usize = 12;
+
+/// Ctrl+P popup listing every action, filtered by a fuzzy search with the
+/// recently used ones first.
+pub struct CommandPalette {
+    query: ImString,
+    selected: usize,
+    recent: Vec<Action>,
+    opening: bool,
+    focus: bool,
+    is_open: bool,
+}
+
+impl CommandPalette {
+    pub fn new() -> CommandPalette {
+        CommandPalette {
+            query: ImString::with_capacity(64),
+            selected: 0,
+            recent: Vec::new(),
+            opening: false,
+            focus: false,
+            is_open: false,
+        }
+    }
+
+    pub fn open(&mut self) {
+        self.opening = true;
+    }
+
+    /// While open the palette takes all keyboard input.
+    pub fn is_open(&self) -> bool {
+        self.is_open || self.opening
+    }
+
+    pub fn record(&mut self, action: Action) {
+        if action == Action::OpenCommandPalette {
+            return;
+        }
+        self.recent.retain(|&recent| recent != action);
+        self.recent.insert(0, action);
+        self.recent.truncate(MAX_RECENT);
+    }
+
+    /// Draws the palette and returns the action picked this frame, if any.
+    pub fn draw<'a>(
+        &mut self,
+        ui: &Ui<'a>,
+        actions: &[Action],
+        bindings: &InputBindings,
+    ) -> Option<Action> {
+        if self.opening {
+            self.opening = false;
+            self.focus = true;
+            self.selected = 0;
+            self.query.clear();
+            ui.open_popup(im_str!("Command palette"));
+        }
+
+        let mut picked = None;
+        let mut is_open = false;
+
+        ui.popup_modal(im_str!("Command palette"))
+            .always_auto_resize(true)
+            .build(|| {
+                is_open = true;
+
+                if self.focus {
+                    self.focus = false;
+                    unsafe { imgui::sys::igSetKeyboardFocusHere(0) };
+                }
+                if ui.input_text(im_str!("##query"), &mut self.query).build() {
+                    self.selected = 0;
+                }
+
+                let matches = self.filter(actions);
+                let imgui = ui.imgui();
+                if imgui.is_key_pressed(KEY_DOWN) && self.selected + 1 < matches.len() {
+                    self.selected += 1;
+                }
+                if imgui.is_key_pressed(KEY_UP) && self.selected > 0 {
+                    self.selected -= 1;
+                }
+
+                for (i, &action) in matches.iter().enumerate() {
+                    let label = bindings.label(&action.name(), action);
+                    if ui.selectable(
+                        &ImString::new(label),
+                        i == self.selected,
+                        ImGuiSelectableFlags::empty(),
+                        (0.0, 0.0),
+                    ) {
+                        picked = Some(action);
+                    }
+                }
+
+                if imgui.is_key_pressed(KEY_ENTER) {
+                    picked = picked.or_else(|| matches.get(self.selected).cloned());
+                }
+
+                if picked.is_some() || imgui.is_key_pressed(KEY_ESCAPE) {
+                    ui.close_current_popup();
+                    is_open = false;
+                }
+            });
+
+        self.is_open = is_open;
+
+        if let Some(action) = picked {
+            self.record(action);
+        }
+        picked
+    }
+
+    fn filter(&self, actions: &[Action]) -> Vec<Action> {
+        let query = self.query.to_str().to_lowercase();
+
+        let mut matches: Vec<(usize, i32, usize, Action)> = actions
+            .iter()
+            .enumerate()
+            .filter(|&(_, &action)| action != Action::OpenCommandPalette)
+            .filter_map(|(i, &action)| {
+                fuzzy_score(&query, &action.name().to_lowercase()).map(|score| {
+                    let recent = self
+                        .recent
+                        .iter()
+                        .position(|&recent| recent == action)
+                        .unwrap_or(MAX_RECENT);
+                    (recent, score, i, action)
+                })
+            })
+            .collect();
+
+        // Recently used first, then the best matches, then registry order
+        matches.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)));
+
+        matches
+            .into_iter()
+            .map(|(_, _, _, action)| action)
+            .collect()
+    }
+}
+
+/// Matches `query` as a subsequence of `text`, scoring consecutive and
+/// early matches higher. `None` if some character of the query is missing.
+fn fuzzy_score(query: &str, text: &str) -> Option<i32> {
+    let mut score = 0;
+    let mut last = None;
+    let mut chars = text.char_indices();
+
+    for q in query.chars().filter(|c| !c.is_whitespace()) {
+        let (i, _) = chars.by_ref().find(|&(_, c)| c == q)?;
+        score += match last {
+            Some(last) if i == last + 1 => 3,
+            _ => 1,
+        };
+        if i == 0 {
+            score += 2;
+        }
+        last = Some(i);
+    }
+
+    Some(score)
+}
@@ -0,0 +1,271 @@
+use crate::stream::{Stream, StreamPool};
+use cgmath::Vector3;
+use glium::{backend::Facade, implement_vertex, vertex::VertexBufferSlice, VertexBuffer};
+use std::error;
+use std::mem;
+
+// Vertices in the pool the lines rewritten every frame are streamed into
+pub const STREAM_CAPACITY: usize = 1 << 17;
+
+// Corners sharper than this, as the ratio of the miter length to half the
+// line width, get a round join instead
+pub const MITER_LIMIT: f32 = 4.0;
+
+// Which end of the segment and which side of it each of the six vertices of
+// its quad is on
+const CORNERS: [[f32; 2]; 6] = [
+    [0.0, -1.0],
+    [1.0, -1.0],
+    [1.0, 1.0],
+    [0.0, -1.0],
+    [1.0, 1.0],
+    [0.0, 1.0],
+];
+
+/// One corner of a segment's quad. Every corner carries the whole segment
+/// and its neighbours, the vertex shader needs them all to place the joins.
+#[derive(Copy, Clone, Default)]
+pub struct PolylineVertex {
+    line_prev: [f32; 3],
+    line_start: [f32; 3],
+    line_end: [f32; 3],
+    line_next: [f32; 3],
+    line_color: [f32; 4],
+    line_corner: [f32; 2],
+}
+implement_vertex!(
+    PolylineVertex,
+    line_prev,
+    line_start,
+    line_end,
+    line_next,
+    line_color,
+    line_corner
+);
+
+/// Polylines drawn as antialiased lines a fixed number of pixels wide. Each
+/// segment becomes a quad that `shaders/polyline.glsl` expands in screen
+/// space, with mitered joins, and round ones past `MITER_LIMIT` and at the
+/// ends. The vertex shader of each feature does its own transform and
+/// color on top.
+pub struct PolylineBatch {
+    vertices: Vec<PolylineVertex>,
+    stream: Stream<PolylineVertex>,
+}
+
+impl PolylineBatch {
+    pub fn new() -> PolylineBatch {
+        PolylineBatch {
+            vertices: Vec::new(),
+            stream: Stream::Empty,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    /// Adds a line through `points`, each with its own color. A single point
+    /// is drawn as a round dot as wide as the line.
+    pub fn push(&mut self, points: &[(Vector3<f32>, [f32; 4])]) {
+        if points.len() == 1 {
+            self.push_segment(points[0], points[0], points[0].0, points[0].0);
+            return;
+        }
+
+        for i in 0..points.len().saturating_sub(1) {
+            let (start, end) = (points[i], points[i + 1]);
+            // An end point is its own neighbour, which caps the line there
+            let prev = if i > 0 { points[i - 1].0 } else { start.0 };
+            let next = points.get(i + 2).map_or(end.0, |point| point.0);
+            self.push_segment(start, end, prev, next);
+        }
+    }
+
+    fn push_segment(
+        &mut self,
+        start: (Vector3<f32>, [f32; 4]),
+        end: (Vector3<f32>, [f32; 4]),
+        prev: Vector3<f32>,
+        next: Vector3<f32>,
+    ) {
+        for &corner in &CORNERS {
+            self.vertices.push(PolylineVertex {
+                line_prev: prev.into(),
+                line_start: start.0.into(),
+                line_end: end.0.into(),
+                line_next: next.into(),
+                line_color: if corner[0] > 0.5 { end.1 } else { start.1 },
+                line_corner: corner,
+            });
+        }
+    }
+
+    /// Copies the lines pushed since the last `clear` to a buffer of their
+    /// own, growing it when they no longer fit. This is for lines that
+    /// change now and then, `stream` is for ones rewritten every frame.
+    pub fn upload<F: Facade>(&mut self, facade: &F) -> Result<(), Box<dyn error::Error>> {
+        let len = self.vertices.len();
+        let buffer = match mem::replace(&mut self.stream, Stream::Empty) {
+            Stream::Own { buffer, .. } if buffer.len() >= len => buffer,
+            _ if len == 0 => return Ok(()),
+            _ => Box::new(VertexBuffer::empty_dynamic(
+                facade,
+                len.next_power_of_two(),
+            )?),
+        };
+        if len > 0 {
+            buffer
+                .slice(0..len)
+                .ok_or("polyline vertices out of range")?
+                .write(&self.vertices);
+        }
+        self.stream = Stream::Own { buffer, len };
+
+        Ok(())
+    }
+
+    /// Copies the lines pushed since the last `clear` into `pool`, for this
+    /// frame only.
+    pub fn stream<F: Facade>(
+        &mut self,
+        facade: &F,
+        pool: &mut StreamPool<PolylineVertex>,
+    ) -> Result<(), Box<dyn error::Error>> {
+        self.stream = pool.write(facade, &self.vertices)?;
+        Ok(())
+    }
+
+    /// The uploaded or streamed quads, to draw as a triangle list, or `None`
+    /// if there are none.
+    pub fn slice<'a>(
+        &'a self,
+        pool: &'a StreamPool<PolylineVertex>,
+    ) -> Option<VertexBufferSlice<'a, PolylineVertex>> {
+        pool.slice(&self.stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{vec2, InnerSpace, Vector2};
+
+    // The expansion runs in the vertex shader, so the tests run on a copy
+    // of its math and check that the shader still reads the same
+    const SHADER: &str = include_str!("../shaders/polyline.glsl");
+
+    #[test]
+    fn shader_math_is_the_one_tested() {
+        for line in [
+            "float halfWidth = 0.5 * lineWidth + 1.0;",
+            "vec2 tangent = d0 + d1;",
+            "vec2 miter = vec2(-tangent.y, tangent.x);",
+            "float cosine = dot(miter, vec2(-d0.y, d0.x));",
+            "if (cosine * miterLimit < 1.0) {",
+            "offset = miter * halfWidth / cosine;",
+            "vec2 normal = vec2(-dir.y, dir.x);",
+            "pixel = b + (endMitered ? side * endOffset : (side * normal + dir) * halfWidth);",
+            "pixel = a + (startMitered ? side * startOffset : (side * normal - dir) * halfWidth);",
+        ] {
+            assert!(
+                SHADER.contains(line),
+                "polyline.glsl no longer has {}",
+                line
+            );
+        }
+    }
+
+    fn half_width(line_width: f32) -> f32 {
+        0.5 * line_width + 1.0
+    }
+
+    fn normal(dir: Vector2<f32>) -> Vector2<f32> {
+        vec2(-dir.y, dir.x)
+    }
+
+    /// lineMiter: the left side of the corner from `d0` to `d1`, or none
+    /// when it is too sharp and gets a round join.
+    fn miter(d0: Vector2<f32>, d1: Vector2<f32>, half_width: f32) -> Option<Vector2<f32>> {
+        let tangent = d0 + d1;
+        if tangent.magnitude2() < 1e-6 {
+            return None;
+        }
+        let miter = normal(tangent.normalize());
+        let cosine = miter.dot(normal(d0));
+        if cosine * MITER_LIMIT < 1.0 {
+            return None;
+        }
+        Some(miter * half_width / cosine)
+    }
+
+    /// The corners of a segment from `a` to `b` without neighbours, which
+    /// is capped round at both ends.
+    fn capped_corners(a: Vector2<f32>, b: Vector2<f32>, half_width: f32) -> Vec<Vector2<f32>> {
+        let dir = (b - a).normalize();
+        CORNERS
+            .iter()
+            .map(|&[end, side]| {
+                if end > 0.5 {
+                    b + (normal(dir) * side + dir) * half_width
+                } else {
+                    a + (normal(dir) * side - dir) * half_width
+                }
+            })
+            .collect()
+    }
+
+    fn turn(degrees: f32) -> Vector2<f32> {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        vec2(cos, sin)
+    }
+
+    #[test]
+    fn straight_segments_are_the_line_width_across() {
+        let (a, b) = (vec2(10.0, 20.0), vec2(70.0, -60.0));
+        let dir = (b - a).normalize();
+        let half_width = half_width(3.0);
+
+        for corner in capped_corners(a, b, half_width) {
+            // Half the width, plus the antialiasing pixel, either side
+            let across = (corner - a).dot(normal(dir));
+            assert!((across.abs() - 2.5).abs() < 1e-4);
+        }
+
+        // Carrying straight on, the miter is just the side offset
+        let offset = miter(dir, dir, half_width).unwrap();
+        assert!((offset - normal(dir) * half_width).magnitude() < 1e-5);
+    }
+
+    #[test]
+    fn right_angle_miters_reach_the_corner_of_the_outline() {
+        let half_width = half_width(4.0);
+        for &start in &[0.0, 30.0, 135.0] {
+            let (d0, d1) = (turn(start), turn(start + 90.0));
+            let offset = miter(d0, d1, half_width).unwrap();
+            assert!((offset.magnitude() - half_width * 2f32.sqrt()).abs() < 1e-4);
+
+            // Where the left edges of both segments meet
+            assert!((offset.dot(normal(d0)) - half_width).abs() < 1e-4);
+            assert!((offset.dot(normal(d1)) - half_width).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn sharp_corners_fall_back_to_round_joins() {
+        let half_width = half_width(2.0);
+        // The miter is half_width / cos(half the angle between the
+        // segments' normals), so the limit is at a turn of 2 acos(1 / 4)
+        let limit = 2.0 * (1.0 / MITER_LIMIT).acos().to_degrees();
+
+        let just_inside = miter(turn(0.0), turn(limit - 1.0), half_width).unwrap();
+        assert!(just_inside.magnitude() <= MITER_LIMIT * half_width);
+        assert!(just_inside.magnitude() > 0.9 * MITER_LIMIT * half_width);
+
+        assert!(miter(turn(0.0), turn(limit + 1.0), half_width).is_none());
+        assert!(miter(turn(0.0), turn(179.0), half_width).is_none());
+        // Doubling straight back
+        assert!(miter(turn(0.0), turn(180.0), half_width).is_none());
+        assert!(miter(turn(0.0), turn(-(limit + 1.0)), half_width).is_none());
+    }
+}
@@ -0,0 +1,444 @@
+use crate::capture;
+use crate::colors::strip_comment;
+use crate::scaling::{self, Decision, Scaler, ScalingSettings};
+use crate::stutter::Span;
+use crate::State;
+use std::error;
+use std::fs;
+use std::path::Path;
+
+// The levels shipped with the app, and local changes to them that survive
+// an update of the shipped file
+const QUALITY_FILE: &str = "quality.toml";
+const OVERRIDE_FILE: &str = "quality.local.toml";
+
+// Frames left out of the auto-pick while shaders compile and buffers fill,
+// then the frames it is timed over
+const WARMUP_FRAMES: usize = 20;
+const SAMPLE_FRAMES: usize = 60;
+
+// The frame time the auto-pick aims for, 60 fps
+const TARGET_MS: f32 = 16.7;
+
+// Renderers drawing on the CPU, which only manage the lowest level
+const SOFTWARE_RENDERERS: [&str; 4] = ["llvmpipe", "softpipe", "swiftshader", "software"];
+// Renderers sharing memory with the CPU, kept below the top two levels
+const INTEGRATED_RENDERERS: [&str; 6] = ["intel", "mali", "adreno", "powervr", "apple", "vivante"];
+
+/// A named bundle of settings, written the way debug captures write them.
+pub struct QualityLevel {
+    pub name: String,
+    pub settings: Vec<(String, String)>,
+}
+
+impl QualityLevel {
+    /// Whether every setting of the bundle still has its bundled value.
+    /// Numbers are compared as numbers, `20000` and `20000.0` are the same.
+    fn matches(&self, current: &[(&'static str, String)]) -> bool {
+        self.settings.iter().all(|(key, value)| {
+            current
+                .iter()
+                .find(|(current_key, _)| *current_key == key.as_str())
+                .is_none_or(
+                    |(_, current)| match (current.parse::<f64>(), value.parse::<f64>()) {
+                        (Ok(a), Ok(b)) => (a - b).abs() <= 1e-6 * b.abs().max(1.0),
+                        _ => current == value,
+                    },
+                )
+        })
+    }
+}
+
+// What the lines of quality.toml are in
+enum Section {
+    Top,
+    Level(usize),
+    // The settings of the automatic scaling
+    Auto,
+}
+
+/// Times the first frames at a middle level and moves up or down from it.
+struct AutoPick {
+    start: usize,
+    // The highest level the renderer is trusted with
+    cap: usize,
+    frames: usize,
+    milliseconds: Vec<f32>,
+}
+
+/// The quality levels of `quality.toml`, lowest first, and which one is
+/// applied. Changing any of its settings afterwards drops to "Custom".
+pub struct Quality {
+    pub levels: Vec<QualityLevel>,
+    // None while custom
+    pub current: Option<usize>,
+    // The level to start with, None to pick one from the renderer and the
+    // first frames
+    pub configured: Option<String>,
+    // Why the level at startup was chosen
+    pub reason: String,
+    auto: Option<AutoPick>,
+
+    // Steps down from the level while the GPU can't keep up, and back up
+    // once it can. Pinned holds the settings as they are.
+    pub scaling: ScalingSettings,
+    pub scaler: Scaler,
+    pub auto_scale: bool,
+    pub pinned: bool,
+    // The values the steps taken replaced, in the order they were taken,
+    // with where in the ladder each step is
+    undo: Vec<(usize, String, String)>,
+}
+
+impl Quality {
+    /// Reads the levels, printing what's wrong with the files rather than
+    /// failing: without them there's only "Custom".
+    pub fn load() -> Quality {
+        let mut quality = Quality {
+            levels: Vec::new(),
+            current: None,
+            configured: None,
+            reason: String::new(),
+            auto: None,
+            scaling: ScalingSettings::new(),
+            scaler: Scaler::new(),
+            auto_scale: true,
+            pinned: false,
+            undo: Vec::new(),
+        };
+
+        for path in [QUALITY_FILE, OVERRIDE_FILE] {
+            let path = Path::new(path);
+            if path == Path::new(OVERRIDE_FILE) && !path.exists() {
+                continue;
+            }
+            if let Err(e) = quality.merge_file(path) {
+                println!("{}: {}", path.display(), e);
+            }
+        }
+
+        quality
+    }
+
+    fn merge_file(&mut self, path: &Path) -> Result<(), Box<dyn error::Error>> {
+        let text = fs::read_to_string(path)?;
+        let mut section = Section::Top;
+
+        for (number, line) in text.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|rest| rest.strip_suffix(']'))
+            {
+                let name = name.trim();
+                if name == "auto" {
+                    section = Section::Auto;
+                    continue;
+                }
+                // The override file changes the levels it names and adds the
+                // ones it doesn't
+                section = Section::Level(match self.index(name) {
+                    Some(index) => index,
+                    None => {
+                        self.levels.push(QualityLevel {
+                            name: name.to_string(),
+                            settings: Vec::new(),
+                        });
+                        self.levels.len() - 1
+                    }
+                });
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let (key, value) = match (parts.next(), parts.next()) {
+                (Some(key), Some(value)) => (key.trim(), unquote(value.trim())),
+                _ => return Err(error(format!("expected 'key = value' but got '{}'", line)).into()),
+            };
+
+            match section {
+                Section::Top if key == "level" => {
+                    self.configured = if value == "auto" {
+                        None
+                    } else {
+                        Some(value.to_string())
+                    };
+                }
+                Section::Top => {
+                    return Err(error(format!("'{}' is outside of a level", key)).into())
+                }
+                Section::Auto => self
+                    .scaling
+                    .set(key, value)
+                    .map_err(|e| error(e.to_string()))?,
+                Section::Level(index) => {
+                    let settings = &mut self.levels[index].settings;
+                    match settings.iter_mut().find(|(existing, _)| existing == key) {
+                        Some(setting) => setting.1 = value.to_string(),
+                        None => settings.push((key.to_string(), value.to_string())),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn index(&self, name: &str) -> Option<usize> {
+        self.levels
+            .iter()
+            .position(|level| level.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn is_picking(&self) -> bool {
+        self.auto.is_some()
+    }
+
+    /// The last step of the ladder that changed a setting, if any.
+    pub fn last_step(&self) -> Option<&(String, String)> {
+        self.undo
+            .last()
+            .and_then(|&(step, _, _)| self.scaling.ladder.get(step))
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// Applies the settings of `level` one by one through `capture::set`, then
+/// regenerates what the UI would have after the same edits. The shadowmap
+/// and the cloud target follow their resolutions on the next frame. Any
+/// automatic steps are taken back first.
+pub fn apply(p: &mut State, level: usize) -> Result<(), Box<dyn error::Error>> {
+    let settings = match p.quality.levels.get(level) {
+        Some(level) => level.settings.clone(),
+        None => return Err(format!("no quality level {}", level).into()),
+    };
+
+    restore(p);
+    set_all(p, &settings)?;
+
+    p.quality.current = Some(level);
+    Ok(())
+}
+
+/// Takes back every automatic step, leaving the settings as they were
+/// before the first.
+pub fn restore(p: &mut State) {
+    let undo: Vec<_> = p
+        .quality
+        .undo
+        .drain(..)
+        .rev()
+        .map(|(_, key, value)| (key, value))
+        .collect();
+    if let Err(e) = set_all(p, &undo) {
+        println!("Taking back the automatic quality steps: {}", e);
+    }
+    p.quality.scaler.reset();
+}
+
+/// Sets `settings` through `capture::set` and regenerates what the UI
+/// would have after the same edits.
+fn set_all(p: &mut State, settings: &[(String, String)]) -> Result<(), Box<dyn error::Error>> {
+    let before = capture::settings(p, 0, 0, 0.0);
+    for (key, value) in settings {
+        let known =
+            capture::set(p, key, value).map_err(|e| format!("{} = {}: {}", key, value, e))?;
+        if !known {
+            println!("{}: ignoring unknown setting '{}'", QUALITY_FILE, key);
+        }
+    }
+    let after = capture::settings(p, 0, 0, 0.0);
+
+    let changed = |prefix: &str| {
+        before
+            .iter()
+            .zip(&after)
+            .any(|(before, after)| before.0.starts_with(prefix) && before.1 != after.1)
+    };
+    if changed("vegetation.seed") || changed("vegetation.candidates") {
+        p.vegetation.regenerate();
+    }
+    if changed("magnetosphere.") {
+        p.magnetosphere.regenerate();
+    }
+    Ok(())
+}
+
+/// Runs the scaler on the frame, and takes or takes back a step of the
+/// ladder when it says to. Held while pinned, and while a benchmark,
+/// recording or time-lapse needs the frames to look the same. Stepping up
+/// goes back to before the last step that changed something.
+fn scale(p: &mut State, dt: f32, gpu_ms: f32) {
+    let held = !p.quality.auto_scale
+        || p.quality.pinned
+        || p.benchmark.is_running()
+        || p.recording.is_running()
+        || p.time_lapse.is_running();
+    if held {
+        p.quality.scaler.pause();
+        return;
+    }
+
+    let quality = &mut p.quality;
+    match quality.scaler.update(&quality.scaling, dt, gpu_ms) {
+        Decision::Hold => {}
+        Decision::Down => {
+            let current = capture::settings(p, 0, 0, 0.0);
+            let quality = &mut p.quality;
+            let ladder = &quality.scaling.ladder;
+            match scaling::next_step(ladder, quality.scaler.step - 1, &current) {
+                Some((step, previous)) => {
+                    let (key, value) = ladder[step].clone();
+                    quality.scaler.step = step + 1;
+                    quality.undo.push((step, key.clone(), previous));
+                    if let Err(e) = set_all(p, &[(key, value)]) {
+                        println!("Stepping quality down: {}", e);
+                    }
+                }
+                // Every step left is passed over
+                None => quality.scaler.step = ladder.len(),
+            }
+        }
+        Decision::Up => match quality.undo.pop() {
+            Some((step, key, previous)) => {
+                quality.scaler.step = step;
+                if let Err(e) = set_all(p, &[(key, previous)]) {
+                    println!("Stepping quality up: {}", e);
+                }
+            }
+            // Only steps that changed nothing
+            None => quality.scaler.step = 0,
+        },
+    }
+}
+
+/// Applies the level at startup: the one named on the command line, else
+/// the one in the config files, else a guess from `renderer` that the
+/// first frames then correct.
+pub fn start(p: &mut State, renderer: &str, requested: Option<&str>) {
+    if p.quality.levels.is_empty() {
+        return;
+    }
+
+    let named = requested
+        .map(|name| (name.to_string(), "command line"))
+        .or_else(|| p.quality.configured.clone().map(|name| (name, "config")));
+    if let Some((name, source)) = named {
+        match p.quality.index(&name) {
+            Some(level) => {
+                p.quality.reason = format!("set in the {}", source);
+                if let Err(e) = apply(p, level) {
+                    println!("Applying quality {}: {}", name, e);
+                }
+                return;
+            }
+            None => println!("Unknown quality level '{}', picking one", name),
+        }
+    }
+
+    let top = p.quality.levels.len() - 1;
+    let renderer_lower = renderer.to_lowercase();
+    let is_any = |names: &[&str]| names.iter().any(|name| renderer_lower.contains(name));
+
+    let cap = if is_any(&SOFTWARE_RENDERERS) {
+        0
+    } else if is_any(&INTEGRATED_RENDERERS) {
+        top.saturating_sub(2)
+    } else {
+        top
+    };
+    let start = top.saturating_sub(1).min(cap);
+
+    if let Err(e) = apply(p, start) {
+        println!("Applying quality {}: {}", p.quality.levels[start].name, e);
+        return;
+    }
+    if cap == 0 {
+        p.quality.reason = format!("{} renders in software", renderer);
+        return;
+    }
+
+    p.quality.reason = format!("timing the first frames on {}", renderer);
+    p.quality.auto = Some(AutoPick {
+        start,
+        cap,
+        frames: 0,
+        milliseconds: Vec::new(),
+    });
+}
+
+/// Once a frame: drops to "Custom" when a bundled setting was changed,
+/// times the first frames for the auto-pick, and after that scales the
+/// quality. `gpu_spans` are the passes of the frame that took `dt` seconds,
+/// which stands in when the GPU timers aren't supported.
+pub fn update(p: &mut State, dt: f32, gpu_spans: &[Span]) {
+    if let Some(level) = p.quality.current {
+        // Still the level when only the automatic steps moved off it
+        let mut current = capture::settings(p, 0, 0, 0.0);
+        for (_, key, value) in p.quality.undo.iter().rev() {
+            if let Some(setting) = current.iter_mut().find(|(current, _)| current == key) {
+                setting.1 = value.clone();
+            }
+        }
+        if !p.quality.levels[level].matches(&current) {
+            p.quality.current = None;
+        }
+    }
+
+    let gpu_ms: f32 = gpu_spans.iter().map(|span| span.milliseconds).sum();
+    let gpu_ms = if gpu_ms > 0.0 { gpu_ms } else { dt * 1000.0 };
+    if p.quality.auto.is_none() {
+        scale(p, dt, gpu_ms);
+    }
+
+    let pick = match p.quality.auto {
+        Some(ref mut auto) => {
+            // Changed by hand before the pick was made, that wins
+            if p.quality.current != Some(auto.start) {
+                p.quality.auto = None;
+                p.quality.reason = "changed while timing the first frames".to_string();
+                return;
+            }
+
+            auto.frames += 1;
+            if auto.frames <= WARMUP_FRAMES {
+                return;
+            }
+            auto.milliseconds.push(gpu_ms);
+            if auto.milliseconds.len() < SAMPLE_FRAMES {
+                return;
+            }
+
+            let average = auto.milliseconds.iter().sum::<f32>() / auto.milliseconds.len() as f32;
+            let level = if average > 2.0 * TARGET_MS {
+                auto.start.saturating_sub(2)
+            } else if average > TARGET_MS {
+                auto.start.saturating_sub(1)
+            } else if average < 0.4 * TARGET_MS {
+                auto.start + 1
+            } else {
+                auto.start
+            };
+            (level.min(auto.cap), average)
+        }
+        None => return,
+    };
+
+    let (level, average) = pick;
+    p.quality.auto = None;
+    p.quality.reason = format!("{:.1} ms a frame at startup", average);
+    if let Err(e) = apply(p, level) {
+        println!("Applying quality {}: {}", p.quality.levels[level].name, e);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::screenshot::{ScreenshotWriter, Written};
+use std::error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Seconds every recorded frame moves everything on by, whatever the frame
+// took to render and write
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+
+// A frame that waited longer than this on the writer is counted as held up
+const STALL_THRESHOLD: Duration = Duration::from_millis(1);
+
+/// Every frame as a numbered PNG, to make a video of with ffmpeg. While
+/// recording the frames are a fixed `TIMESTEP` apart, so a slow frame
+/// makes the recording take longer rather than skip.
+pub struct Recording {
+    // Keep the UI in the frames
+    pub include_ui: bool,
+    pub dir: Option<PathBuf>,
+    pub error: Option<String>,
+    running: bool,
+    queued: u32,
+    written: u32,
+    // Frames that had to wait for the writer to take them
+    stalled: u32,
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording {
+            include_ui: false,
+            dir: None,
+            error: None,
+            running: false,
+            queued: 0,
+            written: 0,
+            stalled: 0,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn queued(&self) -> u32 {
+        self.queued
+    }
+
+    pub fn written(&self) -> u32 {
+        self.written
+    }
+
+    pub fn stalled(&self) -> u32 {
+        self.stalled
+    }
+
+    /// Starts writing into a new folder under `out`.
+    pub fn start(&mut self, out: &Path) -> Result<(), Box<dyn error::Error>> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let dir = out.join(format!("recording_{}", timestamp));
+        fs::create_dir_all(&dir)?;
+
+        self.dir = Some(dir);
+        self.error = None;
+        self.running = true;
+        self.queued = 0;
+        self.written = 0;
+        self.stalled = 0;
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    /// Hands the next frame to the writer.
+    pub fn queue(&mut self, writer: &mut ScreenshotWriter, pixels: Vec<u8>, size: (u32, u32)) {
+        let path = match self.dir {
+            Some(ref dir) => dir.join(format!("frame_{:06}.png", self.queued + 1)),
+            None => return,
+        };
+        let before = writer.stalled();
+        writer.save(path, pixels, size.0, size.1);
+        if writer.stalled() - before > STALL_THRESHOLD {
+            self.stalled += 1;
+        }
+        self.queued += 1;
+    }
+
+    pub fn owns(&self, path: &Path) -> bool {
+        self.dir.as_ref().is_some_and(|dir| path.starts_with(dir))
+    }
+
+    /// Takes note of a frame the writer is done with. The first failed
+    /// write stops the recording, the disk is likely full or gone.
+    pub fn frame_written(&mut self, written: &Written) {
+        if let Some(ref error) = written.error {
+            if self.running {
+                self.error = Some(format!("{}: {}", written.path.display(), error));
+                self.running = false;
+            }
+            return;
+        }
+        self.written += 1;
+    }
+}
@@ -0,0 +1,418 @@
+use glium::{
+    backend::Facade,
+    texture::{texture2d::Texture2d, MipmapsOption, RawImage2d},
+    Rect,
+};
+use image::RgbaImage;
+use std::error;
+use std::f32::consts::PI;
+
+// Lobes of the Lanczos window
+const LANCZOS_LOBES: f32 = 3.0;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Filter {
+    // The average of the source pixels under each target pixel
+    Box,
+    // Sharper, at the cost of slight ringing next to hard edges
+    Lanczos3,
+}
+
+/// What lies past the edges of the image.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Edges {
+    Clamp,
+    // Tiling textures, both directions
+    Wrap,
+    // Longitude wraps around, and the top and bottom rows are each a single
+    // point, the pole, so they come out as their average
+    Equirectangular,
+}
+
+/// Resizes `image` to `width` by `height`. With `srgb` the color channels
+/// are filtered as linear light, which keeps averaged bright and dark
+/// details from coming out too dark. Alpha is always filtered as is.
+pub fn resize(
+    image: &RgbaImage,
+    width: u32,
+    height: u32,
+    filter: Filter,
+    edges: Edges,
+    srgb: bool,
+) -> RgbaImage {
+    let (source_width, source_height) = image.dimensions();
+    let (width, height) = (width.max(1), height.max(1));
+    if (source_width, source_height) == (width, height) || source_width == 0 || source_height == 0 {
+        return image.clone();
+    }
+
+    let decode = decode_table(srgb);
+    let pixels: Vec<[f32; 4]> = image
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b, a] = pixel.0;
+            [
+                decode[r as usize],
+                decode[g as usize],
+                decode[b as usize],
+                f32::from(a) / 255.0,
+            ]
+        })
+        .collect();
+
+    let wrap_x = edges != Edges::Clamp;
+    let wrap_y = edges == Edges::Wrap;
+
+    // Rows first, then columns
+    let columns = weights(source_width as usize, width as usize, filter, wrap_x);
+    let mut rows = vec![[0.0; 4]; width as usize * source_height as usize];
+    for y in 0..source_height as usize {
+        let source = &pixels[y * source_width as usize..(y + 1) * source_width as usize];
+        for (x, taps) in columns.iter().enumerate() {
+            rows[y * width as usize + x] = gather(taps, |i| source[i]);
+        }
+    }
+
+    let row_taps = weights(source_height as usize, height as usize, filter, wrap_y);
+    let mut resized = vec![[0.0; 4]; width as usize * height as usize];
+    for (y, taps) in row_taps.iter().enumerate() {
+        for x in 0..width as usize {
+            resized[y * width as usize + x] = gather(taps, |i| rows[i * width as usize + x]);
+        }
+    }
+
+    if edges == Edges::Equirectangular {
+        for y in [0, height as usize - 1] {
+            let row = &mut resized[y * width as usize..(y + 1) * width as usize];
+            let mut average = [0.0; 4];
+            for pixel in row.iter() {
+                for c in 0..4 {
+                    average[c] += pixel[c] / width as f32;
+                }
+            }
+            row.iter_mut().for_each(|pixel| *pixel = average);
+        }
+    }
+
+    let mut data = Vec::with_capacity(resized.len() * 4);
+    for pixel in resized {
+        data.extend_from_slice(&[
+            encode(pixel[0], srgb),
+            encode(pixel[1], srgb),
+            encode(pixel[2], srgb),
+            encode(pixel[3], false),
+        ]);
+    }
+    RgbaImage::from_raw(width, height, data).unwrap_or_else(|| RgbaImage::new(width, height))
+}
+
+/// `image` and every mip level below it, each half the size of the one
+/// before down to 1x1, box filtered.
+pub fn mip_chain(image: &RgbaImage, edges: Edges, srgb: bool) -> Vec<RgbaImage> {
+    let mut chain = vec![image.clone()];
+    loop {
+        let (width, height) = chain[chain.len() - 1].dimensions();
+        if width <= 1 && height <= 1 {
+            return chain;
+        }
+        let level = resize(
+            &chain[chain.len() - 1],
+            (width / 2).max(1),
+            (height / 2).max(1),
+            Filter::Box,
+            edges,
+            srgb,
+        );
+        chain.push(level);
+    }
+}
+
+/// A texture of `image`, top row first, with the mip levels of `mip_chain`
+/// rather than the driver's, which clamp at the edges and filter sRGB
+/// colors as linear.
+pub fn texture_with_mip_chain<F: Facade>(
+    facade: &F,
+    image: &RgbaImage,
+    edges: Edges,
+    srgb: bool,
+) -> Result<Texture2d, Box<dyn error::Error>> {
+    let chain = mip_chain(image, edges, srgb);
+    let texture = Texture2d::empty_with_mipmaps(
+        facade,
+        MipmapsOption::EmptyMipmapsMax(chain.len() as u32 - 1),
+        image.width(),
+        image.height(),
+    )?;
+
+    for (level, image) in chain.iter().enumerate() {
+        let (width, height) = image.dimensions();
+        let mipmap = texture
+            .mipmap(level as u32)
+            .ok_or("texture has fewer mip levels than the chain")?;
+        mipmap.write(
+            Rect {
+                left: 0,
+                bottom: 0,
+                width,
+                height,
+            },
+            RawImage2d::from_raw_rgba_reversed(image.as_raw(), (width, height)),
+        );
+    }
+
+    Ok(texture)
+}
+
+/// Weighted sum of the source samples `taps` point at.
+fn gather(taps: &[(usize, f32)], sample: impl Fn(usize) -> [f32; 4]) -> [f32; 4] {
+    let mut sum = [0.0; 4];
+    for &(i, weight) in taps {
+        let value = sample(i);
+        for c in 0..4 {
+            sum[c] += value[c] * weight;
+        }
+    }
+    sum
+}
+
+fn lanczos(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else if x.abs() >= LANCZOS_LOBES {
+        0.0
+    } else {
+        let x = x * PI;
+        LANCZOS_LOBES * x.sin() * (x / LANCZOS_LOBES).sin() / (x * x)
+    }
+}
+
+/// Source pixels and their weights for each of `target` pixels along an
+/// axis of `source` pixels. The weights of each add up to one, so a flat
+/// image stays flat.
+fn weights(source: usize, target: usize, filter: Filter, wrap: bool) -> Vec<Vec<(usize, f32)>> {
+    let scale = source as f32 / target as f32;
+    // Shrinking widens the filter to cover every source pixel
+    let stretch = scale.max(1.0);
+    let radius = match filter {
+        Filter::Box => 0.5 * stretch,
+        Filter::Lanczos3 => LANCZOS_LOBES * stretch,
+    };
+
+    (0..target)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            let first = (center - radius).floor() as isize;
+            let last = (center + radius).ceil() as isize;
+
+            let mut taps: Vec<(usize, f32)> = Vec::new();
+            for j in first..last {
+                let weight = match filter {
+                    // The overlap of source pixel j with the footprint
+                    Filter::Box => {
+                        let start = (j as f32).max(center - radius);
+                        let end = (j as f32 + 1.0).min(center + radius);
+                        (end - start).max(0.0)
+                    }
+                    Filter::Lanczos3 => lanczos((j as f32 + 0.5 - center) / stretch),
+                };
+                if weight == 0.0 {
+                    continue;
+                }
+
+                let index = if wrap {
+                    j.rem_euclid(source as isize)
+                } else {
+                    j.clamp(0, source as isize - 1)
+                } as usize;
+                match taps.iter_mut().find(|tap| tap.0 == index) {
+                    Some(tap) => tap.1 += weight,
+                    None => taps.push((index, weight)),
+                }
+            }
+
+            let total: f32 = taps.iter().map(|tap| tap.1).sum();
+            if total.abs() > 1e-6 {
+                taps.iter_mut().for_each(|tap| tap.1 /= total);
+            }
+            taps
+        })
+        .collect()
+}
+
+fn decode_table(srgb: bool) -> [f32; 256] {
+    let mut table = [0.0; 256];
+    for (i, value) in table.iter_mut().enumerate() {
+        let v = i as f32 / 255.0;
+        *value = if !srgb {
+            v
+        } else if v <= 0.04045 {
+            v / 12.92
+        } else {
+            ((v + 0.055) / 1.055).powf(2.4)
+        };
+    }
+    table
+}
+
+fn encode(v: f32, srgb: bool) -> u8 {
+    let v = v.clamp(0.0, 1.0);
+    let v = if !srgb {
+        v
+    } else if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (v * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const FILTERS: [Filter; 2] = [Filter::Box, Filter::Lanczos3];
+    const EDGES: [Edges; 3] = [Edges::Clamp, Edges::Wrap, Edges::Equirectangular];
+
+    fn mean(image: &RgbaImage, channel: usize) -> f32 {
+        let sum: f32 = image
+            .pixels()
+            .map(|pixel| f32::from(pixel.0[channel]))
+            .sum();
+        sum / (image.width() * image.height()) as f32
+    }
+
+    // A smooth pattern that tiles in both directions
+    fn waves(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
+            let value = 128.0 + 60.0 * (2.0 * PI * u).sin() + 40.0 * (4.0 * PI * v).cos();
+            Rgba([value as u8, 255 - value as u8, 90, 255])
+        })
+    }
+
+    #[test]
+    fn weights_add_up_to_one() {
+        for &filter in &FILTERS {
+            for &wrap in &[false, true] {
+                for &(source, target) in &[(64, 16), (64, 63), (16, 64), (7, 3), (1, 5), (5, 1)] {
+                    for taps in weights(source, target, filter, wrap) {
+                        let total: f32 = taps.iter().map(|tap| tap.1).sum();
+                        assert!((total - 1.0).abs() < 1e-5);
+                        assert!(taps.iter().all(|tap| tap.0 < source));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn flat_images_stay_flat() {
+        let flat = RgbaImage::from_pixel(40, 20, Rgba([200, 30, 120, 77]));
+        for &filter in &FILTERS {
+            for &edges in &EDGES {
+                for &srgb in &[false, true] {
+                    for &(width, height) in &[(10, 5), (17, 9), (80, 40), (1, 1)] {
+                        let resized = resize(&flat, width, height, filter, edges, srgb);
+                        assert_eq!(resized.dimensions(), (width, height));
+                        for pixel in resized.pixels() {
+                            for c in 0..4 {
+                                let difference =
+                                    i32::from(pixel.0[c]) - i32::from(flat.get_pixel(0, 0).0[c]);
+                                assert!(difference.abs() <= 1, "{:?} {:?} {}", filter, edges, srgb);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shrinking_keeps_the_average() {
+        let image = waves(64, 32);
+        for &filter in &FILTERS {
+            let small = resize(&image, 16, 8, filter, Edges::Wrap, false);
+            for c in 0..3 {
+                assert!(
+                    (mean(&small, c) - mean(&image, c)).abs() < 1.0,
+                    "{:?} {}",
+                    filter,
+                    c
+                );
+            }
+        }
+        // Each level rounds to whole steps again, up to half a step off
+        for (i, level) in mip_chain(&image, Edges::Wrap, false).iter().enumerate() {
+            for c in 0..3 {
+                let off = (mean(level, c) - mean(&image, c)).abs();
+                assert!(off <= 0.5 * i as f32 + 0.5, "level {} is {} off", i, off);
+            }
+        }
+    }
+
+    #[test]
+    fn srgb_averages_as_light() {
+        let checker = RgbaImage::from_fn(8, 8, |x, y| {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            Rgba([value, value, value, value])
+        });
+        let linear = resize(&checker, 1, 1, Filter::Box, Edges::Clamp, false);
+        let light = resize(&checker, 1, 1, Filter::Box, Edges::Clamp, true);
+        assert_eq!(linear.get_pixel(0, 0).0, [128, 128, 128, 128]);
+        // Half the light is 188 in sRGB, alpha is left as is
+        assert_eq!(light.get_pixel(0, 0).0, [188, 188, 188, 128]);
+    }
+
+    #[test]
+    fn edges_wrap_or_clamp() {
+        // A bright column down the left side
+        let image = RgbaImage::from_fn(16, 16, |x, _| {
+            Rgba([if x == 0 { 255 } else { 0 }, 0, 0, 255])
+        });
+        let right = |edges| {
+            resize(&image, 8, 8, Filter::Lanczos3, edges, false)
+                .get_pixel(7, 4)
+                .0[0]
+        };
+        assert_eq!(right(Edges::Clamp), 0);
+        assert!(right(Edges::Wrap) > 0);
+        assert!(right(Edges::Equirectangular) > 0);
+
+        // And a bright row along the top
+        let image = RgbaImage::from_fn(16, 16, |_, y| {
+            Rgba([if y == 0 { 255 } else { 0 }, 0, 0, 255])
+        });
+        let bottom = |edges| {
+            resize(&image, 8, 8, Filter::Lanczos3, edges, false)
+                .get_pixel(3, 7)
+                .0[0]
+        };
+        assert_eq!(bottom(Edges::Clamp), 0);
+        assert!(bottom(Edges::Wrap) > 0);
+        assert_eq!(bottom(Edges::Equirectangular), 0);
+    }
+
+    #[test]
+    fn equirectangular_poles_are_one_color() {
+        let image = waves(64, 32);
+        for &filter in &FILTERS {
+            let small = resize(&image, 16, 8, filter, Edges::Equirectangular, true);
+            for &y in &[0, 7] {
+                let first = small.get_pixel(0, y);
+                assert!((0..16).all(|x| small.get_pixel(x, y) == first));
+            }
+            // Away from them the rows still vary
+            assert!((0..16).any(|x| small.get_pixel(x, 4) != small.get_pixel(0, 4)));
+        }
+    }
+
+    #[test]
+    fn mip_chains_halve_down_to_one_pixel() {
+        let sizes: Vec<(u32, u32)> = mip_chain(&waves(16, 4), Edges::Wrap, true)
+            .iter()
+            .map(|level| level.dimensions())
+            .collect();
+        assert_eq!(sizes, [(16, 4), (8, 2), (4, 1), (2, 1), (1, 1)]);
+    }
+}
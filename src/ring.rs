@@ -0,0 +1,93 @@
+use crate::mesh::AnyIndexBuffer;
+use crate::{Vertex, PLANET_RADIUS};
+use glium::{backend::Facade, VertexBuffer};
+use std::error;
+use std::f32::consts::PI;
+
+// Quads around the ring
+const RING_SEGMENTS: u32 = 256;
+
+/// A flat annulus in the planet's equatorial plane, the xz plane around its
+/// y pole, facing up. u runs from 0 at `inner_radius` to 1 at
+/// `outer_radius` and v once around from +x.
+pub fn create_ring(inner_radius: f32, outer_radius: f32, segments: u32) -> (Vec<Vertex>, Vec<u32>) {
+    let segments = segments.max(3);
+    let mut vertices = Vec::with_capacity(2 * (segments + 1) as usize);
+    for i in 0..=segments {
+        let v = i as f32 / segments as f32;
+        let (sin, cos) = (2.0 * PI * v).sin_cos();
+        for (u, radius) in [(0.0, inner_radius), (1.0, outer_radius)] {
+            vertices.push(Vertex {
+                pos: [radius * cos, 0.0, radius * sin],
+                normal: [0.0, 1.0, 0.0],
+                tex: [u, v],
+                tangent: [cos, 0.0, sin, 1.0],
+                cell: 0,
+            });
+        }
+    }
+
+    let mut indices = Vec::with_capacity(6 * segments as usize);
+    for i in 0..segments {
+        let (inner, outer) = (2 * i, 2 * i + 1);
+        let (next_inner, next_outer) = (inner + 2, outer + 2);
+        // Counter-clockwise seen from above
+        indices.extend_from_slice(&[inner, next_outer, outer]);
+        indices.extend_from_slice(&[inner, next_inner, next_outer]);
+    }
+    (vertices, indices)
+}
+
+pub struct RingSettings {
+    pub enabled: bool,
+    // In planet radii from the center
+    pub inner_radius: f32,
+    pub outer_radius: f32,
+    pub opacity: f32,
+}
+
+impl RingSettings {
+    pub fn new() -> RingSettings {
+        RingSettings {
+            enabled: false,
+            inner_radius: 1.4,
+            outer_radius: 2.3,
+            opacity: 0.8,
+        }
+    }
+}
+
+/// A ring around the planet, rebuilt when its radii change.
+pub struct Ring {
+    pub settings: RingSettings,
+    pub vertex_buffer: Option<VertexBuffer<Vertex>>,
+    pub index_buffer: Option<AnyIndexBuffer>,
+    built: (f32, f32),
+}
+
+impl Ring {
+    pub fn new() -> Ring {
+        Ring {
+            settings: RingSettings::new(),
+            vertex_buffer: None,
+            index_buffer: None,
+            built: (0.0, 0.0),
+        }
+    }
+
+    pub fn update<F: Facade>(&mut self, facade: &F) -> Result<(), Box<dyn error::Error>> {
+        let settings = &self.settings;
+        let inner = settings.inner_radius.min(settings.outer_radius);
+        let outer = settings.outer_radius.max(settings.inner_radius);
+        if !settings.enabled || (self.vertex_buffer.is_some() && self.built == (inner, outer)) {
+            return Ok(());
+        }
+
+        let (vertices, indices) =
+            create_ring(inner * PLANET_RADIUS, outer * PLANET_RADIUS, RING_SEGMENTS);
+        self.vertex_buffer = Some(VertexBuffer::new(facade, &vertices)?);
+        self.index_buffer = Some(AnyIndexBuffer::new(facade, vertices.len(), &indices)?);
+        self.built = (inner, outer);
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+use crate::orbit::{self, Orbit, SATELLITES};
+use crate::units::{Preset, SceneScale};
+use cgmath::{
+    vec3, EuclideanSpace, InnerSpace, Matrix3, Matrix4, Point3, Quaternion, SquareMatrix, Vector3,
+};
+
+/// Where a satellite is and how fast it goes, in km, km/s and degrees.
+pub struct SatelliteState {
+    pub position: Vector3<f64>,
+    pub velocity: Vector3<f64>,
+    pub altitude_km: f64,
+    pub speed: f64,
+    // How fast the point below moves over the ground
+    pub ground_speed: f64,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// A camera riding one of `orbit::SATELLITES`, looking straight down or
+/// ahead along its track. The orbit is fixed in space and the planet turns
+/// under it, so it doesn't touch the other camera modes: switching in and
+/// out blends from whatever view they give and back to it.
+pub struct SatelliteView {
+    pub enabled: bool,
+    pub satellite: i32,
+    pub nadir: bool,
+    // Sim seconds per real second
+    pub time_warp: f32,
+    pub blend_duration: f32,
+    // Sim seconds since epoch
+    pub time: f64,
+    // From 0 in the other camera to 1 on the satellite
+    blend: f32,
+}
+
+impl SatelliteView {
+    pub fn new() -> SatelliteView {
+        SatelliteView {
+            enabled: false,
+            satellite: 0,
+            nadir: true,
+            time_warp: 1.0,
+            blend_duration: 1.5,
+            time: 0.0,
+            blend: 0.0,
+        }
+    }
+
+    /// Past the blend, into or out of the view.
+    pub fn snap(&mut self) {
+        self.blend = if self.enabled { 1.0 } else { 0.0 };
+    }
+
+    /// Somewhere on the way to or on the satellite.
+    pub fn is_active(&self) -> bool {
+        self.blend > 0.0
+    }
+
+    pub fn is_riding(&self) -> bool {
+        self.blend >= 1.0
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += f64::from(dt * self.time_warp);
+
+        let step = dt / self.blend_duration.max(0.01);
+        self.blend = if self.enabled {
+            (self.blend + step).min(1.0)
+        } else {
+            (self.blend - step).max(0.0)
+        };
+    }
+
+    pub fn orbit(&self, preset: &Preset) -> Orbit {
+        let satellite = &SATELLITES[self.satellite as usize % SATELLITES.len()];
+        Orbit::for_satellite(satellite, preset.planet_radius_km, preset.mu())
+    }
+
+    /// The satellite now, over a planet turned by `rotation`.
+    pub fn state(&self, preset: &Preset, rotation: Quaternion<f32>) -> SatelliteState {
+        let (position, velocity) = self.orbit(preset).state(self.time);
+        let distance = position.magnitude();
+        let radial = position / distance;
+        let horizontal = velocity - radial * velocity.dot(radial);
+
+        // The point below on the turned planet
+        let rotation = Quaternion::new(
+            f64::from(rotation.s),
+            f64::from(rotation.v.x),
+            f64::from(rotation.v.y),
+            f64::from(rotation.v.z),
+        );
+        let (latitude, longitude) = orbit::sub_point(rotation.conjugate() * position);
+
+        SatelliteState {
+            position,
+            velocity,
+            altitude_km: distance - preset.planet_radius_km,
+            speed: velocity.magnitude(),
+            ground_speed: horizontal.magnitude() * preset.planet_radius_km / distance,
+            latitude,
+            longitude,
+        }
+    }
+
+    /// World-to-eye transform on the satellite, for a planet centered at
+    /// `center` in camera-relative render units.
+    fn satellite_view(
+        &self,
+        state: &SatelliteState,
+        center: Vector3<f32>,
+        scale: SceneScale,
+    ) -> Matrix4<f32> {
+        let frame = orbit::along_track_frame(state.position, state.velocity);
+        let to_f32 = |v: Vector3<f64>| vec3(v.x as f32, v.y as f32, v.z as f32);
+        let (along, radial) = (to_f32(frame.x), to_f32(frame.z));
+
+        let eye = center + to_f32(state.position.map(|km| scale.to_render(km)));
+        let (forward, up) = if self.nadir {
+            (-radial, along)
+        } else {
+            (along, radial)
+        };
+        Matrix4::look_at_dir(Point3::from_vec(eye), forward, up)
+    }
+
+    /// `base`, the view of the other camera modes, blended towards the
+    /// satellite's. The eye swings around the planet rather than cutting
+    /// through it.
+    pub fn view_matrix(
+        &self,
+        base: Matrix4<f32>,
+        state: &SatelliteState,
+        center: Vector3<f32>,
+        scale: SceneScale,
+    ) -> Matrix4<f32> {
+        let target = self.satellite_view(state, center, scale);
+        if self.is_riding() {
+            return target;
+        }
+
+        let t = self.blend * self.blend * (3.0 - 2.0 * self.blend);
+        let (from_eye, from_rotation) = decompose(base);
+        let (to_eye, to_rotation) = decompose(target);
+
+        let (from, to) = (from_eye - center, to_eye - center);
+        let (from_distance, to_distance) = (from.magnitude(), to.magnitude());
+        let angle = from.angle(to).0;
+        let direction = if angle.sin() > 1e-3 {
+            (from / from_distance * ((1.0 - t) * angle).sin()
+                + to / to_distance * (t * angle).sin())
+                / angle.sin()
+        } else {
+            (from / from_distance).lerp(to / to_distance, t).normalize()
+        };
+        let eye = center + direction * (from_distance + (to_distance - from_distance) * t);
+
+        // The short way round
+        let to_rotation = if from_rotation.dot(to_rotation) < 0.0 {
+            -to_rotation
+        } else {
+            to_rotation
+        };
+        let rotation = from_rotation.slerp(to_rotation, t).normalize();
+
+        Matrix4::from(rotation) * Matrix4::from_translation(-eye)
+    }
+}
+
+/// Eye position and rotation of a world-to-eye transform.
+fn decompose(view: Matrix4<f32>) -> (Vector3<f32>, Quaternion<f32>) {
+    let eye = view
+        .invert()
+        .map_or(vec3(0.0, 0.0, 0.0), |inverse| inverse.w.truncate());
+    let rotation = Matrix3::from_cols(view.x.truncate(), view.y.truncate(), view.z.truncate());
+    (eye, Quaternion::from(rotation))
+}
@@ -0,0 +1,403 @@
+use std::error;
+
+// Time constant of the smoothing of the GPU time, in seconds
+const SMOOTHING_SECONDS: f32 = 0.5;
+
+// Left alone after a step while the new settings take hold and the timers,
+// which lag a frame or two, catch up
+const SETTLE_SECONDS: f32 = 1.0;
+
+// A step up taken back within this many seconds makes the next one wait
+// twice as long, up to MAX_BACKOFF times
+const RETRY_WINDOW: f32 = 10.0;
+const MAX_BACKOFF: f32 = 8.0;
+
+/// When to step and what each step is, from the `[auto]` section of
+/// quality.toml.
+pub struct ScalingSettings {
+    // Step down while the GPU takes longer than this a frame
+    pub target_ms: f32,
+    // Step back up while it takes less than this, which is below the target
+    // so a step up doesn't take it straight back over
+    pub headroom_ms: f32,
+    // How long in seconds the time has to stay over or under first
+    pub down_after: f32,
+    pub up_after: f32,
+    // The steps down in order, each a setting of debug captures and the
+    // value it steps to
+    pub ladder: Vec<(String, String)>,
+}
+
+impl ScalingSettings {
+    pub fn new() -> ScalingSettings {
+        ScalingSettings {
+            target_ms: 16.7,
+            headroom_ms: 11.0,
+            down_after: 2.0,
+            up_after: 5.0,
+            ladder: Vec::new(),
+        }
+    }
+
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), Box<dyn error::Error>> {
+        match key {
+            "target_ms" => self.target_ms = value.parse()?,
+            "headroom_ms" => self.headroom_ms = value.parse()?,
+            "down_after" => self.down_after = value.parse()?,
+            "up_after" => self.up_after = value.parse()?,
+            "ladder" => self.ladder = parse_ladder(value)?,
+            _ => return Err(format!("unknown setting '{}'", key).into()),
+        }
+        Ok(())
+    }
+}
+
+/// Steps written as `key=value, key=value`.
+fn parse_ladder(value: &str) -> Result<Vec<(String, String)>, Box<dyn error::Error>> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|step| !step.is_empty())
+        .map(|step| match step.split_once('=') {
+            Some((key, value)) => Ok((key.trim().to_string(), value.trim().to_string())),
+            None => Err(format!("expected 'key=value' but got '{}'", step).into()),
+        })
+        .collect()
+}
+
+/// The first step of `ladder` from `from` on that changes a setting of
+/// `current`, and the value it replaces. The steps of a setting go down in
+/// order, so a step is passed over when the setting is already at it or at
+/// one further down, as at the lower levels.
+pub fn next_step(
+    ladder: &[(String, String)],
+    from: usize,
+    current: &[(&'static str, String)],
+) -> Option<(usize, String)> {
+    (from..ladder.len()).find_map(|step| {
+        let key = &ladder[step].0;
+        let previous = match current.iter().find(|(current, _)| current == key) {
+            Some((_, previous)) => previous,
+            None => {
+                println!("No setting '{}' to step down", key);
+                return None;
+            }
+        };
+        let passed = ladder[step..]
+            .iter()
+            .any(|(later, value)| later == key && value == previous);
+        if passed {
+            None
+        } else {
+            Some((step, previous.clone()))
+        }
+    })
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Decision {
+    Hold,
+    // Take the next step of the ladder
+    Down,
+    // Take back the last step taken
+    Up,
+}
+
+/// Decides from the GPU time of each frame how far down the ladder to be.
+/// It only moves once the smoothed time has stayed past a threshold for a
+/// while, leaves the next frames alone after a move, and waits longer to
+/// step up again after a step up didn't hold.
+pub struct Scaler {
+    // How many steps of the ladder are taken
+    pub step: usize,
+    smoothed: Option<f32>,
+    over: f32,
+    under: f32,
+    settle: f32,
+    since_up: Option<f32>,
+    backoff: f32,
+}
+
+impl Scaler {
+    pub fn new() -> Scaler {
+        Scaler {
+            step: 0,
+            smoothed: None,
+            over: 0.0,
+            under: 0.0,
+            settle: 0.0,
+            since_up: None,
+            backoff: 1.0,
+        }
+    }
+
+    /// Back to the top of the ladder, forgetting the past.
+    pub fn reset(&mut self) {
+        *self = Scaler::new();
+    }
+
+    /// Starts timing over, for when the frames aren't the scaler's to judge.
+    pub fn pause(&mut self) {
+        self.smoothed = None;
+        self.over = 0.0;
+        self.under = 0.0;
+    }
+
+    /// The GPU time a frame it goes by, if it has seen any.
+    pub fn smoothed_ms(&self) -> Option<f32> {
+        self.smoothed
+    }
+
+    /// How much longer than `up_after` it waits to step up.
+    pub fn backoff(&self) -> f32 {
+        self.backoff
+    }
+
+    /// Takes in a frame of `dt` seconds the GPU spent `gpu_ms` on, and
+    /// moves `step` if it's time to.
+    pub fn update(&mut self, settings: &ScalingSettings, dt: f32, gpu_ms: f32) -> Decision {
+        self.step = self.step.min(settings.ladder.len());
+
+        let smoothed = match self.smoothed {
+            Some(smoothed) => smoothed + (gpu_ms - smoothed) * (dt / SMOOTHING_SECONDS).min(1.0),
+            None => gpu_ms,
+        };
+        self.smoothed = Some(smoothed);
+
+        if let Some(since) = self.since_up {
+            if since + dt > RETRY_WINDOW {
+                // The last step up held
+                self.since_up = None;
+                self.backoff = 1.0;
+            } else {
+                self.since_up = Some(since + dt);
+            }
+        }
+
+        if self.settle > 0.0 {
+            self.settle -= dt;
+            return Decision::Hold;
+        }
+
+        if smoothed > settings.target_ms {
+            self.over += dt;
+            self.under = 0.0;
+        } else if smoothed < settings.headroom_ms {
+            self.under += dt;
+            self.over = 0.0;
+        } else {
+            self.over = 0.0;
+            self.under = 0.0;
+        }
+
+        if self.over >= settings.down_after && self.step < settings.ladder.len() {
+            if self.since_up.is_some() {
+                self.backoff = (self.backoff * 2.0).min(MAX_BACKOFF);
+                self.since_up = None;
+            }
+            self.step += 1;
+            self.moved();
+            Decision::Down
+        } else if self.under >= settings.up_after * self.backoff && self.step > 0 {
+            self.step -= 1;
+            self.since_up = Some(0.0);
+            self.moved();
+            Decision::Up
+        } else {
+            Decision::Hold
+        }
+    }
+
+    fn moved(&mut self) {
+        self.pause();
+        self.settle = SETTLE_SECONDS;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: f32 = 1.0 / 60.0;
+
+    fn settings(ladder: &str) -> ScalingSettings {
+        let mut settings = ScalingSettings::new();
+        settings.set("ladder", ladder).unwrap();
+        settings
+    }
+
+    // Runs frames of `gpu_ms` for `seconds`, and the seconds in at which it
+    // moved
+    fn run(
+        scaler: &mut Scaler,
+        settings: &ScalingSettings,
+        seconds: f32,
+        gpu_ms: f32,
+    ) -> Vec<(f32, Decision)> {
+        (0..(seconds / FRAME).round() as usize)
+            .filter_map(|frame| match scaler.update(settings, FRAME, gpu_ms) {
+                Decision::Hold => None,
+                decision => Some(((frame + 1) as f32 * FRAME, decision)),
+            })
+            .collect()
+    }
+
+    // Runs frames with plenty of headroom until it steps up, and how many
+    // seconds that took
+    fn until_up(scaler: &mut Scaler, settings: &ScalingSettings) -> f32 {
+        let mut seconds = 0.0;
+        while scaler.update(settings, FRAME, 5.0) != Decision::Up {
+            seconds += FRAME;
+            assert!(seconds < 120.0, "never stepped up");
+        }
+        seconds + FRAME
+    }
+
+    fn decisions(moves: &[(f32, Decision)]) -> Vec<Decision> {
+        moves.iter().map(|&(_, decision)| decision).collect()
+    }
+
+    #[test]
+    fn parses_the_ladder() {
+        let settings = settings(" cloud_resolution = half,shadow_resolution=2 , ");
+        assert_eq!(
+            settings.ladder,
+            vec![
+                ("cloud_resolution".to_string(), "half".to_string()),
+                ("shadow_resolution".to_string(), "2".to_string()),
+            ]
+        );
+        assert!(ScalingSettings::new().set("ladder", "a=1, b").is_err());
+        assert!(ScalingSettings::new().set("target", "1").is_err());
+        assert!(ScalingSettings::new().set("target_ms", "fast").is_err());
+    }
+
+    #[test]
+    fn steps_down_after_staying_over() {
+        let settings = settings("a=1, a=2, b=1");
+        let mut scaler = Scaler::new();
+        let moves = run(&mut scaler, &settings, 3.0, 25.0);
+        assert_eq!(decisions(&moves), vec![Decision::Down]);
+        assert!((moves[0].0 - settings.down_after).abs() < 2.0 * FRAME);
+        assert_eq!(scaler.step, 1);
+    }
+
+    #[test]
+    fn spikes_do_not_step_down() {
+        let settings = settings("a=1");
+        let mut scaler = Scaler::new();
+        for _ in 0..20 {
+            assert!(run(&mut scaler, &settings, 0.1, 40.0).is_empty());
+            assert!(run(&mut scaler, &settings, 1.9, 14.0).is_empty());
+        }
+    }
+
+    #[test]
+    fn settles_after_a_step() {
+        let settings = settings("a=1, a=2, b=1");
+        let mut scaler = Scaler::new();
+        let moves = run(&mut scaler, &settings, 10.0, 25.0);
+        assert_eq!(decisions(&moves), vec![Decision::Down; 3]);
+        // Held while it settles, then over for down_after again
+        let gap = moves[1].0 - moves[0].0;
+        assert!((gap - SETTLE_SECONDS - settings.down_after).abs() < 2.0 * FRAME);
+
+        // No further than the bottom of the ladder
+        assert!(run(&mut scaler, &settings, 10.0, 25.0).is_empty());
+        assert_eq!(scaler.step, 3);
+    }
+
+    #[test]
+    fn holds_between_headroom_and_target() {
+        let settings = settings("a=1, a=2");
+        let mut scaler = Scaler::new();
+        run(&mut scaler, &settings, 3.0, 25.0);
+        assert_eq!(scaler.step, 1);
+
+        // Neither over the target nor under the headroom
+        let between = (settings.headroom_ms + settings.target_ms) / 2.0;
+        assert!(run(&mut scaler, &settings, 60.0, between).is_empty());
+
+        let moves = run(&mut scaler, &settings, 10.0, 5.0);
+        assert_eq!(decisions(&moves), vec![Decision::Up]);
+        assert_eq!(scaler.step, 0);
+    }
+
+    #[test]
+    fn backs_off_after_a_step_up_is_taken_back() {
+        let settings = settings("a=1, a=2");
+        let mut scaler = Scaler::new();
+        run(&mut scaler, &settings, 3.0, 25.0);
+        let moves = run(&mut scaler, &settings, 7.0, 5.0);
+        assert_eq!(decisions(&moves), vec![Decision::Up]);
+        assert_eq!(scaler.backoff(), 1.0);
+
+        // Back over within the retry window
+        let moves = run(&mut scaler, &settings, 4.0, 25.0);
+        assert_eq!(decisions(&moves), vec![Decision::Down]);
+        assert_eq!(scaler.backoff(), 2.0);
+
+        // So the next step up waits twice as long
+        let moves = run(&mut scaler, &settings, 13.0, 5.0);
+        assert_eq!(decisions(&moves), vec![Decision::Up]);
+        assert!(moves[0].0 > 2.0 * settings.up_after);
+
+        // And again, up to the cap
+        for _ in 0..4 {
+            run(&mut scaler, &settings, 4.0, 25.0);
+            until_up(&mut scaler, &settings);
+        }
+        run(&mut scaler, &settings, 4.0, 25.0);
+        assert_eq!(scaler.backoff(), MAX_BACKOFF);
+
+        // A step up that holds for the retry window clears it
+        assert!(until_up(&mut scaler, &settings) > MAX_BACKOFF * settings.up_after);
+        assert_eq!(scaler.backoff(), MAX_BACKOFF);
+        run(&mut scaler, &settings, RETRY_WINDOW + 1.0, 14.0);
+        assert_eq!(scaler.backoff(), 1.0);
+    }
+
+    #[test]
+    fn pause_forgets_the_timing() {
+        let settings = settings("a=1");
+        let mut scaler = Scaler::new();
+        run(&mut scaler, &settings, 1.5, 25.0);
+        assert_eq!(scaler.smoothed_ms(), Some(25.0));
+        scaler.pause();
+        assert_eq!(scaler.smoothed_ms(), None);
+        // Starts counting over
+        assert!(run(&mut scaler, &settings, 1.5, 25.0).is_empty());
+    }
+
+    #[test]
+    fn next_step_passes_over_steps_already_reached() {
+        let settings =
+            settings("cloud=half, cloud=quarter, shadow=2, shadow=1, lod_bias=1, lod_bias=2");
+        let ladder = &settings.ladder;
+        let current = |cloud: &str, shadow: &str, lod_bias: &str| {
+            vec![
+                ("cloud", cloud.to_string()),
+                ("shadow", shadow.to_string()),
+                ("lod_bias", lod_bias.to_string()),
+            ]
+        };
+
+        let high = current("full", "4", "0");
+        assert_eq!(next_step(ladder, 0, &high), Some((0, "full".to_string())));
+        assert_eq!(next_step(ladder, 2, &high), Some((2, "4".to_string())));
+
+        // Already at the first cloud step and the first shadow step
+        let medium = current("half", "2", "0");
+        assert_eq!(next_step(ladder, 0, &medium), Some((1, "half".to_string())));
+        assert_eq!(next_step(ladder, 2, &medium), Some((3, "2".to_string())));
+
+        // Already at the last of both
+        let low = current("quarter", "1", "0");
+        assert_eq!(next_step(ladder, 0, &low), Some((4, "0".to_string())));
+        assert_eq!(next_step(ladder, 0, &current("quarter", "1", "2")), None);
+
+        // Unknown settings are passed over too
+        let partial = vec![("shadow", "4".to_string())];
+        assert_eq!(next_step(ladder, 0, &partial), Some((2, "4".to_string())));
+    }
+}
@@ -0,0 +1,450 @@
+use crate::resample::{self, Edges, Filter};
+use image::{imageops, RgbaImage};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Raw frames waiting for an encoder. Queuing more blocks the render loop
+// until one is taken, rather than piling up frames in memory.
+pub const QUEUE_DEPTH: usize = 8;
+
+// Up to this many PNGs are encoded at once
+const MAX_WORKERS: usize = 4;
+
+// Throughput is averaged over this many seconds
+const THROUGHPUT_WINDOW: f32 = 2.0;
+
+enum Job {
+    Image {
+        path: PathBuf,
+        pixels: Vec<u8>,
+        width: u32,
+        height: u32,
+    },
+    ContactSheet {
+        path: PathBuf,
+        frames: Vec<PathBuf>,
+        tile_width: u32,
+    },
+}
+
+impl Job {
+    fn path(&self) -> &Path {
+        match *self {
+            Job::Image { ref path, .. } => path,
+            Job::ContactSheet { ref path, .. } => path,
+        }
+    }
+}
+
+/// What became of one queued image.
+pub struct Written {
+    pub path: PathBuf,
+    pub error: Option<String>,
+}
+
+/// Raw RGBA frames piped to an ffmpeg process, in order, on a thread of its
+/// own.
+struct Video {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    frames: SyncSender<Vec<u8>>,
+}
+
+/// Encodes and writes PNGs on a pool of threads so that saving never holds
+/// up a frame, unless `QUEUE_DEPTH` frames are already waiting. Images may
+/// finish in any order, a contact sheet is only built once everything
+/// queued before it has been written.
+pub struct ScreenshotWriter {
+    jobs: SyncSender<Job>,
+    results: Receiver<Written>,
+    result_sender: Sender<Written>,
+    workers: usize,
+    // Queued and not yet reported by `poll` or `wait`
+    pending: usize,
+    // Contact sheets waiting for the images before them
+    deferred: VecDeque<Job>,
+    video: Option<Video>,
+    finished: VecDeque<Instant>,
+    stalled: Duration,
+    // The timestamp of the last name handed out and how many had it
+    last_stamp: Option<(String, u32)>,
+}
+
+impl ScreenshotWriter {
+    pub fn new() -> ScreenshotWriter {
+        let (jobs, job_receiver) = mpsc::sync_channel::<Job>(QUEUE_DEPTH);
+        let (result_sender, results) = mpsc::channel();
+
+        let workers = thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .clamp(1, MAX_WORKERS);
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+
+        for _ in 0..workers {
+            let job_receiver = job_receiver.clone();
+            let result_sender = result_sender.clone();
+            thread::spawn(move || loop {
+                // Only held while taking a job, not while encoding it
+                let job = match job_receiver.lock() {
+                    Ok(receiver) => receiver.recv(),
+                    Err(_) => break,
+                };
+                let job = match job {
+                    Ok(job) => job,
+                    Err(_) => break,
+                };
+
+                let path = job.path().to_path_buf();
+                let error = write(job).err();
+                if result_sender.send(Written { path, error }).is_err() {
+                    break;
+                }
+            });
+        }
+
+        ScreenshotWriter {
+            jobs,
+            results,
+            result_sender,
+            workers,
+            pending: 0,
+            deferred: VecDeque::new(),
+            video: None,
+            finished: VecDeque::new(),
+            stalled: Duration::from_secs(0),
+            last_stamp: None,
+        }
+    }
+
+    /// `dir/planet-YYYYMMDD-HHMMSS.png` for `seconds` since the Unix epoch,
+    /// with a count after it for more than one in the same second.
+    pub fn next_path(&mut self, dir: &Path, seconds: u64) -> PathBuf {
+        let stamp = timestamp(seconds);
+        let count = match self.last_stamp {
+            Some((ref last, count)) if *last == stamp => count + 1,
+            _ => 1,
+        };
+        let name = if count == 1 {
+            format!("planet-{}.png", stamp)
+        } else {
+            format!("planet-{}-{}.png", stamp, count)
+        };
+        self.last_stamp = Some((stamp, count));
+        dir.join(name)
+    }
+
+    /// Queues RGBA `pixels`, top row first, to be written to `path`.
+    pub fn save(&mut self, path: PathBuf, pixels: Vec<u8>, width: u32, height: u32) {
+        self.queue(Job::Image {
+            path,
+            pixels,
+            width,
+            height,
+        });
+    }
+
+    /// Queues a grid montage of `frames`, scaled down to `tile_width`. It is
+    /// built after everything queued before it has been written.
+    pub fn contact_sheet(&mut self, path: PathBuf, frames: Vec<PathBuf>, tile_width: u32) {
+        self.pending += 1;
+        self.deferred.push_back(Job::ContactSheet {
+            path,
+            frames,
+            tile_width,
+        });
+        self.release_deferred();
+    }
+
+    fn queue(&mut self, job: Job) {
+        let path = job.path().to_path_buf();
+        let start = Instant::now();
+        match self.jobs.send(job) {
+            Ok(()) => self.pending += 1,
+            Err(_) => println!("Screenshot writer is gone, {} not saved", path.display()),
+        }
+        self.stalled += start.elapsed();
+    }
+
+    /// Sends on the contact sheets once nothing queued before them is left.
+    fn release_deferred(&mut self) {
+        while !self.deferred.is_empty() && self.pending == self.deferred.len() {
+            if let Some(job) = self.deferred.pop_front() {
+                self.pending -= 1;
+                self.queue(job);
+            }
+        }
+    }
+
+    /// Starts an ffmpeg process encoding `width` by `height` frames at `fps`
+    /// into `path`, fed by `video_frame`.
+    pub fn start_video(
+        &mut self,
+        path: PathBuf,
+        width: u32,
+        height: u32,
+        fps: u32,
+    ) -> Result<(), String> {
+        self.finish_video();
+
+        let mut child = Command::new("ffmpeg")
+            .args([
+                "-loglevel",
+                "error",
+                "-y",
+                "-f",
+                "rawvideo",
+                "-pix_fmt",
+                "rgba",
+            ])
+            .args(["-s", &format!("{}x{}", width, height)])
+            .args(["-r", &fps.to_string(), "-i", "-"])
+            .args(["-c:v", "libx264", "-pix_fmt", "yuv420p"])
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("starting ffmpeg: {}", e))?;
+        let mut stdin = child.stdin.take().ok_or("ffmpeg has no stdin")?;
+
+        let (frames, frame_receiver) = mpsc::sync_channel::<Vec<u8>>(QUEUE_DEPTH);
+        let result_sender = self.result_sender.clone();
+        let video_path = path.clone();
+        thread::spawn(move || {
+            let mut failed = false;
+            for pixels in frame_receiver {
+                // Once the pipe breaks, the rest of the frames fail with it
+                let error = if failed {
+                    Some("ffmpeg stopped".to_string())
+                } else {
+                    stdin.write_all(&pixels).err().map(|e| e.to_string())
+                };
+                failed |= error.is_some();
+                let written = Written {
+                    path: video_path.clone(),
+                    error,
+                };
+                if result_sender.send(written).is_err() {
+                    return;
+                }
+            }
+
+            // The frames are done, closing the pipe lets ffmpeg finish
+            drop(stdin);
+            let error = match child.wait() {
+                Ok(status) if status.success() => None,
+                Ok(status) => Some(format!("ffmpeg exited with {}", status)),
+                Err(e) => Some(e.to_string()),
+            };
+            let _ = result_sender.send(Written {
+                path: video_path,
+                error,
+            });
+        });
+
+        self.video = Some(Video {
+            path,
+            width,
+            height,
+            frames,
+        });
+        Ok(())
+    }
+
+    /// Queues a frame of the video started by `start_video`.
+    pub fn video_frame(&mut self, pixels: Vec<u8>, width: u32, height: u32) {
+        let video = match self.video {
+            Some(ref video) => video,
+            None => return,
+        };
+
+        self.pending += 1;
+        if (width, height) != (video.width, video.height) {
+            let _ = self.result_sender.send(Written {
+                path: video.path.clone(),
+                error: Some(format!(
+                    "frame is {}x{}, the video {}x{}",
+                    width, height, video.width, video.height
+                )),
+            });
+            return;
+        }
+
+        let start = Instant::now();
+        if video.frames.send(pixels).is_err() {
+            let _ = self.result_sender.send(Written {
+                path: video.path.clone(),
+                error: Some("the ffmpeg thread is gone".to_string()),
+            });
+        }
+        self.stalled += start.elapsed();
+    }
+
+    /// Closes the video once its queued frames are encoded. That is
+    /// reported as one more image written, with ffmpeg's exit status.
+    pub fn finish_video(&mut self) {
+        if self.video.take().is_some() {
+            self.pending += 1;
+        }
+    }
+
+    pub fn has_video(&self) -> bool {
+        self.video.is_some()
+    }
+
+    /// Images queued and not yet reported by `poll` or `wait`.
+    pub fn pending(&self) -> usize {
+        self.pending
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers
+    }
+
+    /// Images written per second, lately.
+    pub fn throughput(&self) -> f32 {
+        self.finished.len() as f32 / THROUGHPUT_WINDOW
+    }
+
+    /// Total time the render loop waited on a full queue.
+    pub fn stalled(&self) -> Duration {
+        self.stalled
+    }
+
+    fn finish(&mut self, written: &[Written]) {
+        let now = Instant::now();
+        self.pending -= written.len();
+        self.finished.extend(written.iter().map(|_| now));
+        while self
+            .finished
+            .front()
+            .is_some_and(|&time| now.duration_since(time).as_secs_f32() > THROUGHPUT_WINDOW)
+        {
+            self.finished.pop_front();
+        }
+        self.release_deferred();
+    }
+
+    /// The images written since the last call, without blocking.
+    pub fn poll(&mut self) -> Vec<Written> {
+        let written: Vec<Written> = self.results.try_iter().collect();
+        self.finish(&written);
+        written
+    }
+
+    /// Blocks until everything queued has been written, closing the video
+    /// if one is open.
+    pub fn wait(&mut self) -> Vec<Written> {
+        self.finish_video();
+
+        let mut written = Vec::new();
+        while self.pending > 0 {
+            match self.results.recv() {
+                Ok(result) => {
+                    self.finish(std::slice::from_ref(&result));
+                    written.push(result);
+                }
+                Err(_) => break,
+            }
+        }
+        written
+    }
+}
+
+fn write(job: Job) -> Result<(), String> {
+    match job {
+        Job::Image {
+            path,
+            pixels,
+            width,
+            height,
+        } => image::save_buffer(&path, &pixels, width, height, image::ColorType::Rgba8)
+            .map_err(|e| e.to_string()),
+        Job::ContactSheet {
+            path,
+            frames,
+            tile_width,
+        } => build_contact_sheet(&frames, tile_width)?
+            .save(&path)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Lays `frames` out left to right, top to bottom, in a grid about as wide
+/// as it is tall.
+fn build_contact_sheet(frames: &[PathBuf], tile_width: u32) -> Result<RgbaImage, String> {
+    if frames.is_empty() {
+        return Err("no frames for the contact sheet".to_string());
+    }
+
+    let columns = (frames.len() as f32).sqrt().ceil() as u32;
+    let rows = (frames.len() as u32).div_ceil(columns);
+    let mut sheet = None;
+    let mut tile_height = 0;
+
+    for (i, frame) in frames.iter().enumerate() {
+        let frame = image::open(frame)
+            .map_err(|e| format!("{}: {}", frame.display(), e))?
+            .to_rgba8();
+
+        let sheet = sheet.get_or_insert_with(|| {
+            tile_height = (tile_width * frame.height() / frame.width().max(1)).max(1);
+            RgbaImage::new(columns * tile_width, rows * tile_height)
+        });
+
+        let tile = resample::resize(
+            &frame,
+            tile_width,
+            tile_height,
+            Filter::Lanczos3,
+            Edges::Clamp,
+            true,
+        );
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        imageops::replace(
+            sheet,
+            &tile,
+            i64::from(column * tile_width),
+            i64::from(row * tile_height),
+        );
+    }
+
+    sheet.ok_or_else(|| "no frames for the contact sheet".to_string())
+}
+
+/// `seconds` since the Unix epoch as "YYYYMMDD-HHMMSS", in UTC.
+pub fn timestamp(seconds: u64) -> String {
+    let days = (seconds / 86_400) as i64;
+    let time = seconds % 86_400;
+
+    // Days to a civil date, counting in 400 year eras that start on March 1
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
@@ -0,0 +1,241 @@
+use crate::benchmark::json_string;
+use std::backtrace::Backtrace;
+use std::error;
+use std::fs;
+use std::io::{self, Write};
+use std::panic;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const AUTOSAVE_PATH: &str = "autosave.json";
+const SHUTDOWN_PATH: &str = "last_shutdown.txt";
+const CRASH_LOG_PATH: &str = "crash.log";
+
+/// The settings of `capture::settings`, which is everything a session can
+/// be restored from.
+pub type Snapshot = Vec<(&'static str, String)>;
+
+/// Writes `contents` to a temporary file next to `path` and renames it over
+/// `path`, so that a crash halfway leaves the previous file whole.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temp = path.with_extension("tmp");
+    {
+        let mut file = fs::File::create(&temp)?;
+        file.write_all(contents)?;
+        file.sync_all()?;
+    }
+    fs::rename(&temp, path)
+}
+
+fn unix_seconds() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn autosave_json(snapshot: &[(&'static str, String)], saved_at: u64) -> String {
+    let settings: Vec<String> = snapshot
+        .iter()
+        .map(|(key, value)| format!("    {}: {}", json_string(key), json_string(value)))
+        .collect();
+    format!(
+        "{{\n  \"saved_at\": {},\n  \"settings\": {{\n{}\n  }}\n}}\n",
+        saved_at,
+        settings.join(",\n")
+    )
+}
+
+pub fn json_unescape(value: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            unescaped.extend(chars.next());
+        } else {
+            unescaped.push(c);
+        }
+    }
+    unescaped
+}
+
+/// Reads back what `autosave_json` wrote, one member per line.
+fn parse_autosave(text: &str) -> Result<Restorable, Box<dyn error::Error>> {
+    let mut saved_at = None;
+    let mut settings = Vec::new();
+
+    for line in text.lines().map(str::trim) {
+        let line = line.trim_end_matches(',');
+        if let Some(value) = line.strip_prefix("\"saved_at\":") {
+            saved_at = Some(value.trim().parse()?);
+        } else if line.starts_with('"') && line.ends_with('"') {
+            // "key": "value", neither contains an unescaped quote
+            let inner = &line[1..line.len() - 1];
+            let mut split = None;
+            let mut escaped = false;
+            for (i, c) in inner.char_indices() {
+                match c {
+                    '\\' if !escaped => escaped = true,
+                    '"' if !escaped => {
+                        split = Some(i);
+                        break;
+                    }
+                    _ => escaped = false,
+                }
+            }
+            let split = split.ok_or_else(|| format!("malformed autosave line '{}'", line))?;
+            let value = inner[split + 1..].trim_start_matches([':', ' ']);
+            let value = value.strip_prefix('"').unwrap_or(value);
+            settings.push((json_unescape(&inner[..split]), json_unescape(value)));
+        }
+    }
+
+    Ok(Restorable {
+        saved_at: saved_at.ok_or("autosave has no saved_at")?,
+        settings,
+    })
+}
+
+/// An autosave found at startup that is newer than the last clean
+/// shutdown.
+pub struct Restorable {
+    pub saved_at: u64,
+    pub settings: Vec<(String, String)>,
+}
+
+/// Periodic autosave of the session, only when something changed, and a
+/// panic hook that makes a last save and logs the crash.
+pub struct Session {
+    pub interval: f32,
+    pub restorable: Option<Restorable>,
+    pub error: Option<String>,
+    pub last_save: Option<u64>,
+    saved: Snapshot,
+    // The latest snapshot, for the panic hook to save
+    latest: Arc<Mutex<Snapshot>>,
+    timer: f32,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session {
+            interval: 30.0,
+            restorable: None,
+            error: None,
+            last_save: None,
+            saved: Vec::new(),
+            latest: Arc::new(Mutex::new(Vec::new())),
+            timer: 0.0,
+        }
+    }
+
+    /// Looks for an autosave the last run didn't shut down cleanly after,
+    /// and installs the panic hook.
+    pub fn start(&mut self) {
+        let shutdown_at: u64 = fs::read_to_string(SHUTDOWN_PATH)
+            .ok()
+            .and_then(|text| text.trim().parse().ok())
+            .unwrap_or(0);
+
+        match fs::read_to_string(AUTOSAVE_PATH).map(|text| parse_autosave(&text)) {
+            Ok(Ok(restorable)) if restorable.saved_at > shutdown_at => {
+                self.restorable = Some(restorable)
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => println!("Ignoring unreadable {}: {}", AUTOSAVE_PATH, e),
+            Err(_) => {}
+        }
+
+        let latest = self.latest.clone();
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            default_hook(info);
+
+            // The frame may have panicked holding the lock
+            let saved = match latest.try_lock() {
+                Ok(snapshot) if !snapshot.is_empty() => write_atomic(
+                    Path::new(AUTOSAVE_PATH),
+                    autosave_json(&snapshot, unix_seconds()).as_bytes(),
+                )
+                .is_ok(),
+                _ => false,
+            };
+
+            let log = format!(
+                "[{}] {}\nsession {}\n{}\n\n",
+                unix_seconds(),
+                info,
+                if saved { "saved" } else { "not saved" },
+                Backtrace::force_capture()
+            );
+            let written = fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(CRASH_LOG_PATH)
+                .and_then(|mut file| file.write_all(log.as_bytes()));
+            if written.is_ok() {
+                eprintln!("Crash logged to {}", CRASH_LOG_PATH);
+            }
+        }));
+    }
+
+    /// Changed since it was last saved.
+    pub fn is_dirty(&self) -> bool {
+        self.latest.lock().is_ok_and(|latest| *latest != self.saved)
+    }
+
+    /// Takes the frame's snapshot and saves it every `interval` seconds if
+    /// it changed. Nothing is saved while an older session waits to be
+    /// restored, that would overwrite it.
+    pub fn update(&mut self, dt: f32, snapshot: Snapshot) {
+        if self.restorable.is_some() {
+            return;
+        }
+
+        if let Ok(mut latest) = self.latest.lock() {
+            *latest = snapshot;
+        }
+
+        self.timer += dt;
+        if self.timer >= self.interval.max(1.0) {
+            self.timer = 0.0;
+            if self.is_dirty() {
+                self.save();
+            }
+        }
+    }
+
+    pub fn save(&mut self) {
+        let snapshot = match self.latest.lock() {
+            Ok(latest) if !latest.is_empty() => latest.clone(),
+            _ => return,
+        };
+
+        let saved_at = unix_seconds();
+        match write_atomic(
+            Path::new(AUTOSAVE_PATH),
+            autosave_json(&snapshot, saved_at).as_bytes(),
+        ) {
+            Ok(()) => {
+                self.saved = snapshot;
+                self.last_save = Some(saved_at);
+                self.error = None;
+            }
+            Err(e) => self.error = Some(format!("{}: {}", AUTOSAVE_PATH, e)),
+        }
+    }
+
+    /// A last save, then marks the shutdown as clean so the autosave isn't
+    /// offered next time.
+    pub fn shutdown(&mut self) {
+        if self.restorable.is_none() && self.is_dirty() {
+            self.save();
+        }
+        if let Err(e) = write_atomic(
+            Path::new(SHUTDOWN_PATH),
+            format!("{}\n", unix_seconds()).as_bytes(),
+        ) {
+            println!("Recording the clean shutdown failed: {}", e);
+        }
+    }
+}
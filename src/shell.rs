@@ -0,0 +1,78 @@
+use crate::mesh::{create_sphere, flatten, AnyIndexBuffer};
+use crate::{Vertex, PLANET_RADIUS};
+use glium::{backend::Facade, VertexBuffer};
+use std::error;
+
+// Rings of latitude on the shell, it only ever shows as a soft glow
+const SHELL_SEGMENTS: usize = 96;
+
+// Draw the glow over the clouds rather than under them, so it brightens
+// the cloud tops at the limb too instead of being covered by them
+pub const ATMOSPHERE_AFTER_CLOUDS: bool = true;
+
+pub struct ShellSettings {
+    pub enabled: bool,
+    // How far the shell reaches above the surface, in planet radii
+    pub thickness: f32,
+    pub tint: [f32; 3],
+    pub intensity: f32,
+}
+
+impl ShellSettings {
+    pub fn new() -> ShellSettings {
+        ShellSettings {
+            enabled: true,
+            thickness: 0.05,
+            tint: [0.35, 0.55, 1.0],
+            intensity: 1.0,
+        }
+    }
+}
+
+/// A shell a little larger than the planet, and as flattened, that the
+/// atmosphere's limb glow is drawn on. Rebuilt when its thickness or the
+/// planet's flattening changes.
+pub struct AtmosphereShell {
+    pub settings: ShellSettings,
+    pub vertex_buffer: Option<VertexBuffer<Vertex>>,
+    pub index_buffer: Option<AnyIndexBuffer>,
+    built: (f32, f32),
+}
+
+impl AtmosphereShell {
+    pub fn new() -> AtmosphereShell {
+        AtmosphereShell {
+            settings: ShellSettings::new(),
+            vertex_buffer: None,
+            index_buffer: None,
+            built: (0.0, 0.0),
+        }
+    }
+
+    /// The shell's equatorial radius in render units.
+    pub fn radius(&self) -> f32 {
+        PLANET_RADIUS * (1.0 + self.settings.thickness.max(0.001))
+    }
+
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        flattening: f32,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let built = (self.radius(), flattening);
+        if !self.settings.enabled || (self.vertex_buffer.is_some() && self.built == built) {
+            return Ok(());
+        }
+
+        let mut sphere = create_sphere(built.0, SHELL_SEGMENTS);
+        flatten(&mut sphere.vertices, flattening);
+        self.vertex_buffer = Some(VertexBuffer::new(facade, &sphere.vertices)?);
+        self.index_buffer = Some(AnyIndexBuffer::new(
+            facade,
+            sphere.vertices.len(),
+            &sphere.indices,
+        )?);
+        self.built = built;
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use glium::{backend::Facade, implement_vertex, index::PrimitiveType, IndexBuffer, VertexBuffer};
+use std::error;
+
+#[derive(Copy, Clone)]
+pub struct SkyboxVertex {
+    sky_pos: [f32; 3],
+}
+implement_vertex!(SkyboxVertex, sky_pos);
+
+/// The corners of a cube from -1 to 1 and its twelve triangles, wound
+/// counter-clockwise seen from outside like the planet's.
+pub fn create_skybox_cube() -> (Vec<SkyboxVertex>, Vec<u16>) {
+    let vertices = (0..8)
+        .map(|i| SkyboxVertex {
+            sky_pos: [
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { -1.0 } else { 1.0 },
+            ],
+        })
+        .collect();
+
+    // Each face as its corners in order around it
+    let faces: [[u16; 4]; 6] = [
+        [1, 3, 7, 5],
+        [0, 4, 6, 2],
+        [2, 6, 7, 3],
+        [0, 1, 5, 4],
+        [4, 5, 7, 6],
+        [0, 2, 3, 1],
+    ];
+    let indices = faces
+        .iter()
+        .flat_map(|&[a, b, c, d]| [a, b, c, a, c, d])
+        .collect();
+    (vertices, indices)
+}
+
+/// A faint glow behind the stars, the band of the galaxy and dust around
+/// it. Drawn first, at infinity.
+pub struct Skybox {
+    pub enabled: bool,
+    pub brightness: f32,
+    pub vertex_buffer: VertexBuffer<SkyboxVertex>,
+    pub index_buffer: IndexBuffer<u16>,
+}
+
+impl Skybox {
+    pub fn new<F: Facade>(facade: &F) -> Result<Skybox, Box<dyn error::Error>> {
+        let (vertices, indices) = create_skybox_cube();
+        Ok(Skybox {
+            enabled: true,
+            brightness: 1.0,
+            vertex_buffer: VertexBuffer::new(facade, &vertices)?,
+            index_buffer: IndexBuffer::new(facade, PrimitiveType::TrianglesList, &indices)?,
+        })
+    }
+}
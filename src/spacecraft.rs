@@ -0,0 +1,247 @@
+use crate::noise;
+use crate::polyline::{PolylineBatch, PolylineVertex};
+use crate::stream::StreamPool;
+use cgmath::{InnerSpace, Vector3};
+use glium::backend::Facade;
+use std::collections::VecDeque;
+use std::error;
+
+const MAX_TRAIL_POINTS: usize = 512;
+const TRAIL_INTERVAL: f32 = 0.05;
+
+pub struct SpacecraftSettings {
+    pub ascent_duration: f32,
+    pub orbit_altitude_km: f32,
+    pub orbit_period: f32,
+    pub trail_lifetime: f32,
+    pub follow: bool,
+    // In pixels
+    pub trail_width: f32,
+    pub marker_size: f32,
+}
+
+struct Flight {
+    up: Vector3<f32>,
+    downrange: Vector3<f32>,
+    start: Vector3<f32>,
+    orbit_radius: f32,
+    insertion_angle: f32,
+    time: f32,
+}
+
+/// A marker that launches from the surface, climbs along a Hermite spline
+/// and joins a circular orbit with matching position and velocity. All in
+/// planet-local space.
+pub struct Spacecraft {
+    pub settings: SpacecraftSettings,
+    pub trail_lines: PolylineBatch,
+    // A single point, which draws as a dot
+    pub marker: PolylineBatch,
+    flight: Option<Flight>,
+    trail: VecDeque<(Vector3<f32>, f32)>,
+    trail_timer: f32,
+}
+
+impl Spacecraft {
+    pub fn new() -> Spacecraft {
+        Spacecraft {
+            settings: SpacecraftSettings {
+                ascent_duration: 8.0,
+                orbit_altitude_km: 1000.0,
+                orbit_period: 60.0,
+                trail_lifetime: 20.0,
+                follow: false,
+                trail_width: 2.0,
+                marker_size: 6.0,
+            },
+            trail_lines: PolylineBatch::new(),
+            marker: PolylineBatch::new(),
+            flight: None,
+            trail: VecDeque::new(),
+            trail_timer: 0.0,
+        }
+    }
+
+    /// Starts a launch from the surface point under `up`, heading off along
+    /// the tangent `heading`, into an orbit `altitude` above `radius`.
+    pub fn launch(&mut self, up: Vector3<f32>, heading: Vector3<f32>, radius: f32, altitude: f32) {
+        let up = up.normalize();
+        let downrange = (heading - up * heading.dot(up)).normalize();
+        let ground = noise::terrain_altitude([up.x, up.y, up.z], radius);
+        let orbit_radius = radius + altitude;
+
+        self.flight = Some(Flight {
+            up,
+            downrange,
+            start: up * ground,
+            orbit_radius,
+            // Insert about one ascent duration of orbital motion downrange
+            insertion_angle: self.angular_speed() * self.settings.ascent_duration,
+            time: 0.0,
+        });
+        self.trail.clear();
+        self.trail_timer = 0.0;
+    }
+
+    /// Back on the ground with no trail.
+    pub fn reset(&mut self) {
+        self.flight = None;
+        self.trail.clear();
+        self.trail_timer = 0.0;
+    }
+
+    pub fn is_flying(&self) -> bool {
+        self.flight.is_some()
+    }
+
+    pub fn is_ascending(&self) -> bool {
+        self.flight
+            .as_ref()
+            .is_some_and(|flight| flight.time < self.settings.ascent_duration)
+    }
+
+    fn angular_speed(&self) -> f32 {
+        2.0 * std::f32::consts::PI / self.settings.orbit_period.max(1.0)
+    }
+
+    /// Position and velocity at `time` since launch.
+    fn state(&self, flight: &Flight, time: f32) -> (Vector3<f32>, Vector3<f32>) {
+        let omega = self.angular_speed();
+        let orbit = |angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            (
+                (flight.up * cos + flight.downrange * sin) * flight.orbit_radius,
+                (flight.downrange * cos - flight.up * sin) * flight.orbit_radius * omega,
+            )
+        };
+
+        let duration = self.settings.ascent_duration.max(0.1);
+        if time >= duration {
+            return orbit(flight.insertion_angle + omega * (time - duration));
+        }
+
+        // Cubic Hermite from a vertical liftoff to the insertion point, the
+        // end tangent is the orbital velocity so the handover is C1
+        let (p1, v1) = orbit(flight.insertion_angle);
+        let p0 = flight.start;
+        let v0 = flight.up * (p1 - p0).magnitude() / duration;
+        let (m0, m1) = (v0 * duration, v1 * duration);
+
+        let t = time / duration;
+        let (t2, t3) = (t * t, t * t * t);
+        let position = p0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+            + m0 * (t3 - 2.0 * t2 + t)
+            + p1 * (-2.0 * t3 + 3.0 * t2)
+            + m1 * (t3 - t2);
+        let velocity = (p0 * (6.0 * t2 - 6.0 * t)
+            + m0 * (3.0 * t2 - 4.0 * t + 1.0)
+            + p1 * (-6.0 * t2 + 6.0 * t)
+            + m1 * (3.0 * t2 - 2.0 * t))
+            / duration;
+
+        (position, velocity)
+    }
+
+    pub fn position(&self) -> Option<Vector3<f32>> {
+        self.flight
+            .as_ref()
+            .map(|flight| self.state(flight, flight.time).0)
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.flight
+            .as_ref()
+            .map_or(0.0, |flight| self.state(flight, flight.time).1.magnitude())
+    }
+
+    /// Advances the flight and rewrites the trail, which ends at the marker
+    /// once launched.
+    pub fn update<F: Facade>(
+        &mut self,
+        facade: &F,
+        pool: &mut StreamPool<PolylineVertex>,
+        dt: f32,
+    ) -> Result<(), Box<dyn error::Error>> {
+        let position = match self.flight {
+            Some(ref mut flight) => {
+                flight.time += dt;
+                self.position()
+            }
+            None => None,
+        };
+
+        for point in &mut self.trail {
+            point.1 += dt;
+        }
+        let lifetime = self.settings.trail_lifetime;
+        while self.trail.front().is_some_and(|point| point.1 > lifetime) {
+            self.trail.pop_front();
+        }
+
+        if let Some(position) = position {
+            self.trail_timer -= dt;
+            if self.trail_timer <= 0.0 {
+                self.trail_timer = TRAIL_INTERVAL;
+                self.trail.push_back((position, 0.0));
+                if self.trail.len() > MAX_TRAIL_POINTS {
+                    self.trail.pop_front();
+                }
+            }
+        }
+
+        let mut points: Vec<_> = self
+            .trail
+            .iter()
+            .map(|&(pos, age)| {
+                let alpha = (1.0 - age / lifetime.max(0.1)).clamp(0.0, 1.0);
+                (pos, [1.0, 1.0, 1.0, alpha])
+            })
+            .collect();
+
+        self.marker.clear();
+        if let Some(position) = position {
+            points.push((position, [1.0; 4]));
+            self.marker.push(&[(position, [1.0; 4])]);
+        }
+
+        self.trail_lines.clear();
+        if points.len() > 1 {
+            self.trail_lines.push(&points);
+        }
+
+        self.trail_lines.stream(facade, pool)?;
+        self.marker.stream(facade, pool)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::vec3;
+
+    #[test]
+    fn ascent_hands_over_to_the_orbit_smoothly() {
+        let launches = [
+            (vec3(0.0, 1.0, 0.0), vec3(1.0, 0.0, 0.0)),
+            (vec3(0.3, -0.5, 0.8), vec3(0.0, 0.0, 1.0)),
+            (vec3(-1.0, 0.2, 0.1), vec3(0.1, 1.0, -0.4)),
+        ];
+        for &(up, heading) in &launches {
+            let mut spacecraft = Spacecraft::new();
+            spacecraft.launch(up, heading, 1.0, 0.2);
+            let flight = spacecraft.flight.as_ref().unwrap();
+            let duration = spacecraft.settings.ascent_duration;
+
+            // Just before the handover the spline is still flying, at it
+            // the orbit has taken over
+            let h = duration * 1e-3;
+            let (before, before_velocity) = spacecraft.state(flight, duration - h);
+            let (at, at_velocity) = spacecraft.state(flight, duration);
+            let speed = at_velocity.magnitude();
+
+            assert!((at.magnitude() - flight.orbit_radius).abs() < 1e-6);
+            assert!((at - before - before_velocity * h).magnitude() < 1e-2 * speed * h);
+            assert!((at_velocity - before_velocity).magnitude() < 1e-2 * speed);
+        }
+    }
+}
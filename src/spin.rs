@@ -0,0 +1,64 @@
+/// Turning something at a steady rate on its own, negative the other way
+/// round.
+pub struct Spin {
+    pub enabled: bool,
+    pub speed_deg_per_sec: f32,
+}
+
+impl Spin {
+    pub fn new(speed_deg_per_sec: f32) -> Spin {
+        Spin {
+            enabled: false,
+            speed_deg_per_sec,
+        }
+    }
+
+    /// Degrees to turn over `dt` seconds, none while `held` by hand.
+    pub fn step(&self, dt: f32, held: bool) -> f32 {
+        if self.enabled && !held {
+            self.speed_deg_per_sec * dt
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Sweeps the sun angle round on its own, and the season too if asked,
+/// each once per period of sim time.
+pub struct SunCycle {
+    pub enabled: bool,
+    // Sim seconds per turn of the sun angle
+    pub period: f32,
+    pub seasons: bool,
+    // Sim seconds per turn of the season
+    pub season_period: f32,
+    // Set while the sun is moved by hand, the sweep goes on from wherever
+    // it is let go
+    pub held: bool,
+}
+
+impl SunCycle {
+    pub fn new() -> SunCycle {
+        SunCycle {
+            enabled: false,
+            period: 60.0,
+            seasons: false,
+            season_period: 600.0,
+            held: false,
+        }
+    }
+
+    /// Moves `sun_angle` and `season` on by `dt` sim seconds. Returns
+    /// whether either moved.
+    pub fn step(&self, dt: f32, sun_angle: &mut f32, season: &mut f32) -> bool {
+        if !self.enabled || self.held || dt == 0.0 {
+            return false;
+        }
+        *sun_angle =
+            (*sun_angle + 360.0 * dt / self.period.max(1.0) + 180.0).rem_euclid(360.0) - 180.0;
+        if self.seasons {
+            *season = (*season + 360.0 * dt / self.season_period.max(1.0)).rem_euclid(360.0);
+        }
+        true
+    }
+}
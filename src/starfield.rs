@@ -0,0 +1,200 @@
+use crate::StarVertex;
+use cgmath::{vec3, InnerSpace, Vector3};
+use glium::{backend::Facade, VertexBuffer};
+use std::cell::Cell;
+use std::error;
+use std::ops::Range;
+
+// The far stars are split by direction into this many by this many cells on
+// each face of a cube
+const CELLS: usize = 4;
+
+/// Stars in one cell of directions, a cone of `spread` radians around
+/// `axis`.
+struct StarBatch {
+    range: Range<usize>,
+    axis: Vector3<f32>,
+    spread: f32,
+}
+
+/// The cube face and cell a direction falls in.
+fn cell(direction: Vector3<f32>) -> usize {
+    let d = direction;
+    let (abs_x, abs_y, abs_z) = (d.x.abs(), d.y.abs(), d.z.abs());
+    let (face, u, v, major) = if abs_x >= abs_y && abs_x >= abs_z {
+        (if d.x > 0.0 { 0 } else { 1 }, d.y, d.z, abs_x)
+    } else if abs_y >= abs_z {
+        (if d.y > 0.0 { 2 } else { 3 }, d.z, d.x, abs_y)
+    } else {
+        (if d.z > 0.0 { 4 } else { 5 }, d.x, d.y, abs_z)
+    };
+    let to_cell = |t: f32| (((t / major + 1.0) / 2.0 * CELLS as f32) as usize).min(CELLS - 1);
+    (face * CELLS + to_cell(u)) * CELLS + to_cell(v)
+}
+
+/// The stars, with the far ones sorted into batches by direction so that
+/// those behind the planet can be left out.
+pub struct Starfield {
+    pub buffer: VertexBuffer<StarVertex>,
+    // The nearby stars move with the camera, they are always drawn
+    nearby: usize,
+    batches: Vec<StarBatch>,
+    pub culling: bool,
+    // Stars the last frame drew
+    pub drawn: Cell<usize>,
+}
+
+impl Starfield {
+    /// Takes the first `nearby` stars as they are and batches the rest.
+    pub fn new<F: Facade>(
+        facade: &F,
+        mut stars: Vec<StarVertex>,
+        nearby: usize,
+    ) -> Result<Starfield, Box<dyn error::Error>> {
+        let direction = |star: &StarVertex| vec3(star.pos[0], star.pos[1], star.pos[2]);
+        stars[nearby..].sort_by_key(|star| cell(direction(star)));
+
+        let mut batches: Vec<StarBatch> = Vec::new();
+        let mut start = nearby;
+        while start < stars.len() {
+            let key = cell(direction(&stars[start]));
+            let end = start
+                + stars[start..]
+                    .iter()
+                    .take_while(|star| cell(direction(star)) == key)
+                    .count();
+            let axis = stars[start..end]
+                .iter()
+                .fold(vec3(0.0, 0.0, 0.0), |sum, star| sum + direction(star))
+                .normalize();
+            let spread = stars[start..end]
+                .iter()
+                .map(|star| axis.dot(direction(star)).clamp(-1.0, 1.0).acos())
+                .fold(0.0, f32::max);
+            batches.push(StarBatch {
+                range: start..end,
+                axis,
+                spread,
+            });
+            start = end;
+        }
+
+        Ok(Starfield {
+            buffer: VertexBuffer::new(facade, &stars)?,
+            nearby,
+            batches,
+            culling: true,
+            drawn: Cell::new(0),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// The ranges of the buffer to draw for a camera at `camera`, relative
+    /// to the center of a planet of `radius` in the stars' space. A batch is
+    /// left out when all of it is behind the planet's disc.
+    pub fn visible(&self, camera: Vector3<f32>, radius: f32) -> Vec<Range<usize>> {
+        let mut ranges = Vec::new();
+        ranges.push(0..self.nearby);
+        let distance = camera.magnitude();
+        let hidden = |batch: &StarBatch| {
+            if !self.culling || distance <= radius {
+                return false;
+            }
+            let disc = (radius / distance).asin();
+            let to_planet = -camera / distance;
+            batch.axis.dot(to_planet).clamp(-1.0, 1.0).acos() + batch.spread < disc
+        };
+
+        for batch in self.batches.iter().filter(|batch| !hidden(batch)) {
+            match ranges.last_mut() {
+                Some(last) if last.end == batch.range.start => last.end = batch.range.end,
+                _ => ranges.push(batch.range.clone()),
+            }
+        }
+        ranges
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{perspective, Deg, Matrix4, Point3, Vector4};
+
+    // Stars are placed in stars.vert, so the tests run on a copy of its
+    // math and check that the shader still reads the same
+    const SHADER: &str = include_str!("../shaders/stars.vert");
+
+    #[test]
+    fn shader_math_is_the_one_tested() {
+        for line in [
+            "if (distance > 0.0) {",
+            "gl_Position = mvp * vec4(pos * distance * lightYear, 1.0);",
+            "gl_Position = mvp * vec4(pos, 0.0);",
+        ] {
+            assert!(SHADER.contains(line), "stars.vert no longer has {}", line);
+        }
+    }
+
+    /// Where stars.vert puts a star on screen, in normalized device
+    /// coordinates.
+    fn project(mvp: Matrix4<f32>, pos: Vector3<f32>, distance: f32, light_year: f32) -> [f32; 2] {
+        let clip: Vector4<f32> = if distance > 0.0 {
+            mvp * (pos * distance * light_year).extend(1.0)
+        } else {
+            mvp * pos.extend(0.0)
+        };
+        [clip.x / clip.w, clip.y / clip.w]
+    }
+
+    /// The star matrix and view of a camera at `eye` looking about down
+    /// -z, with the stars centered on the planet at the origin.
+    fn mvp(eye: Vector3<f32>) -> Matrix4<f32> {
+        let projection = perspective(Deg(60.0), 16.0 / 9.0, 0.01, 100.0);
+        let view = Matrix4::look_at(
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.1, 0.05, -1.0),
+            vec3(0.0, 1.0, 0.0),
+        );
+        // Camera relative, like State::star_matrix
+        projection * view * Matrix4::from_translation(-eye)
+    }
+
+    #[test]
+    fn only_finite_stars_move_with_the_camera() {
+        let star = vec3(0.2, -0.1, -1.0).normalize();
+        // Light years in render units, exaggerated like the parallax slider
+        let light_year = 0.5;
+        let here = mvp(vec3(0.0, 0.0, 0.0));
+
+        for &step in &[
+            vec3(1.0, 0.0, 0.0),
+            vec3(0.0, -0.5, 0.3),
+            vec3(0.0, 0.0, -1.5),
+        ] {
+            let there = mvp(step);
+
+            let far = (
+                project(here, star, 0.0, light_year),
+                project(there, star, 0.0, light_year),
+            );
+            assert!((far.0[0] - far.1[0]).abs() < 1e-6);
+            assert!((far.0[1] - far.1[1]).abs() < 1e-6);
+
+            let near = (
+                project(here, star, 4.0, light_year),
+                project(there, star, 4.0, light_year),
+            );
+            let moved = ((near.0[0] - near.1[0]).powi(2) + (near.0[1] - near.1[1]).powi(2)).sqrt();
+            assert!(moved > 0.01, "moved {} for a step of {:?}", moved, step);
+        }
+
+        // Without the camera moving the two agree, the distance only adds
+        // parallax
+        let star_at = |distance| project(here, star, distance, light_year);
+        let (near, far) = (star_at(4.0), star_at(0.0));
+        assert!((near[0] - far[0]).abs() < 1e-5 && (near[1] - far[1]).abs() < 1e-5);
+    }
+}
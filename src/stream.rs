@@ -0,0 +1,347 @@
+use glium::{backend::Facade, vertex::VertexBufferSlice, Vertex, VertexBuffer};
+use std::collections::VecDeque;
+use std::error;
+use std::mem;
+use std::ops::Range;
+
+// How many frames' ranges are kept before they are handed out again, the
+// one being drawn and two the GPU may still be reading
+pub const FRAMES_IN_FLIGHT: usize = 3;
+
+/// Hands out ranges of a buffer front to back, wrapping around to the start
+/// when the end doesn't fit. A range is free again `FRAMES_IN_FLIGHT`
+/// frames after the one it was handed out in. Counts in elements, it knows
+/// nothing of the buffer.
+pub struct RingAllocator {
+    capacity: usize,
+    // The next range starts at the head, the oldest in use at the tail
+    head: usize,
+    tail: usize,
+    used: usize,
+    frame_used: usize,
+    // Where the head was at the end of each frame still in flight, and how
+    // much that frame took
+    in_flight: VecDeque<(usize, usize)>,
+}
+
+impl RingAllocator {
+    pub fn new(capacity: usize) -> RingAllocator {
+        RingAllocator {
+            capacity,
+            head: 0,
+            tail: 0,
+            used: 0,
+            frame_used: 0,
+            in_flight: VecDeque::with_capacity(FRAMES_IN_FLIGHT + 1),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// What is in use, including the ends skipped when wrapping around.
+    pub fn used(&self) -> usize {
+        self.used
+    }
+
+    /// A range of `len` no frame in flight uses, or `None` if there isn't
+    /// one in a piece.
+    pub fn allocate(&mut self, len: usize) -> Option<Range<usize>> {
+        if len == 0 || len > self.capacity - self.used {
+            return None;
+        }
+        if self.used == 0 {
+            self.head = 0;
+            self.tail = 0;
+        }
+
+        let start = if self.head >= self.tail {
+            if len <= self.capacity - self.head {
+                self.head
+            } else if len <= self.tail {
+                // The end is too short, it stays unused until the frame
+                // that skipped it is done
+                let skipped = self.capacity - self.head;
+                self.used += skipped;
+                self.frame_used += skipped;
+                0
+            } else {
+                return None;
+            }
+        } else if len <= self.tail - self.head {
+            self.head
+        } else {
+            return None;
+        };
+
+        self.head = start + len;
+        self.used += len;
+        self.frame_used += len;
+        Some(start..start + len)
+    }
+
+    /// Ends the frame, freeing what the frame `FRAMES_IN_FLIGHT` back took.
+    pub fn end_frame(&mut self) {
+        self.in_flight.push_back((self.head, self.frame_used));
+        self.frame_used = 0;
+        while self.in_flight.len() > FRAMES_IN_FLIGHT {
+            if let Some((mark, units)) = self.in_flight.pop_front() {
+                self.tail = mark;
+                self.used -= units;
+            }
+        }
+    }
+}
+
+/// Vertices written for one frame, valid until the next `end_frame` of the
+/// pool they came from.
+pub enum Stream<V: Copy> {
+    Empty,
+    Pooled {
+        range: Range<usize>,
+        frame: u64,
+    },
+    // Didn't fit in the pool, or was never meant to be streamed
+    Own {
+        buffer: Box<VertexBuffer<V>>,
+        len: usize,
+    },
+}
+
+/// What a pool streamed in its last finished frame.
+#[derive(Copy, Clone, Default)]
+pub struct StreamStats {
+    pub streamed_bytes: usize,
+    pub overflow_bytes: usize,
+    pub used_bytes: usize,
+    pub capacity_bytes: usize,
+}
+
+impl StreamStats {
+    pub fn add(self, other: StreamStats) -> StreamStats {
+        StreamStats {
+            streamed_bytes: self.streamed_bytes + other.streamed_bytes,
+            overflow_bytes: self.overflow_bytes + other.overflow_bytes,
+            used_bytes: self.used_bytes + other.used_bytes,
+            capacity_bytes: self.capacity_bytes + other.capacity_bytes,
+        }
+    }
+}
+
+/// Vertices rewritten every frame, in ranges of one large buffer rather than
+/// a buffer each. The buffer stays mapped where that's supported, and glium
+/// fences each range written, which the ring keeps clear of by not handing a
+/// range out again while its frame may still be drawing.
+pub struct StreamPool<V: Vertex> {
+    buffer: VertexBuffer<V>,
+    allocator: RingAllocator,
+    frame: u64,
+    current: StreamStats,
+    last: StreamStats,
+    scratch: Vec<V>,
+}
+
+impl<V: Vertex> StreamPool<V> {
+    pub fn new<F: Facade>(
+        facade: &F,
+        capacity: usize,
+    ) -> Result<StreamPool<V>, Box<dyn error::Error>> {
+        // Persistent mapping needs GL 4.4 or ARB_buffer_storage
+        let buffer = VertexBuffer::empty_persistent(facade, capacity)
+            .or_else(|_| VertexBuffer::empty_dynamic(facade, capacity))?;
+        Ok(StreamPool {
+            buffer,
+            allocator: RingAllocator::new(capacity),
+            frame: 0,
+            current: StreamStats::default(),
+            last: StreamStats::default(),
+            scratch: Vec::new(),
+        })
+    }
+
+    /// Copies `vertices` into the pool, or into a buffer of their own if
+    /// there's no room left.
+    pub fn write<F: Facade>(
+        &mut self,
+        facade: &F,
+        vertices: &[V],
+    ) -> Result<Stream<V>, Box<dyn error::Error>> {
+        if vertices.is_empty() {
+            return Ok(Stream::Empty);
+        }
+
+        let bytes = mem::size_of_val(vertices);
+        self.current.streamed_bytes += bytes;
+
+        match self.allocator.allocate(vertices.len()) {
+            Some(range) => {
+                self.buffer
+                    .slice(range.clone())
+                    .ok_or("stream range out of the pool")?
+                    .write(vertices);
+                Ok(Stream::Pooled {
+                    range,
+                    frame: self.frame,
+                })
+            }
+            None => {
+                self.current.overflow_bytes += bytes;
+                Ok(Stream::Own {
+                    buffer: Box::new(VertexBuffer::dynamic(facade, vertices)?),
+                    len: vertices.len(),
+                })
+            }
+        }
+    }
+
+    /// Collects vertices a few at a time for a single `write`.
+    pub fn writer(&mut self) -> StreamWriter<'_, V> {
+        self.scratch.clear();
+        StreamWriter { pool: self }
+    }
+
+    /// The vertices of `stream`, or `None` if there are none or they were
+    /// written before the last `end_frame`.
+    pub fn slice<'a>(&'a self, stream: &'a Stream<V>) -> Option<VertexBufferSlice<'a, V>> {
+        match stream {
+            Stream::Empty => None,
+            Stream::Pooled { range, frame } if *frame == self.frame => {
+                self.buffer.slice(range.clone())
+            }
+            Stream::Pooled { .. } => None,
+            Stream::Own { buffer, len } if *len > 0 => buffer.slice(0..*len),
+            Stream::Own { .. } => None,
+        }
+    }
+
+    /// Ends the frame. Every `Pooled` stream written before is stale after.
+    pub fn end_frame(&mut self) {
+        self.allocator.end_frame();
+        let size = mem::size_of::<V>();
+        self.last = StreamStats {
+            used_bytes: self.allocator.used() * size,
+            capacity_bytes: self.allocator.capacity() * size,
+            ..self.current
+        };
+        self.current = StreamStats::default();
+        self.frame += 1;
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.last
+    }
+}
+
+/// Gathers the vertices of one stream in the pool's scratch space, so
+/// building them doesn't allocate every frame.
+pub struct StreamWriter<'a, V: Vertex> {
+    pool: &'a mut StreamPool<V>,
+}
+
+impl<'a, V: Vertex> StreamWriter<'a, V> {
+    pub fn extend(&mut self, vertices: &[V]) {
+        self.pool.scratch.extend_from_slice(vertices);
+    }
+
+    pub fn finish<F: Facade>(self, facade: &F) -> Result<Stream<V>, Box<dyn error::Error>> {
+        let vertices = mem::take(&mut self.pool.scratch);
+        let stream = self.pool.write(facade, &vertices);
+        self.pool.scratch = vertices;
+        stream
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Ends a frame, then as many more as it takes to free it
+    fn retire(allocator: &mut RingAllocator) {
+        for _ in 0..=FRAMES_IN_FLIGHT {
+            allocator.end_frame();
+        }
+    }
+
+    #[test]
+    fn wraps_when_the_end_is_too_short() {
+        let mut allocator = RingAllocator::new(10);
+        assert_eq!(allocator.allocate(6), Some(0..6));
+        allocator.end_frame();
+        assert_eq!(allocator.allocate(3), Some(6..9));
+        for _ in 0..FRAMES_IN_FLIGHT {
+            allocator.end_frame();
+        }
+        // The first frame is free, the second is still in flight
+        assert_eq!(allocator.used(), 3);
+
+        // One left at the end, so it starts over at the front
+        assert_eq!(allocator.allocate(3), Some(0..3));
+        assert_eq!(allocator.used(), 3 + 1 + 3);
+        // And can't reach into the frame still in flight
+        assert_eq!(allocator.allocate(4), None);
+        assert_eq!(allocator.allocate(3), Some(3..6));
+    }
+
+    #[test]
+    fn none_when_full() {
+        let mut allocator = RingAllocator::new(10);
+        assert_eq!(allocator.allocate(11), None);
+        assert_eq!(allocator.allocate(0), None);
+        assert_eq!(allocator.allocate(10), Some(0..10));
+        assert_eq!(allocator.allocate(1), None);
+
+        // Still full while the frame is in flight
+        for _ in 0..FRAMES_IN_FLIGHT {
+            allocator.end_frame();
+            assert_eq!(allocator.allocate(1), None);
+        }
+        allocator.end_frame();
+        assert_eq!(allocator.allocate(1), Some(0..1));
+    }
+
+    #[test]
+    fn frees_the_skipped_end_with_its_frame() {
+        let mut allocator = RingAllocator::new(10);
+        allocator.allocate(8);
+        allocator.end_frame();
+        allocator.allocate(1);
+        retire(&mut allocator);
+        assert_eq!(allocator.used(), 0);
+
+        allocator.allocate(8);
+        allocator.end_frame();
+        allocator.allocate(1);
+        allocator.end_frame();
+        for _ in 0..FRAMES_IN_FLIGHT - 1 {
+            allocator.end_frame();
+        }
+        assert_eq!(allocator.used(), 1);
+
+        // Skips the one left after 8..9, which counts as used
+        assert_eq!(allocator.allocate(5), Some(0..5));
+        assert_eq!(allocator.used(), 1 + 1 + 5);
+        allocator.end_frame();
+        assert_eq!(allocator.used(), 1 + 5);
+
+        // Freed with the frame that wrapped, along with what it took
+        for _ in 0..FRAMES_IN_FLIGHT - 1 {
+            allocator.end_frame();
+        }
+        assert_eq!(allocator.used(), 1 + 5);
+        allocator.end_frame();
+        assert_eq!(allocator.used(), 0);
+    }
+
+    #[test]
+    fn starts_over_at_the_front_when_empty() {
+        let mut allocator = RingAllocator::new(10);
+        allocator.allocate(4);
+        allocator.allocate(3);
+        retire(&mut allocator);
+        assert_eq!(allocator.used(), 0);
+
+        // Would not fit after 7, but nothing is in use
+        assert_eq!(allocator.allocate(9), Some(0..9));
+    }
+}
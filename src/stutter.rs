@@ -0,0 +1,128 @@
+use std::collections::VecDeque;
+use std::error;
+use std::fs;
+use std::io::Write;
+use std::time::Instant;
+
+const MAX_ENTRIES: usize = 100;
+
+// Frame times the median is taken over, and how many there have to be
+// before anything is judged, so startup doesn't count as stutter
+const HISTORY: usize = 120;
+const MIN_HISTORY: usize = 30;
+
+// A frame taking longer than this many times the median is a stutter
+const STUTTER_FACTOR: f32 = 2.0;
+
+#[derive(Clone)]
+pub struct Span {
+    pub name: &'static str,
+    pub milliseconds: f32,
+}
+
+pub struct StutterEntry {
+    pub frame: u64,
+    pub milliseconds: f32,
+    pub median: f32,
+    pub spans: Vec<Span>,
+    pub gpu: Vec<Span>,
+}
+
+/// Logs the frames that took much longer than usual, together with the
+/// timed operations that ran in them, so a spike can be attributed after the
+/// fact.
+pub struct StutterDetector {
+    pub entries: VecDeque<StutterEntry>,
+    pub inject_stall: bool,
+    history: VecDeque<f32>,
+    spans: Vec<Span>,
+    frame: u64,
+}
+
+impl StutterDetector {
+    pub fn new() -> StutterDetector {
+        StutterDetector {
+            entries: VecDeque::new(),
+            inject_stall: false,
+            history: VecDeque::new(),
+            spans: Vec::new(),
+            frame: 0,
+        }
+    }
+
+    /// Records an operation of this frame that started at `start` and ends
+    /// now.
+    pub fn record(&mut self, name: &'static str, start: Instant) {
+        let duration = Instant::now().duration_since(start);
+        self.spans.push(Span {
+            name,
+            milliseconds: duration.as_secs() as f32 * 1000.0
+                + duration.subsec_nanos() as f32 * 1e-6,
+        });
+    }
+
+    pub fn median(&self) -> f32 {
+        let mut sorted: Vec<f32> = self.history.iter().cloned().collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        sorted.get(sorted.len() / 2).cloned().unwrap_or(0.0)
+    }
+
+    /// Judges the frame the recorded spans belong to, given how long it
+    /// took in seconds and the latest GPU pass times, and starts the next.
+    pub fn end_frame(&mut self, dt: f32, gpu: &[Span]) {
+        let milliseconds = dt * 1000.0;
+        let median = self.median();
+        let spans = std::mem::take(&mut self.spans);
+
+        if self.history.len() >= MIN_HISTORY && milliseconds > STUTTER_FACTOR * median {
+            let mut spans = spans;
+            spans.sort_by(|a, b| b.milliseconds.partial_cmp(&a.milliseconds).unwrap());
+            self.entries.push_front(StutterEntry {
+                frame: self.frame,
+                milliseconds,
+                median,
+                spans,
+                gpu: gpu.to_vec(),
+            });
+            self.entries.truncate(MAX_ENTRIES);
+        }
+
+        self.history.push_back(milliseconds);
+        while self.history.len() > HISTORY {
+            self.history.pop_front();
+        }
+        self.frame += 1;
+    }
+
+    pub fn export(&self, path: &str) -> Result<(), Box<dyn error::Error>> {
+        let spans_json = |spans: &[Span]| {
+            spans
+                .iter()
+                .map(|span| {
+                    format!(
+                        "{{\"name\": \"{}\", \"ms\": {:.3}}}",
+                        span.name, span.milliseconds
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut file = fs::File::create(path)?;
+        writeln!(file, "[")?;
+        for (i, entry) in self.entries.iter().enumerate() {
+            writeln!(
+                file,
+                "  {{\"frame\": {}, \"ms\": {:.3}, \"median_ms\": {:.3}, \"spans\": [{}], \"gpu\": [{}]}}{}",
+                entry.frame,
+                entry.milliseconds,
+                entry.median,
+                spans_json(&entry.spans),
+                spans_json(&entry.gpu),
+                if i + 1 < self.entries.len() { "," } else { "" }
+            )?;
+        }
+        writeln!(file, "]")?;
+        Ok(())
+    }
+}
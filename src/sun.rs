@@ -0,0 +1,141 @@
+use crate::resample::{self, Edges};
+use cgmath::{vec2, InnerSpace};
+use glium::{backend::Facade, implement_vertex, texture::texture2d::Texture2d, VertexBuffer};
+use image::RgbaImage;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::error;
+
+pub const SUN_RADIUS_KM: f64 = 695_700.0;
+
+// Side of the granulation texture and the number of convection cells in it
+const GRANULATION_SIZE: usize = 128;
+const GRANULATION_CELLS: usize = 48;
+
+#[derive(Copy, Clone)]
+pub struct SunVertex {
+    sun_corner: [f32; 2],
+}
+implement_vertex!(SunVertex, sun_corner);
+
+pub struct SunSettings {
+    pub enabled: bool,
+    // Brightness of the disc center, also how far the corona reaches
+    pub intensity: f32,
+    // Linear limb darkening, I(mu) = 1 - u (1 - mu)
+    pub limb_darkening: f32,
+    pub granulation: f32,
+    // Granulation cells churn over this many seconds
+    pub granulation_period: f32,
+    pub corona: bool,
+    // Corona reach in disc radii at an intensity of 1
+    pub corona_size: f32,
+    // The real disc is a few pixels across, this enlarges it
+    pub disc_scale: f32,
+}
+
+impl SunSettings {
+    pub fn new() -> SunSettings {
+        SunSettings {
+            enabled: true,
+            intensity: 1.0,
+            limb_darkening: 0.6,
+            granulation: 0.25,
+            granulation_period: 60.0,
+            corona: true,
+            corona_size: 2.0,
+            disc_scale: 1.0,
+        }
+    }
+}
+
+/// The sun's disc, a billboard at infinity in the sun's direction.
+pub struct Sun {
+    pub settings: SunSettings,
+    pub vertex_buffer: VertexBuffer<SunVertex>,
+    pub granulation: Texture2d,
+}
+
+impl Sun {
+    pub fn new<F: Facade>(facade: &F) -> Result<Sun, Box<dyn error::Error>> {
+        let corners = [[-1.0, -1.0], [1.0, -1.0], [-1.0, 1.0], [1.0, 1.0]];
+        let vertices: Vec<SunVertex> = corners
+            .iter()
+            .map(|&sun_corner| SunVertex { sun_corner })
+            .collect();
+
+        let data = generate_granulation(GRANULATION_SIZE, GRANULATION_CELLS, 7);
+        let image = RgbaImage::from_raw(GRANULATION_SIZE as u32, GRANULATION_SIZE as u32, data)
+            .ok_or("granulation data doesn't match its size")?;
+
+        Ok(Sun {
+            settings: SunSettings::new(),
+            vertex_buffer: VertexBuffer::new(facade, &vertices)?,
+            // Sampled repeating, so the mips wrap too
+            granulation: resample::texture_with_mip_chain(facade, &image, Edges::Wrap, false)?,
+        })
+    }
+
+    /// Angular radius in radians of the disc as drawn, seen from
+    /// `distance_km`.
+    pub fn angular_radius(&self, distance_km: f64) -> f32 {
+        let radius = (SUN_RADIUS_KM / distance_km.max(SUN_RADIUS_KM)).asin() as f32;
+        (radius * self.settings.disc_scale).min(0.5)
+    }
+
+    /// Extent of the billboard in disc radii, the corona reaches further the
+    /// brighter the sun.
+    pub fn extent(&self) -> f32 {
+        if self.settings.corona {
+            1.0 + self.settings.corona_size * self.settings.intensity.sqrt()
+        } else {
+            1.0
+        }
+    }
+}
+
+/// Wrapping cellular noise, bright cell centers and dark lanes between them,
+/// in the red channel. A second layer with other cells is in green so the
+/// shader can blend between two patterns over time.
+fn generate_granulation(size: usize, cells: usize, seed: u64) -> Vec<u8> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut layers = Vec::new();
+
+    for _ in 0..2 {
+        let points: Vec<_> = (0..cells)
+            .map(|_| vec2(rng.gen::<f32>(), rng.gen::<f32>()))
+            .collect();
+        // Typical distance between neighbouring cell centers
+        let spacing = 1.0 / (cells as f32).sqrt();
+
+        let layer: Vec<f32> = (0..size * size)
+            .map(|i| {
+                let p = vec2(
+                    (i % size) as f32 / size as f32,
+                    (i / size) as f32 / size as f32,
+                );
+                let nearest = points
+                    .iter()
+                    .map(|point| {
+                        let mut d = p - point;
+                        d.x -= d.x.round();
+                        d.y -= d.y.round();
+                        d.magnitude()
+                    })
+                    .fold(f32::MAX, f32::min);
+                (1.0 - nearest / spacing).clamp(0.0, 1.0)
+            })
+            .collect();
+        layers.push(layer);
+    }
+
+    (0..size * size)
+        .flat_map(|i| {
+            [
+                (layers[0][i] * 255.0) as u8,
+                (layers[1][i] * 255.0) as u8,
+                0,
+                255,
+            ]
+        })
+        .collect()
+}
@@ -0,0 +1,139 @@
+use crate::freefly::FlyKeys;
+use crate::noise;
+use cgmath::{vec3, InnerSpace, Matrix4, Point3, Vector3};
+
+// Degrees of view rotation per pixel of mouse drag
+const DRAG_SPEED: f32 = 0.2;
+// Walking speed multiplier while shift is held
+const SPRINT: f32 = 5.0;
+
+/// A camera standing on the planet at a latitude/longitude, looking out at
+/// the sky. Everything is in the planet's rotating frame, so spinning the
+/// planet wheels the sky overhead.
+pub struct SurfaceView {
+    pub enabled: bool,
+    pub latitude: f32,
+    pub longitude: f32,
+    pub heading: f32,
+    pub pitch: f32,
+    pub eye_height_km: f32,
+    pub walk_speed_km: f32,
+}
+
+impl SurfaceView {
+    pub fn new() -> SurfaceView {
+        SurfaceView {
+            enabled: false,
+            latitude: 45.0,
+            longitude: 0.0,
+            heading: 0.0,
+            pitch: 20.0,
+            eye_height_km: 2.0,
+            walk_speed_km: 5.0,
+        }
+    }
+
+    /// Unit surface normal below the eye, in planet-local space.
+    pub fn up(&self) -> Vector3<f32> {
+        let (lat, lon) = (self.latitude.to_radians(), self.longitude.to_radians());
+        vec3(lat.cos() * lon.sin(), lat.sin(), lat.cos() * lon.cos())
+    }
+
+    /// Eye position in planet-local space, `eye_height` above the displaced
+    /// terrain.
+    pub fn eye(&self, radius: f32, eye_height: f32) -> Vector3<f32> {
+        let up = self.up();
+        up * (noise::terrain_altitude([up.x, up.y, up.z], radius) + eye_height)
+    }
+
+    /// Unit east and north along the surface below the eye. Taken from the
+    /// longitude, so they stay defined right at the poles.
+    fn east_north(&self) -> (Vector3<f32>, Vector3<f32>) {
+        let lon = self.longitude.to_radians();
+        let east = vec3(lon.cos(), 0.0, -lon.sin());
+        (east, self.up().cross(east))
+    }
+
+    /// Unit direction along the surface the view is turned to, in
+    /// planet-local space.
+    pub fn heading_dir(&self) -> Vector3<f32> {
+        let (east, north) = self.east_north();
+        let heading = self.heading.to_radians();
+        north * heading.cos() + east * heading.sin()
+    }
+
+    /// Walks along the planet at the eye height with the held W/A/S/D keys,
+    /// over `dt` seconds on a planet of `radius_km`.
+    ///
+    /// The step is taken in the tangent frame of the eye, carrying the view
+    /// direction along the great circle walked, and only then turned back
+    /// into latitude, longitude and heading. Walking over a pole turns the
+    /// heading from north to south without the view flipping.
+    pub fn walk(&mut self, keys: &FlyKeys, dt: f32, radius_km: f32) {
+        let axis = |positive: bool, negative: bool| match (positive, negative) {
+            (true, false) => 1.0,
+            (false, true) => -1.0,
+            _ => 0.0,
+        };
+        let up = self.up();
+        let forward = self.heading_dir();
+        let right = forward.cross(up);
+        let direction =
+            forward * axis(keys.forward, keys.back) + right * axis(keys.right, keys.left);
+        if direction.magnitude2() == 0.0 {
+            return;
+        }
+        let direction = direction.normalize();
+
+        let speed = if keys.sprint {
+            self.walk_speed_km * SPRINT
+        } else {
+            self.walk_speed_km
+        };
+        let angle = speed * dt / radius_km.max(1.0);
+        let (sin, cos) = angle.sin_cos();
+
+        // The part of the view along the step turns with the normal, the
+        // part across it stays as it is
+        let across = up.cross(direction);
+        let new_up = (up * cos + direction * sin).normalize();
+        let new_direction = direction * cos - up * sin;
+        let new_forward = new_direction * forward.dot(direction) + across * forward.dot(across);
+
+        self.latitude = new_up.y.clamp(-1.0, 1.0).asin().to_degrees();
+        // Right on a pole any longitude will do, the heading follows it
+        if new_up.x != 0.0 || new_up.z != 0.0 {
+            self.longitude = new_up.x.atan2(new_up.z).to_degrees();
+        }
+        let (east, north) = self.east_north();
+        self.heading = new_forward
+            .dot(east)
+            .atan2(new_forward.dot(north))
+            .to_degrees();
+    }
+
+    /// World-to-eye transform for a planet placed by `planet_matrix`.
+    pub fn view_matrix(
+        &self,
+        planet_matrix: Matrix4<f32>,
+        radius: f32,
+        eye_height: f32,
+    ) -> Matrix4<f32> {
+        let up = self.up();
+        let pitch = self.pitch.to_radians();
+        let forward = self.heading_dir() * pitch.cos() + up * pitch.sin();
+
+        let eye = planet_matrix * self.eye(radius, eye_height).extend(1.0);
+        let forward = (planet_matrix * forward.extend(0.0)).truncate();
+        let up = (planet_matrix * up.extend(0.0)).truncate();
+
+        Matrix4::look_at_dir(Point3::new(eye.x, eye.y, eye.z), forward, up)
+    }
+
+    /// Pans the view like a planetarium, dragging the sky along with the
+    /// cursor.
+    pub fn drag(&mut self, dx: f32, dy: f32) {
+        self.heading = (self.heading - dx * DRAG_SPEED) % 360.0;
+        self.pitch = (self.pitch + dy * DRAG_SPEED).clamp(-80.0, 89.0);
+    }
+}
@@ -0,0 +1,289 @@
+use crate::capabilities;
+use crate::mesh::PlanetMesh;
+use crate::units::PRESETS;
+use crate::{render_scene, Shadowmap, State, PLANET_RADIUS};
+use cgmath::{vec3, vec4, InnerSpace, Matrix4, SquareMatrix, Vector3};
+use glium::{
+    framebuffer::{DepthRenderBuffer, SimpleFrameBuffer},
+    texture::{
+        texture2d::Texture2d, DepthFormat, MipmapsOption, RawImage2d, UncompressedFloatFormat,
+    },
+};
+use std::error;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+// A healthy sun-facing hemisphere is never close to black, and no single
+// frame of the sweep should stray far from the typical brightness.
+const MIN_BRIGHTNESS: f32 = 0.05;
+const MAX_DEVIATION: f32 = 0.5;
+
+// Frames showing less of the lit hemisphere than this are not judged.
+const MIN_COVERAGE: f32 = 0.01;
+
+pub struct SweepConfig {
+    pub frames: u32,
+    pub out: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SweepConfig {
+    pub fn from_args(args: &[String]) -> Result<SweepConfig, Box<dyn error::Error>> {
+        let mut config = SweepConfig {
+            frames: 360,
+            out: PathBuf::from("sweep"),
+            width: 1280,
+            height: 720,
+        };
+
+        for arg in args.iter().take_while(|arg| !arg.starts_with("--")) {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("frames"), Some(value)) => config.frames = value.parse()?,
+                (Some("out"), Some(value)) => config.out = PathBuf::from(value),
+                (Some("width"), Some(value)) => config.width = value.parse()?,
+                (Some("height"), Some(value)) => config.height = value.parse()?,
+                _ => return Err(format!("unknown sun sweep option '{}'", arg).into()),
+            }
+        }
+
+        if config.frames == 0 || config.width == 0 || config.height == 0 {
+            return Err("sun sweep frames, width and height must be non-zero".into());
+        }
+
+        Ok(config)
+    }
+}
+
+/// Renders one frame per step of a full sun revolution and checks that the
+/// lit side of the planet stays plausibly bright throughout. The sweep runs
+/// once for a body with an atmosphere and once for one without.
+pub fn run(config: &SweepConfig, max_gl: (u8, u8)) -> Result<(), Box<dyn error::Error>> {
+    let facade = capabilities::create_headless(config.width, config.height, max_gl)?;
+
+    let mut p = State::new(&facade, max_gl, PlanetMesh::UvSphere)?;
+    p.vegetation.wait(&facade)?;
+    p.wind.update(&facade)?;
+
+    let shadowmap = Shadowmap::new(&facade, p.shadowmap_size(config.width, config.height))?;
+    let mut shadowmap_framebuffer = shadowmap.framebuffer(&facade)?;
+
+    let color_texture = Texture2d::empty_with_format(
+        &facade,
+        UncompressedFloatFormat::U8U8U8U8,
+        MipmapsOption::NoMipmap,
+        config.width,
+        config.height,
+    )?;
+    let depthbuffer =
+        DepthRenderBuffer::new(&facade, DepthFormat::F32, config.width, config.height)?;
+    let mut target = SimpleFrameBuffer::with_depth_buffer(&facade, &color_texture, &depthbuffer)?;
+
+    let bodies = [
+        PRESETS
+            .iter()
+            .position(|preset| preset.atmosphere.is_some()),
+        PRESETS
+            .iter()
+            .position(|preset| preset.atmosphere.is_none()),
+    ];
+
+    let mut failures = Vec::new();
+    for &index in bodies.iter().flatten() {
+        p.apply_preset(index);
+        p.wind.update(&facade)?;
+
+        let out = config.out.join(PRESETS[index].palette);
+        let failed = sweep_body(
+            config,
+            &out,
+            &mut p,
+            &mut target,
+            &color_texture,
+            &mut shadowmap_framebuffer,
+            &shadowmap.texture,
+        )?;
+
+        if !failed.is_empty() {
+            failures.push(format!("{}: {:?}", PRESETS[index].name, failed));
+        }
+    }
+
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "sun-facing brightness out of bounds at sun angles {}",
+            failures.join(", ")
+        )
+        .into())
+    }
+}
+
+/// Sweeps the sun around the currently applied preset, writing the frames
+/// and a brightness report to `out`. Returns the sun angles that failed.
+fn sweep_body(
+    config: &SweepConfig,
+    out: &Path,
+    p: &mut State,
+    target: &mut SimpleFrameBuffer,
+    color_texture: &Texture2d,
+    shadowmap_framebuffer: &mut SimpleFrameBuffer,
+    shadowmap_texture: &Texture2d,
+) -> Result<Vec<f32>, Box<dyn error::Error>> {
+    fs::create_dir_all(out)?;
+
+    let aspect = config.width as f32 / config.height as f32;
+    let mut results = Vec::new();
+
+    for frame in 0..config.frames {
+        p.view.sun_angle = -180.0 + 360.0 * frame as f32 / config.frames as f32;
+        p.update_sun_pos();
+
+        render_scene(
+            target,
+            p,
+            shadowmap_framebuffer,
+            shadowmap_texture,
+            None,
+            aspect,
+            0.0,
+        )?;
+
+        let image: RawImage2d<u8> = color_texture.read();
+
+        let stride = 4 * config.width as usize;
+        let flipped: Vec<u8> = image
+            .data
+            .chunks(stride)
+            .rev()
+            .flat_map(|row| row.iter().cloned())
+            .collect();
+
+        image::save_buffer(
+            out.join(format!("sun_{:04}.png", frame)),
+            &flipped,
+            config.width,
+            config.height,
+            image::ColorType::Rgba8,
+        )?;
+
+        let brightness = sun_facing_brightness(
+            &image.data,
+            p.camera().projection_matrix(aspect),
+            config.width,
+            config.height,
+            p.camera_relative(p.planet_pos),
+            p.camera_relative(p.sun_pos),
+        );
+        results.push((p.view.sun_angle, brightness));
+    }
+
+    let judged: Vec<f32> = results.iter().filter_map(|&(_, b)| b).collect();
+    let median = {
+        let mut sorted = judged.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        sorted.get(sorted.len() / 2).cloned().unwrap_or(0.0)
+    };
+
+    let mut report = fs::File::create(out.join("brightness.csv"))?;
+    writeln!(report, "sun_angle,brightness,ok")?;
+
+    let mut failures = Vec::new();
+    for &(angle, brightness) in &results {
+        let ok = match brightness {
+            Some(b) => b >= MIN_BRIGHTNESS && (b - median).abs() <= MAX_DEVIATION * median,
+            None => true,
+        };
+
+        match brightness {
+            Some(b) => writeln!(report, "{:.2},{:.4},{}", angle, b, ok)?,
+            None => writeln!(report, "{:.2},,{}", angle, ok)?,
+        }
+
+        if !ok {
+            failures.push(angle);
+        }
+    }
+
+    println!(
+        "Sun sweep of {}: {} frames, {} judged, median brightness {:.3}",
+        PRESETS[p.preset as usize].name,
+        results.len(),
+        judged.len(),
+        median
+    );
+
+    Ok(failures)
+}
+
+/// Average luminance of the visible pixels whose planet surface faces the
+/// sun, or `None` if too little of that hemisphere is in view.
+fn sun_facing_brightness(
+    data: &[u8],
+    projection: Matrix4<f32>,
+    width: u32,
+    height: u32,
+    planet_pos: Vector3<f32>,
+    sun_pos: Vector3<f32>,
+) -> Option<f32> {
+    let inverse_projection = projection.invert()?;
+
+    let mut sum = 0.0;
+    let mut count = 0;
+
+    // Rows come back from GL bottom to top, matching NDC y.
+    for row in 0..height {
+        for col in 0..width {
+            let x = 2.0 * (col as f32 + 0.5) / width as f32 - 1.0;
+            let y = 2.0 * (row as f32 + 0.5) / height as f32 - 1.0;
+
+            let far = inverse_projection * vec4(x, y, 1.0, 1.0);
+            let dir = vec3(far.x, far.y, far.z).normalize();
+
+            let hit = match intersect_planet(dir, planet_pos) {
+                Some(hit) => hit,
+                None => continue,
+            };
+
+            let normal = (hit - planet_pos) / PLANET_RADIUS;
+            if normal.dot((sun_pos - hit).normalize()) < 0.5 {
+                continue;
+            }
+
+            let i = 4 * (row * width + col) as usize;
+            sum += (0.2126 * data[i] as f32
+                + 0.7152 * data[i + 1] as f32
+                + 0.0722 * data[i + 2] as f32)
+                / 255.0;
+            count += 1;
+        }
+    }
+
+    if (count as f32) < MIN_COVERAGE * (width * height) as f32 {
+        None
+    } else {
+        Some(sum / count as f32)
+    }
+}
+
+/// Nearest intersection of a ray from the camera at the origin with the
+/// undisplaced planet sphere.
+fn intersect_planet(dir: Vector3<f32>, planet_pos: Vector3<f32>) -> Option<Vector3<f32>> {
+    let b = dir.dot(planet_pos);
+    let c = planet_pos.magnitude2() - PLANET_RADIUS * PLANET_RADIUS;
+    let discriminant = b * b - c;
+
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let t = b - discriminant.sqrt();
+    if t > 0.0 {
+        Some(dir * t)
+    } else {
+        None
+    }
+}
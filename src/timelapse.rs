@@ -0,0 +1,347 @@
+use crate::capabilities;
+use crate::capture;
+use crate::mesh::PlanetMesh;
+use crate::screenshot::{ScreenshotWriter, Written};
+use crate::units::PRESETS;
+use crate::State;
+use glium::backend::Facade;
+use std::error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Width of one frame in the contact sheet
+const TILE_WIDTH: u32 = 320;
+
+const VIDEO_FPS: u32 = 24;
+
+pub struct TimeLapseSettings {
+    // Sim time between frames
+    pub interval_minutes: f32,
+    pub count: i32,
+    // Sim minutes that pass per real second while running
+    pub speed: f32,
+    pub contact_sheet: bool,
+    // Pipe the frames to ffmpeg for a video instead of writing PNGs
+    pub ffmpeg: bool,
+}
+
+impl TimeLapseSettings {
+    pub fn new() -> TimeLapseSettings {
+        TimeLapseSettings {
+            interval_minutes: 30.0,
+            count: 48,
+            speed: 120.0,
+            contact_sheet: true,
+            ffmpeg: false,
+        }
+    }
+}
+
+/// Stills of the day/night cycle at fixed steps of sim time. The sun is
+/// driven by a sim clock that runs `speed` times faster than real time, and
+/// the clock stops on every tick until its frame is taken, so a slow frame
+/// never skips one.
+pub struct TimeLapse {
+    pub settings: TimeLapseSettings,
+    pub dir: Option<PathBuf>,
+    pub error: Option<String>,
+    running: bool,
+    sim_seconds: f64,
+    start_angle: f32,
+    captured: u32,
+    frames: Vec<PathBuf>,
+    written: usize,
+    expected: usize,
+}
+
+impl TimeLapse {
+    pub fn new() -> TimeLapse {
+        TimeLapse {
+            settings: TimeLapseSettings::new(),
+            dir: None,
+            error: None,
+            running: false,
+            sim_seconds: 0.0,
+            start_angle: 0.0,
+            captured: 0,
+            frames: Vec::new(),
+            written: 0,
+            expected: 0,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    /// Frames written so far, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        if self.expected == 0 {
+            0.0
+        } else {
+            self.written as f32 / self.expected as f32
+        }
+    }
+
+    pub fn sim_seconds(&self) -> f64 {
+        self.sim_seconds
+    }
+
+    /// Starts from the current sun position, writing into a new folder under
+    /// `out`. Frames are `size`, which a video can't change halfway.
+    pub fn start(
+        &mut self,
+        writer: &mut ScreenshotWriter,
+        out: &Path,
+        sun_angle: f32,
+        size: (u32, u32),
+    ) -> Result<(), Box<dyn error::Error>> {
+        if self.settings.count <= 0 || self.settings.interval_minutes <= 0.0 {
+            return Err("time-lapse count and interval must be positive".into());
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let dir = out.join(format!("timelapse_{}", timestamp));
+        fs::create_dir_all(&dir)?;
+        if self.settings.ffmpeg {
+            writer.start_video(dir.join("timelapse.mp4"), size.0, size.1, VIDEO_FPS)?;
+        }
+
+        self.dir = Some(dir);
+        self.error = None;
+        self.running = true;
+        self.sim_seconds = 0.0;
+        self.start_angle = sun_angle;
+        self.captured = 0;
+        self.frames.clear();
+        self.written = 0;
+        // A video reports its frames and then ffmpeg finishing, the contact
+        // sheet needs PNGs
+        let last = self.settings.ffmpeg || self.settings.contact_sheet;
+        self.expected = self.settings.count as usize + last as usize;
+
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    fn all_queued(&self) -> bool {
+        self.captured >= self.settings.count as u32
+    }
+
+    fn interval_seconds(&self) -> f64 {
+        f64::from(self.settings.interval_minutes) * 60.0
+    }
+
+    /// Advances the sim clock by `dt` real seconds, up to the next tick that
+    /// hasn't been captured. Returns the frame due at this time, if any.
+    fn step(&mut self, dt: f32) -> Option<u32> {
+        if !self.running || self.all_queued() {
+            return None;
+        }
+
+        let tick = f64::from(self.captured) * self.interval_seconds();
+        self.sim_seconds =
+            (self.sim_seconds + f64::from(dt * self.settings.speed) * 60.0).min(tick);
+        if self.sim_seconds >= tick {
+            Some(self.captured)
+        } else {
+            None
+        }
+    }
+
+    /// Where the sun is after `sim_seconds` of a day `day_length_hours` long,
+    /// in the -180 to 180 range of the sun angle.
+    fn sun_angle(&self, day_length_hours: f64) -> f32 {
+        let turns = self.sim_seconds / (day_length_hours * 3600.0);
+        let angle = f64::from(self.start_angle) + 360.0 * turns.fract();
+        ((angle + 180.0).rem_euclid(360.0) - 180.0) as f32
+    }
+
+    fn frame_path(&self, frame: u32) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("frame_{:04}.png", frame)))
+    }
+
+    /// Queues the frame for writing, and the contact sheet after the last
+    /// one or the end of the video.
+    fn queue(
+        &mut self,
+        writer: &mut ScreenshotWriter,
+        frame: u32,
+        pixels: Vec<u8>,
+        size: (u32, u32),
+    ) {
+        if self.settings.ffmpeg {
+            writer.video_frame(pixels, size.0, size.1);
+            self.captured += 1;
+            if self.all_queued() {
+                writer.finish_video();
+            }
+            return;
+        }
+
+        let path = match self.frame_path(frame) {
+            Some(path) => path,
+            None => return,
+        };
+        writer.save(path.clone(), pixels, size.0, size.1);
+        self.frames.push(path);
+        self.captured += 1;
+
+        if self.all_queued() && self.settings.contact_sheet {
+            if let Some(ref dir) = self.dir {
+                writer.contact_sheet(
+                    dir.join("contact_sheet.png"),
+                    self.frames.clone(),
+                    TILE_WIDTH,
+                );
+            }
+        }
+    }
+
+    pub fn owns(&self, path: &Path) -> bool {
+        self.dir.as_ref().is_some_and(|dir| path.starts_with(dir))
+    }
+
+    /// Takes note of a frame the writer is done with. The first failed
+    /// write stops the run, the disk is likely full or gone.
+    pub fn written(&mut self, written: &Written) {
+        if !self.running {
+            return;
+        }
+
+        if let Some(ref error) = written.error {
+            self.error = Some(format!("{}: {}", written.path.display(), error));
+            self.running = false;
+            return;
+        }
+
+        self.written += 1;
+        if self.written >= self.expected {
+            self.running = false;
+        }
+    }
+}
+
+/// Advances the time-lapse clock and places the sun where it has it.
+/// Returns the frame to capture this frame, if one is due.
+pub fn pose(p: &mut State, dt: f32) -> Option<u32> {
+    if !p.time_lapse.is_running() {
+        return None;
+    }
+
+    let due = p.time_lapse.step(dt);
+    p.view.sun_angle = p.time_lapse.sun_angle(p.body.day_length_hours);
+    p.update_sun_pos();
+    due
+}
+
+/// Renders the frame without the UI and hands it to the screenshot writer.
+pub fn capture<F: Facade>(
+    facade: &F,
+    p: &mut State,
+    frame: u32,
+    width: u32,
+    height: u32,
+    time: f32,
+) -> Result<(), Box<dyn error::Error>> {
+    let pixels = capture::render_image(facade, p, width, height, time)?;
+    p.time_lapse
+        .queue(&mut p.screenshots, frame, pixels, (width, height));
+    Ok(())
+}
+
+pub struct TimeLapseConfig {
+    pub settings: TimeLapseSettings,
+    pub out: PathBuf,
+    pub preset: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl TimeLapseConfig {
+    pub fn from_args(args: &[String]) -> Result<TimeLapseConfig, Box<dyn error::Error>> {
+        let mut config = TimeLapseConfig {
+            settings: TimeLapseSettings::new(),
+            out: PathBuf::from("timelapses"),
+            preset: 0,
+            width: 1280,
+            height: 720,
+        };
+
+        for arg in args.iter().take_while(|arg| !arg.starts_with("--")) {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some("interval"), Some(value)) => {
+                    config.settings.interval_minutes = value.parse()?
+                }
+                (Some("count"), Some(value)) => config.settings.count = value.parse()?,
+                (Some("sheet"), Some(value)) => config.settings.contact_sheet = value.parse()?,
+                (Some("out"), Some(value)) => config.out = PathBuf::from(value),
+                (Some("preset"), Some(value)) => config.preset = value.parse()?,
+                (Some("width"), Some(value)) => config.width = value.parse()?,
+                (Some("height"), Some(value)) => config.height = value.parse()?,
+                _ => return Err(format!("unknown time-lapse option '{}'", arg).into()),
+            }
+        }
+
+        if config.width == 0 || config.height == 0 {
+            return Err("time-lapse width and height must be non-zero".into());
+        }
+        if config.preset >= PRESETS.len() {
+            return Err(format!("unknown preset {}", config.preset).into());
+        }
+
+        Ok(config)
+    }
+}
+
+/// Renders a time-lapse headless, stepping the sim clock straight from one
+/// tick to the next.
+pub fn run(config: TimeLapseConfig, max_gl: (u8, u8)) -> Result<(), Box<dyn error::Error>> {
+    let facade = capabilities::create_headless(config.width, config.height, max_gl)?;
+
+    let mut p = State::new(&facade, max_gl, PlanetMesh::UvSphere)?;
+    p.apply_preset(config.preset);
+    p.vegetation.wait(&facade)?;
+    p.wind.update(&facade)?;
+
+    p.time_lapse.settings = config.settings;
+    p.time_lapse.start(
+        &mut p.screenshots,
+        &config.out,
+        p.view.sun_angle,
+        (config.width, config.height),
+    )?;
+
+    while p.time_lapse.is_running() {
+        if let Some(frame) = pose(&mut p, f32::INFINITY) {
+            capture(&facade, &mut p, frame, config.width, config.height, 0.0)?;
+        }
+
+        // Only wait on the writer once everything is queued, the frames
+        // before that are encoded while the next ones render
+        let written = if p.time_lapse.all_queued() {
+            p.screenshots.wait()
+        } else {
+            p.screenshots.poll()
+        };
+        for written in &written {
+            p.time_lapse.written(written);
+        }
+    }
+
+    if let Some(error) = p.time_lapse.error {
+        return Err(format!("time-lapse stopped, writing failed: {}", error).into());
+    }
+    if let Some(ref dir) = p.time_lapse.dir {
+        println!("Time-lapse saved to {}", dir.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,324 @@
+pub const KM_PER_AU: f64 = 149_597_870.7;
+pub const KM_PER_LIGHT_YEAR: f64 = 9_460_730_472_580.8;
+pub const HOURS_PER_DAY: f64 = 24.0;
+
+// Physical units are converted here and nowhere else, render units only
+// through a `SceneScale`
+
+pub fn au_to_km(au: f64) -> f64 {
+    au * KM_PER_AU
+}
+
+pub fn km_to_au(km: f64) -> f64 {
+    km / KM_PER_AU
+}
+
+pub fn days_to_hours(days: f64) -> f64 {
+    days * HOURS_PER_DAY
+}
+
+pub fn hours_to_days(hours: f64) -> f64 {
+    hours / HOURS_PER_DAY
+}
+
+/// Sunlight `distance_km` from the sun as a fraction of what it is at 1 AU,
+/// falling off with the square of the distance.
+pub fn irradiance(distance_km: f64) -> f64 {
+    if distance_km <= 0.0 {
+        return 1.0;
+    }
+    (KM_PER_AU / distance_km).powi(2)
+}
+
+/// The gas envelope of a body, which carries its cloud layer and slows
+/// anything falling through it.
+#[derive(Copy, Clone)]
+pub struct Atmosphere {
+    pub height_km: f64,
+    pub scale_height_km: f64,
+    // Thickness of the cloud layer, 1 being the original look
+    pub density: f32,
+    // Multiplies the palette's atmosphere color, for dust and haze
+    pub tint: [f32; 3],
+}
+
+impl Atmosphere {
+    /// Density relative to the surface at `altitude_km`, exponential with
+    /// the scale height and zero above the top.
+    pub fn density_at(&self, altitude_km: f64) -> f32 {
+        if altitude_km >= self.height_km {
+            return 0.0;
+        }
+        (-altitude_km.max(0.0) / self.scale_height_km).exp() as f32
+    }
+}
+
+/// A scene described in physical units. Everything the renderer needs is
+/// derived from these through a `SceneScale`.
+#[derive(Copy, Clone)]
+pub struct Preset {
+    pub name: &'static str,
+    pub planet_radius_km: f64,
+    pub atmosphere: Option<Atmosphere>,
+    pub sun_distance_au: f64,
+    // Length of a solar day, sunrise to sunrise
+    pub day_length_hours: f64,
+    // One trip around the sun
+    pub year_length_hours: f64,
+    // Angle between the equator and the orbit, in degrees
+    pub axial_tilt: f64,
+    // In m/s^2, which with the radius gives the pull on orbits
+    pub surface_gravity: f64,
+    pub camera_altitude_km: f64,
+    pub wind_profile: &'static [[f32; 2]],
+    pub palette: &'static str,
+    // Stamped into the planet mesh
+    pub craters: i32,
+    // How much shorter the polar radius is than the equatorial one, as a
+    // fraction of it
+    pub flattening: f32,
+}
+
+// Zonal wind in m/s against latitude, easterly trades near the equator,
+// westerlies at mid latitudes and weak polar easterlies
+const EARTH_WIND: [[f32; 2]; 11] = [
+    [-90.0, 0.0],
+    [-70.0, -4.0],
+    [-45.0, 15.0],
+    [-30.0, 5.0],
+    [-15.0, -7.0],
+    [0.0, -4.0],
+    [15.0, -7.0],
+    [30.0, 5.0],
+    [45.0, 15.0],
+    [70.0, -4.0],
+    [90.0, 0.0],
+];
+
+// Weak thermal tides, the dust storms are not modelled
+const MARS_WIND: [[f32; 2]; 5] = [
+    [-90.0, 0.0],
+    [-40.0, 8.0],
+    [0.0, -3.0],
+    [40.0, 8.0],
+    [90.0, 0.0],
+];
+
+// Superrotating, prograde at every latitude
+const TITAN_WIND: [[f32; 2]; 5] = [
+    [-90.0, 0.0],
+    [-45.0, 20.0],
+    [0.0, 30.0],
+    [45.0, 20.0],
+    [90.0, 0.0],
+];
+
+const NO_WIND: [[f32; 2]; 2] = [[-90.0, 0.0], [90.0, 0.0]];
+
+pub const PRESETS: [Preset; 5] = [
+    // The original look: a thick cloud shell and the camera about four and a
+    // half planet radii from the center.
+    Preset {
+        name: "Default",
+        planet_radius_km: 6371.0,
+        atmosphere: Some(Atmosphere {
+            height_km: 1274.2,
+            scale_height_km: 106.2,
+            density: 1.0,
+            tint: [1.0, 1.0, 1.0],
+        }),
+        sun_distance_au: 0.655,
+        day_length_hours: 24.0,
+        year_length_hours: 8766.0,
+        axial_tilt: 23.44,
+        surface_gravity: 9.81,
+        camera_altitude_km: 23_034.0,
+        wind_profile: &EARTH_WIND,
+        palette: "temperate",
+        craters: 0,
+        flattening: 0.0,
+    },
+    Preset {
+        name: "Earth (real scale)",
+        planet_radius_km: 6371.0,
+        atmosphere: Some(Atmosphere {
+            height_km: 100.0,
+            scale_height_km: 8.5,
+            density: 1.0,
+            tint: [1.0, 1.0, 1.0],
+        }),
+        sun_distance_au: 1.0,
+        day_length_hours: 24.0,
+        year_length_hours: 8766.0,
+        axial_tilt: 23.44,
+        surface_gravity: 9.81,
+        camera_altitude_km: 35_786.0,
+        wind_profile: &EARTH_WIND,
+        palette: "temperate",
+        craters: 0,
+        flattening: 0.003_35,
+    },
+    // A thin, dusty atmosphere with sparse clouds
+    Preset {
+        name: "Mars-like",
+        planet_radius_km: 3389.5,
+        atmosphere: Some(Atmosphere {
+            height_km: 120.0,
+            scale_height_km: 11.1,
+            density: 0.35,
+            tint: [1.0, 0.72, 0.5],
+        }),
+        sun_distance_au: 1.524,
+        day_length_hours: 24.66,
+        year_length_hours: 16_488.0,
+        axial_tilt: 25.19,
+        surface_gravity: 3.71,
+        camera_altitude_km: 17_000.0,
+        wind_profile: &MARS_WIND,
+        palette: "arid",
+        craters: 0,
+        flattening: 0.005_89,
+    },
+    // A deep orange haze that hides most of the surface
+    Preset {
+        name: "Titan-like",
+        planet_radius_km: 2574.7,
+        atmosphere: Some(Atmosphere {
+            height_km: 600.0,
+            scale_height_km: 40.0,
+            density: 1.8,
+            tint: [1.0, 0.68, 0.32],
+        }),
+        sun_distance_au: 9.58,
+        day_length_hours: 382.7,
+        year_length_hours: 258_216.0,
+        axial_tilt: 26.73,
+        surface_gravity: 1.35,
+        camera_altitude_km: 10_000.0,
+        wind_profile: &TITAN_WIND,
+        palette: "titan",
+        craters: 0,
+        flattening: 0.0,
+    },
+    Preset {
+        name: "Airless moon",
+        planet_radius_km: 1737.4,
+        atmosphere: None,
+        sun_distance_au: 1.0,
+        day_length_hours: 708.7,
+        year_length_hours: 8766.0,
+        axial_tilt: 1.54,
+        surface_gravity: 1.62,
+        camera_altitude_km: 8_000.0,
+        wind_profile: &NO_WIND,
+        palette: "regolith",
+        craters: 600,
+        flattening: 0.001_2,
+    },
+];
+
+impl Preset {
+    /// Gravitational parameter in km^3/s^2.
+    pub fn mu(&self) -> f64 {
+        self.surface_gravity / 1000.0 * self.planet_radius_km * self.planet_radius_km
+    }
+}
+
+/// Conversion between kilometers and render units.
+#[derive(Copy, Clone)]
+pub struct SceneScale {
+    pub render_units_per_km: f64,
+}
+
+impl SceneScale {
+    /// The planet mesh and the terrain noise are authored for a fixed render
+    /// radius, so the scale is picked to map the preset's planet onto it.
+    pub fn for_preset(preset: &Preset, planet_render_radius: f32) -> SceneScale {
+        SceneScale {
+            render_units_per_km: f64::from(planet_render_radius) / preset.planet_radius_km,
+        }
+    }
+
+    pub fn to_render(self, km: f64) -> f64 {
+        km * self.render_units_per_km
+    }
+
+    pub fn to_km(self, render_units: f64) -> f64 {
+        render_units / self.render_units_per_km
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn relative(a: f64, b: f64) -> f64 {
+        ((a - b) / b).abs()
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        assert_eq!(au_to_km(1.0), KM_PER_AU);
+        assert_eq!(days_to_hours(2.0), 48.0);
+        for &value in &[0.0, 0.387, 1.0, 9.58, 30.1, 1e-6] {
+            assert!((km_to_au(au_to_km(value)) - value).abs() <= 1e-15 * value.max(1.0));
+            assert!((hours_to_days(days_to_hours(value)) - value).abs() <= 1e-15 * value.max(1.0));
+        }
+        assert!(relative(KM_PER_LIGHT_YEAR / KM_PER_AU, 63_241.077) < 1e-7);
+    }
+
+    #[test]
+    fn irradiance_falls_with_the_square() {
+        assert_eq!(irradiance(KM_PER_AU), 1.0);
+        assert!((irradiance(au_to_km(2.0)) - 0.25).abs() < 1e-12);
+        assert!((irradiance(au_to_km(0.5)) - 4.0).abs() < 1e-12);
+        assert_eq!(irradiance(0.0), 1.0);
+    }
+
+    #[test]
+    fn atmosphere_density() {
+        let air = Atmosphere {
+            height_km: 100.0,
+            scale_height_km: 8.5,
+            density: 1.0,
+            tint: [1.0; 3],
+        };
+        assert_eq!(air.density_at(0.0), 1.0);
+        assert_eq!(air.density_at(-2.0), 1.0);
+        assert!((air.density_at(8.5) - (-1.0f32).exp()).abs() < 1e-6);
+        assert!(air.density_at(99.0) > 0.0);
+        assert_eq!(air.density_at(100.0), 0.0);
+        assert_eq!(air.density_at(1000.0), 0.0);
+    }
+
+    #[test]
+    fn scene_scale_fits_every_planet_to_the_render_radius() {
+        let radius = 0.65;
+        for preset in PRESETS.iter() {
+            let scale = SceneScale::for_preset(preset, radius);
+            assert!(relative(scale.to_render(preset.planet_radius_km), f64::from(radius)) < 1e-6);
+            assert!(relative(scale.to_km(f64::from(radius)), preset.planet_radius_km) < 1e-6);
+            let camera = preset.planet_radius_km + preset.camera_altitude_km;
+            assert!(relative(scale.to_km(scale.to_render(camera)), camera) < 1e-12);
+            // Linear, twice as far is twice as many render units
+            assert!(relative(scale.to_render(2.0 * camera), 2.0 * scale.to_render(camera)) < 1e-12);
+        }
+    }
+
+    #[test]
+    fn presets_are_physical() {
+        for preset in PRESETS.iter() {
+            assert!(preset.planet_radius_km > 0.0, "{}", preset.name);
+            assert!(preset.camera_altitude_km > 0.0, "{}", preset.name);
+            assert!(preset.day_length_hours > 0.0 && preset.year_length_hours > 0.0);
+            assert!((0.0..1.0).contains(&preset.flattening), "{}", preset.name);
+            assert!(preset.mu() > 0.0);
+        }
+        // The Earth's gravitational parameter, from its radius and gravity
+        let earth = PRESETS
+            .iter()
+            .find(|preset| preset.name == "Earth (real scale)")
+            .unwrap();
+        assert!(relative(earth.mu(), 398_600.0) < 0.01);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::gpu_timer::PassTimer;
+use crate::math;
+use crate::noise;
+use cgmath::{vec3, Vector3};
+use glium::{
+    backend::Facade,
+    implement_vertex,
+    index::PrimitiveType,
+    texture::{texture2d::Texture2d, MipmapsOption, RawImage2d},
+    IndexBuffer, VertexBuffer,
+};
+use rand::distributions::{Distribution, UnitSphereSurface};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::error;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+// Terrain bands from planet.frag, vegetation only grows on the grass between
+// the beaches and the snow line.
+const SAND_HEIGHT: f32 = 0.015;
+const TREE_LINE: f32 = 0.09;
+const MAX_SLOPE: f32 = 0.8;
+
+const ATLAS_CELL: usize = 64;
+
+#[derive(Copy, Clone)]
+pub struct BillboardVertex {
+    corner: [f32; 2],
+    plane: f32,
+}
+implement_vertex!(BillboardVertex, corner, plane);
+
+#[derive(Copy, Clone)]
+pub struct VegetationInstance {
+    instance_pos: [f32; 3],
+    instance_scale: f32,
+    instance_rotation: f32,
+    instance_type: f32,
+    instance_rank: f32,
+}
+implement_vertex!(
+    VegetationInstance,
+    instance_pos,
+    instance_scale,
+    instance_rotation,
+    instance_type,
+    instance_rank
+);
+
+pub struct VegetationSettings {
+    pub enabled: bool,
+    pub seed: i32,
+    pub candidates: i32,
+    pub size: f32,
+    pub view_distance_km: f32,
+}
+
+impl VegetationSettings {
+    pub fn new() -> VegetationSettings {
+        VegetationSettings {
+            enabled: true,
+            seed: 1,
+            candidates: 100_000,
+            size: 0.004,
+            view_distance_km: 29_400.0,
+        }
+    }
+}
+
+pub struct Vegetation {
+    pub settings: VegetationSettings,
+    pub billboard_vertices: VertexBuffer<BillboardVertex>,
+    pub billboard_indices: IndexBuffer<u16>,
+    pub atlas: Texture2d,
+    pub instances: Option<VertexBuffer<VegetationInstance>>,
+    positions: Vec<Vector3<f32>>,
+    pub timer: PassTimer,
+    pending: Option<Receiver<Vec<VegetationInstance>>>,
+    radius: f32,
+}
+
+impl Vegetation {
+    pub fn new<F: Facade>(facade: &F, radius: f32) -> Result<Vegetation, Box<dyn error::Error>> {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for plane in 0..2 {
+            let base = vertices.len() as u16;
+            for &corner in &[[-0.5, 0.0], [0.5, 0.0], [0.5, 1.0], [-0.5, 1.0]] {
+                vertices.push(BillboardVertex {
+                    corner,
+                    plane: plane as f32,
+                });
+            }
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+
+        let atlas = {
+            let data = generate_atlas();
+            let image = RawImage2d::from_raw_rgba_reversed(
+                &data,
+                (2 * ATLAS_CELL as u32, ATLAS_CELL as u32),
+            );
+            Texture2d::with_mipmaps(facade, image, MipmapsOption::AutoGeneratedMipmaps)?
+        };
+
+        let mut vegetation = Vegetation {
+            settings: VegetationSettings::new(),
+            billboard_vertices: VertexBuffer::new(facade, &vertices)?,
+            billboard_indices: IndexBuffer::new(facade, PrimitiveType::TrianglesList, &indices)?,
+            atlas,
+            instances: None,
+            positions: Vec::new(),
+            timer: PassTimer::new(),
+            pending: None,
+            radius,
+        };
+        vegetation.regenerate();
+
+        Ok(vegetation)
+    }
+
+    /// Starts generating a new instance set in the background, replacing any
+    /// generation that is still running.
+    pub fn regenerate(&mut self) {
+        let (sender, receiver) = mpsc::channel();
+        let seed = self.settings.seed as u64;
+        let candidates = self.settings.candidates.max(0) as usize;
+        let radius = self.radius;
+
+        thread::spawn(move || {
+            let _ = sender.send(generate_instances(seed, candidates, radius));
+        });
+
+        self.pending = Some(receiver);
+    }
+
+    /// Uploads the result of the background generation once it is done.
+    pub fn update<F: Facade>(&mut self, facade: &F) -> Result<(), Box<dyn error::Error>> {
+        let result = match self.pending {
+            Some(ref receiver) => receiver.try_recv(),
+            None => return Ok(()),
+        };
+
+        match result {
+            Ok(instances) => {
+                self.pending = None;
+                self.upload(facade, &instances)?;
+            }
+            Err(TryRecvError::Disconnected) => self.pending = None,
+            Err(TryRecvError::Empty) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Blocks until the background generation is done, for offline rendering.
+    pub fn wait<F: Facade>(&mut self, facade: &F) -> Result<(), Box<dyn error::Error>> {
+        if let Some(receiver) = self.pending.take() {
+            if let Ok(instances) = receiver.recv() {
+                self.upload(facade, &instances)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn upload<F: Facade>(
+        &mut self,
+        facade: &F,
+        instances: &[VegetationInstance],
+    ) -> Result<(), Box<dyn error::Error>> {
+        self.positions = instances
+            .iter()
+            .map(|instance| {
+                let pos = instance.instance_pos;
+                vec3(pos[0], pos[1], pos[2])
+            })
+            .collect();
+
+        self.instances = if instances.is_empty() {
+            None
+        } else {
+            Some(VertexBuffer::new(facade, instances)?)
+        };
+        Ok(())
+    }
+
+    pub fn is_generating(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// How many instances the vertex shader's horizon test keeps, given the
+    /// camera in planet-local space.
+    pub fn count_above_horizon(&self, camera: Vector3<f32>, radius: f32) -> usize {
+        self.positions
+            .iter()
+            .filter(|&&pos| !math::is_beyond_horizon(camera, pos, radius))
+            .count()
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.instances
+            .as_ref()
+            .map_or(0, |instances| instances.len())
+    }
+}
+
+fn generate_instances(seed: u64, candidates: usize, radius: f32) -> Vec<VegetationInstance> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let sphere = UnitSphereSurface::new();
+
+    let mut instances = Vec::new();
+    for _ in 0..candidates {
+        let v = sphere.sample(&mut rng);
+        let dir = [v[0] as f32, v[1] as f32, v[2] as f32];
+
+        // Draw every random number up front so the accepted set for a seed
+        // doesn't depend on which candidates get rejected.
+        let scale: f32 = rng.gen_range(0.5, 1.0);
+        let rotation: f32 = rng.gen_range(0.0, std::f32::consts::PI);
+        let kind = rng.gen_range(0, 2);
+        let rank: f32 = rng.gen();
+
+        let altitude = noise::terrain_altitude(dir, radius);
+        if altitude < radius + SAND_HEIGHT || altitude > radius + TREE_LINE {
+            continue;
+        }
+
+        if slope(dir, radius) > MAX_SLOPE {
+            continue;
+        }
+
+        instances.push(VegetationInstance {
+            instance_pos: [dir[0] * altitude, dir[1] * altitude, dir[2] * altitude],
+            instance_scale: scale,
+            instance_rotation: rotation,
+            instance_type: kind as f32,
+            instance_rank: rank,
+        });
+    }
+
+    instances
+}
+
+/// Rise over run of the displaced terrain around `dir`.
+fn slope(dir: [f32; 3], radius: f32) -> f32 {
+    const EPSILON: f32 = 0.002;
+
+    let helper = if dir[1].abs() < 0.99 {
+        [0.0, 1.0, 0.0]
+    } else {
+        [1.0, 0.0, 0.0]
+    };
+    let tangent = normalize(cross(helper, dir));
+    let bitangent = cross(dir, tangent);
+
+    let height = noise::terrain_altitude(dir, radius);
+    let offset = |axis: [f32; 3]| {
+        normalize([
+            dir[0] + axis[0] * EPSILON,
+            dir[1] + axis[1] * EPSILON,
+            dir[2] + axis[2] * EPSILON,
+        ])
+    };
+
+    let du = noise::terrain_altitude(offset(tangent), radius) - height;
+    let dv = noise::terrain_altitude(offset(bitangent), radius) - height;
+
+    (du * du + dv * dv).sqrt() / (radius * EPSILON)
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+/// Two cells side by side: a conifer and a round bush, alpha cut out.
+fn generate_atlas() -> Vec<u8> {
+    let width = 2 * ATLAS_CELL;
+    let mut data = vec![0u8; width * ATLAS_CELL * 4];
+
+    for y in 0..ATLAS_CELL {
+        // v runs from the ground (0) to the top of the cell (1)
+        let v = 1.0 - (y as f32 + 0.5) / ATLAS_CELL as f32;
+
+        for x in 0..width {
+            let cell = x / ATLAS_CELL;
+            let u = ((x % ATLAS_CELL) as f32 + 0.5) / ATLAS_CELL as f32 - 0.5;
+
+            let color = if cell == 0 {
+                if v < 0.2 && u.abs() < 0.05 {
+                    Some([90, 60, 30])
+                } else if v >= 0.2 && u.abs() < 0.4 * (1.0 - (v - 0.2) / 0.8) {
+                    Some([30, 85, 25])
+                } else {
+                    None
+                }
+            } else {
+                let dy = v - 0.35;
+                if u * u + dy * dy < 0.35 * 0.35 {
+                    Some([65, 100, 30])
+                } else {
+                    None
+                }
+            };
+
+            if let Some(rgb) = color {
+                let i = 4 * (y * width + x);
+                data[i..i + 4].copy_from_slice(&[rgb[0], rgb[1], rgb[2], 255]);
+            }
+        }
+    }
+
+    data
+}
@@ -0,0 +1,319 @@
+use glium::{
+    backend::Facade,
+    texture::{texture1d::Texture1d, MipmapsOption, UncompressedFloatFormat},
+};
+use imgui::{ImMouseButton, ImStr, Ui};
+use std::error;
+use std::fmt;
+
+// Texels of the profile texture, spread evenly from the south to the north
+// pole
+const PROFILE_TEXELS: usize = 128;
+
+// Editor canvas height and the speed range it shows, in m/s
+const EDITOR_HEIGHT: f32 = 120.0;
+const EDITOR_MAX_SPEED: f32 = 40.0;
+const POINT_RADIUS: f32 = 4.0;
+
+/// Zonal wind speed (eastward, m/s) against latitude in degrees, as points
+/// sorted by latitude that always span both poles.
+#[derive(Clone)]
+pub struct WindCurve {
+    pub points: Vec<[f32; 2]>,
+}
+
+impl WindCurve {
+    pub fn new(points: &[[f32; 2]]) -> WindCurve {
+        let mut curve = WindCurve {
+            points: points.to_vec(),
+        };
+        curve
+            .points
+            .sort_by(|a, b| a[0].partial_cmp(&b[0]).unwrap());
+        curve
+    }
+
+    /// Monotone cubic interpolation (Fritsch-Carlson) through the points,
+    /// so the speed never overshoots between two of them.
+    pub fn sample(&self, latitude: f32) -> f32 {
+        let points = &self.points;
+        match points.len() {
+            0 => return 0.0,
+            1 => return points[0][1],
+            _ => {}
+        }
+
+        let last = points.len() - 1;
+        if latitude <= points[0][0] {
+            return points[0][1];
+        }
+        if latitude >= points[last][0] {
+            return points[last][1];
+        }
+
+        let secant = |i: usize| {
+            (points[i + 1][1] - points[i][1]) / (points[i + 1][0] - points[i][0]).max(1e-3)
+        };
+        let tangent = |i: usize| {
+            if i == 0 {
+                secant(0)
+            } else if i == last {
+                secant(last - 1)
+            } else {
+                let (before, after) = (secant(i - 1), secant(i));
+                if before * after <= 0.0 {
+                    0.0
+                } else {
+                    // Harmonic mean keeps the tangents within the monotone
+                    // region
+                    2.0 * before * after / (before + after)
+                }
+            }
+        };
+
+        let i = points
+            .windows(2)
+            .position(|pair| latitude < pair[1][0])
+            .unwrap_or(last - 1);
+        let (x0, y0) = (points[i][0], points[i][1]);
+        let (x1, y1) = (points[i + 1][0], points[i + 1][1]);
+        let h = (x1 - x0).max(1e-3);
+        let t = (latitude - x0) / h;
+
+        let t2 = t * t;
+        let t3 = t2 * t;
+        (2.0 * t3 - 3.0 * t2 + 1.0) * y0
+            + (t3 - 2.0 * t2 + t) * h * tangent(i)
+            + (-2.0 * t3 + 3.0 * t2) * y1
+            + (t3 - t2) * h * tangent(i + 1)
+    }
+
+    /// The curve sampled at texel centers from -90 to 90 degrees.
+    pub fn resample(&self, count: usize) -> Vec<f32> {
+        (0..count)
+            .map(|i| {
+                let latitude = -90.0 + 180.0 * (i as f32 + 0.5) / count as f32;
+                self.sample(latitude)
+            })
+            .collect()
+    }
+
+    /// Parses the `latitude:speed,...` form it is displayed as.
+    pub fn parse(text: &str) -> Result<WindCurve, Box<dyn error::Error>> {
+        let mut points = Vec::new();
+        for point in text.split(',').filter(|point| !point.trim().is_empty()) {
+            let mut parts = point.splitn(2, ':');
+            match (parts.next(), parts.next()) {
+                (Some(latitude), Some(speed)) => {
+                    points.push([latitude.trim().parse()?, speed.trim().parse()?])
+                }
+                _ => return Err(format!("malformed wind curve point '{}'", point).into()),
+            }
+        }
+
+        if points.len() < 2 {
+            return Err("a wind curve needs at least two points".into());
+        }
+        Ok(WindCurve::new(&points))
+    }
+}
+
+impl fmt::Display for WindCurve {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, point) in self.points.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}:{}", point[0], point[1])?;
+        }
+        Ok(())
+    }
+}
+
+pub struct WindSettings {
+    pub enabled: bool,
+    pub time_scale: f32,
+}
+
+/// Latitude-banded zonal winds that carry the cloud pattern around the
+/// planet, uploaded to the cloud shaders as a 1D profile texture.
+pub struct Wind {
+    pub settings: WindSettings,
+    pub curve: WindCurve,
+    pub texture: Option<Texture1d>,
+    dirty: bool,
+    dragging: Option<usize>,
+}
+
+impl Wind {
+    pub fn new(curve: &[[f32; 2]]) -> Wind {
+        Wind {
+            settings: WindSettings {
+                enabled: true,
+                time_scale: 3600.0,
+            },
+            curve: WindCurve::new(curve),
+            texture: None,
+            dirty: true,
+            dragging: None,
+        }
+    }
+
+    pub fn set_curve(&mut self, curve: WindCurve) {
+        self.curve = curve;
+        self.dirty = true;
+    }
+
+    /// Reuploads the profile texture if the curve changed.
+    pub fn update<F: Facade>(&mut self, facade: &F) -> Result<(), Box<dyn error::Error>> {
+        if !self.dirty {
+            return Ok(());
+        }
+        self.dirty = false;
+
+        self.texture = Some(Texture1d::with_format(
+            facade,
+            self.curve.resample(PROFILE_TEXELS),
+            UncompressedFloatFormat::F32,
+            MipmapsOption::NoMipmap,
+        )?);
+        Ok(())
+    }
+
+    /// Simulated seconds the clouds have been blown for at `time`.
+    pub fn time(&self, time: f32) -> f32 {
+        if self.settings.enabled {
+            time * self.settings.time_scale
+        } else {
+            0.0
+        }
+    }
+
+    /// Speed against latitude with the south pole on the left. Click to add
+    /// a point, drag to move one and right click to remove it, the points
+    /// at the poles only move up and down.
+    pub fn draw_editor<'a>(&mut self, ui: &Ui<'a>, label: &ImStr) {
+        let origin = ui.get_cursor_screen_pos();
+        let width = ui.get_content_region_avail().0.max(100.0);
+        let size = (width, EDITOR_HEIGHT);
+        ui.invisible_button(label, size);
+        let hovered = ui.is_item_hovered();
+        let active = ui.is_item_active();
+
+        let to_screen = |point: [f32; 2]| {
+            (
+                origin.0 + (point[0] + 90.0) / 180.0 * size.0,
+                origin.1 + (0.5 - point[1] / (2.0 * EDITOR_MAX_SPEED)) * size.1,
+            )
+        };
+        let from_screen = |pos: (f32, f32)| {
+            [
+                ((pos.0 - origin.0) / size.0 * 180.0 - 90.0).clamp(-90.0, 90.0),
+                ((0.5 - (pos.1 - origin.1) / size.1) * 2.0 * EDITOR_MAX_SPEED)
+                    .clamp(-EDITOR_MAX_SPEED, EDITOR_MAX_SPEED),
+            ]
+        };
+
+        let mouse = ui.imgui().mouse_pos();
+        let nearest = self
+            .curve
+            .points
+            .iter()
+            .map(|&point| {
+                let pos = to_screen(point);
+                (pos.0 - mouse.0).powi(2) + (pos.1 - mouse.1).powi(2)
+            })
+            .enumerate()
+            .filter(|&(_, distance2)| distance2 <= (2.0 * POINT_RADIUS).powi(2))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(i, _)| i);
+
+        let last = self.curve.points.len() - 1;
+        if hovered && ui.imgui().is_mouse_clicked(ImMouseButton::Left) {
+            self.dragging = nearest.or_else(|| {
+                let point = from_screen(mouse);
+                let i = self
+                    .curve
+                    .points
+                    .iter()
+                    .position(|p| p[0] > point[0])
+                    .unwrap_or(last);
+                self.curve.points.insert(i, point);
+                Some(i)
+            });
+            self.dirty = true;
+        }
+
+        if hovered && ui.imgui().is_mouse_clicked(ImMouseButton::Right) {
+            if let Some(i) = nearest.filter(|&i| i != 0 && i != last) {
+                self.curve.points.remove(i);
+                self.dragging = None;
+                self.dirty = true;
+            }
+        }
+
+        if !active {
+            self.dragging = None;
+        }
+        if let Some(i) = self.dragging {
+            let last = self.curve.points.len() - 1;
+            let mut point = from_screen(mouse);
+
+            // Points keep their order, the outer ones stay on the poles
+            point[0] = if i == 0 {
+                -90.0
+            } else if i == last {
+                90.0
+            } else {
+                point[0].clamp(self.curve.points[i - 1][0], self.curve.points[i + 1][0])
+            };
+
+            if self.curve.points[i] != point {
+                self.curve.points[i] = point;
+                self.dirty = true;
+            }
+        }
+
+        let draw_list = ui.get_window_draw_list();
+        draw_list
+            .add_rect(
+                origin,
+                (origin.0 + size.0, origin.1 + size.1),
+                [0.2, 0.2, 0.2, 1.0],
+            )
+            .filled(true)
+            .build();
+        draw_list
+            .add_line(
+                to_screen([-90.0, 0.0]),
+                to_screen([90.0, 0.0]),
+                [0.5, 0.5, 0.5, 1.0],
+            )
+            .build();
+
+        let samples = self.curve.resample(PROFILE_TEXELS);
+        for (i, pair) in samples.windows(2).enumerate() {
+            let latitude = |i: usize| -90.0 + 180.0 * (i as f32 + 0.5) / PROFILE_TEXELS as f32;
+            draw_list
+                .add_line(
+                    to_screen([latitude(i), pair[0]]),
+                    to_screen([latitude(i + 1), pair[1]]),
+                    [0.4, 0.7, 1.0, 1.0],
+                )
+                .thickness(2.0)
+                .build();
+        }
+
+        for (i, &point) in self.curve.points.iter().enumerate() {
+            let color = if Some(i) == self.dragging || Some(i) == nearest {
+                [1.0, 0.8, 0.2, 1.0]
+            } else {
+                [1.0, 1.0, 1.0, 1.0]
+            };
+            draw_list
+                .add_circle(to_screen(point), POINT_RADIUS, color)
+                .filled(true)
+                .build();
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use imgui::{im_str, ImGuiCond, ImStr, ImString, Ui};
+use std::error;
+use std::fs;
+use std::io::{self, Write};
+
+const WORKSPACES_PATH: &str = "workspaces.txt";
+
+pub const BUILTIN_NAMES: [&str; 3] = ["Minimal", "Authoring", "Profiling"];
+
+#[derive(Copy, Clone, PartialEq)]
+pub struct WindowLayout {
+    pub open: bool,
+    pub pos: (f32, f32),
+    pub size: (f32, f32),
+}
+
+impl WindowLayout {
+    fn parse(value: &str) -> Result<WindowLayout, Box<dyn error::Error>> {
+        let parts: Vec<&str> = value.split(',').map(|part| part.trim()).collect();
+        if parts.len() != 5 {
+            return Err(format!("expected open,x,y,width,height but got '{}'", value).into());
+        }
+        Ok(WindowLayout {
+            open: parts[0].parse()?,
+            pos: (parts[1].parse()?, parts[2].parse()?),
+            size: (parts[3].parse()?, parts[4].parse()?),
+        })
+    }
+
+    fn to_line(self) -> String {
+        format!(
+            "{},{},{},{},{}",
+            self.open, self.pos.0, self.pos.1, self.size.0, self.size.1
+        )
+    }
+}
+
+/// Which windows are open and where, plus the HUD overlays.
+#[derive(Clone)]
+pub struct Workspace {
+    pub name: String,
+    pub planet: WindowLayout,
+    pub stats: WindowLayout,
+    pub shadowmap_preview: bool,
+}
+
+fn builtin_workspaces() -> Vec<Workspace> {
+    let closed = |pos, size| WindowLayout {
+        open: false,
+        pos,
+        size,
+    };
+    let open = |pos, size| WindowLayout {
+        open: true,
+        pos,
+        size,
+    };
+
+    vec![
+        Workspace {
+            name: BUILTIN_NAMES[0].to_string(),
+            planet: closed((10.0, 30.0), (380.0, 680.0)),
+            stats: open((10.0, 30.0), (300.0, 60.0)),
+            shadowmap_preview: false,
+        },
+        Workspace {
+            name: BUILTIN_NAMES[1].to_string(),
+            planet: open((10.0, 30.0), (380.0, 680.0)),
+            stats: closed((400.0, 30.0), (420.0, 60.0)),
+            shadowmap_preview: false,
+        },
+        Workspace {
+            name: BUILTIN_NAMES[2].to_string(),
+            planet: open((10.0, 30.0), (340.0, 420.0)),
+            stats: open((360.0, 30.0), (420.0, 320.0)),
+            shadowmap_preview: true,
+        },
+    ]
+}
+
+/// Named UI layouts, the built-in ones first and then those saved to
+/// `workspaces.txt`. The layout in use is tracked in `current` rather than
+/// left to imgui, so it can be saved and switched.
+pub struct Workspaces {
+    pub list: Vec<Workspace>,
+    pub current: Workspace,
+    applying: bool,
+    save_name: ImString,
+    opening_save: bool,
+}
+
+impl Workspaces {
+    pub fn load() -> Workspaces {
+        let mut list = builtin_workspaces();
+
+        match fs::read_to_string(WORKSPACES_PATH) {
+            Ok(text) => match parse_workspaces(&text) {
+                Ok(saved) => {
+                    for workspace in saved {
+                        match list.iter().position(|w| w.name == workspace.name) {
+                            Some(i) => list[i] = workspace,
+                            None => list.push(workspace),
+                        }
+                    }
+                }
+                Err(e) => println!("{}: {}", WORKSPACES_PATH, e),
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => println!("{}: {}", WORKSPACES_PATH, e),
+        }
+
+        let current = list[2].clone();
+        Workspaces {
+            list,
+            current,
+            applying: true,
+            save_name: ImString::with_capacity(64),
+            opening_save: false,
+        }
+    }
+
+    pub fn apply(&mut self, index: usize) {
+        if let Some(workspace) = self.list.get(index) {
+            self.current = workspace.clone();
+            self.applying = true;
+        }
+    }
+
+    /// Condition to place the windows with this frame. Only on the frame a
+    /// workspace is applied does its layout override where imgui has them.
+    pub fn window_cond(&mut self) -> ImGuiCond {
+        if self.applying {
+            self.applying = false;
+            ImGuiCond::Always
+        } else {
+            ImGuiCond::FirstUseEver
+        }
+    }
+
+    fn save(&mut self, name: &str) -> Result<(), Box<dyn error::Error>> {
+        let mut workspace = self.current.clone();
+        workspace.name = name.to_string();
+        match self.list.iter().position(|w| w.name == name) {
+            Some(i) => self.list[i] = workspace,
+            None => self.list.push(workspace),
+        }
+
+        let mut file = fs::File::create(WORKSPACES_PATH)?;
+        for workspace in &self.list {
+            writeln!(file, "[{}]", workspace.name)?;
+            writeln!(file, "planet={}", workspace.planet.to_line())?;
+            writeln!(file, "stats={}", workspace.stats.to_line())?;
+            writeln!(file, "shadowmap_preview={}", workspace.shadowmap_preview)?;
+        }
+        Ok(())
+    }
+
+    /// The Workspace menu in the main menu bar, with the save dialog.
+    /// Returns the workspace picked this frame, if any.
+    pub fn draw_menu<'a>(&mut self, ui: &Ui<'a>) -> Option<usize> {
+        let mut picked = None;
+
+        ui.main_menu_bar(|| {
+            ui.menu(im_str!("Workspace")).build(|| {
+                for (i, workspace) in self.list.iter().enumerate() {
+                    let label = ImString::new(workspace.name.as_str());
+                    let shortcut = ImString::new(format!("Ctrl+{}", i + 1));
+                    let mut item = ui.menu_item(&label);
+                    if i < 4 {
+                        item = item.shortcut(&shortcut);
+                    }
+                    if item.build() {
+                        picked = Some(i);
+                    }
+                }
+
+                ui.separator();
+                ui.menu_item(im_str!("Planet window"))
+                    .selected(&mut self.current.planet.open)
+                    .build();
+                ui.menu_item(im_str!("Stats window"))
+                    .selected(&mut self.current.stats.open)
+                    .build();
+                ui.menu_item(im_str!("Shadowmap preview"))
+                    .selected(&mut self.current.shadowmap_preview)
+                    .build();
+
+                ui.separator();
+                if ui.menu_item(im_str!("Save layout as...")).build() {
+                    self.opening_save = true;
+                }
+            });
+        });
+
+        // Opened out here, the popup id has to match where it's drawn
+        let popup: &ImStr = im_str!("Save workspace");
+        if self.opening_save {
+            self.opening_save = false;
+            self.save_name.clear();
+            self.save_name.push_str(&self.current.name);
+            ui.open_popup(popup);
+        }
+
+        ui.popup_modal(popup).always_auto_resize(true).build(|| {
+            ui.input_text(im_str!("Name"), &mut self.save_name).build();
+
+            let name = self.save_name.to_str().trim().to_string();
+            if ui.button(im_str!("Save"), (0.0, 0.0)) && !name.is_empty() {
+                if let Err(e) = self.save(&name) {
+                    println!("Saving workspace failed: {}", e);
+                }
+                ui.close_current_popup();
+            }
+            ui.same_line(0.0);
+            if ui.button(im_str!("Cancel"), (0.0, 0.0)) {
+                ui.close_current_popup();
+            }
+        });
+
+        picked
+    }
+}
+
+fn parse_workspaces(text: &str) -> Result<Vec<Workspace>, Box<dyn error::Error>> {
+    let mut workspaces: Vec<Workspace> = Vec::new();
+
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: String| format!("line {}: {}", number + 1, message);
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            let mut workspace = builtin_workspaces()[2].clone();
+            workspace.name = name.trim().to_string();
+            workspaces.push(workspace);
+            continue;
+        }
+
+        let workspace = workspaces
+            .last_mut()
+            .ok_or_else(|| error("setting before the first [name]".to_string()))?;
+        let mut parts = line.splitn(2, '=');
+        match (parts.next().map(str::trim), parts.next().map(str::trim)) {
+            (Some("planet"), Some(value)) => {
+                workspace.planet = WindowLayout::parse(value).map_err(|e| error(e.to_string()))?
+            }
+            (Some("stats"), Some(value)) => {
+                workspace.stats = WindowLayout::parse(value).map_err(|e| error(e.to_string()))?
+            }
+            (Some("shadowmap_preview"), Some(value)) => {
+                workspace.shadowmap_preview = value
+                    .parse()
+                    .map_err(|_| error(format!("expected true or false but got '{}'", value)))?
+            }
+            _ => return Err(error(format!("unknown setting '{}'", line)).into()),
+        }
+    }
+
+    Ok(workspaces)
+}
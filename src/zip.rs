@@ -0,0 +1,240 @@
+use std::error;
+
+const LOCAL_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_HEADER: u32 = 0x0201_4b50;
+const END_OF_DIRECTORY: u32 = 0x0605_4b50;
+
+// 2.0, the first version with directories, which is all stored files need
+const VERSION: u16 = 20;
+// The names are UTF-8
+const UTF8_NAMES: u16 = 1 << 11;
+// 1980-01-01 00:00, the earliest there is. The capture's name has the time
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+/// A file's path in the archive and its contents.
+pub type File = (String, Vec<u8>);
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// The CRC-32 zip files check their contents with.
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        CRC_TABLE[((crc ^ u32::from(byte)) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn push_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn push_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// A zip archive of `files`, stored without compression. Names use `/`
+/// between directories.
+pub fn write(files: &[File]) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    if files.len() > usize::from(u16::MAX) {
+        return Err("too many files for a zip without zip64".into());
+    }
+
+    let mut out = Vec::new();
+    let mut directory = Vec::new();
+    for (name, data) in files {
+        let offset = out.len();
+        if name.len() > usize::from(u16::MAX)
+            || data.len() > u32::MAX as usize
+            || offset > u32::MAX as usize
+        {
+            return Err(format!("{} is too large for a zip without zip64", name).into());
+        }
+        let crc = crc32(data);
+
+        // The fields the local header and the directory entry share
+        let mut fields = Vec::new();
+        push_u16(&mut fields, VERSION);
+        push_u16(&mut fields, UTF8_NAMES);
+        push_u16(&mut fields, 0); // Stored
+        push_u16(&mut fields, 0);
+        push_u16(&mut fields, DOS_DATE);
+        push_u32(&mut fields, crc);
+        push_u32(&mut fields, data.len() as u32);
+        push_u32(&mut fields, data.len() as u32);
+        push_u16(&mut fields, name.len() as u16);
+        push_u16(&mut fields, 0); // Extra field
+
+        push_u32(&mut out, LOCAL_HEADER);
+        out.extend_from_slice(&fields);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        push_u32(&mut directory, CENTRAL_HEADER);
+        push_u16(&mut directory, VERSION);
+        directory.extend_from_slice(&fields);
+        push_u16(&mut directory, 0); // Comment
+        push_u16(&mut directory, 0); // Disk
+        push_u16(&mut directory, 0); // Internal attributes
+        push_u32(&mut directory, 0); // External attributes
+        push_u32(&mut directory, offset as u32);
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = out.len();
+    if directory_offset > u32::MAX as usize {
+        return Err("too large for a zip without zip64".into());
+    }
+    out.extend_from_slice(&directory);
+
+    push_u32(&mut out, END_OF_DIRECTORY);
+    push_u16(&mut out, 0);
+    push_u16(&mut out, 0);
+    push_u16(&mut out, files.len() as u16);
+    push_u16(&mut out, files.len() as u16);
+    push_u32(&mut out, directory.len() as u32);
+    push_u32(&mut out, directory_offset as u32);
+    push_u16(&mut out, 0);
+    Ok(out)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Result<u16, String> {
+    bytes
+        .get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| format!("zip cut short at byte {}", at))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Result<u32, String> {
+    bytes
+        .get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| format!("zip cut short at byte {}", at))
+}
+
+/// The files in a zip archive, in directory order. Only stored files can
+/// be read, which is what `write` makes.
+pub fn read(bytes: &[u8]) -> Result<Vec<File>, Box<dyn error::Error>> {
+    // The end record is last, before a comment of up to 64 KiB
+    let end = (0..=bytes.len().saturating_sub(22))
+        .rev()
+        .take(usize::from(u16::MAX) + 1)
+        .find(|&at| u32_at(bytes, at) == Ok(END_OF_DIRECTORY))
+        .ok_or("not a zip file")?;
+    let count = u16_at(bytes, end + 10)?;
+    let mut at = u32_at(bytes, end + 16)? as usize;
+
+    let mut files = Vec::new();
+    for _ in 0..count {
+        if u32_at(bytes, at)? != CENTRAL_HEADER {
+            return Err(format!("no zip directory entry at byte {}", at).into());
+        }
+        let method = u16_at(bytes, at + 10)?;
+        let crc = u32_at(bytes, at + 16)?;
+        let size = u32_at(bytes, at + 20)? as usize;
+        let name_len = usize::from(u16_at(bytes, at + 28)?);
+        let extra_len = usize::from(u16_at(bytes, at + 30)?);
+        let comment_len = usize::from(u16_at(bytes, at + 32)?);
+        let offset = u32_at(bytes, at + 42)? as usize;
+        let name = bytes
+            .get(at + 46..at + 46 + name_len)
+            .ok_or("zip cut short in a file name")?;
+        let name = String::from_utf8_lossy(name).into_owned();
+        at += 46 + name_len + extra_len + comment_len;
+
+        if method != 0 {
+            return Err(format!("{} in the zip is compressed", name).into());
+        }
+        if u32_at(bytes, offset)? != LOCAL_HEADER {
+            return Err(format!("no zip file header for {}", name).into());
+        }
+        let start = offset
+            + 30
+            + usize::from(u16_at(bytes, offset + 26)?)
+            + usize::from(u16_at(bytes, offset + 28)?);
+        let data = bytes
+            .get(start..start + size)
+            .ok_or_else(|| format!("zip cut short in {}", name))?;
+        if crc32(data) != crc {
+            return Err(format!("{} in the zip is corrupt", name).into());
+        }
+        files.push((name, data.to_vec()));
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files() -> Vec<File> {
+        vec![
+            ("settings.txt".to_string(), b"preset=1\nfov=45\n".to_vec()),
+            (
+                "shaders/planet.frag".to_string(),
+                b"void main() {}".to_vec(),
+            ),
+            ("empty.txt".to_string(), Vec::new()),
+            (
+                "frame.png".to_string(),
+                (0..=255).cycle().take(70_000).collect(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn crc_is_the_zip_one() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn files_read_back() {
+        let files = files();
+        assert_eq!(read(&write(&files).unwrap()).unwrap(), files);
+    }
+
+    #[test]
+    fn archives_start_and_end_like_zips() {
+        let zip = write(&files()).unwrap();
+        assert_eq!(&zip[..4], b"PK\x03\x04");
+        assert_eq!(&zip[zip.len() - 22..zip.len() - 18], b"PK\x05\x06");
+        assert!(read(&write(&[]).unwrap()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn damage_is_reported() {
+        let mut zip = write(&files()).unwrap();
+        assert!(read(&zip[..zip.len() - 30]).is_err());
+
+        // A byte of settings.txt
+        zip[30 + "settings.txt".len()] ^= 1;
+        assert!(read(&zip).is_err());
+    }
+
+    #[test]
+    fn compressed_files_are_refused() {
+        let mut zip = write(&files()[..1]).unwrap();
+        let directory = zip.len() - 22 - (46 + "settings.txt".len());
+        zip[directory + 10] = 8; // Deflate
+        assert!(read(&zip).is_err());
+    }
+}